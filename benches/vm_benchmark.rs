@@ -7,14 +7,16 @@ use ckb_vm::{
     decoder::build_decoder,
     machine::{
         asm::{
-            traces::{MemoizedDynamicTraceDecoder, MemoizedFixedTraceDecoder},
+            traces::{ChainedTraceDecoder, MemoizedDynamicTraceDecoder, MemoizedFixedTraceDecoder},
             AsmCoreMachine, AsmMachine,
         },
-        DefaultMachineBuilder, VERSION0, VERSION2,
+        VERSION0,
     },
-    ISA_B, ISA_IMC, ISA_MOP,
 };
-use ckb_vm::{run, SparseMemory};
+use ckb_vm::{
+    machine::{trace::TraceMachine, DefaultCoreMachine, DefaultMachineBuilder, VERSION2},
+    run, SparseMemory, WXorXMemory, ISA_B, ISA_IMC, ISA_MOP,
+};
 use criterion::Criterion;
 use std::fs;
 
@@ -31,6 +33,30 @@ fn interpret_benchmark(c: &mut Criterion) {
     });
 }
 
+fn interpret_superblock_benchmark(c: &mut Criterion) {
+    c.bench_function("interpret secp256k1_bench via superblocks", |b| {
+        let buffer = fs::read("benches/data/secp256k1_bench").unwrap().into();
+        let args: Vec<Bytes> = vec!["secp256k1_bench",
+                                      "033f8cf9c4d51a33206a6c1c6b27d2cc5129daa19dbd1fc148d395284f6b26411f",
+                                      "304402203679d909f43f073c7c1dcf8468a485090589079ee834e6eed92fea9b09b06a2402201e46f1075afa18f306715e7db87493e7b7e779569aa13c64ab3d09980b3560a3",
+                                      "foo",
+                                      "bar"].into_iter().map(|a| a.into()).collect();
+
+        b.iter(|| {
+            let core_machine = DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(
+                ISA_IMC | ISA_B | ISA_MOP,
+                VERSION2,
+                u64::MAX,
+            );
+            let mut machine =
+                TraceMachine::new(DefaultMachineBuilder::new(core_machine).build())
+                    .with_superblocks();
+            machine.load_program(&buffer, &args).unwrap();
+            machine.run().unwrap()
+        });
+    });
+}
+
 #[cfg(has_asm)]
 fn asm_benchmark(c: &mut Criterion) {
     c.bench_function("interpret secp256k1_bench via assembly", |b| {
@@ -133,16 +159,49 @@ fn mop_memoized_dynamic_benchmark(c: &mut Criterion) {
     });
 }
 
+#[cfg(has_asm)]
+fn mop_chained_benchmark(c: &mut Criterion) {
+    c.bench_function("interpret secp256k1_bench via assembly mop (chained decoder)", |b| {
+        let isa = ISA_IMC | ISA_B | ISA_MOP;
+        let version = VERSION2;
+        let buffer = fs::read("benches/data/secp256k1_bench").unwrap().into();
+        let args: Vec<Bytes> = vec!["secp256k1_bench",
+                                      "033f8cf9c4d51a33206a6c1c6b27d2cc5129daa19dbd1fc148d395284f6b26411f",
+                                      "304402203679d909f43f073c7c1dcf8468a485090589079ee834e6eed92fea9b09b06a2402201e46f1075afa18f306715e7db87493e7b7e779569aa13c64ab3d09980b3560a3",
+                                      "foo",
+                                      "bar"].into_iter().map(|a| a.into()).collect();
+        let mut decoder = ChainedTraceDecoder::new(build_decoder::<u64>(isa, version));
+        let asm_core = AsmCoreMachine::new(isa, version, u64::MAX);
+        let core = DefaultMachineBuilder::<Box<AsmCoreMachine>>::new(asm_core)
+            .build();
+        let mut machine = AsmMachine::new(core);
+        machine.load_program(&buffer, &args).unwrap();
+        machine.run_with_decoder(&mut decoder).unwrap();
+
+        b.iter(|| {
+            let asm_core = AsmCoreMachine::new(isa, version, u64::MAX);
+            let core = DefaultMachineBuilder::<Box<AsmCoreMachine>>::new(asm_core)
+                .build();
+            let mut machine = AsmMachine::new(core);
+            machine.load_program(&buffer, &args).unwrap();
+            decoder.clear_traces();
+            machine.run_with_decoder(&mut decoder).unwrap()
+        });
+    });
+}
+
 #[cfg(not(has_asm))]
-criterion_group!(benches, interpret_benchmark);
+criterion_group!(benches, interpret_benchmark, interpret_superblock_benchmark);
 
 #[cfg(has_asm)]
 criterion_group!(
     benches,
     interpret_benchmark,
+    interpret_superblock_benchmark,
     asm_benchmark,
     mop_benchmark,
     mop_memoized_benchmark,
-    mop_memoized_dynamic_benchmark
+    mop_memoized_dynamic_benchmark,
+    mop_chained_benchmark
 );
 criterion_main!(benches);
@@ -2,7 +2,8 @@ use crate::{
     instructions::Instruction, DEFAULT_MEMORY_SIZE, MEMORY_FRAMESIZE, MEMORY_FRAME_SHIFTS,
     RISCV_GENERAL_REGISTER_NUMBER, RISCV_PAGESIZE,
 };
-use std::alloc::{alloc, alloc_zeroed, dealloc, Layout};
+use memmap2::{MmapMut, MmapOptions};
+use std::alloc::{alloc_zeroed, dealloc, Layout};
 
 // The number of trace items to keep
 pub const TRACE_SIZE: usize = 8192;
@@ -30,6 +31,11 @@ pub struct FixedTrace {
     pub address: u64,
     pub length: u32,
     pub cycles: u64,
+    // Address of the trace to run when the terminating jump or branch of
+    // this trace is taken, or 0 when no trace has been linked. Decoders can
+    // fill this in for statically known targets so the assembly code skips
+    // the trace table lookup.
+    pub jump_trace: u64,
     // We are using direct threaded code here:
     // https://en.wikipedia.org/wiki/Threaded_code
     // each individual thread is made of 2 consecutive
@@ -64,6 +70,7 @@ impl Default for FixedTrace {
             address: 0,
             length: 0,
             cycles: 0,
+            jump_trace: 0,
             _threads: [0; 2 * (TRACE_ITEM_LENGTH + 1)],
         }
     }
@@ -89,6 +96,8 @@ pub struct AsmCoreMachine {
     pub load_reservation_address: u64,
     pub reset_signal: u8,
     pub isa: u8,
+    // 64 for RV64 machines, 32 for RV32 machines, see new_rv32.
+    pub xlen: u8,
     pub version: u32,
 
     pub error_arg0: u64,
@@ -103,12 +112,16 @@ pub struct AsmCoreMachine {
     pub memory_ptr: u64,
     pub flags_ptr: u64,
     pub frames_ptr: u64,
+
+    // Boxed MmapMut owning the memory pointed to by memory_ptr. It is
+    // reserved without backing it with swap, the OS only commits the pages
+    // being touched, so the memory can be much larger than the physical one.
+    pub memory_map_ptr: u64,
 }
 
 impl Drop for AsmCoreMachine {
     fn drop(&mut self) {
-        let memory_layout = Layout::array::<u8>(self.memory_size as usize).unwrap();
-        unsafe { dealloc(self.memory_ptr as *mut u8, memory_layout) };
+        unsafe { drop(Box::from_raw(self.memory_map_ptr as *mut MmapMut)) };
         let flags_layout = Layout::array::<u8>(self.flags_size as usize).unwrap();
         unsafe { dealloc(self.flags_ptr as *mut u8, flags_layout) };
         let frames_layout = Layout::array::<u8>(self.frames_size as usize).unwrap();
@@ -142,6 +155,7 @@ impl AsmCoreMachine {
         machine.load_reservation_address = u64::MAX;
         machine.version = version;
         machine.isa = isa;
+        machine.xlen = 64;
 
         machine.memory_size = memory_size as u64;
         machine.frames_size = (memory_size / MEMORY_FRAMESIZE) as u64;
@@ -150,8 +164,13 @@ impl AsmCoreMachine {
         machine.last_read_frame = u64::MAX;
         machine.last_write_page = u64::MAX;
 
-        let memory_layout = Layout::array::<u8>(machine.memory_size as usize).unwrap();
-        machine.memory_ptr = unsafe { alloc(memory_layout) } as u64;
+        let mut memory_map = MmapOptions::new()
+            .len(memory_size)
+            .no_reserve_swap()
+            .map_anon()
+            .expect("map machine memory");
+        machine.memory_ptr = memory_map.as_mut_ptr() as u64;
+        machine.memory_map_ptr = Box::into_raw(Box::new(memory_map)) as u64;
         let flags_layout = Layout::array::<u8>(machine.flags_size as usize).unwrap();
         machine.flags_ptr = unsafe { alloc_zeroed(flags_layout) } as u64;
         let frames_layout = Layout::array::<u8>(machine.frames_size as usize).unwrap();
@@ -160,6 +179,27 @@ impl AsmCoreMachine {
         machine
     }
 
+    /// Creates a machine running RV32 programs. Registers are still 64-bit
+    /// wide, they are kept sign extended from their lower 32 bits the same
+    /// way RV64 keeps the results of W instructions.
+    pub fn new_rv32(isa: u8, version: u32, max_cycles: u64) -> Box<AsmCoreMachine> {
+        Self::new_with_memory_rv32(isa, version, max_cycles, DEFAULT_MEMORY_SIZE)
+    }
+
+    pub fn new_with_memory_rv32(
+        isa: u8,
+        version: u32,
+        max_cycles: u64,
+        memory_size: usize,
+    ) -> Box<AsmCoreMachine> {
+        // Addresses held in registers are sign extended as well, memory must
+        // be addressable with non-negative values.
+        assert!(memory_size < 1 << 31);
+        let mut machine = Self::new_with_memory(isa, version, max_cycles, memory_size);
+        machine.xlen = 32;
+        machine
+    }
+
     pub fn set_max_cycles(&mut self, cycles: u64) {
         self.max_cycles = cycles;
     }
@@ -181,4 +221,19 @@ impl AsmCoreMachine {
             std::slice::from_raw_parts_mut(ptr, size)
         }
     }
+
+    /// Hands the pages backing size bytes of memory starting from addr back
+    /// to the OS, this only has an effect on Linux. The range reads as zero
+    /// afterwards, the OS maps in new pages once it is touched again.
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    pub fn release_memory(&mut self, addr: usize, size: usize) {
+        #[cfg(target_os = "linux")]
+        {
+            let memory_map = unsafe { &*(self.memory_map_ptr as *const MmapMut) };
+            // Failing to release memory only keeps it resident.
+            let _ = unsafe {
+                memory_map.unchecked_advise_range(memmap2::UncheckedAdvice::DontNeed, addr, size)
+            };
+        }
+    }
 }
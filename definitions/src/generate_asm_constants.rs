@@ -22,6 +22,57 @@ macro_rules! print_inst_label {
     };
 }
 
+macro_rules! print_rv32_inst_label {
+    ($name:ident, $real_name:ident, $code:expr) => {
+        println!(
+            "\t.long\t.CKB_VM_ASM_LABEL_{} - .CKB_VM_ASM_RV32_LABEL_TABLE",
+            rv32_label(stringify!($real_name))
+        );
+    };
+}
+
+// RV32 registers are kept sign extended by the assembly code, so most RV32
+// instructions share the handler of their RV64 W counterparts. Instructions
+// depending on the register width in other ways have their own handlers,
+// the remaining ones behave the same in RV32 and RV64.
+fn rv32_label(name: &str) -> String {
+    let handler = match name {
+        "ADD" | "ADDUW" => "ADDW",
+        "ADDI" => "ADDIW",
+        "SUB" => "SUBW",
+        "SLL" => "SLLW",
+        "SLLI" => "SLLIW",
+        "SRL" => "SRLW",
+        "SRLI" => "SRLIW",
+        "SRA" => "SRAW",
+        "SRAI" => "SRAIW",
+        "MUL" => "MULW",
+        "DIV" => "DIVW",
+        "DIVU" => "DIVUW",
+        "REM" => "REMW",
+        "REMU" => "REMUW",
+        "CLZ" => "CLZW",
+        "CTZ" => "CTZW",
+        "CPOP" => "CPOPW",
+        "ROL" => "ROLW",
+        "ROR" => "RORW",
+        "RORI" => "RORIW",
+        "SH1ADDUW" => return String::from("RV32_OP_SH1ADD"),
+        "SH2ADDUW" => return String::from("RV32_OP_SH2ADD"),
+        "SH3ADDUW" => return String::from("RV32_OP_SH3ADD"),
+        "AUIPC" | "BEQ" | "BGE" | "BGEU" | "BLT" | "BLTU" | "BNE" | "JAL" | "JALR_VERSION0"
+        | "JALR_VERSION1" | "LB_VERSION0" | "LB_VERSION1" | "LBU_VERSION0" | "LBU_VERSION1"
+        | "LH_VERSION0" | "LH_VERSION1" | "LHU_VERSION0" | "LHU_VERSION1" | "LW_VERSION0"
+        | "LW_VERSION1" | "SB" | "SH" | "SW" | "LR_W" | "SC_W" | "AMOSWAP_W" | "AMOADD_W"
+        | "AMOXOR_W" | "AMOAND_W" | "AMOOR_W" | "AMOMIN_W" | "AMOMAX_W" | "AMOMINU_W"
+        | "AMOMAXU_W" | "MULH" | "MULHSU" | "MULHU" | "BCLR" | "BCLRI" | "BEXT" | "BEXTI"
+        | "BINV" | "BINVI" | "BSET" | "BSETI" | "CLMUL" | "CLMULH" | "CLMULR" | "CLZW" | "ORCB"
+        | "REV8" | "SH1ADD" | "SH2ADD" | "SH3ADD" | "SLLIUW" => return format!("RV32_OP_{}", name),
+        _ => name,
+    };
+    format!("OP_{}", handler)
+}
+
 // This utility helps us generate C-based macros containing definitions
 // such as return code, opcode, struct size, struct offset, etc. The exact
 // data here are derived while inspecting Rust structs dynamically. We keep
@@ -107,6 +158,10 @@ fn main() {
         "#define CKB_VM_ASM_TRACE_OFFSET_CYCLES {}",
         (&t.cycles as *const u64 as usize) - t_address
     );
+    println!(
+        "#define CKB_VM_ASM_TRACE_OFFSET_JUMP_TRACE {}",
+        (&t.jump_trace as *const u64 as usize) - t_address
+    );
     println!(
         "#define CKB_VM_ASM_TRACE_OFFSET_THREADS {}",
         (&t._threads as *const u64 as usize) - t_address
@@ -225,4 +280,20 @@ fn main() {
     }
     for_each_inst!(print_inst_label);
     println!("#endif /* CKB_VM_ASM_GENERATE_LABEL_TABLES */");
+    println!();
+
+    println!("#ifdef CKB_VM_ASM_GENERATE_RV32_LABEL_TABLES");
+    println!("#ifdef __APPLE__");
+    println!(".global _ckb_vm_asm_rv32_labels");
+    println!("_ckb_vm_asm_rv32_labels:");
+    println!("#else");
+    println!(".global ckb_vm_asm_rv32_labels");
+    println!("ckb_vm_asm_rv32_labels:");
+    println!("#endif");
+    println!(".CKB_VM_ASM_RV32_LABEL_TABLE:");
+    for _ in 0..0x10 {
+        println!("\t.long\t.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE");
+    }
+    for_each_inst!(print_rv32_inst_label);
+    println!("#endif /* CKB_VM_ASM_GENERATE_RV32_LABEL_TABLES */");
 }
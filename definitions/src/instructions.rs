@@ -48,28 +48,28 @@ pub use paste::paste;
 #[doc(hidden)]
 #[macro_export]
 macro_rules! __apply {
-    ((0, $callback:ident), $(($name:ident, $code:expr)),*) => {
+    ((0, $callback:ident), $(($name:ident, $code:expr, $spec:tt)),*) => {
         $crate::instructions::paste! {
             $(
                 $callback!([< OP_ $name >], $name, $code);
             )*
         }
     };
-    ((1, $x:ident, $callback:ident), $(($name:ident, $code:expr)),*) => {
+    ((1, $x:ident, $callback:ident), $(($name:ident, $code:expr, $spec:tt)),*) => {
         $crate::instructions::paste! {
             $(
                 $callback!([< OP_ $name >], $name, $code, $x);
             )*
         }
     };
-    ((2, $x:ident, $y:ident, $callback:ident), $(($name:ident, $code:expr)),*) => {
+    ((2, $x:ident, $y:ident, $callback:ident), $(($name:ident, $code:expr, $spec:tt)),*) => {
         $crate::instructions::paste! {
             $(
                 $callback!([< OP_ $name >], $name, $code, $x, $y);
             )*
         }
     };
-    ((100, $res:ident, $val:expr, $callback:ident, $others:expr), $(($name:ident, $code:expr)),*) => {
+    ((100, $res:ident, $val:expr, $callback:ident, $others:expr), $(($name:ident, $code:expr, $spec:tt)),*) => {
         $crate::instructions::paste! {
             let $res = match $val {
                 $( $code => $callback!([< OP_ $name >], $name, $code), )*
@@ -77,7 +77,7 @@ macro_rules! __apply {
             };
         }
     };
-    ((101, $x:ident, $res:ident, $val:expr, $callback:ident, $others:expr), $(($name:ident, $code:expr)),*) => {
+    ((101, $x:ident, $res:ident, $val:expr, $callback:ident, $others:expr), $(($name:ident, $code:expr, $spec:tt)),*) => {
         $crate::instructions::paste! {
             let $res = match $val {
                 $( $code => $callback!([< OP_ $name >], $name, $code, $x), )*
@@ -85,7 +85,7 @@ macro_rules! __apply {
             };
         }
     };
-    ((102, $x:ident, $y:ident, $res:ident, $val:expr, $callback:ident, $others:expr), $(($name:ident, $code:expr)),*) => {
+    ((102, $x:ident, $y:ident, $res:ident, $val:expr, $callback:ident, $others:expr), $(($name:ident, $code:expr, $spec:tt)),*) => {
         $crate::instructions::paste! {
             let $res = match $val {
                 $( $code => $callback!([< OP_ $name >], $name, $code, $x, $y), )*
@@ -93,29 +93,54 @@ macro_rules! __apply {
             };
         }
     };
-    ((200, $res:ident, $callback:ident), $(($name:ident, $code:expr)),*) => {
+    ((200, $res:ident, $callback:ident), $(($name:ident, $code:expr, $spec:tt)),*) => {
         $crate::instructions::paste! {
             let $res = [
                 $( $callback!([< OP_ $name >], $name, $code), )*
             ];
         }
     };
-    ((201, $x:ident, $res:ident, $callback:ident), $(($name:ident, $code:expr)),*) => {
+    ((201, $x:ident, $res:ident, $callback:ident), $(($name:ident, $code:expr, $spec:tt)),*) => {
         $crate::instructions::paste! {
             let $res = [
                 $( $callback!([< OP_ $name >], $name, $code, $x), )*
             ];
         }
     };
-    ((202, $x:ident, $y:ident, $res:ident, $callback:ident), $(($name:ident, $code:expr)),*) => {
+    ((202, $x:ident, $y:ident, $res:ident, $callback:ident), $(($name:ident, $code:expr, $spec:tt)),*) => {
         $crate::instructions::paste! {
             let $res = [
                 $( $callback!([< OP_ $name >], $name, $code, $x, $y), )*
             ];
         }
     };
+    ((300, $res:ident, $callback:ident), $(($name:ident, $code:expr, $spec:tt)),*) => {
+        $crate::instructions::paste! {
+            let $res = [
+                $( $callback!([< OP_ $name >], $name, $code, $spec), )*
+            ];
+        }
+    };
 }
 
+// The single list of instructions. Besides its name and opcode number,
+// each instruction comes with a spec consumed by for_each_inst_spec_array:
+//
+// [extension, format, reads, writes, implicit writes, load bytes,
+//  store bytes, control flow, estimated cycles; encoding]
+//
+// See InstructionMetadata for the meaning of each column. The encoding is
+// the 32-bit RISC-V form the decoder recognizes, instructions never read
+// from memory (fused and internal ones) have none. RVC instructions are
+// decoded into the same opcodes by a dedicated factory.
+//
+// Opcode constants, names, decoding, cycle estimates, the interpreter
+// dispatch (execute_instruction and ThreadFactory) and the assembly label
+// tables in cdefinitions_generated.h (make update-cdefinitions) are all
+// generated from this list. What remains hand-written is the semantics of
+// an instruction: a handle_* function in execute.rs and a
+// .CKB_VM_ASM_LABEL_OP_* body in each assembly interpreter. Forgetting
+// either fails the build instead of silently dispatching elsewhere.
 #[doc(hidden)]
 #[macro_export]
 macro_rules! __for_each_inst_inner {
@@ -123,168 +148,176 @@ macro_rules! __for_each_inst_inner {
         $crate::__apply!(
             $callback,
             // IMC
-            (UNLOADED, 0x10),
-            (ADD, 0x11),
-            (ADDI, 0x12),
-            (ADDIW, 0x13),
-            (ADDW, 0x14),
-            (AND, 0x15),
-            (ANDI, 0x16),
-            (DIV, 0x17),
-            (DIVU, 0x18),
-            (DIVUW, 0x19),
-            (DIVW, 0x1a),
-            (LB_VERSION0, 0x1b),
-            (LB_VERSION1, 0x1c),
-            (LBU_VERSION0, 0x1d),
-            (LBU_VERSION1, 0x1e),
-            (LD_VERSION0, 0x1f),
-            (LD_VERSION1, 0x20),
-            (LH_VERSION0, 0x21),
-            (LH_VERSION1, 0x22),
-            (LHU_VERSION0, 0x23),
-            (LHU_VERSION1, 0x24),
-            (LUI, 0x25),
-            (LW_VERSION0, 0x26),
-            (LW_VERSION1, 0x27),
-            (LWU_VERSION0, 0x28),
-            (LWU_VERSION1, 0x29),
-            (MUL, 0x2a),
-            (MULH, 0x2b),
-            (MULHSU, 0x2c),
-            (MULHU, 0x2d),
-            (MULW, 0x2e),
-            (OR, 0x2f),
-            (ORI, 0x30),
-            (REM, 0x31),
-            (REMU, 0x32),
-            (REMUW, 0x33),
-            (REMW, 0x34),
-            (SB, 0x35),
-            (SD, 0x36),
-            (SH, 0x37),
-            (SLL, 0x38),
-            (SLLI, 0x39),
-            (SLLIW, 0x3a),
-            (SLLW, 0x3b),
-            (SLT, 0x3c),
-            (SLTI, 0x3d),
-            (SLTIU, 0x3e),
-            (SLTU, 0x3f),
-            (SRA, 0x40),
-            (SRAI, 0x41),
-            (SRAIW, 0x42),
-            (SRAW, 0x43),
-            (SRL, 0x44),
-            (SRLI, 0x45),
-            (SRLIW, 0x46),
-            (SRLW, 0x47),
-            (SUB, 0x48),
-            (SUBW, 0x49),
-            (SW, 0x4a),
-            (XOR, 0x4b),
-            (XORI, 0x4c),
+            (UNLOADED, 0x10, [Internal, Rtype, 0, 0, 0, 0, 0, None, 1]),
+            (ADD, 0x11, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_000, 0b_0000000)]),
+            (ADDI, 0x12, [I, Itype, RS1, RD, 0, 0, 0, None, 1; itype(MAJOR_OP_IMM, 0b_000)]),
+            (ADDIW, 0x13, [I, Itype, RS1, RD, 0, 0, 0, None, 1; itype(MAJOR_OP_IMM_32, 0b_000).rv64()]),
+            (ADDW, 0x14, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_32, 0b_000, 0b_0000000).rv64()]),
+            (AND, 0x15, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_111, 0b_0000000)]),
+            (ANDI, 0x16, [I, Itype, RS1, RD, 0, 0, 0, None, 1; itype(MAJOR_OP_IMM, 0b_111)]),
+            (DIV, 0x17, [M, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 32; rtype(MAJOR_OP, 0b_100, 0b_0000001)]),
+            (DIVU, 0x18, [M, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 32; rtype(MAJOR_OP, 0b_101, 0b_0000001)]),
+            (DIVUW, 0x19, [M, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 32; rtype(MAJOR_OP_32, 0b_101, 0b_0000001).rv64()]),
+            (DIVW, 0x1a, [M, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 32; rtype(MAJOR_OP_32, 0b_100, 0b_0000001).rv64()]),
+            (LB_VERSION0, 0x1b, [I, Itype, RS1, RD, 0, 1, 0, None, 3; itype(MAJOR_LOAD, 0b_000).versions(0, 0)]),
+            (LB_VERSION1, 0x1c, [I, Itype, RS1, RD, 0, 1, 0, None, 3; itype(MAJOR_LOAD, 0b_000).versions(1, u32::MAX)]),
+            (LBU_VERSION0, 0x1d, [I, Itype, RS1, RD, 0, 1, 0, None, 3; itype(MAJOR_LOAD, 0b_100).versions(0, 0)]),
+            (LBU_VERSION1, 0x1e, [I, Itype, RS1, RD, 0, 1, 0, None, 3; itype(MAJOR_LOAD, 0b_100).versions(1, u32::MAX)]),
+            (LD_VERSION0, 0x1f, [I, Itype, RS1, RD, 0, 8, 0, None, 2; itype(MAJOR_LOAD, 0b_011).rv64().versions(0, 0)]),
+            (LD_VERSION1, 0x20, [I, Itype, RS1, RD, 0, 8, 0, None, 2; itype(MAJOR_LOAD, 0b_011).rv64().versions(1, u32::MAX)]),
+            (LH_VERSION0, 0x21, [I, Itype, RS1, RD, 0, 2, 0, None, 3; itype(MAJOR_LOAD, 0b_001).versions(0, 0)]),
+            (LH_VERSION1, 0x22, [I, Itype, RS1, RD, 0, 2, 0, None, 3; itype(MAJOR_LOAD, 0b_001).versions(1, u32::MAX)]),
+            (LHU_VERSION0, 0x23, [I, Itype, RS1, RD, 0, 2, 0, None, 3; itype(MAJOR_LOAD, 0b_101).versions(0, 0)]),
+            (LHU_VERSION1, 0x24, [I, Itype, RS1, RD, 0, 2, 0, None, 3; itype(MAJOR_LOAD, 0b_101).versions(1, u32::MAX)]),
+            (LUI, 0x25, [I, Utype, 0, RD, 0, 0, 0, None, 1; utype(MAJOR_LUI)]),
+            (LW_VERSION0, 0x26, [I, Itype, RS1, RD, 0, 4, 0, None, 3; itype(MAJOR_LOAD, 0b_010).versions(0, 0)]),
+            (LW_VERSION1, 0x27, [I, Itype, RS1, RD, 0, 4, 0, None, 3; itype(MAJOR_LOAD, 0b_010).versions(1, u32::MAX)]),
+            (LWU_VERSION0, 0x28, [I, Itype, RS1, RD, 0, 4, 0, None, 3; itype(MAJOR_LOAD, 0b_110).rv64().versions(0, 0)]),
+            (LWU_VERSION1, 0x29, [I, Itype, RS1, RD, 0, 4, 0, None, 3; itype(MAJOR_LOAD, 0b_110).rv64().versions(1, u32::MAX)]),
+            (MUL, 0x2a, [M, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 5; rtype(MAJOR_OP, 0b_000, 0b_0000001)]),
+            (MULH, 0x2b, [M, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 5; rtype(MAJOR_OP, 0b_001, 0b_0000001)]),
+            (MULHSU, 0x2c, [M, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 5; rtype(MAJOR_OP, 0b_010, 0b_0000001)]),
+            (MULHU, 0x2d, [M, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 5; rtype(MAJOR_OP, 0b_011, 0b_0000001)]),
+            (MULW, 0x2e, [M, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 5; rtype(MAJOR_OP_32, 0b_000, 0b_0000001).rv64()]),
+            (OR, 0x2f, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_110, 0b_0000000)]),
+            (ORI, 0x30, [I, Itype, RS1, RD, 0, 0, 0, None, 1; itype(MAJOR_OP_IMM, 0b_110)]),
+            (REM, 0x31, [M, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 32; rtype(MAJOR_OP, 0b_110, 0b_0000001)]),
+            (REMU, 0x32, [M, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 32; rtype(MAJOR_OP, 0b_111, 0b_0000001)]),
+            (REMUW, 0x33, [M, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 32; rtype(MAJOR_OP_32, 0b_111, 0b_0000001).rv64()]),
+            (REMW, 0x34, [M, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 32; rtype(MAJOR_OP_32, 0b_110, 0b_0000001).rv64()]),
+            (SB, 0x35, [I, Stype, RS1 | RS2, 0, 0, 0, 1, None, 3; stype(0b_000)]),
+            (SD, 0x36, [I, Stype, RS1 | RS2, 0, 0, 0, 8, None, 2; stype(0b_011).rv64()]),
+            (SH, 0x37, [I, Stype, RS1 | RS2, 0, 0, 0, 2, None, 3; stype(0b_001)]),
+            (SLL, 0x38, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_001, 0b_0000000)]),
+            (SLLI, 0x39, [I, Itype, RS1, RD, 0, 0, 0, None, 1; shift(MAJOR_OP_IMM, 0b_001, 0b_000000, OperandLayout::ShamtXlen)]),
+            (SLLIW, 0x3a, [I, Itype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM_32, 0b_001, 0b_0000000).layout(OperandLayout::Shamt5).rv64()]),
+            (SLLW, 0x3b, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_32, 0b_001, 0b_0000000).rv64()]),
+            (SLT, 0x3c, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_010, 0b_0000000)]),
+            (SLTI, 0x3d, [I, Itype, RS1, RD, 0, 0, 0, None, 1; itype(MAJOR_OP_IMM, 0b_010)]),
+            (SLTIU, 0x3e, [I, Itype, RS1, RD, 0, 0, 0, None, 1; itype(MAJOR_OP_IMM, 0b_011)]),
+            (SLTU, 0x3f, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_011, 0b_0000000)]),
+            (SRA, 0x40, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_101, 0b_0100000)]),
+            (SRAI, 0x41, [I, Itype, RS1, RD, 0, 0, 0, None, 1; shift(MAJOR_OP_IMM, 0b_101, 0b_010000, OperandLayout::ShamtXlen)]),
+            (SRAIW, 0x42, [I, Itype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM_32, 0b_101, 0b_0100000).layout(OperandLayout::Shamt5).rv64()]),
+            (SRAW, 0x43, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_32, 0b_101, 0b_0100000).rv64()]),
+            (SRL, 0x44, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_101, 0b_0000000)]),
+            (SRLI, 0x45, [I, Itype, RS1, RD, 0, 0, 0, None, 1; shift(MAJOR_OP_IMM, 0b_101, 0b_000000, OperandLayout::ShamtXlen)]),
+            (SRLIW, 0x46, [I, Itype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM_32, 0b_101, 0b_0000000).layout(OperandLayout::Shamt5).rv64()]),
+            (SRLW, 0x47, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_32, 0b_101, 0b_0000000).rv64()]),
+            (SUB, 0x48, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_000, 0b_0100000)]),
+            (SUBW, 0x49, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_32, 0b_000, 0b_0100000).rv64()]),
+            (SW, 0x4a, [I, Stype, RS1 | RS2, 0, 0, 0, 4, None, 3; stype(0b_010)]),
+            (XOR, 0x4b, [I, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_100, 0b_0000000)]),
+            (XORI, 0x4c, [I, Itype, RS1, RD, 0, 0, 0, None, 1; itype(MAJOR_OP_IMM, 0b_100)]),
             // A
-            (LR_W, 0x4d),
-            (SC_W, 0x4e),
-            (AMOSWAP_W, 0x4f),
-            (AMOADD_W, 0x50),
-            (AMOXOR_W, 0x51),
-            (AMOAND_W, 0x52),
-            (AMOOR_W, 0x53),
-            (AMOMIN_W, 0x54),
-            (AMOMAX_W, 0x55),
-            (AMOMINU_W, 0x56),
-            (AMOMAXU_W, 0x57),
-            (LR_D, 0x58),
-            (SC_D, 0x59),
-            (AMOSWAP_D, 0x5a),
-            (AMOADD_D, 0x5b),
-            (AMOXOR_D, 0x5c),
-            (AMOAND_D, 0x5d),
-            (AMOOR_D, 0x5e),
-            (AMOMIN_D, 0x5f),
-            (AMOMAX_D, 0x60),
-            (AMOMINU_D, 0x61),
-            (AMOMAXU_D, 0x62),
+            (LR_W, 0x4d, [A, Rtype, RS1, RD, 0, 4, 0, None, 4; amo(0b_010, 0b_00010).rs2(0)]),
+            (SC_W, 0x4e, [A, Rtype, RS1 | RS2, RD, 0, 0, 4, None, 4; amo(0b_010, 0b_00011)]),
+            (AMOSWAP_W, 0x4f, [A, Rtype, RS1 | RS2, RD, 0, 4, 4, None, 4; amo(0b_010, 0b_00001)]),
+            (AMOADD_W, 0x50, [A, Rtype, RS1 | RS2, RD, 0, 4, 4, None, 4; amo(0b_010, 0b_00000)]),
+            (AMOXOR_W, 0x51, [A, Rtype, RS1 | RS2, RD, 0, 4, 4, None, 4; amo(0b_010, 0b_00100)]),
+            (AMOAND_W, 0x52, [A, Rtype, RS1 | RS2, RD, 0, 4, 4, None, 4; amo(0b_010, 0b_01100)]),
+            (AMOOR_W, 0x53, [A, Rtype, RS1 | RS2, RD, 0, 4, 4, None, 4; amo(0b_010, 0b_01000)]),
+            (AMOMIN_W, 0x54, [A, Rtype, RS1 | RS2, RD, 0, 4, 4, None, 4; amo(0b_010, 0b_10000)]),
+            (AMOMAX_W, 0x55, [A, Rtype, RS1 | RS2, RD, 0, 4, 4, None, 4; amo(0b_010, 0b_10100)]),
+            (AMOMINU_W, 0x56, [A, Rtype, RS1 | RS2, RD, 0, 4, 4, None, 4; amo(0b_010, 0b_11000)]),
+            (AMOMAXU_W, 0x57, [A, Rtype, RS1 | RS2, RD, 0, 4, 4, None, 4; amo(0b_010, 0b_11100)]),
+            (LR_D, 0x58, [A, Rtype, RS1, RD, 0, 8, 0, None, 3; amo(0b_011, 0b_00010).rs2(0).rv64()]),
+            (SC_D, 0x59, [A, Rtype, RS1 | RS2, RD, 0, 0, 8, None, 3; amo(0b_011, 0b_00011).rv64()]),
+            (AMOSWAP_D, 0x5a, [A, Rtype, RS1 | RS2, RD, 0, 8, 8, None, 3; amo(0b_011, 0b_00001).rv64()]),
+            (AMOADD_D, 0x5b, [A, Rtype, RS1 | RS2, RD, 0, 8, 8, None, 3; amo(0b_011, 0b_00000).rv64()]),
+            (AMOXOR_D, 0x5c, [A, Rtype, RS1 | RS2, RD, 0, 8, 8, None, 3; amo(0b_011, 0b_00100).rv64()]),
+            (AMOAND_D, 0x5d, [A, Rtype, RS1 | RS2, RD, 0, 8, 8, None, 3; amo(0b_011, 0b_01100).rv64()]),
+            (AMOOR_D, 0x5e, [A, Rtype, RS1 | RS2, RD, 0, 8, 8, None, 3; amo(0b_011, 0b_01000).rv64()]),
+            (AMOMIN_D, 0x5f, [A, Rtype, RS1 | RS2, RD, 0, 8, 8, None, 3; amo(0b_011, 0b_10000).rv64()]),
+            (AMOMAX_D, 0x60, [A, Rtype, RS1 | RS2, RD, 0, 8, 8, None, 3; amo(0b_011, 0b_10100).rv64()]),
+            (AMOMINU_D, 0x61, [A, Rtype, RS1 | RS2, RD, 0, 8, 8, None, 3; amo(0b_011, 0b_11000).rv64()]),
+            (AMOMAXU_D, 0x62, [A, Rtype, RS1 | RS2, RD, 0, 8, 8, None, 3; amo(0b_011, 0b_11100).rv64()]),
             // B
-            (ADDUW, 0x63),
-            (ANDN, 0x64),
-            (BCLR, 0x65),
-            (BCLRI, 0x66),
-            (BEXT, 0x67),
-            (BEXTI, 0x68),
-            (BINV, 0x69),
-            (BINVI, 0x6a),
-            (BSET, 0x6b),
-            (BSETI, 0x6c),
-            (CLMUL, 0x6d),
-            (CLMULH, 0x6e),
-            (CLMULR, 0x6f),
-            (CLZ, 0x70),
-            (CLZW, 0x71),
-            (CPOP, 0x72),
-            (CPOPW, 0x73),
-            (CTZ, 0x74),
-            (CTZW, 0x75),
-            (MAX, 0x76),
-            (MAXU, 0x77),
-            (MIN, 0x78),
-            (MINU, 0x79),
-            (ORCB, 0x7a),
-            (ORN, 0x7b),
-            (REV8, 0x7c),
-            (ROL, 0x7d),
-            (ROLW, 0x7e),
-            (ROR, 0x7f),
-            (RORI, 0x80),
-            (RORIW, 0x81),
-            (RORW, 0x82),
-            (SEXTB, 0x83),
-            (SEXTH, 0x84),
-            (SH1ADD, 0x85),
-            (SH1ADDUW, 0x86),
-            (SH2ADD, 0x87),
-            (SH2ADDUW, 0x88),
-            (SH3ADD, 0x89),
-            (SH3ADDUW, 0x8a),
-            (SLLIUW, 0x8b),
-            (XNOR, 0x8c),
-            (ZEXTH, 0x8d),
+            (ADDUW, 0x63, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_32, 0b_000, 0b_0000100)]),
+            (ANDN, 0x64, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_111, 0b_0100000)]),
+            (BCLR, 0x65, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_001, 0b_0100100)]),
+            (BCLRI, 0x66, [B, Itype, RS1, RD, 0, 0, 0, None, 1; shift(MAJOR_OP_IMM, 0b_001, 0b_010010, OperandLayout::Shamt6)]),
+            (BEXT, 0x67, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_101, 0b_0100100)]),
+            (BEXTI, 0x68, [B, Itype, RS1, RD, 0, 0, 0, None, 1; shift(MAJOR_OP_IMM, 0b_101, 0b_010010, OperandLayout::Shamt6)]),
+            (BINV, 0x69, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_001, 0b_0110100)]),
+            (BINVI, 0x6a, [B, Itype, RS1, RD, 0, 0, 0, None, 1; shift(MAJOR_OP_IMM, 0b_001, 0b_011010, OperandLayout::Shamt6)]),
+            (BSET, 0x6b, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_001, 0b_0010100)]),
+            (BSETI, 0x6c, [B, Itype, RS1, RD, 0, 0, 0, None, 1; shift(MAJOR_OP_IMM, 0b_001, 0b_001010, OperandLayout::Shamt6)]),
+            (CLMUL, 0x6d, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_001, 0b_0000101)]),
+            (CLMULH, 0x6e, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_011, 0b_0000101)]),
+            (CLMULR, 0x6f, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_010, 0b_0000101)]),
+            (CLZ, 0x70, [B, Rtype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM, 0b_001, 0b_0110000).rs2(0b_00000)]),
+            (CLZW, 0x71, [B, Rtype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM_32, 0b_001, 0b_0110000).rs2(0b_00000)]),
+            (CPOP, 0x72, [B, Rtype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM, 0b_001, 0b_0110000).rs2(0b_00010)]),
+            (CPOPW, 0x73, [B, Rtype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM_32, 0b_001, 0b_0110000).rs2(0b_00010)]),
+            (CTZ, 0x74, [B, Rtype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM, 0b_001, 0b_0110000).rs2(0b_00001)]),
+            (CTZW, 0x75, [B, Rtype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM_32, 0b_001, 0b_0110000).rs2(0b_00001)]),
+            (MAX, 0x76, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_110, 0b_0000101)]),
+            (MAXU, 0x77, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_111, 0b_0000101)]),
+            (MIN, 0x78, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_100, 0b_0000101)]),
+            (MINU, 0x79, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_101, 0b_0000101)]),
+            (ORCB, 0x7a, [B, Rtype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM, 0b_101, 0b_0010100).rs2(0b_00111)]),
+            (ORN, 0x7b, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_110, 0b_0100000)]),
+            (REV8, 0x7c, [B, Rtype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM, 0b_101, 0b_0110101).rs2(0b_11000)]),
+            (ROL, 0x7d, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_001, 0b_0110000)]),
+            (ROLW, 0x7e, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_32, 0b_001, 0b_0110000)]),
+            (ROR, 0x7f, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_101, 0b_0110000)]),
+            (RORI, 0x80, [B, Itype, RS1, RD, 0, 0, 0, None, 1; shift(MAJOR_OP_IMM, 0b_101, 0b_011000, OperandLayout::Shamt6)]),
+            (RORIW, 0x81, [B, Itype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM_32, 0b_101, 0b_0110000).layout(OperandLayout::Shamt5)]),
+            (RORW, 0x82, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_32, 0b_101, 0b_0110000)]),
+            (SEXTB, 0x83, [B, Rtype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM, 0b_001, 0b_0110000).rs2(0b_00100)]),
+            (SEXTH, 0x84, [B, Rtype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_IMM, 0b_001, 0b_0110000).rs2(0b_00101)]),
+            (SH1ADD, 0x85, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_010, 0b_0010000)]),
+            (SH1ADDUW, 0x86, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_32, 0b_010, 0b_0010000)]),
+            (SH2ADD, 0x87, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_100, 0b_0010000)]),
+            (SH2ADDUW, 0x88, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_32, 0b_100, 0b_0010000)]),
+            (SH3ADD, 0x89, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_110, 0b_0010000)]),
+            (SH3ADDUW, 0x8a, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_32, 0b_110, 0b_0010000)]),
+            (SLLIUW, 0x8b, [B, Itype, RS1, RD, 0, 0, 0, None, 1; shift(MAJOR_OP_IMM_32, 0b_001, 0b_000010, OperandLayout::Shamt6)]),
+            (XNOR, 0x8c, [B, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP, 0b_100, 0b_0100000)]),
+            (ZEXTH, 0x8d, [B, Rtype, RS1, RD, 0, 0, 0, None, 1; rtype(MAJOR_OP_32, 0b_100, 0b_0000100).rs2(0b_00000).rv64()]),
             // Mop
-            (WIDE_MUL, 0x8e),
-            (WIDE_MULU, 0x8f),
-            (WIDE_MULSU, 0x90),
-            (WIDE_DIV, 0x91),
-            (WIDE_DIVU, 0x92),
-            (ADC, 0x93),
-            (SBB, 0x94),
-            (ADCS, 0x95),
-            (SBBS, 0x96),
-            (ADD3A, 0x97),
-            (ADD3B, 0x98),
-            (ADD3C, 0x99),
-            (CUSTOM_LOAD_UIMM, 0x9a),
-            (CUSTOM_LOAD_IMM, 0x9b),
+            (WIDE_MUL, 0x8e, [Mop, R4type, RS1 | RS2, RD | RS3, 0, 0, 0, None, 5]),
+            (WIDE_MULU, 0x8f, [Mop, R4type, RS1 | RS2, RD | RS3, 0, 0, 0, None, 5]),
+            (WIDE_MULSU, 0x90, [Mop, R4type, RS1 | RS2, RD | RS3, 0, 0, 0, None, 5]),
+            (WIDE_DIV, 0x91, [Mop, R4type, RS1 | RS2, RD | RS3, 0, 0, 0, None, 32]),
+            (WIDE_DIVU, 0x92, [Mop, R4type, RS1 | RS2, RD | RS3, 0, 0, 0, None, 32]),
+            (ADC, 0x93, [Mop, Rtype, RD | RS1 | RS2, RD | RS1 | RS2, 0, 0, 0, None, 1]),
+            (SBB, 0x94, [Mop, R4type, RD | RS1 | RS2, RD | RS1 | RS2 | RS3, 0, 0, 0, None, 1]),
+            (ADCS, 0x95, [Mop, R4type, RS1 | RS2, RD | RS3, 0, 0, 0, None, 1]),
+            (SBBS, 0x96, [Mop, R4type, RS1 | RS2, RD | RS3, 0, 0, 0, None, 1]),
+            (ADD3A, 0x97, [Mop, R5type, RD | RS1 | RS4, RD | RS2 | RS3, 0, 0, 0, None, 1]),
+            (ADD3B, 0x98, [Mop, R5type, RS1 | RS2 | RS4, RD | RS1 | RS3, 0, 0, 0, None, 1]),
+            (ADD3C, 0x99, [Mop, R5type, RS1 | RS2 | RS4, RD | RS3, 0, 0, 0, None, 1]),
+            (CUSTOM_LOAD_UIMM, 0x9a, [Mop, Utype, 0, RD, 0, 0, 0, None, 1]),
+            (CUSTOM_LOAD_IMM, 0x9b, [Mop, Utype, 0, RD, 0, 0, 0, None, 1]),
+            (SLLI_ADD, 0x9c, [Mop, Rtype, RS1 | RS2, RD, 0, 0, 0, None, 1]),
+            (SLLI_ADDI, 0x9d, [Mop, Itype, RS1, RD, 0, 0, 0, None, 1]),
+            (LD_PAIR, 0x9e, [Mop, Rtype, RS1, RD | RS2, 0, 16, 0, None, 3]),
+            (SD_PAIR, 0x9f, [Mop, Rtype, RD | RS1 | RS2, 0, 0, 0, 16, None, 3]),
             // All branches
-            (AUIPC, 0x9c),
-            (BEQ, 0x9d),
-            (BGE, 0x9e),
-            (BGEU, 0x9f),
-            (BLT, 0xa0),
-            (BLTU, 0xa1),
-            (BNE, 0xa2),
-            (EBREAK, 0xa3),
-            (ECALL, 0xa4),
-            (FENCE, 0xa5),
-            (FENCEI, 0xa6),
-            (JAL, 0xa7),
-            (JALR_VERSION0, 0xa8),
-            (JALR_VERSION1, 0xa9),
-            (FAR_JUMP_REL, 0xaa),
-            (FAR_JUMP_ABS, 0xab),
-            (CUSTOM_ASM_TRACE_JUMP, 0xac),
-            (CUSTOM_TRACE_END, 0xad)
+            (AUIPC, 0xa0, [I, Utype, 0, RD, 0, 0, 0, None, 1; utype(MAJOR_AUIPC)]),
+            (BEQ, 0xa1, [I, Stype, RS1 | RS2, 0, 0, 0, 0, Branch, 3; btype(0b_000)]),
+            (BGE, 0xa2, [I, Stype, RS1 | RS2, 0, 0, 0, 0, Branch, 3; btype(0b_101)]),
+            (BGEU, 0xa3, [I, Stype, RS1 | RS2, 0, 0, 0, 0, Branch, 3; btype(0b_111)]),
+            (BLT, 0xa4, [I, Stype, RS1 | RS2, 0, 0, 0, 0, Branch, 3; btype(0b_100)]),
+            (BLTU, 0xa5, [I, Stype, RS1 | RS2, 0, 0, 0, 0, Branch, 3; btype(0b_110)]),
+            (BNE, 0xa6, [I, Stype, RS1 | RS2, 0, 0, 0, 0, Branch, 3; btype(0b_001)]),
+            (EBREAK, 0xa7, [I, Rtype, 0, 0, 0, 0, 0, System, 500; exact(0b_000000000001_00000_000_00000_1110011)]),
+            (ECALL, 0xa8, [I, Rtype, 0, 0, 0, 0, 0, System, 500; exact(0b_000000000000_00000_000_00000_1110011)]),
+            (FENCE, 0xa9, [I, Rtype, 0, 0, 0, 0, 0, System, 1; fence()]),
+            (FENCEI, 0xaa, [I, Rtype, 0, 0, 0, 0, 0, System, 1; exact(0b_0000_0000_0000_00000_001_00000_0001111)]),
+            (JAL, 0xab, [I, Utype, 0, RD, 0, 0, 0, Jump, 3; jtype()]),
+            (JALR_VERSION0, 0xac, [I, Itype, RS1, RD, 0, 0, 0, Jump, 3; itype(MAJOR_JALR, 0b_000).versions(0, 0)]),
+            (JALR_VERSION1, 0xad, [I, Itype, RS1, RD, 0, 0, 0, Jump, 3; itype(MAJOR_JALR, 0b_000).versions(1, u32::MAX)]),
+            (FAR_JUMP_REL, 0xae, [Mop, Utype, 0, 0, RA, 0, 0, Jump, 3]),
+            (FAR_JUMP_ABS, 0xaf, [Mop, Utype, 0, 0, RA, 0, 0, Jump, 3]),
+            (SLT_BNEZ, 0xb0, [Mop, Rtype, RS1 | RS2, RD, 0, 0, 0, Branch, 3]),
+            (SLT_BEQZ, 0xb1, [Mop, Rtype, RS1 | RS2, RD, 0, 0, 0, Branch, 3]),
+            (SLTU_BNEZ, 0xb2, [Mop, Rtype, RS1 | RS2, RD, 0, 0, 0, Branch, 3]),
+            (SLTU_BEQZ, 0xb3, [Mop, Rtype, RS1 | RS2, RD, 0, 0, 0, Branch, 3]),
+            (CUSTOM_ASM_TRACE_JUMP, 0xb4, [Internal, Rtype, 0, 0, 0, 0, 0, Jump, 1]),
+            (CUSTOM_TRACE_END, 0xb5, [Internal, Rtype, 0, 0, 0, 0, 0, None, 1])
         );
     };
 }
@@ -384,6 +417,19 @@ macro_rules! for_each_inst_array2 {
     }};
 }
 
+/// Generates an array expression on all instructions, in opcode order, the
+/// callback macro receives the spec of the instruction as a fourth argument
+/// in the form of [extension, format, reads, writes, implicit_writes,
+/// load_bytes, store_bytes, control, cycles; encoding], the encoding being
+/// optional.
+#[macro_export]
+macro_rules! for_each_inst_spec_array {
+    ($callback:ident) => {{
+        $crate::__for_each_inst_inner!((300, __res__, $callback));
+        __res__
+    }};
+}
+
 // Define the actual opcodes
 macro_rules! define_instruction {
     ($name:ident, $real_name:ident, $code:expr) => {
@@ -396,7 +442,7 @@ pub const MINIMAL_OPCODE: InstructionOpcode = OP_UNLOADED;
 pub const MAXIMUM_OPCODE: InstructionOpcode = OP_CUSTOM_TRACE_END;
 
 pub const MINIMAL_BASIC_BLOCK_END_OPCODE: InstructionOpcode = OP_AUIPC;
-pub const MAXIMUM_BASIC_BLOCK_END_OPCODE: InstructionOpcode = OP_FAR_JUMP_ABS;
+pub const MAXIMUM_BASIC_BLOCK_END_OPCODE: InstructionOpcode = OP_SLTU_BEQZ;
 
 macro_rules! inst_real_name {
     ($name:ident, $real_name:ident, $code:expr) => {
@@ -407,3 +453,399 @@ macro_rules! inst_real_name {
 pub fn instruction_opcode_name(i: InstructionOpcode) -> &'static str {
     for_each_inst_match!(inst_real_name, i, "UNKNOWN_INSTRUCTION!")
 }
+
+/// The extension an opcode belongs to. RVC instructions decode into the
+/// same opcodes as their full size counterparts, hence have no entry here.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Extension {
+    I,
+    M,
+    A,
+    B,
+    Mop,
+    // Opcodes only used internally by the machines, never decoded.
+    Internal,
+}
+
+/// Internal encoding of an opcode, naming the type used to access its
+/// fields, e.g. Itype.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InstructionFormat {
+    Rtype,
+    R4type,
+    R5type,
+    Itype,
+    Stype,
+    Utype,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ControlFlow {
+    None,
+    // Conditional branches.
+    Branch,
+    // Unconditional jumps, including JALR and fused far jumps.
+    Jump,
+    // ECALL, EBREAK and fences.
+    System,
+}
+
+// Register operands, named after the accessors of the instruction format:
+// for an Stype instruction OPERAND_RS2 means Stype::rs2.
+pub const OPERAND_RD: u8 = 1 << 0;
+pub const OPERAND_RS1: u8 = 1 << 1;
+pub const OPERAND_RS2: u8 = 1 << 2;
+pub const OPERAND_RS3: u8 = 1 << 3;
+pub const OPERAND_RS4: u8 = 1 << 4;
+
+/// Operands of a 32-bit encoding, each layout is extracted the same way by
+/// the decoder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OperandLayout {
+    // rd, rs1 and rs2.
+    R,
+    // rd, rs1 and a 12-bit signed immediate.
+    I,
+    // rd, rs1 and a shift amount as wide as the register width needs, the
+    // highest bit of the field is ignored on RV32.
+    ShamtXlen,
+    // rd, rs1 and a 5-bit shift amount.
+    Shamt5,
+    // rd, rs1 and a 6-bit shift amount.
+    Shamt6,
+    // rs1, rs2 and a 12-bit signed immediate.
+    S,
+    // rs1, rs2 and a 13-bit signed branch offset.
+    B,
+    // rd and an immediate with the lower 12 bits cleared.
+    U,
+    // rd and a 21-bit signed jump offset.
+    J,
+    // fm, pred and succ, kept in rd, rs1 and rs2, see FenceType.
+    Fence,
+    // No operands at all.
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Encoding {
+    // Bits fixed by the encoding and their values.
+    pub mask: u32,
+    pub value: u32,
+    pub layout: OperandLayout,
+    // Only decoded on RV64.
+    pub rv64_only: bool,
+    // Machine versions decoding into this opcode, bounds included.
+    pub min_version: u32,
+    pub max_version: u32,
+}
+
+impl Encoding {
+    pub const fn new(mask: u32, value: u32, layout: OperandLayout) -> Self {
+        Self {
+            mask,
+            value,
+            layout,
+            rv64_only: false,
+            min_version: 0,
+            max_version: u32::MAX,
+        }
+    }
+
+    #[inline(always)]
+    pub fn matches(&self, bits: u32, rv64: bool, version: u32) -> bool {
+        bits & self.mask == self.value
+            && (rv64 || !self.rv64_only)
+            && (self.min_version..=self.max_version).contains(&version)
+    }
+
+    const fn rs2(mut self, rs2: u32) -> Self {
+        self.mask |= 0x1F << 20;
+        self.value |= rs2 << 20;
+        self
+    }
+
+    const fn layout(mut self, layout: OperandLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    const fn rv64(mut self) -> Self {
+        self.rv64_only = true;
+        self
+    }
+
+    const fn versions(mut self, min_version: u32, max_version: u32) -> Self {
+        self.min_version = min_version;
+        self.max_version = max_version;
+        self
+    }
+}
+
+// Major opcodes of the 32-bit encodings.
+const MAJOR_LOAD: u32 = 0b_0000011;
+const MAJOR_OP_IMM: u32 = 0b_0010011;
+const MAJOR_AUIPC: u32 = 0b_0010111;
+const MAJOR_OP_IMM_32: u32 = 0b_0011011;
+const MAJOR_OP: u32 = 0b_0110011;
+const MAJOR_LUI: u32 = 0b_0110111;
+const MAJOR_OP_32: u32 = 0b_0111011;
+const MAJOR_JALR: u32 = 0b_1100111;
+
+const fn rtype(major: u32, funct3: u32, funct7: u32) -> Encoding {
+    Encoding::new(
+        0xFE00_707F,
+        (funct7 << 25) | (funct3 << 12) | major,
+        OperandLayout::R,
+    )
+}
+
+const fn itype(major: u32, funct3: u32) -> Encoding {
+    Encoding::new(0x0000_707F, (funct3 << 12) | major, OperandLayout::I)
+}
+
+// Shift by an immediate, identified by the upper 6 bits of the immediate.
+const fn shift(major: u32, funct3: u32, funct6: u32, layout: OperandLayout) -> Encoding {
+    Encoding::new(0xFC00_707F, (funct6 << 26) | (funct3 << 12) | major, layout)
+}
+
+const fn stype(funct3: u32) -> Encoding {
+    Encoding::new(0x0000_707F, (funct3 << 12) | 0b_0100011, OperandLayout::S)
+}
+
+const fn btype(funct3: u32) -> Encoding {
+    Encoding::new(0x0000_707F, (funct3 << 12) | 0b_1100011, OperandLayout::B)
+}
+
+const fn utype(major: u32) -> Encoding {
+    Encoding::new(0x0000_007F, major, OperandLayout::U)
+}
+
+const fn jtype() -> Encoding {
+    Encoding::new(0x0000_007F, 0b_1101111, OperandLayout::J)
+}
+
+// aq and rl are ignored.
+const fn amo(funct3: u32, funct5: u32) -> Encoding {
+    Encoding::new(
+        0xF800_707F,
+        (funct5 << 27) | (funct3 << 12) | 0b_0101111,
+        OperandLayout::R,
+    )
+}
+
+const fn fence() -> Encoding {
+    Encoding::new(
+        0x000F_FFFF,
+        0b_00000_000_00000_0001111,
+        OperandLayout::Fence,
+    )
+}
+
+const fn exact(bits: u32) -> Encoding {
+    Encoding::new(0xFFFF_FFFF, bits, OperandLayout::None)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct InstructionMetadata {
+    pub opcode: InstructionOpcode,
+    pub name: &'static str,
+    pub extension: Extension,
+    pub format: InstructionFormat,
+    // Register operands whose initial value is used, as OPERAND_* flags.
+    pub reads: u8,
+    // Register operands written, as OPERAND_* flags.
+    pub writes: u8,
+    // Registers written regardless of the operands, one bit per register
+    // index, e.g. ra for fused far jumps.
+    pub implicit_writes: u32,
+    // Bytes read from and written to memory, 0 when there is no access.
+    // Atomic memory operations do both.
+    pub load_bytes: u8,
+    pub store_bytes: u8,
+    pub control: ControlFlow,
+    // Cycles charged by the estimate_cycles cost model.
+    pub cycles: u64,
+    pub encoding: Option<Encoding>,
+}
+
+impl InstructionMetadata {
+    pub fn is_load(&self) -> bool {
+        self.load_bytes > 0
+    }
+
+    pub fn is_store(&self) -> bool {
+        self.store_bytes > 0
+    }
+}
+
+const RD: u8 = OPERAND_RD;
+const RS1: u8 = OPERAND_RS1;
+const RS2: u8 = OPERAND_RS2;
+const RS3: u8 = OPERAND_RS3;
+const RS4: u8 = OPERAND_RS4;
+const RA: u32 = 1 << crate::registers::RA;
+
+macro_rules! optional {
+    () => {
+        None
+    };
+    ($value:expr) => {
+        Some($value)
+    };
+}
+
+macro_rules! inst_metadata {
+    ($name:ident, $real_name:ident, $code:expr, [$extension:ident, $format:ident, $reads:expr, $writes:expr, $implicit_writes:expr, $load_bytes:expr, $store_bytes:expr, $control:ident, $cycles:expr $(; $encoding:expr)?]) => {
+        InstructionMetadata {
+            opcode: $name,
+            name: stringify!($real_name),
+            extension: Extension::$extension,
+            format: InstructionFormat::$format,
+            reads: $reads,
+            writes: $writes,
+            implicit_writes: $implicit_writes,
+            load_bytes: $load_bytes,
+            store_bytes: $store_bytes,
+            control: ControlFlow::$control,
+            cycles: $cycles,
+            encoding: optional!($($encoding)?),
+        }
+    };
+}
+
+const OPCODE_COUNT: usize = (MAXIMUM_OPCODE - MINIMAL_OPCODE + 1) as usize;
+const METADATA: [InstructionMetadata; OPCODE_COUNT] = for_each_inst_spec_array!(inst_metadata);
+
+/// Metadata of all opcodes, indexed by opcode - MINIMAL_OPCODE.
+pub static INSTRUCTION_METADATA: [InstructionMetadata; OPCODE_COUNT] = METADATA;
+
+/// Metadata of an opcode, None for values outside of the opcode range.
+pub fn instruction_metadata(i: InstructionOpcode) -> Option<&'static InstructionMetadata> {
+    if (MINIMAL_OPCODE..=MAXIMUM_OPCODE).contains(&i) {
+        Some(&INSTRUCTION_METADATA[(i - MINIMAL_OPCODE) as usize])
+    } else {
+        None
+    }
+}
+
+// Extensions with 32-bit encodings are the leading variants of Extension.
+const ENCODED_EXTENSIONS: usize = Extension::Mop as usize;
+
+// Bits [6:2] of the major opcode, bits [1:0] are always set for 32-bit
+// encodings.
+const fn major_index(bits: u32) -> usize {
+    ((bits >> 2) & 0x1F) as usize
+}
+
+const fn bucket_index(extension: Extension, bits: u32) -> usize {
+    extension as usize * 32 + major_index(bits)
+}
+
+const ENCODED_COUNT: usize = {
+    let mut count = 0;
+    let mut i = 0;
+    while i < OPCODE_COUNT {
+        if METADATA[i].encoding.is_some() {
+            count += 1;
+        }
+        i += 1;
+    }
+    count
+};
+
+// Start of each bucket in DECODING_TABLE, the next one marking its end.
+const DECODING_OFFSETS: [usize; ENCODED_EXTENSIONS * 32 + 1] = {
+    let mut offsets = [0; ENCODED_EXTENSIONS * 32 + 1];
+    let mut i = 0;
+    while i < OPCODE_COUNT {
+        if let Some(encoding) = METADATA[i].encoding {
+            offsets[bucket_index(METADATA[i].extension, encoding.value) + 1] += 1;
+        }
+        i += 1;
+    }
+    let mut bucket = 1;
+    while bucket < offsets.len() {
+        offsets[bucket] += offsets[bucket - 1];
+        bucket += 1;
+    }
+    offsets
+};
+
+// Encodings grouped by extension and major opcode, kept apart from the
+// metadata so each decoding only walks a few compact entries.
+static DECODING_TABLE: [(InstructionOpcode, Encoding); ENCODED_COUNT] = {
+    let mut table = [(0, Encoding::new(0, 0, OperandLayout::None)); ENCODED_COUNT];
+    let mut filled = [0; ENCODED_EXTENSIONS * 32];
+    let mut i = 0;
+    while i < OPCODE_COUNT {
+        if let Some(encoding) = METADATA[i].encoding {
+            let bucket = bucket_index(METADATA[i].extension, encoding.value);
+            table[DECODING_OFFSETS[bucket] + filled[bucket]] = (METADATA[i].opcode, encoding);
+            filled[bucket] += 1;
+        }
+        i += 1;
+    }
+    table
+};
+
+/// Opcodes of an extension whose encoding shares the major opcode of bits,
+/// the candidates to check with Encoding::matches when decoding bits.
+#[inline(always)]
+pub fn decoding_candidates(
+    extension: Extension,
+    bits: u32,
+) -> &'static [(InstructionOpcode, Encoding)] {
+    if extension as usize >= ENCODED_EXTENSIONS {
+        return &[];
+    }
+    let bucket = bucket_index(extension, bits);
+    &DECODING_TABLE[DECODING_OFFSETS[bucket]..DECODING_OFFSETS[bucket + 1]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! check_metadata {
+        ($name:ident, $real_name:ident, $code:expr) => {
+            let metadata = instruction_metadata($name).unwrap();
+            assert_eq!(metadata.opcode, $name);
+            assert_eq!(metadata.name, stringify!($real_name));
+        };
+    }
+
+    #[test]
+    fn test_metadata_covers_all_opcodes() {
+        for_each_inst!(check_metadata);
+        for (index, metadata) in INSTRUCTION_METADATA.iter().enumerate() {
+            assert_eq!(metadata.opcode, MINIMAL_OPCODE + index as InstructionOpcode);
+        }
+        assert!(instruction_metadata(MINIMAL_OPCODE - 1).is_none());
+        assert!(instruction_metadata(MAXIMUM_OPCODE + 1).is_none());
+    }
+
+    #[test]
+    fn test_encodings_are_disjoint() {
+        let encoded: Vec<_> = INSTRUCTION_METADATA
+            .iter()
+            .filter_map(|m| m.encoding.map(|e| (m, e)))
+            .collect();
+        for (i, (a, x)) in encoded.iter().enumerate() {
+            assert_eq!(x.value & !x.mask, 0, "{}", a.name);
+            assert_eq!(x.value & 0b11, 0b11, "{}", a.name);
+            assert!(
+                decoding_candidates(a.extension, x.value).contains(&(a.opcode, *x)),
+                "{}",
+                a.name
+            );
+            for (b, y) in &encoded[i + 1..] {
+                // Both fixed bit patterns agree on the common bits.
+                let overlap = (x.value ^ y.value) & x.mask & y.mask == 0;
+                let versions = x.min_version <= y.max_version && y.min_version <= x.max_version;
+                assert!(!(overlap && versions), "{} overlaps {}", a.name, b.name);
+            }
+        }
+        assert_eq!(encoded.len(), 139);
+    }
+}
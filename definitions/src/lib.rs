@@ -6,6 +6,8 @@ pub mod registers;
 pub const RISCV_PAGE_SHIFTS: usize = 12;
 pub const RISCV_PAGESIZE: usize = 1 << RISCV_PAGE_SHIFTS;
 pub const RISCV_GENERAL_REGISTER_NUMBER: usize = 32;
+// Registers x0-x15 available to the embedded base ISA, see ISA_E.
+pub const RISCV_EMBEDDED_REGISTER_NUMBER: usize = 16;
 pub const MEMORY_FRAME_SHIFTS: usize = 18;
 pub const MEMORY_FRAMESIZE: usize = 1 << MEMORY_FRAME_SHIFTS; // 256 KB
 pub const MEMORY_FRAME_PAGE_SHIFTS: usize = MEMORY_FRAME_SHIFTS - RISCV_PAGE_SHIFTS;
@@ -16,3 +18,5 @@ pub const ISA_IMC: u8 = 0b0000_0000;
 pub const ISA_B: u8 = 0b0000_0001;
 pub const ISA_MOP: u8 = 0b0000_0010;
 pub const ISA_A: u8 = 0b0000_0100;
+// RV32E/RV64E base ISA, only registers x0-x15 are available.
+pub const ISA_E: u8 = 0b0000_1000;
@@ -1,5 +1,5 @@
 use ckb_vm::cost_model::estimate_cycles;
-use ckb_vm::registers::{A0, A7};
+use ckb_vm::registers::A0;
 use ckb_vm::{Bytes, CoreMachine, Memory, Register, SupportMachine, Syscalls};
 
 pub struct DebugSyscall {}
@@ -10,8 +10,7 @@ impl<Mac: SupportMachine> Syscalls<Mac> for DebugSyscall {
     }
 
     fn ecall(&mut self, machine: &mut Mac) -> Result<bool, ckb_vm::error::Error> {
-        let code = &machine.registers()[A7];
-        if code.to_i32() != 2177 {
+        if machine.syscall_number() != 2177 {
             return Ok(false);
         }
 
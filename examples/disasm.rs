@@ -0,0 +1,97 @@
+// Prints the instructions of a RISC-V program as decoded by ckb-vm.
+//
+//     $ cargo run --example disasm -- tests/programs/clang_sample
+//     $ cargo run --example disasm -- --isa rv64imc --version 1 --no-mop tests/programs/simple64
+//
+// Instructions the MOP decoder fuses are followed by the fused instruction.
+// With --dot, the control flow graph is printed in Graphviz DOT format instead:
+//
+//     $ cargo run --example disasm -- --dot tests/programs/clang_sample | dot -Tsvg > cfg.svg
+//
+// With --cycles, the worst case cycles of each function are estimated, loops
+// are bounded with --loop-bound HEADER=ITERATIONS, HEADER in hex:
+//
+//     $ cargo run --example disasm -- --cycles --loop-bound 11210=8 tests/programs/clang_sample
+use ckb_vm::cfg::ControlFlowGraph;
+use ckb_vm::cost_model::estimate_cycles;
+use ckb_vm::cycles::CycleEstimator;
+use ckb_vm::disasm::disassemble;
+use ckb_vm::isa::parse_isa_str;
+use ckb_vm::machine::VERSION2;
+use ckb_vm::{Bytes, ISA_MOP};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut isa_str = String::from("rv64imac_zba_zbb_zbc_zbs");
+    let mut version = VERSION2;
+    let mut mop = true;
+    let mut dot = false;
+    let mut cycles = false;
+    let mut loop_bounds = vec![];
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--isa" => isa_str = args.next().ok_or("--isa requires a value")?,
+            "--version" => version = args.next().ok_or("--version requires a value")?.parse()?,
+            "--no-mop" => mop = false,
+            "--dot" => dot = true,
+            "--cycles" => cycles = true,
+            "--loop-bound" => {
+                let bound = args.next().ok_or("--loop-bound requires a value")?;
+                let (header, iterations) = bound
+                    .split_once('=')
+                    .ok_or("--loop-bound expects HEADER=ITERATIONS")?;
+                loop_bounds.push((u64::from_str_radix(header, 16)?, iterations.parse()?));
+            }
+            _ => path = Some(arg),
+        }
+    }
+    let path = path
+        .ok_or("usage: disasm [--isa ISA] [--version N] [--no-mop] [--dot | --cycles] PROGRAM")?;
+    let program: Bytes = std::fs::read(path)?.into();
+    let (xlen, mut isa) = parse_isa_str(&isa_str)?;
+    if mop {
+        isa |= ISA_MOP;
+    }
+    if dot || cycles {
+        let cfg = if xlen == 32 {
+            ControlFlowGraph::build::<u32>(&program, isa, version)?
+        } else {
+            ControlFlowGraph::build::<u64>(&program, isa, version)?
+        };
+        if dot {
+            print!("{}", cfg.to_dot());
+            return Ok(());
+        }
+        let mut estimator = CycleEstimator::new(&cfg, &estimate_cycles);
+        for (header, iterations) in loop_bounds {
+            estimator = estimator.loop_bound(header, iterations);
+        }
+        let estimate = estimator.estimate();
+        for function in estimate.most_expensive() {
+            match function.worst_case {
+                Some(worst_case) => println!(
+                    "{:016x} <{}>: {} cycles, worst case {}",
+                    function.entry, function.name, function.cycles, worst_case
+                ),
+                None => println!(
+                    "{:016x} <{}>: {} cycles, unbounded: {:x?}",
+                    function.entry, function.name, function.cycles, function.unbounded
+                ),
+            }
+        }
+        return Ok(());
+    }
+    let lines = if xlen == 32 {
+        disassemble::<u32>(&program, isa, version)?
+    } else {
+        disassemble::<u64>(&program, isa, version)?
+    };
+    for line in lines {
+        if let Some(symbol) = &line.symbol {
+            println!("\n{:016x} <{}>:", line.pc, symbol);
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+#![no_main]
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::generator::{generate, GeneratedProgram, GeneratorConfig, BUFFER_SIZE};
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::trace::TraceMachine;
+use ckb_vm::machine::{
+    DefaultCoreMachine, DefaultMachineBuilder, SupportMachine, VERSION1, VERSION2,
+};
+use ckb_vm::memory::sparse::SparseMemory;
+use ckb_vm::memory::wxorx::WXorXMemory;
+use ckb_vm::{Bytes, Error, Memory, ISA_A, ISA_B, ISA_IMC, ISA_MOP};
+use libfuzzer_sys::fuzz_target;
+
+// Exit code, registers, buffer content and cycles.
+type Outcome = (i8, Vec<u64>, Bytes, u64);
+
+fn observe<M: SupportMachine<REG = u64>>(
+    exit: i8,
+    machine: &mut M,
+    program: &GeneratedProgram,
+) -> Result<Outcome, Error> {
+    let registers = machine.registers().to_vec();
+    let memory = machine
+        .memory_mut()
+        .load_bytes(program.buffer(), BUFFER_SIZE)?;
+    Ok((exit, registers, memory, machine.cycles()))
+}
+
+fn run_asm(program: &GeneratedProgram, version: u32) -> Result<Outcome, Error> {
+    let asm_core = AsmCoreMachine::new(ISA_IMC | ISA_A | ISA_B | ISA_MOP, version, 10_000_000);
+    let core = DefaultMachineBuilder::<Box<AsmCoreMachine>>::new(asm_core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let mut machine = AsmMachine::new(core);
+    machine.load_program(&program.elf(), &[])?;
+    let exit = machine.run()?;
+    observe(exit, &mut machine.machine, program)
+}
+
+fn run_int(program: &GeneratedProgram, version: u32) -> Result<Outcome, Error> {
+    let machine_memory = WXorXMemory::new(SparseMemory::<u64>::default());
+    let machine_core = DefaultCoreMachine::new_with_memory(
+        ISA_IMC | ISA_A | ISA_B | ISA_MOP,
+        version,
+        10_000_000,
+        machine_memory,
+    );
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::new(machine_core)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .build(),
+    );
+    machine.load_program(&program.elf(), &[])?;
+    let exit = machine.run()?;
+    observe(exit, &mut machine.machine, program)
+}
+
+fuzz_target!(|data: &[u8]| {
+    let program = generate::<u64>(&GeneratorConfig::default(), data).unwrap();
+    let v2 = run_asm(&program, VERSION2);
+    assert_eq!(v2, run_int(&program, VERSION2));
+    let v1 = run_asm(&program, VERSION1);
+    assert_eq!(v1, run_int(&program, VERSION1));
+    // Versions only differ in which instructions get fused, so in cycles.
+    let (v1, v2) = (v1.unwrap(), v2.unwrap());
+    assert_eq!((v1.0, v1.1, v1.2), (v2.0, v2.1, v2.2));
+});
@@ -105,12 +105,14 @@ fuzz_target!(|data: [u8; 96]| {
             flags: FLAG_EXECUTABLE | FLAG_FREEZED,
             source: slice_start as u64..slice_end as u64,
             offset_from_addr: padding_start,
+            relocations: vec![],
         })
     }
     let mut ctx = Snapshot2Context::new(dummy_data.clone());
     let metadata = ProgramMetadata {
         actions: loading_action_vec.clone(),
         entry: 0,
+        ..Default::default()
     };
 
     let mut machine1 = build_machine();
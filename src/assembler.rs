@@ -0,0 +1,1731 @@
+// A small two-pass assembler, so tests and bug reports can carry RISC-V
+// programs as text instead of prebuilt binaries. It accepts GNU as syntax for
+// the instructions ckb-vm implements, labels, the common pseudo-instructions
+// and the following directives:
+//
+// * .text, .rodata, .data, .bss and .section with one of those names
+// * .globl, .global, .local, .type, .size, .file (ignored except for binding)
+// * .option rvc / norvc, the latter being the default
+// * .align, .p2align, .balign
+// * .byte, .half, .2byte, .short, .word, .4byte, .long, .dword, .8byte, .quad
+// * .ascii, .asciz, .string, .zero, .space
+// * .equ, .set
+//
+// The result is linked into a static executable: code starts at TEXT_ADDRESS
+// followed by read-only data, writable data and bss start on the next page.
+// Execution starts at _start, or at the first instruction when _start is not
+// defined.
+//
+// The first pass sizes every statement. Instructions whose operands cannot be
+// evaluated yet, because they refer to labels or to the location counter,
+// keep their full uncompressed size, so the second pass can emit exactly the
+// layout the first pass computed.
+use ckb_vm_definitions::instructions::{self as insts, InstructionOpcode};
+use ckb_vm_definitions::registers::{RA, REGISTER_ABI_NAMES, T1, ZERO};
+use ckb_vm_definitions::RISCV_PAGESIZE;
+
+use crate::bits::roundup;
+use crate::elf::{
+    ET_EXEC, PF_R, PF_W, PF_X, PT_LOAD, SHF_EXECINSTR, SHT_NOBITS, SHT_SYMTAB, STB_GLOBAL,
+    STT_FUNC, STT_OBJECT,
+};
+use crate::encoder::{encode_compressed, encode_full};
+use crate::instructions::{
+    a, b, blank_instruction, i, m, rvc, Instruction, InstructionFactory, Itype, Register, Rtype,
+    Stype, Utype,
+};
+use crate::machine::VERSION2;
+use crate::memory::round_page_up;
+use crate::Error;
+use bytes::Bytes;
+use std::collections::HashMap;
+
+/// Address of the first instruction.
+pub const TEXT_ADDRESS: u64 = 0x10000;
+
+// File offset of the text segment, the ELF and program headers come first.
+const TEXT_OFFSET: u64 = 0x1000;
+const EM_RISCV: u16 = 243;
+const EF_RISCV_RVC: u32 = 0x1;
+const SHT_PROGBITS: u32 = 1;
+const SHT_STRTAB: u32 = 3;
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Section {
+    Text,
+    Rodata,
+    Data,
+    Bss,
+}
+
+impl Section {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Section::Text => ".text",
+            Section::Rodata => ".rodata",
+            Section::Data => ".data",
+            Section::Bss => ".bss",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+    pub name: String,
+    pub address: u64,
+    pub section: Section,
+    // Declared with .globl or .global.
+    pub global: bool,
+}
+
+/// A linked program, see to_elf for a loadable image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssembledProgram {
+    pub xlen: u8,
+    pub entry: u64,
+    // Code, loaded at TEXT_ADDRESS.
+    pub text: Vec<u8>,
+    pub rodata_address: u64,
+    pub rodata: Vec<u8>,
+    pub data_address: u64,
+    pub data: Vec<u8>,
+    pub bss_address: u64,
+    pub bss_size: u64,
+    // Sorted by address.
+    pub labels: Vec<Label>,
+    // Set when .option rvc was used, recorded in the ELF header flags.
+    pub compressed: bool,
+}
+
+impl AssembledProgram {
+    /// Address of a label.
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        self.labels
+            .iter()
+            .find(|l| l.name == name)
+            .map(|l| l.address)
+    }
+
+    /// Links the program into a static ELF executable, with a symbol table
+    /// holding every label.
+    pub fn to_elf(&self) -> Bytes {
+        ElfWriter::new(self).write()
+    }
+}
+
+/// Assembles source into a program for R::BITS wide registers.
+pub fn assemble<R: Register>(source: &str) -> Result<AssembledProgram, Error> {
+    let mut assembler = Assembler::<R>::new();
+    for (number, line) in source.lines().enumerate() {
+        assembler.line = number + 1;
+        assembler.parse_line(line).map_err(|e| assembler.error(e))?;
+    }
+    assembler.link()
+}
+
+/// Assembles source into a loadable ELF executable, the program metadata is
+/// available through elf::parse_elf.
+pub fn assemble_elf<R: Register>(source: &str) -> Result<Bytes, Error> {
+    Ok(assemble::<R>(source)?.to_elf())
+}
+
+// Register-register instructions: rd, rs1, rs2.
+const RTYPE: &[(&str, InstructionOpcode)] = &[
+    ("add", insts::OP_ADD),
+    ("sub", insts::OP_SUB),
+    ("sll", insts::OP_SLL),
+    ("slt", insts::OP_SLT),
+    ("sltu", insts::OP_SLTU),
+    ("xor", insts::OP_XOR),
+    ("srl", insts::OP_SRL),
+    ("sra", insts::OP_SRA),
+    ("or", insts::OP_OR),
+    ("and", insts::OP_AND),
+    ("addw", insts::OP_ADDW),
+    ("subw", insts::OP_SUBW),
+    ("sllw", insts::OP_SLLW),
+    ("srlw", insts::OP_SRLW),
+    ("sraw", insts::OP_SRAW),
+    ("mul", insts::OP_MUL),
+    ("mulh", insts::OP_MULH),
+    ("mulhsu", insts::OP_MULHSU),
+    ("mulhu", insts::OP_MULHU),
+    ("div", insts::OP_DIV),
+    ("divu", insts::OP_DIVU),
+    ("rem", insts::OP_REM),
+    ("remu", insts::OP_REMU),
+    ("mulw", insts::OP_MULW),
+    ("divw", insts::OP_DIVW),
+    ("divuw", insts::OP_DIVUW),
+    ("remw", insts::OP_REMW),
+    ("remuw", insts::OP_REMUW),
+    ("add.uw", insts::OP_ADDUW),
+    ("andn", insts::OP_ANDN),
+    ("orn", insts::OP_ORN),
+    ("xnor", insts::OP_XNOR),
+    ("rol", insts::OP_ROL),
+    ("rolw", insts::OP_ROLW),
+    ("ror", insts::OP_ROR),
+    ("rorw", insts::OP_RORW),
+    ("bclr", insts::OP_BCLR),
+    ("bext", insts::OP_BEXT),
+    ("binv", insts::OP_BINV),
+    ("bset", insts::OP_BSET),
+    ("sh1add", insts::OP_SH1ADD),
+    ("sh2add", insts::OP_SH2ADD),
+    ("sh3add", insts::OP_SH3ADD),
+    ("sh1add.uw", insts::OP_SH1ADDUW),
+    ("sh2add.uw", insts::OP_SH2ADDUW),
+    ("sh3add.uw", insts::OP_SH3ADDUW),
+    ("clmul", insts::OP_CLMUL),
+    ("clmulh", insts::OP_CLMULH),
+    ("clmulr", insts::OP_CLMULR),
+    ("min", insts::OP_MIN),
+    ("minu", insts::OP_MINU),
+    ("max", insts::OP_MAX),
+    ("maxu", insts::OP_MAXU),
+];
+
+// Instructions with a single source register: rd, rs1.
+const UNARY: &[(&str, InstructionOpcode)] = &[
+    ("clz", insts::OP_CLZ),
+    ("clzw", insts::OP_CLZW),
+    ("ctz", insts::OP_CTZ),
+    ("ctzw", insts::OP_CTZW),
+    ("cpop", insts::OP_CPOP),
+    ("cpopw", insts::OP_CPOPW),
+    ("sext.b", insts::OP_SEXTB),
+    ("sext.h", insts::OP_SEXTH),
+    ("zext.h", insts::OP_ZEXTH),
+    ("orc.b", insts::OP_ORCB),
+    ("rev8", insts::OP_REV8),
+];
+
+// Instructions with a signed 12-bit immediate: rd, rs1, imm.
+const ITYPE: &[(&str, InstructionOpcode)] = &[
+    ("addi", insts::OP_ADDI),
+    ("slti", insts::OP_SLTI),
+    ("sltiu", insts::OP_SLTIU),
+    ("xori", insts::OP_XORI),
+    ("ori", insts::OP_ORI),
+    ("andi", insts::OP_ANDI),
+    ("addiw", insts::OP_ADDIW),
+];
+
+// Instructions with an unsigned shift amount: rd, rs1, shamt.
+const SHIFT: &[(&str, InstructionOpcode)] = &[
+    ("slli", insts::OP_SLLI),
+    ("srli", insts::OP_SRLI),
+    ("srai", insts::OP_SRAI),
+    ("slliw", insts::OP_SLLIW),
+    ("srliw", insts::OP_SRLIW),
+    ("sraiw", insts::OP_SRAIW),
+    ("bclri", insts::OP_BCLRI),
+    ("bexti", insts::OP_BEXTI),
+    ("binvi", insts::OP_BINVI),
+    ("bseti", insts::OP_BSETI),
+    ("rori", insts::OP_RORI),
+    ("roriw", insts::OP_RORIW),
+    ("slli.uw", insts::OP_SLLIUW),
+];
+
+// rd, imm(rs1)
+const LOAD: &[(&str, InstructionOpcode)] = &[
+    ("lb", insts::OP_LB_VERSION1),
+    ("lh", insts::OP_LH_VERSION1),
+    ("lw", insts::OP_LW_VERSION1),
+    ("ld", insts::OP_LD_VERSION1),
+    ("lbu", insts::OP_LBU_VERSION1),
+    ("lhu", insts::OP_LHU_VERSION1),
+    ("lwu", insts::OP_LWU_VERSION1),
+];
+
+// rs2, imm(rs1)
+const STORE: &[(&str, InstructionOpcode)] = &[
+    ("sb", insts::OP_SB),
+    ("sh", insts::OP_SH),
+    ("sw", insts::OP_SW),
+    ("sd", insts::OP_SD),
+];
+
+// rs1, rs2, target
+const BRANCH: &[(&str, InstructionOpcode)] = &[
+    ("beq", insts::OP_BEQ),
+    ("bne", insts::OP_BNE),
+    ("blt", insts::OP_BLT),
+    ("bge", insts::OP_BGE),
+    ("bltu", insts::OP_BLTU),
+    ("bgeu", insts::OP_BGEU),
+];
+
+// rd, rs2, (rs1). Load reserved takes rd, (rs1) only.
+const AMO: &[(&str, InstructionOpcode)] = &[
+    ("lr.w", insts::OP_LR_W),
+    ("sc.w", insts::OP_SC_W),
+    ("amoswap.w", insts::OP_AMOSWAP_W),
+    ("amoadd.w", insts::OP_AMOADD_W),
+    ("amoxor.w", insts::OP_AMOXOR_W),
+    ("amoand.w", insts::OP_AMOAND_W),
+    ("amoor.w", insts::OP_AMOOR_W),
+    ("amomin.w", insts::OP_AMOMIN_W),
+    ("amomax.w", insts::OP_AMOMAX_W),
+    ("amominu.w", insts::OP_AMOMINU_W),
+    ("amomaxu.w", insts::OP_AMOMAXU_W),
+    ("lr.d", insts::OP_LR_D),
+    ("sc.d", insts::OP_SC_D),
+    ("amoswap.d", insts::OP_AMOSWAP_D),
+    ("amoadd.d", insts::OP_AMOADD_D),
+    ("amoxor.d", insts::OP_AMOXOR_D),
+    ("amoand.d", insts::OP_AMOAND_D),
+    ("amoor.d", insts::OP_AMOOR_D),
+    ("amomin.d", insts::OP_AMOMIN_D),
+    ("amomax.d", insts::OP_AMOMAX_D),
+    ("amominu.d", insts::OP_AMOMINU_D),
+    ("amomaxu.d", insts::OP_AMOMAXU_D),
+];
+
+fn lookup(table: &[(&str, InstructionOpcode)], mnemonic: &str) -> Option<InstructionOpcode> {
+    table
+        .iter()
+        .find(|(name, _)| *name == mnemonic)
+        .map(|(_, op)| *op)
+}
+
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<String>,
+    },
+    Values {
+        width: u64,
+        values: Vec<String>,
+    },
+    Bytes(Vec<u8>),
+    // Zeros, or NOPs when padding code.
+    Fill(u64),
+}
+
+struct Item {
+    line: usize,
+    section: Section,
+    offset: u64,
+    statement: Statement,
+    // Whether each instruction of the expansion uses its compressed form,
+    // decided by the first pass.
+    compressed: Vec<bool>,
+}
+
+struct Assembler<R> {
+    line: usize,
+    section: Section,
+    rvc: bool,
+    used_rvc: bool,
+    offsets: [u64; 4],
+    alignments: [u64; 4],
+    items: Vec<Item>,
+    labels: Vec<(String, Section, u64)>,
+    label_index: HashMap<String, usize>,
+    globals: Vec<String>,
+    // Expressions of .equ and .set, evaluated when used.
+    constants: HashMap<String, String>,
+    // Set in the second pass, when label addresses are known.
+    bases: Option<[u64; 4]>,
+    // Address of the instruction being assembled, second pass only.
+    pc: Option<u64>,
+    // Targets of %pcrel_hi, keyed by the address of the AUIPC.
+    pcrel: HashMap<u64, i64>,
+    _r: std::marker::PhantomData<R>,
+}
+
+impl<R: Register> Assembler<R> {
+    fn new() -> Self {
+        Assembler {
+            line: 0,
+            section: Section::Text,
+            rvc: false,
+            used_rvc: false,
+            offsets: [0; 4],
+            alignments: [1; 4],
+            items: vec![],
+            labels: vec![],
+            label_index: HashMap::new(),
+            globals: vec![],
+            constants: HashMap::new(),
+            bases: None,
+            pc: None,
+            pcrel: HashMap::new(),
+            _r: std::marker::PhantomData,
+        }
+    }
+
+    fn error(&self, message: String) -> Error {
+        Error::Assembler {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let mut rest = strip_comment(line).trim();
+        while let Some(position) = rest.find(':') {
+            let name = rest[..position].trim();
+            if name.is_empty() || !name.chars().all(is_symbol_char) {
+                break;
+            }
+            self.define_label(name)?;
+            rest = rest[position + 1..].trim();
+        }
+        if rest.is_empty() {
+            return Ok(());
+        }
+        let (head, tail) = match rest.find(char::is_whitespace) {
+            Some(position) => (&rest[..position], rest[position..].trim()),
+            None => (rest, ""),
+        };
+        let head = head.to_lowercase();
+        if head.starts_with('.') {
+            self.directive(&head, tail)
+        } else {
+            self.instruction(head, split_operands(tail))
+        }
+    }
+
+    fn define_label(&mut self, name: &str) -> Result<(), String> {
+        if self.label_index.contains_key(name) || self.constants.contains_key(name) {
+            return Err(format!("symbol {} is already defined", name));
+        }
+        self.label_index.insert(name.to_string(), self.labels.len());
+        self.labels.push((
+            name.to_string(),
+            self.section,
+            self.offsets[self.section.index()],
+        ));
+        Ok(())
+    }
+
+    fn push(&mut self, statement: Statement, size: u64, compressed: Vec<bool>) {
+        let section = self.section;
+        if section == Section::Bss {
+            // Only the size matters, the content is zeroed by the loader.
+            self.offsets[section.index()] += size;
+            return;
+        }
+        self.items.push(Item {
+            line: self.line,
+            section,
+            offset: self.offsets[section.index()],
+            statement,
+            compressed,
+        });
+        self.offsets[section.index()] += size;
+    }
+
+    fn align(&mut self, alignment: u64) -> Result<(), String> {
+        if !alignment.is_power_of_two() || alignment > RISCV_PAGESIZE as u64 {
+            return Err(format!("invalid alignment {}", alignment));
+        }
+        let index = self.section.index();
+        self.alignments[index] = self.alignments[index].max(alignment);
+        let offset = self.offsets[index];
+        let padding = roundup(offset, alignment) - offset;
+        if padding > 0 {
+            self.push(Statement::Fill(padding), padding, vec![]);
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, name: &str, args: &str) -> Result<(), String> {
+        let operands = split_operands(args);
+        let width = match name {
+            ".byte" => 1,
+            ".half" | ".2byte" | ".short" => 2,
+            ".word" | ".4byte" | ".long" => 4,
+            ".dword" | ".8byte" | ".quad" => 8,
+            _ => 0,
+        };
+        if width > 0 {
+            let size = width * operands.len() as u64;
+            self.push(
+                Statement::Values {
+                    width,
+                    values: operands,
+                },
+                size,
+                vec![],
+            );
+            return Ok(());
+        }
+        match name {
+            ".text" => self.section = Section::Text,
+            ".rodata" => self.section = Section::Rodata,
+            ".data" => self.section = Section::Data,
+            ".bss" => self.section = Section::Bss,
+            ".section" => {
+                // Flags and subsections, like .text.startup, are ignored.
+                let section = operands.first().map(String::as_str).unwrap_or("");
+                self.section = if section.starts_with(".text") {
+                    Section::Text
+                } else if section.starts_with(".rodata") || section.starts_with(".srodata") {
+                    Section::Rodata
+                } else if section.starts_with(".data") || section.starts_with(".sdata") {
+                    Section::Data
+                } else if section.starts_with(".bss") || section.starts_with(".sbss") {
+                    Section::Bss
+                } else {
+                    return Err(format!("unsupported section {}", section));
+                };
+            }
+            ".globl" | ".global" => self.globals.extend(operands),
+            ".local" | ".type" | ".size" | ".file" => {}
+            ".option" => match args {
+                "rvc" => {
+                    self.rvc = true;
+                    self.used_rvc = true;
+                }
+                "norvc" => self.rvc = false,
+                // .option push, pop, relax and friends only matter to linkers.
+                _ => {}
+            },
+            ".align" | ".p2align" => {
+                let shift = self.evaluate(single(&operands)?)?;
+                if !(0..=12).contains(&shift) {
+                    return Err(format!("invalid alignment 2^{}", shift));
+                }
+                self.align(1 << shift)?;
+            }
+            ".balign" => {
+                let alignment = self.evaluate(single(&operands)?)?;
+                self.align(alignment as u64)?;
+            }
+            ".zero" | ".space" | ".skip" => {
+                let size = self.evaluate(single(&operands)?)?;
+                if size < 0 {
+                    return Err(format!("invalid size {}", size));
+                }
+                self.push(Statement::Fill(size as u64), size as u64, vec![]);
+            }
+            ".ascii" | ".asciz" | ".string" => {
+                for operand in &operands {
+                    let mut bytes = parse_string(operand)?;
+                    if name != ".ascii" {
+                        bytes.push(0);
+                    }
+                    let size = bytes.len() as u64;
+                    self.push(Statement::Bytes(bytes), size, vec![]);
+                }
+            }
+            ".equ" | ".set" => {
+                if operands.len() != 2 || !operands[0].chars().all(is_symbol_char) {
+                    return Err(format!("{} expects a name and a value", name));
+                }
+                if self.label_index.contains_key(&operands[0]) {
+                    return Err(format!("symbol {} is already defined", operands[0]));
+                }
+                self.constants
+                    .insert(operands[0].clone(), operands[1].clone());
+            }
+            _ => return Err(format!("unknown directive {}", name)),
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, mnemonic: String, operands: Vec<String>) -> Result<(), String> {
+        if self.section != Section::Text {
+            return Err(format!(
+                "instruction {} outside of .text",
+                mnemonic.to_lowercase()
+            ));
+        }
+        let (size, compressed) = match self.expand(&mnemonic, &operands) {
+            Ok(instructions) => {
+                let compressed: Vec<bool> = instructions
+                    .iter()
+                    .map(|i| self.rvc && encode_compressed::<R>(*i).is_some())
+                    .collect();
+                let size = compressed.iter().map(|c| if *c { 2 } else { 4 }).sum();
+                (size, compressed)
+            }
+            // Operands refer to labels, which are only known in the second
+            // pass. Every expansion besides li has a fixed length.
+            Err(e) => match mnemonic.as_str() {
+                "li" => return Err(e),
+                "la" | "lla" | "call" | "tail" => (8, vec![false; 2]),
+                _ => (4, vec![false]),
+            },
+        };
+        self.push(
+            Statement::Instruction { mnemonic, operands },
+            size,
+            compressed,
+        );
+        Ok(())
+    }
+
+    fn evaluate(&self, expression: &str) -> Result<i64, String> {
+        let mut parser = Parser {
+            input: expression.as_bytes(),
+            position: 0,
+            assembler: self,
+            depth: 0,
+        };
+        parser.parse()
+    }
+
+    fn symbol(&self, name: &str, depth: usize) -> Result<i64, String> {
+        if let Some(expression) = self.constants.get(name) {
+            if depth > 32 {
+                return Err(format!("recursive definition of {}", name));
+            }
+            let mut parser = Parser {
+                input: expression.as_bytes(),
+                position: 0,
+                assembler: self,
+                depth: depth + 1,
+            };
+            return parser.parse();
+        }
+        match (self.label_index.get(name), self.bases) {
+            (Some(index), Some(bases)) => {
+                let (_, section, offset) = &self.labels[*index];
+                Ok((bases[section.index()] + offset) as i64)
+            }
+            (None, Some(_)) => Err(format!("undefined symbol {}", name)),
+            (_, None) => Err(format!("{} is not a constant", name)),
+        }
+    }
+
+    fn location(&self) -> Result<u64, String> {
+        self.pc
+            .ok_or_else(|| "location counter is not a constant".to_string())
+    }
+
+    // Evaluates an immediate operand, which may use one of the %hi, %lo,
+    // %pcrel_hi and %pcrel_lo relocation functions.
+    fn immediate(&mut self, operand: &str) -> Result<i64, String> {
+        let operand = operand.trim();
+        let function = operand
+            .strip_prefix('%')
+            .and_then(|s| s.find('(').map(|p| (&s[..p], &s[p..])))
+            .filter(|(_, argument)| matching_paren(argument) == Some(argument.len() - 1));
+        let (function, argument) = match function {
+            Some((function, argument)) => (function, &argument[1..argument.len() - 1]),
+            None => return self.evaluate(operand),
+        };
+        let value = self.evaluate(argument)?;
+        match function {
+            "hi" => Ok(hi20(value)),
+            "lo" => Ok(lo12(value)),
+            "pcrel_hi" => {
+                let pc = self.location()?;
+                self.pcrel.insert(pc, value);
+                Ok(hi20(value.wrapping_sub(pc as i64)))
+            }
+            "pcrel_lo" => {
+                // The argument labels the AUIPC holding the upper bits.
+                let target = self
+                    .pcrel
+                    .get(&(value as u64))
+                    .ok_or_else(|| format!("no %pcrel_hi at {}", argument))?;
+                Ok(lo12(target.wrapping_sub(value)))
+            }
+            _ => Err(format!("unknown relocation function %{}", function)),
+        }
+    }
+
+    fn signed(&mut self, operand: &str, bits: u32) -> Result<i32, String> {
+        let value = self.immediate(operand)?;
+        if value < -(1 << (bits - 1)) || value >= 1 << (bits - 1) {
+            return Err(format!("immediate {} out of range", operand));
+        }
+        Ok(value as i32)
+    }
+
+    fn shamt(&mut self, operand: &str) -> Result<u32, String> {
+        let value = self.immediate(operand)?;
+        if !(0..64).contains(&value) {
+            return Err(format!("shift amount {} out of range", operand));
+        }
+        Ok(value as u32)
+    }
+
+    // Upper immediate of LUI and AUIPC, the 20 bits above the lower 12.
+    fn upper(&mut self, operand: &str) -> Result<i32, String> {
+        let value = self.immediate(operand)?;
+        if !(-0x80000..=0xfffff).contains(&value) {
+            return Err(format!("immediate {} out of range", operand));
+        }
+        Ok(((value as u32) << 12) as i32)
+    }
+
+    // Offset from pc to a branch or jump target.
+    fn target(&mut self, operand: &str, pc: u64) -> Result<i32, String> {
+        let value = self.immediate(operand)?;
+        let offset = value.wrapping_sub(pc as i64);
+        i32::try_from(offset).map_err(|_| format!("target {} out of range", operand))
+    }
+
+    // AUIPC and the lower 12 bits of the pc relative offset to a symbol.
+    fn pcrel(&mut self, rd: usize, operand: &str) -> Result<(Instruction, i32), String> {
+        let pc = self.location()?;
+        let offset = self.immediate(operand)?.wrapping_sub(pc as i64);
+        let upper = offset.wrapping_add(0x800) >> 12;
+        if i32::try_from(upper << 12).is_err() {
+            return Err(format!("{} out of range", operand));
+        }
+        let auipc = Utype::new_s(insts::OP_AUIPC, rd, (upper << 12) as i32).0;
+        Ok((auipc, lo12(offset) as i32))
+    }
+
+    // Expands an instruction, or a pseudo-instruction, into the instructions
+    // it is made of.
+    fn expand(&mut self, mnemonic: &str, operands: &[String]) -> Result<Vec<Instruction>, String> {
+        let arity = |n: usize| {
+            if operands.len() == n {
+                Ok(())
+            } else {
+                Err(format!("{} expects {} operands", mnemonic, n))
+            }
+        };
+        let mnemonic = strip_ordering(mnemonic);
+        if let Some(op) = lookup(RTYPE, mnemonic) {
+            arity(3)?;
+            let (rd, rs1, rs2) = (reg(&operands[0])?, reg(&operands[1])?, reg(&operands[2])?);
+            return Ok(vec![Rtype::new(op, rd, rs1, rs2).0]);
+        }
+        if let Some(op) = lookup(UNARY, mnemonic) {
+            arity(2)?;
+            return Ok(vec![
+                Rtype::new(op, reg(&operands[0])?, reg(&operands[1])?, ZERO).0,
+            ]);
+        }
+        if let Some(op) = lookup(ITYPE, mnemonic) {
+            arity(3)?;
+            let (rd, rs1) = (reg(&operands[0])?, reg(&operands[1])?);
+            let imm = self.signed(&operands[2], 12)?;
+            return Ok(vec![Itype::new_s(op, rd, rs1, imm).0]);
+        }
+        if let Some(op) = lookup(SHIFT, mnemonic) {
+            arity(3)?;
+            let (rd, rs1) = (reg(&operands[0])?, reg(&operands[1])?);
+            let shamt = self.shamt(&operands[2])?;
+            return Ok(vec![Itype::new_u(op, rd, rs1, shamt).0]);
+        }
+        if let Some(op) = lookup(LOAD, mnemonic) {
+            arity(2)?;
+            let rd = reg(&operands[0])?;
+            let (offset, rs1) = memory(&operands[1])?;
+            let imm = self.signed(offset, 12)?;
+            return Ok(vec![Itype::new_s(op, rd, rs1, imm).0]);
+        }
+        if let Some(op) = lookup(STORE, mnemonic) {
+            arity(2)?;
+            let rs2 = reg(&operands[0])?;
+            let (offset, rs1) = memory(&operands[1])?;
+            let imm = self.signed(offset, 12)?;
+            return Ok(vec![Stype::new_s(op, imm, rs1, rs2).0]);
+        }
+        if let Some(op) = lookup(BRANCH, mnemonic) {
+            arity(3)?;
+            let (rs1, rs2) = (reg(&operands[0])?, reg(&operands[1])?);
+            return self.branch(op, rs1, rs2, &operands[2]);
+        }
+        if let Some(op) = lookup(AMO, mnemonic) {
+            let lr = op == insts::OP_LR_W || op == insts::OP_LR_D;
+            arity(if lr { 2 } else { 3 })?;
+            let rd = reg(&operands[0])?;
+            let rs2 = if lr { ZERO } else { reg(&operands[1])? };
+            let (offset, rs1) = memory(&operands[operands.len() - 1])?;
+            if !offset.is_empty() && self.immediate(offset)? != 0 {
+                return Err(format!("{} does not take an offset", mnemonic));
+            }
+            return Ok(vec![Rtype::new(op, rd, rs1, rs2).0]);
+        }
+        let instructions = match mnemonic {
+            "lui" | "auipc" => {
+                arity(2)?;
+                let op = if mnemonic == "lui" {
+                    insts::OP_LUI
+                } else {
+                    insts::OP_AUIPC
+                };
+                let rd = reg(&operands[0])?;
+                vec![Utype::new_s(op, rd, self.upper(&operands[1])?).0]
+            }
+            "jal" => {
+                let (rd, target) = match operands.len() {
+                    1 => (RA, &operands[0]),
+                    2 => (reg(&operands[0])?, &operands[1]),
+                    _ => return Err("jal expects 1 or 2 operands".to_string()),
+                };
+                let pc = self.location()?;
+                vec![Utype::new_s(insts::OP_JAL, rd, self.target(target, pc)?).0]
+            }
+            "jalr" => {
+                let (rd, rs1, imm) = match operands.len() {
+                    1 => (RA, reg(&operands[0])?, 0),
+                    2 => match memory(&operands[1]) {
+                        Ok((offset, rs1)) => (reg(&operands[0])?, rs1, self.signed(offset, 12)?),
+                        Err(_) => (reg(&operands[0])?, reg(&operands[1])?, 0),
+                    },
+                    3 => (
+                        reg(&operands[0])?,
+                        reg(&operands[1])?,
+                        self.signed(&operands[2], 12)?,
+                    ),
+                    _ => return Err("jalr expects 1 to 3 operands".to_string()),
+                };
+                vec![Itype::new_s(insts::OP_JALR_VERSION1, rd, rs1, imm).0]
+            }
+            "fence" => {
+                let (pred, succ) = match operands.len() {
+                    0 => (0b1111, 0b1111),
+                    2 => (fence_set(&operands[0])?, fence_set(&operands[1])?),
+                    _ => return Err("fence expects 0 or 2 operands".to_string()),
+                };
+                // Same layout as FenceType: fm, pred and succ in rd, rs1 and rs2.
+                vec![Rtype::new(insts::OP_FENCE, 0, pred, succ).0]
+            }
+            "fence.i" | "ecall" | "ebreak" | "nop" => {
+                arity(0)?;
+                vec![match mnemonic {
+                    "fence.i" => blank_instruction(insts::OP_FENCEI),
+                    "ecall" => blank_instruction(insts::OP_ECALL),
+                    "ebreak" => blank_instruction(insts::OP_EBREAK),
+                    _ => Itype::new_s(insts::OP_ADDI, ZERO, ZERO, 0).0,
+                }]
+            }
+            "li" => {
+                arity(2)?;
+                let rd = reg(&operands[0])?;
+                let mut value = self.immediate(&operands[1])?;
+                if R::BITS == 32 {
+                    if value < i64::from(i32::MIN) || value > i64::from(u32::MAX) {
+                        return Err(format!("immediate {} out of range", operands[1]));
+                    }
+                    value = i64::from(value as i32);
+                }
+                let mut instructions = vec![];
+                load_immediate::<R>(rd, value, &mut instructions);
+                instructions
+            }
+            "la" | "lla" => {
+                arity(2)?;
+                let rd = reg(&operands[0])?;
+                let (auipc, lo) = self.pcrel(rd, &operands[1])?;
+                vec![auipc, Itype::new_s(insts::OP_ADDI, rd, rd, lo).0]
+            }
+            "call" | "tail" => {
+                arity(1)?;
+                let (link, scratch) = if mnemonic == "call" {
+                    (RA, RA)
+                } else {
+                    (ZERO, T1)
+                };
+                let (auipc, lo) = self.pcrel(scratch, &operands[0])?;
+                vec![
+                    auipc,
+                    Itype::new_s(insts::OP_JALR_VERSION1, link, scratch, lo).0,
+                ]
+            }
+            "j" => {
+                arity(1)?;
+                let pc = self.location()?;
+                vec![Utype::new_s(insts::OP_JAL, ZERO, self.target(&operands[0], pc)?).0]
+            }
+            "jr" => {
+                arity(1)?;
+                vec![Itype::new_s(insts::OP_JALR_VERSION1, ZERO, reg(&operands[0])?, 0).0]
+            }
+            "ret" => {
+                arity(0)?;
+                vec![Itype::new_s(insts::OP_JALR_VERSION1, ZERO, RA, 0).0]
+            }
+            "mv" | "not" | "neg" | "negw" | "sext.w" | "zext.b" | "seqz" | "snez" | "sltz"
+            | "sgtz" => {
+                arity(2)?;
+                let (rd, rs) = (reg(&operands[0])?, reg(&operands[1])?);
+                vec![match mnemonic {
+                    "mv" => Itype::new_s(insts::OP_ADDI, rd, rs, 0).0,
+                    "not" => Itype::new_s(insts::OP_XORI, rd, rs, -1).0,
+                    "neg" => Rtype::new(insts::OP_SUB, rd, ZERO, rs).0,
+                    "negw" => Rtype::new(insts::OP_SUBW, rd, ZERO, rs).0,
+                    "sext.w" => Itype::new_s(insts::OP_ADDIW, rd, rs, 0).0,
+                    "zext.b" => Itype::new_s(insts::OP_ANDI, rd, rs, 255).0,
+                    "seqz" => Itype::new_s(insts::OP_SLTIU, rd, rs, 1).0,
+                    "snez" => Rtype::new(insts::OP_SLTU, rd, ZERO, rs).0,
+                    "sltz" => Rtype::new(insts::OP_SLT, rd, rs, ZERO).0,
+                    _ => Rtype::new(insts::OP_SLT, rd, ZERO, rs).0,
+                }]
+            }
+            "beqz" | "bnez" | "blez" | "bgez" | "bltz" | "bgtz" => {
+                arity(2)?;
+                let rs = reg(&operands[0])?;
+                let (op, rs1, rs2) = match mnemonic {
+                    "beqz" => (insts::OP_BEQ, rs, ZERO),
+                    "bnez" => (insts::OP_BNE, rs, ZERO),
+                    "blez" => (insts::OP_BGE, ZERO, rs),
+                    "bgez" => (insts::OP_BGE, rs, ZERO),
+                    "bltz" => (insts::OP_BLT, rs, ZERO),
+                    _ => (insts::OP_BLT, ZERO, rs),
+                };
+                return self.branch(op, rs1, rs2, &operands[1]);
+            }
+            "bgt" | "ble" | "bgtu" | "bleu" => {
+                arity(3)?;
+                let (rs1, rs2) = (reg(&operands[0])?, reg(&operands[1])?);
+                let op = match mnemonic {
+                    "bgt" => insts::OP_BLT,
+                    "ble" => insts::OP_BGE,
+                    "bgtu" => insts::OP_BLTU,
+                    _ => insts::OP_BGEU,
+                };
+                return self.branch(op, rs2, rs1, &operands[2]);
+            }
+            _ => return Err(format!("unknown instruction {}", mnemonic)),
+        };
+        Ok(instructions)
+    }
+
+    fn branch(
+        &mut self,
+        op: InstructionOpcode,
+        rs1: usize,
+        rs2: usize,
+        target: &str,
+    ) -> Result<Vec<Instruction>, String> {
+        let pc = self.location()?;
+        let offset = self.target(target, pc)?;
+        Ok(vec![Stype::new_s(op, offset, rs1, rs2).0])
+    }
+
+    // Second pass, every label has an address now.
+    fn link(mut self) -> Result<AssembledProgram, Error> {
+        let sizes = self.offsets;
+        let text_end = TEXT_ADDRESS + sizes[Section::Text.index()];
+        let rodata_address = roundup(text_end, self.alignments[Section::Rodata.index()].max(8));
+        let data_address = round_page_up(rodata_address + sizes[Section::Rodata.index()]);
+        let bss_address = roundup(
+            data_address + sizes[Section::Data.index()],
+            self.alignments[Section::Bss.index()].max(8),
+        );
+        let bases = [TEXT_ADDRESS, rodata_address, data_address, bss_address];
+        self.bases = Some(bases);
+
+        let mut contents: [Vec<u8>; 3] = [
+            Vec::with_capacity(sizes[0] as usize),
+            Vec::with_capacity(sizes[1] as usize),
+            Vec::with_capacity(sizes[2] as usize),
+        ];
+        let items = std::mem::take(&mut self.items);
+        for item in &items {
+            self.line = item.line;
+            let address = bases[item.section.index()] + item.offset;
+            let output = &mut contents[item.section.index()];
+            debug_assert_eq!(output.len() as u64, item.offset);
+            match &item.statement {
+                Statement::Instruction { mnemonic, operands } => {
+                    self.pc = Some(address);
+                    let instructions =
+                        self.expand(mnemonic, operands).map_err(|e| self.error(e))?;
+                    if instructions.len() != item.compressed.len() {
+                        return Err(
+                            self.error(format!("{} changed length between passes", mnemonic))
+                        );
+                    }
+                    for (instruction, compressed) in instructions.iter().zip(&item.compressed) {
+                        let bits = self.encode(*instruction, *compressed)?;
+                        if *compressed {
+                            output.extend_from_slice(&(bits as u16).to_le_bytes());
+                        } else {
+                            output.extend_from_slice(&bits.to_le_bytes());
+                        }
+                    }
+                }
+                Statement::Values { width, values } => {
+                    self.pc = Some(address);
+                    for value in values {
+                        let v = self.evaluate(value).map_err(|e| self.error(e))?;
+                        if *width < 8 {
+                            let bits = *width * 8;
+                            if v < -(1 << (bits - 1)) || v >= 1 << bits {
+                                return Err(self.error(format!("value {} out of range", value)));
+                            }
+                        }
+                        output.extend_from_slice(&v.to_le_bytes()[..*width as usize]);
+                    }
+                }
+                Statement::Bytes(bytes) => output.extend_from_slice(bytes),
+                Statement::Fill(size) => {
+                    let start = output.len();
+                    output.resize(start + *size as usize, 0);
+                    if item.section == Section::Text {
+                        fill_nops(&mut output[start..], address);
+                    }
+                }
+            }
+        }
+        self.pc = None;
+
+        let mut labels: Vec<Label> = self
+            .labels
+            .iter()
+            .map(|(name, section, offset)| Label {
+                name: name.clone(),
+                address: bases[section.index()] + offset,
+                section: *section,
+                global: self.globals.contains(name),
+            })
+            .collect();
+        labels.sort_by_key(|l| l.address);
+        for global in &self.globals {
+            if !self.label_index.contains_key(global) {
+                self.line = 0;
+                return Err(self.error(format!("undefined global symbol {}", global)));
+            }
+        }
+        let entry = labels
+            .iter()
+            .find(|l| l.name == "_start")
+            .map(|l| l.address)
+            .unwrap_or(TEXT_ADDRESS);
+        let [text, rodata, data] = contents;
+        Ok(AssembledProgram {
+            xlen: R::BITS,
+            entry,
+            text,
+            rodata_address,
+            rodata,
+            data_address,
+            data,
+            bss_address,
+            bss_size: sizes[Section::Bss.index()],
+            labels,
+            compressed: self.used_rvc,
+        })
+    }
+
+    fn encode(&self, instruction: Instruction, compressed: bool) -> Result<u32, Error> {
+        let bits = if compressed {
+            encode_compressed::<R>(instruction).map(u32::from)
+        } else {
+            encode_full::<R>(instruction).ok()
+        };
+        // The encoder does not know about XLEN, instructions like LD would
+        // be accepted on RV32. Decoding the result again rejects them.
+        let factories: [InstructionFactory; 5] = [
+            rvc::factory::<R>,
+            i::factory::<R>,
+            m::factory::<R>,
+            b::factory::<R>,
+            a::factory::<R>,
+        ];
+        match bits {
+            Some(bits) if factories.iter().any(|f| f(bits, VERSION2).is_some()) => Ok(bits),
+            _ => Err(self.error(format!("instruction is not supported on rv{}", R::BITS))),
+        }
+    }
+}
+
+// Appends the shortest sequence loading value into rd, this follows the
+// algorithm LLVM uses for RISCVMatInt without the B extension.
+fn load_immediate<R: Register>(rd: usize, value: i64, output: &mut Vec<Instruction>) {
+    if R::BITS == 32 || i64::from(value as i32) == value {
+        let hi = (value.wrapping_add(0x800) >> 12) & 0xfffff;
+        let lo = lo12(value) as i32;
+        let mut rs1 = ZERO;
+        if hi != 0 {
+            output.push(Utype::new_s(insts::OP_LUI, rd, ((hi as u32) << 12) as i32).0);
+            rs1 = rd;
+        }
+        if lo != 0 || hi == 0 {
+            let op = if hi != 0 && R::BITS == 64 {
+                insts::OP_ADDIW
+            } else {
+                insts::OP_ADDI
+            };
+            output.push(Itype::new_s(op, rd, rs1, lo).0);
+        }
+        return;
+    }
+    let lo = lo12(value);
+    let hi = (value.wrapping_add(0x800) as u64) >> 12;
+    let shift = 12 + hi.trailing_zeros();
+    // Sign extends the remaining upper bits.
+    let hi = ((hi >> (shift - 12)) << shift) as i64 >> shift;
+    load_immediate::<R>(rd, hi, output);
+    output.push(Itype::new_u(insts::OP_SLLI, rd, rd, shift).0);
+    if lo != 0 {
+        output.push(Itype::new_s(insts::OP_ADDI, rd, rd, lo as i32).0);
+    }
+}
+
+// Upper 20 bits of value, rounded so adding the signed lower 12 bits gives
+// value back.
+fn hi20(value: i64) -> i64 {
+    (value.wrapping_add(0x800) >> 12) & 0xfffff
+}
+
+fn lo12(value: i64) -> i64 {
+    (value << 52) >> 52
+}
+
+fn fill_nops(output: &mut [u8], address: u64) {
+    let mut position = 0;
+    if address % 4 == 2 && output.len() >= 2 {
+        // c.nop
+        output[..2].copy_from_slice(&0x0001u16.to_le_bytes());
+        position = 2;
+    }
+    while position + 4 <= output.len() {
+        output[position..position + 4].copy_from_slice(&0x00000013u32.to_le_bytes());
+        position += 4;
+    }
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (position, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(_), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), _) if c == q && !escaped => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '#') => return &line[..position],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+// Splits on commas outside of parentheses and quotes.
+fn split_operands(s: &str) -> Vec<String> {
+    let mut operands = vec![];
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut start = 0;
+    for (position, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(_), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), _) if c == q && !escaped => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                operands.push(s[start..position].trim().to_string());
+                start = position + 1;
+            }
+            _ => {}
+        }
+        escaped = false;
+    }
+    let last = s[start..].trim();
+    if !last.is_empty() || !operands.is_empty() {
+        operands.push(last.to_string());
+    }
+    operands
+}
+
+fn single(operands: &[String]) -> Result<&str, String> {
+    match operands {
+        [operand] => Ok(operand),
+        _ => Err("expects a single operand".to_string()),
+    }
+}
+
+// Atomic instructions may carry .aq and .rl suffixes, ckb-vm runs a single
+// hart so the ordering bits have no effect and are not encoded.
+fn strip_ordering(mnemonic: &str) -> &str {
+    if mnemonic.starts_with("amo") || mnemonic.starts_with("lr.") || mnemonic.starts_with("sc.") {
+        for suffix in [".aqrl", ".aq", ".rl"] {
+            if let Some(stripped) = mnemonic.strip_suffix(suffix) {
+                return stripped;
+            }
+        }
+    }
+    mnemonic
+}
+
+fn reg(name: &str) -> Result<usize, String> {
+    let name = name.trim();
+    if let Some(index) = name.strip_prefix('x').and_then(|n| n.parse::<usize>().ok()) {
+        if index < 32 {
+            return Ok(index);
+        }
+    }
+    if name == "fp" {
+        return Ok(8);
+    }
+    REGISTER_ABI_NAMES
+        .iter()
+        .position(|n| *n == name)
+        .ok_or_else(|| format!("invalid register {}", name))
+}
+
+// Position of the parenthesis closing the one s starts with.
+fn matching_paren(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (position, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(position);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Splits a memory operand offset(register) into the offset expression, which
+// may be empty, and the register.
+fn memory(operand: &str) -> Result<(&str, usize), String> {
+    let operand = operand.trim();
+    let invalid = || format!("invalid memory operand {}", operand);
+    if !operand.ends_with(')') {
+        return Err(invalid());
+    }
+    let open = operand.rfind('(').ok_or_else(invalid)?;
+    let register = reg(&operand[open + 1..operand.len() - 1]).map_err(|_| invalid())?;
+    let offset = operand[..open].trim();
+    Ok((if offset.is_empty() { "0" } else { offset }, register))
+}
+
+// Predecessor or successor set of FENCE, any of i, o, r and w.
+fn fence_set(operand: &str) -> Result<usize, String> {
+    let mut set = 0;
+    for c in operand.chars() {
+        set |= match c {
+            'i' => 0b1000,
+            'o' => 0b0100,
+            'r' => 0b0010,
+            'w' => 0b0001,
+            _ => return Err(format!("invalid fence operand {}", operand)),
+        };
+    }
+    Ok(set)
+}
+
+fn parse_escape(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<u8, String> {
+    let c = chars.next().ok_or("unterminated escape")?;
+    Ok(match c {
+        'n' => b'\n',
+        't' => b'\t',
+        'r' => b'\r',
+        '0' => 0,
+        '\\' => b'\\',
+        '"' => b'"',
+        '\'' => b'\'',
+        'x' => {
+            let mut value = 0u8;
+            for _ in 0..2 {
+                let digit = chars
+                    .next()
+                    .and_then(|d| d.to_digit(16))
+                    .ok_or("invalid \\x escape")?;
+                value = value * 16 + digit as u8;
+            }
+            value
+        }
+        _ => return Err(format!("unknown escape \\{}", c)),
+    })
+}
+
+fn parse_string(operand: &str) -> Result<Vec<u8>, String> {
+    let inner = operand
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| format!("invalid string {}", operand))?;
+    let mut bytes = vec![];
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            bytes.push(parse_escape(&mut chars)?);
+        } else {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+        }
+    }
+    Ok(bytes)
+}
+
+// Recursive descent parser for expressions, with C operator precedence.
+// Values wrap around as 64-bit integers.
+struct Parser<'a, R> {
+    input: &'a [u8],
+    position: usize,
+    assembler: &'a Assembler<R>,
+    depth: usize,
+}
+
+impl<'a, R: Register> Parser<'a, R> {
+    fn parse(&mut self) -> Result<i64, String> {
+        let value = self.binary(0)?;
+        self.skip_whitespace();
+        if self.position != self.input.len() {
+            return Err(format!(
+                "invalid expression {}",
+                String::from_utf8_lossy(self.input)
+            ));
+        }
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .input
+            .get(self.position)
+            .map_or(false, |c| c.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+    }
+
+    fn operator(&mut self, level: usize) -> Option<&'static str> {
+        const LEVELS: [&[&str]; 6] = [
+            &["|"],
+            &["^"],
+            &["&"],
+            &["<<", ">>"],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        self.skip_whitespace();
+        let rest = &self.input[self.position..];
+        let operator = LEVELS[level]
+            .iter()
+            .find(|o| rest.starts_with(o.as_bytes()))?;
+        self.position += operator.len();
+        Some(operator)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        if level == 6 {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        while let Some(operator) = self.operator(level) {
+            let rhs = self.binary(level + 1)?;
+            value = match operator {
+                "|" => value | rhs,
+                "^" => value ^ rhs,
+                "&" => value & rhs,
+                "<<" => value.wrapping_shl(rhs as u32),
+                ">>" => value.wrapping_shr(rhs as u32),
+                "+" => value.wrapping_add(rhs),
+                "-" => value.wrapping_sub(rhs),
+                "*" => value.wrapping_mul(rhs),
+                _ if rhs == 0 => return Err("division by zero".to_string()),
+                "/" => value.wrapping_div(rhs),
+                _ => value.wrapping_rem(rhs),
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        self.skip_whitespace();
+        match self.input.get(self.position) {
+            Some(b'-') => {
+                self.position += 1;
+                Ok(self.unary()?.wrapping_neg())
+            }
+            Some(b'+') => {
+                self.position += 1;
+                self.unary()
+            }
+            Some(b'~') => {
+                self.position += 1;
+                Ok(!self.unary()?)
+            }
+            Some(b'(') => {
+                self.position += 1;
+                let value = self.binary(0)?;
+                self.skip_whitespace();
+                if self.input.get(self.position) != Some(&b')') {
+                    return Err("missing )".to_string());
+                }
+                self.position += 1;
+                Ok(value)
+            }
+            Some(b'\'') => self.character(),
+            Some(c) if c.is_ascii_digit() => self.number(),
+            Some(_) => self.identifier(),
+            None => Err("missing operand".to_string()),
+        }
+    }
+
+    fn character(&mut self) -> Result<i64, String> {
+        let rest =
+            std::str::from_utf8(&self.input[self.position + 1..]).map_err(|e| e.to_string())?;
+        let mut chars = rest.chars().peekable();
+        let value = match chars.next() {
+            Some('\\') => parse_escape(&mut chars)?,
+            Some(c) if c.is_ascii() => c as u8,
+            _ => return Err("invalid character literal".to_string()),
+        };
+        if chars.next() != Some('\'') {
+            return Err("invalid character literal".to_string());
+        }
+        let consumed = rest.len() - chars.map(char::len_utf8).sum::<usize>();
+        self.position += 1 + consumed;
+        Ok(i64::from(value))
+    }
+
+    fn number(&mut self) -> Result<i64, String> {
+        let start = self.position;
+        while self
+            .input
+            .get(self.position)
+            .map_or(false, |c| c.is_ascii_alphanumeric() || *c == b'_')
+        {
+            self.position += 1;
+        }
+        let text = std::str::from_utf8(&self.input[start..self.position])
+            .unwrap()
+            .replace('_', "")
+            .to_lowercase();
+        let (digits, radix) = if let Some(digits) = text.strip_prefix("0x") {
+            (digits, 16)
+        } else if let Some(digits) = text.strip_prefix("0b") {
+            (digits, 2)
+        } else {
+            (text.as_str(), 10)
+        };
+        u64::from_str_radix(digits, radix)
+            .map(|v| v as i64)
+            .map_err(|_| format!("invalid number {}", text))
+    }
+
+    fn identifier(&mut self) -> Result<i64, String> {
+        let start = self.position;
+        while self
+            .input
+            .get(self.position)
+            .map_or(false, |c| is_symbol_char(*c as char))
+        {
+            self.position += 1;
+        }
+        let name = std::str::from_utf8(&self.input[start..self.position]).unwrap();
+        match name {
+            "" => Err(format!(
+                "unexpected character in {}",
+                String::from_utf8_lossy(self.input)
+            )),
+            "." => Ok(self.assembler.location()? as i64),
+            _ => self.assembler.symbol(name, self.depth),
+        }
+    }
+}
+
+// Writes the ELF image: headers, the text segment at TEXT_OFFSET, the data
+// segment on the following page, then the symbol table and section headers.
+struct ElfWriter<'a> {
+    program: &'a AssembledProgram,
+    wide: bool,
+    buffer: Vec<u8>,
+}
+
+impl<'a> ElfWriter<'a> {
+    fn new(program: &'a AssembledProgram) -> Self {
+        ElfWriter {
+            program,
+            wide: program.xlen == 64,
+            buffer: vec![],
+        }
+    }
+
+    fn u8(&mut self, v: u8) {
+        self.buffer.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.buffer.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.buffer.extend_from_slice(&v.to_le_bytes());
+    }
+
+    // Address sized field.
+    fn word(&mut self, v: u64) {
+        if self.wide {
+            self.buffer.extend_from_slice(&v.to_le_bytes());
+        } else {
+            self.u32(v as u32);
+        }
+    }
+
+    fn pad_to(&mut self, offset: u64) {
+        self.buffer.resize(offset as usize, 0);
+    }
+
+    fn program_header(&mut self, flags: u32, offset: u64, vaddr: u64, filesz: u64, memsz: u64) {
+        self.u32(PT_LOAD);
+        if self.wide {
+            self.u32(flags);
+        }
+        self.word(offset);
+        self.word(vaddr);
+        self.word(vaddr);
+        self.word(filesz);
+        self.word(memsz);
+        if !self.wide {
+            self.u32(flags);
+        }
+        self.word(RISCV_PAGESIZE as u64);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn section_header(
+        &mut self,
+        name: u32,
+        kind: u32,
+        flags: u64,
+        addr: u64,
+        offset: u64,
+        size: u64,
+        link: u32,
+        info: u32,
+        align: u64,
+        entsize: u64,
+    ) {
+        self.u32(name);
+        self.u32(kind);
+        self.word(flags);
+        self.word(addr);
+        self.word(offset);
+        self.word(size);
+        self.u32(link);
+        self.u32(info);
+        self.word(align);
+        self.word(entsize);
+    }
+
+    fn write(mut self) -> Bytes {
+        let p = self.program;
+        let (ehsize, phentsize, shentsize, symsize) = if self.wide {
+            (64u64, 56u64, 64u64, 24u64)
+        } else {
+            (52, 32, 40, 16)
+        };
+        let text_size = if p.rodata.is_empty() {
+            p.text.len() as u64
+        } else {
+            p.rodata_address + p.rodata.len() as u64 - TEXT_ADDRESS
+        };
+        let has_data = !p.data.is_empty() || p.bss_size > 0;
+        let data_offset = TEXT_OFFSET + round_page_up(text_size);
+        let data_memsz = p.bss_address + p.bss_size - p.data_address;
+
+        // Sections, in the order of their headers.
+        let mut sections = vec![(Section::Text, TEXT_ADDRESS, p.text.len() as u64)];
+        if !p.rodata.is_empty() {
+            sections.push((Section::Rodata, p.rodata_address, p.rodata.len() as u64));
+        }
+        if !p.data.is_empty() {
+            sections.push((Section::Data, p.data_address, p.data.len() as u64));
+        }
+        if p.bss_size > 0 {
+            sections.push((Section::Bss, p.bss_address, p.bss_size));
+        }
+        let mut shstrtab = vec![0u8];
+        let mut name = |s: &str| {
+            let offset = shstrtab.len() as u32;
+            shstrtab.extend_from_slice(s.as_bytes());
+            shstrtab.push(0);
+            offset
+        };
+        let section_names: Vec<u32> = sections.iter().map(|(s, _, _)| name(s.name())).collect();
+        let symtab_name = name(".symtab");
+        let strtab_name = name(".strtab");
+        let shstrtab_name = name(".shstrtab");
+        let symtab_index = sections.len() as u32 + 1;
+        let shnum = sections.len() as u16 + 4;
+
+        // Local symbols must come first.
+        let mut labels: Vec<&Label> = p.labels.iter().collect();
+        labels.sort_by_key(|l| l.global);
+        let first_global = 1 + labels.iter().filter(|l| !l.global).count() as u32;
+        let mut strtab = vec![0u8];
+        let mut symbols = vec![];
+        for label in &labels {
+            let name = strtab.len() as u32;
+            strtab.extend_from_slice(label.name.as_bytes());
+            strtab.push(0);
+            let index = sections
+                .iter()
+                .position(|(s, _, _)| *s == label.section)
+                // Labels of empty sections are attached to the text.
+                .unwrap_or(0) as u16
+                + 1;
+            let kind = if label.section == Section::Text {
+                STT_FUNC
+            } else {
+                STT_OBJECT
+            };
+            let bind = if label.global { STB_GLOBAL } else { 0 };
+            symbols.push((name, (bind << 4) | kind, index, label.address));
+        }
+
+        // ELF header
+        self.buffer.extend_from_slice(&[
+            0x7f,
+            b'E',
+            b'L',
+            b'F',
+            if self.wide { 2 } else { 1 },
+            1,
+            1,
+        ]);
+        self.pad_to(16);
+        self.u16(ET_EXEC);
+        self.u16(EM_RISCV);
+        self.u32(1);
+        self.word(p.entry);
+        self.word(ehsize);
+        let shoff_position = self.buffer.len();
+        self.word(0);
+        self.u32(if p.compressed { EF_RISCV_RVC } else { 0 });
+        self.u16(ehsize as u16);
+        self.u16(phentsize as u16);
+        self.u16(if has_data { 2 } else { 1 });
+        self.u16(shentsize as u16);
+        self.u16(shnum);
+        self.u16(shnum - 1);
+
+        // Program headers
+        self.program_header(PF_R | PF_X, TEXT_OFFSET, TEXT_ADDRESS, text_size, text_size);
+        if has_data {
+            self.program_header(
+                PF_R | PF_W,
+                data_offset,
+                p.data_address,
+                p.data.len() as u64,
+                data_memsz,
+            );
+        }
+
+        // Segments
+        self.pad_to(TEXT_OFFSET);
+        self.buffer.extend_from_slice(&p.text);
+        if !p.rodata.is_empty() {
+            self.pad_to(TEXT_OFFSET + p.rodata_address - TEXT_ADDRESS);
+            self.buffer.extend_from_slice(&p.rodata);
+        }
+        self.pad_to(data_offset);
+        self.buffer.extend_from_slice(&p.data);
+
+        // Symbol and string tables
+        let symtab_offset = roundup(self.buffer.len() as u64, 8);
+        self.pad_to(symtab_offset);
+        self.buffer.resize(self.buffer.len() + symsize as usize, 0);
+        for (name, info, index, value) in symbols {
+            self.u32(name);
+            if self.wide {
+                self.u8(info);
+                self.u8(0);
+                self.u16(index);
+                self.word(value);
+                self.word(0);
+            } else {
+                self.word(value);
+                self.word(0);
+                self.u8(info);
+                self.u8(0);
+                self.u16(index);
+            }
+        }
+        let symtab_size = self.buffer.len() as u64 - symtab_offset;
+        let strtab_offset = self.buffer.len() as u64;
+        self.buffer.extend_from_slice(&strtab);
+        let shstrtab_offset = self.buffer.len() as u64;
+        self.buffer.extend_from_slice(&shstrtab);
+
+        // Section headers
+        let shoff = roundup(self.buffer.len() as u64, 8);
+        self.pad_to(shoff);
+        self.section_header(0, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+        for ((section, addr, size), name) in sections.iter().zip(section_names) {
+            let (kind, flags, offset) = match section {
+                Section::Text => (
+                    SHT_PROGBITS,
+                    SHF_ALLOC | u64::from(SHF_EXECINSTR),
+                    TEXT_OFFSET,
+                ),
+                Section::Rodata => (SHT_PROGBITS, SHF_ALLOC, TEXT_OFFSET + addr - TEXT_ADDRESS),
+                Section::Data => (SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, data_offset),
+                Section::Bss => (
+                    SHT_NOBITS,
+                    SHF_ALLOC | SHF_WRITE,
+                    data_offset + addr - p.data_address,
+                ),
+            };
+            self.section_header(name, kind, flags, *addr, offset, *size, 0, 0, 8, 0);
+        }
+        self.section_header(
+            symtab_name,
+            SHT_SYMTAB,
+            0,
+            0,
+            symtab_offset,
+            symtab_size,
+            symtab_index + 1,
+            first_global,
+            8,
+            symsize,
+        );
+        self.section_header(
+            strtab_name,
+            SHT_STRTAB,
+            0,
+            0,
+            strtab_offset,
+            strtab.len() as u64,
+            0,
+            0,
+            1,
+            0,
+        );
+        self.section_header(
+            shstrtab_name,
+            SHT_STRTAB,
+            0,
+            0,
+            shstrtab_offset,
+            shstrtab.len() as u64,
+            0,
+            0,
+            1,
+            0,
+        );
+        let shoff_bytes = if self.wide {
+            shoff.to_le_bytes().to_vec()
+        } else {
+            (shoff as u32).to_le_bytes().to_vec()
+        };
+        self.buffer[shoff_position..shoff_position + shoff_bytes.len()]
+            .copy_from_slice(&shoff_bytes);
+        self.buffer.into()
+    }
+}
@@ -0,0 +1,418 @@
+// Control flow graph recovery over the executable segments of a program.
+//
+// Basic blocks follow the machine's own definition: a block ends at every
+// instruction is_basic_block_end_instruction flags, which is also where
+// TraceMachine and AsmMachine end their traces, as well as before any
+// instruction control flow can reach from elsewhere.
+use crate::{
+    disasm::{disassemble, DisassembledInstruction},
+    elf::parse_elf,
+    instructions::{
+        extract_opcode, insts, is_basic_block_end_instruction, Instruction, Itype, Rtype, Stype,
+        Utype,
+    },
+    registers::{RA, T0},
+    Error, Register,
+};
+use bytes::Bytes;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    // Execution continues right after the block, including returning from
+    // a call.
+    Fallthrough,
+    // A taken conditional branch.
+    Branch,
+    // An unconditional jump, either direct or an indirect one whose target
+    // could be resolved.
+    Jump,
+    // Entering a called function.
+    Call,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Edge {
+    pub target: u64,
+    pub kind: EdgeKind,
+}
+
+/// How control leaves a basic block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Terminator {
+    // The block ends without transferring control, e.g. at ECALL, AUIPC or
+    // right before a jump target.
+    Fallthrough,
+    Branch { target: u64 },
+    Jump { target: u64 },
+    // Target is None for indirect calls that could not be resolved.
+    Call { target: Option<u64> },
+    // JALR to the link register.
+    Return,
+    // Any other indirect jump, the target is only known at runtime.
+    IndirectJump,
+    // The block ends with bits that do not decode for the ISA.
+    Invalid,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: u64,
+    // Address right after the last instruction.
+    pub end: u64,
+    pub instructions: Vec<DisassembledInstruction>,
+    pub terminator: Terminator,
+    // Targets outside of executable segments have no block in the graph.
+    pub successors: Vec<Edge>,
+}
+
+impl BasicBlock {
+    /// Whether control can leave the block to somewhere unknown statically.
+    pub fn is_unresolved(&self) -> bool {
+        matches!(
+            self.terminator,
+            Terminator::IndirectJump | Terminator::Call { target: None }
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    pub entry: u64,
+    pub blocks: BTreeMap<u64, BasicBlock>,
+    // Entry points of functions: the program entry, STT_FUNC symbols and
+    // targets of direct calls. Functions without a symbol are named after
+    // their address.
+    pub functions: BTreeMap<u64, String>,
+}
+
+fn is_link_register(r: usize) -> bool {
+    r == RA || r == T0
+}
+
+// Target of a JALR, when the instruction right before it is an AUIPC or LUI
+// materializing the base register, as in the call and tail pseudo
+// instructions. This is the same pattern FAR_JUMP_REL and FAR_JUMP_ABS fuse.
+fn resolve_jalr(previous: Option<&DisassembledInstruction>, jalr: Itype, mask: u64) -> Option<u64> {
+    let previous = previous?;
+    let u = Utype(previous.instruction?);
+    if u.rd() == 0 || u.rd() != jalr.rs1() {
+        return None;
+    }
+    let base = match u.op() {
+        insts::OP_AUIPC => previous.pc.wrapping_add(u.immediate_s() as i64 as u64),
+        insts::OP_LUI => u.immediate_s() as i64 as u64,
+        _ => return None,
+    };
+    Some(base.wrapping_add(jalr.immediate_s() as i64 as u64) & mask & !1)
+}
+
+// Classifies the last instruction of a block. previous is the instruction
+// right before it, provided only when nothing else jumps in between.
+fn terminator(
+    line: &DisassembledInstruction,
+    previous: Option<&DisassembledInstruction>,
+    mask: u64,
+) -> Terminator {
+    let instruction: Instruction = match line.instruction {
+        Some(i) => i,
+        None => return Terminator::Invalid,
+    };
+    let relative = |offset: i32| line.pc.wrapping_add(offset as i64 as u64) & mask;
+    match extract_opcode(instruction) {
+        insts::OP_BEQ
+        | insts::OP_BNE
+        | insts::OP_BLT
+        | insts::OP_BGE
+        | insts::OP_BLTU
+        | insts::OP_BGEU => Terminator::Branch {
+            target: relative(Stype(instruction).immediate_s()),
+        },
+        insts::OP_SLT_BNEZ | insts::OP_SLT_BEQZ | insts::OP_SLTU_BNEZ | insts::OP_SLTU_BEQZ => {
+            Terminator::Branch {
+                target: relative(Rtype(instruction).immediate_s()),
+            }
+        }
+        insts::OP_JAL => {
+            let i = Utype(instruction);
+            let target = relative(i.immediate_s());
+            if i.rd() == 0 {
+                Terminator::Jump { target }
+            } else {
+                Terminator::Call {
+                    target: Some(target),
+                }
+            }
+        }
+        insts::OP_JALR_VERSION0 | insts::OP_JALR_VERSION1 => {
+            let i = Itype(instruction);
+            let target = resolve_jalr(previous, i, mask);
+            match (i.rd(), target) {
+                (0, Some(target)) => Terminator::Jump { target },
+                (0, None) if is_link_register(i.rs1()) && i.immediate_s() == 0 => {
+                    Terminator::Return
+                }
+                (0, None) => Terminator::IndirectJump,
+                (_, target) => Terminator::Call { target },
+            }
+        }
+        _ => Terminator::Fallthrough,
+    }
+}
+
+impl ControlFlowGraph {
+    /// Recovers the control flow graph of all executable segments of
+    /// program, with instructions decoded the same way as disassemble does.
+    pub fn build<R: Register>(program: &Bytes, isa: u8, version: u32) -> Result<Self, Error> {
+        let entry = parse_elf::<R>(program, version)?.entry;
+        let lines = disassemble::<R>(program, isa, version)?;
+        Ok(Self::from_instructions::<R>(entry, &lines))
+    }
+
+    /// Builds the graph from an existing listing, lines must be sorted by pc.
+    pub fn from_instructions<R: Register>(entry: u64, lines: &[DisassembledInstruction]) -> Self {
+        let mask = if R::BITS == 32 {
+            u64::from(u32::MAX)
+        } else {
+            u64::MAX
+        };
+        // Instructions reachable other than by falling through from the
+        // previous one. Resolved JALR targets are left out here, since
+        // resolving requires knowing these first.
+        let mut targets = BTreeSet::new();
+        targets.insert(entry);
+        for (index, line) in lines.iter().enumerate() {
+            if line.symbol.is_some() {
+                targets.insert(line.pc);
+            }
+            match terminator(line, None, mask) {
+                Terminator::Branch { target }
+                | Terminator::Jump { target }
+                | Terminator::Call {
+                    target: Some(target),
+                } => {
+                    targets.insert(target);
+                }
+                _ => (),
+            }
+            let contiguous =
+                index > 0 && lines[index - 1].pc + lines[index - 1].length() == line.pc;
+            if !contiguous {
+                targets.insert(line.pc);
+            }
+        }
+
+        let mut leaders = targets.clone();
+        let mut terminators = Vec::with_capacity(lines.len());
+        for (index, line) in lines.iter().enumerate() {
+            let previous = if index > 0 && !targets.contains(&line.pc) {
+                Some(&lines[index - 1])
+            } else {
+                None
+            };
+            let t = terminator(line, previous, mask);
+            match t {
+                Terminator::Jump { target }
+                | Terminator::Call {
+                    target: Some(target),
+                } => {
+                    leaders.insert(target);
+                }
+                _ => (),
+            }
+            let ends = match line.instruction {
+                Some(i) => is_basic_block_end_instruction(i),
+                None => true,
+            };
+            if ends {
+                leaders.insert(line.pc + line.length());
+            }
+            terminators.push(if ends { Some(t) } else { None });
+        }
+
+        let mut functions = BTreeMap::new();
+        for line in lines {
+            if let Some(symbol) = &line.symbol {
+                functions.insert(line.pc, symbol.clone());
+            }
+        }
+        let mut blocks = BTreeMap::new();
+        let mut index = 0;
+        while index < lines.len() {
+            let start = lines[index].pc;
+            let mut instructions = vec![];
+            let mut terminator = Terminator::Fallthrough;
+            while index < lines.len() {
+                instructions.push(lines[index].clone());
+                index += 1;
+                if let Some(t) = terminators[index - 1] {
+                    terminator = t;
+                    break;
+                }
+                if index < lines.len() && leaders.contains(&lines[index].pc) {
+                    break;
+                }
+            }
+            let last = instructions.last().unwrap();
+            let end = last.pc + last.length();
+            let fallthrough = Edge {
+                target: end,
+                kind: EdgeKind::Fallthrough,
+            };
+            let successors = match terminator {
+                Terminator::Fallthrough => vec![fallthrough],
+                Terminator::Branch { target } => vec![
+                    Edge {
+                        target,
+                        kind: EdgeKind::Branch,
+                    },
+                    fallthrough,
+                ],
+                Terminator::Jump { target } => vec![Edge {
+                    target,
+                    kind: EdgeKind::Jump,
+                }],
+                Terminator::Call { target } => {
+                    let mut edges = vec![];
+                    if let Some(target) = target {
+                        edges.push(Edge {
+                            target,
+                            kind: EdgeKind::Call,
+                        });
+                    }
+                    edges.push(fallthrough);
+                    edges
+                }
+                Terminator::Return | Terminator::IndirectJump | Terminator::Invalid => vec![],
+            };
+            for edge in &successors {
+                if edge.kind == EdgeKind::Call {
+                    functions
+                        .entry(edge.target)
+                        .or_insert_with(|| format!("sub_{:x}", edge.target));
+                }
+            }
+            blocks.insert(
+                start,
+                BasicBlock {
+                    start,
+                    end,
+                    instructions,
+                    terminator,
+                    successors,
+                },
+            );
+        }
+        functions
+            .entry(entry)
+            .or_insert_with(|| format!("sub_{:x}", entry));
+        Self {
+            entry,
+            blocks,
+            functions,
+        }
+    }
+
+    /// The block starting at pc.
+    pub fn block(&self, pc: u64) -> Option<&BasicBlock> {
+        self.blocks.get(&pc)
+    }
+
+    /// The block containing the instruction at pc.
+    pub fn block_containing(&self, pc: u64) -> Option<&BasicBlock> {
+        self.blocks
+            .range(..=pc)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| pc < block.end)
+    }
+
+    /// Start addresses of blocks having an edge to the block at pc.
+    pub fn predecessors(&self, pc: u64) -> Vec<u64> {
+        self.blocks
+            .values()
+            .filter(|block| block.successors.iter().any(|edge| edge.target == pc))
+            .map(|block| block.start)
+            .collect()
+    }
+
+    /// Blocks ending with an indirect jump or call whose target could not
+    /// be resolved statically.
+    pub fn unresolved_blocks(&self) -> Vec<&BasicBlock> {
+        self.blocks.values().filter(|b| b.is_unresolved()).collect()
+    }
+
+    /// Blocks of the function starting at entry, that is, blocks reachable
+    /// from it without entering called functions, sorted by address.
+    pub fn function_blocks(&self, entry: u64) -> Vec<u64> {
+        let mut visited = HashSet::new();
+        let mut pending = vec![entry];
+        while let Some(pc) = pending.pop() {
+            if let Some(block) = self.block(pc) {
+                if visited.insert(pc) {
+                    pending.extend(
+                        block
+                            .successors
+                            .iter()
+                            .filter(|edge| edge.kind != EdgeKind::Call)
+                            .map(|edge| edge.target),
+                    );
+                }
+            }
+        }
+        let mut result: Vec<u64> = visited.into_iter().collect();
+        result.sort_unstable();
+        result
+    }
+
+    /// Renders the graph in Graphviz DOT format. Blocks ending with an
+    /// unresolved indirect jump are drawn in red, call edges are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph cfg {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            if let Some(name) = self.functions.get(&block.start) {
+                write!(label, "<{}>:\\l", escape(name)).unwrap();
+            }
+            for line in &block.instructions {
+                write!(label, "{}\\l", escape(line.to_string().trim_start())).unwrap();
+            }
+            let style = match block.terminator {
+                _ if block.is_unresolved() => ", color=red",
+                Terminator::Invalid => ", style=dashed",
+                _ => "",
+            };
+            writeln!(
+                dot,
+                "    \"{:x}\" [label=\"{}\"{}];",
+                block.start, label, style
+            )
+            .unwrap();
+        }
+        for block in self.blocks.values() {
+            for edge in &block.successors {
+                let attributes = match edge.kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Branch => " [label=\"taken\"]",
+                    EdgeKind::Jump => " [label=\"jump\"]",
+                    EdgeKind::Call => " [label=\"call\", style=dashed]",
+                };
+                writeln!(
+                    dot,
+                    "    \"{:x}\" -> \"{:x}\"{};",
+                    block.start, edge.target, attributes
+                )
+                .unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
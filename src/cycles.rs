@@ -0,0 +1,351 @@
+// Static estimation of the cycles a program can consume, on top of the
+// control flow graph.
+//
+// Cycles are charged per instruction with the same cost function the machine
+// uses. Loops only get an upper bound when their header is annotated with the
+// maximum number of times it runs, and ECALL is assumed to return, so syscalls
+// charging extra cycles need to be accounted for separately.
+use crate::{
+    cfg::{BasicBlock, ControlFlowGraph, EdgeKind, Terminator},
+    instructions::instruction_length,
+    machine::InstructionCycleFunc,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// Why no upper bound could be computed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Unbounded {
+    // A loop, identified by its header block, without a bound.
+    Loop(u64),
+    // A loop entered at more than one block, identified by the lowest one.
+    Irreducible(u64),
+    // A block ending with an unresolved indirect jump or call, or calling
+    // outside of executable segments.
+    Indirect(u64),
+    // A call to an unbounded function, including recursive calls.
+    Call(u64),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FunctionCycles {
+    pub entry: u64,
+    pub name: String,
+    // Cycles of all blocks of the function, each counted once.
+    pub cycles: u64,
+    // Upper bound of the cycles of one invocation, including called functions.
+    pub worst_case: Option<u64>,
+    // Blocks along the most expensive path from the entry. The body of a
+    // loop is listed once. Empty when the function is unbounded.
+    pub worst_path: Vec<u64>,
+    pub unbounded: Vec<Unbounded>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CycleEstimate {
+    pub entry: u64,
+    // Cycles of a single run through each block, keyed by block start.
+    pub blocks: BTreeMap<u64, u64>,
+    pub functions: BTreeMap<u64, FunctionCycles>,
+}
+
+impl CycleEstimate {
+    /// Upper bound of the cycles of the whole program.
+    pub fn worst_case(&self) -> Option<u64> {
+        self.functions.get(&self.entry).and_then(|f| f.worst_case)
+    }
+
+    /// Functions sorted by decreasing worst case cycles, unbounded ones
+    /// first.
+    pub fn most_expensive(&self) -> Vec<&FunctionCycles> {
+        let mut functions: Vec<&FunctionCycles> = self.functions.values().collect();
+        functions.sort_by_key(|f| std::cmp::Reverse(f.worst_case.unwrap_or(u64::MAX)));
+        functions
+    }
+}
+
+pub struct CycleEstimator<'a> {
+    cfg: &'a ControlFlowGraph,
+    instruction_cycle_func: &'a InstructionCycleFunc,
+    loop_bounds: HashMap<u64, u64>,
+}
+
+impl<'a> CycleEstimator<'a> {
+    pub fn new(
+        cfg: &'a ControlFlowGraph,
+        instruction_cycle_func: &'a InstructionCycleFunc,
+    ) -> Self {
+        Self {
+            cfg,
+            instruction_cycle_func,
+            loop_bounds: HashMap::new(),
+        }
+    }
+
+    /// Declares that the header block of a loop runs at most iterations
+    /// times for each entry into the loop.
+    pub fn loop_bound(mut self, header: u64, iterations: u64) -> Self {
+        self.loop_bounds.insert(header, iterations);
+        self
+    }
+
+    /// Cycles of a single run through block. Like the machine, when ISA_MOP
+    /// is enabled, a fused instruction is charged instead of the ones it
+    /// covers. A fusion crossing the end of the block is charged in full and
+    /// the remaining instructions again in the next block, which keeps the
+    /// result an upper bound.
+    pub fn block_cycles(&self, block: &BasicBlock) -> u64 {
+        let mut cycles: u64 = 0;
+        let mut next = block.start;
+        for line in &block.instructions {
+            if line.pc < next {
+                continue;
+            }
+            let instruction = match (line.fused, line.instruction) {
+                (Some(fused), _) => fused,
+                (None, Some(instruction)) => instruction,
+                (None, None) => continue,
+            };
+            cycles = cycles.saturating_add((self.instruction_cycle_func)(instruction));
+            next = line.pc + u64::from(instruction_length(instruction));
+        }
+        cycles
+    }
+
+    pub fn estimate(&self) -> CycleEstimate {
+        let mut analysis = Analysis {
+            estimator: self,
+            blocks: self
+                .cfg
+                .blocks
+                .values()
+                .map(|b| (b.start, self.block_cycles(b)))
+                .collect(),
+            functions: BTreeMap::new(),
+            in_progress: HashSet::new(),
+        };
+        for entry in self.cfg.functions.keys() {
+            if self.cfg.block(*entry).is_some() {
+                analysis.function(*entry);
+            }
+        }
+        CycleEstimate {
+            entry: self.cfg.entry,
+            blocks: analysis.blocks,
+            functions: analysis.functions,
+        }
+    }
+}
+
+struct Analysis<'a, 'b> {
+    estimator: &'b CycleEstimator<'a>,
+    blocks: BTreeMap<u64, u64>,
+    functions: BTreeMap<u64, FunctionCycles>,
+    in_progress: HashSet<u64>,
+}
+
+// Worst case cycles of a region, with the blocks along the path.
+type RegionCycles = (u64, Vec<u64>);
+
+impl<'a, 'b> Analysis<'a, 'b> {
+    fn function(&mut self, entry: u64) -> Option<u64> {
+        if let Some(f) = self.functions.get(&entry) {
+            return f.worst_case;
+        }
+        if !self.in_progress.insert(entry) {
+            return None;
+        }
+        let cfg = self.estimator.cfg;
+        let nodes: BTreeSet<u64> = cfg.function_blocks(entry).into_iter().collect();
+        let cycles = nodes
+            .iter()
+            .fold(0u64, |sum, pc| sum.saturating_add(self.blocks[pc]));
+        let mut unbounded = BTreeSet::new();
+        let (worst_case, worst_path) = self.region(&nodes, entry, false, &mut unbounded);
+        self.in_progress.remove(&entry);
+        let bounded = unbounded.is_empty();
+        self.functions.insert(
+            entry,
+            FunctionCycles {
+                entry,
+                name: cfg.functions.get(&entry).cloned().unwrap_or_default(),
+                cycles,
+                worst_case: if bounded { Some(worst_case) } else { None },
+                worst_path: if bounded { worst_path } else { vec![] },
+                unbounded: unbounded.into_iter().collect(),
+            },
+        );
+        self.functions[&entry].worst_case
+    }
+
+    // Cycles of one run through the block, including called functions.
+    fn weight(&mut self, pc: u64, unbounded: &mut BTreeSet<Unbounded>) -> u64 {
+        let cfg = self.estimator.cfg;
+        let block = &cfg.blocks[&pc];
+        let mut cycles = self.blocks[&pc];
+        match block.terminator {
+            Terminator::IndirectJump | Terminator::Call { target: None } => {
+                unbounded.insert(Unbounded::Indirect(pc));
+            }
+            Terminator::Call {
+                target: Some(target),
+            } => {
+                if cfg.block(target).is_none() {
+                    unbounded.insert(Unbounded::Indirect(pc));
+                } else if let Some(callee) = self.function(target) {
+                    cycles = cycles.saturating_add(callee);
+                } else {
+                    unbounded.insert(Unbounded::Call(target));
+                }
+            }
+            _ => (),
+        }
+        cycles
+    }
+
+    // Successors of pc within nodes, skipping calls and, when cut is set,
+    // edges going back to entry.
+    fn successors(&self, nodes: &BTreeSet<u64>, entry: u64, cut: bool, pc: u64) -> Vec<u64> {
+        self.estimator.cfg.blocks[&pc]
+            .successors
+            .iter()
+            .filter(|e| e.kind != EdgeKind::Call)
+            .map(|e| e.target)
+            .filter(|t| nodes.contains(t) && !(cut && *t == entry))
+            .collect()
+    }
+
+    // Longest path from entry until control leaves nodes. Loops are
+    // collapsed into their header, costing their bound times the longest
+    // path through one iteration.
+    fn region(
+        &mut self,
+        nodes: &BTreeSet<u64>,
+        entry: u64,
+        cut: bool,
+        unbounded: &mut BTreeSet<Unbounded>,
+    ) -> RegionCycles {
+        let components = self.components(nodes, entry, cut);
+        let mut component_of = HashMap::new();
+        for (index, component) in components.iter().enumerate() {
+            for pc in component {
+                component_of.insert(*pc, index);
+            }
+        }
+        let mut best: Vec<RegionCycles> = vec![(0, vec![]); components.len()];
+        // Components are in topological order, successors come later.
+        for index in (0..components.len()).rev() {
+            let members = &components[index];
+            let mut exits = vec![];
+            let mut looping = members.len() > 1;
+            for pc in members {
+                for target in self.successors(nodes, entry, cut, *pc) {
+                    match component_of.get(&target) {
+                        Some(c) if *c == index => looping = true,
+                        Some(c) => exits.push(*c),
+                        None => (),
+                    }
+                }
+            }
+            let (cycles, mut path) = if looping {
+                self.loop_cycles(nodes, entry, cut, members, &component_of, unbounded)
+            } else {
+                (self.weight(members[0], unbounded), vec![members[0]])
+            };
+            let (after, rest) = exits
+                .into_iter()
+                .map(|c| &best[c])
+                .max_by_key(|(cycles, _)| *cycles)
+                .cloned()
+                .unwrap_or_default();
+            path.extend(rest);
+            best[index] = (cycles.saturating_add(after), path);
+        }
+        best.into_iter().next().unwrap_or_default()
+    }
+
+    fn loop_cycles(
+        &mut self,
+        nodes: &BTreeSet<u64>,
+        entry: u64,
+        cut: bool,
+        members: &[u64],
+        component_of: &HashMap<u64, usize>,
+        unbounded: &mut BTreeSet<Unbounded>,
+    ) -> RegionCycles {
+        let index = component_of[&members[0]];
+        let mut headers = BTreeSet::new();
+        for pc in component_of.keys() {
+            if component_of[pc] == index {
+                continue;
+            }
+            for target in self.successors(nodes, entry, cut, *pc) {
+                if component_of.get(&target) == Some(&index) {
+                    headers.insert(target);
+                }
+            }
+        }
+        if members.contains(&entry) {
+            headers.insert(entry);
+        }
+        let header = *headers.iter().next().unwrap();
+        if headers.len() > 1 {
+            unbounded.insert(Unbounded::Irreducible(header));
+            return (0, vec![]);
+        }
+        let body: BTreeSet<u64> = members.iter().cloned().collect();
+        let (cycles, path) = self.region(&body, header, true, unbounded);
+        match self.estimator.loop_bounds.get(&header) {
+            Some(bound) => (cycles.saturating_mul(*bound), path),
+            None => {
+                unbounded.insert(Unbounded::Loop(header));
+                (0, vec![])
+            }
+        }
+    }
+
+    // Strongly connected components of the blocks reachable from entry,
+    // in topological order, the first one containing entry.
+    fn components(&self, nodes: &BTreeSet<u64>, entry: u64, cut: bool) -> Vec<Vec<u64>> {
+        let mut order = vec![];
+        let mut visited = HashSet::new();
+        let mut predecessors: HashMap<u64, Vec<u64>> = HashMap::new();
+        let mut stack = vec![(entry, self.successors(nodes, entry, cut, entry))];
+        visited.insert(entry);
+        while let Some((pc, pending)) = stack.last_mut() {
+            let pc = *pc;
+            match pending.pop() {
+                Some(target) => {
+                    predecessors.entry(target).or_default().push(pc);
+                    if visited.insert(target) {
+                        let successors = self.successors(nodes, entry, cut, target);
+                        stack.push((target, successors));
+                    }
+                }
+                None => {
+                    order.push(pc);
+                    stack.pop();
+                }
+            }
+        }
+        let mut components = vec![];
+        let mut assigned = HashSet::new();
+        for pc in order.into_iter().rev() {
+            if !assigned.insert(pc) {
+                continue;
+            }
+            let mut component = vec![pc];
+            let mut pending = vec![pc];
+            while let Some(current) = pending.pop() {
+                for p in predecessors.get(&current).into_iter().flatten() {
+                    if assigned.insert(*p) {
+                        component.push(*p);
+                        pending.push(*p);
+                    }
+                }
+            }
+            component.sort_unstable();
+            components.push(component);
+        }
+        components
+    }
+}
@@ -1,14 +1,21 @@
-use ckb_vm_definitions::instructions::{self as insts};
-use ckb_vm_definitions::registers::{RA, ZERO};
-
+use crate::elf::ProgramMetadata;
 use crate::error::OutOfBoundKind;
+use crate::fusion::{
+    default_fusion_rules, CompiledRule, FusionRule, MAX_FUSED_LENGTH, MAX_PATTERN_LENGTH,
+};
+use crate::hash::blake2b_256;
 use crate::instructions::{
-    a, b, extract_opcode, i, instruction_length, m, rvc, set_instruction_length_n, Instruction,
-    InstructionFactory, Itype, R4type, R5type, Register, Rtype, Utype,
+    a, b, extract_opcode, i, instruction_length, instruction_registers, m, rvc,
+    set_instruction_length_n, Instruction, InstructionFactory, Register,
+};
+use crate::isa::{isa_to_str, register_number};
+use crate::machine::{CoreMachine, DefaultCoreMachine, SupportMachine};
+use crate::memory::{
+    sparse::SparseMemory, wxorx::WXorXMemory, Memory, FLAG_EXECUTABLE, FLAG_WXORX_BIT,
 };
-use crate::machine::VERSION2;
-use crate::memory::Memory;
-use crate::{Error, ISA_A, ISA_B, ISA_MOP, RISCV_PAGESIZE};
+use crate::{Error, ISA_A, ISA_B, ISA_MOP, RISCV_GENERAL_REGISTER_NUMBER, RISCV_PAGESIZE};
+use bytes::Bytes;
+use std::sync::Arc;
 
 const RISCV_PAGESIZE_MASK: u64 = RISCV_PAGESIZE as u64 - 1;
 const INSTRUCTION_CACHE_SIZE: usize = 4096;
@@ -16,34 +23,97 @@ const INSTRUCTION_CACHE_SIZE: usize = 4096;
 pub trait InstDecoder {
     fn decode<M: Memory>(&mut self, memory: &mut M, pc: u64) -> Result<Instruction, Error>;
     fn reset_instructions_cache(&mut self) -> Result<(), Error>;
+
+    // Register width, in bits, the decoded instructions are meant for.
+    fn xlen(&self) -> u8 {
+        64
+    }
+
+    // Called before a machine starts running with this decoder, so decoders
+    // built ahead of time can reject a machine they were not built for.
+    fn check_machine(&self, _isa: u8, _version: u32, _xlen: u8) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 pub struct Decoder {
     factories: Vec<InstructionFactory>,
     mop: bool,
     version: u32,
+    xlen: u8,
+    // Instructions referencing registers beyond this are rejected, see ISA_E.
+    register_number: usize,
+    // Fusion rules indexed by the opcode of their first instruction.
+    fusion_rules: Vec<Vec<CompiledRule>>,
     // Use a cache of instructions to avoid decoding the same instruction
     // twice, pc is the key and the instruction is the value.
     //
     // Use Vector so that the data is on the heap. Otherwise, if there is
     // a vm call chain, it will quickly consume Rust's 2M stack space.
     instructions_cache: Vec<(u64, u64)>,
+    // Results of matching the fusion rules at a pc, keyed like the
+    // instructions cache. Only instructions heading some rule land here.
+    fusion_cache: Vec<(u64, u64)>,
 }
 
 impl Decoder {
     pub fn new(mop: bool, version: u32) -> Decoder {
-        Decoder {
+        let mut decoder = Decoder {
             factories: vec![],
             mop,
             version,
+            xlen: 64,
+            register_number: RISCV_GENERAL_REGISTER_NUMBER,
+            fusion_rules: vec![],
             instructions_cache: vec![(u64::MAX as u64, 0); INSTRUCTION_CACHE_SIZE],
+            fusion_cache: vec![(u64::MAX, 0); INSTRUCTION_CACHE_SIZE],
+        };
+        if mop {
+            for rule in default_fusion_rules(version) {
+                decoder
+                    .add_fusion_rule(rule)
+                    .expect("default fusion rules are valid");
+            }
         }
+        decoder
     }
 
     pub fn add_instruction_factory(&mut self, factory: InstructionFactory) {
         self.factories.push(factory);
     }
 
+    // Appends a fusion rule, it is tried after the rules already registered
+    // for the same head. Rules not enabled in the decoder's VERSION are
+    // ignored. Fusion only happens when the decoder is created with mop on.
+    pub fn add_fusion_rule(&mut self, rule: FusionRule) -> Result<(), Error> {
+        let compiled = CompiledRule::new(rule)?;
+        if let (true, Some(head)) = (rule.enabled(self.version), rule.head()) {
+            let head = head as usize;
+            if head >= self.fusion_rules.len() {
+                self.fusion_rules.resize(head + 1, vec![]);
+            }
+            self.fusion_rules[head].push(compiled);
+            self.reset_fusion_cache();
+        }
+        Ok(())
+    }
+
+    pub fn clear_fusion_rules(&mut self) {
+        self.fusion_rules.clear();
+        self.reset_fusion_cache();
+    }
+
+    fn reset_fusion_cache(&mut self) {
+        self.fusion_cache = vec![(u64::MAX, 0); INSTRUCTION_CACHE_SIZE];
+    }
+
+    pub fn fusion_rules(&self) -> impl Iterator<Item = &FusionRule> {
+        self.fusion_rules
+            .iter()
+            .flatten()
+            .map(|compiled| &compiled.rule)
+    }
+
     // This method is used to decode instruction raw bits from memory pointed
     // by current PC. Right now we support 32-bit instructions and RVC compressed
     // instructions. In future version we might add support for longer instructions.
@@ -102,18 +172,7 @@ impl Decoder {
         if pc as usize >= memory.memory_size() {
             return Err(Error::MemOutOfBound(pc, OutOfBoundKind::Memory));
         }
-        let instruction_cache_key = {
-            // according to RISC-V instruction encoding, the lowest bit in PC will always be zero
-            let pc = pc >> 1;
-            // Here we try to balance between local code and remote code. At times,
-            // we can find the code jumping to a remote function(e.g., memcpy or
-            // alloc), then resumes execution at a local location. Previous cache
-            // key only optimizes for local operations, while this new cache key
-            // balances the code between a 8192-byte local region, and certain remote
-            // code region. Notice the value 12 and 8 here are chosen by empirical
-            // evidence.
-            ((pc & 0xFF) | (pc >> 12 << 8)) as usize % INSTRUCTION_CACHE_SIZE
-        };
+        let instruction_cache_key = instruction_cache_key(pc);
         let cached_instruction = self.instructions_cache[instruction_cache_key];
         if cached_instruction.0 == pc {
             return Ok(cached_instruction.1);
@@ -121,6 +180,14 @@ impl Decoder {
         let instruction_bits = self.decode_bits(memory, pc)?;
         for factory in &self.factories {
             if let Some(instruction) = factory(instruction_bits, self.version) {
+                let registers = instruction_registers(instruction);
+                if let Some(register) = registers.iter().find(|r| **r >= self.register_number) {
+                    return Err(Error::InvalidRegister {
+                        pc,
+                        instruction: instruction_bits,
+                        register: *register as u8,
+                    });
+                }
                 self.instructions_cache[instruction_cache_key] = (pc, instruction);
                 return Ok(instruction);
             }
@@ -141,721 +208,119 @@ impl Decoder {
     pub fn decode_mop<M: Memory>(&mut self, memory: &mut M, pc: u64) -> Result<Instruction, Error> {
         let head_instruction = self.decode_raw(memory, pc)?;
         let head_opcode = extract_opcode(head_instruction);
-        match head_opcode {
-            insts::OP_ADD => {
-                let rule_adc = |decoder: &mut Self,
-                                memory: &mut M|
-                 -> Result<Option<Instruction>, Error> {
-                    let head_inst = Rtype(head_instruction);
-                    let head_size = instruction_length(head_instruction);
-                    if head_inst.rd() != head_inst.rs1() || head_inst.rs1() == head_inst.rs2() {
-                        return Ok(None);
-                    }
-                    let next_instruction = decoder.decode_raw(memory, pc + head_size as u64)?;
-                    let next_opcode = extract_opcode(next_instruction);
-                    if next_opcode != insts::OP_SLTU {
-                        return Ok(None);
-                    }
-                    let next_inst = Rtype(next_instruction);
-                    let next_size = instruction_length(next_instruction);
-                    if next_inst.rd() != head_inst.rs2()
-                        || head_inst.rs2() != next_inst.rs2()
-                        || next_inst.rs1() != head_inst.rs1()
-                    {
-                        return Ok(None);
-                    }
-                    let neck_instruction =
-                        decoder.decode_raw(memory, pc + head_size as u64 + next_size as u64)?;
-                    let neck_opcode = extract_opcode(neck_instruction);
-                    if neck_opcode != insts::OP_ADD {
-                        return Ok(None);
-                    }
-                    let neck_inst = Rtype(neck_instruction);
-                    let neck_size = instruction_length(neck_instruction);
-                    if neck_inst.rd() != neck_inst.rs1()
-                        || neck_inst.rs1() != next_inst.rs1()
-                        || neck_inst.rs2() == head_inst.rs1()
-                        || neck_inst.rs2() == head_inst.rs2()
-                    {
-                        return Ok(None);
-                    }
-                    let body_instruction = decoder.decode_raw(
-                        memory,
-                        pc + head_size as u64 + next_size as u64 + neck_size as u64,
-                    )?;
-                    let body_opcode = extract_opcode(body_instruction);
-                    if body_opcode != insts::OP_SLTU {
-                        return Ok(None);
-                    }
-                    let body_inst = Rtype(body_instruction);
-                    let body_size = instruction_length(body_instruction);
-                    if body_inst.rd() != body_inst.rs2()
-                        || body_inst.rs2() != neck_inst.rs2()
-                        || body_inst.rs1() != neck_inst.rs1()
-                    {
-                        return Ok(None);
-                    }
-                    let tail_instruction = decoder.decode_raw(
-                        memory,
-                        pc + head_size as u64
-                            + next_size as u64
-                            + neck_size as u64
-                            + body_size as u64,
-                    )?;
-                    let tail_opcode = extract_opcode(tail_instruction);
-                    if tail_opcode != insts::OP_OR {
-                        return Ok(None);
-                    }
-                    let tail_inst = Rtype(tail_instruction);
-                    let tail_size = instruction_length(tail_instruction);
-                    if tail_inst.rd() != tail_inst.rs1()
-                        || tail_inst.rs1() != head_inst.rs2()
-                        || tail_inst.rs2() != body_inst.rs2()
-                    {
-                        return Ok(None);
-                    }
-                    if head_inst.rd() == ZERO || next_inst.rd() == ZERO || body_inst.rd() == ZERO {
-                        return Ok(None);
-                    }
-                    let fuze_inst = Rtype::new(
-                        insts::OP_ADC,
-                        head_inst.rd(),
-                        next_inst.rd(),
-                        body_inst.rd(),
-                    );
-                    let fuze_size = head_size + next_size + neck_size + body_size + tail_size;
-                    Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)))
-                };
-                let rule_add3 =
-                    |decoder: &mut Self, memory: &mut M| -> Result<Option<Instruction>, Error> {
-                        if decoder.version < VERSION2 {
-                            return Ok(None);
-                        }
-
-                        let i0 = Rtype(head_instruction);
-                        let i0_size = instruction_length(head_instruction);
-
-                        let (i1, i1_size) = {
-                            let i1 = decoder.decode_raw(memory, pc + i0_size as u64)?;
-                            let i1_opcode = extract_opcode(i1);
-                            if i1_opcode != insts::OP_SLTU {
-                                return Ok(None);
-                            }
-                            (Rtype(i1), instruction_length(i1))
-                        };
-
-                        let (i2, i2_size) = {
-                            let i2 =
-                                decoder.decode_raw(memory, pc + i0_size as u64 + i1_size as u64)?;
-                            let i2_opcode = extract_opcode(i2);
-                            if i2_opcode != insts::OP_ADD {
-                                return Ok(None);
-                            }
-                            (Rtype(i2), instruction_length(i2))
-                        };
-
-                        let fuze_size = i0_size + i1_size + i2_size;
-
-                        {
-                            // add r0, r1, r0
-                            // sltu r2, r0, r1
-                            // add r3, r2, r4
-                            //
-                            // r0 != r1
-                            // r0 != r4
-                            // r2 != r4
-                            // r0 != x0
-                            // r2 != x0
-                            let r0 = i0.rd();
-                            let r1 = i0.rs1();
-                            let r2 = i1.rd();
-                            let r3 = i2.rd();
-                            let r4 = i2.rs2();
-
-                            if i0.rd() == r0
-                                && i0.rs1() == r1
-                                && i0.rs2() == r0
-                                && i1.rd() == r2
-                                && i1.rs1() == r0
-                                && i1.rs2() == r1
-                                && i2.rd() == r3
-                                && i2.rs1() == r2
-                                && i2.rs2() == r4
-                                && r0 != r1
-                                && r0 != r4
-                                && r2 != r4
-                                && r0 != ZERO
-                                && r2 != ZERO
-                            {
-                                let fuze_inst = R5type::new(insts::OP_ADD3A, r0, r1, r2, r3, r4);
-                                return Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)));
-                            }
-                        }
-
-                        {
-                            // add r0, r1, r2
-                            // sltu r1, r0, r1
-                            // add r3, r1, r4
-                            //
-                            // r0 != r1
-                            // r0 != r4
-                            // r1 != r4
-                            // r0 != x0
-                            // r1 != x0
-                            let r0 = i0.rd();
-                            let r1 = i0.rs1();
-                            let r2 = i0.rs2();
-                            let r3 = i2.rd();
-                            let r4 = i2.rs2();
-
-                            if i0.rd() == r0
-                                && i0.rs1() == r1
-                                && i0.rs2() == r2
-                                && i1.rd() == r1
-                                && i1.rs1() == r0
-                                && i1.rs2() == r1
-                                && i2.rd() == r3
-                                && i2.rs1() == r1
-                                && i2.rs2() == r4
-                                && r0 != r1
-                                && r0 != r4
-                                && r1 != r4
-                                && r0 != ZERO
-                                && r1 != ZERO
-                            {
-                                let fuze_inst = R5type::new(insts::OP_ADD3B, r0, r1, r2, r3, r4);
-                                return Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)));
-                            }
-                        }
-                        {
-                            // add r0, r1, r2
-                            // sltu r3, r0, r1
-                            // add r3, r3, r4
-                            //
-                            // r0 != r1
-                            // r0 != r4
-                            // r3 != r4
-                            // r0 != x0
-                            // r3 != x0
-                            let r0 = i0.rd();
-                            let r1 = i0.rs1();
-                            let r2 = i0.rs2();
-                            let r3 = i1.rd();
-                            let r4 = i2.rs2();
-
-                            if i0.rd() == r0
-                                && i0.rs1() == r1
-                                && i0.rs2() == r2
-                                && i1.rd() == r3
-                                && i1.rs1() == r0
-                                && i1.rs2() == r1
-                                && i2.rd() == r3
-                                && i2.rs1() == r3
-                                && i2.rs2() == r4
-                                && r0 != r1
-                                && r0 != r4
-                                && r3 != r4
-                                && r0 != ZERO
-                                && r3 != ZERO
-                            {
-                                let fuze_inst = R5type::new(insts::OP_ADD3C, r0, r1, r2, r3, r4);
-                                return Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)));
-                            }
-                        }
-                        Ok(None)
-                    };
-                let rule_adcs =
-                    |decoder: &mut Self, memory: &mut M| -> Result<Option<Instruction>, Error> {
-                        // add r0, r1, r2
-                        // sltu r3, r0, r1
-                        //
-                        // or
-                        //
-                        // add r0, r2, r1
-                        // sltu r3, r0, r1
-                        //
-                        // r0 != r1
-                        // r0 != x0
-                        if decoder.version < VERSION2 {
-                            return Ok(None);
-                        }
-
-                        let mut i0 = Rtype(head_instruction);
-                        let i0_size = instruction_length(head_instruction);
-
-                        if i0.rd() == i0.rs1() && i0.rd() != i0.rs2() {
-                            i0 = Rtype::new(i0.op(), i0.rd(), i0.rs2(), i0.rs1());
-                        }
-
-                        let (i1, i1_size) = {
-                            let i1 = decoder.decode_raw(memory, pc + i0_size as u64)?;
-                            let i1_opcode = extract_opcode(i1);
-                            if i1_opcode != insts::OP_SLTU {
-                                return Ok(None);
-                            }
-                            (Rtype(i1), instruction_length(i1))
-                        };
-
-                        let r0 = i0.rd();
-                        let r1 = i0.rs1();
-                        let r2 = i0.rs2();
-                        let r3 = i1.rd();
-
-                        if i0.rd() == r0
-                            && i0.rs1() == r1
-                            && i0.rs2() == r2
-                            && i1.rd() == r3
-                            && i1.rs1() == r0
-                            && i1.rs2() == r1
-                            && r0 != r1
-                            && r0 != ZERO
-                        {
-                            let fuze_inst = R4type::new(insts::OP_ADCS, r0, r1, r2, r3);
-                            let fuze_size = i0_size + i1_size;
-                            Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)))
-                        } else {
-                            Ok(None)
-                        }
-                    };
-                if let Ok(Some(i)) = rule_adc(self, memory) {
-                    Ok(i)
-                } else if let Ok(Some(i)) = rule_add3(self, memory) {
-                    Ok(i)
-                } else if let Ok(Some(i)) = rule_adcs(self, memory) {
-                    Ok(i)
-                } else {
-                    Ok(head_instruction)
-                }
-            }
-            insts::OP_SUB => {
-                let rule_sbb =
-                    |decoder: &mut Self, memory: &mut M| -> Result<Option<Instruction>, Error> {
-                        let head_inst = Rtype(head_instruction);
-                        let head_size = instruction_length(head_instruction);
-                        if head_inst.rd() != head_inst.rs2() || head_inst.rs1() == head_inst.rs2() {
-                            return Ok(None);
-                        }
-                        let next_instruction = decoder.decode_raw(memory, pc + head_size as u64)?;
-                        let next_opcode = extract_opcode(next_instruction);
-                        if next_opcode != insts::OP_SLTU {
-                            return Ok(None);
-                        }
-                        let next_inst = Rtype(next_instruction);
-                        let next_size = instruction_length(next_instruction);
-                        if next_inst.rd() == head_inst.rs1()
-                            || next_inst.rd() == head_inst.rs2()
-                            || next_inst.rs1() != head_inst.rs1()
-                            || next_inst.rs2() != next_inst.rs2()
-                        {
-                            return Ok(None);
-                        }
-                        let neck_instruction =
-                            decoder.decode_raw(memory, pc + head_size as u64 + next_size as u64)?;
-                        let neck_opcode = extract_opcode(neck_instruction);
-                        if neck_opcode != insts::OP_SUB {
-                            return Ok(None);
-                        }
-                        let neck_inst = Rtype(neck_instruction);
-                        let neck_size = instruction_length(neck_instruction);
-                        if neck_inst.rd() != head_inst.rs1()
-                            || neck_inst.rs1() != head_inst.rs2()
-                            || neck_inst.rs2() == head_inst.rs1()
-                            || neck_inst.rs2() == head_inst.rs2()
-                            || neck_inst.rs2() == next_inst.rd()
-                        {
-                            return Ok(None);
-                        }
-                        let body_instruction = decoder.decode_raw(
-                            memory,
-                            pc + head_size as u64 + next_size as u64 + neck_size as u64,
-                        )?;
-                        let body_opcode = extract_opcode(body_instruction);
-                        if body_opcode != insts::OP_SLTU {
-                            return Ok(None);
-                        }
-                        let body_inst = Rtype(body_instruction);
-                        let body_size = instruction_length(body_instruction);
-                        if body_inst.rd() != neck_inst.rs2()
-                            || body_inst.rs1() != head_inst.rs2()
-                            || body_inst.rs2() != head_inst.rs1()
-                        {
-                            return Ok(None);
-                        }
-                        let tail_instruction = decoder.decode_raw(
-                            memory,
-                            pc + head_size as u64
-                                + next_size as u64
-                                + neck_size as u64
-                                + body_size as u64,
-                        )?;
-                        let tail_opcode = extract_opcode(tail_instruction);
-                        if tail_opcode != insts::OP_OR {
-                            return Ok(None);
-                        }
-                        let tail_inst = Rtype(tail_instruction);
-                        let tail_size = instruction_length(tail_instruction);
-                        if tail_inst.rd() != head_inst.rd()
-                            || tail_inst.rs1() != neck_inst.rs2()
-                            || tail_inst.rs2() != next_inst.rd()
-                        {
-                            return Ok(None);
-                        }
-                        let fuze_inst = R4type::new(
-                            insts::OP_SBB,
-                            head_inst.rs1(),
-                            head_inst.rs2(),
-                            neck_inst.rs2(),
-                            next_inst.rd(),
-                        );
-                        if head_inst.rs1() == ZERO
-                            || head_inst.rs2() == ZERO
-                            || neck_inst.rs2() == ZERO
-                            || next_inst.rd() == ZERO
-                        {
-                            return Ok(None);
-                        }
-                        let fuze_size = head_size + next_size + neck_size + body_size + tail_size;
-                        Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)))
-                    };
-                let rule_sbbs =
-                    |decoder: &mut Self, memory: &mut M| -> Result<Option<Instruction>, Error> {
-                        // sub r0, r1, r2
-                        // sltu r3, r1, r2
-                        //
-                        // r0 != r1
-                        // r0 != r2
-                        if decoder.version < VERSION2 {
-                            return Ok(None);
-                        }
-
-                        let i0 = Rtype(head_instruction);
-                        let i0_size = instruction_length(head_instruction);
-
-                        let (i1, i1_size) = {
-                            let i1 = decoder.decode_raw(memory, pc + i0_size as u64)?;
-                            let i1_opcode = extract_opcode(i1);
-                            if i1_opcode != insts::OP_SLTU {
-                                return Ok(None);
-                            }
-                            (Rtype(i1), instruction_length(i1))
-                        };
-
-                        let r0 = i0.rd();
-                        let r1 = i0.rs1();
-                        let r2 = i0.rs2();
-                        let r3 = i1.rd();
-
-                        if i0.rd() == r0
-                            && i0.rs1() == r1
-                            && i0.rs2() == r2
-                            && i1.rd() == r3
-                            && i1.rs1() == r1
-                            && i1.rs2() == r2
-                            && r0 != r1
-                            && r0 != r2
-                        {
-                            let fuze_inst = R4type::new(insts::OP_SBBS, r0, r1, r2, r3);
-                            let fuze_size = i0_size + i1_size;
-                            Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)))
-                        } else {
-                            Ok(None)
-                        }
-                    };
-                if let Ok(Some(i)) = rule_sbb(self, memory) {
-                    Ok(i)
-                } else if let Ok(Some(i)) = rule_sbbs(self, memory) {
-                    Ok(i)
-                } else {
-                    Ok(head_instruction)
-                }
-            }
-            insts::OP_LUI => {
-                let head_inst = Utype(head_instruction);
-                let head_size = instruction_length(head_instruction);
-                let next_instruction = match self.decode_raw(memory, pc + head_size as u64) {
-                    Ok(ni) => ni,
-                    Err(_) => return Ok(head_instruction),
-                };
-                let next_opcode = extract_opcode(next_instruction);
-                match next_opcode {
-                    insts::OP_JALR_VERSION1 => {
-                        let next_inst = Itype(next_instruction);
-                        let test_condition = if self.version >= VERSION2 {
-                            next_inst.rs1() == head_inst.rd()
-                                && next_inst.rd() == RA
-                                && next_inst.rs1() == RA
-                        } else {
-                            next_inst.rs1() == head_inst.rd() && next_inst.rd() == RA
-                        };
-                        if test_condition {
-                            let fuze_imm = head_inst
-                                .immediate_s()
-                                .wrapping_add(next_inst.immediate_s());
-                            let fuze_inst = Utype::new_s(insts::OP_FAR_JUMP_ABS, RA, fuze_imm);
-                            let next_size = instruction_length(next_instruction);
-                            let fuze_size = head_size + next_size;
-                            Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
-                        } else {
-                            Ok(head_instruction)
-                        }
-                    }
-                    insts::OP_ADDIW => {
-                        let next_inst = Itype(next_instruction);
-                        if next_inst.rs1() == next_inst.rd() && next_inst.rd() == head_inst.rd() {
-                            let fuze_imm = head_inst
-                                .immediate_s()
-                                .wrapping_add(next_inst.immediate_s());
-                            let fuze_inst =
-                                Utype::new_s(insts::OP_CUSTOM_LOAD_IMM, head_inst.rd(), fuze_imm);
-                            let next_size = instruction_length(next_instruction);
-                            let fuze_size = head_size + next_size;
-                            Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
-                        } else {
-                            Ok(head_instruction)
-                        }
-                    }
-                    _ => Ok(head_instruction),
-                }
-            }
-            insts::OP_AUIPC => {
-                let head_inst = Utype(head_instruction);
-                let head_size = instruction_length(head_instruction);
-                let next_instruction = match self.decode_raw(memory, pc + head_size as u64) {
-                    Ok(ni) => ni,
-                    Err(_) => return Ok(head_instruction),
-                };
-                let next_opcode = extract_opcode(next_instruction);
-                match next_opcode {
-                    insts::OP_JALR_VERSION1 => {
-                        let next_inst = Itype(next_instruction);
-                        let mut result = head_instruction;
-
-                        if self.version >= VERSION2 {
-                            if next_inst.rs1() == head_inst.rd()
-                                && next_inst.rd() == RA
-                                && next_inst.rs1() == RA
-                            {
-                                if let Some(fuze_imm) =
-                                    head_inst.immediate_s().checked_add(next_inst.immediate_s())
-                                {
-                                    let fuze_inst =
-                                        Utype::new_s(insts::OP_FAR_JUMP_REL, RA, fuze_imm);
-                                    let next_size = instruction_length(next_instruction);
-                                    let fuze_size = head_size + next_size;
-                                    result = set_instruction_length_n(fuze_inst.0, fuze_size);
-                                }
-                            }
-                        } else {
-                            if next_inst.rs1() == head_inst.rd() && next_inst.rd() == RA {
-                                let fuze_imm = head_inst
-                                    .immediate_s()
-                                    .wrapping_add(next_inst.immediate_s());
-                                let fuze_inst = Utype::new_s(insts::OP_FAR_JUMP_REL, RA, fuze_imm);
-                                let next_size = instruction_length(next_instruction);
-                                let fuze_size = head_size + next_size;
-                                result = set_instruction_length_n(fuze_inst.0, fuze_size);
-                            }
-                        }
-                        Ok(result)
-                    }
-                    insts::OP_ADDI if self.version >= VERSION2 => {
-                        let next_inst = Itype(next_instruction);
-                        let mut result = head_instruction;
-
-                        if next_inst.rs1() == next_inst.rd() && next_inst.rd() == head_inst.rd() {
-                            if let Ok(pc) = i32::try_from(pc) {
-                                if let Some(fuze_imm) = head_inst
-                                    .immediate_s()
-                                    .checked_add(next_inst.immediate_s())
-                                    .and_then(|s| s.checked_add(pc))
-                                {
-                                    let fuze_inst = Utype::new_s(
-                                        insts::OP_CUSTOM_LOAD_IMM,
-                                        head_inst.rd(),
-                                        fuze_imm,
-                                    );
-                                    let next_size = instruction_length(next_instruction);
-                                    let fuze_size = head_size + next_size;
-                                    result = set_instruction_length_n(fuze_inst.0, fuze_size);
-                                }
-                            }
-                        }
-                        Ok(result)
-                    }
-                    _ => Ok(head_instruction),
-                }
-            }
-            insts::OP_MULH => {
-                let head_inst = Rtype(head_instruction);
-                let head_size = instruction_length(head_instruction);
-                let next_instruction = match self.decode_raw(memory, pc + head_size as u64) {
-                    Ok(ni) => ni,
-                    Err(_) => return Ok(head_instruction),
-                };
-                let next_opcode = extract_opcode(next_instruction);
-                match next_opcode {
-                    insts::OP_MUL => {
-                        let next_inst = Rtype(next_instruction);
-                        if head_inst.rd() != head_inst.rs1()
-                            && head_inst.rd() != head_inst.rs2()
-                            && head_inst.rs1() == next_inst.rs1()
-                            && head_inst.rs2() == next_inst.rs2()
-                            && head_inst.rd() != next_inst.rd()
-                        {
-                            let next_size = instruction_length(next_instruction);
-                            let fuze_inst = R4type::new(
-                                insts::OP_WIDE_MUL,
-                                head_inst.rd(),
-                                head_inst.rs1(),
-                                head_inst.rs2(),
-                                next_inst.rd(),
-                            );
-                            let fuze_size = head_size + next_size;
-                            Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
-                        } else {
-                            Ok(head_instruction)
-                        }
-                    }
-                    _ => Ok(head_instruction),
-                }
-            }
-            insts::OP_MULHU => {
-                let head_inst = Rtype(head_instruction);
-                let head_size = instruction_length(head_instruction);
-                let next_instruction = match self.decode_raw(memory, pc + head_size as u64) {
-                    Ok(ni) => ni,
-                    Err(_) => return Ok(head_instruction),
-                };
-                let next_opcode = extract_opcode(next_instruction);
-                match next_opcode {
-                    insts::OP_MUL => {
-                        let next_inst = Rtype(next_instruction);
-                        if head_inst.rd() != head_inst.rs1()
-                            && head_inst.rd() != head_inst.rs2()
-                            && head_inst.rs1() == next_inst.rs1()
-                            && head_inst.rs2() == next_inst.rs2()
-                            && head_inst.rd() != next_inst.rd()
-                        {
-                            let next_size = instruction_length(next_instruction);
-                            let fuze_inst = R4type::new(
-                                insts::OP_WIDE_MULU,
-                                head_inst.rd(),
-                                head_inst.rs1(),
-                                head_inst.rs2(),
-                                next_inst.rd(),
-                            );
-                            let fuze_size = head_size + next_size;
-                            Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
-                        } else {
-                            Ok(head_instruction)
-                        }
-                    }
-                    _ => Ok(head_instruction),
-                }
+        if self
+            .fusion_rules
+            .get(head_opcode as usize)
+            .map_or(true, Vec::is_empty)
+        {
+            return Ok(head_instruction);
+        }
+        let cache_key = instruction_cache_key(pc);
+        let cached_instruction = self.fusion_cache[cache_key];
+        if cached_instruction.0 == pc {
+            return Ok(cached_instruction.1);
+        }
+        let instruction = self
+            .fuse_head(memory, pc, head_instruction)
+            .unwrap_or(head_instruction);
+        self.fusion_cache[cache_key] = (pc, instruction);
+        Ok(instruction)
+    }
+
+    // Tries the rules starting with the head instruction in order.
+    fn fuse_head<M: Memory>(
+        &mut self,
+        memory: &mut M,
+        pc: u64,
+        head_instruction: Instruction,
+    ) -> Option<Instruction> {
+        let index = extract_opcode(head_instruction) as usize;
+        // Rules are moved out so they can be borrowed while decoding the
+        // rest of the sequence.
+        let rules = std::mem::take(&mut self.fusion_rules[index]);
+        let mut sequence = Sequence::new(pc, head_instruction);
+        let fused = rules
+            .iter()
+            .find_map(|rule| self.fuse(rule, memory, &mut sequence));
+        self.fusion_rules[index] = rules;
+        fused
+    }
+
+    // Matches the instructions starting at the sequence's pc against a fusion
+    // rule. Failing to decode any instruction of the sequence means the rule
+    // does not match, only a head failing to decode is an error. This is what
+    // the hand-written fusion did, tests/legacy_mop.rs keeps it as reference.
+    fn fuse<M: Memory>(
+        &mut self,
+        rule: &CompiledRule,
+        memory: &mut M,
+        sequence: &mut Sequence,
+    ) -> Option<Instruction> {
+        for check in rule.checks() {
+            if check.index() >= sequence.decoded {
+                self.sequence_instruction(memory, sequence, check.index())?;
             }
-            insts::OP_MULHSU => {
-                let head_inst = Rtype(head_instruction);
-                let head_size = instruction_length(head_instruction);
-                let next_instruction = match self.decode_raw(memory, pc + head_size as u64) {
-                    Ok(ni) => ni,
-                    Err(_) => return Ok(head_instruction),
-                };
-                let next_opcode = extract_opcode(next_instruction);
-                match next_opcode {
-                    insts::OP_MUL => {
-                        let next_inst = Rtype(next_instruction);
-                        if head_inst.rd() != head_inst.rs1()
-                            && head_inst.rd() != head_inst.rs2()
-                            && head_inst.rs1() == next_inst.rs1()
-                            && head_inst.rs2() == next_inst.rs2()
-                            && head_inst.rd() != next_inst.rd()
-                        {
-                            let next_size = instruction_length(next_instruction);
-                            let fuze_inst = R4type::new(
-                                insts::OP_WIDE_MULSU,
-                                head_inst.rd(),
-                                head_inst.rs1(),
-                                head_inst.rs2(),
-                                next_inst.rd(),
-                            );
-                            let fuze_size = head_size + next_size;
-                            Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
-                        } else {
-                            Ok(head_instruction)
-                        }
-                    }
-                    _ => Ok(head_instruction),
-                }
+            if !check.holds(&sequence.instructions) {
+                return None;
             }
-            insts::OP_DIV => {
-                let head_inst = Rtype(head_instruction);
-                let head_size = instruction_length(head_instruction);
-                let next_instruction = match self.decode_raw(memory, pc + head_size as u64) {
-                    Ok(ni) => ni,
-                    Err(_) => return Ok(head_instruction),
-                };
-                let next_opcode = extract_opcode(next_instruction);
-                match next_opcode {
-                    insts::OP_REM => {
-                        let next_inst = Rtype(next_instruction);
-                        if head_inst.rd() != head_inst.rs1()
-                            && head_inst.rd() != head_inst.rs2()
-                            && head_inst.rs1() == next_inst.rs1()
-                            && head_inst.rs2() == next_inst.rs2()
-                            && head_inst.rd() != next_inst.rd()
-                        {
-                            let next_size = instruction_length(next_instruction);
-                            let fuze_inst = R4type::new(
-                                insts::OP_WIDE_DIV,
-                                head_inst.rd(),
-                                head_inst.rs1(),
-                                head_inst.rs2(),
-                                next_inst.rd(),
-                            );
-                            let fuze_size = head_size + next_size;
-                            Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
-                        } else {
-                            Ok(head_instruction)
-                        }
-                    }
-                    _ => Ok(head_instruction),
-                }
+        }
+        self.sequence_instruction(memory, sequence, rule.len() - 1)?;
+        let instructions = &sequence.instructions[..rule.len()];
+        let size: u8 = instructions.iter().map(|i| instruction_length(*i)).sum();
+        if size > MAX_FUSED_LENGTH {
+            return None;
+        }
+        let fused = (rule.rule.fuse)(&rule.captures(sequence.pc, instructions))?;
+        Some(set_instruction_length_n(fused, size))
+    }
+
+    fn sequence_instruction<M: Memory>(
+        &mut self,
+        memory: &mut M,
+        sequence: &mut Sequence,
+        index: usize,
+    ) -> Option<Instruction> {
+        if index < sequence.decoded {
+            return Some(sequence.instructions[index]);
+        }
+        while sequence.decoded <= index {
+            if sequence.failed || sequence.decoded >= MAX_PATTERN_LENGTH {
+                return None;
             }
-            insts::OP_DIVU => {
-                let head_inst = Rtype(head_instruction);
-                let head_size = instruction_length(head_instruction);
-                let next_instruction = match self.decode_raw(memory, pc + head_size as u64) {
-                    Ok(ni) => ni,
-                    Err(_) => return Ok(head_instruction),
-                };
-                let next_opcode = extract_opcode(next_instruction);
-                match next_opcode {
-                    insts::OP_REMU => {
-                        let next_inst = Rtype(next_instruction);
-                        if head_inst.rd() != head_inst.rs1()
-                            && head_inst.rd() != head_inst.rs2()
-                            && head_inst.rs1() == next_inst.rs1()
-                            && head_inst.rs2() == next_inst.rs2()
-                            && head_inst.rd() != next_inst.rd()
-                        {
-                            let next_size = instruction_length(next_instruction);
-                            let fuze_inst = R4type::new(
-                                insts::OP_WIDE_DIVU,
-                                head_inst.rd(),
-                                head_inst.rs1(),
-                                head_inst.rs2(),
-                                next_inst.rd(),
-                            );
-                            let fuze_size = head_size + next_size;
-                            Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
-                        } else {
-                            Ok(head_instruction)
-                        }
-                    }
-                    _ => Ok(head_instruction),
+            match self.decode_raw(memory, sequence.end) {
+                Ok(instruction) => {
+                    sequence.instructions[sequence.decoded] = instruction;
+                    sequence.decoded += 1;
+                    sequence.end += u64::from(instruction_length(instruction));
                 }
+                Err(_) => sequence.failed = true,
             }
-            _ => Ok(head_instruction),
+        }
+        Some(sequence.instructions[index])
+    }
+}
+
+// Instructions starting at a fusion head, decoded on demand and shared by all
+// the rules tried at the same pc.
+struct Sequence {
+    pc: u64,
+    // Address following the last decoded instruction.
+    end: u64,
+    instructions: [Instruction; MAX_PATTERN_LENGTH],
+    decoded: usize,
+    failed: bool,
+}
+
+impl Sequence {
+    fn new(pc: u64, head_instruction: Instruction) -> Self {
+        let mut instructions = [0; MAX_PATTERN_LENGTH];
+        instructions[0] = head_instruction;
+        Sequence {
+            pc,
+            end: pc + u64::from(instruction_length(head_instruction)),
+            instructions,
+            decoded: 1,
+            failed: false,
         }
     }
 }
@@ -871,12 +336,32 @@ impl InstDecoder for Decoder {
 
     fn reset_instructions_cache(&mut self) -> Result<(), Error> {
         self.instructions_cache = vec![(u64::MAX, 0); INSTRUCTION_CACHE_SIZE];
+        self.reset_fusion_cache();
         Ok(())
     }
+
+    fn xlen(&self) -> u8 {
+        self.xlen
+    }
+}
+
+fn instruction_cache_key(pc: u64) -> usize {
+    // according to RISC-V instruction encoding, the lowest bit in PC will always be zero
+    let pc = pc >> 1;
+    // Here we try to balance between local code and remote code. At times,
+    // we can find the code jumping to a remote function(e.g., memcpy or
+    // alloc), then resumes execution at a local location. Previous cache
+    // key only optimizes for local operations, while this new cache key
+    // balances the code between a 8192-byte local region, and certain remote
+    // code region. Notice the value 12 and 8 here are chosen by empirical
+    // evidence.
+    ((pc & 0xFF) | (pc >> 12 << 8)) as usize % INSTRUCTION_CACHE_SIZE
 }
 
 pub fn build_decoder<R: Register>(isa: u8, version: u32) -> Decoder {
     let mut decoder = Decoder::new(isa & ISA_MOP != 0, version);
+    decoder.xlen = R::BITS;
+    decoder.register_number = register_number(isa);
     decoder.add_instruction_factory(rvc::factory::<R>);
     decoder.add_instruction_factory(i::factory::<R>);
     decoder.add_instruction_factory(m::factory::<R>);
@@ -888,3 +373,186 @@ pub fn build_decoder<R: Register>(isa: u8, version: u32) -> Decoder {
     }
     decoder
 }
+
+// Bytes read past pc when decoding an instruction: a fusion rule can look
+// at up to MAX_PATTERN_LENGTH instructions of 4 bytes.
+const DECODING_WINDOW: u64 = MAX_PATTERN_LENGTH as u64 * 4;
+
+/// A program decoded ahead of time for a given ISA and VERSION, fusion
+/// included when ISA_MOP is set. It is immutable, so it can be built once and
+/// shared through Arc by every machine running the same program, see
+/// ProgramDecoder.
+pub struct DecodedProgram {
+    isa: u8,
+    version: u32,
+    xlen: u8,
+    // Program the instructions were decoded from and its layout, see
+    // ProgramMetadata::layout_hash. A decoder is only attached to a machine
+    // loading the same program with the same metadata.
+    program_hash: [u8; 32],
+    layout_hash: [u8; 32],
+    // Executable segments: their address and the instruction decoded at every
+    // 2-byte aligned address, or 0 when the address is left to the runtime
+    // decoder.
+    segments: Vec<(u64, Vec<Instruction>)>,
+}
+
+impl DecodedProgram {
+    pub fn new<R: Register>(
+        program: &Bytes,
+        metadata: &ProgramMetadata,
+        isa: u8,
+        version: u32,
+    ) -> Result<Self, Error> {
+        let mut machine =
+            DefaultCoreMachine::<R, WXorXMemory<SparseMemory<R>>>::new(isa, version, 0);
+        machine.load_binary(program, metadata, false)?;
+        let mut decoder = build_decoder::<R>(isa, version);
+        let mut segments = vec![];
+        for action in &metadata.actions {
+            if action.flags & FLAG_WXORX_BIT != FLAG_EXECUTABLE {
+                continue;
+            }
+            let end = action.addr + action.size;
+            let instructions = (action.addr..end)
+                .step_by(2)
+                .map(|pc| {
+                    // Decoding near the end of the segment depends on the
+                    // memory following it, which differs between machines.
+                    // Failures are reported by the runtime decoder as well.
+                    if pc + DECODING_WINDOW > end {
+                        return 0;
+                    }
+                    decoder.decode(machine.memory_mut(), pc).unwrap_or(0)
+                })
+                .collect();
+            segments.push((action.addr, instructions));
+        }
+        Ok(Self {
+            isa,
+            version,
+            xlen: R::BITS,
+            program_hash: blake2b_256(b"ckb-vm-decoded", program),
+            layout_hash: metadata.layout_hash(),
+            segments,
+        })
+    }
+
+    pub fn isa(&self) -> u8 {
+        self.isa
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Returns the instruction decoded at pc, if any.
+    pub fn instruction(&self, pc: u64) -> Option<Instruction> {
+        self.segments.iter().find_map(|(addr, instructions)| {
+            let offset = pc.checked_sub(*addr)?;
+            if offset & 1 != 0 {
+                return None;
+            }
+            instructions
+                .get((offset >> 1) as usize)
+                .copied()
+                .filter(|instruction| *instruction != 0)
+        })
+    }
+
+    /// Builds a decoder reading from this program, with a fallback decoder
+    /// for the same ISA and VERSION. Program and metadata must be the ones
+    /// this program was decoded from.
+    pub fn decoder<R: Register>(
+        self: &Arc<Self>,
+        program: &Bytes,
+        metadata: &ProgramMetadata,
+    ) -> Result<ProgramDecoder<Decoder>, Error> {
+        self.attach(
+            program,
+            metadata,
+            build_decoder::<R>(self.isa, self.version),
+        )
+    }
+
+    /// Same as decoder, with a custom fallback decoder.
+    pub fn attach<D: InstDecoder>(
+        self: &Arc<Self>,
+        program: &Bytes,
+        metadata: &ProgramMetadata,
+        inner: D,
+    ) -> Result<ProgramDecoder<D>, Error> {
+        if blake2b_256(b"ckb-vm-decoded", program) != self.program_hash {
+            return Err(Error::DecodedProgram("program mismatch".to_string()));
+        }
+        if metadata.layout_hash() != self.layout_hash {
+            return Err(Error::DecodedProgram("metadata mismatch".to_string()));
+        }
+        if inner.xlen() != self.xlen {
+            return Err(Error::DecodedProgram(format!(
+                "decoder xlen {} mismatch, expected {}",
+                inner.xlen(),
+                self.xlen
+            )));
+        }
+        Ok(ProgramDecoder {
+            program: Arc::clone(self),
+            inner,
+            active: true,
+        })
+    }
+
+    fn check_machine(&self, isa: u8, version: u32, xlen: u8) -> Result<(), Error> {
+        if isa != self.isa || version != self.version || xlen != self.xlen {
+            return Err(Error::DecodedProgram(format!(
+                "decoded for {} version {}, machine is {} version {}",
+                isa_to_str(self.isa, self.xlen),
+                self.version,
+                isa_to_str(isa, xlen),
+                version
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Decodes instructions from a shared DecodedProgram, addresses it doesn't
+/// hold go to the inner decoder. Once the machine resets, which means another
+/// program is loaded, only the inner decoder is used. Built with
+/// DecodedProgram::decoder or DecodedProgram::attach.
+pub struct ProgramDecoder<D> {
+    program: Arc<DecodedProgram>,
+    inner: D,
+    active: bool,
+}
+
+impl<D: InstDecoder> ProgramDecoder<D> {
+    pub fn program(&self) -> &Arc<DecodedProgram> {
+        &self.program
+    }
+}
+
+impl<D: InstDecoder> InstDecoder for ProgramDecoder<D> {
+    fn decode<M: Memory>(&mut self, memory: &mut M, pc: u64) -> Result<Instruction, Error> {
+        if self.active {
+            if let Some(instruction) = self.program.instruction(pc) {
+                return Ok(instruction);
+            }
+        }
+        self.inner.decode(memory, pc)
+    }
+
+    fn reset_instructions_cache(&mut self) -> Result<(), Error> {
+        self.active = false;
+        self.inner.reset_instructions_cache()
+    }
+
+    fn xlen(&self) -> u8 {
+        self.inner.xlen()
+    }
+
+    fn check_machine(&self, isa: u8, version: u32, xlen: u8) -> Result<(), Error> {
+        self.program.check_machine(isa, version, xlen)?;
+        self.inner.check_machine(isa, version, xlen)
+    }
+}
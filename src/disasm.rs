@@ -0,0 +1,143 @@
+// Static disassembler walking the executable segments of an ELF program with
+// the same decoder the machine uses, so the listing matches what ckb-vm
+// actually executes, including macro-op fusions.
+use crate::{
+    decoder::{build_decoder, InstDecoder},
+    elf::{parse_elf, ProgramInfo, STT_FUNC},
+    instructions::{instruction_length, tagged::TaggedInstruction, Instruction},
+    memory::{round_page_up, sparse::SparseMemory, Memory, FLAG_EXECUTABLE},
+    Error, Register, ISA_MOP,
+};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// One line of the listing, covering a single raw instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisassembledInstruction {
+    pub pc: u64,
+    // Raw bits, only the lower 16 bits are meaningful for RVC instructions.
+    pub bits: u32,
+    // None when the bits do not form a valid instruction for the ISA, which
+    // happens for data or padding inside executable segments.
+    pub instruction: Option<Instruction>,
+    // The instruction the MOP decoder produces at pc, when it fuses this
+    // instruction with the following ones. Only set when ISA_MOP is enabled.
+    pub fused: Option<Instruction>,
+    // Name of the function starting at pc.
+    pub symbol: Option<String>,
+}
+
+impl DisassembledInstruction {
+    pub fn is_compressed(&self) -> bool {
+        self.bits & 0x3 != 0x3
+    }
+
+    /// Length in bytes of the raw instruction.
+    pub fn length(&self) -> u64 {
+        if self.is_compressed() {
+            2
+        } else {
+            4
+        }
+    }
+}
+
+fn fmt_instruction(f: &mut fmt::Formatter<'_>, instruction: Instruction) -> fmt::Result {
+    match TaggedInstruction::try_from(instruction) {
+        Ok(tagged) => write!(f, "{}", tagged),
+        Err(_) => write!(f, "unknown 0x{:x}", instruction),
+    }
+}
+
+impl fmt::Display for DisassembledInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_compressed() {
+            write!(f, "{:8x}:  {:04x}      ", self.pc, self.bits & 0xffff)?;
+        } else {
+            write!(f, "{:8x}:  {:08x}  ", self.pc, self.bits)?;
+        }
+        match self.instruction {
+            Some(instruction) => fmt_instruction(f, instruction)?,
+            None if self.is_compressed() => write!(f, ".2byte 0x{:x}", self.bits & 0xffff)?,
+            None => write!(f, ".4byte 0x{:x}", self.bits)?,
+        }
+        if let Some(fused) = self.fused {
+            write!(f, "  # fused {} bytes: ", instruction_length(fused))?;
+            fmt_instruction(f, fused)?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassembles all executable segments of program, at their link-time
+/// addresses. The decoder is built from isa and version the same way as
+/// DefaultCoreMachine does.
+pub fn disassemble<R: Register>(
+    program: &Bytes,
+    isa: u8,
+    version: u32,
+) -> Result<Vec<DisassembledInstruction>, Error> {
+    let metadata = parse_elf::<R>(program, version)?;
+    let info = ProgramInfo::parse::<R>(program, version)?;
+    let mut symbols = HashMap::new();
+    for symbol in info.symbols.iter().chain(&info.dynamic_symbols) {
+        if symbol.is_defined() && symbol.kind == STT_FUNC {
+            symbols
+                .entry(symbol.value)
+                .or_insert_with(|| symbol.name.clone());
+        }
+    }
+    let mut decoder = build_decoder::<R>(isa, version);
+    let mut result = vec![];
+    for action in metadata
+        .actions
+        .iter()
+        .filter(|a| a.flags & FLAG_EXECUTABLE != 0)
+    {
+        // Segments are loaded at their real addresses, since some fusions,
+        // like AUIPC + ADDI, fold pc into the fused instruction.
+        let mut memory = SparseMemory::<R>::new_with_memory(round_page_up(
+            action.addr.saturating_add(action.size),
+        ) as usize);
+        let source = program.slice(action.source.start as usize..action.source.end as usize);
+        memory.init_pages(
+            action.addr,
+            action.size,
+            FLAG_EXECUTABLE,
+            Some(source),
+            action.offset_from_addr,
+        )?;
+        decoder.reset_instructions_cache()?;
+        let mut pc = action.addr + action.offset_from_addr;
+        let end = pc + (action.source.end - action.source.start);
+        while pc < end {
+            let mut bits = u32::from(memory.execute_load16(pc)?);
+            if bits & 0x3 == 0x3 {
+                bits |= u32::from(memory.execute_load16(pc + 2)?) << 16;
+            }
+            let instruction = decoder.decode_raw(&mut memory, pc).ok();
+            let mut fused = None;
+            if instruction.is_some() && isa & ISA_MOP != 0 {
+                // Fusion rules look ahead, failing to decode the following
+                // instructions simply means nothing is fused.
+                if let Ok(i) = decoder.decode_mop(&mut memory, pc) {
+                    if Some(i) != instruction {
+                        fused = Some(i);
+                    }
+                }
+            }
+            let line = DisassembledInstruction {
+                pc,
+                bits,
+                instruction,
+                fused,
+                symbol: symbols.get(&pc).cloned(),
+            };
+            pc += line.length();
+            result.push(line);
+        }
+    }
+    Ok(result)
+}
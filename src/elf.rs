@@ -1,15 +1,35 @@
 // This module maps the data structure of different versions of goblin to the
 // same internal structure.
-use crate::machine::VERSION1;
+use crate::bits::roundup;
+use crate::hash::blake2b_256;
+use crate::isa::{isa_to_str, parse_isa_str};
+use crate::machine::{VERSION1, VERSION3};
 use crate::memory::{round_page_down, round_page_up, FLAG_EXECUTABLE, FLAG_FREEZED};
-use crate::{Error, Register};
-use bytes::Bytes;
-use scroll::Pread;
+use crate::{Error, Register, ISA_E, RISCV_PAGESIZE};
+use bytes::{Bytes, BytesMut};
+use scroll::{Pread, LE};
+use std::collections::{BTreeMap, HashMap};
 use std::ops::Range;
 
 // Even for different versions of goblin, their values must be consistent.
-pub use goblin_v023::elf::program_header::{PF_R, PF_W, PF_X, PT_LOAD};
-pub use goblin_v023::elf::section_header::SHF_EXECINSTR;
+pub use goblin_v023::elf::header::{ET_DYN, ET_EXEC};
+pub use goblin_v023::elf::program_header::{
+    PF_R, PF_W, PF_X, PT_DYNAMIC, PT_LOAD, PT_NOTE, PT_PHDR,
+};
+pub use goblin_v023::elf::section_header::{
+    SHF_EXECINSTR, SHT_DYNSYM, SHT_NOBITS, SHT_NOTE, SHT_SYMTAB,
+};
+// Relocation and dynamic section constants are only provided by newer goblin.
+pub use goblin_v040::elf::dynamic::{
+    DT_GNU_HASH, DT_HASH, DT_JMPREL, DT_NULL, DT_PLTRELSZ, DT_RELA, DT_RELAENT, DT_RELASZ,
+    DT_STRTAB, DT_SYMENT, DT_SYMTAB,
+};
+pub use goblin_v040::elf::note::NT_GNU_BUILD_ID;
+pub use goblin_v040::elf::reloc::{
+    R_RISCV_32, R_RISCV_64, R_RISCV_JUMP_SLOT, R_RISCV_NONE, R_RISCV_RELATIVE,
+};
+pub use goblin_v040::elf::section_header::SHN_UNDEF;
+pub use goblin_v040::elf::sym::{STB_GLOBAL, STB_WEAK, STT_FUNC, STT_OBJECT};
 
 /// Converts goblin's ELF flags into RISC-V flags
 pub fn convert_flags(p_flags: u32, allow_freeze_writable: bool, vaddr: u64) -> Result<u8, Error> {
@@ -31,7 +51,67 @@ pub fn convert_flags(p_flags: u32, allow_freeze_writable: bool, vaddr: u64) -> R
     }
 }
 
+/// Same as goblin::elf::Header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub e_ident: [u8; 16],
+    pub e_type: u16,
+    pub e_machine: u16,
+    pub e_version: u32,
+    pub e_entry: u64,
+    pub e_phoff: u64,
+    pub e_shoff: u64,
+    pub e_flags: u32,
+    pub e_ehsize: u16,
+    pub e_phentsize: u16,
+    pub e_phnum: u16,
+    pub e_shentsize: u16,
+    pub e_shnum: u16,
+    pub e_shstrndx: u16,
+}
+
+impl Header {
+    pub fn from_v0(header: &goblin_v023::elf::Header) -> Self {
+        Self {
+            e_ident: header.e_ident,
+            e_type: header.e_type,
+            e_machine: header.e_machine,
+            e_version: header.e_version,
+            e_entry: header.e_entry,
+            e_phoff: header.e_phoff,
+            e_shoff: header.e_shoff,
+            e_flags: header.e_flags,
+            e_ehsize: header.e_ehsize,
+            e_phentsize: header.e_phentsize,
+            e_phnum: header.e_phnum,
+            e_shentsize: header.e_shentsize,
+            e_shnum: header.e_shnum,
+            e_shstrndx: header.e_shstrndx,
+        }
+    }
+
+    pub fn from_v1(header: &goblin_v040::elf::Header) -> Self {
+        Self {
+            e_ident: header.e_ident,
+            e_type: header.e_type,
+            e_machine: header.e_machine,
+            e_version: header.e_version,
+            e_entry: header.e_entry,
+            e_phoff: header.e_phoff,
+            e_shoff: header.e_shoff,
+            e_flags: header.e_flags,
+            e_ehsize: header.e_ehsize,
+            e_phentsize: header.e_phentsize,
+            e_phnum: header.e_phnum,
+            e_shentsize: header.e_shentsize,
+            e_shnum: header.e_shnum,
+            e_shstrndx: header.e_shstrndx,
+        }
+    }
+}
+
 /// Same as goblin::elf::ProgramHeader.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ProgramHeader {
     pub p_type: u32,
     pub p_flags: u32,
@@ -72,6 +152,7 @@ impl ProgramHeader {
 }
 
 /// Same as goblin::elf::SectionHeader.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SectionHeader {
     pub sh_name: usize,
     pub sh_type: u32,
@@ -124,21 +205,108 @@ pub struct LoadingAction {
     pub flags: u8,
     pub source: Range<u64>,
     pub offset_from_addr: u64,
+    // Words to be patched into the segment content before it is loaded, see
+    // parse_elf_with_load_bias.
+    pub relocations: Vec<Relocation>,
 }
 
+/// A machine word that a dynamic relocation writes at addr. Both addr and
+/// value already have the load bias applied.
 #[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Relocation {
+    pub addr: u64,
+    pub value: u64,
+}
+
+/// Addresses of symbols provided by the host, typically exported by other
+/// images sharing the same address space.
+pub type SymbolTable = HashMap<String, u64>;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct ProgramMetadata {
     pub actions: Vec<LoadingAction>,
     pub entry: u64,
+    // Address of the program header table in VM memory, or 0 if the table is
+    // not part of any loaded segment. Together with phent and phnum, this is
+    // only used to build the auxiliary vector, see auxiliary_vector below.
+    pub phdr: u64,
+    pub phent: u64,
+    pub phnum: u64,
 }
 
-pub fn parse_elf<R: Register>(program: &Bytes, version: u32) -> Result<ProgramMetadata, Error> {
+impl ProgramMetadata {
+    /// Hash of everything that decides what ends up in executable memory: the
+    /// loading actions, relocations included, and the entry point. Program
+    /// header fields only feed the auxiliary vector and are left out.
+    pub fn layout_hash(&self) -> [u8; 32] {
+        let mut data = self.entry.to_le_bytes().to_vec();
+        for action in &self.actions {
+            data.extend_from_slice(&action.addr.to_le_bytes());
+            data.extend_from_slice(&action.size.to_le_bytes());
+            data.push(action.flags);
+            data.extend_from_slice(&action.source.start.to_le_bytes());
+            data.extend_from_slice(&action.source.end.to_le_bytes());
+            data.extend_from_slice(&action.offset_from_addr.to_le_bytes());
+            data.extend_from_slice(&(action.relocations.len() as u64).to_le_bytes());
+            for relocation in &action.relocations {
+                data.extend_from_slice(&relocation.addr.to_le_bytes());
+                data.extend_from_slice(&relocation.value.to_le_bytes());
+            }
+        }
+        blake2b_256(b"ckb-vm-layout", &data)
+    }
+}
+
+// Auxiliary vector types, the values are the same as the ones used in Linux:
+// https://github.com/torvalds/linux/blob/master/include/uapi/linux/auxvec.h
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_ENTRY: u64 = 9;
+pub const AT_RANDOM: u64 = 25;
+
+/// Builds the auxiliary vector entries that can be derived from the program
+/// itself. AT_RANDOM and the terminating AT_NULL depend on the stack content,
+/// they are appended by SupportMachine::initialize_stack_with_env.
+pub fn auxiliary_vector(metadata: &ProgramMetadata) -> Vec<(u64, u64)> {
+    let mut auxv = vec![];
+    if metadata.phdr != 0 {
+        auxv.push((AT_PHDR, metadata.phdr));
+        auxv.push((AT_PHENT, metadata.phent));
+        auxv.push((AT_PHNUM, metadata.phnum));
+    }
+    auxv.push((AT_PAGESZ, RISCV_PAGESIZE as u64));
+    auxv.push((AT_ENTRY, metadata.entry));
+    auxv
+}
+
+// Locates the program header table in VM memory. PT_PHDR is preferred when
+// present, otherwise we look for the loadable segment containing the table.
+fn program_header_address(program_headers: &[ProgramHeader], phoff: u64) -> u64 {
+    if let Some(header) = program_headers.iter().find(|h| h.p_type == PT_PHDR) {
+        return header.p_vaddr;
+    }
+    program_headers
+        .iter()
+        .find(|h| h.p_type == PT_LOAD && h.p_offset <= phoff && phoff - h.p_offset < h.p_filesz)
+        .map(|h| h.p_vaddr.wrapping_add(phoff - h.p_offset))
+        .unwrap_or(0)
+}
+
+fn parse_headers<R: Register>(
+    program: &Bytes,
+    version: u32,
+) -> Result<(Header, Vec<ProgramHeader>), Error> {
     // We did not use Elf::parse here to avoid triggering potential bugs in goblin.
     // * https://github.com/nervosnetwork/ckb-vm/issues/143
-    let (entry, program_headers): (u64, Vec<ProgramHeader>) = if version < VERSION1 {
+    let headers = if version < VERSION1 {
         use goblin_v023::container::Ctx;
-        use goblin_v023::elf::{program_header::ProgramHeader as GoblinProgramHeader, Header};
-        let header = program.pread::<Header>(0)?;
+        use goblin_v023::elf::{
+            program_header::ProgramHeader as GoblinProgramHeader, Header as GoblinHeader,
+        };
+        let header = program.pread::<GoblinHeader>(0)?;
         let container = header.container().map_err(|_e| Error::ElfBits)?;
         let endianness = header.endianness().map_err(|_e| Error::ElfBits)?;
         if R::BITS != if container.is_big() { 64 } else { 32 } {
@@ -154,11 +322,13 @@ pub fn parse_elf<R: Register>(program: &Bytes, version: u32) -> Result<ProgramMe
         .iter()
         .map(ProgramHeader::from_v0)
         .collect();
-        (header.e_entry, program_headers)
+        (Header::from_v0(&header), program_headers)
     } else {
         use goblin_v040::container::Ctx;
-        use goblin_v040::elf::{program_header::ProgramHeader as GoblinProgramHeader, Header};
-        let header = program.pread::<Header>(0)?;
+        use goblin_v040::elf::{
+            program_header::ProgramHeader as GoblinProgramHeader, Header as GoblinHeader,
+        };
+        let header = program.pread::<GoblinHeader>(0)?;
         let container = header.container().map_err(|_e| Error::ElfBits)?;
         let endianness = header.endianness().map_err(|_e| Error::ElfBits)?;
         if R::BITS != if container.is_big() { 64 } else { 32 } {
@@ -174,37 +344,850 @@ pub fn parse_elf<R: Register>(program: &Bytes, version: u32) -> Result<ProgramMe
         .iter()
         .map(ProgramHeader::from_v1)
         .collect();
-        (header.e_entry, program_headers)
+        (Header::from_v1(&header), program_headers)
     };
+    Ok(headers)
+}
+
+pub fn parse_elf<R: Register>(program: &Bytes, version: u32) -> Result<ProgramMetadata, Error> {
+    let (header, program_headers) = parse_headers::<R>(program, version)?;
+    build_metadata(program, version, &header, &program_headers, 0)
+}
+
+/// Parses an ELF the same way as parse_elf, but loads it load_bias bytes
+/// above its link-time addresses, which is only possible for position
+/// independent (ET_DYN) images. Dynamic relocations are resolved here and
+/// attached to the resulting loading actions: R_RISCV_RELATIVE only needs the
+/// load bias, while R_RISCV_64 (R_RISCV_32 for 32-bit programs) and
+/// R_RISCV_JUMP_SLOT refer to symbols, which are looked up in the image's own
+/// dynamic symbol table first, then in symbols.
+pub fn parse_elf_with_load_bias<R: Register>(
+    program: &Bytes,
+    version: u32,
+    load_bias: u64,
+    symbols: &SymbolTable,
+) -> Result<ProgramMetadata, Error> {
+    if version < VERSION3 {
+        return Err(Error::InvalidVersion);
+    }
+    let (header, program_headers) = parse_headers::<R>(program, version)?;
+    if load_bias % RISCV_PAGESIZE as u64 != 0 || (load_bias != 0 && header.e_type != ET_DYN) {
+        return Err(Error::ElfParseError(format!(
+            "invalid load bias 0x{:x}",
+            load_bias
+        )));
+    }
+    let mut metadata = build_metadata(program, version, &header, &program_headers, load_bias)?;
+    let word_size = u64::from(R::BITS / 8);
+    for relocation in parse_relocations::<R>(program, &program_headers, load_bias, symbols)? {
+        let action = metadata
+            .actions
+            .iter_mut()
+            .find(|action| {
+                let start = action.addr.wrapping_add(action.offset_from_addr);
+                let end = start.saturating_add(action.source.end - action.source.start);
+                relocation.addr >= start && relocation.addr.saturating_add(word_size) <= end
+            })
+            .ok_or(Error::ElfSegmentAddrOrSizeError(relocation.addr))?;
+        action.relocations.push(relocation);
+    }
+    Ok(metadata)
+}
+
+fn build_metadata(
+    program: &Bytes,
+    version: u32,
+    header: &Header,
+    program_headers: &[ProgramHeader],
+    load_bias: u64,
+) -> Result<ProgramMetadata, Error> {
+    let phdr = match program_header_address(program_headers, header.e_phoff) {
+        0 => 0,
+        phdr => phdr.wrapping_add(load_bias),
+    };
+    let phnum = program_headers.len() as u64;
     let mut bytes: u64 = 0;
     let mut actions = vec![];
     for program_header in program_headers {
         if program_header.p_type == PT_LOAD {
-            let aligned_start = round_page_down(program_header.p_vaddr);
-            let padding_start = program_header.p_vaddr.wrapping_sub(aligned_start);
+            let vaddr = program_header.p_vaddr.wrapping_add(load_bias);
+            let aligned_start = round_page_down(vaddr);
+            let padding_start = vaddr.wrapping_sub(aligned_start);
             let size = round_page_up(program_header.p_memsz.wrapping_add(padding_start));
             let slice_start = program_header.p_offset;
             let slice_end = program_header
                 .p_offset
                 .wrapping_add(program_header.p_filesz);
             if slice_start > slice_end || slice_end > program.len() as u64 {
-                return Err(Error::ElfSegmentAddrOrSizeError(program_header.p_vaddr));
+                return Err(Error::ElfSegmentAddrOrSizeError(vaddr));
             }
             actions.push(LoadingAction {
                 addr: aligned_start,
                 size,
-                flags: convert_flags(
-                    program_header.p_flags,
-                    version < VERSION1,
-                    program_header.p_vaddr,
-                )?,
+                flags: convert_flags(program_header.p_flags, version < VERSION1, vaddr)?,
                 source: slice_start..slice_end,
                 offset_from_addr: padding_start,
+                relocations: vec![],
             });
             bytes = bytes.checked_add(slice_end - slice_start).ok_or_else(|| {
                 Error::Unexpected(String::from("The bytes count overflowed on loading elf"))
             })?;
         }
     }
-    Ok(ProgramMetadata { actions, entry })
+    Ok(ProgramMetadata {
+        actions,
+        entry: header.e_entry.wrapping_add(load_bias),
+        phdr,
+        phent: u64::from(header.e_phentsize),
+        phnum,
+    })
+}
+
+// Values collected from the PT_DYNAMIC segment that are needed to process
+// relocations. All addresses are link-time virtual addresses.
+#[derive(Default)]
+struct DynamicInfo {
+    hash: u64,
+    gnu_hash: u64,
+    rela: u64,
+    rela_size: u64,
+    rela_ent: u64,
+    jmprel: u64,
+    jmprel_size: u64,
+    symtab: u64,
+    strtab: u64,
+    syment: u64,
+}
+
+fn read_word<R: Register>(program: &Bytes, offset: u64) -> Result<u64, Error> {
+    let offset = offset as usize;
+    let value = if R::BITS == 64 {
+        program.pread_with::<u64>(offset, LE)
+    } else {
+        program.pread_with::<u32>(offset, LE).map(u64::from)
+    };
+    value.map_err(|e| Error::ElfParseError(e.to_string()))
+}
+
+// File offset of the index-th entry of size bytes in a table starting at
+// base. All of them come from the file, hence are checked for overflow.
+fn entry_offset(base: u64, index: u64, size: u64) -> Result<u64, Error> {
+    index
+        .checked_mul(size)
+        .and_then(|offset| offset.checked_add(base))
+        .ok_or_else(|| Error::ElfParseError(format!("invalid file offset {:#x}", base)))
+}
+
+fn vaddr_to_offset(program_headers: &[ProgramHeader], vaddr: u64) -> Result<u64, Error> {
+    let header = program_headers
+        .iter()
+        .find(|h| h.p_type == PT_LOAD && h.p_vaddr <= vaddr && vaddr - h.p_vaddr < h.p_filesz)
+        .ok_or(Error::ElfSegmentAddrOrSizeError(vaddr))?;
+    entry_offset(header.p_offset, vaddr - header.p_vaddr, 1)
+}
+
+fn parse_dynamic<R: Register>(
+    program: &Bytes,
+    dynamic: &ProgramHeader,
+) -> Result<DynamicInfo, Error> {
+    let word_size = u64::from(R::BITS / 8);
+    let mut info = DynamicInfo::default();
+    let mut offset = dynamic.p_offset;
+    while entry_offset(offset, 2, word_size)? <= dynamic.p_offset.saturating_add(dynamic.p_filesz) {
+        let tag = read_word::<R>(program, offset)?;
+        let value = read_word::<R>(program, entry_offset(offset, 1, word_size)?)?;
+        match tag {
+            DT_NULL => break,
+            DT_HASH => info.hash = value,
+            DT_GNU_HASH => info.gnu_hash = value,
+            DT_RELA => info.rela = value,
+            DT_RELASZ => info.rela_size = value,
+            DT_RELAENT => info.rela_ent = value,
+            DT_JMPREL => info.jmprel = value,
+            DT_PLTRELSZ => info.jmprel_size = value,
+            DT_SYMTAB => info.symtab = value,
+            DT_STRTAB => info.strtab = value,
+            DT_SYMENT => info.syment = value,
+            _ => (),
+        }
+        offset += 2 * word_size;
+    }
+    Ok(info)
+}
+
+/// An entry of .symtab or .dynsym, value does not include any load bias.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u64,
+    pub size: u64,
+    // STB_* binding and STT_* type, extracted from st_info.
+    pub bind: u8,
+    pub kind: u8,
+    pub section_index: u16,
+}
+
+impl Symbol {
+    pub fn is_defined(&self) -> bool {
+        u32::from(self.section_index) != SHN_UNDEF
+    }
+}
+
+// Size of a symbol table entry.
+fn symbol_entry_size<R: Register>() -> u64 {
+    if R::BITS == 64 {
+        24
+    } else {
+        16
+    }
+}
+
+fn read_string(program: &Bytes, offset: u64) -> Result<String, Error> {
+    program
+        .get(offset as usize..)
+        .and_then(|s| s.split(|c| *c == 0).next())
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+        .ok_or_else(|| Error::ElfParseError(format!("invalid string offset {}", offset)))
+}
+
+// Reads the symbol table entry at offset, names are resolved against the
+// string table at strtab_offset.
+fn read_symbol<R: Register>(
+    program: &Bytes,
+    offset: u64,
+    strtab_offset: u64,
+) -> Result<Symbol, Error> {
+    let (info_offset, shndx_offset, value_offset, size_offset) = if R::BITS == 64 {
+        (4, 6, 8, 16)
+    } else {
+        (12, 14, 4, 8)
+    };
+    let offset = offset as usize;
+    let read_err = |e: scroll::Error| Error::ElfParseError(e.to_string());
+    let st_name = program.pread_with::<u32>(offset, LE).map_err(read_err)?;
+    let st_info = program
+        .pread_with::<u8>(offset + info_offset, LE)
+        .map_err(read_err)?;
+    let st_shndx = program
+        .pread_with::<u16>(offset + shndx_offset, LE)
+        .map_err(read_err)?;
+    let st_value = read_word::<R>(program, (offset + value_offset) as u64)?;
+    let st_size = read_word::<R>(program, (offset + size_offset) as u64)?;
+    Ok(Symbol {
+        name: read_string(program, strtab_offset.saturating_add(u64::from(st_name)))?,
+        value: st_value,
+        size: st_size,
+        bind: st_info >> 4,
+        kind: st_info & 0xf,
+        section_index: st_shndx,
+    })
+}
+
+fn read_dynamic_symbol<R: Register>(
+    program: &Bytes,
+    program_headers: &[ProgramHeader],
+    info: &DynamicInfo,
+    index: u64,
+) -> Result<Symbol, Error> {
+    let syment = if info.syment != 0 {
+        info.syment
+    } else {
+        symbol_entry_size::<R>()
+    };
+    let offset = vaddr_to_offset(program_headers, info.symtab)?
+        .checked_add(index.wrapping_mul(syment))
+        .ok_or_else(|| Error::ElfParseError(format!("invalid symbol index {}", index)))?;
+    read_symbol::<R>(
+        program,
+        offset,
+        vaddr_to_offset(program_headers, info.strtab)?,
+    )
+}
+
+fn resolve_symbol<R: Register>(
+    program: &Bytes,
+    program_headers: &[ProgramHeader],
+    info: &DynamicInfo,
+    index: u64,
+    load_bias: u64,
+    symbols: &SymbolTable,
+) -> Result<u64, Error> {
+    let symbol = read_dynamic_symbol::<R>(program, program_headers, info, index)?;
+    if symbol.is_defined() {
+        return Ok(symbol.value.wrapping_add(load_bias));
+    }
+    match symbols.get(&symbol.name) {
+        Some(value) => Ok(*value),
+        None if symbol.bind == STB_WEAK => Ok(0),
+        None => Err(Error::ElfUndefinedSymbol(symbol.name)),
+    }
+}
+
+fn parse_section_headers<R: Register>(
+    program: &Bytes,
+    version: u32,
+    header: &Header,
+) -> Result<Vec<SectionHeader>, Error> {
+    if header.e_shnum == 0 {
+        return Ok(vec![]);
+    }
+    let section_headers = if version < VERSION1 {
+        use goblin_v023::container::{Container, Ctx, Endian};
+        use goblin_v023::elf::section_header::SectionHeader as GoblinSectionHeader;
+        let container = if R::BITS == 64 {
+            Container::Big
+        } else {
+            Container::Little
+        };
+        GoblinSectionHeader::parse(
+            program,
+            header.e_shoff as usize,
+            header.e_shnum as usize,
+            Ctx::new(container, Endian::Little),
+        )?
+        .iter()
+        .map(SectionHeader::from_v0)
+        .collect()
+    } else {
+        use goblin_v040::container::{Container, Ctx, Endian};
+        use goblin_v040::elf::section_header::SectionHeader as GoblinSectionHeader;
+        let container = if R::BITS == 64 {
+            Container::Big
+        } else {
+            Container::Little
+        };
+        GoblinSectionHeader::parse(
+            program,
+            header.e_shoff as usize,
+            header.e_shnum as usize,
+            Ctx::new(container, Endian::Little),
+        )?
+        .iter()
+        .map(SectionHeader::from_v1)
+        .collect()
+    };
+    Ok(section_headers)
+}
+
+// Number of entries in .dynsym. The dynamic section does not record it, we
+// take it from DT_HASH or DT_GNU_HASH, or the section headers when neither
+// is present.
+fn dynamic_symbol_count<R: Register>(
+    program: &Bytes,
+    version: u32,
+    header: &Header,
+    program_headers: &[ProgramHeader],
+    info: &DynamicInfo,
+) -> Result<u64, Error> {
+    if info.hash != 0 {
+        let offset = vaddr_to_offset(program_headers, info.hash)? + 4;
+        return read_u32(program, offset);
+    }
+    if info.gnu_hash != 0 {
+        return gnu_hash_symbol_count::<R>(program, program_headers, info.gnu_hash);
+    }
+    parse_section_headers::<R>(program, version, header)?
+        .iter()
+        .find(|h| h.sh_type == SHT_DYNSYM && h.sh_entsize != 0)
+        .map(|h| h.sh_size / h.sh_entsize)
+        .ok_or_else(|| {
+            Error::ElfParseError("no DT_HASH, DT_GNU_HASH or .dynsym to size symbols".to_string())
+        })
+}
+
+fn read_u32(program: &Bytes, offset: u64) -> Result<u64, Error> {
+    program
+        .pread_with::<u32>(offset as usize, LE)
+        .map(u64::from)
+        .map_err(|e| Error::ElfParseError(e.to_string()))
+}
+
+// DT_GNU_HASH only covers the symbols from symoffset on, sorted by bucket.
+// The last symbol ends the chain of the last non-empty bucket, its hash has
+// the lowest bit set.
+fn gnu_hash_symbol_count<R: Register>(
+    program: &Bytes,
+    program_headers: &[ProgramHeader],
+    addr: u64,
+) -> Result<u64, Error> {
+    let offset = vaddr_to_offset(program_headers, addr)?;
+    let nbuckets = read_u32(program, offset)?;
+    let symoffset = read_u32(program, entry_offset(offset, 1, 4)?)?;
+    let bloom_size = read_u32(program, entry_offset(offset, 2, 4)?)?;
+    let buckets = entry_offset(
+        entry_offset(offset, 1, 16)?,
+        bloom_size,
+        u64::from(R::BITS / 8),
+    )?;
+    let mut last = 0;
+    for i in 0..nbuckets {
+        last = last.max(read_u32(program, entry_offset(buckets, i, 4)?)?);
+    }
+    if last == 0 {
+        return Ok(symoffset);
+    }
+    let chain = entry_offset(buckets, nbuckets, 4)?;
+    let mut index = last
+        .checked_sub(symoffset)
+        .ok_or_else(|| Error::ElfParseError(format!("invalid GNU hash bucket {}", last)))?;
+    while read_u32(program, entry_offset(chain, index, 4)?)? & 1 == 0 {
+        index += 1;
+    }
+    Ok(symoffset + index + 1)
+}
+
+// Reads all the entries of .dynsym but the reserved first one, located via
+// the dynamic section.
+fn parse_dynamic_symbols<R: Register>(
+    program: &Bytes,
+    version: u32,
+    header: &Header,
+    program_headers: &[ProgramHeader],
+) -> Result<Vec<Symbol>, Error> {
+    let dynamic = match program_headers.iter().find(|h| h.p_type == PT_DYNAMIC) {
+        Some(dynamic) => dynamic,
+        None => return Ok(vec![]),
+    };
+    let info = parse_dynamic::<R>(program, dynamic)?;
+    if info.symtab == 0 {
+        return Ok(vec![]);
+    }
+    let count = dynamic_symbol_count::<R>(program, version, header, program_headers, &info)?;
+    (1..count)
+        .map(|index| read_dynamic_symbol::<R>(program, program_headers, &info, index))
+        .collect()
+}
+
+/// Lists the global and weak symbols an ELF image defines in its dynamic
+/// symbol table, with load_bias applied to their values. These are the
+/// symbols other images can import through the SymbolTable passed to
+/// parse_elf_with_load_bias.
+pub fn parse_exported_symbols<R: Register>(
+    program: &Bytes,
+    version: u32,
+    load_bias: u64,
+) -> Result<SymbolTable, Error> {
+    let (header, program_headers) = parse_headers::<R>(program, version)?;
+    Ok(
+        parse_dynamic_symbols::<R>(program, version, &header, &program_headers)?
+            .into_iter()
+            .filter(|symbol| {
+                symbol.is_defined()
+                    && !symbol.name.is_empty()
+                    && (symbol.bind == STB_GLOBAL || symbol.bind == STB_WEAK)
+            })
+            .map(|symbol| (symbol.name, symbol.value.wrapping_add(load_bias)))
+            .collect(),
+    )
+}
+
+fn parse_relocations<R: Register>(
+    program: &Bytes,
+    program_headers: &[ProgramHeader],
+    load_bias: u64,
+    symbols: &SymbolTable,
+) -> Result<Vec<Relocation>, Error> {
+    let dynamic = match program_headers.iter().find(|h| h.p_type == PT_DYNAMIC) {
+        Some(dynamic) => dynamic,
+        None => return Ok(vec![]),
+    };
+    let info = parse_dynamic::<R>(program, dynamic)?;
+    let word_size = u64::from(R::BITS / 8);
+    let rela_ent = if info.rela_ent != 0 {
+        info.rela_ent
+    } else {
+        3 * word_size
+    };
+    let mut relocations = vec![];
+    for (table, table_size) in [(info.rela, info.rela_size), (info.jmprel, info.jmprel_size)] {
+        if table_size == 0 {
+            continue;
+        }
+        let table_offset = vaddr_to_offset(program_headers, table)?;
+        for i in 0..table_size / rela_ent {
+            let offset = entry_offset(table_offset, i, rela_ent)?;
+            let r_offset = read_word::<R>(program, offset)?;
+            let r_info = read_word::<R>(program, entry_offset(offset, 1, word_size)?)?;
+            let r_addend = read_word::<R>(program, entry_offset(offset, 2, word_size)?)?;
+            let (r_sym, r_type) = if R::BITS == 64 {
+                (r_info >> 32, (r_info & 0xFFFF_FFFF) as u32)
+            } else {
+                (r_info >> 8, (r_info & 0xFF) as u32)
+            };
+            let symbol =
+                || resolve_symbol::<R>(program, program_headers, &info, r_sym, load_bias, symbols);
+            let value = match r_type {
+                R_RISCV_NONE => continue,
+                R_RISCV_RELATIVE => load_bias.wrapping_add(r_addend),
+                R_RISCV_64 if R::BITS == 64 => symbol()?.wrapping_add(r_addend),
+                R_RISCV_32 if R::BITS == 32 => symbol()?.wrapping_add(r_addend),
+                R_RISCV_JUMP_SLOT => symbol()?,
+                _ => return Err(Error::ElfUnsupportedRelocation(r_type)),
+            };
+            relocations.push(Relocation {
+                addr: r_offset.wrapping_add(load_bias),
+                value: if R::BITS == 64 {
+                    value
+                } else {
+                    value & 0xFFFF_FFFF
+                },
+            });
+        }
+    }
+    Ok(relocations)
+}
+
+/// Writes relocated words into the content of a segment that will be loaded
+/// at start.
+pub fn apply_relocations<R: Register>(
+    data: Bytes,
+    start: u64,
+    relocations: &[Relocation],
+) -> Result<Bytes, Error> {
+    if relocations.is_empty() {
+        return Ok(data);
+    }
+    let word_size = (R::BITS / 8) as usize;
+    let mut data = BytesMut::from(&data[..]);
+    for relocation in relocations {
+        let offset = relocation.addr.wrapping_sub(start) as usize;
+        if relocation.addr < start || offset.saturating_add(word_size) > data.len() {
+            return Err(Error::ElfSegmentAddrOrSizeError(relocation.addr));
+        }
+        data[offset..offset + word_size]
+            .copy_from_slice(&relocation.value.to_le_bytes()[..word_size]);
+    }
+    Ok(data.freeze())
+}
+
+/// A section header together with its name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub header: SectionHeader,
+}
+
+/// An entry of a note section or segment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Note {
+    pub name: String,
+    pub n_type: u32,
+    pub desc: Bytes,
+}
+
+// Name and type of the attributes section, see
+// https://github.com/riscv-non-isa/riscv-elf-psabi-doc/blob/master/riscv-elf.adoc
+pub const RISCV_ATTRIBUTES_SECTION: &str = ".riscv.attributes";
+pub const SHT_RISCV_ATTRIBUTES: u32 = 0x7000_0003;
+pub const TAG_RISCV_STACK_ALIGN: u64 = 4;
+pub const TAG_RISCV_ARCH: u64 = 5;
+pub const TAG_RISCV_UNALIGNED_ACCESS: u64 = 6;
+
+/// File level attributes of the "riscv" vendor in .riscv.attributes. Tags
+/// with an even number hold integers, odd ones hold strings.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RiscvAttributes {
+    pub integers: BTreeMap<u64, u64>,
+    pub strings: BTreeMap<u64, String>,
+}
+
+fn read_uleb128(data: &[u8], offset: &mut usize) -> Option<u64> {
+    let mut result: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*offset)?;
+        *offset += 1;
+        if shift < 64 {
+            result |= u64::from(byte & 0x7f) << shift;
+        }
+        if byte & 0x80 == 0 {
+            return Some(result);
+        }
+        shift += 7;
+    }
+}
+
+impl RiscvAttributes {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        Self::parse_inner(data)
+            .ok_or_else(|| Error::ElfParseError(String::from("invalid .riscv.attributes")))
+    }
+
+    fn parse_inner(data: &[u8]) -> Option<Self> {
+        let mut attributes = Self::default();
+        if data.first() != Some(&b'A') {
+            return None;
+        }
+        let mut offset = 1;
+        while offset < data.len() {
+            let length = data.pread_with::<u32>(offset, LE).ok()? as usize;
+            let end = offset.checked_add(length).filter(|e| *e <= data.len())?;
+            let vendor_start = offset + 4;
+            let vendor_length = data.get(vendor_start..end)?.iter().position(|c| *c == 0)?;
+            let mut sub_offset = vendor_start + vendor_length + 1;
+            if &data[vendor_start..vendor_start + vendor_length] == b"riscv" {
+                while sub_offset < end {
+                    let tag = data[sub_offset];
+                    let sub_length = data.pread_with::<u32>(sub_offset + 1, LE).ok()? as usize;
+                    let sub_end = sub_offset.checked_add(sub_length).filter(|e| *e <= end)?;
+                    // Only file level (Tag_File) attributes are used by RISC-V toolchains.
+                    if tag == 1 {
+                        let mut attr_offset = sub_offset + 5;
+                        while attr_offset < sub_end {
+                            let attr = read_uleb128(&data[..sub_end], &mut attr_offset)?;
+                            if attr % 2 == 0 {
+                                let value = read_uleb128(&data[..sub_end], &mut attr_offset)?;
+                                attributes.integers.insert(attr, value);
+                            } else {
+                                let value =
+                                    data.get(attr_offset..sub_end)?.split(|c| *c == 0).next()?;
+                                attr_offset += value.len() + 1;
+                                attributes
+                                    .strings
+                                    .insert(attr, String::from_utf8_lossy(value).into_owned());
+                            }
+                        }
+                    }
+                    sub_offset = sub_end.max(sub_offset + 1);
+                }
+            }
+            offset = end.max(offset + 1);
+        }
+        Some(attributes)
+    }
+
+    /// The architecture string, such as rv64i2p1_m2p0_a2p1_c2p0.
+    pub fn arch(&self) -> Option<&str> {
+        self.strings.get(&TAG_RISCV_ARCH).map(|s| s.as_str())
+    }
+
+    pub fn stack_align(&self) -> Option<u64> {
+        self.integers.get(&TAG_RISCV_STACK_ALIGN).copied()
+    }
+}
+
+fn parse_notes(data: &Bytes, align: u64) -> Result<Vec<Note>, Error> {
+    let align = if align == 8 { 8 } else { 4 };
+    let read_err = |e: scroll::Error| Error::ElfParseError(e.to_string());
+    let mut notes = vec![];
+    let mut offset: u64 = 0;
+    while offset + 12 <= data.len() as u64 {
+        let namesz = u64::from(
+            data.pread_with::<u32>(offset as usize, LE)
+                .map_err(read_err)?,
+        );
+        let descsz = u64::from(
+            data.pread_with::<u32>(offset as usize + 4, LE)
+                .map_err(read_err)?,
+        );
+        let n_type = data
+            .pread_with::<u32>(offset as usize + 8, LE)
+            .map_err(read_err)?;
+        let name_start = offset + 12;
+        let desc_start = roundup(name_start + namesz, align);
+        let desc_end = desc_start + descsz;
+        if desc_end > data.len() as u64 {
+            return Err(Error::ElfParseError(String::from("invalid note")));
+        }
+        let name = data.slice(name_start as usize..(name_start + namesz) as usize);
+        notes.push(Note {
+            name: String::from_utf8_lossy(name.split(|c| *c == 0).next().unwrap_or(&[]))
+                .into_owned(),
+            n_type,
+            desc: data.slice(desc_start as usize..desc_end as usize),
+        });
+        offset = roundup(desc_end, align);
+    }
+    Ok(notes)
+}
+
+/// Static information about an ELF, parsed without loading it into a
+/// machine. This is meant for tooling such as profilers and debuggers.
+#[derive(Clone, Debug)]
+pub struct ProgramInfo {
+    program: Bytes,
+    pub header: Header,
+    pub program_headers: Vec<ProgramHeader>,
+    pub sections: Vec<Section>,
+    // Entries of .symtab and .dynsym, the reserved null symbols are skipped.
+    pub symbols: Vec<Symbol>,
+    pub dynamic_symbols: Vec<Symbol>,
+    pub notes: Vec<Note>,
+    pub riscv_attributes: Option<RiscvAttributes>,
+}
+
+impl ProgramInfo {
+    pub fn parse<R: Register>(program: &Bytes, version: u32) -> Result<Self, Error> {
+        let (header, program_headers) = parse_headers::<R>(program, version)?;
+        let section_headers = parse_section_headers::<R>(program, version, &header)?;
+        let shstrtab_offset = section_headers
+            .get(header.e_shstrndx as usize)
+            .map(|h| h.sh_offset);
+        let sections = section_headers
+            .into_iter()
+            .map(|header| {
+                let name = match shstrtab_offset {
+                    Some(offset) => {
+                        read_string(program, offset.saturating_add(header.sh_name as u64))?
+                    }
+                    None => String::new(),
+                };
+                Ok(Section { name, header })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut info = Self {
+            program: program.clone(),
+            dynamic_symbols: parse_dynamic_symbols::<R>(
+                program,
+                version,
+                &header,
+                &program_headers,
+            )?,
+            header,
+            program_headers,
+            sections,
+            symbols: vec![],
+            notes: vec![],
+            riscv_attributes: None,
+        };
+        for section in &info.sections {
+            match section.header.sh_type {
+                SHT_SYMTAB => {
+                    let entsize = match section.header.sh_entsize {
+                        0 => symbol_entry_size::<R>(),
+                        entsize => entsize,
+                    };
+                    let strtab_offset = info
+                        .sections
+                        .get(section.header.sh_link as usize)
+                        .map(|s| s.header.sh_offset)
+                        .unwrap_or(0);
+                    for index in 1..section.header.sh_size / entsize {
+                        info.symbols.push(read_symbol::<R>(
+                            program,
+                            section.header.sh_offset + index * entsize,
+                            strtab_offset,
+                        )?);
+                    }
+                }
+                SHT_NOTE => {
+                    let data = info.section_data(section)?;
+                    info.notes
+                        .extend(parse_notes(&data, section.header.sh_addralign)?);
+                }
+                SHT_RISCV_ATTRIBUTES => {
+                    info.riscv_attributes =
+                        Some(RiscvAttributes::parse(&info.section_data(section)?)?);
+                }
+                _ => (),
+            }
+        }
+        // Stripped binaries might only keep notes in PT_NOTE segments.
+        if !info.sections.iter().any(|s| s.header.sh_type == SHT_NOTE) {
+            for program_header in info.program_headers.iter().filter(|h| h.p_type == PT_NOTE) {
+                let data = info.slice(program_header.p_offset, program_header.p_filesz)?;
+                info.notes
+                    .extend(parse_notes(&data, program_header.p_align)?);
+            }
+        }
+        Ok(info)
+    }
+
+    fn slice(&self, offset: u64, size: u64) -> Result<Bytes, Error> {
+        let end = offset
+            .checked_add(size)
+            .filter(|end| *end <= self.program.len() as u64)
+            .ok_or(Error::ElfSegmentAddrOrSizeError(offset))?;
+        Ok(self.program.slice(offset as usize..end as usize))
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// Content of a section in the ELF file, empty for SHT_NOBITS sections.
+    pub fn section_data(&self, section: &Section) -> Result<Bytes, Error> {
+        if section.header.sh_type == SHT_NOBITS {
+            return Ok(Bytes::new());
+        }
+        self.slice(section.header.sh_offset, section.header.sh_size)
+    }
+
+    /// Reads a NUL terminated string from the string table section at
+    /// section_index.
+    pub fn string(&self, section_index: usize, offset: u64) -> Result<String, Error> {
+        let section = self
+            .sections
+            .get(section_index)
+            .ok_or_else(|| Error::ElfParseError(format!("invalid section {}", section_index)))?;
+        let data = self.section_data(section)?;
+        read_string(&data, offset)
+    }
+
+    /// Looks up a symbol by name, .symtab takes precedence over .dynsym.
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols
+            .iter()
+            .chain(&self.dynamic_symbols)
+            .find(|s| s.name == name && s.is_defined())
+    }
+
+    /// Finds the function or object symbol covering addr. When symbols
+    /// overlap, the one starting closest to addr wins.
+    pub fn symbol_at(&self, addr: u64) -> Option<&Symbol> {
+        self.symbols
+            .iter()
+            .chain(&self.dynamic_symbols)
+            .filter(|s| s.is_defined() && (s.kind == STT_FUNC || s.kind == STT_OBJECT))
+            .filter(|s| s.value <= addr && addr - s.value < s.size.max(1))
+            .max_by_key(|s| s.value)
+    }
+
+    /// Content of the NT_GNU_BUILD_ID note, if any.
+    pub fn build_id(&self) -> Option<&Bytes> {
+        self.notes
+            .iter()
+            .find(|n| n.name == "GNU" && n.n_type == NT_GNU_BUILD_ID)
+            .map(|n| &n.desc)
+    }
+}
+
+/// Checks the Tag_RISCV_arch attribute of a program against the ISA of the
+/// machine. Programs without .riscv.attributes are accepted, as older
+/// toolchains do not emit it.
+pub fn check_isa<R: Register>(program: &Bytes, version: u32, isa: u8) -> Result<(), Error> {
+    let (header, _) = parse_headers::<R>(program, version)?;
+    let section_headers = parse_section_headers::<R>(program, version, &header)?;
+    let section_header = match section_headers
+        .iter()
+        .find(|h| h.sh_type == SHT_RISCV_ATTRIBUTES)
+    {
+        Some(section_header) => section_header,
+        None => return Ok(()),
+    };
+    let data = section_header
+        .sh_offset
+        .checked_add(section_header.sh_size)
+        .filter(|end| *end <= program.len() as u64)
+        .map(|end| program.slice(section_header.sh_offset as usize..end as usize))
+        .ok_or(Error::ElfSegmentAddrOrSizeError(section_header.sh_offset))?;
+    let attributes = RiscvAttributes::parse(&data)?;
+    let arch = match attributes.arch() {
+        Some(arch) => arch,
+        None => return Ok(()),
+    };
+    let (xlen, required) = parse_isa_str(arch)?;
+    if xlen != R::BITS {
+        return Err(Error::ElfBits);
+    }
+    // RV32E/RV64E programs run on full register files too, the reverse
+    // doesn't hold.
+    if required & !isa & !ISA_E != 0 || isa & !required & ISA_E != 0 {
+        return Err(Error::InvalidIsa(format!(
+            "{}, machine supports {}",
+            arch,
+            isa_to_str(isa, xlen)
+        )));
+    }
+    Ok(())
 }
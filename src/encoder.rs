@@ -0,0 +1,492 @@
+// Turns decoded instructions back into RISC-V machine code. This is the
+// inverse of the instruction factories in the instructions module: for any
+// instruction a factory produces, encoding it and decoding the result again
+// yields the same instruction. Fused opcodes produced by the MOP decoder have
+// no encoding of their own and are rejected.
+use ckb_vm_definitions::instructions::{self as insts, OperandLayout};
+use ckb_vm_definitions::registers::{RA, SP, ZERO};
+
+use crate::instructions::{
+    extract_opcode, instruction_length, instruction_metadata, Instruction, Itype, Register, Rtype,
+    Stype, Utype,
+};
+use crate::Error;
+
+fn invalid(i: Instruction) -> Error {
+    Error::InvalidEncoding(i)
+}
+
+fn register(i: Instruction, r: usize) -> Result<u32, Error> {
+    if r < 32 {
+        Ok(r as u32)
+    } else {
+        Err(invalid(i))
+    }
+}
+
+// Checks imm is a multiple of align and fits in a bits wide signed field.
+fn signed(i: Instruction, imm: i32, bits: u32, align: i32) -> Result<u32, Error> {
+    let min = -(1i64 << (bits - 1));
+    let max = (1i64 << (bits - 1)) - 1;
+    if (min..=max).contains(&i64::from(imm)) && imm % align == 0 {
+        Ok(imm as u32)
+    } else {
+        Err(invalid(i))
+    }
+}
+
+// Checks imm is a multiple of align and fits in a bits wide unsigned field.
+fn unsigned(i: Instruction, imm: u32, bits: u32, align: u32) -> Result<u32, Error> {
+    if imm < (1 << bits) && imm % align == 0 {
+        Ok(imm)
+    } else {
+        Err(invalid(i))
+    }
+}
+
+// Bit field helper, the inverse of utils::x.
+#[inline(always)]
+fn f(value: u32, lower: u32, length: u32, shifts: u32) -> u32 {
+    ((value >> lower) & ((1 << length) - 1)) << shifts
+}
+
+// Operand fields of the 32-bit formats, the opcode and function fields come
+// from the encoding listed with the instruction.
+fn itype(rd: u32, rs1: u32, imm: u32) -> u32 {
+    f(imm, 0, 12, 20) | (rs1 << 15) | (rd << 7)
+}
+
+fn stype(rs1: u32, rs2: u32, imm: u32) -> u32 {
+    f(imm, 5, 7, 25) | (rs2 << 20) | (rs1 << 15) | f(imm, 0, 5, 7)
+}
+
+fn btype(rs1: u32, rs2: u32, imm: u32) -> u32 {
+    f(imm, 12, 1, 31)
+        | f(imm, 5, 6, 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | f(imm, 1, 4, 8)
+        | f(imm, 11, 1, 7)
+}
+
+fn jtype(rd: u32, imm: u32) -> u32 {
+    f(imm, 20, 1, 31) | f(imm, 1, 10, 21) | f(imm, 11, 1, 20) | f(imm, 12, 8, 12) | (rd << 7)
+}
+
+/// Encodes an instruction into its canonical 32-bit form, regardless of
+/// the instruction length recorded in it.
+pub fn encode_full<R: Register>(i: Instruction) -> Result<u32, Error> {
+    let encoding = instruction_metadata(extract_opcode(i))
+        .and_then(|metadata| metadata.encoding)
+        .ok_or_else(|| invalid(i))?;
+    let shamt_bits = match encoding.layout {
+        OperandLayout::ShamtXlen if R::BITS == 64 => 6,
+        OperandLayout::Shamt6 => 6,
+        _ => 5,
+    };
+    let operands = match encoding.layout {
+        OperandLayout::R => {
+            let inst = Rtype(i);
+            // The rs2 field is fixed for unary instructions and LR.
+            let rs2 = if encoding.mask & (0x1F << 20) == 0 {
+                register(i, inst.rs2())? << 20
+            } else {
+                0
+            };
+            (register(i, inst.rs1())? << 15) | (register(i, inst.rd())? << 7) | rs2
+        }
+        OperandLayout::I => {
+            let inst = Itype(i);
+            itype(
+                register(i, inst.rd())?,
+                register(i, inst.rs1())?,
+                signed(i, inst.immediate_s(), 12, 1)?,
+            )
+        }
+        OperandLayout::ShamtXlen | OperandLayout::Shamt5 | OperandLayout::Shamt6 => {
+            let inst = Itype(i);
+            itype(
+                register(i, inst.rd())?,
+                register(i, inst.rs1())?,
+                unsigned(i, inst.immediate_u(), shamt_bits, 1)?,
+            )
+        }
+        OperandLayout::S => {
+            let inst = Stype(i);
+            stype(
+                register(i, inst.rs1())?,
+                register(i, inst.rs2())?,
+                signed(i, inst.immediate_s(), 12, 1)?,
+            )
+        }
+        OperandLayout::B => {
+            let inst = Stype(i);
+            btype(
+                register(i, inst.rs1())?,
+                register(i, inst.rs2())?,
+                signed(i, inst.immediate_s(), 13, 2)?,
+            )
+        }
+        OperandLayout::U => {
+            let inst = Utype(i);
+            let imm = inst.immediate_s();
+            if imm & 0xfff != 0 {
+                return Err(invalid(i));
+            }
+            imm as u32 | (register(i, inst.rd())? << 7)
+        }
+        OperandLayout::J => {
+            let inst = Utype(i);
+            jtype(
+                register(i, inst.rd())?,
+                signed(i, inst.immediate_s(), 21, 2)?,
+            )
+        }
+        OperandLayout::Fence => {
+            // See FenceType, fm, pred and succ are kept in rd, rs1 and rs2.
+            let inst = Rtype(i);
+            (unsigned(i, inst.rd() as u32, 4, 1)? << 28)
+                | (unsigned(i, inst.rs1() as u32, 4, 1)? << 24)
+                | (unsigned(i, inst.rs2() as u32, 4, 1)? << 20)
+        }
+        OperandLayout::None => 0,
+    };
+    Ok(encoding.value | operands)
+}
+
+// Register index in the 3-bit field of RVC instructions, only x8 - x15 can
+// be expressed.
+fn compact(r: usize) -> Option<u32> {
+    if (8..16).contains(&r) {
+        Some(r as u32 - 8)
+    } else {
+        None
+    }
+}
+
+fn fits_signed(imm: i32, bits: u32, align: i32) -> bool {
+    imm >= -(1 << (bits - 1)) && imm < (1 << (bits - 1)) && imm % align == 0
+}
+
+fn fits_unsigned(imm: u32, bits: u32, align: u32) -> bool {
+    imm < (1 << bits) && imm % align == 0
+}
+
+// [12]  => imm[5]
+// [6:2] => imm[4:0]
+fn c_immediate(imm: u32) -> u32 {
+    f(imm, 0, 5, 2) | f(imm, 5, 1, 12)
+}
+
+// [12:10] => uimm[5:3]
+// [6:5]   => uimm[7:6]
+fn c_ld_uimmediate(imm: u32) -> u32 {
+    f(imm, 3, 3, 10) | f(imm, 6, 2, 5)
+}
+
+// [12:10] => uimm[5:3]
+// [6:5]   => uimm[2|6]
+fn c_lw_uimmediate(imm: u32) -> u32 {
+    f(imm, 2, 1, 6) | f(imm, 3, 3, 10) | f(imm, 6, 1, 5)
+}
+
+// [12:2] => imm[11|4|9:8|10|6|7|3:1|5]
+fn c_j_immediate(imm: u32) -> u32 {
+    f(imm, 1, 3, 3)
+        | f(imm, 4, 1, 11)
+        | f(imm, 5, 1, 2)
+        | f(imm, 6, 1, 7)
+        | f(imm, 7, 1, 6)
+        | f(imm, 8, 2, 9)
+        | f(imm, 10, 1, 8)
+        | f(imm, 11, 1, 12)
+}
+
+// [12:10] => imm[8|4:3]
+// [6:2]   => imm[7:6|2:1|5]
+fn c_b_immediate(imm: u32) -> u32 {
+    f(imm, 1, 2, 3) | f(imm, 3, 2, 10) | f(imm, 5, 1, 2) | f(imm, 6, 2, 5) | f(imm, 8, 1, 12)
+}
+
+// Shift amount field of C.SLLI, C.SRLI and C.SRAI, which cannot be zero.
+// On RV32 the decoder masks the shift amount, so a zero shift still has a
+// compressed form with shamt[5] set.
+fn c_shamt<R: Register>(shamt: u32) -> Option<u32> {
+    if shamt == 0 {
+        if R::BITS == 32 {
+            Some(32)
+        } else {
+            None
+        }
+    } else if shamt <= u32::from(R::SHIFT_MASK) {
+        Some(shamt)
+    } else {
+        None
+    }
+}
+
+/// Encodes an instruction into an RVC instruction, returns None when it has
+/// no compressed form.
+pub fn encode_compressed<R: Register>(i: Instruction) -> Option<u16> {
+    let rv32 = R::BITS == 32;
+    let op = extract_opcode(i);
+    let bits = match op {
+        insts::OP_ADDI => {
+            let inst = Itype(i);
+            let (rd, rs1, imm) = (inst.rd(), inst.rs1(), inst.immediate_s());
+            if rd == ZERO && rs1 == ZERO && imm == 0 {
+                // C.NOP
+                0b_000_0_00000_00000_01
+            } else if rd != ZERO && rd == rs1 && imm != 0 && fits_signed(imm, 6, 1) {
+                // C.ADDI
+                0b_000_0_00000_00000_01 | ((rd as u32) << 7) | c_immediate(imm as u32)
+            } else if rd != ZERO && rs1 == ZERO && fits_signed(imm, 6, 1) {
+                // C.LI
+                0b_010_0_00000_00000_01 | ((rd as u32) << 7) | c_immediate(imm as u32)
+            } else if rd == SP && rs1 == SP && imm != 0 && fits_signed(imm, 10, 16) {
+                // C.ADDI16SP
+                let imm = imm as u32;
+                0b_011_0_00010_00000_01
+                    | f(imm, 4, 1, 6)
+                    | f(imm, 5, 1, 2)
+                    | f(imm, 6, 1, 5)
+                    | f(imm, 7, 2, 3)
+                    | f(imm, 9, 1, 12)
+            } else if rs1 == SP && imm > 0 && fits_unsigned(imm as u32, 10, 4) {
+                // C.ADDI4SPN
+                let imm = imm as u32;
+                (compact(rd)? << 2)
+                    | f(imm, 2, 1, 6)
+                    | f(imm, 3, 1, 5)
+                    | f(imm, 4, 2, 11)
+                    | f(imm, 6, 4, 7)
+            } else {
+                return None;
+            }
+        }
+        insts::OP_ADDIW if !rv32 => {
+            let inst = Itype(i);
+            let (rd, imm) = (inst.rd(), inst.immediate_s());
+            if rd == ZERO || rd != inst.rs1() || !fits_signed(imm, 6, 1) {
+                return None;
+            }
+            // C.ADDIW
+            0b_001_0_00000_00000_01 | ((rd as u32) << 7) | c_immediate(imm as u32)
+        }
+        insts::OP_LUI => {
+            let inst = Utype(i);
+            let (rd, imm) = (inst.rd(), inst.immediate_s());
+            if rd == ZERO || rd == SP || imm == 0 || imm & 0xfff != 0 {
+                return None;
+            }
+            if !fits_signed(imm >> 12, 6, 1) {
+                return None;
+            }
+            // C.LUI
+            0b_011_0_00000_00000_01 | ((rd as u32) << 7) | c_immediate((imm >> 12) as u32)
+        }
+        insts::OP_SRLI | insts::OP_SRAI | insts::OP_ANDI => {
+            let inst = Itype(i);
+            let rd = compact(inst.rd())?;
+            if inst.rd() != inst.rs1() {
+                return None;
+            }
+            let (funct2, imm) = match op {
+                insts::OP_SRLI | insts::OP_SRAI => {
+                    let shamt = c_shamt::<R>(inst.immediate_u())?;
+                    (if op == insts::OP_SRLI { 0b_00 } else { 0b_01 }, shamt)
+                }
+                _ => {
+                    let imm = inst.immediate_s();
+                    if !fits_signed(imm, 6, 1) {
+                        return None;
+                    }
+                    (0b_10, imm as u32)
+                }
+            };
+            0b_100_0_00_000_00000_01 | (funct2 << 10) | (rd << 7) | c_immediate(imm)
+        }
+        insts::OP_SLLI => {
+            let inst = Itype(i);
+            let rd = inst.rd();
+            if rd == ZERO || rd != inst.rs1() {
+                return None;
+            }
+            let shamt = c_shamt::<R>(inst.immediate_u())?;
+            // C.SLLI
+            0b_000_0_00000_00000_10 | ((rd as u32) << 7) | c_immediate(shamt)
+        }
+        insts::OP_SUB | insts::OP_XOR | insts::OP_OR | insts::OP_AND => {
+            let inst = Rtype(i);
+            let (rd, rs2) = (compact(inst.rd())?, compact(inst.rs2())?);
+            if inst.rd() != inst.rs1() {
+                return None;
+            }
+            let funct2 = match op {
+                insts::OP_SUB => 0b_00,
+                insts::OP_XOR => 0b_01,
+                insts::OP_OR => 0b_10,
+                _ => 0b_11,
+            };
+            0b_100_0_11_000_00_000_01 | (rd << 7) | (funct2 << 5) | (rs2 << 2)
+        }
+        insts::OP_SUBW | insts::OP_ADDW if !rv32 => {
+            let inst = Rtype(i);
+            let (rd, rs2) = (compact(inst.rd())?, compact(inst.rs2())?);
+            if inst.rd() != inst.rs1() {
+                return None;
+            }
+            let funct2 = if op == insts::OP_SUBW { 0b_00 } else { 0b_01 };
+            0b_100_1_11_000_00_000_01 | (rd << 7) | (funct2 << 5) | (rs2 << 2)
+        }
+        insts::OP_ADD => {
+            let inst = Rtype(i);
+            let (rd, rs1, rs2) = (inst.rd(), inst.rs1(), inst.rs2());
+            if rd == ZERO || rs2 == ZERO || rs2 >= 32 {
+                return None;
+            }
+            if rs1 == ZERO {
+                // C.MV
+                0b_100_0_00000_00000_10 | ((rd as u32) << 7) | ((rs2 as u32) << 2)
+            } else if rs1 == rd {
+                // C.ADD
+                0b_100_1_00000_00000_10 | ((rd as u32) << 7) | ((rs2 as u32) << 2)
+            } else {
+                return None;
+            }
+        }
+        insts::OP_JAL => {
+            let inst = Utype(i);
+            let imm = inst.immediate_s();
+            if !fits_signed(imm, 12, 2) {
+                return None;
+            }
+            match inst.rd() {
+                // C.J
+                ZERO => 0b_101_00000000000_01 | c_j_immediate(imm as u32),
+                // C.JAL
+                RA if rv32 => 0b_001_00000000000_01 | c_j_immediate(imm as u32),
+                _ => return None,
+            }
+        }
+        insts::OP_JALR_VERSION0 | insts::OP_JALR_VERSION1 => {
+            let inst = Itype(i);
+            let rs1 = inst.rs1();
+            if rs1 == ZERO || rs1 >= 32 || inst.immediate_s() != 0 {
+                return None;
+            }
+            match inst.rd() {
+                // C.JR
+                ZERO => 0b_100_0_00000_00000_10 | ((rs1 as u32) << 7),
+                // C.JALR
+                RA => 0b_100_1_00000_00000_10 | ((rs1 as u32) << 7),
+                _ => return None,
+            }
+        }
+        insts::OP_BEQ | insts::OP_BNE => {
+            let inst = Stype(i);
+            let imm = inst.immediate_s();
+            if inst.rs2() != ZERO || !fits_signed(imm, 9, 2) {
+                return None;
+            }
+            let funct3 = if op == insts::OP_BEQ { 0b_110 } else { 0b_111 };
+            (funct3 << 13) | (compact(inst.rs1())? << 7) | c_b_immediate(imm as u32) | 0b_01
+        }
+        insts::OP_LW_VERSION0
+        | insts::OP_LW_VERSION1
+        | insts::OP_LD_VERSION0
+        | insts::OP_LD_VERSION1 => {
+            let inst = Itype(i);
+            let (rd, rs1, imm) = (inst.rd(), inst.rs1(), inst.immediate_s());
+            let word = matches!(op, insts::OP_LW_VERSION0 | insts::OP_LW_VERSION1);
+            if !word && rv32 {
+                return None;
+            }
+            if rs1 == SP && rd != ZERO && rd < 32 && imm >= 0 {
+                let imm = imm as u32;
+                if word && fits_unsigned(imm, 8, 4) {
+                    // C.LWSP
+                    0b_010_0_00000_00000_10
+                        | ((rd as u32) << 7)
+                        | f(imm, 5, 1, 12)
+                        | f(imm, 2, 3, 4)
+                        | f(imm, 6, 2, 2)
+                } else if !word && fits_unsigned(imm, 9, 8) {
+                    // C.LDSP
+                    0b_011_0_00000_00000_10
+                        | ((rd as u32) << 7)
+                        | f(imm, 5, 1, 12)
+                        | f(imm, 3, 2, 5)
+                        | f(imm, 6, 3, 2)
+                } else {
+                    return None;
+                }
+            } else if imm >= 0 {
+                let (rd, rs1, imm) = (compact(rd)?, compact(rs1)?, imm as u32);
+                if word && fits_unsigned(imm, 7, 4) {
+                    // C.LW
+                    0b_010_00000000000_00 | (rs1 << 7) | (rd << 2) | c_lw_uimmediate(imm)
+                } else if !word && fits_unsigned(imm, 8, 8) {
+                    // C.LD
+                    0b_011_00000000000_00 | (rs1 << 7) | (rd << 2) | c_ld_uimmediate(imm)
+                } else {
+                    return None;
+                }
+            } else {
+                return None;
+            }
+        }
+        insts::OP_SW | insts::OP_SD => {
+            let inst = Stype(i);
+            let (rs1, rs2, imm) = (inst.rs1(), inst.rs2(), inst.immediate_s());
+            let word = op == insts::OP_SW;
+            if (!word && rv32) || imm < 0 || rs2 >= 32 {
+                return None;
+            }
+            let imm = imm as u32;
+            if rs1 == SP {
+                if word && fits_unsigned(imm, 8, 4) {
+                    // C.SWSP
+                    0b_110_000000_00000_10 | ((rs2 as u32) << 2) | f(imm, 2, 4, 9) | f(imm, 6, 2, 7)
+                } else if !word && fits_unsigned(imm, 9, 8) {
+                    // C.SDSP
+                    0b_111_000000_00000_10
+                        | ((rs2 as u32) << 2)
+                        | f(imm, 3, 3, 10)
+                        | f(imm, 6, 3, 7)
+                } else {
+                    return None;
+                }
+            } else {
+                let (rs1, rs2) = (compact(rs1)?, compact(rs2)?);
+                if word && fits_unsigned(imm, 7, 4) {
+                    // C.SW
+                    0b_110_00000000000_00 | (rs1 << 7) | (rs2 << 2) | c_lw_uimmediate(imm)
+                } else if !word && fits_unsigned(imm, 8, 8) {
+                    // C.SD
+                    0b_111_00000000000_00 | (rs1 << 7) | (rs2 << 2) | c_ld_uimmediate(imm)
+                } else {
+                    return None;
+                }
+            }
+        }
+        // C.EBREAK
+        insts::OP_EBREAK => 0b_100_1_00000_00000_10,
+        _ => return None,
+    };
+    Some(bits as u16)
+}
+
+/// Encodes an instruction following the length recorded in it: instructions
+/// decoded from RVC encodings are compressed again, all others use the
+/// 32-bit form. The returned bits should be stored in little endian, only
+/// the lower 16 bits are used for RVC instructions.
+pub fn encode<R: Register>(i: Instruction) -> Result<u32, Error> {
+    if instruction_length(i) == 2 {
+        encode_compressed::<R>(i)
+            .map(u32::from)
+            .ok_or_else(|| invalid(i))
+    } else {
+        encode_full::<R>(i)
+    }
+}
@@ -1,11 +1,17 @@
 #[derive(Debug, PartialEq, Clone, Eq, Display)]
 pub enum Error {
+    #[display("aot error: {_0}")]
+    Aot(String),
     #[display("asm error: {_0}")]
     Asm(u8),
+    #[display("assembler error: line {line}: {message}")]
+    Assembler { line: usize, message: String },
     #[display("cycles error: max cycles exceeded")]
     CyclesExceeded,
     #[display("cycles error: overflow")]
     CyclesOverflow,
+    #[display("decoded program error: {_0}")]
+    DecodedProgram(String),
     #[display("elf error: bits")]
     ElfBits,
     #[display("elf error: {_0}")]
@@ -16,16 +22,32 @@ pub enum Error {
     ElfSegmentWritableAndExecutable(u64),
     #[display("elf error: segment addr or size is wrong vaddr=0x{_0:x}")]
     ElfSegmentAddrOrSizeError(u64),
+    #[display("elf error: undefined symbol {_0}")]
+    ElfUndefinedSymbol(String),
+    #[display("elf error: unsupported relocation type={_0}")]
+    ElfUnsupportedRelocation(u32),
     // When users need to implement traits defined in CKB-VM, they can use
     // this error type to wrap their own errors.
     #[display("external error: {_0}")]
     External(String),
     #[display("invalid syscall {_0}")]
     InvalidEcall(u64),
+    #[display("invalid isa {_0}")]
+    InvalidIsa(String),
+    #[display("invalid instruction to encode 0x{_0:x}")]
+    InvalidEncoding(u64),
+    #[display("invalid fusion rule {name}: {message}")]
+    InvalidFusionRule { name: String, message: String },
     #[display("invalid instruction pc=0x{pc:x} instruction=0x{instruction:x}")]
     InvalidInstruction { pc: u64, instruction: u32 },
     #[display("invalid operand {_0}")]
     InvalidOp(u16),
+    #[display("invalid register pc=0x{pc:x} instruction=0x{instruction:x} register=x{register}")]
+    InvalidRegister {
+        pc: u64,
+        instruction: u32,
+        register: u8,
+    },
     #[display("invalid version")]
     InvalidVersion,
     #[display("I/O error: {kind:?} {data}")]
@@ -47,6 +69,8 @@ pub enum Error {
     Pause,
     #[display("snapshot data load error")]
     SnapshotDataLoadError,
+    #[display("trace cache error: {_0}")]
+    TraceCache(String),
     #[display("unexpected error")]
     Unexpected(String),
     #[display("yield")]
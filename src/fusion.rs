@@ -0,0 +1,809 @@
+// Rule based macro-op fusion.
+//
+// A fusion rule describes a sequence of adjacent instructions, the constraints
+// their register operands must satisfy, and a function building the fused
+// instruction from the captured operands. The decoder tries the rules whose
+// first instruction matches the decoded head in order, the first rule that
+// matches wins. Rules not matching any instruction leave the head untouched.
+//
+// Operands of a pattern instruction follow the accessors of its instruction
+// format:
+//
+// - Rtype: rd, rs1, rs2
+// - R4type: rd, rs1, rs2, rs3
+// - R5type: rd, rs1, rs2, rs3, rs4
+// - Itype: rd, rs1, immediate
+// - Stype: rs1, rs2, immediate
+// - Utype: rd, immediate
+//
+// A register variable is bound on its first occurrence, later occurrences must
+// name the same register. Different variables may still name the same register
+// unless a Distinct constraint says otherwise. An immediate variable can only
+// appear once.
+//
+// Rules are compiled to checks on the decoded instructions when added to a
+// decoder, checks are ordered by the instruction they need so a sequence is
+// only decoded as far as it keeps matching.
+use ckb_vm_definitions::instructions::{self as insts, InstructionFormat, InstructionOpcode};
+use ckb_vm_definitions::registers::RA;
+
+use crate::instructions::{
+    blank_instruction, instruction_metadata, Instruction, Itype, R4type, R5type, RegisterIndex,
+    Rtype, Utype,
+};
+use crate::machine::{VERSION0, VERSION2, VERSION3};
+use crate::Error;
+
+// Number of register and immediate variables a rule can capture.
+pub const MAX_REGISTER_VARIABLES: usize = 8;
+pub const MAX_IMMEDIATE_VARIABLES: usize = 4;
+pub const MAX_PATTERN_LENGTH: usize = 8;
+// The length field of an instruction holds at most 30 bytes, longer
+// sequences are never fused.
+pub const MAX_FUSED_LENGTH: u8 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    // Register variable.
+    Reg(usize),
+    // A specific register, e.g. RA.
+    Fixed(RegisterIndex),
+    // Immediate variable.
+    Imm(usize),
+    // Any register or immediate.
+    Any,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Constraint {
+    // The two register variables hold different registers.
+    Distinct(usize, usize),
+    // The register variable is not x0.
+    NonZero(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PatternInstruction {
+    pub opcode: InstructionOpcode,
+    pub operands: &'static [Operand],
+}
+
+impl PatternInstruction {
+    pub const fn new(opcode: InstructionOpcode, operands: &'static [Operand]) -> Self {
+        PatternInstruction { opcode, operands }
+    }
+}
+
+// Operands captured by a matching rule.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Captures {
+    // Address of the first instruction in the sequence.
+    pub pc: u64,
+    registers: [RegisterIndex; MAX_REGISTER_VARIABLES],
+    immediates: [i32; MAX_IMMEDIATE_VARIABLES],
+}
+
+impl Captures {
+    pub fn reg(&self, variable: usize) -> RegisterIndex {
+        self.registers[variable]
+    }
+
+    pub fn imm(&self, variable: usize) -> i32 {
+        self.immediates[variable]
+    }
+}
+
+// Bit offsets of the register operands and of the trailing immediate of a
+// decoded instruction, matching the accessors of Rtype, Itype and others.
+fn operand_layout(format: InstructionFormat) -> (&'static [u8], Option<u8>) {
+    match format {
+        InstructionFormat::Rtype => (&[8, 32, 40], None),
+        InstructionFormat::R4type => (&[8, 32, 40, 48], None),
+        InstructionFormat::R5type => (&[8, 32, 40, 48, 56], None),
+        InstructionFormat::Itype => (&[8, 32], Some(40)),
+        InstructionFormat::Stype => (&[32, 8], Some(40)),
+        InstructionFormat::Utype => (&[8], Some(32)),
+    }
+}
+
+pub type FuseFunc = fn(&Captures) -> Option<Instruction>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FusionRule {
+    pub name: &'static str,
+    // Inclusive range of VERSIONs the rule is enabled in.
+    pub min_version: u32,
+    pub max_version: u32,
+    pub pattern: &'static [PatternInstruction],
+    pub constraints: &'static [Constraint],
+    // Builds the fused instruction, the decoder sets its length to the total
+    // length of the sequence. Returning None rejects the match.
+    pub fuse: FuseFunc,
+}
+
+impl FusionRule {
+    pub fn head(&self) -> Option<InstructionOpcode> {
+        self.pattern.first().map(|p| p.opcode)
+    }
+
+    pub fn enabled(&self, version: u32) -> bool {
+        self.min_version <= version && version <= self.max_version
+    }
+}
+
+// An operand in a sequence: the index of its instruction and its bit offset
+// in the decoded instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Location {
+    index: u8,
+    shift: u8,
+}
+
+impl Location {
+    fn register(self, instructions: &[Instruction]) -> RegisterIndex {
+        (instructions[self.index as usize] >> self.shift) as u8 as RegisterIndex
+    }
+
+    fn immediate(self, instructions: &[Instruction]) -> i32 {
+        ((instructions[self.index as usize] as i64) >> self.shift) as i32
+    }
+}
+
+// Every check compares a field of one decoded instruction with a field of
+// another one or a constant, so matching is a tight loop without dispatch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Check {
+    // Index of the last instruction the check reads.
+    index: u8,
+    left: u8,
+    left_shift: u8,
+    left_mask: u64,
+    right: u8,
+    right_shift: u8,
+    right_mask: u64,
+    constant: u64,
+    equal: bool,
+}
+
+impl Check {
+    fn compare(left: Location, right: Option<Location>, constant: u64, equal: bool) -> Self {
+        let right_location = right.unwrap_or(left);
+        Check {
+            index: left.index.max(right_location.index),
+            left: left.index,
+            left_shift: left.shift,
+            left_mask: 0xFF,
+            right: right_location.index,
+            right_shift: right_location.shift,
+            right_mask: if right.is_some() { 0xFF } else { 0 },
+            constant,
+            equal,
+        }
+    }
+
+    fn opcode(index: u8, opcode: InstructionOpcode) -> Self {
+        let location = Location { index, shift: 0 };
+        Check {
+            // Opcodes are stored in bits [7:0] and [23:16].
+            left_mask: 0xFF00FF,
+            constant: blank_instruction(opcode),
+            ..Check::compare(location, None, 0, true)
+        }
+    }
+
+    fn same(a: Location, b: Location) -> Self {
+        Check::compare(a, Some(b), 0, true)
+    }
+
+    fn fixed(a: Location, register: RegisterIndex) -> Self {
+        Check::compare(a, None, register as u64, true)
+    }
+
+    fn distinct(a: Location, b: Location) -> Self {
+        Check::compare(a, Some(b), 0, false)
+    }
+
+    fn non_zero(a: Location) -> Self {
+        Check::compare(a, None, 0, false)
+    }
+
+    pub(crate) fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    #[inline(always)]
+    pub(crate) fn holds(&self, instructions: &[Instruction; MAX_PATTERN_LENGTH]) -> bool {
+        let left = (instructions[self.left as usize] >> self.left_shift) & self.left_mask;
+        let right = ((instructions[self.right as usize] >> self.right_shift) & self.right_mask)
+            | self.constant;
+        (left == right) == self.equal
+    }
+}
+
+// A fusion rule resolved to checks on decoded instructions, see the top of
+// this file.
+#[derive(Clone, Debug)]
+pub(crate) struct CompiledRule {
+    pub(crate) rule: FusionRule,
+    checks: Vec<Check>,
+    registers: [Option<Location>; MAX_REGISTER_VARIABLES],
+    immediates: [Option<Location>; MAX_IMMEDIATE_VARIABLES],
+}
+
+impl CompiledRule {
+    pub(crate) fn new(rule: FusionRule) -> Result<Self, Error> {
+        let invalid = |message: &str| Error::InvalidFusionRule {
+            name: rule.name.to_string(),
+            message: message.to_string(),
+        };
+        if rule.pattern.is_empty() || rule.pattern.len() > MAX_PATTERN_LENGTH {
+            return Err(invalid("pattern length out of range"));
+        }
+        for constraint in rule.constraints {
+            let (a, b) = match *constraint {
+                Constraint::Distinct(a, b) => (a, b),
+                Constraint::NonZero(a) => (a, a),
+            };
+            if a.max(b) >= MAX_REGISTER_VARIABLES {
+                return Err(invalid("constraint variable out of range"));
+            }
+        }
+        let mut compiled = CompiledRule {
+            rule,
+            checks: vec![],
+            registers: [None; MAX_REGISTER_VARIABLES],
+            immediates: [None; MAX_IMMEDIATE_VARIABLES],
+        };
+        let mut pending: Vec<Constraint> = rule.constraints.to_vec();
+        for (index, pattern) in rule.pattern.iter().enumerate() {
+            let metadata =
+                instruction_metadata(pattern.opcode).ok_or_else(|| invalid("unknown opcode"))?;
+            let (registers, immediate) = operand_layout(metadata.format);
+            if pattern.operands.len() > registers.len() + immediate.is_some() as usize {
+                return Err(invalid("too many operands"));
+            }
+            // The head opcode is checked by the decoder when picking rules.
+            if index > 0 {
+                compiled
+                    .checks
+                    .push(Check::opcode(index as u8, pattern.opcode));
+            }
+            for (position, operand) in pattern.operands.iter().enumerate() {
+                let location = |shift| Location {
+                    index: index as u8,
+                    shift,
+                };
+                let register = registers.get(position).copied().map(location);
+                let immediate = immediate.map(location);
+                match (*operand, register) {
+                    (Operand::Any, _) => {}
+                    (Operand::Fixed(value), Some(location)) => {
+                        compiled.checks.push(Check::fixed(location, value))
+                    }
+                    (Operand::Reg(variable), Some(location)) => {
+                        match compiled.registers.get(variable) {
+                            Some(Some(bound)) => {
+                                compiled.checks.push(Check::same(*bound, location))
+                            }
+                            Some(None) => compiled.registers[variable] = Some(location),
+                            None => return Err(invalid("register variable out of range")),
+                        }
+                    }
+                    (Operand::Imm(variable), None) => match compiled.immediates.get_mut(variable) {
+                        Some(slot @ None) => *slot = immediate,
+                        Some(Some(_)) => return Err(invalid("immediate variable bound twice")),
+                        None => return Err(invalid("immediate variable out of range")),
+                    },
+                    _ => return Err(invalid("operand does not fit the instruction format")),
+                }
+            }
+            // Constraints are checked as soon as their variables are bound.
+            let registers = compiled.registers;
+            pending.retain(|constraint| {
+                let check = match *constraint {
+                    Constraint::Distinct(a, b) => match (registers[a], registers[b]) {
+                        (Some(a), Some(b)) => Check::distinct(a, b),
+                        _ => return true,
+                    },
+                    Constraint::NonZero(a) => match registers[a] {
+                        Some(a) => Check::non_zero(a),
+                        None => return true,
+                    },
+                };
+                compiled.checks.push(check);
+                false
+            });
+        }
+        debug_assert!(compiled.checks.windows(2).all(|w| w[0].index <= w[1].index));
+        Ok(compiled)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.rule.pattern.len()
+    }
+
+    // Checks of the rule, ordered by the last instruction they read.
+    pub(crate) fn checks(&self) -> &[Check] {
+        &self.checks
+    }
+
+    // Captures of a sequence passing all checks. Constraints mentioning
+    // variables the pattern never binds are ignored, such variables read as
+    // x0.
+    pub(crate) fn captures(&self, pc: u64, instructions: &[Instruction]) -> Captures {
+        let mut captures = Captures {
+            pc,
+            ..Default::default()
+        };
+        for (register, location) in captures.registers.iter_mut().zip(self.registers) {
+            if let Some(location) = location {
+                *register = location.register(instructions);
+            }
+        }
+        for (immediate, location) in captures.immediates.iter_mut().zip(self.immediates) {
+            if let Some(location) = location {
+                *immediate = location.immediate(instructions);
+            }
+        }
+        captures
+    }
+}
+
+use Constraint::{Distinct, NonZero};
+use Operand::{Any, Fixed, Imm, Reg};
+
+const fn inst(opcode: InstructionOpcode, operands: &'static [Operand]) -> PatternInstruction {
+    PatternInstruction::new(opcode, operands)
+}
+
+fn wide_arithmetic(op: InstructionOpcode, c: &Captures) -> Option<Instruction> {
+    Some(R4type::new(op, c.reg(0), c.reg(1), c.reg(2), c.reg(3)).0)
+}
+
+fn shift_add(c: &Captures) -> Option<Instruction> {
+    let shamt = i16::try_from(c.imm(0)).ok()?;
+    Some(Rtype::new_with_immediate(insts::OP_SLLI_ADD, c.reg(0), c.reg(1), c.reg(2), shamt).0)
+}
+
+// Register 0 accesses offset imm 0 and register 1 offset imm 1 from base
+// register 2. The fused instruction accesses rd at imm and rs2 at imm + 8.
+// Only ascending pairs are fused: the fused instructions access the lower
+// address first, so a descending pair would fault on a different access
+// than the original sequence.
+fn memory_pair(op: InstructionOpcode, c: &Captures) -> Option<Instruction> {
+    if c.imm(0).checked_add(8) != Some(c.imm(1)) {
+        return None;
+    }
+    let offset = i16::try_from(c.imm(0)).ok()?;
+    Some(Rtype::new_with_immediate(op, c.reg(0), c.reg(2), c.reg(1), offset).0)
+}
+
+// The branch offset is relative to the branch, which follows an uncompressed
+// slt(u). The fused offset is relative to the slt(u).
+fn compare_and_branch(op: InstructionOpcode, c: &Captures) -> Option<Instruction> {
+    let offset = i16::try_from(c.imm(0).checked_add(4)?).ok()?;
+    Some(Rtype::new_with_immediate(op, c.reg(0), c.reg(1), c.reg(2), offset).0)
+}
+
+// Fusion rules shipped with the VM. Rules sharing a head are listed in the
+// order they are tried.
+pub static DEFAULT_FUSION_RULES: &[FusionRule] = &[
+    FusionRule {
+        name: "adc",
+        min_version: VERSION0,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_ADD, &[Reg(0), Reg(0), Reg(1)]),
+            inst(insts::OP_SLTU, &[Reg(1), Reg(0), Reg(1)]),
+            inst(insts::OP_ADD, &[Reg(0), Reg(0), Reg(2)]),
+            inst(insts::OP_SLTU, &[Reg(2), Reg(0), Reg(2)]),
+            inst(insts::OP_OR, &[Reg(1), Reg(1), Reg(2)]),
+        ],
+        constraints: &[
+            Distinct(0, 1),
+            Distinct(0, 2),
+            Distinct(1, 2),
+            NonZero(0),
+            NonZero(1),
+            NonZero(2),
+        ],
+        fuse: |c| Some(Rtype::new(insts::OP_ADC, c.reg(0), c.reg(1), c.reg(2)).0),
+    },
+    FusionRule {
+        name: "add3a",
+        min_version: VERSION2,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_ADD, &[Reg(0), Reg(1), Reg(0)]),
+            inst(insts::OP_SLTU, &[Reg(2), Reg(0), Reg(1)]),
+            inst(insts::OP_ADD, &[Reg(3), Reg(2), Reg(4)]),
+        ],
+        constraints: &[
+            Distinct(0, 1),
+            Distinct(0, 4),
+            Distinct(2, 4),
+            NonZero(0),
+            NonZero(2),
+        ],
+        fuse: |c| {
+            let (r0, r1, r2, r3, r4) = (c.reg(0), c.reg(1), c.reg(2), c.reg(3), c.reg(4));
+            Some(R5type::new(insts::OP_ADD3A, r0, r1, r2, r3, r4).0)
+        },
+    },
+    FusionRule {
+        name: "add3b",
+        min_version: VERSION2,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_ADD, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_SLTU, &[Reg(1), Reg(0), Reg(1)]),
+            inst(insts::OP_ADD, &[Reg(3), Reg(1), Reg(4)]),
+        ],
+        constraints: &[
+            Distinct(0, 1),
+            Distinct(0, 4),
+            Distinct(1, 4),
+            NonZero(0),
+            NonZero(1),
+        ],
+        fuse: |c| {
+            let (r0, r1, r2, r3, r4) = (c.reg(0), c.reg(1), c.reg(2), c.reg(3), c.reg(4));
+            Some(R5type::new(insts::OP_ADD3B, r0, r1, r2, r3, r4).0)
+        },
+    },
+    FusionRule {
+        name: "add3c",
+        min_version: VERSION2,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_ADD, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_SLTU, &[Reg(3), Reg(0), Reg(1)]),
+            inst(insts::OP_ADD, &[Reg(3), Reg(3), Reg(4)]),
+        ],
+        constraints: &[
+            Distinct(0, 1),
+            Distinct(0, 4),
+            Distinct(3, 4),
+            NonZero(0),
+            NonZero(3),
+        ],
+        fuse: |c| {
+            let (r0, r1, r2, r3, r4) = (c.reg(0), c.reg(1), c.reg(2), c.reg(3), c.reg(4));
+            Some(R5type::new(insts::OP_ADD3C, r0, r1, r2, r3, r4).0)
+        },
+    },
+    FusionRule {
+        name: "adcs",
+        min_version: VERSION2,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_ADD, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_SLTU, &[Reg(3), Reg(0), Reg(1)]),
+        ],
+        constraints: &[Distinct(0, 1), NonZero(0)],
+        fuse: |c| wide_arithmetic(insts::OP_ADCS, c),
+    },
+    // Addition is commutative, add r0, r0, r1 is treated as add r0, r1, r0.
+    FusionRule {
+        name: "adcs_commuted",
+        min_version: VERSION2,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_ADD, &[Reg(0), Reg(0), Reg(1)]),
+            inst(insts::OP_SLTU, &[Reg(3), Reg(0), Reg(1)]),
+        ],
+        constraints: &[Distinct(0, 1), NonZero(0)],
+        fuse: |c| Some(R4type::new(insts::OP_ADCS, c.reg(0), c.reg(1), c.reg(0), c.reg(3)).0),
+    },
+    // The second instruction only constrains its destination and first
+    // source, its second source is not checked.
+    FusionRule {
+        name: "sbb",
+        min_version: VERSION0,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_SUB, &[Reg(1), Reg(0), Reg(1)]),
+            inst(insts::OP_SLTU, &[Reg(3), Reg(0), Any]),
+            inst(insts::OP_SUB, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_SLTU, &[Reg(2), Reg(1), Reg(0)]),
+            inst(insts::OP_OR, &[Reg(1), Reg(2), Reg(3)]),
+        ],
+        constraints: &[
+            Distinct(0, 1),
+            Distinct(3, 0),
+            Distinct(3, 1),
+            Distinct(2, 0),
+            Distinct(2, 1),
+            Distinct(2, 3),
+            NonZero(0),
+            NonZero(1),
+            NonZero(2),
+            NonZero(3),
+        ],
+        fuse: |c| wide_arithmetic(insts::OP_SBB, c),
+    },
+    FusionRule {
+        name: "sbbs",
+        min_version: VERSION2,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_SUB, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_SLTU, &[Reg(3), Reg(1), Reg(2)]),
+        ],
+        constraints: &[Distinct(0, 1), Distinct(0, 2)],
+        fuse: |c| wide_arithmetic(insts::OP_SBBS, c),
+    },
+    FusionRule {
+        name: "far_jump_abs",
+        min_version: VERSION2,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_LUI, &[Fixed(RA), Imm(0)]),
+            inst(insts::OP_JALR_VERSION1, &[Fixed(RA), Fixed(RA), Imm(1)]),
+        ],
+        constraints: &[],
+        fuse: |c| {
+            let imm = c.imm(0).wrapping_add(c.imm(1));
+            Some(Utype::new_s(insts::OP_FAR_JUMP_ABS, RA, imm).0)
+        },
+    },
+    FusionRule {
+        name: "far_jump_abs_version1",
+        min_version: VERSION0,
+        max_version: VERSION2 - 1,
+        pattern: &[
+            inst(insts::OP_LUI, &[Reg(0), Imm(0)]),
+            inst(insts::OP_JALR_VERSION1, &[Fixed(RA), Reg(0), Imm(1)]),
+        ],
+        constraints: &[],
+        fuse: |c| {
+            let imm = c.imm(0).wrapping_add(c.imm(1));
+            Some(Utype::new_s(insts::OP_FAR_JUMP_ABS, RA, imm).0)
+        },
+    },
+    FusionRule {
+        name: "load_imm_lui",
+        min_version: VERSION0,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_LUI, &[Reg(0), Imm(0)]),
+            inst(insts::OP_ADDIW, &[Reg(0), Reg(0), Imm(1)]),
+        ],
+        constraints: &[],
+        fuse: |c| {
+            let imm = c.imm(0).wrapping_add(c.imm(1));
+            Some(Utype::new_s(insts::OP_CUSTOM_LOAD_IMM, c.reg(0), imm).0)
+        },
+    },
+    FusionRule {
+        name: "far_jump_rel",
+        min_version: VERSION2,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_AUIPC, &[Fixed(RA), Imm(0)]),
+            inst(insts::OP_JALR_VERSION1, &[Fixed(RA), Fixed(RA), Imm(1)]),
+        ],
+        constraints: &[],
+        fuse: |c| {
+            let imm = c.imm(0).checked_add(c.imm(1))?;
+            Some(Utype::new_s(insts::OP_FAR_JUMP_REL, RA, imm).0)
+        },
+    },
+    FusionRule {
+        name: "far_jump_rel_version1",
+        min_version: VERSION0,
+        max_version: VERSION2 - 1,
+        pattern: &[
+            inst(insts::OP_AUIPC, &[Reg(0), Imm(0)]),
+            inst(insts::OP_JALR_VERSION1, &[Fixed(RA), Reg(0), Imm(1)]),
+        ],
+        constraints: &[],
+        fuse: |c| {
+            let imm = c.imm(0).wrapping_add(c.imm(1));
+            Some(Utype::new_s(insts::OP_FAR_JUMP_REL, RA, imm).0)
+        },
+    },
+    // The loaded value is pc relative, so the sequence only fuses when pc
+    // and the result fit in 32 bits.
+    FusionRule {
+        name: "load_imm_auipc",
+        min_version: VERSION2,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_AUIPC, &[Reg(0), Imm(0)]),
+            inst(insts::OP_ADDI, &[Reg(0), Reg(0), Imm(1)]),
+        ],
+        constraints: &[],
+        fuse: |c| {
+            let pc = i32::try_from(c.pc).ok()?;
+            let imm = c.imm(0).checked_add(c.imm(1))?.checked_add(pc)?;
+            Some(Utype::new_s(insts::OP_CUSTOM_LOAD_IMM, c.reg(0), imm).0)
+        },
+    },
+    FusionRule {
+        name: "wide_mul",
+        min_version: VERSION0,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_MULH, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_MUL, &[Reg(3), Reg(1), Reg(2)]),
+        ],
+        constraints: &[Distinct(0, 1), Distinct(0, 2), Distinct(0, 3)],
+        fuse: |c| wide_arithmetic(insts::OP_WIDE_MUL, c),
+    },
+    FusionRule {
+        name: "wide_mulu",
+        min_version: VERSION0,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_MULHU, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_MUL, &[Reg(3), Reg(1), Reg(2)]),
+        ],
+        constraints: &[Distinct(0, 1), Distinct(0, 2), Distinct(0, 3)],
+        fuse: |c| wide_arithmetic(insts::OP_WIDE_MULU, c),
+    },
+    FusionRule {
+        name: "wide_mulsu",
+        min_version: VERSION0,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_MULHSU, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_MUL, &[Reg(3), Reg(1), Reg(2)]),
+        ],
+        constraints: &[Distinct(0, 1), Distinct(0, 2), Distinct(0, 3)],
+        fuse: |c| wide_arithmetic(insts::OP_WIDE_MULSU, c),
+    },
+    FusionRule {
+        name: "wide_div",
+        min_version: VERSION0,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_DIV, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_REM, &[Reg(3), Reg(1), Reg(2)]),
+        ],
+        constraints: &[Distinct(0, 1), Distinct(0, 2), Distinct(0, 3)],
+        fuse: |c| wide_arithmetic(insts::OP_WIDE_DIV, c),
+    },
+    FusionRule {
+        name: "wide_divu",
+        min_version: VERSION0,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_DIVU, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_REMU, &[Reg(3), Reg(1), Reg(2)]),
+        ],
+        constraints: &[Distinct(0, 1), Distinct(0, 2), Distinct(0, 3)],
+        fuse: |c| wide_arithmetic(insts::OP_WIDE_DIVU, c),
+    },
+    FusionRule {
+        name: "slli_add",
+        min_version: VERSION3,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_SLLI, &[Reg(0), Reg(1), Imm(0)]),
+            inst(insts::OP_ADD, &[Reg(0), Reg(0), Reg(2)]),
+        ],
+        constraints: &[Distinct(0, 2), NonZero(0)],
+        fuse: shift_add,
+    },
+    FusionRule {
+        name: "slli_add_commuted",
+        min_version: VERSION3,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_SLLI, &[Reg(0), Reg(1), Imm(0)]),
+            inst(insts::OP_ADD, &[Reg(0), Reg(2), Reg(0)]),
+        ],
+        constraints: &[Distinct(0, 2), NonZero(0)],
+        fuse: shift_add,
+    },
+    // A 64-bit constant built as lui, addi(w), then repeated slli and addi
+    // does not fit in a single instruction. The lui and addi(w) head becomes
+    // CUSTOM_LOAD_IMM, each following slli and addi pair becomes SLLI_ADDI,
+    // which packs the addend above the 8 bit shift amount.
+    FusionRule {
+        name: "slli_addi",
+        min_version: VERSION3,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_SLLI, &[Reg(0), Reg(1), Imm(0)]),
+            inst(insts::OP_ADDI, &[Reg(0), Reg(0), Imm(1)]),
+        ],
+        constraints: &[NonZero(0)],
+        fuse: |c| {
+            let imm = (c.imm(1) << 8) | c.imm(0);
+            Some(Itype::new_s(insts::OP_SLLI_ADDI, c.reg(0), c.reg(1), imm).0)
+        },
+    },
+    FusionRule {
+        name: "load_imm_lui_addi",
+        min_version: VERSION3,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_LUI, &[Reg(0), Imm(0)]),
+            inst(insts::OP_ADDI, &[Reg(0), Reg(0), Imm(1)]),
+        ],
+        constraints: &[],
+        fuse: |c| {
+            let imm = c.imm(0).checked_add(c.imm(1))?;
+            Some(Utype::new_s(insts::OP_CUSTOM_LOAD_IMM, c.reg(0), imm).0)
+        },
+    },
+    // Two loads from the same base at ascending offsets. The base must
+    // survive the first load.
+    FusionRule {
+        name: "ld_pair",
+        min_version: VERSION3,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_LD_VERSION1, &[Reg(0), Reg(2), Imm(0)]),
+            inst(insts::OP_LD_VERSION1, &[Reg(1), Reg(2), Imm(1)]),
+        ],
+        constraints: &[Distinct(0, 1), Distinct(0, 2)],
+        fuse: |c| memory_pair(insts::OP_LD_PAIR, c),
+    },
+    FusionRule {
+        name: "sd_pair",
+        min_version: VERSION3,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_SD, &[Reg(2), Reg(0), Imm(0)]),
+            inst(insts::OP_SD, &[Reg(2), Reg(1), Imm(1)]),
+        ],
+        constraints: &[],
+        fuse: |c| memory_pair(insts::OP_SD_PAIR, c),
+    },
+    FusionRule {
+        name: "slt_bnez",
+        min_version: VERSION3,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_SLT, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_BNE, &[Reg(0), Fixed(0), Imm(0)]),
+        ],
+        constraints: &[NonZero(0)],
+        fuse: |c| compare_and_branch(insts::OP_SLT_BNEZ, c),
+    },
+    FusionRule {
+        name: "slt_beqz",
+        min_version: VERSION3,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_SLT, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_BEQ, &[Reg(0), Fixed(0), Imm(0)]),
+        ],
+        constraints: &[NonZero(0)],
+        fuse: |c| compare_and_branch(insts::OP_SLT_BEQZ, c),
+    },
+    FusionRule {
+        name: "sltu_bnez",
+        min_version: VERSION3,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_SLTU, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_BNE, &[Reg(0), Fixed(0), Imm(0)]),
+        ],
+        constraints: &[NonZero(0)],
+        fuse: |c| compare_and_branch(insts::OP_SLTU_BNEZ, c),
+    },
+    FusionRule {
+        name: "sltu_beqz",
+        min_version: VERSION3,
+        max_version: u32::MAX,
+        pattern: &[
+            inst(insts::OP_SLTU, &[Reg(0), Reg(1), Reg(2)]),
+            inst(insts::OP_BEQ, &[Reg(0), Fixed(0), Imm(0)]),
+        ],
+        constraints: &[NonZero(0)],
+        fuse: |c| compare_and_branch(insts::OP_SLTU_BEQZ, c),
+    },
+];
+
+// Rules from DEFAULT_FUSION_RULES enabled in the given VERSION.
+pub fn default_fusion_rules(version: u32) -> Vec<FusionRule> {
+    DEFAULT_FUSION_RULES
+        .iter()
+        .filter(|rule| rule.enabled(version))
+        .copied()
+        .collect()
+}
@@ -0,0 +1,593 @@
+// Generates random but well-formed RISC-V programs for differential testing.
+// Unlike raw bytes fed to the fuzzers, every generated program loads and
+// terminates, so the interesting part of a run is the execution itself.
+//
+// A program is made of blocks of random instructions from the enabled ISA
+// subsets, forward branches, bounded loops, calls to leaf functions and
+// instruction sequences matching the macro-op fusion rules of decode_mop. It
+// ends with the exit syscall. Memory accesses stay inside a BUFFER_SIZE bytes
+// buffer, initialized with random data and labelled buffer.
+//
+// The generator is driven by an entropy slice: each decision consumes bytes
+// from it, so fuzzers can steer the program, and once the slice runs out a
+// PRNG seeded from its content takes over. The same entropy always yields
+// the same program.
+use ckb_vm_definitions::registers::{REGISTER_ABI_NAMES, S10, S11, S8, S9};
+use ckb_vm_definitions::{ISA_A, ISA_B, ISA_IMC};
+
+use crate::assembler::{assemble, AssembledProgram};
+use crate::{Error, Register};
+use bytes::Bytes;
+
+/// Size of the data buffer memory accesses go to.
+pub const BUFFER_SIZE: u64 = 512;
+
+// Registers with a fixed role, random instructions never write them:
+// * ra holds return addresses of calls
+// * sp is kept valid for the environment
+// * s8 holds addresses of atomic memory operations
+// * s9 points to the buffer
+// * s10 and s11 are loop counters
+const ADDRESS: usize = S8;
+const BASE: usize = S9;
+const COUNTERS: [usize; 2] = [S10, S11];
+
+#[derive(Clone, Debug)]
+pub struct GeneratorConfig {
+    // Instruction sets to draw from, ISA_B and ISA_A are honored. MOP
+    // patterns are always generated, since they are valid code on their own.
+    pub isa: u8,
+    // Whether the assembler may use compressed instructions.
+    pub rvc: bool,
+    // Number of top-level blocks.
+    pub blocks: usize,
+    // Maximum number of instructions in a straight-line block.
+    pub block_size: usize,
+    // Maximum iteration count of a loop, loops nest at most twice.
+    pub loop_iterations: u64,
+    // Number of leaf functions blocks can call.
+    pub functions: usize,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            isa: ISA_IMC | ISA_A | ISA_B,
+            rvc: true,
+            blocks: 16,
+            block_size: 16,
+            loop_iterations: 8,
+            functions: 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GeneratedProgram {
+    pub source: String,
+    pub program: AssembledProgram,
+}
+
+impl GeneratedProgram {
+    pub fn elf(&self) -> Bytes {
+        self.program.to_elf()
+    }
+
+    /// Address of the data buffer, for comparing memory after runs.
+    pub fn buffer(&self) -> u64 {
+        self.program.symbol("buffer").unwrap_or_default()
+    }
+}
+
+/// Generates a program for R::BITS wide registers from entropy.
+pub fn generate<R: Register>(
+    config: &GeneratorConfig,
+    entropy: &[u8],
+) -> Result<GeneratedProgram, Error> {
+    let mut generator = Generator::<R> {
+        config,
+        entropy: Entropy::new(entropy),
+        source: String::new(),
+        labels: 0,
+        _r: std::marker::PhantomData,
+    };
+    generator.program();
+    let program = assemble::<R>(&generator.source)?;
+    Ok(GeneratedProgram {
+        source: generator.source,
+        program,
+    })
+}
+
+struct Entropy<'a> {
+    data: &'a [u8],
+    position: usize,
+    state: u64,
+}
+
+impl<'a> Entropy<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        // FNV-1a, so the PRNG continues differently for different inputs.
+        let state = data.iter().fold(0xcbf29ce484222325u64, |h, b| {
+            (h ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+        });
+        Entropy {
+            data,
+            position: 0,
+            state,
+        }
+    }
+
+    fn byte(&mut self) -> u8 {
+        if let Some(b) = self.data.get(self.position) {
+            self.position += 1;
+            *b
+        } else {
+            self.u64() as u8
+        }
+    }
+
+    fn u64(&mut self) -> u64 {
+        if self.position + 8 <= self.data.len() {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&self.data[self.position..self.position + 8]);
+            self.position += 8;
+            return u64::from_le_bytes(bytes);
+        }
+        self.position = self.data.len();
+        // splitmix64
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform enough value in 0..n, small ranges take a single byte.
+    fn below(&mut self, n: u64) -> u64 {
+        match n {
+            0 => 0,
+            1..=256 => u64::from(self.byte()) % n,
+            _ => self.u64() % n,
+        }
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+
+    fn choose<'b, T>(&mut self, items: &'b [T]) -> &'b T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+}
+
+const RTYPE: &[&str] = &[
+    "add", "sub", "sll", "slt", "sltu", "xor", "srl", "sra", "or", "and", "mul", "mulh", "mulhsu",
+    "mulhu", "div", "divu", "rem", "remu",
+];
+const RTYPE_64: &[&str] = &[
+    "addw", "subw", "sllw", "srlw", "sraw", "mulw", "divw", "divuw", "remw", "remuw",
+];
+const RTYPE_B: &[&str] = &[
+    "andn", "orn", "xnor", "rol", "ror", "bclr", "bext", "binv", "bset", "sh1add", "sh2add",
+    "sh3add", "clmul", "clmulh", "clmulr", "min", "minu", "max", "maxu",
+];
+const RTYPE_B_64: &[&str] = &[
+    "add.uw",
+    "rolw",
+    "rorw",
+    "sh1add.uw",
+    "sh2add.uw",
+    "sh3add.uw",
+];
+const UNARY_B: &[&str] = &["clz", "ctz", "cpop", "sext.b", "sext.h", "orc.b"];
+const UNARY_B_64: &[&str] = &["clzw", "ctzw", "cpopw", "zext.h", "rev8"];
+const ITYPE: &[&str] = &["addi", "slti", "sltiu", "xori", "ori", "andi"];
+const SHIFT: &[&str] = &["slli", "srli", "srai"];
+const SHIFT_64: &[&str] = &["slliw", "srliw", "sraiw"];
+const SHIFT_B: &[&str] = &["bclri", "bexti", "binvi", "bseti", "rori"];
+const BRANCH: &[&str] = &["beq", "bne", "blt", "bge", "bltu", "bgeu"];
+const AMO: &[&str] = &[
+    "amoswap", "amoadd", "amoxor", "amoand", "amoor", "amomin", "amomax", "amominu", "amomaxu",
+];
+// (mnemonic, access width)
+const LOAD: &[(&str, u64)] = &[("lb", 1), ("lh", 2), ("lw", 4), ("lbu", 1), ("lhu", 2)];
+const LOAD_64: &[(&str, u64)] = &[("ld", 8), ("lwu", 4)];
+const STORE: &[(&str, u64)] = &[("sb", 1), ("sh", 2), ("sw", 4)];
+const STORE_64: &[(&str, u64)] = &[("sd", 8)];
+
+struct Generator<'a, R> {
+    config: &'a GeneratorConfig,
+    entropy: Entropy<'a>,
+    source: String,
+    labels: usize,
+    _r: std::marker::PhantomData<R>,
+}
+
+impl<'a, R: Register> Generator<'a, R> {
+    fn emit(&mut self, line: &str) {
+        self.source.push_str("    ");
+        self.source.push_str(line);
+        self.source.push('\n');
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels)
+    }
+
+    fn place(&mut self, label: &str) {
+        self.source.push_str(label);
+        self.source.push_str(":\n");
+    }
+
+    fn wide(&self) -> bool {
+        R::BITS == 64
+    }
+
+    fn writable(r: usize) -> bool {
+        r > 2 && r != ADDRESS && r != BASE && !COUNTERS.contains(&r)
+    }
+
+    // A register random instructions may write.
+    fn dst(&mut self) -> &'static str {
+        loop {
+            let r = self.entropy.below(32) as usize;
+            if Self::writable(r) {
+                return REGISTER_ABI_NAMES[r];
+            }
+        }
+    }
+
+    // Any register, mostly writable ones so values flow between instructions.
+    fn src(&mut self) -> &'static str {
+        if self.entropy.chance(10) {
+            REGISTER_ABI_NAMES[self.entropy.below(32) as usize]
+        } else {
+            self.dst()
+        }
+    }
+
+    // n distinct writable registers.
+    fn distinct(&mut self, n: usize) -> Vec<&'static str> {
+        let mut registers: Vec<&'static str> = vec![];
+        while registers.len() < n {
+            let r = self.dst();
+            if !registers.contains(&r) {
+                registers.push(r);
+            }
+        }
+        registers
+    }
+
+    fn immediate(&mut self) -> i64 {
+        match self.entropy.below(8) {
+            0 => 0,
+            1 => -1,
+            2 => *self.entropy.choose(&[1, 2047, -2048]),
+            _ => self.entropy.below(4096) as i64 - 2048,
+        }
+    }
+
+    // Interesting values, used to seed registers.
+    fn value(&mut self) -> i64 {
+        let value = match self.entropy.below(8) {
+            0 => *self
+                .entropy
+                .choose(&[0, 1, -1, i64::MIN, i64::MAX, 0x80000000, 0x7fffffff]),
+            1 => self.entropy.below(4096) as i64 - 2048,
+            2 => self.entropy.u64() as i32 as i64,
+            _ => self.entropy.u64() as i64,
+        };
+        if self.wide() {
+            value
+        } else {
+            value as i32 as i64
+        }
+    }
+
+    fn shamt(&mut self, bits: u64) -> u64 {
+        self.entropy.below(bits)
+    }
+
+    fn program(&mut self) {
+        if self.config.rvc {
+            self.emit(".option rvc");
+        }
+        self.emit(".data");
+        self.place("buffer");
+        let bytes: Vec<String> = (0..BUFFER_SIZE)
+            .map(|_| self.entropy.byte().to_string())
+            .collect();
+        for chunk in bytes.chunks(32) {
+            self.emit(&format!(".byte {}", chunk.join(", ")));
+        }
+        self.emit(".text");
+        self.emit(".globl _start");
+        self.place("_start");
+        self.emit("la s9, buffer");
+        for (r, name) in REGISTER_ABI_NAMES.iter().enumerate() {
+            if Self::writable(r) {
+                let value = self.value();
+                self.emit(&format!("li {}, {}", name, value));
+            }
+        }
+        for _ in 0..self.config.blocks {
+            self.block(0);
+        }
+        self.emit("li a7, 93");
+        self.emit("ecall");
+        for function in 0..self.config.functions {
+            self.place(&format!("function{}", function));
+            self.straight_line();
+            self.emit("ret");
+        }
+    }
+
+    fn block(&mut self, depth: usize) {
+        match self.entropy.below(10) {
+            0..=3 => self.straight_line(),
+            4 | 5 => self.fusion(),
+            6 => self.forward_branch(),
+            7 if depth < COUNTERS.len() && self.config.loop_iterations > 0 => {
+                self.bounded_loop(depth)
+            }
+            8 if self.config.functions > 0 => self.call(),
+            _ => self.straight_line(),
+        }
+    }
+
+    fn straight_line(&mut self) {
+        let size = self.entropy.below(self.config.block_size as u64) + 1;
+        for _ in 0..size {
+            self.instruction();
+        }
+    }
+
+    fn forward_branch(&mut self) {
+        let label = self.label();
+        let branch = *self.entropy.choose(BRANCH);
+        let (rs1, rs2) = (self.src(), self.src());
+        self.emit(&format!("{} {}, {}, {}", branch, rs1, rs2, label));
+        self.straight_line();
+        self.place(&label);
+    }
+
+    fn bounded_loop(&mut self, depth: usize) {
+        let counter = REGISTER_ABI_NAMES[COUNTERS[depth]];
+        let label = self.label();
+        let iterations = self.entropy.below(self.config.loop_iterations) + 1;
+        self.emit(&format!("li {}, {}", counter, iterations));
+        self.place(&label);
+        for _ in 0..self.entropy.below(3) + 1 {
+            self.block(depth + 1);
+        }
+        self.emit(&format!("addi {}, {}, -1", counter, counter));
+        self.emit(&format!("bnez {}, {}", counter, label));
+    }
+
+    fn call(&mut self) {
+        let function = self.entropy.below(self.config.functions as u64);
+        if self.entropy.chance(50) {
+            // AUIPC + JALR, fused into FAR_JUMP_REL.
+            self.emit(&format!("call function{}", function));
+        } else {
+            // LUI + JALR, fused into FAR_JUMP_ABS.
+            self.emit(&format!("lui ra, %hi(function{})", function));
+            self.emit(&format!("jalr ra, %lo(function{})(ra)", function));
+        }
+    }
+
+    // Sequences matching the fusion rules of decode_mop.
+    fn fusion(&mut self) {
+        match self.entropy.below(10) {
+            0 => {
+                let r = self.distinct(3);
+                self.emit(&format!("add {0}, {0}, {1}", r[0], r[1]));
+                self.emit(&format!("sltu {1}, {0}, {1}", r[0], r[1]));
+                self.emit(&format!("add {0}, {0}, {1}", r[0], r[2]));
+                self.emit(&format!("sltu {1}, {0}, {1}", r[0], r[2]));
+                self.emit(&format!("or {0}, {0}, {1}", r[1], r[2]));
+            }
+            1 => {
+                let r = self.distinct(4);
+                self.emit(&format!("sub {1}, {0}, {1}", r[0], r[1]));
+                self.emit(&format!("sltu {2}, {0}, {1}", r[0], r[1], r[2]));
+                self.emit(&format!("sub {0}, {1}, {2}", r[0], r[1], r[3]));
+                self.emit(&format!("sltu {2}, {1}, {0}", r[0], r[1], r[3]));
+                self.emit(&format!("or {1}, {2}, {0}", r[2], r[1], r[3]));
+            }
+            2 => {
+                let r = self.distinct(5);
+                match self.entropy.below(3) {
+                    // ADD3A
+                    0 => {
+                        self.emit(&format!("add {0}, {1}, {0}", r[0], r[1]));
+                        self.emit(&format!("sltu {2}, {0}, {1}", r[0], r[1], r[2]));
+                        self.emit(&format!("add {0}, {1}, {2}", r[3], r[2], r[4]));
+                    }
+                    // ADD3B
+                    1 => {
+                        self.emit(&format!("add {0}, {1}, {2}", r[0], r[1], r[2]));
+                        self.emit(&format!("sltu {1}, {0}, {1}", r[0], r[1]));
+                        self.emit(&format!("add {0}, {1}, {2}", r[3], r[1], r[4]));
+                    }
+                    // ADD3C
+                    _ => {
+                        self.emit(&format!("add {0}, {1}, {2}", r[0], r[1], r[2]));
+                        self.emit(&format!("sltu {2}, {0}, {1}", r[0], r[1], r[3]));
+                        self.emit(&format!("add {0}, {0}, {1}", r[3], r[4]));
+                    }
+                }
+            }
+            3 => {
+                // ADCS and SBBS
+                let r = self.distinct(4);
+                let op = *self.entropy.choose(&["add", "sub"]);
+                self.emit(&format!("{} {}, {}, {}", op, r[0], r[1], r[2]));
+                if op == "add" {
+                    self.emit(&format!("sltu {}, {}, {}", r[3], r[0], r[1]));
+                } else {
+                    self.emit(&format!("sltu {}, {}, {}", r[3], r[1], r[2]));
+                }
+            }
+            4 | 5 => {
+                let r = self.distinct(4);
+                let (high, low) = *self.entropy.choose(&[
+                    ("mulh", "mul"),
+                    ("mulhu", "mul"),
+                    ("mulhsu", "mul"),
+                    ("div", "rem"),
+                    ("divu", "remu"),
+                ]);
+                self.emit(&format!("{} {}, {}, {}", high, r[0], r[1], r[2]));
+                self.emit(&format!("{} {}, {}, {}", low, r[3], r[1], r[2]));
+            }
+            6 if self.wide() => {
+                let rd = self.dst();
+                let upper = self.entropy.below(1 << 20);
+                let lower = self.immediate();
+                self.emit(&format!("lui {}, {}", rd, upper));
+                self.emit(&format!("addiw {0}, {0}, {1}", rd, lower));
+            }
+            7 => {
+                // AUIPC + ADDI
+                let rd = self.dst();
+                let offset = self.entropy.below(BUFFER_SIZE);
+                self.emit(&format!("la {}, buffer + {}", rd, offset));
+            }
+            _ => {
+                let rd = self.dst();
+                let value = self.value();
+                self.emit(&format!("li {}, {}", rd, value));
+            }
+        }
+    }
+
+    fn instruction(&mut self) {
+        let isa = self.config.isa;
+        let b = isa & ISA_B != 0;
+        let a = isa & ISA_A != 0;
+        let bits = u64::from(R::BITS);
+        let line = match self.entropy.below(16) {
+            0..=3 => {
+                let mut op = *self.entropy.choose(RTYPE);
+                if self.wide() && self.entropy.chance(25) {
+                    op = *self.entropy.choose(RTYPE_64);
+                }
+                format!("{} {}, {}, {}", op, self.dst(), self.src(), self.src())
+            }
+            4 | 5 => {
+                let op = *self.entropy.choose(ITYPE);
+                format!(
+                    "{} {}, {}, {}",
+                    op,
+                    self.dst(),
+                    self.src(),
+                    self.immediate()
+                )
+            }
+            6 => {
+                if self.wide() && self.entropy.chance(30) {
+                    let op = *self.entropy.choose(SHIFT_64);
+                    format!("{} {}, {}, {}", op, self.dst(), self.src(), self.shamt(32))
+                } else {
+                    let op = *self.entropy.choose(SHIFT);
+                    format!(
+                        "{} {}, {}, {}",
+                        op,
+                        self.dst(),
+                        self.src(),
+                        self.shamt(bits)
+                    )
+                }
+            }
+            7 if b => {
+                let mut op = *self.entropy.choose(RTYPE_B);
+                if self.wide() && self.entropy.chance(25) {
+                    op = *self.entropy.choose(RTYPE_B_64);
+                }
+                format!("{} {}, {}, {}", op, self.dst(), self.src(), self.src())
+            }
+            8 if b => {
+                let mut op = *self.entropy.choose(UNARY_B);
+                if self.wide() && self.entropy.chance(40) {
+                    op = *self.entropy.choose(UNARY_B_64);
+                }
+                format!("{} {}, {}", op, self.dst(), self.src())
+            }
+            9 if b => {
+                let (op, shamt) = match self.entropy.below(6) {
+                    0 if self.wide() => ("roriw", self.shamt(32)),
+                    1 if self.wide() => ("slli.uw", self.shamt(64)),
+                    _ => (*self.entropy.choose(SHIFT_B), self.shamt(bits)),
+                };
+                format!("{} {}, {}, {}", op, self.dst(), self.src(), shamt)
+            }
+            10 | 11 => {
+                let (op, width) = if self.wide() && self.entropy.chance(30) {
+                    *self.entropy.choose(LOAD_64)
+                } else {
+                    *self.entropy.choose(LOAD)
+                };
+                let offset = self.offset(width);
+                format!("{} {}, {}(s9)", op, self.dst(), offset)
+            }
+            12 | 13 => {
+                let (op, width) = if self.wide() && self.entropy.chance(30) {
+                    *self.entropy.choose(STORE_64)
+                } else {
+                    *self.entropy.choose(STORE)
+                };
+                let offset = self.offset(width);
+                format!("{} {}, {}(s9)", op, self.src(), offset)
+            }
+            14 if a => return self.atomic(),
+            15 => {
+                let op = *self.entropy.choose(&["lui", "auipc"]);
+                format!("{} {}, {}", op, self.dst(), self.entropy.below(1 << 20))
+            }
+            _ => {
+                let op = *self.entropy.choose(ITYPE);
+                format!(
+                    "{} {}, {}, {}",
+                    op,
+                    self.dst(),
+                    self.src(),
+                    self.immediate()
+                )
+            }
+        };
+        self.emit(&line);
+    }
+
+    // Offset into the buffer, mostly aligned to width.
+    fn offset(&mut self, width: u64) -> u64 {
+        let offset = self.entropy.below(BUFFER_SIZE - width + 1);
+        if self.entropy.chance(90) {
+            offset / width * width
+        } else {
+            offset
+        }
+    }
+
+    fn atomic(&mut self) {
+        let width = if self.wide() && self.entropy.chance(50) {
+            8
+        } else {
+            4
+        };
+        let suffix = if width == 8 { "d" } else { "w" };
+        let offset = self.entropy.below(BUFFER_SIZE / width) * width;
+        self.emit(&format!("addi s8, s9, {}", offset));
+        if self.entropy.chance(20) {
+            let (rd, status, rs2) = (self.dst(), self.dst(), self.src());
+            self.emit(&format!("lr.{} {}, (s8)", suffix, rd));
+            self.emit(&format!("sc.{} {}, {}, (s8)", suffix, status, rs2));
+        } else {
+            let op = *self.entropy.choose(AMO);
+            let (rd, rs2) = (self.dst(), self.src());
+            self.emit(&format!("{}.{} {}, {}, (s8)", op, suffix, rd, rs2));
+        }
+    }
+}
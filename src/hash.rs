@@ -0,0 +1,11 @@
+use blake2b_rs::Blake2bBuilder;
+
+/// Blake2b-256 of data, personalized so hashes computed for different
+/// purposes never collide. The personalization is at most 16 bytes.
+pub fn blake2b_256(personal: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut hasher = Blake2bBuilder::new(32).personal(personal).build();
+    hasher.update(data);
+    let mut hash = [0u8; 32];
+    hasher.finalize(&mut hash);
+    hash
+}
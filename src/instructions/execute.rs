@@ -1522,6 +1522,98 @@ pub fn handle_custom_load_imm<Mac: Machine>(
     Ok(())
 }
 
+pub fn handle_slli_add<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    let i = Rtype(inst);
+    let shamt = Mac::REG::from_u32(i.immediate_s() as u32);
+    let rs1_value = &machine.registers()[i.rs1()];
+    let rs2_value = &machine.registers()[i.rs2()];
+    let value = (rs1_value.clone() << shamt).overflowing_add(rs2_value);
+    update_register(machine, i.rd(), value);
+    Ok(())
+}
+
+pub fn handle_slli_addi<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    let i = Itype(inst);
+    // The low 8 bits hold the shift amount, the remaining bits the addend.
+    let shamt = Mac::REG::from_u32(i.immediate_u() & 0xFF);
+    let addend = Mac::REG::from_i32(i.immediate_s() >> 8);
+    let rs1_value = &machine.registers()[i.rs1()];
+    let value = (rs1_value.clone() << shamt).overflowing_add(&addend);
+    update_register(machine, i.rd(), value);
+    Ok(())
+}
+
+pub fn handle_ld_pair<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    let i = Rtype(inst);
+    let address =
+        machine.registers()[i.rs1()].overflowing_add(&Mac::REG::from_i32(i.immediate_s()));
+    let low = machine.memory_mut().load64(&address)?;
+    let high = machine
+        .memory_mut()
+        .load64(&address.overflowing_add(&Mac::REG::from_u8(8)))?;
+    update_register(machine, i.rd(), low);
+    update_register(machine, i.rs2(), high);
+    Ok(())
+}
+
+pub fn handle_sd_pair<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    let i = Rtype(inst);
+    let address =
+        machine.registers()[i.rs1()].overflowing_add(&Mac::REG::from_i32(i.immediate_s()));
+    let low = machine.registers()[i.rd()].clone();
+    let high = machine.registers()[i.rs2()].clone();
+    machine.memory_mut().store64(&address, &low)?;
+    machine
+        .memory_mut()
+        .store64(&address.overflowing_add(&Mac::REG::from_u8(8)), &high)?;
+    Ok(())
+}
+
+fn compare_and_branch<Mac: Machine>(
+    machine: &mut Mac,
+    inst: Instruction,
+    signed: bool,
+    branch_if_set: bool,
+) -> Result<(), Error> {
+    let i = Rtype(inst);
+    let pc = machine.pc();
+    let rs1_value = &machine.registers()[i.rs1()];
+    let rs2_value = &machine.registers()[i.rs2()];
+    let value = if signed {
+        rs1_value.lt_s(rs2_value)
+    } else {
+        rs1_value.lt(rs2_value)
+    };
+    let condition = if branch_if_set {
+        value.ne(&Mac::REG::zero())
+    } else {
+        value.eq(&Mac::REG::zero())
+    };
+    let new_pc = condition.cond(
+        &Mac::REG::from_i32(i.immediate_s()).overflowing_add(pc),
+        &Mac::REG::from_u8(instruction_length(inst)).overflowing_add(pc),
+    );
+    update_register(machine, i.rd(), value);
+    machine.update_pc(new_pc);
+    Ok(())
+}
+
+pub fn handle_slt_bnez<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    compare_and_branch(machine, inst, true, true)
+}
+
+pub fn handle_slt_beqz<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    compare_and_branch(machine, inst, true, false)
+}
+
+pub fn handle_sltu_bnez<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    compare_and_branch(machine, inst, false, true)
+}
+
+pub fn handle_sltu_beqz<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
+    compare_and_branch(machine, inst, false, false)
+}
+
 pub fn handle_unloaded<Mac: Machine>(machine: &mut Mac, inst: Instruction) -> Result<(), Error> {
     handle_invalid_op(machine, inst)
 }
@@ -15,8 +15,9 @@ pub use self::register::Register;
 use super::Error;
 pub use ckb_vm_definitions::{
     instructions::{
-        self as insts, instruction_opcode_name, Instruction, InstructionOpcode,
-        MAXIMUM_BASIC_BLOCK_END_OPCODE, MINIMAL_BASIC_BLOCK_END_OPCODE, MINIMAL_OPCODE,
+        self as insts, instruction_metadata, instruction_opcode_name, Instruction,
+        InstructionOpcode, MAXIMUM_BASIC_BLOCK_END_OPCODE, MINIMAL_BASIC_BLOCK_END_OPCODE,
+        MINIMAL_OPCODE,
     },
     registers::REGISTER_ABI_NAMES,
 };
@@ -76,6 +77,22 @@ impl Rtype {
     pub fn rs2(self) -> RegisterIndex {
         (self.0 >> 40) as u8 as RegisterIndex
     }
+
+    // Fused instructions in R-type, e.g. LD_PAIR, keep a 16-bit immediate
+    // in the otherwise unused highest 2 bytes.
+    pub fn new_with_immediate(
+        op: InstructionOpcode,
+        rd: RegisterIndex,
+        rs1: RegisterIndex,
+        rs2: RegisterIndex,
+        immediate_s: i16,
+    ) -> Self {
+        Rtype(Self::new(op, rd, rs1, rs2).0 | (u64::from(immediate_s as u16) << 48))
+    }
+
+    pub fn immediate_s(self) -> SImmediate {
+        ((self.0 as i64) >> 48) as SImmediate
+    }
 }
 
 impl fmt::Display for Rtype {
@@ -87,7 +104,11 @@ impl fmt::Display for Rtype {
             REGISTER_ABI_NAMES[self.rd()],
             REGISTER_ABI_NAMES[self.rs1()],
             REGISTER_ABI_NAMES[self.rs2()]
-        )
+        )?;
+        if self.immediate_s() != 0 {
+            write!(f, ",{}", self.immediate_s())?;
+        }
+        Ok(())
     }
 }
 
@@ -418,6 +439,38 @@ pub fn is_basic_block_end_instruction(i: Instruction) -> bool {
         || is_slowpath_instruction(i)
 }
 
+// Registers referenced by an instruction according to its format, fields
+// the format does not have are left as x0.
+pub fn instruction_registers(i: Instruction) -> [RegisterIndex; 5] {
+    let format = match instruction_metadata(extract_opcode(i)) {
+        Some(metadata) => metadata.format,
+        None => return [0; 5],
+    };
+    match format {
+        insts::InstructionFormat::Rtype => {
+            let i = Rtype(i);
+            [i.rd(), i.rs1(), i.rs2(), 0, 0]
+        }
+        insts::InstructionFormat::R4type => {
+            let i = R4type(i);
+            [i.rd(), i.rs1(), i.rs2(), i.rs3(), 0]
+        }
+        insts::InstructionFormat::R5type => {
+            let i = R5type(i);
+            [i.rd(), i.rs1(), i.rs2(), i.rs3(), i.rs4()]
+        }
+        insts::InstructionFormat::Itype => {
+            let i = Itype(i);
+            [i.rd(), i.rs1(), 0, 0, 0]
+        }
+        insts::InstructionFormat::Stype => {
+            let i = Stype(i);
+            [i.rs1(), i.rs2(), 0, 0, 0]
+        }
+        insts::InstructionFormat::Utype => [Utype(i).rd(), 0, 0, 0, 0],
+    }
+}
+
 #[inline(always)]
 pub fn set_instruction_length_2(i: u64) -> u64 {
     i | 0x1000000
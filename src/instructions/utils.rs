@@ -1,6 +1,11 @@
 use super::super::machine::Machine;
+use super::{
+    blank_instruction, set_instruction_length_4, Instruction, Itype, Register, Rtype, Stype, Utype,
+};
 use crate::RISCV_GENERAL_REGISTER_NUMBER;
-use ckb_vm_definitions::instructions::{self as insts, InstructionOpcode};
+use ckb_vm_definitions::instructions::{
+    self as insts, decoding_candidates, Extension, InstructionOpcode, OperandLayout,
+};
 
 // Inspired from https://github.com/riscv/riscv-isa-sim/blob/master/riscv/decode.h#L105-L106
 #[inline(always)]
@@ -13,21 +18,6 @@ pub fn xs(instruction_bits: u32, lower: usize, length: usize, shifts: usize) ->
     ((instruction_bits as i32) << (32 - lower - length) >> (32 - length) << shifts) as u32
 }
 
-#[inline(always)]
-pub fn opcode(instruction_bits: u32) -> u32 {
-    x(instruction_bits, 0, 7, 0)
-}
-
-#[inline(always)]
-pub fn funct3(instruction_bits: u32) -> u32 {
-    x(instruction_bits, 12, 3, 0)
-}
-
-#[inline(always)]
-pub fn funct7(instruction_bits: u32) -> u32 {
-    x(instruction_bits, 25, 7, 0)
-}
-
 #[inline(always)]
 pub fn rd(instruction_bits: u32) -> usize {
     x(instruction_bits, 7, 5, 0) as usize
@@ -74,6 +64,107 @@ pub fn utype_immediate(instruction_bits: u32) -> i32 {
     xs(instruction_bits, 12, 20, 12) as i32
 }
 
+// Decodes a 32-bit instruction of the extension, following the encodings
+// listed with the instructions in ckb-vm-definitions.
+pub fn decode<R: Register>(
+    extension: Extension,
+    instruction_bits: u32,
+    version: u32,
+) -> Option<Instruction> {
+    let bit_length = R::BITS;
+    if bit_length != 32 && bit_length != 64 {
+        return None;
+    }
+    let rv64 = bit_length == 64;
+    let (op, encoding) = decoding_candidates(extension, instruction_bits)
+        .iter()
+        .find(|(_, e)| e.matches(instruction_bits, rv64, version))?;
+    let op = *op;
+    let inst = match encoding.layout {
+        OperandLayout::R => {
+            Rtype::new(
+                op,
+                rd(instruction_bits),
+                rs1(instruction_bits),
+                rs2(instruction_bits),
+            )
+            .0
+        }
+        OperandLayout::I => {
+            Itype::new_s(
+                op,
+                rd(instruction_bits),
+                rs1(instruction_bits),
+                itype_immediate(instruction_bits),
+            )
+            .0
+        }
+        OperandLayout::ShamtXlen => {
+            Itype::new_s(
+                op,
+                rd(instruction_bits),
+                rs1(instruction_bits),
+                itype_immediate(instruction_bits) & i32::from(R::SHIFT_MASK),
+            )
+            .0
+        }
+        OperandLayout::Shamt5 => {
+            Itype::new_u(
+                op,
+                rd(instruction_bits),
+                rs1(instruction_bits),
+                x(instruction_bits, 20, 5, 0),
+            )
+            .0
+        }
+        OperandLayout::Shamt6 => {
+            Itype::new_u(
+                op,
+                rd(instruction_bits),
+                rs1(instruction_bits),
+                x(instruction_bits, 20, 6, 0),
+            )
+            .0
+        }
+        OperandLayout::S => {
+            Stype::new_s(
+                op,
+                stype_immediate(instruction_bits),
+                rs1(instruction_bits),
+                rs2(instruction_bits),
+            )
+            .0
+        }
+        OperandLayout::B => {
+            Stype::new_s(
+                op,
+                btype_immediate(instruction_bits),
+                rs1(instruction_bits),
+                rs2(instruction_bits),
+            )
+            .0
+        }
+        OperandLayout::U => {
+            Utype::new_s(op, rd(instruction_bits), utype_immediate(instruction_bits)).0
+        }
+        OperandLayout::J => {
+            Utype::new_s(op, rd(instruction_bits), jtype_immediate(instruction_bits)).0
+        }
+        // See FenceType.
+        OperandLayout::Fence => {
+            Rtype::new(
+                op,
+                x(instruction_bits, 28, 4, 0) as usize,
+                x(instruction_bits, 24, 4, 0) as usize,
+                x(instruction_bits, 20, 4, 0) as usize,
+            )
+            .0
+        }
+        OperandLayout::None => blank_instruction(op),
+    };
+    Some(set_instruction_length_4(inst))
+}
+
 pub fn update_register<M: Machine>(machine: &mut M, register_index: usize, value: M::REG) {
     let register_index = register_index % RISCV_GENERAL_REGISTER_NUMBER;
     // In RISC-V, x0 is a special zero register with the following properties:
@@ -97,24 +188,6 @@ pub fn jalr(version: u32) -> InstructionOpcode {
     }
 }
 
-#[inline(always)]
-pub fn lb(version: u32) -> InstructionOpcode {
-    if version >= 1 {
-        insts::OP_LB_VERSION1
-    } else {
-        insts::OP_LB_VERSION0
-    }
-}
-
-#[inline(always)]
-pub fn lbu(version: u32) -> InstructionOpcode {
-    if version >= 1 {
-        insts::OP_LBU_VERSION1
-    } else {
-        insts::OP_LBU_VERSION0
-    }
-}
-
 #[inline(always)]
 pub fn ld(version: u32) -> InstructionOpcode {
     if version >= 1 {
@@ -124,24 +197,6 @@ pub fn ld(version: u32) -> InstructionOpcode {
     }
 }
 
-#[inline(always)]
-pub fn lh(version: u32) -> InstructionOpcode {
-    if version >= 1 {
-        insts::OP_LH_VERSION1
-    } else {
-        insts::OP_LH_VERSION0
-    }
-}
-
-#[inline(always)]
-pub fn lhu(version: u32) -> InstructionOpcode {
-    if version >= 1 {
-        insts::OP_LHU_VERSION1
-    } else {
-        insts::OP_LHU_VERSION0
-    }
-}
-
 #[inline(always)]
 pub fn lw(version: u32) -> InstructionOpcode {
     if version >= 1 {
@@ -150,12 +205,3 @@ pub fn lw(version: u32) -> InstructionOpcode {
         insts::OP_LW_VERSION0
     }
 }
-
-#[inline(always)]
-pub fn lwu(version: u32) -> InstructionOpcode {
-    if version >= 1 {
-        insts::OP_LWU_VERSION1
-    } else {
-        insts::OP_LWU_VERSION0
-    }
-}
@@ -0,0 +1,191 @@
+// Conversion between ISA strings, as used by -march and .riscv.attributes,
+// and the ISA_* flags of CKB-VM.
+use crate::registers::{A7, T0};
+use crate::{
+    Error, ISA_A, ISA_B, ISA_E, ISA_IMC, RISCV_EMBEDDED_REGISTER_NUMBER,
+    RISCV_GENERAL_REGISTER_NUMBER,
+};
+
+// Extensions CKB-VM can execute, together with the flags they require.
+// Multi-letter extensions only cover what is needed by the single-letter
+// ones, e.g. zmmul is a subset of m.
+const EXTENSIONS: &[(&str, u8)] = &[
+    ("i", ISA_IMC),
+    ("e", ISA_E),
+    ("m", ISA_IMC),
+    ("a", ISA_A),
+    ("c", ISA_IMC),
+    ("b", ISA_B),
+    ("zifencei", ISA_IMC),
+    ("zmmul", ISA_IMC),
+    ("zaamo", ISA_A),
+    ("zalrsc", ISA_A),
+    ("zca", ISA_IMC),
+    ("zba", ISA_B),
+    ("zbb", ISA_B),
+    ("zbc", ISA_B),
+    ("zbs", ISA_B),
+];
+
+fn extension_flags(name: &str, isa: &str) -> Result<u8, Error> {
+    EXTENSIONS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, flags)| *flags)
+        .ok_or_else(|| Error::InvalidIsa(isa.to_string()))
+}
+
+// Strips the optional version suffix, such as 2p1 or 1, from an extension.
+fn strip_version(name: &str) -> &str {
+    let name = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    match name.strip_suffix('p') {
+        Some(major) if major.ends_with(|c: char| c.is_ascii_digit()) => {
+            major.trim_end_matches(|c: char| c.is_ascii_digit())
+        }
+        _ => name,
+    }
+}
+
+/// Parses an ISA string such as rv64imac_zba_zbb_zbc_zbs or
+/// rv64i2p1_m2p0_a2p1_c2p0, returning XLEN and the ISA_* flags required to
+/// run programs built for it. Extensions CKB-VM does not implement result
+/// in Error::InvalidIsa.
+pub fn parse_isa_str(isa: &str) -> Result<(u8, u8), Error> {
+    let lower = isa.to_ascii_lowercase();
+    let (xlen, rest) = if let Some(rest) = lower.strip_prefix("rv64") {
+        (64, rest)
+    } else if let Some(rest) = lower.strip_prefix("rv32") {
+        (32, rest)
+    } else {
+        return Err(Error::InvalidIsa(isa.to_string()));
+    };
+    if !rest.starts_with(['i', 'e']) {
+        return Err(Error::InvalidIsa(isa.to_string()));
+    }
+    let mut flags = ISA_IMC;
+    for token in rest.split('_') {
+        if token.is_empty() {
+            return Err(Error::InvalidIsa(isa.to_string()));
+        }
+        if token.len() > 1 && matches!(token.as_bytes()[0], b'z' | b's' | b'x') {
+            flags |= extension_flags(strip_version(token), isa)?;
+            continue;
+        }
+        // A run of single-letter extensions, each might carry a version.
+        let bytes = token.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            if !bytes[i].is_ascii_lowercase() {
+                return Err(Error::InvalidIsa(isa.to_string()));
+            }
+            flags |= extension_flags(&token[i..i + 1], isa)?;
+            i += 1;
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+            if i + 1 < bytes.len() && bytes[i] == b'p' && bytes[i + 1].is_ascii_digit() {
+                i += 1;
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+        }
+    }
+    Ok((xlen, flags))
+}
+
+/// Returns the ISA_* flags required by an ISA string, see parse_isa_str.
+pub fn isa_from_str(isa: &str) -> Result<u8, Error> {
+    parse_isa_str(isa).map(|(_, flags)| flags)
+}
+
+/// Formats ISA_* flags as an ISA string, e.g. rv64imac_zba_zbb_zbc_zbs for
+/// ISA_IMC | ISA_A | ISA_B. ISA_MOP is an implementation detail of CKB-VM
+/// and does not show up in the string.
+pub fn isa_to_str(isa: u8, xlen: u8) -> String {
+    let base = if isa & ISA_E != 0 { 'e' } else { 'i' };
+    let mut s = format!("rv{}{}m", xlen, base);
+    if isa & ISA_A != 0 {
+        s.push('a');
+    }
+    s.push('c');
+    if isa & ISA_B != 0 {
+        s.push_str("_zba_zbb_zbc_zbs");
+    }
+    s
+}
+
+/// Returns the number of general registers a machine with the ISA_* flags
+/// has, 16 for RV32E/RV64E and 32 otherwise.
+pub fn register_number(isa: u8) -> usize {
+    if isa & ISA_E != 0 {
+        RISCV_EMBEDDED_REGISTER_NUMBER
+    } else {
+        RISCV_GENERAL_REGISTER_NUMBER
+    }
+}
+
+/// Returns the register holding the syscall number on ecall. It is a7, which
+/// RV32E/RV64E lack, those use t0 instead like embedded kernels do.
+pub fn syscall_number_register(isa: u8) -> usize {
+    if isa & ISA_E != 0 {
+        T0
+    } else {
+        A7
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ISA_MOP;
+
+    #[test]
+    fn test_isa_from_str() {
+        assert_eq!(isa_from_str("rv64imc"), Ok(ISA_IMC));
+        assert_eq!(isa_from_str("rv64imac"), Ok(ISA_IMC | ISA_A));
+        assert_eq!(
+            isa_from_str("rv64imac_zba_zbb_zbc_zbs"),
+            Ok(ISA_IMC | ISA_A | ISA_B)
+        );
+        assert_eq!(isa_from_str("rv64imc_zbb"), Ok(ISA_B));
+        assert_eq!(
+            isa_from_str("rv64i2p1_m2p0_a2p1_c2p0_zba1p0_zbb1p0_zbc1p0_zbs1p0"),
+            Ok(ISA_IMC | ISA_A | ISA_B)
+        );
+        assert_eq!(parse_isa_str("RV32IMC"), Ok((32, ISA_IMC)));
+        assert_eq!(parse_isa_str("rv32i2p0_m2p0"), Ok((32, ISA_IMC)));
+        assert_eq!(parse_isa_str("rv32emc"), Ok((32, ISA_E)));
+        assert_eq!(parse_isa_str("rv64e2p0_m2p0"), Ok((64, ISA_E)));
+    }
+
+    #[test]
+    fn test_isa_from_str_unsupported() {
+        for isa in [
+            "rv64gc",
+            "rv64imafdc",
+            "rv64i2p0_m2p0_a2p0_f2p0_d2p0",
+            "rv64imc_zicsr",
+            "rv64imc_zba_",
+            "rv128i",
+            "rv64mc",
+            "imc",
+        ] {
+            assert_eq!(isa_from_str(isa), Err(Error::InvalidIsa(isa.to_string())));
+        }
+    }
+
+    #[test]
+    fn test_isa_to_str() {
+        assert_eq!(isa_to_str(ISA_IMC, 64), "rv64imc");
+        assert_eq!(isa_to_str(ISA_IMC | ISA_MOP, 64), "rv64imc");
+        assert_eq!(isa_to_str(ISA_IMC | ISA_A, 32), "rv32imac");
+        assert_eq!(isa_to_str(ISA_E, 32), "rv32emc");
+        let all = ISA_IMC | ISA_A | ISA_B | ISA_MOP;
+        assert_eq!(isa_to_str(all, 64), "rv64imac_zba_zbb_zbc_zbs");
+        assert_eq!(
+            isa_from_str(&isa_to_str(all, 64)),
+            Ok(ISA_IMC | ISA_A | ISA_B)
+        );
+    }
+}
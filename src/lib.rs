@@ -1,13 +1,23 @@
 #[macro_use]
 extern crate derive_more;
 
+pub mod assembler;
 pub mod bits;
+pub mod cfg;
 pub mod cost_model;
+pub mod cycles;
 pub mod debugger;
 pub mod decoder;
+pub mod disasm;
 pub mod elf;
+pub mod encoder;
 pub mod error;
+pub mod fusion;
+pub mod generator;
+pub mod hash;
 pub mod instructions;
+pub mod isa;
+pub mod loader;
 pub mod machine;
 pub mod memory;
 pub mod snapshot;
@@ -20,9 +30,10 @@ pub use ckb_vm_definitions;
 pub use crate::{
     debugger::Debugger,
     instructions::{Instruction, Register},
+    isa::{isa_from_str, isa_to_str},
     machine::{
         trace::TraceMachine, CoreMachine, DefaultCoreMachine, DefaultMachine,
-        DefaultMachineBuilder, InstructionCycleFunc, Machine, SupportMachine,
+        DefaultMachineBuilder, InstructionCycleFunc, Machine, StackEnv, SupportMachine,
     },
     memory::{flat::FlatMemory, sparse::SparseMemory, wxorx::WXorXMemory, Memory},
     syscalls::Syscalls,
@@ -30,8 +41,9 @@ pub use crate::{
 pub use bytes::Bytes;
 
 pub use ckb_vm_definitions::{
-    registers, DEFAULT_MEMORY_SIZE, ISA_A, ISA_B, ISA_IMC, ISA_MOP, MEMORY_FRAMESIZE,
-    MEMORY_FRAME_SHIFTS, RISCV_GENERAL_REGISTER_NUMBER, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS,
+    registers, DEFAULT_MEMORY_SIZE, ISA_A, ISA_B, ISA_E, ISA_IMC, ISA_MOP, MEMORY_FRAMESIZE,
+    MEMORY_FRAME_SHIFTS, RISCV_EMBEDDED_REGISTER_NUMBER, RISCV_GENERAL_REGISTER_NUMBER,
+    RISCV_PAGESIZE, RISCV_PAGE_SHIFTS,
 };
 
 pub use error::Error;
@@ -0,0 +1,149 @@
+// Host side loader mapping an executable together with the shared objects it
+// depends on into a single machine.
+use crate::{
+    elf::{
+        parse_elf, parse_elf_with_load_bias, parse_exported_symbols, ProgramMetadata, SymbolTable,
+    },
+    machine::SupportMachine,
+    memory::round_page_up,
+    Error,
+};
+use bytes::Bytes;
+use std::ops::Range;
+
+/// An ELF image mapped into the machine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Module<I> {
+    pub name: String,
+    // Identifies where the image comes from, it is used as the DataSource id
+    // in Snapshot2Context::mark_modules.
+    pub id: I,
+    pub load_bias: u64,
+    // Range of memory pages occupied by the image.
+    pub range: Range<u64>,
+    pub metadata: ProgramMetadata,
+    // Dynamic symbols exported by the image, with load bias applied.
+    pub exports: SymbolTable,
+}
+
+/// Loader places shared objects one after another starting from
+/// library_base, resolving the dynamic symbols of each image against the
+/// images loaded before it. Shared objects must be loaded first, then the
+/// executable, whose imports can come from any of them. Make sure
+/// library_base leaves enough room for the executable below it and the stack
+/// above the shared objects.
+#[derive(Clone, Debug)]
+pub struct Loader<I> {
+    modules: Vec<Module<I>>,
+    symbols: SymbolTable,
+    next_address: u64,
+}
+
+fn metadata_range(metadata: &ProgramMetadata) -> Range<u64> {
+    let start = metadata.actions.iter().map(|a| a.addr).min().unwrap_or(0);
+    let end = metadata
+        .actions
+        .iter()
+        .map(|a| a.addr.saturating_add(a.size))
+        .max()
+        .unwrap_or(0);
+    start..end
+}
+
+impl<I: Clone> Loader<I> {
+    pub fn new(library_base: u64) -> Self {
+        Self {
+            modules: vec![],
+            symbols: SymbolTable::new(),
+            next_address: round_page_up(library_base),
+        }
+    }
+
+    /// Maps a shared object into the machine at the next free address.
+    pub fn load_library<M: SupportMachine>(
+        &mut self,
+        machine: &mut M,
+        name: &str,
+        id: I,
+        program: &Bytes,
+    ) -> Result<&Module<I>, Error> {
+        let version = machine.version();
+        let range = metadata_range(&parse_elf::<M::REG>(program, version)?);
+        let load_bias = self.next_address.wrapping_sub(range.start);
+        let metadata =
+            parse_elf_with_load_bias::<M::REG>(program, version, load_bias, &self.symbols)?;
+        machine.load_binary(program, &metadata, false)?;
+        let exports = parse_exported_symbols::<M::REG>(program, version, load_bias)?;
+        let range = metadata_range(&metadata);
+        self.next_address = range.end;
+        Ok(self.add_module(name, id, load_bias, range, metadata, exports))
+    }
+
+    /// Parses the executable at its link-time addresses and resolves its
+    /// imports against the loaded shared objects. The executable itself is
+    /// not loaded, the returned metadata shall be passed to
+    /// DefaultMachine::load_program_with_metadata so the stack is
+    /// initialized as well.
+    pub fn prepare_executable<M: SupportMachine>(
+        &mut self,
+        machine: &M,
+        name: &str,
+        id: I,
+        program: &Bytes,
+    ) -> Result<ProgramMetadata, Error> {
+        let version = machine.version();
+        let metadata = parse_elf_with_load_bias::<M::REG>(program, version, 0, &self.symbols)?;
+        let range = metadata_range(&metadata);
+        if self
+            .modules
+            .iter()
+            .any(|m| m.range.start < range.end && range.start < m.range.end)
+        {
+            return Err(Error::ElfSegmentAddrOrSizeError(range.start));
+        }
+        let exports = parse_exported_symbols::<M::REG>(program, version, 0)?;
+        Ok(self
+            .add_module(name, id, 0, range, metadata, exports)
+            .metadata
+            .clone())
+    }
+
+    fn add_module(
+        &mut self,
+        name: &str,
+        id: I,
+        load_bias: u64,
+        range: Range<u64>,
+        metadata: ProgramMetadata,
+        exports: SymbolTable,
+    ) -> &Module<I> {
+        for (symbol, value) in &exports {
+            // The first definition wins, like the lookup order of ld.so.
+            self.symbols.entry(symbol.clone()).or_insert(*value);
+        }
+        self.modules.push(Module {
+            name: name.to_string(),
+            id,
+            load_bias,
+            range,
+            metadata,
+            exports,
+        });
+        self.modules.last().unwrap()
+    }
+
+    /// Loaded modules, in loading order.
+    pub fn modules(&self) -> &[Module<I>] {
+        &self.modules
+    }
+
+    /// Finds the module an address belongs to.
+    pub fn find_module(&self, addr: u64) -> Option<&Module<I>> {
+        self.modules.iter().find(|m| m.range.contains(&addr))
+    }
+
+    /// All symbols exported by the loaded modules.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+}
@@ -0,0 +1,630 @@
+use std::collections::HashMap;
+use std::mem::offset_of;
+
+use ckb_vm_definitions::{
+    asm::{AsmCoreMachine, RET_DYNAMIC_JUMP, RET_MAX_CYCLES_EXCEEDED, RET_PAUSE, RET_SLOWPATH},
+    instructions as insts,
+    registers::RA,
+    MEMORY_FRAME_PAGE_SHIFTS, MEMORY_FRAME_SHIFTS, RISCV_PAGE_SHIFTS,
+};
+
+use super::{
+    aarch64_emitter::{
+        Emitter, Label, Mem, ALU_ADD, ALU_ADDS, ALU_AND, ALU_ASRV, ALU_EOR, ALU_LSLV, ALU_LSRV,
+        ALU_MUL, ALU_ORR, ALU_SMULH, ALU_SUB, ALU_UMULH, CC_EQ, CC_GE, CC_HI, CC_HS, CC_LO, CC_LT,
+        CC_NE, IMM_ADD, IMM_ADDS, IMM_SUB, SP, X0, X1, X16, X19, X2, X20, X21, X22, X29, X3, X30,
+        XZR,
+    },
+    AotTrace, Context, Translation,
+};
+use crate::machine::{trace::calculate_slot, VERSION2};
+use crate::{
+    instructions::{
+        extract_opcode, instruction_length, is_basic_block_end_instruction, Instruction, Itype,
+        Rtype, Stype, Utype,
+    },
+    memory::{FLAG_DIRTY, FLAG_WRITABLE, FLAG_WXORX_BIT},
+};
+
+// The generated code uses the following registers:
+// * x19: pointer to AsmCoreMachine
+// * x20: pointer to Context
+// * x21: pointer to the start of VM memory
+// * x0 - x3, x16: scratch registers
+// All RISC-V registers are kept in AsmCoreMachine, each instruction loads its
+// operands and writes back its result, so any exit point leaves the machine
+// in a consistent state.
+const MACHINE: u8 = X19;
+const CONTEXT: u8 = X20;
+const MEMORY: u8 = X21;
+
+fn register(index: usize) -> Mem {
+    Mem::base(
+        MACHINE,
+        (offset_of!(AsmCoreMachine, registers) + index * 8) as u32,
+    )
+}
+
+fn machine_field(offset: usize) -> Mem {
+    Mem::base(MACHINE, offset as u32)
+}
+
+fn context_field(offset: usize) -> Mem {
+    Mem::base(CONTEXT, offset as u32)
+}
+
+// Slow paths are emitted out of line after each trace body
+enum ColdPath {
+    Exit {
+        pc: u64,
+        code: u8,
+    },
+    Execute {
+        inst: Instruction,
+        pc: u64,
+        position: u64,
+        resume: Label,
+    },
+}
+
+struct Translator<'a> {
+    e: Emitter,
+    traces: &'a [AotTrace],
+    labels: Vec<Label>,
+    index: HashMap<u64, usize>,
+    epilogue: Label,
+    dispatch: Label,
+    cold: Vec<(Label, ColdPath)>,
+    track_slots: bool,
+}
+
+pub fn translate(traces: &[AotTrace], version: u32) -> Translation {
+    let mut e = Emitter::new();
+    let labels = traces.iter().map(|_| e.new_label()).collect();
+    let epilogue = e.new_label();
+    let dispatch = e.new_label();
+    let mut t = Translator {
+        e,
+        traces,
+        labels,
+        index: traces
+            .iter()
+            .enumerate()
+            .map(|(i, trace)| (trace.address, i))
+            .collect(),
+        epilogue,
+        dispatch,
+        cold: vec![],
+        track_slots: version < VERSION2,
+    };
+    t.emit_prologue();
+    for i in 0..traces.len() {
+        t.emit_trace(i);
+    }
+    let offsets = t
+        .labels
+        .iter()
+        .map(|label| t.e.label_offset(*label).unwrap() as u32)
+        .collect();
+    Translation {
+        code: t.e.finish(),
+        offsets,
+    }
+}
+
+impl<'a> Translator<'a> {
+    // Entry point at offset 0:
+    // extern "C" fn(context: *mut Context, target: *const u8) -> u8
+    fn emit_prologue(&mut self) {
+        let e = &mut self.e;
+        // x22 is only saved to keep the frame 16-byte aligned
+        e.stp_pre(X29, X30, SP, -48);
+        e.alu_ri(true, IMM_ADD, X29, SP, 0);
+        e.stp(X19, X20, SP, 16);
+        e.stp(X21, X22, SP, 32);
+        e.mov_rr(CONTEXT, X0);
+        e.load64(MACHINE, context_field(offset_of!(Context, machine)));
+        e.load64(
+            MEMORY,
+            machine_field(offset_of!(AsmCoreMachine, memory_ptr)),
+        );
+        e.br(X1);
+
+        e.bind(self.epilogue);
+        e.ldp(X21, X22, SP, 32);
+        e.ldp(X19, X20, SP, 16);
+        e.ldp_post(X29, X30, SP, 48);
+        e.ret();
+
+        // Looks up the trace starting at the address kept in x0, falls back
+        // to the runtime when no native code exists for the address.
+        let miss = e.new_label();
+        e.bind(self.dispatch);
+        e.store64(machine_field(offset_of!(AsmCoreMachine, pc)), X0);
+        e.load64(X1, context_field(offset_of!(Context, dispatch_base)));
+        e.alu_rr(true, ALU_SUB, X0, X0, X1);
+        e.load64(X1, context_field(offset_of!(Context, dispatch_length)));
+        e.cmp_rr(X0, X1);
+        e.b_cond(CC_HS, miss);
+        e.tbnz(X0, 0, miss);
+        e.load64(X1, context_field(offset_of!(Context, dispatch)));
+        // Each 2-byte slot maps to a 4-byte offset
+        e.lsl_imm(true, X2, X0, 1);
+        e.load32_zx(X1, Mem::indexed(X1, X2));
+        e.cbz32(X1, miss);
+        e.load64(X2, context_field(offset_of!(Context, code)));
+        e.alu_rr(true, ALU_ADD, X1, X1, X2);
+        e.br(X1);
+        e.bind(miss);
+        e.mov_imm(X0, u64::from(RET_DYNAMIC_JUMP));
+        e.b(self.epilogue);
+    }
+
+    fn cold(&mut self, path: ColdPath) -> Label {
+        let label = self.e.new_label();
+        self.cold.push((label, path));
+        label
+    }
+
+    fn exit(&mut self, pc: u64, code: u8) -> Label {
+        self.cold(ColdPath::Exit { pc, code })
+    }
+
+    fn store_pc(&mut self, pc: u64) {
+        self.e.mov_imm(X0, pc);
+        self.e
+            .store64(machine_field(offset_of!(AsmCoreMachine, pc)), X0);
+    }
+
+    // Continues execution at a statically known address
+    fn goto(&mut self, pc: u64) {
+        match self.index.get(&pc) {
+            Some(i) => {
+                let label = self.labels[*i];
+                self.e.b(label);
+            }
+            None => {
+                self.store_pc(pc);
+                self.e.mov_imm(X0, u64::from(RET_DYNAMIC_JUMP));
+                self.e.b(self.epilogue);
+            }
+        }
+    }
+
+    fn read(&mut self, reg: u8, index: usize) {
+        if index == 0 {
+            self.e.mov_imm(reg, 0);
+        } else {
+            self.e.load64(reg, register(index));
+        }
+    }
+
+    fn write(&mut self, index: usize, reg: u8) {
+        if index != 0 {
+            self.e.store64(register(index), reg);
+        }
+    }
+
+    // dst = src + imm, RISC-V immediates fit in the 12-bit field of
+    // ADD / SUB, anything larger goes through x16.
+    fn add_imm(&mut self, wide: bool, dst: u8, src: u8, imm: i32) {
+        if imm.unsigned_abs() >= 4096 {
+            self.e.mov_imm(X16, i64::from(imm) as u64);
+            self.e.alu_rr(wide, ALU_ADD, dst, src, X16);
+        } else if imm >= 0 {
+            self.e.alu_ri(wide, IMM_ADD, dst, src, imm as u32);
+        } else {
+            self.e.alu_ri(wide, IMM_SUB, dst, src, imm.unsigned_abs());
+        }
+    }
+
+    fn emit_trace(&mut self, trace_index: usize) {
+        let trace = &self.traces[trace_index];
+        let address = trace.address;
+        self.e.bind(self.labels[trace_index]);
+
+        let pause = self.exit(address, RET_PAUSE);
+        self.e.load64(X0, context_field(offset_of!(Context, pause)));
+        self.e.load8_zx(X0, Mem::base(X0, 0));
+        self.e.cbnz32(X0, pause);
+
+        // Before VERSION2, TraceMachine matches a trace by the lower 32 bits
+        // of pc, the runtime needs to know the last trace run in each slot
+        // to replicate that.
+        if self.track_slots {
+            self.e.load64(X1, context_field(offset_of!(Context, slots)));
+            self.e.mov_imm(X0, address);
+            self.e.mov_imm(X2, (calculate_slot(address) * 8) as u64);
+            self.e.store64(Mem::indexed(X1, X2), X0);
+        }
+
+        // Cycles of the whole trace are charged upfront. When they do not fit,
+        // the runtime interprets the trace so the error fires on exactly the
+        // same instruction as in TraceMachine.
+        let interpret = self.exit(address, RET_MAX_CYCLES_EXCEEDED);
+        self.e
+            .load64(X0, context_field(offset_of!(Context, cycles)));
+        self.e.mov_imm(X2, (trace_index * 8) as u64);
+        self.e.load64(X0, Mem::indexed(X0, X2));
+        self.e
+            .load64(X1, machine_field(offset_of!(AsmCoreMachine, cycles)));
+        self.e.alu_rr(true, ALU_ADDS, X0, X0, X1);
+        self.e.b_cond(CC_HS, interpret);
+        self.e
+            .load64(X1, machine_field(offset_of!(AsmCoreMachine, max_cycles)));
+        self.e.cmp_rr(X0, X1);
+        self.e.b_cond(CC_HI, interpret);
+        self.e
+            .store64(machine_field(offset_of!(AsmCoreMachine, cycles)), X0);
+
+        let mut pc = address;
+        let mut ended = false;
+        for (item, inst) in trace.instructions.iter().enumerate() {
+            let position = ((trace_index as u64) << 8) | item as u64;
+            self.emit_instruction(*inst, pc, position);
+            // AUIPC ends a trace but falls through to the next instruction
+            ended =
+                is_basic_block_end_instruction(*inst) && extract_opcode(*inst) != insts::OP_AUIPC;
+            pc = pc.wrapping_add(u64::from(instruction_length(*inst)));
+        }
+        if !ended {
+            self.goto(pc);
+        }
+
+        for (label, path) in std::mem::take(&mut self.cold) {
+            self.e.bind(label);
+            match path {
+                ColdPath::Exit { pc, code } => {
+                    self.store_pc(pc);
+                    self.e.mov_imm(X0, u64::from(code));
+                    self.e.b(self.epilogue);
+                }
+                ColdPath::Execute {
+                    inst,
+                    pc,
+                    position,
+                    resume,
+                } => {
+                    self.emit_execute(inst, pc, position);
+                    self.e.b(resume);
+                }
+            }
+        }
+    }
+
+    // Runs one instruction via the interpreter, exits when it fails.
+    fn emit_execute(&mut self, inst: Instruction, pc: u64, position: u64) {
+        let done = self.e.new_label();
+        self.e.mov_rr(X0, CONTEXT);
+        self.e.mov_imm(X1, inst);
+        self.e.mov_imm(X2, pc);
+        self.e.mov_imm(X3, position);
+        self.e
+            .load64(X16, context_field(offset_of!(Context, execute)));
+        self.e.blr(X16);
+        // Conditional branches only reach 1MB, the epilogue may be farther
+        self.e.uxtb(X0, X0);
+        self.e.cbz32(X0, done);
+        self.e.b(self.epilogue);
+        self.e.bind(done);
+    }
+
+    fn emit_instruction(&mut self, inst: Instruction, pc: u64, position: u64) {
+        let op = extract_opcode(inst);
+        let length = u64::from(instruction_length(inst));
+        match op {
+            insts::OP_ADD => self.rtype(inst, true, ALU_ADD),
+            insts::OP_SUB => self.rtype(inst, true, ALU_SUB),
+            insts::OP_AND => self.rtype(inst, true, ALU_AND),
+            insts::OP_OR => self.rtype(inst, true, ALU_ORR),
+            insts::OP_XOR => self.rtype(inst, true, ALU_EOR),
+            insts::OP_SLL => self.rtype(inst, true, ALU_LSLV),
+            insts::OP_SRL => self.rtype(inst, true, ALU_LSRV),
+            insts::OP_SRA => self.rtype(inst, true, ALU_ASRV),
+            insts::OP_SLT => self.compare(inst, CC_LT),
+            insts::OP_SLTU => self.compare(inst, CC_LO),
+            insts::OP_MUL => self.rtype(inst, true, ALU_MUL),
+            insts::OP_MULH => self.rtype(inst, true, ALU_SMULH),
+            insts::OP_MULHU => self.rtype(inst, true, ALU_UMULH),
+            insts::OP_ADDW => self.rtype(inst, false, ALU_ADD),
+            insts::OP_SUBW => self.rtype(inst, false, ALU_SUB),
+            insts::OP_SLLW => self.rtype(inst, false, ALU_LSLV),
+            insts::OP_SRLW => self.rtype(inst, false, ALU_LSRV),
+            insts::OP_SRAW => self.rtype(inst, false, ALU_ASRV),
+            insts::OP_MULW => self.rtype(inst, false, ALU_MUL),
+            insts::OP_ADDI => self.itype(inst, true, |t, imm| t.add_imm(true, X0, X0, imm)),
+            insts::OP_ANDI => self.itype(inst, true, |t, imm| t.logic_imm(ALU_AND, imm)),
+            insts::OP_ORI => self.itype(inst, true, |t, imm| t.logic_imm(ALU_ORR, imm)),
+            insts::OP_XORI => self.itype(inst, true, |t, imm| t.logic_imm(ALU_EOR, imm)),
+            insts::OP_SLTI => self.itype(inst, true, |t, imm| t.compare_imm(imm, CC_LT)),
+            insts::OP_SLTIU => self.itype(inst, true, |t, imm| t.compare_imm(imm, CC_LO)),
+            insts::OP_ADDIW => self.itype(inst, false, |t, imm| t.add_imm(false, X0, X0, imm)),
+            insts::OP_SLLI => self.shift_imm(inst, true, Emitter::lsl_imm),
+            insts::OP_SRLI => self.shift_imm(inst, true, Emitter::lsr_imm),
+            insts::OP_SRAI => self.shift_imm(inst, true, Emitter::asr_imm),
+            insts::OP_SLLIW => self.shift_imm(inst, false, Emitter::lsl_imm),
+            insts::OP_SRLIW => self.shift_imm(inst, false, Emitter::lsr_imm),
+            insts::OP_SRAIW => self.shift_imm(inst, false, Emitter::asr_imm),
+            insts::OP_LUI | insts::OP_CUSTOM_LOAD_IMM => {
+                let i = Utype(inst);
+                self.constant(i.rd(), i64::from(i.immediate_s()) as u64);
+            }
+            insts::OP_CUSTOM_LOAD_UIMM => {
+                let i = Utype(inst);
+                self.constant(i.rd(), u64::from(i.immediate_u()));
+            }
+            insts::OP_AUIPC => {
+                let i = Utype(inst);
+                self.constant(i.rd(), pc.wrapping_add(i64::from(i.immediate_s()) as u64));
+            }
+            insts::OP_LB_VERSION0 | insts::OP_LB_VERSION1 => {
+                self.load(inst, pc, position, 1, Emitter::load8_sx)
+            }
+            insts::OP_LBU_VERSION0 | insts::OP_LBU_VERSION1 => {
+                self.load(inst, pc, position, 1, Emitter::load8_zx)
+            }
+            insts::OP_LH_VERSION0 | insts::OP_LH_VERSION1 => {
+                self.load(inst, pc, position, 2, Emitter::load16_sx)
+            }
+            insts::OP_LHU_VERSION0 | insts::OP_LHU_VERSION1 => {
+                self.load(inst, pc, position, 2, Emitter::load16_zx)
+            }
+            insts::OP_LW_VERSION0 | insts::OP_LW_VERSION1 => {
+                self.load(inst, pc, position, 4, Emitter::load32_sx)
+            }
+            insts::OP_LWU_VERSION0 | insts::OP_LWU_VERSION1 => {
+                self.load(inst, pc, position, 4, Emitter::load32_zx)
+            }
+            insts::OP_LD_VERSION0 | insts::OP_LD_VERSION1 => {
+                self.load(inst, pc, position, 8, Emitter::load64)
+            }
+            insts::OP_SB => self.store(inst, pc, position, 1, Emitter::store8),
+            insts::OP_SH => self.store(inst, pc, position, 2, Emitter::store16),
+            insts::OP_SW => self.store(inst, pc, position, 4, Emitter::store32),
+            insts::OP_SD => self.store(inst, pc, position, 8, Emitter::store64),
+            insts::OP_BEQ => self.branch(inst, pc, length, CC_EQ),
+            insts::OP_BNE => self.branch(inst, pc, length, CC_NE),
+            insts::OP_BLT => self.branch(inst, pc, length, CC_LT),
+            insts::OP_BGE => self.branch(inst, pc, length, CC_GE),
+            insts::OP_BLTU => self.branch(inst, pc, length, CC_LO),
+            insts::OP_BGEU => self.branch(inst, pc, length, CC_HS),
+            insts::OP_JAL => {
+                let i = Utype(inst);
+                self.constant(i.rd(), pc.wrapping_add(length));
+                self.goto(pc.wrapping_add(i64::from(i.immediate_s()) as u64));
+            }
+            insts::OP_FAR_JUMP_REL => {
+                let i = Utype(inst);
+                self.constant(RA, pc.wrapping_add(length));
+                self.goto(pc.wrapping_add(i64::from(i.immediate_s()) as u64) & !1);
+            }
+            insts::OP_FAR_JUMP_ABS => {
+                let i = Utype(inst);
+                self.constant(RA, pc.wrapping_add(length));
+                self.goto(i64::from(i.immediate_s()) as u64 & !1);
+            }
+            insts::OP_JALR_VERSION0 | insts::OP_JALR_VERSION1 => {
+                let i = Itype(inst);
+                // Version 0 writes the link register before reading rs1
+                if op == insts::OP_JALR_VERSION0 {
+                    self.constant(i.rd(), pc.wrapping_add(length));
+                }
+                self.read(X0, i.rs1());
+                self.add_imm(true, X0, X0, i.immediate_s());
+                self.e.mov_imm(X1, !1);
+                self.e.alu_rr(true, ALU_AND, X0, X0, X1);
+                if op == insts::OP_JALR_VERSION1 {
+                    self.e.mov_imm(X1, pc.wrapping_add(length));
+                    self.write(i.rd(), X1);
+                }
+                let dispatch = self.dispatch;
+                self.e.b(dispatch);
+            }
+            insts::OP_ECALL | insts::OP_EBREAK => {
+                // Syscalls run outside of native code, the runtime executes
+                // the instruction kept in the context.
+                self.e.mov_imm(X0, inst);
+                self.e
+                    .store64(context_field(offset_of!(Context, instruction)), X0);
+                self.store_pc(pc);
+                self.e.mov_imm(X0, u64::from(RET_SLOWPATH));
+                self.e.b(self.epilogue);
+            }
+            _ => {
+                self.emit_execute(inst, pc, position);
+                if is_basic_block_end_instruction(inst) {
+                    self.e
+                        .load64(X0, machine_field(offset_of!(AsmCoreMachine, pc)));
+                    let dispatch = self.dispatch;
+                    self.e.b(dispatch);
+                }
+            }
+        }
+    }
+
+    fn constant(&mut self, rd: usize, value: u64) {
+        if rd != 0 {
+            if value == 0 {
+                self.e.store64(register(rd), XZR);
+            } else {
+                self.e.mov_imm(X0, value);
+                self.write(rd, X0);
+            }
+        }
+    }
+
+    // x0 = rs1 op rs2, 32-bit operations get their result sign extended.
+    fn rtype(&mut self, inst: Instruction, wide: bool, opcode: u32) {
+        let i = Rtype(inst);
+        if i.rd() == 0 {
+            return;
+        }
+        self.read(X0, i.rs1());
+        self.read(X1, i.rs2());
+        self.e.alu_rr(wide, opcode, X0, X0, X1);
+        if !wide {
+            self.e.sxtw(X0, X0);
+        }
+        self.write(i.rd(), X0);
+    }
+
+    fn compare(&mut self, inst: Instruction, cc: u8) {
+        let i = Rtype(inst);
+        if i.rd() == 0 {
+            return;
+        }
+        self.read(X0, i.rs1());
+        self.read(X1, i.rs2());
+        self.e.cmp_rr(X0, X1);
+        self.e.cset(X0, cc);
+        self.write(i.rd(), X0);
+    }
+
+    // x0 = rs1, the body leaves the result in x0.
+    fn itype<F: FnOnce(&mut Self, i32)>(&mut self, inst: Instruction, wide: bool, body: F) {
+        let i = Itype(inst);
+        if i.rd() == 0 {
+            return;
+        }
+        self.read(X0, i.rs1());
+        body(self, i.immediate_s());
+        if !wide {
+            self.e.sxtw(X0, X0);
+        }
+        self.write(i.rd(), X0);
+    }
+
+    fn logic_imm(&mut self, opcode: u32, imm: i32) {
+        self.e.mov_imm(X1, i64::from(imm) as u64);
+        self.e.alu_rr(true, opcode, X0, X0, X1);
+    }
+
+    fn compare_imm(&mut self, imm: i32, cc: u8) {
+        self.e.mov_imm(X1, i64::from(imm) as u64);
+        self.e.cmp_rr(X0, X1);
+        self.e.cset(X0, cc);
+    }
+
+    fn shift_imm(
+        &mut self,
+        inst: Instruction,
+        wide: bool,
+        shift: fn(&mut Emitter, bool, u8, u8, u32),
+    ) {
+        let i = Itype(inst);
+        if i.rd() == 0 {
+            return;
+        }
+        let mask = if wide { 0x3f } else { 0x1f };
+        self.read(X0, i.rs1());
+        shift(&mut self.e, wide, X0, X0, i.immediate_u() & mask);
+        if !wide {
+            self.e.sxtw(X0, X0);
+        }
+        self.write(i.rd(), X0);
+    }
+
+    fn branch(&mut self, inst: Instruction, pc: u64, length: u64, cc: u8) {
+        let i = Stype(inst);
+        let taken = self.e.new_label();
+        self.read(X0, i.rs1());
+        self.read(X1, i.rs2());
+        self.e.cmp_rr(X0, X1);
+        self.e.b_cond(cc, taken);
+        self.goto(pc.wrapping_add(length));
+        self.e.bind(taken);
+        self.goto(pc.wrapping_add(i64::from(i.immediate_s()) as u64));
+    }
+
+    // Leaves the effective address in x0, jumps to the interpreter whenever
+    // the access is not known to succeed without side effects.
+    fn load(
+        &mut self,
+        inst: Instruction,
+        pc: u64,
+        position: u64,
+        size: u32,
+        body: fn(&mut Emitter, u8, Mem),
+    ) {
+        let i = Itype(inst);
+        let resume = self.e.new_label();
+        let slow = self.cold(ColdPath::Execute {
+            inst,
+            pc,
+            position,
+            resume,
+        });
+        self.read(X0, i.rs1());
+        self.add_imm(true, X0, X0, i.immediate_s());
+        // The access must end before the memory boundary, version 0 refuses
+        // to touch the last byte so the slow path reports that case.
+        self.e.alu_ri(true, IMM_ADDS, X2, X0, size);
+        self.e.b_cond(CC_HS, slow);
+        self.e
+            .load64(X1, machine_field(offset_of!(AsmCoreMachine, memory_size)));
+        self.e.cmp_rr(X2, X1);
+        self.e.b_cond(CC_HS, slow);
+        // Both the first and the last frame touched must be initialized
+        self.e
+            .load64(X3, machine_field(offset_of!(AsmCoreMachine, frames_ptr)));
+        self.e.lsr_imm(true, X1, X0, MEMORY_FRAME_SHIFTS as u32);
+        self.e.load8_zx(X1, Mem::indexed(X3, X1));
+        self.e.cbz32(X1, slow);
+        self.e.alu_ri(true, IMM_SUB, X2, X2, 1);
+        self.e.lsr_imm(true, X2, X2, MEMORY_FRAME_SHIFTS as u32);
+        self.e.load8_zx(X2, Mem::indexed(X3, X2));
+        self.e.cbz32(X2, slow);
+        body(&mut self.e, X0, Mem::indexed(MEMORY, X0));
+        self.write(i.rd(), X0);
+        self.e.bind(resume);
+    }
+
+    fn store(
+        &mut self,
+        inst: Instruction,
+        pc: u64,
+        position: u64,
+        size: u32,
+        body: fn(&mut Emitter, Mem, u8),
+    ) {
+        let i = Stype(inst);
+        let resume = self.e.new_label();
+        let slow = self.cold(ColdPath::Execute {
+            inst,
+            pc,
+            position,
+            resume,
+        });
+        self.read(X0, i.rs1());
+        self.add_imm(true, X0, X0, i.immediate_s());
+        // The access must stay in one page
+        self.e.lsr_imm(true, X2, X0, RISCV_PAGE_SHIFTS as u32);
+        self.e.alu_ri(true, IMM_ADDS, X1, X0, size - 1);
+        self.e.b_cond(CC_HS, slow);
+        self.e.lsr_imm(true, X1, X1, RISCV_PAGE_SHIFTS as u32);
+        self.e.cmp_rr(X1, X2);
+        self.e.b_cond(CC_NE, slow);
+        self.e
+            .load64(X1, machine_field(offset_of!(AsmCoreMachine, flags_size)));
+        self.e.cmp_rr(X2, X1);
+        self.e.b_cond(CC_HS, slow);
+        // The page must be writable and already marked as dirty, which also
+        // means its frame has been initialized.
+        self.e
+            .load64(X3, machine_field(offset_of!(AsmCoreMachine, flags_ptr)));
+        self.e.load8_zx(X1, Mem::indexed(X3, X2));
+        self.e.mov_imm(X3, u64::from(FLAG_WXORX_BIT | FLAG_DIRTY));
+        self.e.alu_rr(true, ALU_AND, X1, X1, X3);
+        self.e.cmp_ri(X1, u32::from(FLAG_WRITABLE | FLAG_DIRTY));
+        self.e.b_cond(CC_NE, slow);
+        self.e
+            .load64(X3, machine_field(offset_of!(AsmCoreMachine, frames_ptr)));
+        self.e
+            .lsr_imm(true, X2, X2, MEMORY_FRAME_PAGE_SHIFTS as u32);
+        self.e.load8_zx(X2, Mem::indexed(X3, X2));
+        self.e.cbz32(X2, slow);
+        self.read(X1, i.rs2());
+        body(&mut self.e, Mem::indexed(MEMORY, X0), X1);
+        self.e.bind(resume);
+    }
+}
@@ -0,0 +1,497 @@
+// A minimal AArch64 code emitter covering exactly the instruction forms the
+// AOT translator needs. Memory operands are either a base register with an
+// unsigned, scaled offset or a base register plus an index register; the
+// emitted code is position independent.
+use std::collections::HashMap;
+
+pub const X0: u8 = 0;
+pub const X1: u8 = 1;
+pub const X2: u8 = 2;
+pub const X3: u8 = 3;
+pub const X16: u8 = 16;
+pub const X19: u8 = 19;
+pub const X20: u8 = 20;
+pub const X21: u8 = 21;
+pub const X22: u8 = 22;
+pub const X29: u8 = 29;
+pub const X30: u8 = 30;
+// Register number 31 is the stack pointer or the zero register, depending on
+// the instruction.
+pub const SP: u8 = 31;
+pub const XZR: u8 = 31;
+
+// Condition codes of B.cond and CSET
+pub const CC_EQ: u8 = 0x0;
+pub const CC_NE: u8 = 0x1;
+pub const CC_HS: u8 = 0x2;
+pub const CC_LO: u8 = 0x3;
+pub const CC_HI: u8 = 0x8;
+pub const CC_GE: u8 = 0xa;
+pub const CC_LT: u8 = 0xb;
+
+// Data processing (register) opcodes, in their 32-bit form
+pub const ALU_ADD: u32 = 0x0b00_0000;
+pub const ALU_ADDS: u32 = 0x2b00_0000;
+pub const ALU_SUB: u32 = 0x4b00_0000;
+pub const ALU_SUBS: u32 = 0x6b00_0000;
+pub const ALU_AND: u32 = 0x0a00_0000;
+pub const ALU_ORR: u32 = 0x2a00_0000;
+pub const ALU_EOR: u32 = 0x4a00_0000;
+pub const ALU_LSLV: u32 = 0x1ac0_2000;
+pub const ALU_LSRV: u32 = 0x1ac0_2400;
+pub const ALU_ASRV: u32 = 0x1ac0_2800;
+pub const ALU_MUL: u32 = 0x1b00_7c00;
+// Only exist in the 64-bit form
+pub const ALU_SMULH: u32 = 0x9b40_7c00;
+pub const ALU_UMULH: u32 = 0x9bc0_7c00;
+
+// Add / subtract (immediate) opcodes, in their 32-bit form
+pub const IMM_ADD: u32 = 0x1100_0000;
+pub const IMM_ADDS: u32 = 0x3100_0000;
+pub const IMM_SUB: u32 = 0x5100_0000;
+pub const IMM_SUBS: u32 = 0x7100_0000;
+
+// Bitfield move opcodes, in their 32-bit form
+const BFM_SIGNED: u32 = 0x1300_0000;
+const BFM_UNSIGNED: u32 = 0x5300_0000;
+
+const SF: u32 = 0x8000_0000;
+
+/// Memory operand in the form of [base + offset] or [base + index]
+#[derive(Debug, Clone, Copy)]
+pub struct Mem {
+    pub base: u8,
+    pub index: Option<u8>,
+    pub offset: u32,
+}
+
+impl Mem {
+    pub fn base(base: u8, offset: u32) -> Self {
+        Mem {
+            base,
+            index: None,
+            offset,
+        }
+    }
+
+    pub fn indexed(base: u8, index: u8) -> Self {
+        Mem {
+            base,
+            index: Some(index),
+            offset: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Label(usize);
+
+// Width in bits of the word offset of a branch instruction
+#[derive(Debug, Clone, Copy)]
+enum Fixup {
+    Imm26,
+    Imm19,
+    Imm14,
+}
+
+#[derive(Default)]
+pub struct Emitter {
+    code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    // Position of a branch instruction -> label it refers to
+    fixups: HashMap<usize, (Label, Fixup)>,
+}
+
+impl Emitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    pub fn bind(&mut self, label: Label) {
+        debug_assert!(self.labels[label.0].is_none());
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    pub fn label_offset(&self, label: Label) -> Option<usize> {
+        self.labels[label.0]
+    }
+
+    /// Resolves all branches and returns the final code. Conditional
+    /// branches only reach 1MB, the translator keeps them local.
+    pub fn finish(mut self) -> Vec<u8> {
+        for (position, (label, fixup)) in self.fixups.iter() {
+            let target = self.labels[label.0].expect("unbound label");
+            let relative = (target as i64 - *position as i64) >> 2;
+            let (bits, shift) = match fixup {
+                Fixup::Imm26 => (26, 0),
+                Fixup::Imm19 => (19, 5),
+                Fixup::Imm14 => (14, 5),
+            };
+            assert!(
+                relative >= -(1 << (bits - 1)) && relative < 1 << (bits - 1),
+                "branch out of range"
+            );
+            let field = ((relative as u32) & ((1 << bits) - 1)) << shift;
+            let mut word = [0u8; 4];
+            word.copy_from_slice(&self.code[*position..*position + 4]);
+            let word = u32::from_le_bytes(word) | field;
+            self.code[*position..*position + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        self.code
+    }
+
+    fn word(&mut self, word: u32) {
+        self.code.extend_from_slice(&word.to_le_bytes());
+    }
+
+    fn branch(&mut self, word: u32, label: Label, fixup: Fixup) {
+        self.fixups.insert(self.code.len(), (label, fixup));
+        self.word(word);
+    }
+
+    // Load / store with size being log2 of the access width, opc selects
+    // between store (0), zero extending load (1) and sign extending load to
+    // a 64-bit register (2).
+    fn mem_op(&mut self, size: u32, opc: u32, rt: u8, mem: Mem) {
+        let common = (size << 30) | (opc << 22) | (u32::from(mem.base) << 5) | u32::from(rt);
+        match mem.index {
+            Some(index) => self.word(0x3820_6800 | common | (u32::from(index) << 16)),
+            None => {
+                debug_assert!(mem.offset % (1 << size) == 0 && mem.offset >> size < 4096);
+                self.word(0x3900_0000 | common | ((mem.offset >> size) << 10));
+            }
+        }
+    }
+
+    pub fn ret(&mut self) {
+        self.word(0xd65f_03c0);
+    }
+
+    pub fn mov_rr(&mut self, dst: u8, src: u8) {
+        self.alu_rr(true, ALU_ORR, dst, XZR, src);
+    }
+
+    pub fn load64(&mut self, dst: u8, mem: Mem) {
+        self.mem_op(3, 1, dst, mem);
+    }
+
+    pub fn store64(&mut self, mem: Mem, src: u8) {
+        self.mem_op(3, 0, src, mem);
+    }
+
+    pub fn store32(&mut self, mem: Mem, src: u8) {
+        self.mem_op(2, 0, src, mem);
+    }
+
+    pub fn store16(&mut self, mem: Mem, src: u8) {
+        self.mem_op(1, 0, src, mem);
+    }
+
+    pub fn store8(&mut self, mem: Mem, src: u8) {
+        self.mem_op(0, 0, src, mem);
+    }
+
+    pub fn load32_zx(&mut self, dst: u8, mem: Mem) {
+        self.mem_op(2, 1, dst, mem);
+    }
+
+    pub fn load32_sx(&mut self, dst: u8, mem: Mem) {
+        self.mem_op(2, 2, dst, mem);
+    }
+
+    pub fn load16_zx(&mut self, dst: u8, mem: Mem) {
+        self.mem_op(1, 1, dst, mem);
+    }
+
+    pub fn load16_sx(&mut self, dst: u8, mem: Mem) {
+        self.mem_op(1, 2, dst, mem);
+    }
+
+    pub fn load8_zx(&mut self, dst: u8, mem: Mem) {
+        self.mem_op(0, 1, dst, mem);
+    }
+
+    pub fn load8_sx(&mut self, dst: u8, mem: Mem) {
+        self.mem_op(0, 2, dst, mem);
+    }
+
+    /// Stores a pair of registers at [base + offset], updating base first
+    pub fn stp_pre(&mut self, a: u8, b: u8, base: u8, offset: i32) {
+        self.pair(0xa980_0000, a, b, base, offset);
+    }
+
+    /// Loads a pair of registers from [base], then adds offset to base
+    pub fn ldp_post(&mut self, a: u8, b: u8, base: u8, offset: i32) {
+        self.pair(0xa8c0_0000, a, b, base, offset);
+    }
+
+    pub fn stp(&mut self, a: u8, b: u8, base: u8, offset: i32) {
+        self.pair(0xa900_0000, a, b, base, offset);
+    }
+
+    pub fn ldp(&mut self, a: u8, b: u8, base: u8, offset: i32) {
+        self.pair(0xa940_0000, a, b, base, offset);
+    }
+
+    fn pair(&mut self, opcode: u32, a: u8, b: u8, base: u8, offset: i32) {
+        debug_assert!(offset % 8 == 0 && (-512..512).contains(&offset));
+        self.word(
+            opcode
+                | ((((offset / 8) as u32) & 0x7f) << 15)
+                | (u32::from(b) << 10)
+                | (u32::from(base) << 5)
+                | u32::from(a),
+        );
+    }
+
+    /// Materializes a 64-bit constant with MOVZ or MOVN followed by MOVK
+    pub fn mov_imm(&mut self, dst: u8, imm: u64) {
+        let chunks: Vec<u32> = (0..4)
+            .map(|i| ((imm >> (i * 16)) & 0xffff) as u32)
+            .collect();
+        let zeros = chunks.iter().filter(|c| **c == 0).count();
+        let ones = chunks.iter().filter(|c| **c == 0xffff).count();
+        // Chunks equal to the filler of the first instruction are skipped
+        let (filler, first) = if ones > zeros {
+            (0xffff, 0x9280_0000)
+        } else {
+            (0, 0xd280_0000)
+        };
+        let start = chunks.iter().position(|c| *c != filler).unwrap_or(0);
+        let value = if filler == 0 {
+            chunks[start]
+        } else {
+            !chunks[start] & 0xffff
+        };
+        self.word(first | ((start as u32) << 21) | (value << 5) | u32::from(dst));
+        for (i, chunk) in chunks.iter().enumerate().skip(start + 1) {
+            if *chunk != filler {
+                self.word(0xf280_0000 | ((i as u32) << 21) | (chunk << 5) | u32::from(dst));
+            }
+        }
+    }
+
+    /// Three register data processing instruction, e.g. ALU_ADD, register
+    /// number 31 means the zero register.
+    pub fn alu_rr(&mut self, w: bool, opcode: u32, dst: u8, a: u8, b: u8) {
+        let sf = if w { SF } else { 0 };
+        self.word(opcode | sf | (u32::from(b) << 16) | (u32::from(a) << 5) | u32::from(dst));
+    }
+
+    /// Add or subtract an unsigned 12-bit immediate, e.g. IMM_ADD, register
+    /// number 31 means the stack pointer except for the destination of the
+    /// flag setting forms.
+    pub fn alu_ri(&mut self, w: bool, opcode: u32, dst: u8, src: u8, imm: u32) {
+        debug_assert!(imm < 4096);
+        let sf = if w { SF } else { 0 };
+        self.word(opcode | sf | (imm << 10) | (u32::from(src) << 5) | u32::from(dst));
+    }
+
+    pub fn cmp_rr(&mut self, a: u8, b: u8) {
+        self.alu_rr(true, ALU_SUBS, XZR, a, b);
+    }
+
+    pub fn cmp_ri(&mut self, a: u8, imm: u32) {
+        self.alu_ri(true, IMM_SUBS, XZR, a, imm);
+    }
+
+    /// Sets dst to 1 when the condition holds, 0 otherwise
+    pub fn cset(&mut self, dst: u8, cc: u8) {
+        self.word(0x9a9f_07e0 | (u32::from(cc ^ 1) << 12) | u32::from(dst));
+    }
+
+    pub fn sxtw(&mut self, dst: u8, src: u8) {
+        self.bitfield(true, BFM_SIGNED, dst, src, 0, 31);
+    }
+
+    pub fn uxtb(&mut self, dst: u8, src: u8) {
+        self.bitfield(false, BFM_UNSIGNED, dst, src, 0, 7);
+    }
+
+    pub fn lsl_imm(&mut self, w: bool, dst: u8, src: u8, shift: u32) {
+        let bits = if w { 64 } else { 32 };
+        self.bitfield(
+            w,
+            BFM_UNSIGNED,
+            dst,
+            src,
+            (bits - shift) % bits,
+            bits - 1 - shift,
+        );
+    }
+
+    pub fn lsr_imm(&mut self, w: bool, dst: u8, src: u8, shift: u32) {
+        let bits = if w { 64 } else { 32 };
+        self.bitfield(w, BFM_UNSIGNED, dst, src, shift, bits - 1);
+    }
+
+    pub fn asr_imm(&mut self, w: bool, dst: u8, src: u8, shift: u32) {
+        let bits = if w { 64 } else { 32 };
+        self.bitfield(w, BFM_SIGNED, dst, src, shift, bits - 1);
+    }
+
+    fn bitfield(&mut self, w: bool, opcode: u32, dst: u8, src: u8, immr: u32, imms: u32) {
+        // The N bit accompanies sf in the 64-bit form
+        let sf = if w { SF | 0x0040_0000 } else { 0 };
+        self.word(
+            opcode | sf | (immr << 16) | (imms << 10) | (u32::from(src) << 5) | u32::from(dst),
+        );
+    }
+
+    pub fn b(&mut self, label: Label) {
+        self.branch(0x1400_0000, label, Fixup::Imm26);
+    }
+
+    pub fn b_cond(&mut self, cc: u8, label: Label) {
+        self.branch(0x5400_0000 | u32::from(cc), label, Fixup::Imm19);
+    }
+
+    /// Branches when the low 32 bits of reg are zero
+    pub fn cbz32(&mut self, reg: u8, label: Label) {
+        self.branch(0x3400_0000 | u32::from(reg), label, Fixup::Imm19);
+    }
+
+    /// Branches when the low 32 bits of reg are not zero
+    pub fn cbnz32(&mut self, reg: u8, label: Label) {
+        self.branch(0x3500_0000 | u32::from(reg), label, Fixup::Imm19);
+    }
+
+    /// Branches when the given bit (0 - 31) of reg is set
+    pub fn tbnz(&mut self, reg: u8, bit: u32, label: Label) {
+        debug_assert!(bit < 32);
+        self.branch(
+            0x3700_0000 | (bit << 19) | u32::from(reg),
+            label,
+            Fixup::Imm14,
+        );
+    }
+
+    pub fn br(&mut self, reg: u8) {
+        self.word(0xd61f_0000 | (u32::from(reg) << 5));
+    }
+
+    pub fn blr(&mut self, reg: u8) {
+        self.word(0xd63f_0000 | (u32::from(reg) << 5));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(code: Vec<u8>) -> Vec<u32> {
+        code.chunks(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn test_encodings() {
+        let mut e = Emitter::new();
+        e.stp_pre(X29, X30, SP, -48);
+        e.alu_ri(true, IMM_ADD, X29, SP, 0);
+        e.stp(X19, X20, SP, 16);
+        e.ldp_post(X29, X30, SP, 48);
+        e.mov_rr(X20, X0);
+        e.load64(X19, Mem::base(X20, 8));
+        e.load32_zx(X1, Mem::indexed(X1, X2));
+        e.load8_sx(X0, Mem::indexed(X21, X0));
+        e.load32_sx(X0, Mem::base(X21, 4));
+        e.store16(Mem::indexed(X21, X0), X1);
+        e.store64(Mem::base(X19, 248), XZR);
+        e.alu_rr(true, ALU_SUB, X0, X0, X1);
+        e.alu_rr(false, ALU_LSLV, X0, X0, X1);
+        e.alu_rr(true, ALU_UMULH, X0, X0, X1);
+        e.alu_ri(true, IMM_ADDS, X2, X0, 8);
+        e.alu_ri(false, IMM_SUB, X0, X0, 2048);
+        e.cmp_rr(X0, X1);
+        e.cset(X0, CC_LT);
+        e.sxtw(X0, X0);
+        e.uxtb(X0, X0);
+        e.lsl_imm(true, X0, X0, 1);
+        e.lsr_imm(false, X0, X0, 5);
+        e.asr_imm(true, X0, X0, 63);
+        e.blr(X16);
+        e.br(X1);
+        e.ret();
+        assert_eq!(
+            words(e.finish()),
+            vec![
+                0xa9bd7bfd, // stp x29, x30, [sp, #-48]!
+                0x910003fd, // add x29, sp, #0
+                0xa90153f3, // stp x19, x20, [sp, #16]
+                0xa8c37bfd, // ldp x29, x30, [sp], #48
+                0xaa0003f4, // mov x20, x0
+                0xf9400693, // ldr x19, [x20, #8]
+                0xb8626821, // ldr w1, [x1, x2]
+                0x38a06aa0, // ldrsb x0, [x21, x0]
+                0xb98006a0, // ldrsw x0, [x21, #4]
+                0x78206aa1, // strh w1, [x21, x0]
+                0xf9007e7f, // str xzr, [x19, #248]
+                0xcb010000, // sub x0, x0, x1
+                0x1ac12000, // lsl w0, w0, w1
+                0x9bc17c00, // umulh x0, x0, x1
+                0xb1002002, // adds x2, x0, #8
+                0x51200000, // sub w0, w0, #2048
+                0xeb01001f, // cmp x0, x1
+                0x9a9fa7e0, // cset x0, lt
+                0x93407c00, // sxtw x0, w0
+                0x53001c00, // uxtb w0, w0
+                0xd37ff800, // lsl x0, x0, #1
+                0x53057c00, // lsr w0, w0, #5
+                0x937ffc00, // asr x0, x0, #63
+                0xd63f0200, // blr x16
+                0xd61f0020, // br x1
+                0xd65f03c0, // ret
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mov_imm() {
+        let encode = |imm: u64| {
+            let mut e = Emitter::new();
+            e.mov_imm(X0, imm);
+            words(e.finish())
+        };
+        // mov x0, #0
+        assert_eq!(encode(0), vec![0xd2800000]);
+        // mov x0, #-1
+        assert_eq!(encode(u64::MAX), vec![0x92800000]);
+        // mov x0, #-2
+        assert_eq!(encode(!1), vec![0x92800020]);
+        // mov x0, #0x10000; movk x0, #0x8000, lsl #48
+        assert_eq!(encode(0x8000_0000_0001_0000), vec![0xd2a00020, 0xf2f00000]);
+        // mov x0, #-0xa988; movk x0, #0xedcc, lsl #16
+        assert_eq!(encode(0xffff_ffff_edcc_5678), vec![0x929530e0, 0xf2bdb980]);
+    }
+
+    #[test]
+    fn test_label_fixups() {
+        let mut e = Emitter::new();
+        let start = e.new_label();
+        let end = e.new_label();
+        e.bind(start);
+        e.b_cond(CC_EQ, end);
+        e.cbz32(X2, end);
+        e.tbnz(X0, 0, end);
+        e.b(start);
+        e.bind(end);
+        e.ret();
+        assert_eq!(
+            words(e.finish()),
+            vec![
+                0x54000080, // b.eq end
+                0x34000062, // cbz w2, end
+                0x37000040, // tbnz w0, #0, end
+                0x17fffffd, // b start
+                0xd65f03c0, // ret
+            ]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use bytes::Bytes;
+
+use super::{AotCode, AotTrace};
+use crate::{
+    hash::blake2b_256,
+    instructions::{instruction_opcode_name, insts, Instruction},
+    machine::trace::TRACE_ITEM_LENGTH,
+    Error,
+};
+
+const MAGIC: &[u8; 8] = b"CKBVMAOT";
+// Bump whenever the layout of the file or the generated code changes, so
+// stale artifacts are recompiled instead of being loaded.
+const FORMAT_VERSION: u32 = 2;
+// Architecture tags, artifacts are only loaded on the architecture whose
+// tag they carry.
+#[cfg(target_arch = "x86_64")]
+const ARCH_X64: u8 = 1;
+#[cfg(target_arch = "aarch64")]
+const ARCH_AARCH64: u8 = 2;
+#[cfg(target_arch = "x86_64")]
+const ARCH: u8 = ARCH_X64;
+#[cfg(target_arch = "aarch64")]
+const ARCH: u8 = ARCH_AARCH64;
+
+fn blake2b(data: &[u8]) -> [u8; 32] {
+    blake2b_256(b"ckb-vm-aot", data)
+}
+
+/// Hash of a program binary, used as the key of the AOT cache.
+pub fn program_hash(program: &[u8]) -> [u8; 32] {
+    blake2b(program)
+}
+
+// Hash of the opcode numbers and names. Generated code embeds opcodes, so an
+// artifact built against a different opcode table must not be loaded even
+// when the crate version is the same, e.g. from a development build.
+fn opcode_table_hash() -> [u8; 32] {
+    let mut table = vec![];
+    for opcode in insts::MINIMAL_OPCODE..=insts::MAXIMUM_OPCODE {
+        table.write_u16::<LittleEndian>(opcode).unwrap();
+        table.extend_from_slice(instruction_opcode_name(opcode).as_bytes());
+        table.push(0);
+    }
+    blake2b_256(b"ckb-vm-aot-ops", &table)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{:02x}", b);
+        hex
+    })
+}
+
+fn corrupted(message: &str) -> Error {
+    Error::Aot(format!("corrupted artifact: {}", message))
+}
+
+impl AotCode {
+    /// Serializes the compiled code, the result is only valid on the
+    /// architecture it was compiled on.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend_from_slice(MAGIC);
+        buf.write_u32::<LittleEndian>(FORMAT_VERSION).unwrap();
+        let crate_version = env!("CARGO_PKG_VERSION").as_bytes();
+        buf.write_u32::<LittleEndian>(crate_version.len() as u32)
+            .unwrap();
+        buf.extend_from_slice(crate_version);
+        buf.extend_from_slice(&opcode_table_hash());
+        buf.write_u8(ARCH).unwrap();
+        buf.write_u8(self.isa).unwrap();
+        buf.write_u32::<LittleEndian>(self.version).unwrap();
+        buf.extend_from_slice(&self.program_hash);
+        buf.write_u32::<LittleEndian>(self.segments.len() as u32)
+            .unwrap();
+        for (addr, content) in &self.segments {
+            buf.write_u64::<LittleEndian>(*addr).unwrap();
+            buf.write_u64::<LittleEndian>(content.len() as u64).unwrap();
+            buf.extend_from_slice(content);
+        }
+        buf.write_u32::<LittleEndian>(self.traces.len() as u32)
+            .unwrap();
+        for (trace, offset) in self.traces.iter().zip(self.offsets.iter()) {
+            buf.write_u64::<LittleEndian>(trace.address).unwrap();
+            buf.write_u32::<LittleEndian>(*offset).unwrap();
+            buf.write_u8(trace.instructions.len() as u8).unwrap();
+            for instruction in &trace.instructions {
+                buf.write_u64::<LittleEndian>(*instruction).unwrap();
+            }
+        }
+        buf.write_u64::<LittleEndian>(self.code.len() as u64)
+            .unwrap();
+        buf.extend_from_slice(&self.code);
+        let checksum = blake2b(&buf);
+        buf.extend_from_slice(&checksum);
+        buf
+    }
+
+    /// Loads code serialized by to_bytes, all fields are validated so a
+    /// damaged or foreign file results in an error.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < MAGIC.len() + 32 {
+            return Err(corrupted("too short"));
+        }
+        let (body, checksum) = data.split_at(data.len() - 32);
+        if blake2b(body) != checksum {
+            return Err(corrupted("checksum mismatch"));
+        }
+        let mut reader = Cursor::new(body);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC || reader.read_u32::<LittleEndian>()? != FORMAT_VERSION {
+            return Err(corrupted("unknown format"));
+        }
+        let length = reader.read_u32::<LittleEndian>()? as usize;
+        if length > 64 {
+            return Err(corrupted("unknown format"));
+        }
+        let mut crate_version = vec![0; length];
+        reader.read_exact(&mut crate_version)?;
+        if crate_version != env!("CARGO_PKG_VERSION").as_bytes() {
+            return Err(Error::Aot(String::from("crate version mismatch")));
+        }
+        let mut opcode_hash = [0u8; 32];
+        reader.read_exact(&mut opcode_hash)?;
+        if opcode_hash != opcode_table_hash() {
+            return Err(Error::Aot(String::from("opcode table mismatch")));
+        }
+        if reader.read_u8()? != ARCH {
+            return Err(Error::Aot(String::from("unsupported architecture")));
+        }
+        let isa = reader.read_u8()?;
+        let version = reader.read_u32::<LittleEndian>()?;
+        let mut program_hash = [0u8; 32];
+        reader.read_exact(&mut program_hash)?;
+
+        let read_bytes = |reader: &mut Cursor<&[u8]>, length: u64| -> Result<Vec<u8>, Error> {
+            let remaining = body.len() as u64 - reader.position();
+            if length > remaining {
+                return Err(corrupted("truncated"));
+            }
+            let mut bytes = vec![0; length as usize];
+            reader.read_exact(&mut bytes)?;
+            Ok(bytes)
+        };
+        let segment_count = reader.read_u32::<LittleEndian>()?;
+        let mut segments = vec![];
+        let mut end = 0;
+        for _ in 0..segment_count {
+            let addr = reader.read_u64::<LittleEndian>()?;
+            let length = reader.read_u64::<LittleEndian>()?;
+            let content = read_bytes(&mut reader, length)?;
+            if addr < end {
+                return Err(corrupted("overlapping segments"));
+            }
+            end = addr
+                .checked_add(length)
+                .ok_or_else(|| corrupted("segment overflow"))?;
+            segments.push((addr, Bytes::from(content)));
+        }
+        let trace_count = reader.read_u32::<LittleEndian>()?;
+        let mut traces = vec![];
+        let mut offsets = vec![];
+        for _ in 0..trace_count {
+            let address = reader.read_u64::<LittleEndian>()?;
+            offsets.push(reader.read_u32::<LittleEndian>()?);
+            let length = reader.read_u8()? as usize;
+            if length == 0 || length > TRACE_ITEM_LENGTH {
+                return Err(corrupted("invalid trace"));
+            }
+            let mut instructions: Vec<Instruction> = Vec::with_capacity(length);
+            for _ in 0..length {
+                instructions.push(reader.read_u64::<LittleEndian>()?);
+            }
+            let in_segment = segments
+                .iter()
+                .any(|(addr, content)| address >= *addr && address - *addr < content.len() as u64);
+            if !in_segment || address & 1 != 0 {
+                return Err(corrupted("trace outside of segments"));
+            }
+            traces.push(AotTrace {
+                address,
+                instructions,
+            });
+        }
+        let code_length = reader.read_u64::<LittleEndian>()?;
+        let code = read_bytes(&mut reader, code_length)?;
+        if reader.position() != body.len() as u64 {
+            return Err(corrupted("trailing data"));
+        }
+        if offsets
+            .iter()
+            .any(|offset| *offset == 0 || *offset as usize >= code.len())
+        {
+            return Err(corrupted("invalid trace offset"));
+        }
+        Self::new(isa, version, program_hash, segments, traces, offsets, &code)
+    }
+}
+
+/// On-disk cache of compiled programs, each artifact is keyed by the hash of
+/// the program, the ISA, the VM version, the host architecture, the crate
+/// version and the opcode table.
+///
+/// Artifacts contain native code which is executed as is, the checksum only
+/// guards against damaged files, hence the directory must not be writable
+/// by untrusted users.
+pub struct AotCache {
+    dir: PathBuf,
+}
+
+impl AotCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self, program: &[u8], isa: u8, version: u32) -> PathBuf {
+        self.dir.join(format!(
+            "{}-{}-{}-{}-{}-{}.aot",
+            hex(&program_hash(program)),
+            isa,
+            version,
+            std::env::consts::ARCH,
+            env!("CARGO_PKG_VERSION"),
+            hex(&opcode_table_hash()[..8])
+        ))
+    }
+
+    /// Loads the cached code for a program, it is compiled and stored when
+    /// no valid artifact exists. Unreadable, corrupted or mismatched
+    /// artifacts are treated as cache misses.
+    pub fn load_or_compile(
+        &self,
+        program: &Bytes,
+        isa: u8,
+        version: u32,
+    ) -> Result<AotCode, Error> {
+        let path = self.path(program, isa, version);
+        if let Ok(data) = fs::read(&path) {
+            if let Ok(code) = AotCode::from_bytes(&data) {
+                if code.isa == isa
+                    && code.version == version
+                    && code.program_hash == program_hash(program)
+                {
+                    return Ok(code);
+                }
+            }
+        }
+        let code = AotCode::compile(program, isa, version)?;
+        self.store(&path, &code)?;
+        Ok(code)
+    }
+
+    // Writes to a temporary file first, so concurrent readers never observe
+    // a partially written artifact.
+    fn store(&self, path: &Path, code: &AotCode) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir)?;
+        let temp = path.with_extension(format!("tmp{}", std::process::id()));
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(&code.to_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, path)?;
+        Ok(())
+    }
+}
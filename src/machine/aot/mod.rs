@@ -0,0 +1,639 @@
+// Ahead-of-time compilation backend. The executable segments of a program
+// are split into traces exactly the way TraceMachine splits them, each trace
+// is translated into native code once, and the result can be cached on disk
+// (see AotCache). At runtime the native code works directly on the memory
+// and cycle counters of AsmCoreMachine, anything it cannot handle natively
+// is delegated to the interpreter, so the observable results, including
+// cycles and errors, are identical to TraceMachine.
+//
+// Native code is generated for x86-64 and AArch64, the translator of the
+// other architecture is only built for its unit tests.
+#[cfg(any(target_arch = "aarch64", test))]
+mod aarch64;
+#[cfg(any(target_arch = "aarch64", test))]
+mod aarch64_emitter;
+mod cache;
+#[cfg(any(target_arch = "x86_64", test))]
+mod x64;
+#[cfg(any(target_arch = "x86_64", test))]
+mod x64_emitter;
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use ckb_vm_definitions::{
+    asm::{AsmCoreMachine, RET_DYNAMIC_JUMP, RET_MAX_CYCLES_EXCEEDED, RET_PAUSE, RET_SLOWPATH},
+    instructions as insts, ISA_MOP, MEMORY_FRAME_SHIFTS, RISCV_PAGE_SHIFTS,
+};
+use memmap2::{Mmap, MmapMut};
+
+pub use cache::{program_hash, AotCache};
+
+use crate::{
+    cfg::ControlFlowGraph,
+    decoder::{build_decoder, InstDecoder},
+    elf::{parse_elf, ProgramMetadata},
+    instructions::{
+        execute, extract_opcode, instruction_length, is_basic_block_end_instruction, Instruction,
+        Rtype, Stype, Utype,
+    },
+    machine::{
+        trace::{calculate_slot, TRACE_ITEM_LENGTH, TRACE_SIZE},
+        StackEnv, VERSION0, VERSION2,
+    },
+    memory::{FLAG_EXECUTABLE, FLAG_WXORX_BIT},
+    CoreMachine, DefaultMachine, Error, Memory, SupportMachine,
+};
+
+// Returned by native code when an instruction delegated to the interpreter
+// failed, the error itself is kept in the context.
+const RET_EXECUTE_ERROR: u8 = 0x80;
+// Marks a trace slot no trace has been run in
+const EMPTY_SLOT: u64 = u64::MAX;
+
+#[cfg(target_arch = "x86_64")]
+type EntryFunc = unsafe extern "sysv64" fn(*mut Context, *const u8) -> u8;
+#[cfg(target_arch = "x86_64")]
+type ExecuteFunc = extern "sysv64" fn(*mut Context, Instruction, u64, u64) -> u8;
+#[cfg(target_arch = "aarch64")]
+type EntryFunc = unsafe extern "C" fn(*mut Context, *const u8) -> u8;
+#[cfg(target_arch = "aarch64")]
+type ExecuteFunc = extern "C" fn(*mut Context, Instruction, u64, u64) -> u8;
+
+/// Native code generated from a list of traces, `offsets` keeps the entry
+/// offset of each trace in `code`.
+struct Translation {
+    code: Vec<u8>,
+    offsets: Vec<u32>,
+}
+
+// Shared between the runtime and native code, which accesses the fields
+// by their offsets.
+#[repr(C)]
+struct Context {
+    machine: *mut AsmCoreMachine,
+    pause: *mut u8,
+    // Cycles of each trace under the cost model of the running machine
+    cycles: *const u64,
+    dispatch: *const u32,
+    dispatch_base: u64,
+    dispatch_length: u64,
+    code: *const u8,
+    slots: *mut u64,
+    execute: ExecuteFunc,
+    // The ECALL or EBREAK instruction to run when returning RET_SLOWPATH
+    instruction: Instruction,
+    default_machine: *mut DefaultMachine<Box<AsmCoreMachine>>,
+    // Trace and item index of the failed instruction, see aot_execute
+    position: u64,
+    error: Option<Error>,
+}
+
+#[cfg(target_arch = "x86_64")]
+extern "sysv64" fn aot_execute(
+    context: *mut Context,
+    inst: Instruction,
+    pc: u64,
+    position: u64,
+) -> u8 {
+    execute_for_native(context, inst, pc, position)
+}
+
+#[cfg(target_arch = "aarch64")]
+extern "C" fn aot_execute(context: *mut Context, inst: Instruction, pc: u64, position: u64) -> u8 {
+    execute_for_native(context, inst, pc, position)
+}
+
+// Runs one instruction on behalf of native code. Like TraceMachine, the pc
+// is committed even when the instruction fails.
+fn execute_for_native(context: *mut Context, inst: Instruction, pc: u64, position: u64) -> u8 {
+    let context = unsafe { &mut *context };
+    let machine = unsafe { &mut *context.default_machine };
+    machine.update_pc(pc);
+    machine.commit_pc();
+    match execute(inst, machine) {
+        Ok(()) => 0,
+        Err(e) => {
+            context.error = Some(e);
+            context.position = position;
+            RET_EXECUTE_ERROR
+        }
+    }
+}
+
+// Address and content of executable segments
+type Segments = Vec<(u64, Bytes)>;
+
+struct AotTrace {
+    address: u64,
+    instructions: Vec<Instruction>,
+}
+
+// Decodes the trace starting at pc, using the same rules as TraceMachine.
+fn decode_trace<D: InstDecoder, M: Memory>(
+    decoder: &mut D,
+    memory: &mut M,
+    pc: u64,
+) -> Result<Vec<Instruction>, Error> {
+    let mut instructions = Vec::with_capacity(TRACE_ITEM_LENGTH);
+    let mut current_pc = pc;
+    while instructions.len() < TRACE_ITEM_LENGTH {
+        let instruction = decoder.decode(memory, current_pc)?;
+        current_pc = current_pc.wrapping_add(u64::from(instruction_length(instruction)));
+        instructions.push(instruction);
+        if is_basic_block_end_instruction(instruction) {
+            break;
+        }
+    }
+    Ok(instructions)
+}
+
+// Statically known targets of a trace ending instruction
+fn static_targets(inst: Instruction, pc: u64) -> Vec<u64> {
+    let relative = |imm: i32| pc.wrapping_add(i64::from(imm) as u64);
+    match extract_opcode(inst) {
+        insts::OP_BEQ
+        | insts::OP_BNE
+        | insts::OP_BLT
+        | insts::OP_BGE
+        | insts::OP_BLTU
+        | insts::OP_BGEU => vec![relative(Stype(inst).immediate_s())],
+        insts::OP_SLT_BNEZ | insts::OP_SLT_BEQZ | insts::OP_SLTU_BNEZ | insts::OP_SLTU_BEQZ => {
+            vec![relative(Rtype(inst).immediate_s())]
+        }
+        insts::OP_JAL => vec![relative(Utype(inst).immediate_s())],
+        insts::OP_FAR_JUMP_REL => vec![relative(Utype(inst).immediate_s()) & !1],
+        insts::OP_FAR_JUMP_ABS => vec![i64::from(Utype(inst).immediate_s()) as u64 & !1],
+        _ => vec![],
+    }
+}
+
+// Loads the executable segments of a program and decodes every trace
+// reachable from the entry.
+fn find_traces(program: &Bytes, isa: u8, version: u32) -> Result<(Segments, Vec<AotTrace>), Error> {
+    let metadata = parse_elf::<u64>(program, version)?;
+    let mut machine = AsmCoreMachine::new(isa, version, u64::MAX);
+    machine.load_binary(program, &metadata, true)?;
+    let mut segments = vec![];
+    for action in &metadata.actions {
+        if action.flags & FLAG_WXORX_BIT == FLAG_EXECUTABLE {
+            segments.push((action.addr, machine.load_bytes(action.addr, action.size)?));
+        }
+    }
+    let executable = |pc: u64| {
+        pc & 1 == 0
+            && segments
+                .iter()
+                .any(|(addr, content)| pc >= *addr && pc - *addr < content.len() as u64)
+    };
+
+    // Besides the entry, every basic block found by the static analysis
+    // starts a trace, so most indirect jump targets have native code.
+    let mut pending = VecDeque::from([metadata.entry]);
+    if let Ok(cfg) = ControlFlowGraph::build::<u64>(program, isa, version) {
+        pending.extend(cfg.blocks.keys());
+    }
+    let mut decoder = build_decoder::<u64>(isa, version);
+    let mut visited = HashSet::new();
+    let mut traces = vec![];
+    while let Some(pc) = pending.pop_front() {
+        if !executable(pc) || !visited.insert(pc) {
+            continue;
+        }
+        // Traces failing to decode are left to the interpreter, which
+        // reports the error at runtime.
+        let instructions = match decode_trace(&mut decoder, &mut machine, pc) {
+            Ok(instructions) => instructions,
+            Err(_) => continue,
+        };
+        let mut current_pc = pc;
+        for instruction in &instructions {
+            pending.extend(static_targets(*instruction, current_pc));
+            current_pc = current_pc.wrapping_add(u64::from(instruction_length(*instruction)));
+        }
+        pending.push_back(current_pc);
+        traces.push(AotTrace {
+            address: pc,
+            instructions,
+        });
+    }
+    traces.sort_by_key(|trace| trace.address);
+    Ok((segments, traces))
+}
+
+/// Native code compiled from one program for a specific ISA and version.
+pub struct AotCode {
+    isa: u8,
+    version: u32,
+    program_hash: [u8; 32],
+    // Address and content of each executable segment the code was compiled
+    // from, the code is only used when the machine memory has the same
+    // content.
+    segments: Vec<(u64, Bytes)>,
+    traces: Vec<AotTrace>,
+    offsets: Vec<u32>,
+    code: Mmap,
+    // Maps (pc - dispatch_base) / 2 to the offset of the trace starting at
+    // pc, 0 means there is no trace.
+    dispatch_base: u64,
+    dispatch: Vec<u32>,
+}
+
+impl AotCode {
+    /// Compiles the executable segments of an ELF program.
+    pub fn compile(program: &Bytes, isa: u8, version: u32) -> Result<Self, Error> {
+        if isa & ISA_MOP != 0 && version == VERSION0 {
+            return Err(Error::InvalidVersion);
+        }
+        let (segments, traces) = find_traces(program, isa, version)?;
+        #[cfg(target_arch = "x86_64")]
+        let translation = x64::translate(&traces, version);
+        #[cfg(target_arch = "aarch64")]
+        let translation = aarch64::translate(&traces, version);
+        Self::new(
+            isa,
+            version,
+            program_hash(program),
+            segments,
+            traces,
+            translation.offsets,
+            &translation.code,
+        )
+    }
+
+    fn new(
+        isa: u8,
+        version: u32,
+        program_hash: [u8; 32],
+        segments: Vec<(u64, Bytes)>,
+        traces: Vec<AotTrace>,
+        offsets: Vec<u32>,
+        code: &[u8],
+    ) -> Result<Self, Error> {
+        let mut buffer = MmapMut::map_anon(code.len().max(1))?;
+        buffer[..code.len()].copy_from_slice(code);
+        let code = buffer.make_exec()?;
+        #[cfg(target_arch = "aarch64")]
+        flush_instruction_cache(&code);
+
+        let dispatch_base = segments.iter().map(|(addr, _)| *addr).min().unwrap_or(0);
+        let dispatch_end = segments
+            .iter()
+            .map(|(addr, content)| addr + content.len() as u64)
+            .max()
+            .unwrap_or(0);
+        let mut dispatch = vec![0; ((dispatch_end - dispatch_base) / 2) as usize];
+        for (trace, offset) in traces.iter().zip(offsets.iter()) {
+            dispatch[((trace.address - dispatch_base) / 2) as usize] = *offset;
+        }
+        Ok(Self {
+            isa,
+            version,
+            program_hash,
+            segments,
+            traces,
+            offsets,
+            code,
+            dispatch_base,
+            dispatch,
+        })
+    }
+
+    pub fn isa(&self) -> u8 {
+        self.isa
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn program_hash(&self) -> &[u8; 32] {
+        &self.program_hash
+    }
+
+    /// Number of traces compiled into native code.
+    pub fn trace_count(&self) -> usize {
+        self.traces.len()
+    }
+
+    fn lookup(&self, pc: u64) -> Option<u32> {
+        let index = pc.wrapping_sub(self.dispatch_base);
+        if index & 1 != 0 {
+            return None;
+        }
+        match self.dispatch.get((index / 2) as usize) {
+            Some(offset) if *offset != 0 => Some(*offset),
+            _ => None,
+        }
+    }
+
+    // Checks that the machine holds exactly the executable memory this code
+    // was compiled from. Memory is only inspected, never initialized, so a
+    // failed check leaves no trace in the machine.
+    fn matches(&self, machine: &AsmCoreMachine) -> bool {
+        if machine.isa != self.isa || machine.version != self.version {
+            return false;
+        }
+        self.segments.iter().all(|(addr, content)| {
+            let end = match addr.checked_add(content.len() as u64) {
+                Some(end) if end <= machine.memory_size && !content.is_empty() => end,
+                _ => return content.is_empty(),
+            };
+            let pages = (addr >> RISCV_PAGE_SHIFTS)..=((end - 1) >> RISCV_PAGE_SHIFTS);
+            let flags =
+                machine.cast_ptr_to_slice(machine.flags_ptr, 0, machine.flags_size as usize);
+            let frames =
+                machine.cast_ptr_to_slice(machine.frames_ptr, 0, machine.frames_size as usize);
+            pages.into_iter().all(|page| {
+                flags[page as usize] & FLAG_WXORX_BIT == FLAG_EXECUTABLE
+                    && frames[((page << RISCV_PAGE_SHIFTS) >> MEMORY_FRAME_SHIFTS) as usize] != 0
+            }) && machine.cast_ptr_to_slice(machine.memory_ptr, *addr as usize, content.len())
+                == &content[..]
+        })
+    }
+}
+
+// AArch64 does not keep the instruction cache coherent with data writes, the
+// freshly written code must be cleaned to the point of unification and the
+// stale instructions invalidated before it runs.
+#[cfg(all(target_arch = "aarch64", target_os = "macos"))]
+fn flush_instruction_cache(code: &[u8]) {
+    extern "C" {
+        fn sys_icache_invalidate(start: *mut std::ffi::c_void, len: usize);
+    }
+    unsafe { sys_icache_invalidate(code.as_ptr() as *mut _, code.len()) };
+}
+
+#[cfg(all(target_arch = "aarch64", not(target_os = "macos")))]
+fn flush_instruction_cache(code: &[u8]) {
+    use std::arch::asm;
+
+    let ctr: u64;
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack)) };
+    let data_line = 4usize << ((ctr >> 16) & 0xf);
+    let instruction_line = 4usize << (ctr & 0xf);
+    let start = code.as_ptr() as usize;
+    let end = start + code.len();
+    unsafe {
+        let mut addr = start & !(data_line - 1);
+        while addr < end {
+            asm!("dc cvau, {}", in(reg) addr, options(nostack));
+            addr += data_line;
+        }
+        asm!("dsb ish", options(nostack));
+        let mut addr = start & !(instruction_line - 1);
+        while addr < end {
+            asm!("ic ivau, {}", in(reg) addr, options(nostack));
+            addr += instruction_line;
+        }
+        asm!("dsb ish", "isb", options(nostack));
+    }
+}
+
+pub struct AotMachine {
+    pub machine: DefaultMachine<Box<AsmCoreMachine>>,
+    pub code: Arc<AotCode>,
+    // Address of the last trace run in each TraceMachine slot, only kept
+    // before VERSION2, see interpret_trace
+    slots: Vec<u64>,
+}
+
+impl AotMachine {
+    pub fn new(machine: DefaultMachine<Box<AsmCoreMachine>>, code: Arc<AotCode>) -> Self {
+        let slots = if machine.version() < VERSION2 {
+            vec![EMPTY_SLOT; TRACE_SIZE]
+        } else {
+            vec![]
+        };
+        Self {
+            machine,
+            code,
+            slots,
+        }
+    }
+
+    pub fn set_max_cycles(&mut self, cycles: u64) {
+        self.machine.inner.max_cycles = cycles;
+    }
+
+    pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
+        self.machine.load_program(program, args)
+    }
+
+    pub fn load_program_with_metadata(
+        &mut self,
+        program: &Bytes,
+        metadata: &ProgramMetadata,
+        args: &[Bytes],
+    ) -> Result<u64, Error> {
+        self.machine
+            .load_program_with_metadata(program, metadata, args)
+    }
+
+    pub fn load_program_with_env(
+        &mut self,
+        program: &Bytes,
+        args: &[Bytes],
+        env: &StackEnv,
+    ) -> Result<u64, Error> {
+        self.machine.load_program_with_env(program, args, env)
+    }
+
+    pub fn load_program_with_metadata_and_env(
+        &mut self,
+        program: &Bytes,
+        metadata: &ProgramMetadata,
+        args: &[Bytes],
+        env: &StackEnv,
+    ) -> Result<u64, Error> {
+        self.machine
+            .load_program_with_metadata_and_env(program, metadata, args, env)
+    }
+
+    pub fn run(&mut self) -> Result<i8, Error> {
+        if self.machine.isa() & ISA_MOP != 0 && self.machine.version() == VERSION0 {
+            return Err(Error::InvalidVersion);
+        }
+        if self.machine.xlen() != 64 {
+            return Err(Error::Aot(String::from("unsupported xlen")));
+        }
+        let mut decoder = build_decoder::<u64>(self.machine.isa(), self.machine.version());
+        let cycles: Vec<u64> = self
+            .code
+            .traces
+            .iter()
+            .map(|trace| self.trace_cycles(&trace.instructions))
+            .collect();
+        let mut native = self.code.matches(&self.machine.inner);
+        self.machine.set_running(true);
+        while self.machine.running() {
+            if self.machine.pause.has_interrupted() {
+                self.machine.pause.free();
+                return Err(Error::Pause);
+            }
+            if self.machine.reset_signal() {
+                decoder.reset_instructions_cache()?;
+                self.slots.fill(EMPTY_SLOT);
+                native = self.code.matches(&self.machine.inner);
+            }
+            let offset = if native {
+                self.code.lookup(*self.machine.pc())
+            } else {
+                None
+            };
+            match offset {
+                Some(offset) => self.run_native(offset, &cycles, &mut decoder)?,
+                None => self.interpret_trace(&mut decoder)?,
+            }
+        }
+        Ok(self.machine.exit_code())
+    }
+
+    fn trace_cycles(&self, instructions: &[Instruction]) -> u64 {
+        instructions.iter().fold(0u64, |sum, instruction| {
+            sum.saturating_add(self.machine.instruction_cycle_func()(*instruction))
+        })
+    }
+
+    fn run_native<D: InstDecoder>(
+        &mut self,
+        offset: u32,
+        cycles: &[u64],
+        decoder: &mut D,
+    ) -> Result<(), Error> {
+        let code = Arc::clone(&self.code);
+        let default_machine = &mut self.machine as *mut DefaultMachine<Box<AsmCoreMachine>>;
+        let mut context = Context {
+            machine: unsafe { &mut **(*default_machine).inner_mut() },
+            pause: self.machine.pause.get_raw_ptr(),
+            cycles: cycles.as_ptr(),
+            dispatch: code.dispatch.as_ptr(),
+            dispatch_base: code.dispatch_base,
+            dispatch_length: code.dispatch.len() as u64 * 2,
+            code: code.code.as_ptr(),
+            slots: self.slots.as_mut_ptr(),
+            execute: aot_execute,
+            instruction: 0,
+            default_machine,
+            position: 0,
+            error: None,
+        };
+        let result = unsafe {
+            let entry: EntryFunc = std::mem::transmute(code.code.as_ptr());
+            entry(&mut context, code.code.as_ptr().add(offset as usize))
+        };
+        match result {
+            RET_DYNAMIC_JUMP => (),
+            // Cycles of the trace do not fit, interpreting it finds out which
+            // instruction exceeds the limit.
+            RET_MAX_CYCLES_EXCEEDED => self.interpret_trace(decoder)?,
+            RET_SLOWPATH => execute(context.instruction, &mut self.machine)?,
+            RET_PAUSE => {
+                self.machine.pause.free();
+                return Err(Error::Pause);
+            }
+            RET_EXECUTE_ERROR => {
+                // Only charge the cycles up to and including the failed
+                // instruction, as TraceMachine does.
+                let trace_index = (context.position >> 8) as usize;
+                let item = (context.position & 0xff) as usize;
+                let instructions = &code.traces[trace_index].instructions;
+                let charged = self.machine.cycles().wrapping_sub(cycles[trace_index]);
+                let executed = self.trace_cycles(&instructions[..=item]);
+                self.machine.set_cycles(charged.wrapping_add(executed));
+                return Err(context.error.take().unwrap_or(Error::Asm(result)));
+            }
+            _ => return Err(Error::Asm(result)),
+        }
+        Ok(())
+    }
+
+    fn interpret_trace<D: InstDecoder>(&mut self, decoder: &mut D) -> Result<(), Error> {
+        let pc = *self.machine.pc();
+        // This is to replicate a bug in x64 VM: before VERSION2, TraceMachine
+        // runs the trace cached in the slot of pc as long as its address
+        // matches the lower 32 bits of pc.
+        let slot = calculate_slot(pc);
+        let aliased = !self.slots.is_empty() && self.slots[slot] == pc as u32 as u64;
+        let address = if aliased { pc as u32 as u64 } else { pc };
+        let instructions = decode_trace(decoder, self.machine.memory_mut(), address)?;
+        if !self.slots.is_empty() && !aliased {
+            self.slots[slot] = pc;
+        }
+        for instruction in instructions {
+            let cycles = self.machine.instruction_cycle_func()(instruction);
+            self.machine.add_cycles(cycles)?;
+            execute(instruction, &mut self.machine)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::assemble_elf, machine::VERSION1, ISA_B, ISA_IMC};
+
+    const SOURCE: &str = r#"
+    .text
+    .globl _start
+_start:
+    la s0, buffer
+    li s1, 0
+    li a0, 0x123456789abcdef
+loop:
+    add t0, a0, s1
+    sub t1, a0, s1
+    sll t2, t0, s1
+    sra t3, t1, s1
+    sltu t4, t1, t0
+    mulh t5, t0, t1
+    addw t6, t0, t1
+    sraw a1, t6, s1
+    addiw a2, a1, -2000
+    slli a3, a2, 63
+    srliw a4, a2, 31
+    slti a5, a4, -3
+    andi a6, a5, 0x7f0
+    lui a7, 0xfffff
+    auipc t0, 0x12
+    rol t1, t0, s1
+    sb a0, 0(s0)
+    sd a0, 8(s0)
+    lh t2, 2(s0)
+    lwu t3, 4(s0)
+    jal ra, next
+    addi s1, s1, 1
+    li t0, 40
+    bltu s1, t0, loop
+    li a7, 93
+    ecall
+next:
+    jalr zero, 0(ra)
+    .data
+buffer:
+    .dword 0, 0
+"#;
+
+    // Both translators run on any host, every branch must resolve in range
+    // and every trace must have an entry in the code.
+    #[test]
+    fn test_translate_all_architectures() {
+        let program = assemble_elf::<u64>(SOURCE).unwrap();
+        for version in [VERSION1, VERSION2] {
+            let (_, traces) = find_traces(&program, ISA_IMC | ISA_B, version).unwrap();
+            assert!(traces.len() > 2);
+            let aarch64 = aarch64::translate(&traces, version);
+            assert_eq!(aarch64.code.len() % 4, 0);
+            assert!(aarch64.offsets.iter().all(|offset| offset % 4 == 0));
+            for translation in [x64::translate(&traces, version), aarch64] {
+                assert_eq!(translation.offsets.len(), traces.len());
+                assert!(translation
+                    .offsets
+                    .iter()
+                    .all(|offset| *offset > 0 && (*offset as usize) < translation.code.len()));
+            }
+        }
+    }
+}
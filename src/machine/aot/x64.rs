@@ -0,0 +1,636 @@
+use std::collections::HashMap;
+use std::mem::offset_of;
+
+use ckb_vm_definitions::{
+    asm::{AsmCoreMachine, RET_DYNAMIC_JUMP, RET_MAX_CYCLES_EXCEEDED, RET_PAUSE, RET_SLOWPATH},
+    instructions as insts,
+    registers::RA,
+    MEMORY_FRAME_PAGE_SHIFTS, MEMORY_FRAME_SHIFTS, RISCV_PAGE_SHIFTS,
+};
+
+use super::{
+    x64_emitter::{
+        Emitter, Label, Mem, ALU_ADD, ALU_AND, ALU_CMP, ALU_OR, ALU_SUB, ALU_XOR, CC_A, CC_AE,
+        CC_B, CC_E, CC_GE, CC_L, CC_NE, R12, R13, R14, R15, RAX, RBP, RBX, RCX, RDI, RDX, RSI, RSP,
+        SHIFT_SAR, SHIFT_SHL, SHIFT_SHR,
+    },
+    AotTrace, Context, Translation,
+};
+use crate::machine::{trace::calculate_slot, VERSION2};
+use crate::{
+    instructions::{
+        extract_opcode, instruction_length, is_basic_block_end_instruction, Instruction, Itype,
+        Rtype, Stype, Utype,
+    },
+    memory::{FLAG_DIRTY, FLAG_WRITABLE, FLAG_WXORX_BIT},
+};
+
+// The generated code uses the following registers:
+// * rbx: pointer to AsmCoreMachine
+// * r12: pointer to Context
+// * r13: pointer to the start of VM memory
+// * rax, rcx, rdx, rsi: scratch registers
+// All RISC-V registers are kept in AsmCoreMachine, each instruction loads its
+// operands and writes back its result, so any exit point leaves the machine
+// in a consistent state.
+const MACHINE: u8 = RBX;
+const CONTEXT: u8 = R12;
+const MEMORY: u8 = R13;
+
+fn register(index: usize) -> Mem {
+    Mem::base(
+        MACHINE,
+        (offset_of!(AsmCoreMachine, registers) + index * 8) as i32,
+    )
+}
+
+fn machine_field(offset: usize) -> Mem {
+    Mem::base(MACHINE, offset as i32)
+}
+
+fn context_field(offset: usize) -> Mem {
+    Mem::base(CONTEXT, offset as i32)
+}
+
+// Slow paths are emitted out of line after each trace body
+enum ColdPath {
+    Exit {
+        pc: u64,
+        code: u8,
+    },
+    Execute {
+        inst: Instruction,
+        pc: u64,
+        position: u64,
+        resume: Label,
+    },
+}
+
+struct Translator<'a> {
+    e: Emitter,
+    traces: &'a [AotTrace],
+    labels: Vec<Label>,
+    index: HashMap<u64, usize>,
+    epilogue: Label,
+    dispatch: Label,
+    cold: Vec<(Label, ColdPath)>,
+    track_slots: bool,
+}
+
+pub fn translate(traces: &[AotTrace], version: u32) -> Translation {
+    let mut e = Emitter::new();
+    let labels = traces.iter().map(|_| e.new_label()).collect();
+    let epilogue = e.new_label();
+    let dispatch = e.new_label();
+    let mut t = Translator {
+        e,
+        traces,
+        labels,
+        index: traces
+            .iter()
+            .enumerate()
+            .map(|(i, trace)| (trace.address, i))
+            .collect(),
+        epilogue,
+        dispatch,
+        cold: vec![],
+        track_slots: version < VERSION2,
+    };
+    t.emit_prologue();
+    for i in 0..traces.len() {
+        t.emit_trace(i);
+    }
+    let offsets = t
+        .labels
+        .iter()
+        .map(|label| t.e.label_offset(*label).unwrap() as u32)
+        .collect();
+    Translation {
+        code: t.e.finish(),
+        offsets,
+    }
+}
+
+impl<'a> Translator<'a> {
+    // Entry point at offset 0:
+    // extern "sysv64" fn(context: *mut Context, target: *const u8) -> u8
+    fn emit_prologue(&mut self) {
+        let e = &mut self.e;
+        for reg in [RBP, RBX, R12, R13, R14, R15] {
+            e.push(reg);
+        }
+        // Keep the stack 16-byte aligned for helper calls
+        e.alu_ri(true, ALU_SUB, RSP, 8);
+        e.mov_rr(CONTEXT, RDI);
+        e.load64(MACHINE, context_field(offset_of!(Context, machine)));
+        e.load64(
+            MEMORY,
+            machine_field(offset_of!(AsmCoreMachine, memory_ptr)),
+        );
+        e.jmp_reg(RSI);
+
+        e.bind(self.epilogue);
+        e.alu_ri(true, ALU_ADD, RSP, 8);
+        for reg in [R15, R14, R13, R12, RBX, RBP] {
+            e.pop(reg);
+        }
+        e.ret();
+
+        // Looks up the trace starting at the address kept in rax, falls back
+        // to the runtime when no native code exists for the address.
+        let miss = e.new_label();
+        e.bind(self.dispatch);
+        e.store64(machine_field(offset_of!(AsmCoreMachine, pc)), RAX);
+        e.alu_rm(
+            true,
+            0x29,
+            RAX,
+            context_field(offset_of!(Context, dispatch_base)),
+        );
+        e.alu_rm(
+            true,
+            0x39,
+            RAX,
+            context_field(offset_of!(Context, dispatch_length)),
+        );
+        e.jcc(CC_AE, miss);
+        e.mov_imm(RCX, 1);
+        e.test_rr(false, RAX, RCX);
+        e.jcc(CC_NE, miss);
+        e.load64(RDX, context_field(offset_of!(Context, dispatch)));
+        // Each 2-byte slot maps to a 4-byte offset
+        e.load32_zx(RDX, Mem::indexed(RDX, RAX, 2, 0));
+        e.test_rr(false, RDX, RDX);
+        e.jcc(CC_E, miss);
+        e.alu_rm(true, 0x01, RDX, context_field(offset_of!(Context, code)));
+        e.jmp_reg(RDX);
+        e.bind(miss);
+        e.mov_imm(RAX, u64::from(RET_DYNAMIC_JUMP));
+        e.jmp(self.epilogue);
+    }
+
+    fn cold(&mut self, path: ColdPath) -> Label {
+        let label = self.e.new_label();
+        self.cold.push((label, path));
+        label
+    }
+
+    fn exit(&mut self, pc: u64, code: u8) -> Label {
+        self.cold(ColdPath::Exit { pc, code })
+    }
+
+    fn store_pc(&mut self, pc: u64) {
+        let mem = machine_field(offset_of!(AsmCoreMachine, pc));
+        if pc <= i32::MAX as u64 {
+            self.e.store64_imm(mem, pc as i32);
+        } else {
+            self.e.mov_imm(RAX, pc);
+            self.e.store64(mem, RAX);
+        }
+    }
+
+    // Continues execution at a statically known address
+    fn goto(&mut self, pc: u64) {
+        match self.index.get(&pc) {
+            Some(i) => {
+                let label = self.labels[*i];
+                self.e.jmp(label);
+            }
+            None => {
+                self.store_pc(pc);
+                self.e.mov_imm(RAX, u64::from(RET_DYNAMIC_JUMP));
+                self.e.jmp(self.epilogue);
+            }
+        }
+    }
+
+    fn read(&mut self, reg: u8, index: usize) {
+        if index == 0 {
+            self.e.zero(reg);
+        } else {
+            self.e.load64(reg, register(index));
+        }
+    }
+
+    fn write(&mut self, index: usize, reg: u8) {
+        if index != 0 {
+            self.e.store64(register(index), reg);
+        }
+    }
+
+    fn emit_trace(&mut self, trace_index: usize) {
+        let trace = &self.traces[trace_index];
+        let address = trace.address;
+        self.e.bind(self.labels[trace_index]);
+
+        let pause = self.exit(address, RET_PAUSE);
+        self.e
+            .load64(RAX, context_field(offset_of!(Context, pause)));
+        self.e.cmp_mem8_imm(Mem::base(RAX, 0), 0);
+        self.e.jcc(CC_NE, pause);
+
+        // Before VERSION2, TraceMachine matches a trace by the lower 32 bits
+        // of pc, the runtime needs to know the last trace run in each slot
+        // to replicate that.
+        if self.track_slots {
+            self.e
+                .load64(RCX, context_field(offset_of!(Context, slots)));
+            self.e.mov_imm(RAX, address);
+            self.e
+                .store64(Mem::base(RCX, (calculate_slot(address) * 8) as i32), RAX);
+        }
+
+        // Cycles of the whole trace are charged upfront. When they do not fit,
+        // the runtime interprets the trace so the error fires on exactly the
+        // same instruction as in TraceMachine.
+        let interpret = self.exit(address, RET_MAX_CYCLES_EXCEEDED);
+        self.e
+            .load64(RAX, context_field(offset_of!(Context, cycles)));
+        self.e.load64(RAX, Mem::base(RAX, (trace_index * 8) as i32));
+        self.e.alu_rm(
+            true,
+            0x01,
+            RAX,
+            machine_field(offset_of!(AsmCoreMachine, cycles)),
+        );
+        self.e.jcc(CC_B, interpret);
+        self.e.alu_rm(
+            true,
+            0x39,
+            RAX,
+            machine_field(offset_of!(AsmCoreMachine, max_cycles)),
+        );
+        self.e.jcc(CC_A, interpret);
+        self.e
+            .store64(machine_field(offset_of!(AsmCoreMachine, cycles)), RAX);
+
+        let mut pc = address;
+        let mut ended = false;
+        for (item, inst) in trace.instructions.iter().enumerate() {
+            let position = ((trace_index as u64) << 8) | item as u64;
+            self.emit_instruction(*inst, pc, position);
+            // AUIPC ends a trace but falls through to the next instruction
+            ended =
+                is_basic_block_end_instruction(*inst) && extract_opcode(*inst) != insts::OP_AUIPC;
+            pc = pc.wrapping_add(u64::from(instruction_length(*inst)));
+        }
+        if !ended {
+            self.goto(pc);
+        }
+
+        for (label, path) in std::mem::take(&mut self.cold) {
+            self.e.bind(label);
+            match path {
+                ColdPath::Exit { pc, code } => {
+                    self.store_pc(pc);
+                    self.e.mov_imm(RAX, u64::from(code));
+                    self.e.jmp(self.epilogue);
+                }
+                ColdPath::Execute {
+                    inst,
+                    pc,
+                    position,
+                    resume,
+                } => {
+                    self.emit_execute(inst, pc, position);
+                    self.e.jmp(resume);
+                }
+            }
+        }
+    }
+
+    // Runs one instruction via the interpreter, exits when it fails.
+    fn emit_execute(&mut self, inst: Instruction, pc: u64, position: u64) {
+        self.e.mov_rr(RDI, CONTEXT);
+        self.e.mov_imm(RSI, inst);
+        self.e.mov_imm(RDX, pc);
+        self.e.mov_imm(RCX, position);
+        self.e.call_mem(context_field(offset_of!(Context, execute)));
+        self.e.alu_ri(false, ALU_AND, RAX, 0xff);
+        self.e.jcc(CC_NE, self.epilogue);
+    }
+
+    fn emit_instruction(&mut self, inst: Instruction, pc: u64, position: u64) {
+        let op = extract_opcode(inst);
+        let length = u64::from(instruction_length(inst));
+        match op {
+            insts::OP_ADD => self.rtype(inst, true, |e| e.alu_rr(true, 0x01, RAX, RCX)),
+            insts::OP_SUB => self.rtype(inst, true, |e| e.alu_rr(true, 0x29, RAX, RCX)),
+            insts::OP_AND => self.rtype(inst, true, |e| e.alu_rr(true, 0x21, RAX, RCX)),
+            insts::OP_OR => self.rtype(inst, true, |e| e.alu_rr(true, 0x09, RAX, RCX)),
+            insts::OP_XOR => self.rtype(inst, true, |e| e.alu_rr(true, 0x31, RAX, RCX)),
+            insts::OP_SLL => self.rtype(inst, true, |e| e.shift_cl(true, SHIFT_SHL, RAX)),
+            insts::OP_SRL => self.rtype(inst, true, |e| e.shift_cl(true, SHIFT_SHR, RAX)),
+            insts::OP_SRA => self.rtype(inst, true, |e| e.shift_cl(true, SHIFT_SAR, RAX)),
+            insts::OP_SLT => self.rtype(inst, true, |e| {
+                e.alu_rr(true, 0x39, RAX, RCX);
+                e.setcc_rax(CC_L);
+            }),
+            insts::OP_SLTU => self.rtype(inst, true, |e| {
+                e.alu_rr(true, 0x39, RAX, RCX);
+                e.setcc_rax(CC_B);
+            }),
+            insts::OP_MUL => self.rtype(inst, true, |e| e.imul_rr(true, RAX, RCX)),
+            insts::OP_MULH => self.rtype(inst, true, |e| {
+                e.imul_wide(RCX);
+                e.mov_rr(RAX, RDX);
+            }),
+            insts::OP_MULHU => self.rtype(inst, true, |e| {
+                e.mul_wide(RCX);
+                e.mov_rr(RAX, RDX);
+            }),
+            insts::OP_ADDW => self.rtype(inst, false, |e| e.alu_rr(false, 0x01, RAX, RCX)),
+            insts::OP_SUBW => self.rtype(inst, false, |e| e.alu_rr(false, 0x29, RAX, RCX)),
+            insts::OP_SLLW => self.rtype(inst, false, |e| e.shift_cl(false, SHIFT_SHL, RAX)),
+            insts::OP_SRLW => self.rtype(inst, false, |e| e.shift_cl(false, SHIFT_SHR, RAX)),
+            insts::OP_SRAW => self.rtype(inst, false, |e| e.shift_cl(false, SHIFT_SAR, RAX)),
+            insts::OP_MULW => self.rtype(inst, false, |e| e.imul_rr(false, RAX, RCX)),
+            insts::OP_ADDI => self.itype(inst, true, |e, imm| e.alu_ri(true, ALU_ADD, RAX, imm)),
+            insts::OP_ANDI => self.itype(inst, true, |e, imm| e.alu_ri(true, ALU_AND, RAX, imm)),
+            insts::OP_ORI => self.itype(inst, true, |e, imm| e.alu_ri(true, ALU_OR, RAX, imm)),
+            insts::OP_XORI => self.itype(inst, true, |e, imm| e.alu_ri(true, ALU_XOR, RAX, imm)),
+            insts::OP_SLTI => self.itype(inst, true, |e, imm| {
+                e.alu_ri(true, ALU_CMP, RAX, imm);
+                e.setcc_rax(CC_L);
+            }),
+            insts::OP_SLTIU => self.itype(inst, true, |e, imm| {
+                e.alu_ri(true, ALU_CMP, RAX, imm);
+                e.setcc_rax(CC_B);
+            }),
+            insts::OP_ADDIW => self.itype(inst, false, |e, imm| e.alu_ri(false, ALU_ADD, RAX, imm)),
+            insts::OP_SLLI => self.shift_imm(inst, true, SHIFT_SHL),
+            insts::OP_SRLI => self.shift_imm(inst, true, SHIFT_SHR),
+            insts::OP_SRAI => self.shift_imm(inst, true, SHIFT_SAR),
+            insts::OP_SLLIW => self.shift_imm(inst, false, SHIFT_SHL),
+            insts::OP_SRLIW => self.shift_imm(inst, false, SHIFT_SHR),
+            insts::OP_SRAIW => self.shift_imm(inst, false, SHIFT_SAR),
+            insts::OP_LUI | insts::OP_CUSTOM_LOAD_IMM => {
+                let i = Utype(inst);
+                self.constant(i.rd(), i64::from(i.immediate_s()) as u64);
+            }
+            insts::OP_CUSTOM_LOAD_UIMM => {
+                let i = Utype(inst);
+                self.constant(i.rd(), u64::from(i.immediate_u()));
+            }
+            insts::OP_AUIPC => {
+                let i = Utype(inst);
+                self.constant(i.rd(), pc.wrapping_add(i64::from(i.immediate_s()) as u64));
+            }
+            insts::OP_LB_VERSION0 | insts::OP_LB_VERSION1 => {
+                self.load(inst, pc, position, 1, |e, m| e.load8_sx(RAX, m))
+            }
+            insts::OP_LBU_VERSION0 | insts::OP_LBU_VERSION1 => {
+                self.load(inst, pc, position, 1, |e, m| e.load8_zx(RAX, m))
+            }
+            insts::OP_LH_VERSION0 | insts::OP_LH_VERSION1 => {
+                self.load(inst, pc, position, 2, |e, m| e.load16_sx(RAX, m))
+            }
+            insts::OP_LHU_VERSION0 | insts::OP_LHU_VERSION1 => {
+                self.load(inst, pc, position, 2, |e, m| e.load16_zx(RAX, m))
+            }
+            insts::OP_LW_VERSION0 | insts::OP_LW_VERSION1 => {
+                self.load(inst, pc, position, 4, |e, m| e.load32_sx(RAX, m))
+            }
+            insts::OP_LWU_VERSION0 | insts::OP_LWU_VERSION1 => {
+                self.load(inst, pc, position, 4, |e, m| e.load32_zx(RAX, m))
+            }
+            insts::OP_LD_VERSION0 | insts::OP_LD_VERSION1 => {
+                self.load(inst, pc, position, 8, |e, m| e.load64(RAX, m))
+            }
+            insts::OP_SB => self.store(inst, pc, position, 1, |e, m| e.store8(m, RCX)),
+            insts::OP_SH => self.store(inst, pc, position, 2, |e, m| e.store16(m, RCX)),
+            insts::OP_SW => self.store(inst, pc, position, 4, |e, m| e.store32(m, RCX)),
+            insts::OP_SD => self.store(inst, pc, position, 8, |e, m| e.store64(m, RCX)),
+            insts::OP_BEQ => self.branch(inst, pc, length, CC_E),
+            insts::OP_BNE => self.branch(inst, pc, length, CC_NE),
+            insts::OP_BLT => self.branch(inst, pc, length, CC_L),
+            insts::OP_BGE => self.branch(inst, pc, length, CC_GE),
+            insts::OP_BLTU => self.branch(inst, pc, length, CC_B),
+            insts::OP_BGEU => self.branch(inst, pc, length, CC_AE),
+            insts::OP_JAL => {
+                let i = Utype(inst);
+                self.constant(i.rd(), pc.wrapping_add(length));
+                self.goto(pc.wrapping_add(i64::from(i.immediate_s()) as u64));
+            }
+            insts::OP_FAR_JUMP_REL => {
+                let i = Utype(inst);
+                self.constant(RA, pc.wrapping_add(length));
+                self.goto(pc.wrapping_add(i64::from(i.immediate_s()) as u64) & !1);
+            }
+            insts::OP_FAR_JUMP_ABS => {
+                let i = Utype(inst);
+                self.constant(RA, pc.wrapping_add(length));
+                self.goto(i64::from(i.immediate_s()) as u64 & !1);
+            }
+            insts::OP_JALR_VERSION0 | insts::OP_JALR_VERSION1 => {
+                let i = Itype(inst);
+                // Version 0 writes the link register before reading rs1
+                if op == insts::OP_JALR_VERSION0 {
+                    self.constant(i.rd(), pc.wrapping_add(length));
+                }
+                self.read(RAX, i.rs1());
+                self.e.alu_ri(true, ALU_ADD, RAX, i.immediate_s());
+                self.e.alu_ri(true, ALU_AND, RAX, -2);
+                if op == insts::OP_JALR_VERSION1 {
+                    self.e.mov_imm(RCX, pc.wrapping_add(length));
+                    self.write(i.rd(), RCX);
+                }
+                let dispatch = self.dispatch;
+                self.e.jmp(dispatch);
+            }
+            insts::OP_ECALL | insts::OP_EBREAK => {
+                // Syscalls run outside of native code, the runtime executes
+                // the instruction kept in the context.
+                self.e.mov_imm(RAX, inst);
+                self.e
+                    .store64(context_field(offset_of!(Context, instruction)), RAX);
+                self.store_pc(pc);
+                self.e.mov_imm(RAX, u64::from(RET_SLOWPATH));
+                self.e.jmp(self.epilogue);
+            }
+            _ => {
+                self.emit_execute(inst, pc, position);
+                if is_basic_block_end_instruction(inst) {
+                    self.e
+                        .load64(RAX, machine_field(offset_of!(AsmCoreMachine, pc)));
+                    let dispatch = self.dispatch;
+                    self.e.jmp(dispatch);
+                }
+            }
+        }
+    }
+
+    fn constant(&mut self, rd: usize, value: u64) {
+        if rd != 0 {
+            if value as i64 >= i64::from(i32::MIN) && value as i64 <= i64::from(i32::MAX) {
+                self.e.store64_imm(register(rd), value as i32);
+            } else {
+                self.e.mov_imm(RAX, value);
+                self.write(rd, RAX);
+            }
+        }
+    }
+
+    // rax = rs1, rcx = rs2, the body leaves the result in rax. 32-bit
+    // operations get their result sign extended.
+    fn rtype<F: FnOnce(&mut Emitter)>(&mut self, inst: Instruction, wide: bool, body: F) {
+        let i = Rtype(inst);
+        if i.rd() == 0 {
+            return;
+        }
+        self.read(RAX, i.rs1());
+        self.read(RCX, i.rs2());
+        body(&mut self.e);
+        if !wide {
+            self.e.movsxd(RAX, RAX);
+        }
+        self.write(i.rd(), RAX);
+    }
+
+    fn itype<F: FnOnce(&mut Emitter, i32)>(&mut self, inst: Instruction, wide: bool, body: F) {
+        let i = Itype(inst);
+        if i.rd() == 0 {
+            return;
+        }
+        self.read(RAX, i.rs1());
+        body(&mut self.e, i.immediate_s());
+        if !wide {
+            self.e.movsxd(RAX, RAX);
+        }
+        self.write(i.rd(), RAX);
+    }
+
+    fn shift_imm(&mut self, inst: Instruction, wide: bool, ext: u8) {
+        let i = Itype(inst);
+        if i.rd() == 0 {
+            return;
+        }
+        let mask = if wide { 0x3f } else { 0x1f };
+        self.read(RAX, i.rs1());
+        self.e
+            .shift_imm(wide, ext, RAX, (i.immediate_u() & mask) as u8);
+        if !wide {
+            self.e.movsxd(RAX, RAX);
+        }
+        self.write(i.rd(), RAX);
+    }
+
+    fn branch(&mut self, inst: Instruction, pc: u64, length: u64, cc: u8) {
+        let i = Stype(inst);
+        let taken = self.e.new_label();
+        self.read(RAX, i.rs1());
+        self.read(RCX, i.rs2());
+        self.e.alu_rr(true, 0x39, RAX, RCX);
+        self.e.jcc(cc, taken);
+        self.goto(pc.wrapping_add(length));
+        self.e.bind(taken);
+        self.goto(pc.wrapping_add(i64::from(i.immediate_s()) as u64));
+    }
+
+    // Leaves the effective address in rax, jumps to the interpreter
+    // whenever the access is not known to succeed without side effects.
+    fn load<F: FnOnce(&mut Emitter, Mem)>(
+        &mut self,
+        inst: Instruction,
+        pc: u64,
+        position: u64,
+        size: i32,
+        body: F,
+    ) {
+        let i = Itype(inst);
+        let resume = self.e.new_label();
+        let slow = self.cold(ColdPath::Execute {
+            inst,
+            pc,
+            position,
+            resume,
+        });
+        self.read(RAX, i.rs1());
+        self.e.alu_ri(true, ALU_ADD, RAX, i.immediate_s());
+        // The access must end before the memory boundary, version 0 refuses
+        // to touch the last byte so the slow path reports that case.
+        self.e.mov_rr(RDX, RAX);
+        self.e.alu_ri(true, ALU_ADD, RDX, size);
+        self.e.jcc(CC_B, slow);
+        self.e.alu_rm(
+            true,
+            0x39,
+            RDX,
+            machine_field(offset_of!(AsmCoreMachine, memory_size)),
+        );
+        self.e.jcc(CC_AE, slow);
+        // Both the first and the last frame touched must be initialized
+        self.e
+            .load64(RSI, machine_field(offset_of!(AsmCoreMachine, frames_ptr)));
+        self.e.mov_rr(RCX, RAX);
+        self.e
+            .shift_imm(true, SHIFT_SHR, RCX, MEMORY_FRAME_SHIFTS as u8);
+        self.e.cmp_mem8_imm(Mem::indexed(RSI, RCX, 1, 0), 0);
+        self.e.jcc(CC_E, slow);
+        self.e.alu_ri(true, ALU_SUB, RDX, 1);
+        self.e
+            .shift_imm(true, SHIFT_SHR, RDX, MEMORY_FRAME_SHIFTS as u8);
+        self.e.cmp_mem8_imm(Mem::indexed(RSI, RDX, 1, 0), 0);
+        self.e.jcc(CC_E, slow);
+        body(&mut self.e, Mem::indexed(MEMORY, RAX, 1, 0));
+        self.write(i.rd(), RAX);
+        self.e.bind(resume);
+    }
+
+    fn store<F: FnOnce(&mut Emitter, Mem)>(
+        &mut self,
+        inst: Instruction,
+        pc: u64,
+        position: u64,
+        size: i32,
+        body: F,
+    ) {
+        let i = Stype(inst);
+        let resume = self.e.new_label();
+        let slow = self.cold(ColdPath::Execute {
+            inst,
+            pc,
+            position,
+            resume,
+        });
+        self.read(RAX, i.rs1());
+        self.e.alu_ri(true, ALU_ADD, RAX, i.immediate_s());
+        // The access must stay in one page
+        self.e.mov_rr(RDX, RAX);
+        self.e
+            .shift_imm(true, SHIFT_SHR, RDX, RISCV_PAGE_SHIFTS as u8);
+        self.e.mov_rr(RCX, RAX);
+        self.e.alu_ri(true, ALU_ADD, RCX, size - 1);
+        self.e.jcc(CC_B, slow);
+        self.e
+            .shift_imm(true, SHIFT_SHR, RCX, RISCV_PAGE_SHIFTS as u8);
+        self.e.alu_rr(true, 0x39, RCX, RDX);
+        self.e.jcc(CC_NE, slow);
+        self.e.alu_rm(
+            true,
+            0x39,
+            RDX,
+            machine_field(offset_of!(AsmCoreMachine, flags_size)),
+        );
+        self.e.jcc(CC_AE, slow);
+        // The page must be writable and already marked as dirty, which also
+        // means its frame has been initialized.
+        self.e
+            .load64(RSI, machine_field(offset_of!(AsmCoreMachine, flags_ptr)));
+        self.e.load8_zx(RCX, Mem::indexed(RSI, RDX, 1, 0));
+        self.e
+            .alu_ri(false, ALU_AND, RCX, i32::from(FLAG_WXORX_BIT | FLAG_DIRTY));
+        self.e
+            .alu_ri(false, ALU_CMP, RCX, i32::from(FLAG_WRITABLE | FLAG_DIRTY));
+        self.e.jcc(CC_NE, slow);
+        self.e
+            .load64(RSI, machine_field(offset_of!(AsmCoreMachine, frames_ptr)));
+        self.e
+            .shift_imm(true, SHIFT_SHR, RDX, MEMORY_FRAME_PAGE_SHIFTS as u8);
+        self.e.cmp_mem8_imm(Mem::indexed(RSI, RDX, 1, 0), 0);
+        self.e.jcc(CC_E, slow);
+        self.read(RCX, i.rs2());
+        body(&mut self.e, Mem::indexed(MEMORY, RAX, 1, 0));
+        self.e.bind(resume);
+    }
+}
@@ -0,0 +1,374 @@
+// A minimal x86-64 code emitter covering exactly the instruction forms the
+// AOT translator needs. All memory operands use a 32-bit displacement so
+// the encodings stay uniform; the emitted code is position independent.
+use std::collections::HashMap;
+
+pub const RAX: u8 = 0;
+pub const RCX: u8 = 1;
+pub const RDX: u8 = 2;
+pub const RBX: u8 = 3;
+pub const RSP: u8 = 4;
+pub const RBP: u8 = 5;
+pub const RSI: u8 = 6;
+pub const RDI: u8 = 7;
+pub const R12: u8 = 12;
+pub const R13: u8 = 13;
+pub const R14: u8 = 14;
+pub const R15: u8 = 15;
+
+// Condition codes, used as the low nibble of Jcc / SETcc opcodes
+pub const CC_B: u8 = 0x2;
+pub const CC_AE: u8 = 0x3;
+pub const CC_E: u8 = 0x4;
+pub const CC_NE: u8 = 0x5;
+pub const CC_A: u8 = 0x7;
+pub const CC_L: u8 = 0xc;
+pub const CC_GE: u8 = 0xd;
+
+// Opcode extensions of the 0x81 (ALU r/m, imm32) group
+pub const ALU_ADD: u8 = 0;
+pub const ALU_OR: u8 = 1;
+pub const ALU_AND: u8 = 4;
+pub const ALU_SUB: u8 = 5;
+pub const ALU_XOR: u8 = 6;
+pub const ALU_CMP: u8 = 7;
+
+// Opcode extensions of the 0xC1 / 0xD3 shift group
+pub const SHIFT_SHL: u8 = 4;
+pub const SHIFT_SHR: u8 = 5;
+pub const SHIFT_SAR: u8 = 7;
+
+/// Memory operand in the form of [base + index * scale + disp]
+#[derive(Debug, Clone, Copy)]
+pub struct Mem {
+    pub base: u8,
+    pub index: Option<(u8, u8)>,
+    pub disp: i32,
+}
+
+impl Mem {
+    pub fn base(base: u8, disp: i32) -> Self {
+        Mem {
+            base,
+            index: None,
+            disp,
+        }
+    }
+
+    pub fn indexed(base: u8, index: u8, scale: u8, disp: i32) -> Self {
+        debug_assert!(index != RSP);
+        Mem {
+            base,
+            index: Some((index, scale)),
+            disp,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Label(usize);
+
+#[derive(Default)]
+pub struct Emitter {
+    code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    // Position of a rel32 field -> label it refers to
+    fixups: HashMap<usize, Label>,
+}
+
+impl Emitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    pub fn bind(&mut self, label: Label) {
+        debug_assert!(self.labels[label.0].is_none());
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    pub fn label_offset(&self, label: Label) -> Option<usize> {
+        self.labels[label.0]
+    }
+
+    /// Resolves all jumps and returns the final code.
+    pub fn finish(mut self) -> Vec<u8> {
+        for (position, label) in self.fixups.iter() {
+            let target = self.labels[label.0].expect("unbound label");
+            let relative = target as i64 - (*position as i64 + 4);
+            self.code[*position..*position + 4].copy_from_slice(&(relative as i32).to_le_bytes());
+        }
+        self.code
+    }
+
+    fn byte(&mut self, b: u8) {
+        self.code.push(b);
+    }
+
+    fn bytes(&mut self, b: &[u8]) {
+        self.code.extend_from_slice(b);
+    }
+
+    fn rex(&mut self, w: bool, reg: u8, index: u8, base: u8, force: bool) {
+        let rex = 0x40
+            | (u8::from(w) << 3)
+            | ((reg >> 3) << 2)
+            | (((index >> 3) & 1) << 1)
+            | ((base >> 3) & 1);
+        if rex != 0x40 || force {
+            self.byte(rex);
+        }
+    }
+
+    // Emits [prefix] REX opcode ModRM with a register as the r/m operand
+    fn op_rr(&mut self, w: bool, opcode: &[u8], reg: u8, rm: u8) {
+        self.rex(w, reg, 0, rm, false);
+        self.bytes(opcode);
+        self.byte(0xc0 | ((reg & 7) << 3) | (rm & 7));
+    }
+
+    // Emits REX opcode ModRM [SIB] disp32 with a memory r/m operand
+    fn op_rm(&mut self, w: bool, opcode: &[u8], reg: u8, mem: Mem) {
+        let index = mem.index.map(|(i, _)| i).unwrap_or(0);
+        self.rex(w, reg, index, mem.base, false);
+        self.bytes(opcode);
+        match mem.index {
+            Some((index, scale)) => {
+                let ss = match scale {
+                    1 => 0,
+                    2 => 1,
+                    4 => 2,
+                    8 => 3,
+                    _ => unreachable!(),
+                };
+                self.byte(0x80 | ((reg & 7) << 3) | 0x4);
+                self.byte((ss << 6) | ((index & 7) << 3) | (mem.base & 7));
+            }
+            None => {
+                self.byte(0x80 | ((reg & 7) << 3) | (mem.base & 7));
+                if mem.base & 7 == RSP {
+                    self.byte(0x24);
+                }
+            }
+        }
+        self.bytes(&mem.disp.to_le_bytes());
+    }
+
+    pub fn push(&mut self, reg: u8) {
+        self.rex(false, 0, 0, reg, false);
+        self.byte(0x50 | (reg & 7));
+    }
+
+    pub fn pop(&mut self, reg: u8) {
+        self.rex(false, 0, 0, reg, false);
+        self.byte(0x58 | (reg & 7));
+    }
+
+    pub fn ret(&mut self) {
+        self.byte(0xc3);
+    }
+
+    pub fn mov_rr(&mut self, dst: u8, src: u8) {
+        self.op_rr(true, &[0x89], src, dst);
+    }
+
+    pub fn load64(&mut self, dst: u8, mem: Mem) {
+        self.op_rm(true, &[0x8b], dst, mem);
+    }
+
+    pub fn store64(&mut self, mem: Mem, src: u8) {
+        self.op_rm(true, &[0x89], src, mem);
+    }
+
+    pub fn store32(&mut self, mem: Mem, src: u8) {
+        self.op_rm(false, &[0x89], src, mem);
+    }
+
+    pub fn store16(&mut self, mem: Mem, src: u8) {
+        self.byte(0x66);
+        self.op_rm(false, &[0x89], src, mem);
+    }
+
+    pub fn store8(&mut self, mem: Mem, src: u8) {
+        // Only the legacy low byte registers are used, hence no forced REX
+        debug_assert!(src < RSP);
+        self.op_rm(false, &[0x88], src, mem);
+    }
+
+    /// Stores a sign extended 32-bit immediate as a 64-bit value
+    pub fn store64_imm(&mut self, mem: Mem, imm: i32) {
+        self.op_rm(true, &[0xc7], 0, mem);
+        self.bytes(&imm.to_le_bytes());
+    }
+
+    pub fn load32_zx(&mut self, dst: u8, mem: Mem) {
+        self.op_rm(false, &[0x8b], dst, mem);
+    }
+
+    pub fn load32_sx(&mut self, dst: u8, mem: Mem) {
+        self.op_rm(true, &[0x63], dst, mem);
+    }
+
+    pub fn load16_zx(&mut self, dst: u8, mem: Mem) {
+        self.op_rm(false, &[0x0f, 0xb7], dst, mem);
+    }
+
+    pub fn load16_sx(&mut self, dst: u8, mem: Mem) {
+        self.op_rm(true, &[0x0f, 0xbf], dst, mem);
+    }
+
+    pub fn load8_zx(&mut self, dst: u8, mem: Mem) {
+        self.op_rm(false, &[0x0f, 0xb6], dst, mem);
+    }
+
+    pub fn load8_sx(&mut self, dst: u8, mem: Mem) {
+        self.op_rm(true, &[0x0f, 0xbe], dst, mem);
+    }
+
+    pub fn cmp_mem8_imm(&mut self, mem: Mem, imm: u8) {
+        self.op_rm(false, &[0x80], ALU_CMP, mem);
+        self.byte(imm);
+    }
+
+    pub fn mov_imm(&mut self, dst: u8, imm: u64) {
+        if imm as i64 >= i64::from(i32::MIN) && imm as i64 <= i64::from(i32::MAX) {
+            self.op_rr(true, &[0xc7], 0, dst);
+            self.bytes(&(imm as i32).to_le_bytes());
+        } else if imm <= u64::from(u32::MAX) {
+            self.rex(false, 0, 0, dst, false);
+            self.byte(0xb8 | (dst & 7));
+            self.bytes(&(imm as u32).to_le_bytes());
+        } else {
+            self.rex(true, 0, 0, dst, false);
+            self.byte(0xb8 | (dst & 7));
+            self.bytes(&imm.to_le_bytes());
+        }
+    }
+
+    pub fn zero(&mut self, dst: u8) {
+        self.op_rr(false, &[0x31], dst, dst);
+    }
+
+    /// Two register ALU operation, opcode is the `r/m, r` form, e.g. 0x01 for add
+    pub fn alu_rr(&mut self, w: bool, opcode: u8, dst: u8, src: u8) {
+        self.op_rr(w, &[opcode], src, dst);
+    }
+
+    pub fn alu_rm(&mut self, w: bool, opcode: u8, dst: u8, mem: Mem) {
+        // The `r, r/m` form is the `r/m, r` form plus 2
+        self.op_rm(w, &[opcode + 2], dst, mem);
+    }
+
+    pub fn alu_ri(&mut self, w: bool, ext: u8, dst: u8, imm: i32) {
+        self.op_rr(w, &[0x81], ext, dst);
+        self.bytes(&imm.to_le_bytes());
+    }
+
+    pub fn test_rr(&mut self, w: bool, a: u8, b: u8) {
+        self.op_rr(w, &[0x85], b, a);
+    }
+
+    pub fn imul_rr(&mut self, w: bool, dst: u8, src: u8) {
+        self.op_rr(w, &[0x0f, 0xaf], dst, src);
+    }
+
+    /// One operand signed multiplication, rdx:rax = rax * src
+    pub fn imul_wide(&mut self, src: u8) {
+        self.op_rr(true, &[0xf7], 5, src);
+    }
+
+    /// One operand unsigned multiplication, rdx:rax = rax * src
+    pub fn mul_wide(&mut self, src: u8) {
+        self.op_rr(true, &[0xf7], 4, src);
+    }
+
+    pub fn shift_cl(&mut self, w: bool, ext: u8, dst: u8) {
+        self.op_rr(w, &[0xd3], ext, dst);
+    }
+
+    pub fn shift_imm(&mut self, w: bool, ext: u8, dst: u8, imm: u8) {
+        self.op_rr(w, &[0xc1], ext, dst);
+        self.byte(imm);
+    }
+
+    pub fn movsxd(&mut self, dst: u8, src: u8) {
+        self.op_rr(true, &[0x63], dst, src);
+    }
+
+    /// SETcc on al followed by zero extending it to rax
+    pub fn setcc_rax(&mut self, cc: u8) {
+        self.bytes(&[0x0f, 0x90 | cc, 0xc0]);
+        self.bytes(&[0x0f, 0xb6, 0xc0]);
+    }
+
+    pub fn jmp(&mut self, label: Label) {
+        self.byte(0xe9);
+        self.fixups.insert(self.code.len(), label);
+        self.bytes(&[0; 4]);
+    }
+
+    pub fn jcc(&mut self, cc: u8, label: Label) {
+        self.bytes(&[0x0f, 0x80 | cc]);
+        self.fixups.insert(self.code.len(), label);
+        self.bytes(&[0; 4]);
+    }
+
+    pub fn jmp_reg(&mut self, reg: u8) {
+        self.op_rr(false, &[0xff], 4, reg);
+    }
+
+    pub fn call_mem(&mut self, mem: Mem) {
+        self.op_rm(false, &[0xff], 2, mem);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings() {
+        let mut e = Emitter::new();
+        e.push(R12);
+        e.mov_rr(R12, RDI);
+        e.load64(RAX, Mem::base(RBX, 8));
+        e.load64(RDX, Mem::base(R12, 16));
+        e.store8(Mem::indexed(R13, RAX, 1, 0), RCX);
+        e.mov_imm(RAX, u64::MAX);
+        e.mov_imm(RAX, 0x8000_0000);
+        e.setcc_rax(CC_L);
+        assert_eq!(
+            e.finish(),
+            vec![
+                0x41, 0x54, // push r12
+                0x49, 0x89, 0xfc, // mov r12, rdi
+                0x48, 0x8b, 0x83, 8, 0, 0, 0, // mov rax, [rbx+8]
+                0x49, 0x8b, 0x94, 0x24, 16, 0, 0, 0, // mov rdx, [r12+16]
+                0x41, 0x88, 0x8c, 0x05, 0, 0, 0, 0, // mov [r13+rax], cl
+                0x48, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff, // mov rax, -1
+                0xb8, 0, 0, 0, 0x80, // mov eax, 0x80000000
+                0x0f, 0x9c, 0xc0, 0x0f, 0xb6, 0xc0, // setl al; movzx eax, al
+            ]
+        );
+    }
+
+    #[test]
+    fn test_label_fixups() {
+        let mut e = Emitter::new();
+        let start = e.new_label();
+        let end = e.new_label();
+        e.bind(start);
+        e.jcc(CC_E, end);
+        e.jmp(start);
+        e.bind(end);
+        e.ret();
+        assert_eq!(
+            e.finish(),
+            vec![0x0f, 0x84, 5, 0, 0, 0, 0xe9, 0xf5, 0xff, 0xff, 0xff, 0xc3]
+        );
+    }
+}
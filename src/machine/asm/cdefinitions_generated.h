@@ -27,11 +27,12 @@
 #define CKB_VM_ASM_MEMORY_FLAG_WRITABLE 0
 #define CKB_VM_ASM_MEMORY_FLAG_DIRTY 4
 
-#define CKB_VM_ASM_FIXED_TRACE_STRUCT_SIZE 296
+#define CKB_VM_ASM_FIXED_TRACE_STRUCT_SIZE 304
 #define CKB_VM_ASM_TRACE_OFFSET_ADDRESS 0
 #define CKB_VM_ASM_TRACE_OFFSET_LENGTH 8
 #define CKB_VM_ASM_TRACE_OFFSET_CYCLES 16
-#define CKB_VM_ASM_TRACE_OFFSET_THREADS 24
+#define CKB_VM_ASM_TRACE_OFFSET_JUMP_TRACE 24
+#define CKB_VM_ASM_TRACE_OFFSET_THREADS 32
 
 #define CKB_VM_ASM_INVOKE_DATA_OFFSET_PAUSE 0
 #define CKB_VM_ASM_INVOKE_DATA_OFFSET_FIXED_TRACES 8
@@ -195,23 +196,31 @@
 #define CKB_VM_ASM_OP_ADD3C 153
 #define CKB_VM_ASM_OP_CUSTOM_LOAD_UIMM 154
 #define CKB_VM_ASM_OP_CUSTOM_LOAD_IMM 155
-#define CKB_VM_ASM_OP_AUIPC 156
-#define CKB_VM_ASM_OP_BEQ 157
-#define CKB_VM_ASM_OP_BGE 158
-#define CKB_VM_ASM_OP_BGEU 159
-#define CKB_VM_ASM_OP_BLT 160
-#define CKB_VM_ASM_OP_BLTU 161
-#define CKB_VM_ASM_OP_BNE 162
-#define CKB_VM_ASM_OP_EBREAK 163
-#define CKB_VM_ASM_OP_ECALL 164
-#define CKB_VM_ASM_OP_FENCE 165
-#define CKB_VM_ASM_OP_FENCEI 166
-#define CKB_VM_ASM_OP_JAL 167
-#define CKB_VM_ASM_OP_JALR_VERSION0 168
-#define CKB_VM_ASM_OP_JALR_VERSION1 169
-#define CKB_VM_ASM_OP_FAR_JUMP_REL 170
-#define CKB_VM_ASM_OP_FAR_JUMP_ABS 171
-#define CKB_VM_ASM_OP_CUSTOM_ASM_TRACE_JUMP 172
+#define CKB_VM_ASM_OP_SLLI_ADD 156
+#define CKB_VM_ASM_OP_SLLI_ADDI 157
+#define CKB_VM_ASM_OP_LD_PAIR 158
+#define CKB_VM_ASM_OP_SD_PAIR 159
+#define CKB_VM_ASM_OP_AUIPC 160
+#define CKB_VM_ASM_OP_BEQ 161
+#define CKB_VM_ASM_OP_BGE 162
+#define CKB_VM_ASM_OP_BGEU 163
+#define CKB_VM_ASM_OP_BLT 164
+#define CKB_VM_ASM_OP_BLTU 165
+#define CKB_VM_ASM_OP_BNE 166
+#define CKB_VM_ASM_OP_EBREAK 167
+#define CKB_VM_ASM_OP_ECALL 168
+#define CKB_VM_ASM_OP_FENCE 169
+#define CKB_VM_ASM_OP_FENCEI 170
+#define CKB_VM_ASM_OP_JAL 171
+#define CKB_VM_ASM_OP_JALR_VERSION0 172
+#define CKB_VM_ASM_OP_JALR_VERSION1 173
+#define CKB_VM_ASM_OP_FAR_JUMP_REL 174
+#define CKB_VM_ASM_OP_FAR_JUMP_ABS 175
+#define CKB_VM_ASM_OP_SLT_BNEZ 176
+#define CKB_VM_ASM_OP_SLT_BEQZ 177
+#define CKB_VM_ASM_OP_SLTU_BNEZ 178
+#define CKB_VM_ASM_OP_SLTU_BEQZ 179
+#define CKB_VM_ASM_OP_CUSTOM_ASM_TRACE_JUMP 180
 
 #ifdef CKB_VM_ASM_GENERATE_LABEL_TABLES
 #ifdef __APPLE__
@@ -378,6 +387,10 @@ ckb_vm_asm_labels:
 	.long	.CKB_VM_ASM_LABEL_OP_ADD3C - .CKB_VM_ASM_LABEL_TABLE
 	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM_LOAD_UIMM - .CKB_VM_ASM_LABEL_TABLE
 	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM_LOAD_IMM - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLLI_ADD - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLLI_ADDI - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_LD_PAIR - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SD_PAIR - .CKB_VM_ASM_LABEL_TABLE
 	.long	.CKB_VM_ASM_LABEL_OP_AUIPC - .CKB_VM_ASM_LABEL_TABLE
 	.long	.CKB_VM_ASM_LABEL_OP_BEQ - .CKB_VM_ASM_LABEL_TABLE
 	.long	.CKB_VM_ASM_LABEL_OP_BGE - .CKB_VM_ASM_LABEL_TABLE
@@ -394,6 +407,203 @@ ckb_vm_asm_labels:
 	.long	.CKB_VM_ASM_LABEL_OP_JALR_VERSION1 - .CKB_VM_ASM_LABEL_TABLE
 	.long	.CKB_VM_ASM_LABEL_OP_FAR_JUMP_REL - .CKB_VM_ASM_LABEL_TABLE
 	.long	.CKB_VM_ASM_LABEL_OP_FAR_JUMP_ABS - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLT_BNEZ - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLT_BEQZ - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLTU_BNEZ - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLTU_BEQZ - .CKB_VM_ASM_LABEL_TABLE
 	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM_ASM_TRACE_JUMP - .CKB_VM_ASM_LABEL_TABLE
 	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM_TRACE_END - .CKB_VM_ASM_LABEL_TABLE
 #endif /* CKB_VM_ASM_GENERATE_LABEL_TABLES */
+
+#ifdef CKB_VM_ASM_GENERATE_RV32_LABEL_TABLES
+#ifdef __APPLE__
+.global _ckb_vm_asm_rv32_labels
+_ckb_vm_asm_rv32_labels:
+#else
+.global ckb_vm_asm_rv32_labels
+ckb_vm_asm_rv32_labels:
+#endif
+.CKB_VM_ASM_RV32_LABEL_TABLE:
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.exit_slowpath - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_UNLOADED - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ADDW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ADDIW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ADDIW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ADDW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AND - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ANDI - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_DIVW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_DIVUW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_DIVUW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_DIVW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_LB_VERSION0 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_LB_VERSION1 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_LBU_VERSION0 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_LBU_VERSION1 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_LD_VERSION0 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_LD_VERSION1 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_LH_VERSION0 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_LH_VERSION1 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_LHU_VERSION0 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_LHU_VERSION1 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_LUI - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_LW_VERSION0 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_LW_VERSION1 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_LWU_VERSION0 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_LWU_VERSION1 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_MULW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_MULH - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_MULHSU - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_MULHU - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_MULW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_OR - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ORI - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_REMW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_REMUW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_REMUW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_REMW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_SB - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SD - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_SH - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLLW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLLIW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLLIW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLLW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLT - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLTI - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLTIU - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLTU - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SRAW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SRAIW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SRAIW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SRAW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SRLW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SRLIW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SRLIW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SRLW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SUBW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SUBW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_SW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_XOR - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_XORI - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_LR_W - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_SC_W - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_AMOSWAP_W - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_AMOADD_W - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_AMOXOR_W - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_AMOAND_W - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_AMOOR_W - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_AMOMIN_W - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_AMOMAX_W - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_AMOMINU_W - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_AMOMAXU_W - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_LR_D - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SC_D - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOSWAP_D - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOADD_D - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOXOR_D - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOAND_D - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOOR_D - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOMIN_D - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOMAX_D - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOMINU_D - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_AMOMAXU_D - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ADDW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ANDN - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BCLR - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BCLRI - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BEXT - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BEXTI - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BINV - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BINVI - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BSET - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BSETI - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_CLMUL - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_CLMULH - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_CLMULR - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CLZW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_CLZW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CPOPW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CPOPW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CTZW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CTZW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_MAX - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_MAXU - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_MIN - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_MINU - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_ORCB - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ORN - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_REV8 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ROLW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ROLW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_RORW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_RORIW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_RORIW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_RORW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SEXTB - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SEXTH - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_SH1ADD - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_SH1ADD - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_SH2ADD - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_SH2ADD - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_SH3ADD - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_SH3ADD - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_SLLIUW - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_XNOR - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ZEXTH - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_WIDE_MUL - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_WIDE_MULU - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_WIDE_MULSU - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_WIDE_DIV - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_WIDE_DIVU - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ADC - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SBB - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ADCS - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SBBS - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ADD3A - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ADD3B - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ADD3C - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM_LOAD_UIMM - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM_LOAD_IMM - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLLI_ADD - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLLI_ADDI - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_LD_PAIR - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SD_PAIR - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_AUIPC - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BEQ - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BGE - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BGEU - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BLT - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BLTU - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_BNE - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_EBREAK - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_ECALL - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FENCE - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FENCEI - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_JAL - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_JALR_VERSION0 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_RV32_OP_JALR_VERSION1 - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FAR_JUMP_REL - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_FAR_JUMP_ABS - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLT_BNEZ - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLT_BEQZ - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLTU_BNEZ - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_SLTU_BEQZ - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM_ASM_TRACE_JUMP - .CKB_VM_ASM_RV32_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM_TRACE_END - .CKB_VM_ASM_RV32_LABEL_TABLE
+#endif /* CKB_VM_ASM_GENERATE_RV32_LABEL_TABLES */
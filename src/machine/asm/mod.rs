@@ -1,3 +1,4 @@
+pub mod pool;
 pub mod traces;
 
 use byteorder::{ByteOrder, LittleEndian};
@@ -19,9 +20,10 @@ use crate::{
     elf::ProgramMetadata,
     error::OutOfBoundKind,
     instructions::execute_instruction,
+    isa::isa_to_str,
     machine::{
         asm::traces::{decode_fixed_trace, SimpleFixedTraceDecoder, TraceDecoder},
-        VERSION0,
+        StackEnv, VERSION0,
     },
     memory::{
         check_no_overflow, fill_page_data, get_page_indices, memset, round_page_down,
@@ -31,6 +33,9 @@ use crate::{
     RISCV_PAGESIZE,
 };
 
+// Runs of initialized memory from this size are released to the OS on reset.
+const RELEASE_MEMORY_THRESHOLD: usize = 16 << 20;
+
 impl CoreMachine for Box<AsmCoreMachine> {
     type REG = u64;
     type MEM = Self;
@@ -40,7 +45,11 @@ impl CoreMachine for Box<AsmCoreMachine> {
     }
 
     fn update_pc(&mut self, pc: Self::REG) {
-        self.next_pc = pc;
+        self.next_pc = if self.xlen == 32 {
+            pc & u64::from(u32::MAX)
+        } else {
+            pc
+        };
     }
 
     fn commit_pc(&mut self) {
@@ -60,7 +69,12 @@ impl CoreMachine for Box<AsmCoreMachine> {
     }
 
     fn set_register(&mut self, idx: usize, value: Self::REG) {
-        self.registers[idx] = value;
+        // The assembly code relies on RV32 registers being sign extended.
+        self.registers[idx] = if self.xlen == 32 {
+            value as i32 as i64 as u64
+        } else {
+            value
+        };
     }
 
     fn isa(&self) -> u8 {
@@ -70,6 +84,10 @@ impl CoreMachine for Box<AsmCoreMachine> {
     fn version(&self) -> u32 {
         self.version
     }
+
+    fn xlen(&self) -> u8 {
+        self.xlen
+    }
 }
 
 // This function is exported for asm and aot machine.
@@ -363,8 +381,29 @@ impl Memory for Box<AsmCoreMachine> {
     fn reset_memory(&mut self) -> Result<(), Error> {
         let slice = self.cast_ptr_to_slice_mut(self.flags_ptr, 0, self.flags_size as usize);
         memset(slice, 0);
-        let slice = self.cast_ptr_to_slice_mut(self.frames_ptr, 0, self.frames_size as usize);
-        memset(slice, 0);
+        // Frames are zeroed when initialized again, only long runs of
+        // initialized frames are worth handing back to the OS.
+        let frames_size = self.frames_size as usize;
+        let mut frame = 0;
+        while frame < frames_size {
+            let frames = self.cast_ptr_to_slice_mut(self.frames_ptr, 0, frames_size);
+            let start = match frames[frame..].iter().position(|f| *f != 0) {
+                Some(offset) => frame + offset,
+                None => break,
+            };
+            let end = frames[start..]
+                .iter()
+                .position(|f| *f == 0)
+                .map_or(frames_size, |offset| start + offset);
+            memset(&mut frames[start..end], 0);
+            if (end - start) << MEMORY_FRAME_SHIFTS >= RELEASE_MEMORY_THRESHOLD {
+                self.release_memory(
+                    start << MEMORY_FRAME_SHIFTS,
+                    (end - start) << MEMORY_FRAME_SHIFTS,
+                );
+            }
+            frame = end;
+        }
         self.load_reservation_address = u64::MAX;
         self.last_read_frame = u64::MAX;
         self.last_write_page = u64::MAX;
@@ -518,7 +557,9 @@ impl Memory for Box<AsmCoreMachine> {
             let memory_from = memory.add(addr as usize);
             std::slice::from_raw_parts(memory_from, size as usize)
         };
-        Ok(Bytes::from(slice))
+        // Bytes::from would borrow the machine memory as 'static, which
+        // dangles once the machine is dropped.
+        Ok(Bytes::copy_from_slice(slice))
     }
 
     fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
@@ -653,6 +694,9 @@ extern "C" {
     // We are keeping this as a function here, but at the bottom level this really
     // just points to an array of assembly label offsets for each opcode.
     pub fn ckb_vm_asm_labels();
+    // Labels used by RV32 machines, RV32 is only supported on x86_64.
+    #[cfg(target_arch = "x86_64")]
+    pub fn ckb_vm_asm_rv32_labels();
 }
 
 pub struct AsmMachine {
@@ -682,8 +726,31 @@ impl AsmMachine {
             .load_program_with_metadata(program, metadata, args)
     }
 
+    pub fn load_program_with_env(
+        &mut self,
+        program: &Bytes,
+        args: &[Bytes],
+        env: &StackEnv,
+    ) -> Result<u64, Error> {
+        self.machine.load_program_with_env(program, args, env)
+    }
+
+    pub fn load_program_with_metadata_and_env(
+        &mut self,
+        program: &Bytes,
+        metadata: &ProgramMetadata,
+        args: &[Bytes],
+        env: &StackEnv,
+    ) -> Result<u64, Error> {
+        self.machine
+            .load_program_with_metadata_and_env(program, metadata, args, env)
+    }
+
     pub fn run(&mut self) -> Result<i8, Error> {
-        let decoder = build_decoder::<u64>(self.machine.isa(), self.machine.version());
+        let decoder = match self.machine.xlen() {
+            32 => build_decoder::<u32>(self.machine.isa(), self.machine.version()),
+            _ => build_decoder::<u64>(self.machine.isa(), self.machine.version()),
+        };
         let mut decoder = SimpleFixedTraceDecoder::new(decoder);
         self.run_with_decoder(&mut decoder)
     }
@@ -692,6 +759,7 @@ impl AsmMachine {
         if self.machine.isa() & ISA_MOP != 0 && self.machine.version() == VERSION0 {
             return Err(Error::InvalidVersion);
         }
+        self.check_decoder(decoder)?;
         self.machine.set_running(true);
         while self.machine.running() {
             if self.machine.reset_signal() {
@@ -740,6 +808,7 @@ impl AsmMachine {
     }
 
     pub fn step<D: InstDecoder>(&mut self, decoder: &mut D) -> Result<(), Error> {
+        self.check_decoder(decoder)?;
         // Decode only one instruction into a trace
         let (trace, _) = decode_fixed_trace(decoder, &mut self.machine, Some(1))?;
 
@@ -776,6 +845,30 @@ impl AsmMachine {
         }
         Ok(())
     }
+
+    // RV32 machines run RV32IMC with the A and B extensions on x86_64,
+    // using a decoder producing RV32 instructions. Decoders built ahead of
+    // time must also match the machine's isa and version.
+    fn check_decoder<D: InstDecoder>(&self, decoder: &D) -> Result<(), Error> {
+        let xlen = self.machine.xlen();
+        if decoder.xlen() != xlen {
+            return Err(Error::InvalidIsa(format!(
+                "decoder is for RV{}, machine is RV{}",
+                decoder.xlen(),
+                xlen
+            )));
+        }
+        if xlen == 32 && !cfg!(target_arch = "x86_64") {
+            return Err(Error::InvalidIsa(isa_to_str(self.machine.isa(), 32)));
+        }
+        if xlen == 32 && self.machine.isa() & ISA_MOP != 0 {
+            return Err(Error::InvalidIsa(format!(
+                "{} with ISA_MOP",
+                isa_to_str(self.machine.isa(), 32)
+            )));
+        }
+        decoder.check_machine(self.machine.isa(), self.machine.version(), xlen)
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,418 @@
+use crate::{
+    decoder::{build_decoder, Decoder, InstDecoder},
+    elf::{parse_elf, ProgramMetadata},
+    error::Error,
+    hash::blake2b_256,
+    instructions::Instruction,
+    machine::{
+        asm::{
+            traces::{MemoizedFixedTraceDecoder, TraceDecoder},
+            AsmCoreMachine, AsmMachine,
+        },
+        CoreMachine, DefaultMachine, DefaultMachineBuilder, SupportMachine,
+    },
+    memory::Memory,
+};
+use bytes::Bytes;
+use ckb_vm_definitions::asm::FixedTrace;
+use std::collections::{HashMap, VecDeque};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+type PoolDecoder = MemoizedFixedTraceDecoder<Decoder>;
+
+// Program a decoder memoizes traces for, and its layout, see
+// ProgramMetadata::layout_hash.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct ProgramTag {
+    program: [u8; 32],
+    layout: [u8; 32],
+}
+
+impl ProgramTag {
+    fn new(program: &Bytes, metadata: &ProgramMetadata) -> Self {
+        Self {
+            program: blake2b_256(b"ckb-vm-pool", program),
+            layout: metadata.layout_hash(),
+        }
+    }
+}
+
+/// Configuration of the machines handed out by an AsmMachinePool, machines
+/// and decoders are only reused for the same configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    pub isa: u8,
+    pub version: u32,
+    pub memory_size: usize,
+    // 64 for RV64 machines, 32 for RV32 machines.
+    pub xlen: u8,
+    // Identifies the instruction cycle function set up by the build closure
+    // passed to AsmMachinePool::acquire. Memoized traces carry their cycles,
+    // so machines with different cycle models never share decoders. There is
+    // no default, every caller has to name the cycle function it installs.
+    pub cycle_model: u64,
+}
+
+impl PoolKey {
+    pub fn new(isa: u8, version: u32, memory_size: usize, cycle_model: u64) -> Self {
+        Self {
+            isa,
+            version,
+            memory_size,
+            xlen: 64,
+            cycle_model,
+        }
+    }
+
+    pub fn new_rv32(isa: u8, version: u32, memory_size: usize, cycle_model: u64) -> Self {
+        Self {
+            isa,
+            version,
+            memory_size,
+            xlen: 32,
+            cycle_model,
+        }
+    }
+
+    // Syscalls may reset the machine with another configuration, the cycle
+    // model stays the one of the builder.
+    fn of(machine: &AsmCoreMachine, cycle_model: u64) -> Self {
+        Self {
+            isa: machine.isa,
+            version: machine.version,
+            memory_size: machine.memory_size as usize,
+            xlen: machine.xlen,
+            cycle_model,
+        }
+    }
+
+    fn build_machine(&self, max_cycles: u64) -> Box<AsmCoreMachine> {
+        match self.xlen {
+            32 => AsmCoreMachine::new_with_memory_rv32(
+                self.isa,
+                self.version,
+                max_cycles,
+                self.memory_size,
+            ),
+            _ => AsmCoreMachine::new_with_memory(
+                self.isa,
+                self.version,
+                max_cycles,
+                self.memory_size,
+            ),
+        }
+    }
+
+    fn build_decoder(&self) -> PoolDecoder {
+        let decoder = match self.xlen {
+            32 => build_decoder::<u32>(self.isa, self.version),
+            _ => build_decoder::<u64>(self.isa, self.version),
+        };
+        MemoizedFixedTraceDecoder::new(decoder)
+    }
+}
+
+/// Counters of an AsmMachinePool, since its creation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolMetrics {
+    /// Machines acquired from the idle ones.
+    pub machine_hits: u64,
+    /// Machines allocated since no idle one was available.
+    pub machine_misses: u64,
+    /// Programs loaded with a decoder memoizing traces of the same program.
+    pub decoder_hits: u64,
+    /// Programs loaded with a new decoder.
+    pub decoder_misses: u64,
+}
+
+impl PoolMetrics {
+    pub fn machine_hit_rate(&self) -> f64 {
+        hit_rate(self.machine_hits, self.machine_misses)
+    }
+
+    pub fn decoder_hit_rate(&self) -> f64 {
+        hit_rate(self.decoder_hits, self.decoder_misses)
+    }
+}
+
+fn hit_rate(hits: u64, misses: u64) -> f64 {
+    let total = hits + misses;
+    if total == 0 {
+        0.0
+    } else {
+        hits as f64 / total as f64
+    }
+}
+
+#[derive(Default)]
+struct PoolState {
+    // Machines are only created boxed, see AsmCoreMachine::new_with_memory.
+    #[allow(clippy::vec_box)]
+    machines: HashMap<PoolKey, Vec<Box<AsmCoreMachine>>>,
+    // Idle decoders and the program they were used for, the most recently
+    // returned ones come last.
+    decoders: HashMap<PoolKey, VecDeque<(ProgramTag, PoolDecoder)>>,
+    metrics: PoolMetrics,
+}
+
+/// A pool of asm machines, so running many programs doesn't allocate memory
+/// for each of them. Machines are reset when returned, which only goes over
+/// the memory frames touched by the last program. Decoders are kept along with the
+/// program and metadata they were used for, loading the same program again
+/// with the same configuration, see PoolKey, reuses the traces memoized by
+/// its previous runs.
+///
+/// Cloning a pool returns a handle to the same pool, it can be shared
+/// between threads.
+#[derive(Clone)]
+pub struct AsmMachinePool {
+    state: Arc<Mutex<PoolState>>,
+    max_idle: usize,
+}
+
+impl AsmMachinePool {
+    /// Creates a pool keeping at most max_idle idle machines, and at most
+    /// max_idle idle decoders, for each configuration.
+    pub fn new(max_idle: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(PoolState::default())),
+            max_idle,
+        }
+    }
+
+    /// Acquires a machine for the configuration, build sets up the rest of
+    /// the machine such as syscalls, and must install the instruction cycle
+    /// function key.cycle_model stands for. The machine is returned to the
+    /// pool when dropped.
+    pub fn acquire<F>(&self, key: PoolKey, max_cycles: u64, build: F) -> PooledAsmMachine
+    where
+        F: FnOnce(
+            DefaultMachineBuilder<Box<AsmCoreMachine>>,
+        ) -> DefaultMachine<Box<AsmCoreMachine>>,
+    {
+        let idle = {
+            let mut state = self.state();
+            let idle = state.machines.get_mut(&key).and_then(Vec::pop);
+            if idle.is_some() {
+                state.metrics.machine_hits += 1;
+            } else {
+                state.metrics.machine_misses += 1;
+            }
+            idle
+        };
+        let core = match idle {
+            Some(mut core) => {
+                core.max_cycles = max_cycles;
+                core
+            }
+            None => key.build_machine(max_cycles),
+        };
+        PooledAsmMachine {
+            machine: Some(AsmMachine::new(build(DefaultMachineBuilder::new(core)))),
+            pool: self.clone(),
+            key,
+            decoder: None,
+            program: None,
+        }
+    }
+
+    pub fn metrics(&self) -> PoolMetrics {
+        self.state().metrics
+    }
+
+    fn state(&self) -> MutexGuard<'_, PoolState> {
+        // The state only holds idle machines and counters, it stays
+        // consistent even if a thread panicked while holding it.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn take_decoder(&self, key: &PoolKey, program: &ProgramTag) -> PoolDecoder {
+        let idle = {
+            let mut state = self.state();
+            let idle = state.decoders.get_mut(key).and_then(|decoders| {
+                let index = decoders.iter().rposition(|(hash, _)| hash == program)?;
+                decoders.remove(index).map(|(_, decoder)| decoder)
+            });
+            if idle.is_some() {
+                state.metrics.decoder_hits += 1;
+            } else {
+                state.metrics.decoder_misses += 1;
+            }
+            idle
+        };
+        idle.unwrap_or_else(|| key.build_decoder())
+    }
+
+    fn put_decoder(&self, key: PoolKey, program: ProgramTag, decoder: PoolDecoder) {
+        // Evicted decoders are dropped once the lock is released.
+        let _evicted = {
+            let mut state = self.state();
+            let decoders = state.decoders.entry(key).or_default();
+            decoders.push_back((program, decoder));
+            if decoders.len() > self.max_idle {
+                decoders.pop_front()
+            } else {
+                None
+            }
+        };
+    }
+
+    fn put_machine(&self, key: &PoolKey, mut core: Box<AsmCoreMachine>) {
+        if core.reset(0).is_err() {
+            return;
+        }
+        // Reset signals the decoder of a running machine, a machine acquired
+        // later starts with a decoder of its own.
+        core.reset_signal = 0;
+        core.running = 0;
+        let mut state = self.state();
+        let machines = state
+            .machines
+            .entry(PoolKey::of(&core, key.cycle_model))
+            .or_default();
+        if machines.len() < self.max_idle {
+            machines.push(core);
+        }
+    }
+}
+
+/// A machine acquired from an AsmMachinePool. Programs loaded through
+/// load_program are run with a pooled decoder, programs loaded through the
+/// inner machine get a new decoder on each acquire.
+pub struct PooledAsmMachine {
+    // Only taken when dropped.
+    machine: Option<AsmMachine>,
+    pool: AsmMachinePool,
+    key: PoolKey,
+    decoder: Option<PoolDecoder>,
+    // Program the decoder memoizes traces for.
+    program: Option<ProgramTag>,
+}
+
+impl PooledAsmMachine {
+    pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
+        let version = self.deref_mut().machine.version();
+        let metadata = match self.deref_mut().machine.xlen() {
+            32 => parse_elf::<u32>(program, version)?,
+            _ => parse_elf::<u64>(program, version)?,
+        };
+        self.load_program_with_metadata(program, &metadata, args)
+    }
+
+    pub fn load_program_with_metadata(
+        &mut self,
+        program: &Bytes,
+        metadata: &ProgramMetadata,
+        args: &[Bytes],
+    ) -> Result<u64, Error> {
+        let size = self
+            .deref_mut()
+            .load_program_with_metadata(program, metadata, args)?;
+        self.attach_decoder(program, metadata);
+        Ok(size)
+    }
+
+    pub fn run(&mut self) -> Result<i8, Error> {
+        let key = self.key;
+        let machine = self.machine.as_mut().expect("machine is taken on drop");
+        let mut decoder = ResetTracker {
+            decoder: self.decoder.get_or_insert_with(|| key.build_decoder()),
+            reset: false,
+        };
+        let result = machine.run_with_decoder(&mut decoder);
+        if decoder.reset {
+            // Syscalls resetting the machine might load another program, the
+            // decoder no longer holds traces for the one loaded here.
+            self.program = None;
+        }
+        result
+    }
+
+    fn attach_decoder(&mut self, program: &Bytes, metadata: &ProgramMetadata) {
+        self.release_decoder();
+        let tag = ProgramTag::new(program, metadata);
+        self.decoder = Some(self.pool.take_decoder(&self.key, &tag));
+        self.program = Some(tag);
+        // The decoder is either new or was used for this very program, loaded
+        // the same way, on a machine with the same configuration. There is no
+        // need to clear it for the reset preceding the load.
+        self.deref_mut().machine.reset_signal();
+    }
+
+    fn release_decoder(&mut self) {
+        if let (Some(decoder), Some(program)) = (self.decoder.take(), self.program.take()) {
+            self.pool.put_decoder(self.key, program, decoder);
+        }
+    }
+}
+
+impl Deref for PooledAsmMachine {
+    type Target = AsmMachine;
+
+    fn deref(&self) -> &AsmMachine {
+        self.machine.as_ref().expect("machine is taken on drop")
+    }
+}
+
+impl DerefMut for PooledAsmMachine {
+    fn deref_mut(&mut self) -> &mut AsmMachine {
+        self.machine.as_mut().expect("machine is taken on drop")
+    }
+}
+
+impl Drop for PooledAsmMachine {
+    fn drop(&mut self) {
+        self.release_decoder();
+        if let Some(machine) = self.machine.take() {
+            self.pool
+                .put_machine(&self.key, machine.machine.take_inner());
+        }
+    }
+}
+
+// Records resets of the decoder requested while running.
+struct ResetTracker<'a> {
+    decoder: &'a mut PoolDecoder,
+    reset: bool,
+}
+
+impl InstDecoder for ResetTracker<'_> {
+    fn decode<M: Memory>(&mut self, memory: &mut M, pc: u64) -> Result<Instruction, Error> {
+        self.decoder.decode(memory, pc)
+    }
+
+    fn reset_instructions_cache(&mut self) -> Result<(), Error> {
+        self.decoder.reset_instructions_cache()
+    }
+
+    fn xlen(&self) -> u8 {
+        self.decoder.xlen()
+    }
+
+    fn check_machine(&self, isa: u8, version: u32, xlen: u8) -> Result<(), Error> {
+        self.decoder.check_machine(isa, version, xlen)
+    }
+}
+
+impl TraceDecoder for ResetTracker<'_> {
+    fn fixed_traces(&self) -> *const FixedTrace {
+        self.decoder.fixed_traces()
+    }
+
+    fn fixed_trace_size(&self) -> u64 {
+        self.decoder.fixed_trace_size()
+    }
+
+    fn prepare_traces(
+        &mut self,
+        machine: &mut DefaultMachine<Box<AsmCoreMachine>>,
+    ) -> Result<(), Error> {
+        self.decoder.prepare_traces(machine)
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        self.reset = true;
+        self.decoder.reset()
+    }
+}
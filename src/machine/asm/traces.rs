@@ -1,24 +1,31 @@
+#[cfg(target_arch = "x86_64")]
+use crate::machine::asm::ckb_vm_asm_rv32_labels;
 use crate::{
     ckb_vm_definitions::{
         asm::{calculate_slot, FixedTrace, TRACE_ITEM_LENGTH, TRACE_SIZE},
         instructions::{
-            Instruction, InstructionOpcode, OP_CUSTOM_ASM_TRACE_JUMP, OP_CUSTOM_TRACE_END,
+            self as insts, instruction_metadata, Instruction, InstructionFormat, InstructionOpcode,
+            OP_CUSTOM_ASM_TRACE_JUMP, OP_CUSTOM_TRACE_END,
         },
+        RISCV_GENERAL_REGISTER_NUMBER,
     },
     decoder::InstDecoder,
     error::Error,
+    hash::blake2b_256,
     instructions::{
         blank_instruction, extract_opcode, instruction_length, is_basic_block_end_instruction,
-        is_slowpath_instruction,
+        is_slowpath_instruction, Itype, R4type, R5type, Rtype, Stype, Utype,
     },
     machine::{
         asm::{ckb_vm_asm_labels, AsmCoreMachine},
-        CoreMachine, DefaultMachine,
+        CoreMachine, DefaultMachine, InstructionCycleFunc, VERSION2,
     },
     memory::Memory,
 };
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::alloc::{alloc, alloc_zeroed, Layout};
 use std::collections::HashMap;
+use std::io::{Cursor, Read};
 
 pub trait TraceDecoder: InstDecoder {
     fn fixed_traces(&self) -> *const FixedTrace;
@@ -31,13 +38,32 @@ pub trait TraceDecoder: InstDecoder {
 }
 
 pub fn label_from_fastpath_opcode(opcode: InstructionOpcode) -> u64 {
+    label_from_fastpath_opcode_with_xlen(opcode, 64)
+}
+
+/// Same as label_from_fastpath_opcode, for instructions decoded for the
+/// given register width. RV32 instructions are only supported on x86_64.
+pub fn label_from_fastpath_opcode_with_xlen(opcode: InstructionOpcode, xlen: u8) -> u64 {
     debug_assert!(!is_slowpath_instruction(blank_instruction(opcode)));
-    unsafe {
-        u64::from(*(ckb_vm_asm_labels as *const u32).offset(opcode as u8 as isize))
-            + (ckb_vm_asm_labels as *const u32 as u64)
+    let table = label_table(xlen);
+    unsafe { u64::from(*table.offset(opcode as u8 as isize)) + (table as u64) }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn label_table(xlen: u8) -> *const u32 {
+    if xlen == 32 {
+        ckb_vm_asm_rv32_labels as *const u32
+    } else {
+        ckb_vm_asm_labels as *const u32
     }
 }
 
+#[cfg(not(target_arch = "x86_64"))]
+fn label_table(xlen: u8) -> *const u32 {
+    debug_assert_eq!(xlen, 64);
+    ckb_vm_asm_labels as *const u32
+}
+
 pub fn decode_fixed_trace<D: InstDecoder>(
     decoder: &mut D,
     machine: &mut DefaultMachine<Box<AsmCoreMachine>>,
@@ -48,6 +74,7 @@ pub fn decode_fixed_trace<D: InstDecoder>(
     let mut trace = FixedTrace::default();
     let mut current_pc = pc;
     let mut i = 0;
+    let xlen = decoder.xlen();
 
     let size = match maximum_insts {
         Some(items) => std::cmp::min(items, TRACE_ITEM_LENGTH),
@@ -61,7 +88,11 @@ pub fn decode_fixed_trace<D: InstDecoder>(
         let opcode = extract_opcode(instruction);
         // Here we are calculating the absolute address used in direct threading
         // from label offsets.
-        trace.set_thread(i, instruction, label_from_fastpath_opcode(opcode));
+        trace.set_thread(
+            i,
+            instruction,
+            label_from_fastpath_opcode_with_xlen(opcode, xlen),
+        );
         i += 1;
         if end_instruction {
             break;
@@ -135,6 +166,14 @@ impl<D: InstDecoder> InstDecoder for SimpleFixedTraceDecoder<D> {
     fn reset_instructions_cache(&mut self) -> Result<(), Error> {
         self.decoder.reset_instructions_cache()
     }
+
+    fn xlen(&self) -> u8 {
+        self.decoder.xlen()
+    }
+
+    fn check_machine(&self, isa: u8, version: u32, xlen: u8) -> Result<(), Error> {
+        self.decoder.check_machine(isa, version, xlen)
+    }
 }
 
 /// A fixed trace decoder that memorizes all traces after the initial decoding
@@ -154,6 +193,51 @@ impl<D: InstDecoder> MemoizedFixedTraceDecoder<D> {
     pub fn clear_traces(&mut self) {
         self.inner.clear_traces();
     }
+
+    /// Serializes the memoized traces, so a later process can start with
+    /// them through from_bytes. Labels are not part of the result, they are
+    /// recomputed on load. The result is bound to the program, ISA and
+    /// VERSION the traces were decoded for.
+    pub fn to_bytes(&self, program: &[u8], isa: u8, version: u32) -> Vec<u8> {
+        let mut buf = TraceCacheWriter::new(program, isa, version, self.inner.decoder.xlen());
+        let mut addresses: Vec<u64> = self.cache.keys().copied().collect();
+        addresses.sort_unstable();
+        buf.write_count(addresses.len());
+        for address in addresses {
+            let (instructions, _) = fixed_trace_instructions(&self.cache[&address]);
+            buf.write_trace(&self.cache[&address], &instructions);
+        }
+        buf.finish()
+    }
+
+    /// Restores traces serialized by to_bytes. Caches written for another
+    /// program, ISA, VERSION or register width are rejected. Cycles are not
+    /// stored, they are computed with the cycle function of the machine the
+    /// traces will run on.
+    pub fn from_bytes(
+        decoder: D,
+        data: &[u8],
+        program: &[u8],
+        isa: u8,
+        version: u32,
+        cycle_func: &InstructionCycleFunc,
+    ) -> Result<Self, Error> {
+        let mut reader = TraceCacheReader::new(data, program, isa, version, decoder.xlen())?;
+        let mut cache = HashMap::default();
+        for _ in 0..reader.read_count()? {
+            let (address, length, instructions) = reader.read_trace(TRACE_ITEM_LENGTH)?;
+            let cycles = instructions.iter().map(|i| cycle_func(*i)).sum();
+            let trace = build_fixed_trace(address, length, cycles, &instructions, decoder.xlen());
+            if cache.insert(address, trace).is_some() {
+                return Err(trace_cache_error("duplicate trace"));
+            }
+        }
+        reader.finish()?;
+        Ok(Self {
+            inner: SimpleFixedTraceDecoder::new(decoder),
+            cache,
+        })
+    }
 }
 
 impl<D: InstDecoder> TraceDecoder for MemoizedFixedTraceDecoder<D> {
@@ -197,6 +281,210 @@ impl<D: InstDecoder> InstDecoder for MemoizedFixedTraceDecoder<D> {
     fn reset_instructions_cache(&mut self) -> Result<(), Error> {
         self.inner.reset_instructions_cache()
     }
+
+    fn xlen(&self) -> u8 {
+        self.inner.xlen()
+    }
+
+    fn check_machine(&self, isa: u8, version: u32, xlen: u8) -> Result<(), Error> {
+        self.inner.check_machine(isa, version, xlen)
+    }
+}
+
+// The way a trace continues into one of its successors
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Link {
+    // Via the jump_trace field, used when a jump or branch is taken
+    Jump,
+    // Via the thread at the given index, which replaces OP_CUSTOM_TRACE_END
+    // when execution falls through to the next trace
+    Fallthrough(usize),
+}
+
+// Statically known successors of a trace, `count` is the number of threads
+// including the final OP_CUSTOM_TRACE_END. Jump targets of RV32 code wrap
+// around at 32 bits.
+fn trace_successors(trace: &FixedTrace, count: usize, xlen: u8) -> Vec<(u64, Link)> {
+    let (inst, _) = match trace.thread(count.wrapping_sub(2)) {
+        Some(thread) => thread,
+        None => return vec![],
+    };
+    let next_pc = trace.address + u64::from(trace.length);
+    let pc = next_pc - u64::from(instruction_length(inst));
+    let mask = if xlen == 32 {
+        u64::from(u32::MAX)
+    } else {
+        u64::MAX
+    };
+    let relative = |imm: i32| pc.wrapping_add(i64::from(imm) as u64) & mask;
+    let fallthrough = (next_pc, Link::Fallthrough(count - 1));
+    match extract_opcode(inst) {
+        insts::OP_BEQ
+        | insts::OP_BNE
+        | insts::OP_BLT
+        | insts::OP_BGE
+        | insts::OP_BLTU
+        | insts::OP_BGEU => vec![
+            (relative(Stype(inst).immediate_s()), Link::Jump),
+            fallthrough,
+        ],
+        insts::OP_SLT_BNEZ | insts::OP_SLT_BEQZ | insts::OP_SLTU_BNEZ | insts::OP_SLTU_BEQZ => {
+            vec![
+                (relative(Rtype(inst).immediate_s()), Link::Jump),
+                fallthrough,
+            ]
+        }
+        insts::OP_JAL => vec![(relative(Utype(inst).immediate_s()), Link::Jump)],
+        insts::OP_FAR_JUMP_REL => vec![(relative(Utype(inst).immediate_s()) & !1, Link::Jump)],
+        insts::OP_FAR_JUMP_ABS => {
+            vec![(
+                i64::from(Utype(inst).immediate_s()) as u64 & !1 & mask,
+                Link::Jump,
+            )]
+        }
+        insts::OP_AUIPC => vec![fallthrough],
+        _ if !is_basic_block_end_instruction(inst) => vec![fallthrough],
+        _ => vec![],
+    }
+}
+
+/// A memoized fixed trace decoder that chains traces together: whenever
+/// both a trace and one of its statically known successors (branch targets,
+/// fallthrough code and jal targets) are decoded, the trace is patched to
+/// continue into the successor directly, skipping the trace table lookup.
+///
+/// Memoized traces are kept at stable heap addresses so they can point at
+/// each other, all of them are dropped by `reset`, which runs whenever the
+/// machine raises `reset_signal`.
+///
+/// Chaining is opt-in: AsmMachine::run keeps using SimpleFixedTraceDecoder,
+/// pass this decoder to run_with_decoder to enable it.
+pub struct ChainedTraceDecoder<D: InstDecoder> {
+    inner: SimpleFixedTraceDecoder<D>,
+    cache: HashMap<u64, Box<FixedTrace>>,
+    // Links waiting for the trace at the key address to be decoded, each
+    // entry holds the address of the trace to patch.
+    pending: HashMap<u64, Vec<(u64, Link)>>,
+}
+
+impl<D: InstDecoder> ChainedTraceDecoder<D> {
+    pub fn new(decoder: D) -> Self {
+        Self {
+            inner: SimpleFixedTraceDecoder::new(decoder),
+            cache: HashMap::default(),
+            pending: HashMap::default(),
+        }
+    }
+
+    pub fn clear_traces(&mut self) {
+        self.inner.clear_traces();
+    }
+
+    /// Number of links patched into the memoized traces.
+    pub fn chained_count(&self) -> usize {
+        self.cache
+            .values()
+            .map(|trace| {
+                let fallthrough = (0..=TRACE_ITEM_LENGTH)
+                    .filter_map(|i| trace.thread(i))
+                    .filter(|(_, label)| {
+                        *label == label_from_fastpath_opcode(OP_CUSTOM_ASM_TRACE_JUMP)
+                    })
+                    .count();
+                fallthrough + usize::from(trace.jump_trace != 0)
+            })
+            .sum()
+    }
+
+    fn link(&mut self, from: u64, link: Link, to: u64) {
+        let target = match self.cache.get(&to) {
+            Some(trace) => trace.as_ref() as *const FixedTrace as u64,
+            None => return,
+        };
+        if let Some(trace) = self.cache.get_mut(&from) {
+            match link {
+                Link::Jump => trace.jump_trace = target,
+                Link::Fallthrough(i) => trace.set_thread(
+                    i,
+                    target,
+                    label_from_fastpath_opcode(OP_CUSTOM_ASM_TRACE_JUMP),
+                ),
+            }
+        }
+    }
+
+    fn insert(&mut self, trace: FixedTrace, count: usize, version: u32) {
+        let pc = trace.address;
+        let successors = trace_successors(&trace, count, self.xlen());
+        self.cache.insert(pc, Box::new(trace));
+        for (target, link) in successors {
+            // Before VERSION2 the trace table is matched by the lower 32 bits
+            // of pc, chaining to targets beyond that would bypass the
+            // behavior.
+            if version < VERSION2 && target > u64::from(u32::MAX) {
+                continue;
+            }
+            if self.cache.contains_key(&target) {
+                self.link(pc, link, target);
+            } else {
+                self.pending.entry(target).or_default().push((pc, link));
+            }
+        }
+        for (from, link) in self.pending.remove(&pc).unwrap_or_default() {
+            self.link(from, link, pc);
+        }
+    }
+}
+
+impl<D: InstDecoder> TraceDecoder for ChainedTraceDecoder<D> {
+    fn fixed_traces(&self) -> *const FixedTrace {
+        self.inner.fixed_traces()
+    }
+
+    fn fixed_trace_size(&self) -> u64 {
+        self.inner.fixed_trace_size()
+    }
+
+    fn prepare_traces(
+        &mut self,
+        machine: &mut DefaultMachine<Box<AsmCoreMachine>>,
+    ) -> Result<(), Error> {
+        let pc = *machine.pc();
+        let slot = calculate_slot(pc);
+        if !self.cache.contains_key(&pc) {
+            let (trace, count) = decode_fixed_trace(&mut self.inner.decoder, machine, None)?;
+            self.insert(trace, count, machine.version());
+        }
+        self.inner.traces[slot] = self.cache[&pc].as_ref().clone();
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        // The trace table still points at memoized traces, it must be
+        // cleared before they are dropped.
+        self.inner.reset()?;
+        self.pending.clear();
+        self.cache.clear();
+        Ok(())
+    }
+}
+
+impl<D: InstDecoder> InstDecoder for ChainedTraceDecoder<D> {
+    fn decode<M: Memory>(&mut self, memory: &mut M, pc: u64) -> Result<Instruction, Error> {
+        self.inner.decode(memory, pc)
+    }
+
+    fn reset_instructions_cache(&mut self) -> Result<(), Error> {
+        self.inner.reset_instructions_cache()
+    }
+
+    fn xlen(&self) -> u8 {
+        self.inner.xlen()
+    }
+
+    fn check_machine(&self, isa: u8, version: u32, xlen: u8) -> Result<(), Error> {
+        self.inner.check_machine(isa, version, xlen)
+    }
 }
 
 /// This is similar to FixedTrace, except that it uses a special pattern
@@ -207,9 +495,9 @@ impl<D: InstDecoder> InstDecoder for MemoizedFixedTraceDecoder<D> {
 /// DynamicTraceBuilder, which builds `Box<DynamicTrace>`. This is due to the
 /// reasons that we might have a variable number of `threads` allocated at the
 /// end of this data structure. So on the surface, it might appear that a
-/// struct of DynamicTrace is 24 bytes. In reality it is bigger than this:
+/// struct of DynamicTrace is 32 bytes. In reality it is bigger than this:
 /// a struct of DynamicTrace containing 30 opcodes could be
-/// 24 + 30 * 16 + 16 = 520 bytes long(the final 16 bytes are for OP_CUSTOM_TRACE_END).
+/// 32 + 30 * 16 + 16 = 528 bytes long(the final 16 bytes are for OP_CUSTOM_TRACE_END).
 /// Using `Box<DynamicTrace>`, together with `DynamicTraceBuilder`, helps us to
 /// abstract this details away when we are constructing the types. The underlying
 /// assembly code cannot tell the difference between DynamicTrace and FixedTrace.
@@ -218,21 +506,45 @@ pub struct DynamicTrace {
     address: u64,
     length: u32,
     cycles: u64,
+    jump_trace: u64,
+}
+
+impl DynamicTrace {
+    // Instructions of the trace, without the final OP_CUSTOM_TRACE_END.
+    fn instructions(&self) -> Vec<Instruction> {
+        let threads = unsafe {
+            (self as *const DynamicTrace as *const u8).add(std::mem::size_of::<DynamicTrace>())
+        } as *const u64;
+        let mut instructions = vec![];
+        loop {
+            let instruction = unsafe { threads.add(instructions.len() * 2 + 1).read() };
+            if extract_opcode(instruction) == OP_CUSTOM_TRACE_END {
+                return instructions;
+            }
+            instructions.push(instruction);
+        }
+    }
 }
 
 pub struct DynamicTraceBuilder {
     start_address: u64,
     length: u32,
     cycles: u64,
+    xlen: u8,
     insts: Vec<(Instruction, u64)>,
 }
 
 impl DynamicTraceBuilder {
     pub fn new(start_address: u64) -> Self {
+        Self::new_with_xlen(start_address, 64)
+    }
+
+    pub fn new_with_xlen(start_address: u64, xlen: u8) -> Self {
         Self {
             start_address,
             length: 0,
             cycles: 0,
+            xlen,
             insts: vec![],
         }
     }
@@ -245,7 +557,7 @@ impl DynamicTraceBuilder {
         let opcode = extract_opcode(inst);
         // Here we are calculating the absolute address used in direct threading
         // from label offsets.
-        let label = label_from_fastpath_opcode(opcode);
+        let label = label_from_fastpath_opcode_with_xlen(opcode, self.xlen);
         self.length += u32::from(instruction_length(inst));
         self.cycles += cycles;
         self.insts.push((inst, label));
@@ -273,6 +585,7 @@ impl DynamicTraceBuilder {
         trace.address = self.start_address;
         trace.length = self.length;
         trace.cycles = self.cycles;
+        trace.jump_trace = 0;
         trace
     }
 }
@@ -298,6 +611,100 @@ impl<D: InstDecoder> MemoizedDynamicTraceDecoder<D> {
         self.inner.clear_traces();
     }
 
+    /// Serializes the memoized fixed and dynamic traces, see
+    /// MemoizedFixedTraceDecoder::to_bytes. Links from fixed traces to
+    /// dynamic traces are kept as addresses.
+    pub fn to_bytes(&self, program: &[u8], isa: u8, version: u32) -> Vec<u8> {
+        let mut buf = TraceCacheWriter::new(program, isa, version, self.inner.decoder.xlen());
+        let mut addresses: Vec<u64> = self.fixed_cache.keys().copied().collect();
+        addresses.sort_unstable();
+        buf.write_count(addresses.len());
+        for address in addresses {
+            let trace = &self.fixed_cache[&address];
+            let (instructions, link) = fixed_trace_instructions(trace);
+            buf.write_trace(trace, &instructions);
+            // The link points at one of the dynamic traces owned by this
+            // decoder.
+            let target = link.map_or(0, |p| unsafe { (*(p as *const DynamicTrace)).address });
+            buf.write_u64(target);
+        }
+        let mut addresses: Vec<u64> = self.dynamic_cache.keys().copied().collect();
+        addresses.sort_unstable();
+        buf.write_count(addresses.len());
+        for address in addresses {
+            let trace = &self.dynamic_cache[&address];
+            let instructions = trace.instructions();
+            buf.write_u64(trace.address);
+            buf.write_u32(trace.length);
+            buf.write_count(instructions.len());
+            for instruction in instructions {
+                buf.write_u64(instruction);
+            }
+        }
+        buf.finish()
+    }
+
+    /// Restores traces serialized by to_bytes, with the same requirements as
+    /// MemoizedFixedTraceDecoder::from_bytes.
+    pub fn from_bytes(
+        decoder: D,
+        data: &[u8],
+        program: &[u8],
+        isa: u8,
+        version: u32,
+        cycle_func: &InstructionCycleFunc,
+    ) -> Result<Self, Error> {
+        let mut reader = TraceCacheReader::new(data, program, isa, version, decoder.xlen())?;
+        let mut fixed = vec![];
+        for _ in 0..reader.read_count()? {
+            let (address, length, instructions) = reader.read_trace(TRACE_ITEM_LENGTH)?;
+            let cycles = instructions.iter().map(|i| cycle_func(*i)).sum();
+            let target = reader.read_u64()?;
+            fixed.push((
+                build_fixed_trace(address, length, cycles, &instructions, decoder.xlen()),
+                instructions.len(),
+                target,
+            ));
+        }
+        let mut dynamic_cache = HashMap::default();
+        for _ in 0..reader.read_count()? {
+            let (address, length, instructions) = reader.read_trace(usize::MAX)?;
+            let mut builder = DynamicTraceBuilder::new_with_xlen(address, decoder.xlen());
+            for instruction in instructions {
+                builder.push(instruction, cycle_func(instruction));
+            }
+            debug_assert_eq!(builder.length, length);
+            if dynamic_cache.insert(address, builder.build()).is_some() {
+                return Err(trace_cache_error("duplicate trace"));
+            }
+        }
+        reader.finish()?;
+        let mut fixed_cache = HashMap::default();
+        for (mut trace, count, target) in fixed {
+            if target != 0 {
+                let dynamic_trace: &DynamicTrace = dynamic_cache
+                    .get(&target)
+                    .ok_or_else(|| trace_cache_error("missing dynamic trace"))?;
+                if target != trace.address + u64::from(trace.length) {
+                    return Err(trace_cache_error("invalid dynamic trace link"));
+                }
+                trace.set_thread(
+                    count,
+                    dynamic_trace as *const DynamicTrace as u64,
+                    label_from_fastpath_opcode(OP_CUSTOM_ASM_TRACE_JUMP),
+                );
+            }
+            if fixed_cache.insert(trace.address, trace).is_some() {
+                return Err(trace_cache_error("duplicate trace"));
+            }
+        }
+        Ok(Self {
+            inner: SimpleFixedTraceDecoder::new(decoder),
+            fixed_cache,
+            dynamic_cache,
+        })
+    }
+
     fn find_or_build_dynamic_trace(
         &mut self,
         pc: u64,
@@ -306,7 +713,7 @@ impl<D: InstDecoder> MemoizedDynamicTraceDecoder<D> {
         if let Some(trace) = self.dynamic_cache.get(&pc) {
             return Ok(trace.as_ref() as *const DynamicTrace);
         }
-        let mut builder = DynamicTraceBuilder::new(pc);
+        let mut builder = DynamicTraceBuilder::new_with_xlen(pc, self.xlen());
         loop {
             let instruction = self.decode(machine.memory_mut(), builder.next_pc())?;
             let end_instruction = is_basic_block_end_instruction(instruction);
@@ -381,6 +788,272 @@ impl<D: InstDecoder> InstDecoder for MemoizedDynamicTraceDecoder<D> {
     fn reset_instructions_cache(&mut self) -> Result<(), Error> {
         self.inner.reset_instructions_cache()
     }
+
+    fn xlen(&self) -> u8 {
+        self.inner.xlen()
+    }
+
+    fn check_machine(&self, isa: u8, version: u32, xlen: u8) -> Result<(), Error> {
+        self.inner.check_machine(isa, version, xlen)
+    }
+}
+
+const TRACE_CACHE_MAGIC: &[u8; 8] = b"CKBVMTRC";
+// Bump whenever the layout of serialized trace caches changes. Caches are
+// also bound to the crate version, since decoded instructions are an
+// internal representation.
+const TRACE_CACHE_FORMAT_VERSION: u32 = 3;
+
+fn trace_cache_error(message: &str) -> Error {
+    Error::TraceCache(String::from(message))
+}
+
+fn trace_cache_checksum(data: &[u8]) -> [u8; 32] {
+    blake2b_256(b"ckb-vm-traces", data)
+}
+
+fn trace_cache_program_hash(program: &[u8]) -> [u8; 32] {
+    blake2b_256(b"ckb-vm-trc-prog", program)
+}
+
+// Instructions of a fixed trace, without the final thread, together with
+// the dynamic trace the final thread jumps to, if any. A jump to a dynamic
+// trace keeps the trace pointer in place of the instruction, so it is told
+// apart by its label.
+fn fixed_trace_instructions(trace: &FixedTrace) -> (Vec<Instruction>, Option<u64>) {
+    let trace_jump_label = label_from_fastpath_opcode(OP_CUSTOM_ASM_TRACE_JUMP);
+    let mut instructions = vec![];
+    while let Some((instruction, label)) = trace.thread(instructions.len()) {
+        if label == trace_jump_label {
+            return (instructions, Some(instruction));
+        }
+        if extract_opcode(instruction) == OP_CUSTOM_TRACE_END {
+            return (instructions, None);
+        }
+        instructions.push(instruction);
+    }
+    (instructions, None)
+}
+
+fn build_fixed_trace(
+    address: u64,
+    length: u32,
+    cycles: u64,
+    instructions: &[Instruction],
+    xlen: u8,
+) -> FixedTrace {
+    let mut trace = FixedTrace {
+        address,
+        length,
+        cycles,
+        ..FixedTrace::default()
+    };
+    for (i, instruction) in instructions.iter().enumerate() {
+        trace.set_thread(
+            i,
+            *instruction,
+            label_from_fastpath_opcode_with_xlen(extract_opcode(*instruction), xlen),
+        );
+    }
+    trace.set_thread(
+        instructions.len(),
+        blank_instruction(OP_CUSTOM_TRACE_END),
+        label_from_fastpath_opcode(OP_CUSTOM_TRACE_END),
+    );
+    trace
+}
+
+// Checks an instruction read from a serialized cache can be run by the
+// assembly code: it must have a fastpath opcode of a real instruction, and
+// its register operands, used as indices by the assembly code, must stay
+// within the register file.
+fn check_cached_instruction(instruction: Instruction) -> Result<(), Error> {
+    let opcode = extract_opcode(instruction);
+    let metadata = match instruction_metadata(opcode) {
+        Some(metadata) if !is_slowpath_instruction(instruction) => metadata,
+        _ => return Err(trace_cache_error("invalid opcode")),
+    };
+    if opcode == OP_CUSTOM_TRACE_END
+        || opcode == OP_CUSTOM_ASM_TRACE_JUMP
+        || instruction_length(instruction) == 0
+    {
+        return Err(trace_cache_error("invalid opcode"));
+    }
+    let registers = match metadata.format {
+        InstructionFormat::Rtype => {
+            let i = Rtype(instruction);
+            vec![i.rd(), i.rs1(), i.rs2()]
+        }
+        InstructionFormat::R4type => {
+            let i = R4type(instruction);
+            vec![i.rd(), i.rs1(), i.rs2(), i.rs3()]
+        }
+        InstructionFormat::R5type => {
+            let i = R5type(instruction);
+            vec![i.rd(), i.rs1(), i.rs2(), i.rs3(), i.rs4()]
+        }
+        InstructionFormat::Itype => {
+            let i = Itype(instruction);
+            vec![i.rd(), i.rs1()]
+        }
+        InstructionFormat::Stype => {
+            let i = Stype(instruction);
+            vec![i.rs1(), i.rs2()]
+        }
+        InstructionFormat::Utype => vec![Utype(instruction).rd()],
+    };
+    if registers
+        .iter()
+        .any(|r| *r >= RISCV_GENERAL_REGISTER_NUMBER)
+    {
+        return Err(trace_cache_error("invalid register"));
+    }
+    Ok(())
+}
+
+struct TraceCacheWriter {
+    buf: Vec<u8>,
+}
+
+impl TraceCacheWriter {
+    fn new(program: &[u8], isa: u8, version: u32, xlen: u8) -> Self {
+        let mut writer = Self { buf: vec![] };
+        writer.buf.extend_from_slice(TRACE_CACHE_MAGIC);
+        writer.write_u32(TRACE_CACHE_FORMAT_VERSION);
+        let crate_version = env!("CARGO_PKG_VERSION").as_bytes();
+        writer.write_u32(crate_version.len() as u32);
+        writer.buf.extend_from_slice(crate_version);
+        writer
+            .buf
+            .extend_from_slice(&trace_cache_program_hash(program));
+        writer.buf.push(isa);
+        writer.write_u32(version);
+        writer.buf.push(xlen);
+        writer
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.buf.write_u32::<LittleEndian>(value).unwrap();
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.buf.write_u64::<LittleEndian>(value).unwrap();
+    }
+
+    fn write_count(&mut self, count: usize) {
+        self.write_u32(count as u32);
+    }
+
+    fn write_trace(&mut self, trace: &FixedTrace, instructions: &[Instruction]) {
+        self.write_u64(trace.address);
+        self.write_u32(trace.length);
+        self.write_count(instructions.len());
+        for instruction in instructions {
+            self.write_u64(*instruction);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let checksum = trace_cache_checksum(&self.buf);
+        self.buf.extend_from_slice(&checksum);
+        self.buf
+    }
+}
+
+struct TraceCacheReader<'a> {
+    reader: Cursor<&'a [u8]>,
+}
+
+impl<'a> TraceCacheReader<'a> {
+    fn new(data: &'a [u8], program: &[u8], isa: u8, version: u32, xlen: u8) -> Result<Self, Error> {
+        if data.len() < TRACE_CACHE_MAGIC.len() + 32 {
+            return Err(trace_cache_error("too short"));
+        }
+        let (body, checksum) = data.split_at(data.len() - 32);
+        if trace_cache_checksum(body) != checksum {
+            return Err(trace_cache_error("checksum mismatch"));
+        }
+        let mut reader = Self {
+            reader: Cursor::new(body),
+        };
+        let mut magic = [0u8; 8];
+        reader.reader.read_exact(&mut magic)?;
+        if &magic != TRACE_CACHE_MAGIC || reader.read_u32()? != TRACE_CACHE_FORMAT_VERSION {
+            return Err(trace_cache_error("unknown format"));
+        }
+        let length = reader.read_u32()? as usize;
+        if length > 64 {
+            return Err(trace_cache_error("unknown format"));
+        }
+        let mut crate_version = vec![0; length];
+        reader.reader.read_exact(&mut crate_version)?;
+        if crate_version != env!("CARGO_PKG_VERSION").as_bytes() {
+            return Err(trace_cache_error("crate version mismatch"));
+        }
+        let mut program_hash = [0u8; 32];
+        reader.reader.read_exact(&mut program_hash)?;
+        if program_hash != trace_cache_program_hash(program) {
+            return Err(trace_cache_error("program mismatch"));
+        }
+        if reader.reader.read_u8()? != isa
+            || reader.read_u32()? != version
+            || reader.reader.read_u8()? != xlen
+        {
+            return Err(trace_cache_error("machine mismatch"));
+        }
+        Ok(reader)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        Ok(self.reader.read_u32::<LittleEndian>()?)
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Error> {
+        Ok(self.reader.read_u64::<LittleEndian>()?)
+    }
+
+    // Reads a count of items, each of them taking at least 8 bytes, so
+    // damaged counts are rejected before allocating.
+    fn read_count(&mut self) -> Result<usize, Error> {
+        let count = self.read_u32()? as u64;
+        let remaining = self.reader.get_ref().len() as u64 - self.reader.position();
+        if count > remaining / 8 + 1 {
+            return Err(trace_cache_error("truncated"));
+        }
+        Ok(count as usize)
+    }
+
+    fn read_trace(&mut self, maximum_insts: usize) -> Result<(u64, u32, Vec<Instruction>), Error> {
+        let address = self.read_u64()?;
+        let length = self.read_u32()?;
+        let count = self.read_count()?;
+        if count == 0 || count > maximum_insts {
+            return Err(trace_cache_error("invalid trace"));
+        }
+        let mut instructions = Vec::with_capacity(count);
+        let mut actual_length: u64 = 0;
+        for i in 0..count {
+            let instruction = self.read_u64()?;
+            check_cached_instruction(instruction)?;
+            // Only the last instruction may end a basic block.
+            if i + 1 < count && is_basic_block_end_instruction(instruction) {
+                return Err(trace_cache_error("invalid trace"));
+            }
+            actual_length += u64::from(instruction_length(instruction));
+            instructions.push(instruction);
+        }
+        if address & 1 != 0 || actual_length != u64::from(length) {
+            return Err(trace_cache_error("invalid trace"));
+        }
+        Ok((address, length, instructions))
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        if self.reader.position() != self.reader.get_ref().len() as u64 {
+            return Err(trace_cache_error("trailing data"));
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -408,6 +1081,10 @@ mod tests {
             (&f.cycles as *const _ as usize) - f_address,
             (&d.cycles as *const _ as usize) - d_address,
         );
+        assert_eq!(
+            (&f.jump_trace as *const _ as usize) - f_address,
+            (&d.jump_trace as *const _ as usize) - d_address,
+        );
         assert_eq!(
             (&f._threads as *const _ as usize) - f_address,
             size_of::<DynamicTrace>(),
@@ -1,3 +1,5 @@
+#[cfg(all(has_asm, any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub mod aot;
 #[cfg(has_asm)]
 pub mod asm;
 pub mod trace;
@@ -10,12 +12,16 @@ use bytes::Bytes;
 
 use super::debugger::Debugger;
 use super::decoder::{build_decoder, InstDecoder};
-use super::elf::{parse_elf, LoadingAction, ProgramMetadata};
+use super::elf::{
+    apply_relocations, auxiliary_vector, check_isa, parse_elf, LoadingAction, ProgramMetadata,
+    AT_NULL, AT_RANDOM,
+};
 use super::instructions::{execute, Instruction, Register};
+use super::isa::syscall_number_register;
 use super::memory::Memory;
 use super::syscalls::Syscalls;
 use super::{
-    registers::{A0, A7, REGISTER_ABI_NAMES, SP},
+    registers::{A0, REGISTER_ABI_NAMES, SP},
     Error, ISA_MOP, RISCV_GENERAL_REGISTER_NUMBER,
 };
 
@@ -29,6 +35,31 @@ pub const VERSION0: u32 = 0;
 // * https://github.com/nervosnetwork/ckb-vm/issues/106
 pub const VERSION1: u32 = 1;
 pub const VERSION2: u32 = 2;
+// Version 3 adds features that change what a program observes, hence they
+// cannot be enabled in earlier versions:
+// * Opt-in initial stack layout containing envp and auxv, see
+//   SupportMachine::initialize_stack_with_env
+// * Macro-op fusion of slli + add(i), lui + addi, ld / sd pairs on adjacent
+//   offsets and slt(u) + beqz / bnez, see crate::fusion
+pub const VERSION3: u32 = 3;
+
+/// Data written by the extended stack layout on top of argc / argv.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StackEnv {
+    pub envs: Vec<Bytes>,
+    // The 16 bytes AT_RANDOM points to. CKB-VM must stay deterministic, so
+    // it is up to the caller to decide on the content.
+    pub random: [u8; 16],
+}
+
+impl StackEnv {
+    pub fn new(envs: &[Bytes]) -> Self {
+        Self {
+            envs: envs.to_vec(),
+            random: [0; 16],
+        }
+    }
+}
 
 /// This is the core part of RISC-V that only deals with data part, it
 /// is extracted from Machine so we can handle lifetime logic in dynamic
@@ -49,6 +80,12 @@ pub trait CoreMachine {
     // in case of bug fixes.
     fn version(&self) -> u32;
     fn isa(&self) -> u8;
+
+    // Width of the emulated RISC-V registers, in bits. It only differs from
+    // REG::BITS for machines running RV32 programs with 64-bit registers.
+    fn xlen(&self) -> u8 {
+        Self::REG::BITS
+    }
 }
 
 /// This is the core trait describing a full RISC-V machine. Instruction
@@ -78,6 +115,12 @@ pub trait SupportMachine: CoreMachine {
     fn reset(&mut self, max_cycles: u64) -> Result<(), Error>;
     fn reset_signal(&mut self) -> bool;
 
+    // Number of the syscall requested by ecall, read from a7, or from t0 on
+    // RV32E/RV64E machines which lack a7.
+    fn syscall_number(&self) -> u64 {
+        self.registers()[syscall_number_register(self.isa())].to_u64()
+    }
+
     fn add_cycles(&mut self, cycles: u64) -> Result<(), Error> {
         let new_cycles = self
             .cycles()
@@ -101,7 +144,10 @@ pub trait SupportMachine: CoreMachine {
 
     fn load_elf_inner(&mut self, program: &Bytes, update_pc: bool) -> Result<u64, Error> {
         let version = self.version();
-        let metadata = parse_elf::<Self::REG>(program, version)?;
+        let metadata = match self.xlen() {
+            32 => parse_elf::<u32>(program, version)?,
+            _ => parse_elf::<u64>(program, version)?,
+        };
         self.load_binary(program, &metadata, update_pc)
     }
 
@@ -134,15 +180,16 @@ pub trait SupportMachine: CoreMachine {
                 flags,
                 source,
                 offset_from_addr,
+                relocations,
             } = action;
 
-            self.memory_mut().init_pages(
-                *addr,
-                *size,
-                *flags,
-                Some(program.slice(source.start as usize..source.end as usize)),
-                *offset_from_addr,
-            )?;
+            let program = program.slice(source.start as usize..source.end as usize);
+            let data = match self.xlen() {
+                32 => apply_relocations::<u32>(program, *addr + *offset_from_addr, relocations)?,
+                _ => apply_relocations::<u64>(program, *addr + *offset_from_addr, relocations)?,
+            };
+            self.memory_mut()
+                .init_pages(*addr, *size, *flags, Some(data), *offset_from_addr)?;
             if version < VERSION1 {
                 self.memory_mut().store_byte(*addr, *offset_from_addr, 0)?;
             }
@@ -184,7 +231,7 @@ pub trait SupportMachine: CoreMachine {
         //
         // See https://github.com/nervosnetwork/ckb-vm/issues/106 for more details.
         if self.version() >= VERSION1 && args.is_empty() {
-            let argc_size = u64::from(Self::REG::BITS / 8);
+            let argc_size = u64::from(self.xlen() / 8);
             let origin_sp = stack_start + stack_size;
             let unaligned_sp_address = origin_sp - argc_size;
             let aligned_sp_address = unaligned_sp_address & (!15);
@@ -219,7 +266,7 @@ pub trait SupportMachine: CoreMachine {
             // will read argc from SP and argv from SP + 8, we have to factor in
             // alignment here first, then push the values.
             let values_bytes =
-                Self::REG::from_u64(Self::REG::BITS as u64 / 8 * values.len() as u64);
+                Self::REG::from_u64(u64::from(self.xlen() / 8) * values.len() as u64);
             let unaligned_sp_address = self.registers()[SP].overflowing_sub(&values_bytes).to_u64();
             // Perform alignment at 16-byte boundary towards lower address
             let aligned_sp_address = unaligned_sp_address & (!15);
@@ -233,9 +280,9 @@ pub trait SupportMachine: CoreMachine {
         // order
         for value in values.iter().rev() {
             let address =
-                self.registers()[SP].overflowing_sub(&Self::REG::from_u8(Self::REG::BITS / 8));
+                self.registers()[SP].overflowing_sub(&Self::REG::from_u8(self.xlen() / 8));
             if self.version() >= VERSION1 {
-                if Self::REG::BITS == 64 {
+                if self.xlen() == 64 {
                     self.memory_mut().store64(&address, value)?;
                 } else {
                     self.memory_mut().store32(&address, value)?;
@@ -252,6 +299,76 @@ pub trait SupportMachine: CoreMachine {
         Ok(stack_start + stack_size - self.registers()[SP].to_u64())
     }
 
+    /// Lays out the stack the way standard C runtimes expect it: argc, argv,
+    /// a NULL pointer, envp, another NULL pointer, then the auxiliary vector.
+    /// `auxv` is usually built by elf::auxiliary_vector, AT_RANDOM and AT_NULL
+    /// are appended here. The layout is only available since VERSION3, so
+    /// programs running in earlier versions keep seeing the exact same stack.
+    fn initialize_stack_with_env(
+        &mut self,
+        args: &[Bytes],
+        env: &StackEnv,
+        auxv: &[(u64, u64)],
+        stack_start: u64,
+        stack_size: u64,
+    ) -> Result<u64, Error> {
+        if self.version() < VERSION3 {
+            return Err(Error::InvalidVersion);
+        }
+        let stack_end = stack_start + stack_size;
+        self.set_register(SP, Self::REG::from_u64(stack_end));
+        let push_bytes = |machine: &mut Self, data: &[u8], nul: bool| -> Result<u64, Error> {
+            let size = data.len() as u64 + u64::from(nul);
+            let address = machine.registers()[SP].overflowing_sub(&Self::REG::from_u64(size));
+            if address.to_u64() < stack_start || address.to_u64() > stack_end {
+                return Err(Error::MemOutOfStack);
+            }
+            machine.memory_mut().store_bytes(address.to_u64(), data)?;
+            if nul {
+                machine
+                    .memory_mut()
+                    .store_byte(address.to_u64() + data.len() as u64, 1, 0)?;
+            }
+            machine.set_register(SP, address.clone());
+            Ok(address.to_u64())
+        };
+        let random_address = push_bytes(self, &env.random, false)?;
+        let mut values = vec![Self::REG::from_u64(args.len() as u64)];
+        for arg in args {
+            values.push(Self::REG::from_u64(push_bytes(self, arg, true)?));
+        }
+        values.push(Self::REG::zero());
+        for e in &env.envs {
+            values.push(Self::REG::from_u64(push_bytes(self, e, true)?));
+        }
+        values.push(Self::REG::zero());
+        for (key, value) in auxv
+            .iter()
+            .chain(&[(AT_RANDOM, random_address), (AT_NULL, 0)])
+        {
+            values.push(Self::REG::from_u64(*key));
+            values.push(Self::REG::from_u64(*value));
+        }
+        // SP must be aligned to 16-byte boundary after all values are pushed.
+        let values_bytes = u64::from(self.xlen() / 8) * values.len() as u64;
+        let unaligned_sp_address = self.registers()[SP].to_u64().wrapping_sub(values_bytes);
+        let aligned_sp_address = unaligned_sp_address & (!15);
+        if aligned_sp_address < stack_start || aligned_sp_address > stack_end {
+            return Err(Error::MemOutOfStack);
+        }
+        for (i, value) in values.iter().enumerate() {
+            let address =
+                Self::REG::from_u64(aligned_sp_address + u64::from(self.xlen() / 8) * i as u64);
+            if self.xlen() == 64 {
+                self.memory_mut().store64(&address, value)?;
+            } else {
+                self.memory_mut().store32(&address, value)?;
+            }
+        }
+        self.set_register(SP, Self::REG::from_u64(aligned_sp_address));
+        Ok(stack_end - aligned_sp_address)
+    }
+
     #[cfg(feature = "pprof")]
     fn code(&self) -> &Bytes;
 }
@@ -428,6 +545,7 @@ pub struct DefaultMachine<Inner> {
     debugger: Option<Box<dyn Debugger<Inner>>>,
     syscalls: Vec<Box<dyn Syscalls<Inner>>>,
     exit_code: i8,
+    check_isa: bool,
 }
 
 impl<Inner: CoreMachine> CoreMachine for DefaultMachine<Inner> {
@@ -469,6 +587,10 @@ impl<Inner: CoreMachine> CoreMachine for DefaultMachine<Inner> {
     fn version(&self) -> u32 {
         self.inner.version()
     }
+
+    fn xlen(&self) -> u8 {
+        self.inner.xlen()
+    }
 }
 
 impl<Inner: SupportMachine> SupportMachine for DefaultMachine<Inner> {
@@ -525,7 +647,7 @@ impl<Inner: SupportMachine> SupportMachine for DefaultMachine<Inner> {
 
 impl<Inner: SupportMachine> Machine for DefaultMachine<Inner> {
     fn ecall(&mut self) -> Result<(), Error> {
-        let code = self.registers()[A7].to_u64();
+        let code = self.syscall_number();
         match code {
             93 => {
                 // exit
@@ -576,6 +698,7 @@ impl<Inner: CoreMachine> Display for DefaultMachine<Inner> {
 
 impl<Inner: SupportMachine> DefaultMachine<Inner> {
     pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
+        self.check_program_isa(program)?;
         let elf_bytes = self.load_elf(program, true)?;
         let stack_bytes = self.initialize(args)?;
         let bytes = elf_bytes.checked_add(stack_bytes).ok_or_else(|| {
@@ -592,6 +715,7 @@ impl<Inner: SupportMachine> DefaultMachine<Inner> {
         metadata: &ProgramMetadata,
         args: &[Bytes],
     ) -> Result<u64, Error> {
+        self.check_program_isa(program)?;
         let elf_bytes = self.load_binary(program, metadata, true)?;
         let stack_bytes = self.initialize(args)?;
         let bytes = elf_bytes.checked_add(stack_bytes).ok_or_else(|| {
@@ -602,7 +726,62 @@ impl<Inner: SupportMachine> DefaultMachine<Inner> {
         Ok(bytes)
     }
 
+    /// Same as load_program, but uses the extended stack layout containing
+    /// envp and auxv. This requires VERSION3 or above.
+    pub fn load_program_with_env(
+        &mut self,
+        program: &Bytes,
+        args: &[Bytes],
+        env: &StackEnv,
+    ) -> Result<u64, Error> {
+        let metadata = match self.xlen() {
+            32 => parse_elf::<u32>(program, self.version())?,
+            _ => parse_elf::<u64>(program, self.version())?,
+        };
+        self.load_program_with_metadata_and_env(program, &metadata, args, env)
+    }
+
+    pub fn load_program_with_metadata_and_env(
+        &mut self,
+        program: &Bytes,
+        metadata: &ProgramMetadata,
+        args: &[Bytes],
+        env: &StackEnv,
+    ) -> Result<u64, Error> {
+        if self.version() < VERSION3 {
+            return Err(Error::InvalidVersion);
+        }
+        self.check_program_isa(program)?;
+        let elf_bytes = self.load_binary(program, metadata, true)?;
+        let auxv = auxiliary_vector(metadata);
+        let stack_bytes = self.initialize_with_env(args, Some((env, &auxv)))?;
+        let bytes = elf_bytes.checked_add(stack_bytes).ok_or_else(|| {
+            Error::Unexpected(String::from(
+                "The bytes count overflowed on loading program",
+            ))
+        })?;
+        Ok(bytes)
+    }
+
+    fn check_program_isa(&self, program: &Bytes) -> Result<(), Error> {
+        if self.check_isa {
+            match self.xlen() {
+                32 => check_isa::<u32>(program, self.version(), self.isa())?,
+                _ => check_isa::<u64>(program, self.version(), self.isa())?,
+            }
+        }
+        Ok(())
+    }
+
     fn initialize(&mut self, args: &[Bytes]) -> Result<u64, Error> {
+        self.initialize_with_env(args, None)
+    }
+
+    fn initialize_with_env(
+        &mut self,
+        args: &[Bytes],
+        env: Option<(&StackEnv, &[(u64, u64)])>,
+    ) -> Result<u64, Error> {
         for syscall in &mut self.syscalls {
             syscall.initialize(&mut self.inner)?;
         }
@@ -611,8 +790,13 @@ impl<Inner: SupportMachine> DefaultMachine<Inner> {
         }
         let memory_size = self.memory().memory_size();
         let stack_size = memory_size / 4;
-        let stack_bytes =
-            self.initialize_stack(args, (memory_size - stack_size) as u64, stack_size as u64)?;
+        let stack_start = (memory_size - stack_size) as u64;
+        let stack_bytes = match env {
+            Some((env, auxv)) => {
+                self.initialize_stack_with_env(args, env, auxv, stack_start, stack_size as u64)?
+            }
+            None => self.initialize_stack(args, stack_start, stack_size as u64)?,
+        };
         // Make sure SP is 16 byte aligned
         if self.inner.version() >= VERSION1 {
             debug_assert!(self.registers()[SP].to_u64() % 16 == 0);
@@ -657,6 +841,7 @@ impl<Inner: SupportMachine> DefaultMachine<Inner> {
         if self.isa() & ISA_MOP != 0 && self.version() == VERSION0 {
             return Err(Error::InvalidVersion);
         }
+        decoder.check_machine(self.isa(), self.version(), self.xlen())?;
         self.set_running(true);
         while self.running() {
             if self.pause.has_interrupted() {
@@ -689,6 +874,7 @@ pub struct DefaultMachineBuilder<Inner> {
     debugger: Option<Box<dyn Debugger<Inner>>>,
     syscalls: Vec<Box<dyn Syscalls<Inner>>>,
     pause: Pause,
+    check_isa: bool,
 }
 
 impl<Inner> DefaultMachineBuilder<Inner> {
@@ -699,6 +885,7 @@ impl<Inner> DefaultMachineBuilder<Inner> {
             debugger: None,
             syscalls: vec![],
             pause: Pause::new(),
+            check_isa: false,
         }
     }
 
@@ -725,6 +912,13 @@ impl<Inner> DefaultMachineBuilder<Inner> {
         self
     }
 
+    /// Rejects programs whose .riscv.attributes require extensions the
+    /// machine does not support when loading them. Disabled by default.
+    pub fn check_isa(mut self, check_isa: bool) -> Self {
+        self.check_isa = check_isa;
+        self
+    }
+
     pub fn build(self) -> DefaultMachine<Inner> {
         DefaultMachine {
             inner: self.inner,
@@ -733,6 +927,7 @@ impl<Inner> DefaultMachineBuilder<Inner> {
             debugger: self.debugger,
             syscalls: self.syscalls,
             exit_code: 0,
+            check_isa: self.check_isa,
         }
     }
 }
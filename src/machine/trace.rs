@@ -4,22 +4,26 @@ use super::{
         elf::ProgramMetadata,
         instructions::{
             execute_with_thread, extract_opcode, handle_invalid_op, instruction_length,
-            is_basic_block_end_instruction, Instruction, Register, Thread, ThreadFactory,
+            instruction_metadata, insts, is_basic_block_end_instruction, Instruction, Register,
+            Thread, ThreadFactory,
         },
         Error,
     },
-    CoreMachine, DefaultMachine, Machine, SupportMachine, VERSION2,
+    CoreMachine, DefaultMachine, Machine, Memory, StackEnv, SupportMachine, VERSION2,
 };
 use bytes::Bytes;
+use ckb_vm_definitions::instructions::ControlFlow;
 
 // The number of trace items to keep
-const TRACE_SIZE: usize = 8192;
+pub(crate) const TRACE_SIZE: usize = 8192;
 // Quick bit-mask to truncate a value in trace size range
 const TRACE_MASK: usize = TRACE_SIZE - 1;
 // The maximum number of instructions to cache in a trace item
-const TRACE_ITEM_LENGTH: usize = 16;
+pub(crate) const TRACE_ITEM_LENGTH: usize = 16;
 // Shifts to truncate a value so 2 traces has the minimal chance of sharing code.
 const TRACE_ADDRESS_SHIFTS: usize = 2;
+// The maximum number of instructions to cache in a superblock
+const SUPERBLOCK_LENGTH: usize = 256;
 
 struct Trace<Inner: Machine> {
     address: u64,
@@ -41,8 +45,81 @@ impl<Inner: Machine> Default for Trace<Inner> {
     }
 }
 
+// A trace of variable length which continues across the not-taken side of
+// conditional branches. A branch in the middle of a superblock keeps the
+// address it falls through to, when the branch is taken execution leaves the
+// superblock through this side exit.
+struct Superblock<Inner: Machine> {
+    address: u64,
+    instructions: Vec<Instruction>,
+    threads: Vec<Thread<Inner>>,
+    fallthroughs: Vec<Option<u64>>,
+}
+
+impl<Inner: Machine> Superblock<Inner> {
+    // Decodes a superblock starting at pc. The superblock is cut into the
+    // same segments TraceMachine would build traces from, so decoding errors
+    // in the first segment are returned like a trace would do, while an error
+    // in a later segment only truncates the superblock before that segment.
+    fn build<D: InstDecoder, M: Memory>(
+        decoder: &mut D,
+        memory: &mut M,
+        factory: &ThreadFactory<Inner>,
+        pc: u64,
+    ) -> Result<Self, Error> {
+        let mut superblock = Superblock {
+            address: pc,
+            instructions: Vec::new(),
+            threads: Vec::new(),
+            fallthroughs: Vec::new(),
+        };
+        let mut current_pc = pc;
+        let mut segment_start = 0;
+        while superblock.instructions.len() < SUPERBLOCK_LENGTH {
+            if superblock.instructions.len() - segment_start == TRACE_ITEM_LENGTH {
+                segment_start = superblock.instructions.len();
+            }
+            let instruction = match decoder.decode(memory, current_pc) {
+                Ok(instruction) => instruction,
+                Err(e) if segment_start == 0 => return Err(e),
+                Err(_) => {
+                    superblock.truncate(segment_start);
+                    break;
+                }
+            };
+            let opcode = extract_opcode(instruction);
+            current_pc += u64::from(instruction_length(instruction));
+            superblock.instructions.push(instruction);
+            superblock.threads.push(factory[opcode]);
+            if !is_basic_block_end_instruction(instruction) {
+                superblock.fallthroughs.push(None);
+                continue;
+            }
+            let branch = instruction_metadata(opcode)
+                .map(|metadata| metadata.control == ControlFlow::Branch)
+                .unwrap_or(false);
+            if branch {
+                superblock.fallthroughs.push(Some(current_pc));
+            } else if opcode == insts::OP_AUIPC {
+                superblock.fallthroughs.push(None);
+            } else {
+                superblock.fallthroughs.push(None);
+                break;
+            }
+            segment_start = superblock.instructions.len();
+        }
+        Ok(superblock)
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.instructions.truncate(len);
+        self.threads.truncate(len);
+        self.fallthroughs.truncate(len);
+    }
+}
+
 #[inline(always)]
-fn calculate_slot(addr: u64) -> usize {
+pub(crate) fn calculate_slot(addr: u64) -> usize {
     (addr as usize >> TRACE_ADDRESS_SHIFTS) & TRACE_MASK
 }
 
@@ -51,6 +128,10 @@ pub struct TraceMachine<Inner: SupportMachine> {
 
     factory: ThreadFactory<DefaultMachine<Inner>>,
     traces: Vec<Trace<DefaultMachine<Inner>>>,
+    // Superblocks share the slots of the trace table, so at most TRACE_SIZE
+    // of them are kept, a superblock is replaced by the next one decoded for
+    // its slot.
+    superblocks: Option<Vec<Option<Superblock<DefaultMachine<Inner>>>>>,
 }
 
 impl<Inner: SupportMachine> CoreMachine for TraceMachine<Inner> {
@@ -92,6 +173,10 @@ impl<Inner: SupportMachine> CoreMachine for TraceMachine<Inner> {
     fn version(&self) -> u32 {
         self.machine.version()
     }
+
+    fn xlen(&self) -> u8 {
+        self.machine.xlen()
+    }
 }
 
 impl<Inner: SupportMachine> Machine for TraceMachine<Inner> {
@@ -110,9 +195,30 @@ impl<Inner: SupportMachine> TraceMachine<Inner> {
             machine,
             factory: ThreadFactory::create(),
             traces: vec![],
+            superblocks: None,
         }
     }
 
+    /// Runs the program with superblocks instead of fixed size traces. A
+    /// superblock is memoized by its starting address, in a table of 8192
+    /// slots like traces, and continues across the not-taken side of
+    /// conditional branches, up to 256 instructions.
+    /// Results and cycles are the same as running with traces. Superblocks
+    /// are only used from VERSION2 on, earlier versions keep the trace table
+    /// so as to replicate the address truncation bug in x64 VM.
+    pub fn with_superblocks(mut self) -> Self {
+        self.superblocks = Some(vec![]);
+        self
+    }
+
+    /// Returns the number of memoized superblocks.
+    pub fn superblock_count(&self) -> usize {
+        self.superblocks
+            .as_ref()
+            .map(|s| s.iter().filter(|s| s.is_some()).count())
+            .unwrap_or(0)
+    }
+
     pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
         self.machine.load_program(program, args)
     }
@@ -127,6 +233,26 @@ impl<Inner: SupportMachine> TraceMachine<Inner> {
             .load_program_with_metadata(program, metadata, args)
     }
 
+    pub fn load_program_with_env(
+        &mut self,
+        program: &Bytes,
+        args: &[Bytes],
+        env: &StackEnv,
+    ) -> Result<u64, Error> {
+        self.machine.load_program_with_env(program, args, env)
+    }
+
+    pub fn load_program_with_metadata_and_env(
+        &mut self,
+        program: &Bytes,
+        metadata: &ProgramMetadata,
+        args: &[Bytes],
+        env: &StackEnv,
+    ) -> Result<u64, Error> {
+        self.machine
+            .load_program_with_metadata_and_env(program, metadata, args, env)
+    }
+
     pub fn set_max_cycles(&mut self, cycles: u64) {
         self.machine.inner_mut().set_max_cycles(cycles)
     }
@@ -137,6 +263,14 @@ impl<Inner: SupportMachine> TraceMachine<Inner> {
     }
 
     pub fn run_with_decoder<D: InstDecoder>(&mut self, decoder: &mut D) -> Result<i8, Error> {
+        decoder.check_machine(
+            self.machine.isa(),
+            self.machine.version(),
+            self.machine.xlen(),
+        )?;
+        if self.superblocks.is_some() && self.machine.version() >= VERSION2 {
+            return self.run_superblocks(decoder);
+        }
         self.machine.set_running(true);
         // For current trace size this is acceptable, however we might want
         // to tweak the code here if we choose to use a larger trace size or
@@ -193,6 +327,45 @@ impl<Inner: SupportMachine> TraceMachine<Inner> {
         }
         Ok(self.machine.exit_code())
     }
+
+    fn run_superblocks<D: InstDecoder>(&mut self, decoder: &mut D) -> Result<i8, Error> {
+        self.machine.set_running(true);
+        let superblocks = self.superblocks.get_or_insert_with(Vec::new);
+        superblocks.resize_with(TRACE_SIZE, || None);
+        while self.machine.running() {
+            if self.machine.pause.has_interrupted() {
+                self.machine.pause.free();
+                return Err(Error::Pause);
+            }
+            if self.machine.reset_signal() {
+                decoder.reset_instructions_cache()?;
+                superblocks.iter_mut().for_each(|s| *s = None);
+            }
+            let pc = self.machine.pc().to_u64();
+            let slot = &mut superblocks[calculate_slot(pc)];
+            let superblock = match slot {
+                Some(superblock) if superblock.address == pc => superblock,
+                _ => slot.insert(Superblock::build(
+                    decoder,
+                    self.machine.memory_mut(),
+                    &self.factory,
+                    pc,
+                )?),
+            };
+            for i in 0..superblock.instructions.len() {
+                let inst = superblock.instructions[i];
+                let cycles = self.machine.instruction_cycle_func()(inst);
+                self.machine.add_cycles(cycles)?;
+                execute_with_thread(inst, &mut self.machine, &superblock.threads[i])?;
+                if let Some(fallthrough) = superblock.fallthroughs[i] {
+                    if self.machine.pc().to_u64() != fallthrough {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(self.machine.exit_code())
+    }
 }
 
 #[cfg(test)]
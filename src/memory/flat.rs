@@ -1,10 +1,12 @@
 use super::super::{
-    error::OutOfBoundKind, Error, Register, DEFAULT_MEMORY_SIZE, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS,
+    error::OutOfBoundKind, Error, Register, DEFAULT_MEMORY_SIZE, MEMORY_FRAME_SHIFTS,
+    RISCV_PAGESIZE, RISCV_PAGE_SHIFTS,
 };
 use super::{check_no_overflow, fill_page_data, get_page_indices, memset, set_dirty, Memory};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use bytes::Bytes;
+use std::cmp::min;
 use std::io::{Cursor, Seek, SeekFrom};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
@@ -12,6 +14,9 @@ use std::ops::{Deref, DerefMut};
 pub struct FlatMemory<R> {
     data: Vec<u8>,
     flags: Vec<u8>,
+    // Memory frames written to since the last reset, only those need to be
+    // zeroed by reset_memory.
+    frames: Vec<u8>,
     memory_size: usize,
     riscv_pages: usize,
     load_reservation_address: R,
@@ -28,6 +33,8 @@ impl<R> Deref for FlatMemory<R> {
 
 impl<R> DerefMut for FlatMemory<R> {
     fn deref_mut(&mut self) -> &mut Vec<u8> {
+        // Writes through the returned Vec can't be tracked.
+        memset(&mut self.frames, 1);
         &mut self.data
     }
 }
@@ -38,12 +45,19 @@ impl<R: Register> FlatMemory<R> {
         Self {
             data: vec![0; memory_size],
             flags: vec![0; memory_size / RISCV_PAGESIZE],
+            frames: vec![0; (memory_size + (1 << MEMORY_FRAME_SHIFTS) - 1) >> MEMORY_FRAME_SHIFTS],
             memory_size,
             riscv_pages: memory_size / RISCV_PAGESIZE,
             load_reservation_address: R::from_u64(u64::MAX),
             _inner: PhantomData,
         }
     }
+
+    fn touch_frames(&mut self, addr: u64, size: u64) {
+        let from = (addr >> MEMORY_FRAME_SHIFTS) as usize;
+        let to = ((addr + size - 1) >> MEMORY_FRAME_SHIFTS) as usize;
+        memset(&mut self.frames[from..=to], 1);
+    }
 }
 
 impl<R: Register> Default for FlatMemory<R> {
@@ -58,7 +72,14 @@ impl<R: Register> Memory for FlatMemory<R> {
     type REG = R;
 
     fn reset_memory(&mut self) -> Result<(), Error> {
-        memset(&mut self.data, 0);
+        for (frame, touched) in self.frames.iter_mut().enumerate() {
+            if *touched != 0 {
+                let from = frame << MEMORY_FRAME_SHIFTS;
+                let to = min((frame + 1) << MEMORY_FRAME_SHIFTS, self.memory_size);
+                memset(&mut self.data[from..to], 0);
+                *touched = 0;
+            }
+        }
         memset(&mut self.flags, 0);
         self.load_reservation_address = R::from_u64(u64::MAX);
         Ok(())
@@ -166,6 +187,7 @@ impl<R: Register> Memory for FlatMemory<R> {
         check_no_overflow(addr, 1, self.memory_size as u64)?;
         let page_indices = get_page_indices(addr, 1);
         set_dirty(self, &page_indices)?;
+        self.touch_frames(addr, 1);
         let mut writer = Cursor::new(&mut self.data);
         writer.seek(SeekFrom::Start(addr as u64))?;
         writer.write_u8(value.to_u8())?;
@@ -177,6 +199,7 @@ impl<R: Register> Memory for FlatMemory<R> {
         check_no_overflow(addr, 2, self.memory_size as u64)?;
         let page_indices = get_page_indices(addr, 2);
         set_dirty(self, &page_indices)?;
+        self.touch_frames(addr, 2);
         let mut writer = Cursor::new(&mut self.data);
         writer.seek(SeekFrom::Start(addr as u64))?;
         writer.write_u16::<LittleEndian>(value.to_u16())?;
@@ -188,6 +211,7 @@ impl<R: Register> Memory for FlatMemory<R> {
         check_no_overflow(addr, 4, self.memory_size as u64)?;
         let page_indices = get_page_indices(addr, 4);
         set_dirty(self, &page_indices)?;
+        self.touch_frames(addr, 4);
         let mut writer = Cursor::new(&mut self.data);
         writer.seek(SeekFrom::Start(addr as u64))?;
         writer.write_u32::<LittleEndian>(value.to_u32())?;
@@ -199,6 +223,7 @@ impl<R: Register> Memory for FlatMemory<R> {
         check_no_overflow(addr, 8, self.memory_size as u64)?;
         let page_indices = get_page_indices(addr, 8);
         set_dirty(self, &page_indices)?;
+        self.touch_frames(addr, 8);
         let mut writer = Cursor::new(&mut self.data);
         writer.seek(SeekFrom::Start(addr as u64))?;
         writer.write_u64::<LittleEndian>(value.to_u64())?;
@@ -213,7 +238,8 @@ impl<R: Register> Memory for FlatMemory<R> {
         check_no_overflow(addr, size, self.memory_size as u64)?;
         let page_indices = get_page_indices(addr, size);
         set_dirty(self, &page_indices)?;
-        let slice = &mut self[addr as usize..(addr + size) as usize];
+        self.touch_frames(addr, size);
+        let slice = &mut self.data[addr as usize..(addr + size) as usize];
         slice.copy_from_slice(value);
         Ok(())
     }
@@ -225,7 +251,8 @@ impl<R: Register> Memory for FlatMemory<R> {
         check_no_overflow(addr, size, self.memory_size as u64)?;
         let page_indices = get_page_indices(addr, size);
         set_dirty(self, &page_indices)?;
-        memset(&mut self[addr as usize..(addr + size) as usize], value);
+        self.touch_frames(addr, size);
+        memset(&mut self.data[addr as usize..(addr + size) as usize], value);
         Ok(())
     }
 
@@ -235,7 +262,7 @@ impl<R: Register> Memory for FlatMemory<R> {
         }
         check_no_overflow(addr, size, self.memory_size as u64)?;
         Ok(Bytes::from(
-            self[addr as usize..(addr + size) as usize].to_vec(),
+            self.data[addr as usize..(addr + size) as usize].to_vec(),
         ))
     }
 
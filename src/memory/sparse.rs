@@ -7,17 +7,25 @@ use bytes::Bytes;
 use std::cmp::min;
 use std::marker::PhantomData;
 
-const INVALID_PAGE_INDEX: u16 = 0xFFFF;
+// Pages covered by a single second level table, 4 MB of memory.
+const TABLE_SHIFTS: usize = 10;
+const TABLE_SIZE: usize = 1 << TABLE_SHIFTS;
+
+// Pages that have never been written to read as zeros.
+static ZERO_PAGE: Page = [0; RISCV_PAGESIZE];
+
+type Table = Box<[Option<Box<Page>>]>;
 
 /// A sparse flat memory implementation, it allocates pages only when requested,
 /// but besides that, it does not permission checking.
 pub struct SparseMemory<R> {
-    // Stores the indices of each page in pages data structure, if a page hasn't
-    // been initialized, the corresponding position will be filled with
-    // INVALID_PAGE_INDEX. Considering u16 takes 2 bytes, this add an additional
-    // of 64KB extra storage cost assuming we have 128MB memory.
-    indices: Vec<u16>,
-    pages: Vec<Page>,
+    // A two level page table, the first level holds a table for every
+    // TABLE_SIZE pages, the second level holds the pages. Both are allocated
+    // when a page is first written to, so the memory can span multiple GBs
+    // while only paying for the pages in use. Considering a first level entry
+    // takes 8 bytes, this adds an additional of 16KB extra storage cost
+    // assuming we have 8GB memory.
+    tables: Vec<Option<Table>>,
     flags: Vec<u8>,
     memory_size: usize,
     riscv_pages: usize,
@@ -31,13 +39,22 @@ impl<R: Register> SparseMemory<R> {
         if page >= self.riscv_pages as u64 {
             return Err(Error::MemOutOfBound(aligned_addr, OutOfBoundKind::Memory));
         }
-        let mut index = self.indices[page as usize];
-        if index == INVALID_PAGE_INDEX {
-            self.pages.push([0; RISCV_PAGESIZE]);
-            index = (self.pages.len() - 1) as u16;
-            self.indices[page as usize] = index;
+        let table = self.tables[page as usize >> TABLE_SHIFTS]
+            .get_or_insert_with(|| vec![None; TABLE_SIZE].into_boxed_slice());
+        Ok(table[page as usize & (TABLE_SIZE - 1)]
+            .get_or_insert_with(|| Box::new([0; RISCV_PAGESIZE])))
+    }
+
+    // Like fetch_page, but reading doesn't allocate the page.
+    fn read_page(&self, aligned_addr: u64) -> Result<&Page, Error> {
+        let page = aligned_addr / RISCV_PAGESIZE as u64;
+        if page >= self.riscv_pages as u64 {
+            return Err(Error::MemOutOfBound(aligned_addr, OutOfBoundKind::Memory));
         }
-        Ok(&mut self.pages[index as usize])
+        Ok(self.tables[page as usize >> TABLE_SHIFTS]
+            .as_ref()
+            .and_then(|table| table[page as usize & (TABLE_SIZE - 1)].as_deref())
+            .unwrap_or(&ZERO_PAGE))
     }
 
     fn load(&mut self, addr: u64, bytes: u64) -> Result<u64, Error> {
@@ -47,7 +64,7 @@ impl<R: Register> SparseMemory<R> {
         let mut shift = 0;
         let mut value: u64 = 0;
         {
-            let page = self.fetch_page(page_addr)?;
+            let page = self.read_page(page_addr)?;
             for &byte in page
                 .iter()
                 .skip((addr - page_addr) as usize)
@@ -59,7 +76,7 @@ impl<R: Register> SparseMemory<R> {
         }
         let second_page_bytes = bytes - first_page_bytes;
         if second_page_bytes > 0 {
-            let second_page = self.fetch_page(page_addr + RISCV_PAGESIZE as u64)?;
+            let second_page = self.read_page(page_addr + RISCV_PAGESIZE as u64)?;
             for &byte in second_page.iter().take(second_page_bytes as usize) {
                 value |= u64::from(byte) << shift;
                 shift += 8;
@@ -70,12 +87,12 @@ impl<R: Register> SparseMemory<R> {
 
     pub fn new_with_memory(memory_size: usize) -> Self {
         assert!(memory_size % RISCV_PAGESIZE == 0);
+        let riscv_pages = memory_size / RISCV_PAGESIZE;
         Self {
-            indices: vec![INVALID_PAGE_INDEX; memory_size / RISCV_PAGESIZE],
-            pages: Vec::new(),
+            tables: vec![None; (riscv_pages + TABLE_SIZE - 1) >> TABLE_SHIFTS],
             flags: vec![0; memory_size / RISCV_PAGESIZE],
             memory_size,
-            riscv_pages: memory_size / RISCV_PAGESIZE,
+            riscv_pages,
             load_reservation_address: R::from_u64(u64::MAX),
             _inner: PhantomData,
         }
@@ -92,9 +109,8 @@ impl<R: Register> Memory for SparseMemory<R> {
     type REG = R;
 
     fn reset_memory(&mut self) -> Result<(), Error> {
-        self.indices = vec![INVALID_PAGE_INDEX; self.indices.len()];
+        self.tables = vec![None; self.tables.len()];
         memset(&mut self.flags, 0);
-        self.pages.clear();
         self.load_reservation_address = R::from_u64(u64::MAX);
         Ok(())
     }
@@ -229,7 +245,7 @@ impl<R: Register> Memory for SparseMemory<R> {
         let mut need_read_len = size;
         let mut out_value = Vec::<u8>::with_capacity(size as usize);
         while need_read_len != 0 {
-            let page = self.fetch_page(current_page_addr)?;
+            let page = self.read_page(current_page_addr)?;
             let bytes = min(RISCV_PAGESIZE as u64 - current_page_offset, need_read_len);
             out_value.extend(
                 &page[current_page_offset as usize..(current_page_offset + bytes) as usize],
@@ -1,7 +1,8 @@
 use crate::instructions::Register;
+use crate::isa::register_number;
 use crate::memory::Memory;
 use crate::memory::FLAG_DIRTY;
-use crate::{CoreMachine, Error, RISCV_GENERAL_REGISTER_NUMBER, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS};
+use crate::{CoreMachine, Error, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS};
 use serde::{Deserialize, Serialize};
 
 // Snapshot provides a mechanism for suspending and resuming a virtual machine.
@@ -13,8 +14,9 @@ use serde::{Deserialize, Serialize};
 //
 // For the following data, we simply save them, and then restore them.
 //   - machine.version
+//   - machine.isa, resuming needs a machine with the same register file
 //   - machine.pc
-//   - machine.registers
+//   - machine.registers, only x0-x15 for RV32E/RV64E machines
 //
 // For memory, the situation becomes more complicated. Every memory page has
 // page flag where each page flag stores a optional FLAG_DIRTY. When this page
@@ -27,7 +29,8 @@ use serde::{Deserialize, Serialize};
 #[derive(Default, Deserialize, Serialize)]
 pub struct Snapshot {
     pub version: u32,
-    pub registers: [u64; RISCV_GENERAL_REGISTER_NUMBER],
+    pub isa: u8,
+    pub registers: Vec<u64>,
     pub pc: u64,
     pub page_indices: Vec<u64>,
     pub page_flags: Vec<u8>,
@@ -38,13 +41,15 @@ pub struct Snapshot {
 pub fn make_snapshot<T: CoreMachine>(machine: &mut T) -> Result<Snapshot, Error> {
     let mut snap = Snapshot {
         version: machine.version(),
+        isa: machine.isa(),
         pc: machine.pc().to_u64(),
         load_reservation_address: machine.memory().lr().to_u64(),
         ..Default::default()
     };
-    for (i, v) in machine.registers().iter().enumerate() {
-        snap.registers[i] = v.to_u64();
-    }
+    snap.registers = machine.registers()[..register_number(machine.isa())]
+        .iter()
+        .map(|v| v.to_u64())
+        .collect();
 
     for i in 0..machine.memory().memory_pages() {
         let flag = machine.memory_mut().fetch_flag(i as u64)?;
@@ -81,6 +86,10 @@ pub fn resume<T: CoreMachine>(machine: &mut T, snapshot: &Snapshot) -> Result<()
     if machine.version() != snapshot.version {
         return Err(Error::InvalidVersion);
     }
+    let number = register_number(machine.isa());
+    if register_number(snapshot.isa) != number || snapshot.registers.len() != number {
+        return Err(Error::SnapshotDataLoadError);
+    }
     for (i, v) in snapshot.registers.iter().enumerate() {
         machine.set_register(i, T::REG::from_u64(*v));
     }
@@ -1,9 +1,11 @@
 use crate::{
     bits::roundup,
     elf::{LoadingAction, ProgramMetadata},
+    isa::register_number,
+    loader::Module,
     machine::SupportMachine,
     memory::{get_page_indices, Memory, FLAG_DIRTY},
-    Error, Register, RISCV_GENERAL_REGISTER_NUMBER, RISCV_PAGESIZE,
+    Error, Register, RISCV_PAGESIZE,
 };
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
@@ -57,6 +59,10 @@ impl<I: Clone + PartialEq, D: DataSource<I>> Snapshot2Context<I, D> {
         if machine.version() != snapshot.version {
             return Err(Error::InvalidVersion);
         }
+        let number = register_number(machine.isa());
+        if register_number(snapshot.isa) != number || snapshot.registers.len() != number {
+            return Err(Error::SnapshotDataLoadError);
+        }
         // A resume basically means we reside in a new context
         self.pages.clear();
         for (i, v) in snapshot.registers.iter().enumerate() {
@@ -158,6 +164,20 @@ impl<I: Clone + PartialEq, D: DataSource<I>> Snapshot2Context<I, D> {
         Ok(())
     }
 
+    /// Similar to mark_program, but marks all the modules mapped by a
+    /// loader::Loader, each against its own data source id. Like
+    /// mark_program, this must be called after the executable is loaded.
+    pub fn mark_modules<M: SupportMachine>(
+        &mut self,
+        machine: &mut M,
+        modules: &[Module<I>],
+    ) -> Result<(), Error> {
+        for module in modules {
+            self.mark_program(machine, &module.metadata, &module.id, 0)?;
+        }
+        Ok(())
+    }
+
     /// Create a snapshot for the passed machine.
     pub fn make_snapshot<M: SupportMachine>(&self, machine: &mut M) -> Result<Snapshot2<I>, Error> {
         let mut dirty_pages: Vec<(u64, u8, Vec<u8>)> = vec![];
@@ -206,14 +226,15 @@ impl<I: Clone + PartialEq, D: DataSource<I>> Snapshot2Context<I, D> {
                 pages_from_source.push((address, *flag, id.clone(), *offset, PAGE_SIZE));
             }
         }
-        let mut registers = [0u64; RISCV_GENERAL_REGISTER_NUMBER];
-        for (i, v) in machine.registers().iter().enumerate() {
-            registers[i] = v.to_u64();
-        }
+        let registers = machine.registers()[..register_number(machine.isa())]
+            .iter()
+            .map(|v| v.to_u64())
+            .collect();
         Ok(Snapshot2 {
             pages_from_source,
             dirty_pages,
             version: machine.version(),
+            isa: machine.isa(),
             registers,
             pc: machine.pc().to_u64(),
             cycles: machine.cycles(),
@@ -234,7 +255,18 @@ impl<I: Clone + PartialEq, D: DataSource<I>> Snapshot2Context<I, D> {
             action.source.end - action.source.start,
             action.size - action.offset_from_addr,
         );
-        self.track_pages(machine, start, length, id, offset + action.source.start)
+        self.track_pages(machine, start, length, id, offset + action.source.start)?;
+        // Relocated pages no longer match the data source, they are kept as
+        // dirty pages so their content ends up in the snapshot.
+        let word_size = u64::from(M::REG::BITS / 8);
+        for relocation in &action.relocations {
+            let (page_start, page_end) = get_page_indices(relocation.addr, word_size);
+            for page in page_start..=page_end {
+                self.pages.remove(&page);
+                machine.memory_mut().set_flag(page, FLAG_DIRTY)?;
+            }
+        }
+        Ok(())
     }
 
     /// The followings are only made public for advanced usages, but make sure to exercise more
@@ -291,7 +323,10 @@ pub struct Snapshot2<I: Clone + PartialEq> {
     // (address, flag, content)
     pub dirty_pages: Vec<(u64, u8, Vec<u8>)>,
     pub version: u32,
-    pub registers: [u64; RISCV_GENERAL_REGISTER_NUMBER],
+    // Resuming needs a machine with the same register file.
+    pub isa: u8,
+    // x0-x31, or x0-x15 for RV32E/RV64E machines.
+    pub registers: Vec<u64>,
     pub pc: u64,
     pub cycles: u64,
     pub max_cycles: u64,
@@ -6,5 +6,6 @@ pub trait Syscalls<Mac: SupportMachine>: Send + Sync {
     // Returned bool means if the syscall has been processed, if
     // a module returns false, Machine would continue to leverage
     // the next syscall module to process.
+    // The requested syscall is given by SupportMachine::syscall_number.
     fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error>;
 }
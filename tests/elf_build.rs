@@ -0,0 +1,202 @@
+// Builds minimal 64-bit position independent ELF images, so relocation logic
+// can be tested without a RISC-V toolchain.
+//
+// Layout of the generated image, all addresses are link-time addresses:
+// * 0x000: ELF header
+// * 0x100: TEXT, the code
+// * 0x300: .dynsym
+// * 0x500: .dynstr
+// * 0x600: .rela.dyn
+// * 0x700: .hash or .gnu.hash
+// * 0x800: RODATA
+// * 0xc00: program headers
+// * 0xd00: a GNU build-id note
+// * 0x1000: DATA, followed by .dynamic
+use ckb_vm::elf::{
+    DT_GNU_HASH, DT_HASH, DT_NULL, DT_RELA, DT_RELAENT, DT_RELASZ, DT_STRTAB, DT_SYMENT, DT_SYMTAB,
+    ET_DYN, NT_GNU_BUILD_ID, PF_R, PF_W, PF_X, PT_DYNAMIC, PT_LOAD, PT_NOTE,
+};
+use ckb_vm::Bytes;
+
+pub const TEXT: u64 = 0x100;
+pub const RODATA: u64 = 0x800;
+pub const DATA: u64 = 0x1000;
+
+const DYNSYM: u64 = 0x300;
+const DYNSTR: u64 = 0x500;
+const RELA: u64 = 0x600;
+const HASH: u64 = 0x700;
+const PHDR: u64 = 0xc00;
+const NOTE: u64 = 0xd00;
+
+pub const BUILD_ID: [u8; 20] = [
+    0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f,
+    0x20, 0x21, 0x22, 0x23,
+];
+
+pub struct Symbol {
+    pub name: &'static str,
+    // Link-time address for symbols defined in the image, None for imports.
+    pub value: Option<u64>,
+}
+
+// Hash table the image sizes its dynamic symbol table with.
+#[derive(Clone, Copy)]
+pub enum HashStyle {
+    Sysv,
+    Gnu,
+}
+
+pub struct Rela {
+    pub offset: u64,
+    pub kind: u32,
+    // Index into the symbols passed to build_pie, 0 means no symbol.
+    pub symbol: u64,
+    pub addend: i64,
+}
+
+fn put(buf: &mut Vec<u8>, offset: u64, data: &[u8]) {
+    let offset = offset as usize;
+    if buf.len() < offset + data.len() {
+        buf.resize(offset + data.len(), 0);
+    }
+    buf[offset..offset + data.len()].copy_from_slice(data);
+}
+
+fn gnu_hash(name: &str) -> u32 {
+    name.bytes().fold(5381u32, |h, c| {
+        h.wrapping_mul(33).wrapping_add(u32::from(c))
+    })
+}
+
+pub fn build_pie(
+    code: &[u32],
+    rodata: &[u8],
+    data: &[u8],
+    symbols: &[Symbol],
+    relas: &[Rela],
+) -> Bytes {
+    build_pie_with_hash(code, rodata, data, symbols, relas, HashStyle::Sysv)
+}
+
+pub fn build_pie_with_hash(
+    code: &[u32],
+    rodata: &[u8],
+    data: &[u8],
+    symbols: &[Symbol],
+    relas: &[Rela],
+    hash: HashStyle,
+) -> Bytes {
+    let mut buf = vec![0u8; 0x1000];
+    let dynamic = DATA + (data.len() as u64 + 7) / 8 * 8;
+    let dynamic_entries = [
+        (DT_RELA, RELA),
+        (DT_RELASZ, 24 * relas.len() as u64),
+        (DT_RELAENT, 24),
+        (DT_SYMTAB, DYNSYM),
+        (DT_STRTAB, DYNSTR),
+        (DT_SYMENT, 24),
+        match hash {
+            HashStyle::Sysv => (DT_HASH, HASH),
+            HashStyle::Gnu => (DT_GNU_HASH, HASH),
+        },
+        (DT_NULL, 0),
+    ];
+    let data_end = dynamic + 16 * dynamic_entries.len() as u64;
+
+    // ELF header
+    put(&mut buf, 0, b"\x7fELF\x02\x01\x01");
+    put(&mut buf, 16, &ET_DYN.to_le_bytes());
+    put(&mut buf, 18, &243u16.to_le_bytes());
+    put(&mut buf, 20, &1u32.to_le_bytes());
+    put(&mut buf, 24, &TEXT.to_le_bytes());
+    put(&mut buf, 32, &PHDR.to_le_bytes());
+    put(&mut buf, 52, &64u16.to_le_bytes());
+    put(&mut buf, 54, &56u16.to_le_bytes());
+    put(&mut buf, 56, &4u16.to_le_bytes());
+    put(&mut buf, 58, &64u16.to_le_bytes());
+    // Program headers
+    let program_headers = [
+        (PT_LOAD, PF_R | PF_X, 0, 0x1000),
+        (PT_LOAD, PF_R | PF_W, DATA, data_end - DATA),
+        (PT_DYNAMIC, PF_R | PF_W, dynamic, data_end - dynamic),
+        (PT_NOTE, PF_R, NOTE, 12 + 4 + BUILD_ID.len() as u64),
+    ];
+    for (i, (p_type, p_flags, addr, size)) in program_headers.iter().enumerate() {
+        let offset = PHDR + 56 * i as u64;
+        put(&mut buf, offset, &p_type.to_le_bytes());
+        put(&mut buf, offset + 4, &p_flags.to_le_bytes());
+        put(&mut buf, offset + 8, &addr.to_le_bytes());
+        put(&mut buf, offset + 16, &addr.to_le_bytes());
+        put(&mut buf, offset + 24, &addr.to_le_bytes());
+        put(&mut buf, offset + 32, &size.to_le_bytes());
+        put(&mut buf, offset + 40, &size.to_le_bytes());
+        put(&mut buf, offset + 48, &0x1000u64.to_le_bytes());
+    }
+    for (i, inst) in code.iter().enumerate() {
+        put(&mut buf, TEXT + 4 * i as u64, &inst.to_le_bytes());
+    }
+    // Symbol 0 is the reserved null symbol.
+    let mut name_offset = 1;
+    for (i, symbol) in symbols.iter().enumerate() {
+        let offset = DYNSYM + 24 * (i as u64 + 1);
+        put(&mut buf, offset, &(name_offset as u32).to_le_bytes());
+        // STB_GLOBAL, STT_NOTYPE
+        put(&mut buf, offset + 4, &[0x10]);
+        let shndx: u16 = if symbol.value.is_some() { 1 } else { 0 };
+        put(&mut buf, offset + 6, &shndx.to_le_bytes());
+        put(
+            &mut buf,
+            offset + 8,
+            &symbol.value.unwrap_or(0).to_le_bytes(),
+        );
+        put(&mut buf, DYNSTR + name_offset, symbol.name.as_bytes());
+        name_offset += symbol.name.len() as u64 + 1;
+    }
+    for (i, rela) in relas.iter().enumerate() {
+        let offset = RELA + 24 * i as u64;
+        put(&mut buf, offset, &rela.offset.to_le_bytes());
+        let info = (rela.symbol << 32) | u64::from(rela.kind);
+        put(&mut buf, offset + 8, &info.to_le_bytes());
+        put(&mut buf, offset + 16, &rela.addend.to_le_bytes());
+    }
+    match hash {
+        HashStyle::Sysv => {
+            // A single bucket hash table, loaders only need nchain from it.
+            put(&mut buf, HASH, &1u32.to_le_bytes());
+            put(
+                &mut buf,
+                HASH + 4,
+                &(symbols.len() as u32 + 1).to_le_bytes(),
+            );
+        }
+        HashStyle::Gnu => {
+            // A single bucket holding all symbols but the null one, with a
+            // bloom filter letting every name through.
+            put(&mut buf, HASH, &1u32.to_le_bytes());
+            put(&mut buf, HASH + 4, &1u32.to_le_bytes());
+            put(&mut buf, HASH + 8, &1u32.to_le_bytes());
+            put(&mut buf, HASH + 16, &u64::MAX.to_le_bytes());
+            let bucket: u32 = if symbols.is_empty() { 0 } else { 1 };
+            put(&mut buf, HASH + 24, &bucket.to_le_bytes());
+            for (i, symbol) in symbols.iter().enumerate() {
+                let end = (i + 1 == symbols.len()) as u32;
+                let chain = (gnu_hash(symbol.name) & !1) | end;
+                put(&mut buf, HASH + 28 + 4 * i as u64, &chain.to_le_bytes());
+            }
+        }
+    }
+    put(&mut buf, NOTE, &4u32.to_le_bytes());
+    put(&mut buf, NOTE + 4, &(BUILD_ID.len() as u32).to_le_bytes());
+    put(&mut buf, NOTE + 8, &NT_GNU_BUILD_ID.to_le_bytes());
+    put(&mut buf, NOTE + 12, b"GNU\0");
+    put(&mut buf, NOTE + 16, &BUILD_ID);
+    put(&mut buf, RODATA, rodata);
+    put(&mut buf, DATA, data);
+    for (i, (tag, value)) in dynamic_entries.iter().enumerate() {
+        let offset = dynamic + 16 * i as u64;
+        put(&mut buf, offset, &tag.to_le_bytes());
+        put(&mut buf, offset + 8, &value.to_le_bytes());
+    }
+    buf.into()
+}
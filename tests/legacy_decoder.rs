@@ -0,0 +1,646 @@
+// The hand-written I, M, A and B decoders ckb-vm shipped before decoding was
+// generated from the instruction definitions. Decoding is consensus
+// critical, these are kept verbatim as the reference the generated decoders
+// are checked against, quirks included.
+use ckb_vm::instructions::{
+    blank_instruction, insts, set_instruction_length_4, Instruction, InstructionOpcode, Itype,
+    Register, Rtype, Stype, Utype,
+};
+
+fn x(instruction_bits: u32, lower: usize, length: usize, shifts: usize) -> u32 {
+    ((instruction_bits >> lower) & ((1 << length) - 1)) << shifts
+}
+
+fn xs(instruction_bits: u32, lower: usize, length: usize, shifts: usize) -> u32 {
+    ((instruction_bits as i32) << (32 - lower - length) >> (32 - length) << shifts) as u32
+}
+
+fn opcode(instruction_bits: u32) -> u32 {
+    x(instruction_bits, 0, 7, 0)
+}
+
+fn funct3(instruction_bits: u32) -> u32 {
+    x(instruction_bits, 12, 3, 0)
+}
+
+fn funct7(instruction_bits: u32) -> u32 {
+    x(instruction_bits, 25, 7, 0)
+}
+
+fn rd(instruction_bits: u32) -> usize {
+    x(instruction_bits, 7, 5, 0) as usize
+}
+
+fn rs1(instruction_bits: u32) -> usize {
+    x(instruction_bits, 15, 5, 0) as usize
+}
+
+fn rs2(instruction_bits: u32) -> usize {
+    x(instruction_bits, 20, 5, 0) as usize
+}
+
+fn btype_immediate(instruction_bits: u32) -> i32 {
+    (x(instruction_bits, 8, 4, 1)
+        | x(instruction_bits, 25, 6, 5)
+        | x(instruction_bits, 7, 1, 11)
+        | xs(instruction_bits, 31, 1, 12)) as i32
+}
+
+fn jtype_immediate(instruction_bits: u32) -> i32 {
+    (x(instruction_bits, 21, 10, 1)
+        | x(instruction_bits, 20, 1, 11)
+        | x(instruction_bits, 12, 8, 12)
+        | xs(instruction_bits, 31, 1, 20)) as i32
+}
+
+fn itype_immediate(instruction_bits: u32) -> i32 {
+    xs(instruction_bits, 20, 12, 0) as i32
+}
+
+fn stype_immediate(instruction_bits: u32) -> i32 {
+    (x(instruction_bits, 7, 5, 0) | xs(instruction_bits, 25, 7, 5)) as i32
+}
+
+fn utype_immediate(instruction_bits: u32) -> i32 {
+    xs(instruction_bits, 12, 20, 12) as i32
+}
+
+fn versioned(
+    version: u32,
+    version0: InstructionOpcode,
+    version1: InstructionOpcode,
+) -> InstructionOpcode {
+    if version >= 1 {
+        version1
+    } else {
+        version0
+    }
+}
+
+pub fn i_factory<R: Register>(instruction_bits: u32, version: u32) -> Option<Instruction> {
+    let bit_length = R::BITS;
+    if bit_length != 32 && bit_length != 64 {
+        return None;
+    }
+    let rv64 = bit_length == 64;
+    let inst = (|| match opcode(instruction_bits) {
+        0b_0110111 => Some(
+            Utype::new_s(
+                insts::OP_LUI,
+                rd(instruction_bits),
+                utype_immediate(instruction_bits),
+            )
+            .0,
+        ),
+        0b_0010111 => Some(
+            Utype::new_s(
+                insts::OP_AUIPC,
+                rd(instruction_bits),
+                utype_immediate(instruction_bits),
+            )
+            .0,
+        ),
+        0b_1101111 => Some(
+            Utype::new_s(
+                insts::OP_JAL,
+                rd(instruction_bits),
+                jtype_immediate(instruction_bits),
+            )
+            .0,
+        ),
+        0b_1100111 => {
+            let inst_opt = match funct3(instruction_bits) {
+                // I-type jump instructions
+                0b_000 => Some(versioned(
+                    version,
+                    insts::OP_JALR_VERSION0,
+                    insts::OP_JALR_VERSION1,
+                )),
+                _ => None,
+            };
+            inst_opt.map(|inst| {
+                Itype::new_s(
+                    inst,
+                    rd(instruction_bits),
+                    rs1(instruction_bits),
+                    itype_immediate(instruction_bits),
+                )
+                .0
+            })
+        }
+        0b_0000011 => {
+            // I-type load instructions
+            let inst_opt = match funct3(instruction_bits) {
+                0b_000 => Some(versioned(
+                    version,
+                    insts::OP_LB_VERSION0,
+                    insts::OP_LB_VERSION1,
+                )),
+                0b_001 => Some(versioned(
+                    version,
+                    insts::OP_LH_VERSION0,
+                    insts::OP_LH_VERSION1,
+                )),
+                0b_010 => Some(versioned(
+                    version,
+                    insts::OP_LW_VERSION0,
+                    insts::OP_LW_VERSION1,
+                )),
+                0b_100 => Some(versioned(
+                    version,
+                    insts::OP_LBU_VERSION0,
+                    insts::OP_LBU_VERSION1,
+                )),
+                0b_101 => Some(versioned(
+                    version,
+                    insts::OP_LHU_VERSION0,
+                    insts::OP_LHU_VERSION1,
+                )),
+                0b_110 if rv64 => Some(versioned(
+                    version,
+                    insts::OP_LWU_VERSION0,
+                    insts::OP_LWU_VERSION1,
+                )),
+                0b_011 if rv64 => Some(versioned(
+                    version,
+                    insts::OP_LD_VERSION0,
+                    insts::OP_LD_VERSION1,
+                )),
+                _ => None,
+            };
+            inst_opt.map(|inst| {
+                Itype::new_s(
+                    inst,
+                    rd(instruction_bits),
+                    rs1(instruction_bits),
+                    itype_immediate(instruction_bits),
+                )
+                .0
+            })
+        }
+        0b_0010011 => {
+            let funct3_value = funct3(instruction_bits);
+            let inst_opt = match funct3_value {
+                // I-type ALU instructions
+                0b_000 => Some(insts::OP_ADDI),
+                0b_010 => Some(insts::OP_SLTI),
+                0b_011 => Some(insts::OP_SLTIU),
+                0b_100 => Some(insts::OP_XORI),
+                0b_110 => Some(insts::OP_ORI),
+                0b_111 => Some(insts::OP_ANDI),
+                // I-type special ALU instructions
+                0b_001 | 0b_101 => {
+                    let top6_value = funct7(instruction_bits) >> 1;
+                    let inst_opt = match (funct3_value, top6_value) {
+                        (0b_001, 0b_000000) => Some(insts::OP_SLLI),
+                        (0b_101, 0b_000000) => Some(insts::OP_SRLI),
+                        (0b_101, 0b_010000) => Some(insts::OP_SRAI),
+                        _ => None,
+                    };
+                    return inst_opt.map(|inst| {
+                        Itype::new_s(
+                            inst,
+                            rd(instruction_bits),
+                            rs1(instruction_bits),
+                            itype_immediate(instruction_bits) & i32::from(R::SHIFT_MASK),
+                        )
+                        .0
+                    });
+                }
+                _ => None,
+            };
+
+            inst_opt.map(|inst| {
+                Itype::new_s(
+                    inst,
+                    rd(instruction_bits),
+                    rs1(instruction_bits),
+                    itype_immediate(instruction_bits),
+                )
+                .0
+            })
+        }
+        0b_1100011 => {
+            let inst_opt = match funct3(instruction_bits) {
+                0b_000 => Some(insts::OP_BEQ),
+                0b_001 => Some(insts::OP_BNE),
+                0b_100 => Some(insts::OP_BLT),
+                0b_101 => Some(insts::OP_BGE),
+                0b_110 => Some(insts::OP_BLTU),
+                0b_111 => Some(insts::OP_BGEU),
+                _ => None,
+            };
+            inst_opt.map(|inst| {
+                Stype::new_s(
+                    inst,
+                    btype_immediate(instruction_bits),
+                    rs1(instruction_bits),
+                    rs2(instruction_bits),
+                )
+                .0
+            })
+        }
+        0b_0100011 => {
+            let inst_opt = match funct3(instruction_bits) {
+                0b_000 => Some(insts::OP_SB),
+                0b_001 => Some(insts::OP_SH),
+                0b_010 => Some(insts::OP_SW),
+                0b_011 if rv64 => Some(insts::OP_SD),
+                _ => None,
+            };
+            inst_opt.map(|inst| {
+                Stype::new_s(
+                    inst,
+                    stype_immediate(instruction_bits),
+                    rs1(instruction_bits),
+                    rs2(instruction_bits),
+                )
+                .0
+            })
+        }
+        0b_0110011 => {
+            let inst_opt = match (funct3(instruction_bits), funct7(instruction_bits)) {
+                (0b_000, 0b_0000000) => Some(insts::OP_ADD),
+                (0b_000, 0b_0100000) => Some(insts::OP_SUB),
+                (0b_001, 0b_0000000) => Some(insts::OP_SLL),
+                (0b_010, 0b_0000000) => Some(insts::OP_SLT),
+                (0b_011, 0b_0000000) => Some(insts::OP_SLTU),
+                (0b_100, 0b_0000000) => Some(insts::OP_XOR),
+                (0b_101, 0b_0000000) => Some(insts::OP_SRL),
+                (0b_101, 0b_0100000) => Some(insts::OP_SRA),
+                (0b_110, 0b_0000000) => Some(insts::OP_OR),
+                (0b_111, 0b_0000000) => Some(insts::OP_AND),
+                _ => None,
+            };
+            inst_opt.map(|inst| {
+                Rtype::new(
+                    inst,
+                    rd(instruction_bits),
+                    rs1(instruction_bits),
+                    rs2(instruction_bits),
+                )
+                .0
+            })
+        }
+        0b_0001111 => {
+            const FENCE_LOW_BITS: u32 = 0b_00000_000_00000_0001111;
+            const FENCEI_VALUE: u32 = 0b_0000_0000_0000_00000_001_00000_0001111;
+            if instruction_bits == FENCEI_VALUE {
+                Some(blank_instruction(insts::OP_FENCEI))
+            } else if instruction_bits & 0x000_FFFFF == FENCE_LOW_BITS {
+                Some(
+                    Rtype::new(
+                        insts::OP_FENCE,
+                        ((instruction_bits & 0xF00_00000) >> 28) as usize,
+                        ((instruction_bits & 0x0F0_00000) >> 24) as usize,
+                        ((instruction_bits & 0x00F_00000) >> 20) as usize,
+                    )
+                    .0,
+                )
+            } else {
+                None
+            }
+        }
+        0b_1110011 => match instruction_bits {
+            0b_000000000000_00000_000_00000_1110011 => Some(blank_instruction(insts::OP_ECALL)),
+            0b_000000000001_00000_000_00000_1110011 => Some(blank_instruction(insts::OP_EBREAK)),
+            _ => None,
+        },
+        0b_0011011 if rv64 => {
+            let funct3_value = funct3(instruction_bits);
+            match funct3_value {
+                0b_000 => Some(
+                    Itype::new_s(
+                        insts::OP_ADDIW,
+                        rd(instruction_bits),
+                        rs1(instruction_bits),
+                        itype_immediate(instruction_bits),
+                    )
+                    .0,
+                ),
+                0b_001 | 0b_101 => {
+                    let funct7_value = funct7(instruction_bits);
+                    let inst_opt = match (funct3_value, funct7_value) {
+                        (0b_001, 0b_0000000) => Some(insts::OP_SLLIW),
+                        (0b_101, 0b_0000000) => Some(insts::OP_SRLIW),
+                        (0b_101, 0b_0100000) => Some(insts::OP_SRAIW),
+                        _ => None,
+                    };
+                    inst_opt.map(|inst| {
+                        Itype::new_s(
+                            inst,
+                            rd(instruction_bits),
+                            rs1(instruction_bits),
+                            itype_immediate(instruction_bits) & 0x1F,
+                        )
+                        .0
+                    })
+                }
+                _ => None,
+            }
+        }
+        0b_0111011 if rv64 => {
+            let inst_opt = match (funct3(instruction_bits), funct7(instruction_bits)) {
+                (0b_000, 0b_0000000) => Some(insts::OP_ADDW),
+                (0b_000, 0b_0100000) => Some(insts::OP_SUBW),
+                (0b_001, 0b_0000000) => Some(insts::OP_SLLW),
+                (0b_101, 0b_0000000) => Some(insts::OP_SRLW),
+                (0b_101, 0b_0100000) => Some(insts::OP_SRAW),
+                _ => None,
+            };
+            inst_opt.map(|inst| {
+                Rtype::new(
+                    inst,
+                    rd(instruction_bits),
+                    rs1(instruction_bits),
+                    rs2(instruction_bits),
+                )
+                .0
+            })
+        }
+        _ => None,
+    })();
+    inst.map(set_instruction_length_4)
+}
+
+pub fn m_factory<R: Register>(instruction_bits: u32, _: u32) -> Option<Instruction> {
+    let bit_length = R::BITS;
+    if bit_length != 32 && bit_length != 64 {
+        return None;
+    }
+    let rv64 = bit_length == 64;
+    if funct7(instruction_bits) != 0b_0000001 {
+        return None;
+    }
+    let inst_opt = match opcode(instruction_bits) {
+        0b_0110011 => match funct3(instruction_bits) {
+            0b_000 => Some(insts::OP_MUL),
+            0b_001 => Some(insts::OP_MULH),
+            0b_010 => Some(insts::OP_MULHSU),
+            0b_011 => Some(insts::OP_MULHU),
+            0b_100 => Some(insts::OP_DIV),
+            0b_101 => Some(insts::OP_DIVU),
+            0b_110 => Some(insts::OP_REM),
+            0b_111 => Some(insts::OP_REMU),
+            _ => None,
+        },
+        0b_0111011 if rv64 => match funct3(instruction_bits) {
+            0b_000 => Some(insts::OP_MULW),
+            0b_100 => Some(insts::OP_DIVW),
+            0b_101 => Some(insts::OP_DIVUW),
+            0b_110 => Some(insts::OP_REMW),
+            0b_111 => Some(insts::OP_REMUW),
+            _ => None,
+        },
+        _ => None,
+    };
+    inst_opt
+        .map(|inst| {
+            Rtype::new(
+                inst,
+                rd(instruction_bits),
+                rs1(instruction_bits),
+                rs2(instruction_bits),
+            )
+            .0
+        })
+        .map(set_instruction_length_4)
+}
+
+pub fn a_factory<R: Register>(instruction_bits: u32, _: u32) -> Option<Instruction> {
+    let bit_length = R::BITS;
+    if bit_length != 32 && bit_length != 64 {
+        return None;
+    }
+    let rv64 = bit_length == 64;
+    if opcode(instruction_bits) != 0b_0101111 {
+        return None;
+    }
+    let f7 = funct7(instruction_bits);
+    let f5 = f7 >> 2;
+    let f3 = funct3(instruction_bits);
+    let match_rv32 = || match (f3, f5) {
+        (0b010, 0b00010) => {
+            if rs2(instruction_bits) == 0 {
+                Some(insts::OP_LR_W)
+            } else {
+                None
+            }
+        }
+        (0b010, 0b00011) => Some(insts::OP_SC_W),
+        (0b010, 0b00001) => Some(insts::OP_AMOSWAP_W),
+        (0b010, 0b00000) => Some(insts::OP_AMOADD_W),
+        (0b010, 0b00100) => Some(insts::OP_AMOXOR_W),
+        (0b010, 0b01100) => Some(insts::OP_AMOAND_W),
+        (0b010, 0b01000) => Some(insts::OP_AMOOR_W),
+        (0b010, 0b10000) => Some(insts::OP_AMOMIN_W),
+        (0b010, 0b10100) => Some(insts::OP_AMOMAX_W),
+        (0b010, 0b11000) => Some(insts::OP_AMOMINU_W),
+        (0b010, 0b11100) => Some(insts::OP_AMOMAXU_W),
+        _ => None,
+    };
+    let match_rv64 = || match (f3, f5) {
+        (0b011, 0b00010) => {
+            if rs2(instruction_bits) == 0 {
+                Some(insts::OP_LR_D)
+            } else {
+                None
+            }
+        }
+        (0b011, 0b00011) => Some(insts::OP_SC_D),
+        (0b011, 0b00001) => Some(insts::OP_AMOSWAP_D),
+        (0b011, 0b00000) => Some(insts::OP_AMOADD_D),
+        (0b011, 0b00100) => Some(insts::OP_AMOXOR_D),
+        (0b011, 0b01100) => Some(insts::OP_AMOAND_D),
+        (0b011, 0b01000) => Some(insts::OP_AMOOR_D),
+        (0b011, 0b10000) => Some(insts::OP_AMOMIN_D),
+        (0b011, 0b10100) => Some(insts::OP_AMOMAX_D),
+        (0b011, 0b11000) => Some(insts::OP_AMOMINU_D),
+        (0b011, 0b11100) => Some(insts::OP_AMOMAXU_D),
+        _ => None,
+    };
+    let inst_opt = if rv64 {
+        match_rv32().or_else(match_rv64)
+    } else {
+        match_rv32()
+    };
+    inst_opt
+        .map(|inst| {
+            Rtype::new(
+                inst,
+                rd(instruction_bits),
+                rs1(instruction_bits),
+                rs2(instruction_bits),
+            )
+            .0
+        })
+        .map(set_instruction_length_4)
+}
+
+pub fn b_factory<R: Register>(instruction_bits: u32, _: u32) -> Option<Instruction> {
+    let bit_length = R::BITS;
+    if bit_length != 32 && bit_length != 64 {
+        return None;
+    }
+    let rv64 = bit_length == 64;
+    let inst = match opcode(instruction_bits) {
+        0b_0111011 => {
+            let funct3_value = funct3(instruction_bits);
+            let funct7_value = funct7(instruction_bits);
+            let inst_opt = match (funct3_value, funct7_value) {
+                (0b_000, 0b_0000100) => Some(insts::OP_ADDUW),
+                (0b_001, 0b_0110000) => Some(insts::OP_ROLW),
+                (0b_010, 0b_0010000) => Some(insts::OP_SH1ADDUW),
+                (0b_100, 0b_0000100) => {
+                    if rv64 && rs2(instruction_bits) == 0 {
+                        Some(insts::OP_ZEXTH)
+                    } else {
+                        None
+                    }
+                }
+                (0b_100, 0b_0010000) => Some(insts::OP_SH2ADDUW),
+                (0b_101, 0b_0110000) => Some(insts::OP_RORW),
+                (0b_110, 0b_0010000) => Some(insts::OP_SH3ADDUW),
+                _ => None,
+            };
+            inst_opt.map(|inst| {
+                Rtype::new(
+                    inst,
+                    rd(instruction_bits),
+                    rs1(instruction_bits),
+                    rs2(instruction_bits),
+                )
+                .0
+            })
+        }
+        0b_0110011 => {
+            let funct3_value = funct3(instruction_bits);
+            let funct7_value = funct7(instruction_bits);
+            let inst_opt = match (funct3_value, funct7_value) {
+                (0b_111, 0b_0100000) => Some(insts::OP_ANDN),
+                (0b_110, 0b_0100000) => Some(insts::OP_ORN),
+                (0b_100, 0b_0100000) => Some(insts::OP_XNOR),
+                (0b_001, 0b_0110000) => Some(insts::OP_ROL),
+                (0b_101, 0b_0110000) => Some(insts::OP_ROR),
+                (0b_001, 0b_0110100) => Some(insts::OP_BINV),
+                (0b_001, 0b_0010100) => Some(insts::OP_BSET),
+                (0b_001, 0b_0100100) => Some(insts::OP_BCLR),
+                (0b_101, 0b_0100100) => Some(insts::OP_BEXT),
+                (0b_010, 0b_0010000) => Some(insts::OP_SH1ADD),
+                (0b_100, 0b_0010000) => Some(insts::OP_SH2ADD),
+                (0b_110, 0b_0010000) => Some(insts::OP_SH3ADD),
+                (0b_001, 0b_0000101) => Some(insts::OP_CLMUL),
+                (0b_011, 0b_0000101) => Some(insts::OP_CLMULH),
+                (0b_010, 0b_0000101) => Some(insts::OP_CLMULR),
+                (0b_100, 0b_0000101) => Some(insts::OP_MIN),
+                (0b_101, 0b_0000101) => Some(insts::OP_MINU),
+                (0b_110, 0b_0000101) => Some(insts::OP_MAX),
+                (0b_111, 0b_0000101) => Some(insts::OP_MAXU),
+                _ => None,
+            };
+            inst_opt.map(|inst| {
+                Rtype::new(
+                    inst,
+                    rd(instruction_bits),
+                    rs1(instruction_bits),
+                    rs2(instruction_bits),
+                )
+                .0
+            })
+        }
+        0b_0010011 => {
+            let funct3_value = funct3(instruction_bits);
+            let funct7_value = funct7(instruction_bits);
+            let rs2_value = rs2(instruction_bits);
+            let inst_opt = match (funct7_value, funct3_value, rs2_value) {
+                (0b_0010100, 0b_101, 0b_00111) => Some(insts::OP_ORCB),
+                (0b_0110101, 0b_101, 0b_11000) => Some(insts::OP_REV8),
+                (0b_0110000, 0b_001, 0b_00000) => Some(insts::OP_CLZ),
+                (0b_0110000, 0b_001, 0b_00010) => Some(insts::OP_CPOP),
+                (0b_0110000, 0b_001, 0b_00001) => Some(insts::OP_CTZ),
+                (0b_0110000, 0b_001, 0b_00100) => Some(insts::OP_SEXTB),
+                (0b_0110000, 0b_001, 0b_00101) => Some(insts::OP_SEXTH),
+                _ => None,
+            };
+            if let Some(inst) = inst_opt {
+                Some(
+                    Rtype::new(
+                        inst,
+                        rd(instruction_bits),
+                        rs1(instruction_bits),
+                        rs2(instruction_bits),
+                    )
+                    .0,
+                )
+            } else {
+                let inst_opt = match (funct7_value >> 1, funct3_value) {
+                    (0b_010010, 0b_001) => Some(insts::OP_BCLRI),
+                    (0b_010010, 0b_101) => Some(insts::OP_BEXTI),
+                    (0b_011010, 0b_001) => Some(insts::OP_BINVI),
+                    (0b_001010, 0b_001) => Some(insts::OP_BSETI),
+                    (0b_011000, 0b_101) => Some(insts::OP_RORI),
+                    _ => None,
+                };
+                inst_opt.map(|inst| {
+                    Itype::new_u(
+                        inst,
+                        rd(instruction_bits),
+                        rs1(instruction_bits),
+                        x(instruction_bits, 20, 6, 0),
+                    )
+                    .0
+                })
+            }
+        }
+        0b_0011011 => {
+            let funct3_value = funct3(instruction_bits);
+            let funct7_value = funct7(instruction_bits);
+            let rs2_value = rs2(instruction_bits);
+
+            match funct7_value {
+                0b_0110000 => match funct3_value {
+                    0b_001 => {
+                        let inst_opt = match rs2_value {
+                            0b_00000 => Some(insts::OP_CLZW),
+                            0b_00010 => Some(insts::OP_CPOPW),
+                            0b_00001 => Some(insts::OP_CTZW),
+                            _ => None,
+                        };
+                        inst_opt.map(|inst| {
+                            Rtype::new(inst, rd(instruction_bits), rs1(instruction_bits), rs2_value)
+                                .0
+                        })
+                    }
+                    0b_101 => Some(
+                        Itype::new_u(
+                            insts::OP_RORIW,
+                            rd(instruction_bits),
+                            rs1(instruction_bits),
+                            x(instruction_bits, 20, 5, 0),
+                        )
+                        .0,
+                    ),
+                    _ => None,
+                },
+                _ => {
+                    if funct7_value >> 1 == 0b_000010 && funct3_value == 0b_001 {
+                        Some(
+                            Itype::new_u(
+                                insts::OP_SLLIUW,
+                                rd(instruction_bits),
+                                rs1(instruction_bits),
+                                x(instruction_bits, 20, 6, 0),
+                            )
+                            .0,
+                        )
+                    } else {
+                        None
+                    }
+                }
+            }
+        }
+        _ => None,
+    };
+
+    inst.map(set_instruction_length_4)
+}
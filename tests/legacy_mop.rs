@@ -0,0 +1,731 @@
+// The hand-written macro-op fusion ckb-vm shipped before fusion became rule
+// based, kept verbatim as the reference the fusion rules are checked
+// against. Only self is replaced by the decoder and its VERSION.
+use ckb_vm::decoder::Decoder;
+use ckb_vm::instructions::{
+    extract_opcode, instruction_length, insts, set_instruction_length_n, Instruction, Itype,
+    R4type, R5type, Rtype, Utype,
+};
+use ckb_vm::machine::VERSION2;
+use ckb_vm::registers::{RA, ZERO};
+use ckb_vm::{Error, Memory};
+
+pub fn decode_mop<M: Memory>(
+    decoder: &mut Decoder,
+    version: u32,
+    memory: &mut M,
+    pc: u64,
+) -> Result<Instruction, Error> {
+    let head_instruction = decoder.decode_raw(memory, pc)?;
+    let head_opcode = extract_opcode(head_instruction);
+    match head_opcode {
+        insts::OP_ADD => {
+            let rule_adc = |decoder: &mut Decoder,
+                            memory: &mut M|
+             -> Result<Option<Instruction>, Error> {
+                let head_inst = Rtype(head_instruction);
+                let head_size = instruction_length(head_instruction);
+                if head_inst.rd() != head_inst.rs1() || head_inst.rs1() == head_inst.rs2() {
+                    return Ok(None);
+                }
+                let next_instruction = decoder.decode_raw(memory, pc + head_size as u64)?;
+                let next_opcode = extract_opcode(next_instruction);
+                if next_opcode != insts::OP_SLTU {
+                    return Ok(None);
+                }
+                let next_inst = Rtype(next_instruction);
+                let next_size = instruction_length(next_instruction);
+                if next_inst.rd() != head_inst.rs2()
+                    || head_inst.rs2() != next_inst.rs2()
+                    || next_inst.rs1() != head_inst.rs1()
+                {
+                    return Ok(None);
+                }
+                let neck_instruction =
+                    decoder.decode_raw(memory, pc + head_size as u64 + next_size as u64)?;
+                let neck_opcode = extract_opcode(neck_instruction);
+                if neck_opcode != insts::OP_ADD {
+                    return Ok(None);
+                }
+                let neck_inst = Rtype(neck_instruction);
+                let neck_size = instruction_length(neck_instruction);
+                if neck_inst.rd() != neck_inst.rs1()
+                    || neck_inst.rs1() != next_inst.rs1()
+                    || neck_inst.rs2() == head_inst.rs1()
+                    || neck_inst.rs2() == head_inst.rs2()
+                {
+                    return Ok(None);
+                }
+                let body_instruction = decoder.decode_raw(
+                    memory,
+                    pc + head_size as u64 + next_size as u64 + neck_size as u64,
+                )?;
+                let body_opcode = extract_opcode(body_instruction);
+                if body_opcode != insts::OP_SLTU {
+                    return Ok(None);
+                }
+                let body_inst = Rtype(body_instruction);
+                let body_size = instruction_length(body_instruction);
+                if body_inst.rd() != body_inst.rs2()
+                    || body_inst.rs2() != neck_inst.rs2()
+                    || body_inst.rs1() != neck_inst.rs1()
+                {
+                    return Ok(None);
+                }
+                let tail_instruction = decoder.decode_raw(
+                    memory,
+                    pc + head_size as u64 + next_size as u64 + neck_size as u64 + body_size as u64,
+                )?;
+                let tail_opcode = extract_opcode(tail_instruction);
+                if tail_opcode != insts::OP_OR {
+                    return Ok(None);
+                }
+                let tail_inst = Rtype(tail_instruction);
+                let tail_size = instruction_length(tail_instruction);
+                if tail_inst.rd() != tail_inst.rs1()
+                    || tail_inst.rs1() != head_inst.rs2()
+                    || tail_inst.rs2() != body_inst.rs2()
+                {
+                    return Ok(None);
+                }
+                if head_inst.rd() == ZERO || next_inst.rd() == ZERO || body_inst.rd() == ZERO {
+                    return Ok(None);
+                }
+                let fuze_inst = Rtype::new(
+                    insts::OP_ADC,
+                    head_inst.rd(),
+                    next_inst.rd(),
+                    body_inst.rd(),
+                );
+                let fuze_size = head_size + next_size + neck_size + body_size + tail_size;
+                Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)))
+            };
+            let rule_add3 = |decoder: &mut Decoder,
+                             memory: &mut M|
+             -> Result<Option<Instruction>, Error> {
+                if version < VERSION2 {
+                    return Ok(None);
+                }
+
+                let i0 = Rtype(head_instruction);
+                let i0_size = instruction_length(head_instruction);
+
+                let (i1, i1_size) = {
+                    let i1 = decoder.decode_raw(memory, pc + i0_size as u64)?;
+                    let i1_opcode = extract_opcode(i1);
+                    if i1_opcode != insts::OP_SLTU {
+                        return Ok(None);
+                    }
+                    (Rtype(i1), instruction_length(i1))
+                };
+
+                let (i2, i2_size) = {
+                    let i2 = decoder.decode_raw(memory, pc + i0_size as u64 + i1_size as u64)?;
+                    let i2_opcode = extract_opcode(i2);
+                    if i2_opcode != insts::OP_ADD {
+                        return Ok(None);
+                    }
+                    (Rtype(i2), instruction_length(i2))
+                };
+
+                let fuze_size = i0_size + i1_size + i2_size;
+
+                {
+                    // add r0, r1, r0
+                    // sltu r2, r0, r1
+                    // add r3, r2, r4
+                    //
+                    // r0 != r1
+                    // r0 != r4
+                    // r2 != r4
+                    // r0 != x0
+                    // r2 != x0
+                    let r0 = i0.rd();
+                    let r1 = i0.rs1();
+                    let r2 = i1.rd();
+                    let r3 = i2.rd();
+                    let r4 = i2.rs2();
+
+                    if i0.rd() == r0
+                        && i0.rs1() == r1
+                        && i0.rs2() == r0
+                        && i1.rd() == r2
+                        && i1.rs1() == r0
+                        && i1.rs2() == r1
+                        && i2.rd() == r3
+                        && i2.rs1() == r2
+                        && i2.rs2() == r4
+                        && r0 != r1
+                        && r0 != r4
+                        && r2 != r4
+                        && r0 != ZERO
+                        && r2 != ZERO
+                    {
+                        let fuze_inst = R5type::new(insts::OP_ADD3A, r0, r1, r2, r3, r4);
+                        return Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)));
+                    }
+                }
+
+                {
+                    // add r0, r1, r2
+                    // sltu r1, r0, r1
+                    // add r3, r1, r4
+                    //
+                    // r0 != r1
+                    // r0 != r4
+                    // r1 != r4
+                    // r0 != x0
+                    // r1 != x0
+                    let r0 = i0.rd();
+                    let r1 = i0.rs1();
+                    let r2 = i0.rs2();
+                    let r3 = i2.rd();
+                    let r4 = i2.rs2();
+
+                    if i0.rd() == r0
+                        && i0.rs1() == r1
+                        && i0.rs2() == r2
+                        && i1.rd() == r1
+                        && i1.rs1() == r0
+                        && i1.rs2() == r1
+                        && i2.rd() == r3
+                        && i2.rs1() == r1
+                        && i2.rs2() == r4
+                        && r0 != r1
+                        && r0 != r4
+                        && r1 != r4
+                        && r0 != ZERO
+                        && r1 != ZERO
+                    {
+                        let fuze_inst = R5type::new(insts::OP_ADD3B, r0, r1, r2, r3, r4);
+                        return Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)));
+                    }
+                }
+                {
+                    // add r0, r1, r2
+                    // sltu r3, r0, r1
+                    // add r3, r3, r4
+                    //
+                    // r0 != r1
+                    // r0 != r4
+                    // r3 != r4
+                    // r0 != x0
+                    // r3 != x0
+                    let r0 = i0.rd();
+                    let r1 = i0.rs1();
+                    let r2 = i0.rs2();
+                    let r3 = i1.rd();
+                    let r4 = i2.rs2();
+
+                    if i0.rd() == r0
+                        && i0.rs1() == r1
+                        && i0.rs2() == r2
+                        && i1.rd() == r3
+                        && i1.rs1() == r0
+                        && i1.rs2() == r1
+                        && i2.rd() == r3
+                        && i2.rs1() == r3
+                        && i2.rs2() == r4
+                        && r0 != r1
+                        && r0 != r4
+                        && r3 != r4
+                        && r0 != ZERO
+                        && r3 != ZERO
+                    {
+                        let fuze_inst = R5type::new(insts::OP_ADD3C, r0, r1, r2, r3, r4);
+                        return Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)));
+                    }
+                }
+                Ok(None)
+            };
+            let rule_adcs =
+                |decoder: &mut Decoder, memory: &mut M| -> Result<Option<Instruction>, Error> {
+                    // add r0, r1, r2
+                    // sltu r3, r0, r1
+                    //
+                    // or
+                    //
+                    // add r0, r2, r1
+                    // sltu r3, r0, r1
+                    //
+                    // r0 != r1
+                    // r0 != x0
+                    if version < VERSION2 {
+                        return Ok(None);
+                    }
+
+                    let mut i0 = Rtype(head_instruction);
+                    let i0_size = instruction_length(head_instruction);
+
+                    if i0.rd() == i0.rs1() && i0.rd() != i0.rs2() {
+                        i0 = Rtype::new(i0.op(), i0.rd(), i0.rs2(), i0.rs1());
+                    }
+
+                    let (i1, i1_size) = {
+                        let i1 = decoder.decode_raw(memory, pc + i0_size as u64)?;
+                        let i1_opcode = extract_opcode(i1);
+                        if i1_opcode != insts::OP_SLTU {
+                            return Ok(None);
+                        }
+                        (Rtype(i1), instruction_length(i1))
+                    };
+
+                    let r0 = i0.rd();
+                    let r1 = i0.rs1();
+                    let r2 = i0.rs2();
+                    let r3 = i1.rd();
+
+                    if i0.rd() == r0
+                        && i0.rs1() == r1
+                        && i0.rs2() == r2
+                        && i1.rd() == r3
+                        && i1.rs1() == r0
+                        && i1.rs2() == r1
+                        && r0 != r1
+                        && r0 != ZERO
+                    {
+                        let fuze_inst = R4type::new(insts::OP_ADCS, r0, r1, r2, r3);
+                        let fuze_size = i0_size + i1_size;
+                        Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)))
+                    } else {
+                        Ok(None)
+                    }
+                };
+            if let Ok(Some(i)) = rule_adc(decoder, memory) {
+                Ok(i)
+            } else if let Ok(Some(i)) = rule_add3(decoder, memory) {
+                Ok(i)
+            } else if let Ok(Some(i)) = rule_adcs(decoder, memory) {
+                Ok(i)
+            } else {
+                Ok(head_instruction)
+            }
+        }
+        insts::OP_SUB => {
+            let rule_sbb = |decoder: &mut Decoder,
+                            memory: &mut M|
+             -> Result<Option<Instruction>, Error> {
+                let head_inst = Rtype(head_instruction);
+                let head_size = instruction_length(head_instruction);
+                if head_inst.rd() != head_inst.rs2() || head_inst.rs1() == head_inst.rs2() {
+                    return Ok(None);
+                }
+                let next_instruction = decoder.decode_raw(memory, pc + head_size as u64)?;
+                let next_opcode = extract_opcode(next_instruction);
+                if next_opcode != insts::OP_SLTU {
+                    return Ok(None);
+                }
+                let next_inst = Rtype(next_instruction);
+                let next_size = instruction_length(next_instruction);
+                if next_inst.rd() == head_inst.rs1()
+                    || next_inst.rd() == head_inst.rs2()
+                    || next_inst.rs1() != head_inst.rs1()
+                    || next_inst.rs2() != next_inst.rs2()
+                {
+                    return Ok(None);
+                }
+                let neck_instruction =
+                    decoder.decode_raw(memory, pc + head_size as u64 + next_size as u64)?;
+                let neck_opcode = extract_opcode(neck_instruction);
+                if neck_opcode != insts::OP_SUB {
+                    return Ok(None);
+                }
+                let neck_inst = Rtype(neck_instruction);
+                let neck_size = instruction_length(neck_instruction);
+                if neck_inst.rd() != head_inst.rs1()
+                    || neck_inst.rs1() != head_inst.rs2()
+                    || neck_inst.rs2() == head_inst.rs1()
+                    || neck_inst.rs2() == head_inst.rs2()
+                    || neck_inst.rs2() == next_inst.rd()
+                {
+                    return Ok(None);
+                }
+                let body_instruction = decoder.decode_raw(
+                    memory,
+                    pc + head_size as u64 + next_size as u64 + neck_size as u64,
+                )?;
+                let body_opcode = extract_opcode(body_instruction);
+                if body_opcode != insts::OP_SLTU {
+                    return Ok(None);
+                }
+                let body_inst = Rtype(body_instruction);
+                let body_size = instruction_length(body_instruction);
+                if body_inst.rd() != neck_inst.rs2()
+                    || body_inst.rs1() != head_inst.rs2()
+                    || body_inst.rs2() != head_inst.rs1()
+                {
+                    return Ok(None);
+                }
+                let tail_instruction = decoder.decode_raw(
+                    memory,
+                    pc + head_size as u64 + next_size as u64 + neck_size as u64 + body_size as u64,
+                )?;
+                let tail_opcode = extract_opcode(tail_instruction);
+                if tail_opcode != insts::OP_OR {
+                    return Ok(None);
+                }
+                let tail_inst = Rtype(tail_instruction);
+                let tail_size = instruction_length(tail_instruction);
+                if tail_inst.rd() != head_inst.rd()
+                    || tail_inst.rs1() != neck_inst.rs2()
+                    || tail_inst.rs2() != next_inst.rd()
+                {
+                    return Ok(None);
+                }
+                let fuze_inst = R4type::new(
+                    insts::OP_SBB,
+                    head_inst.rs1(),
+                    head_inst.rs2(),
+                    neck_inst.rs2(),
+                    next_inst.rd(),
+                );
+                if head_inst.rs1() == ZERO
+                    || head_inst.rs2() == ZERO
+                    || neck_inst.rs2() == ZERO
+                    || next_inst.rd() == ZERO
+                {
+                    return Ok(None);
+                }
+                let fuze_size = head_size + next_size + neck_size + body_size + tail_size;
+                Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)))
+            };
+            let rule_sbbs =
+                |decoder: &mut Decoder, memory: &mut M| -> Result<Option<Instruction>, Error> {
+                    // sub r0, r1, r2
+                    // sltu r3, r1, r2
+                    //
+                    // r0 != r1
+                    // r0 != r2
+                    if version < VERSION2 {
+                        return Ok(None);
+                    }
+
+                    let i0 = Rtype(head_instruction);
+                    let i0_size = instruction_length(head_instruction);
+
+                    let (i1, i1_size) = {
+                        let i1 = decoder.decode_raw(memory, pc + i0_size as u64)?;
+                        let i1_opcode = extract_opcode(i1);
+                        if i1_opcode != insts::OP_SLTU {
+                            return Ok(None);
+                        }
+                        (Rtype(i1), instruction_length(i1))
+                    };
+
+                    let r0 = i0.rd();
+                    let r1 = i0.rs1();
+                    let r2 = i0.rs2();
+                    let r3 = i1.rd();
+
+                    if i0.rd() == r0
+                        && i0.rs1() == r1
+                        && i0.rs2() == r2
+                        && i1.rd() == r3
+                        && i1.rs1() == r1
+                        && i1.rs2() == r2
+                        && r0 != r1
+                        && r0 != r2
+                    {
+                        let fuze_inst = R4type::new(insts::OP_SBBS, r0, r1, r2, r3);
+                        let fuze_size = i0_size + i1_size;
+                        Ok(Some(set_instruction_length_n(fuze_inst.0, fuze_size)))
+                    } else {
+                        Ok(None)
+                    }
+                };
+            if let Ok(Some(i)) = rule_sbb(decoder, memory) {
+                Ok(i)
+            } else if let Ok(Some(i)) = rule_sbbs(decoder, memory) {
+                Ok(i)
+            } else {
+                Ok(head_instruction)
+            }
+        }
+        insts::OP_LUI => {
+            let head_inst = Utype(head_instruction);
+            let head_size = instruction_length(head_instruction);
+            let next_instruction = match decoder.decode_raw(memory, pc + head_size as u64) {
+                Ok(ni) => ni,
+                Err(_) => return Ok(head_instruction),
+            };
+            let next_opcode = extract_opcode(next_instruction);
+            match next_opcode {
+                insts::OP_JALR_VERSION1 => {
+                    let next_inst = Itype(next_instruction);
+                    let test_condition = if version >= VERSION2 {
+                        next_inst.rs1() == head_inst.rd()
+                            && next_inst.rd() == RA
+                            && next_inst.rs1() == RA
+                    } else {
+                        next_inst.rs1() == head_inst.rd() && next_inst.rd() == RA
+                    };
+                    if test_condition {
+                        let fuze_imm = head_inst
+                            .immediate_s()
+                            .wrapping_add(next_inst.immediate_s());
+                        let fuze_inst = Utype::new_s(insts::OP_FAR_JUMP_ABS, RA, fuze_imm);
+                        let next_size = instruction_length(next_instruction);
+                        let fuze_size = head_size + next_size;
+                        Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
+                    } else {
+                        Ok(head_instruction)
+                    }
+                }
+                insts::OP_ADDIW => {
+                    let next_inst = Itype(next_instruction);
+                    if next_inst.rs1() == next_inst.rd() && next_inst.rd() == head_inst.rd() {
+                        let fuze_imm = head_inst
+                            .immediate_s()
+                            .wrapping_add(next_inst.immediate_s());
+                        let fuze_inst =
+                            Utype::new_s(insts::OP_CUSTOM_LOAD_IMM, head_inst.rd(), fuze_imm);
+                        let next_size = instruction_length(next_instruction);
+                        let fuze_size = head_size + next_size;
+                        Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
+                    } else {
+                        Ok(head_instruction)
+                    }
+                }
+                _ => Ok(head_instruction),
+            }
+        }
+        insts::OP_AUIPC => {
+            let head_inst = Utype(head_instruction);
+            let head_size = instruction_length(head_instruction);
+            let next_instruction = match decoder.decode_raw(memory, pc + head_size as u64) {
+                Ok(ni) => ni,
+                Err(_) => return Ok(head_instruction),
+            };
+            let next_opcode = extract_opcode(next_instruction);
+            match next_opcode {
+                insts::OP_JALR_VERSION1 => {
+                    let next_inst = Itype(next_instruction);
+                    let mut result = head_instruction;
+
+                    if version >= VERSION2 {
+                        if next_inst.rs1() == head_inst.rd()
+                            && next_inst.rd() == RA
+                            && next_inst.rs1() == RA
+                        {
+                            if let Some(fuze_imm) =
+                                head_inst.immediate_s().checked_add(next_inst.immediate_s())
+                            {
+                                let fuze_inst = Utype::new_s(insts::OP_FAR_JUMP_REL, RA, fuze_imm);
+                                let next_size = instruction_length(next_instruction);
+                                let fuze_size = head_size + next_size;
+                                result = set_instruction_length_n(fuze_inst.0, fuze_size);
+                            }
+                        }
+                    } else {
+                        if next_inst.rs1() == head_inst.rd() && next_inst.rd() == RA {
+                            let fuze_imm = head_inst
+                                .immediate_s()
+                                .wrapping_add(next_inst.immediate_s());
+                            let fuze_inst = Utype::new_s(insts::OP_FAR_JUMP_REL, RA, fuze_imm);
+                            let next_size = instruction_length(next_instruction);
+                            let fuze_size = head_size + next_size;
+                            result = set_instruction_length_n(fuze_inst.0, fuze_size);
+                        }
+                    }
+                    Ok(result)
+                }
+                insts::OP_ADDI if version >= VERSION2 => {
+                    let next_inst = Itype(next_instruction);
+                    let mut result = head_instruction;
+
+                    if next_inst.rs1() == next_inst.rd() && next_inst.rd() == head_inst.rd() {
+                        if let Ok(pc) = i32::try_from(pc) {
+                            if let Some(fuze_imm) = head_inst
+                                .immediate_s()
+                                .checked_add(next_inst.immediate_s())
+                                .and_then(|s| s.checked_add(pc))
+                            {
+                                let fuze_inst = Utype::new_s(
+                                    insts::OP_CUSTOM_LOAD_IMM,
+                                    head_inst.rd(),
+                                    fuze_imm,
+                                );
+                                let next_size = instruction_length(next_instruction);
+                                let fuze_size = head_size + next_size;
+                                result = set_instruction_length_n(fuze_inst.0, fuze_size);
+                            }
+                        }
+                    }
+                    Ok(result)
+                }
+                _ => Ok(head_instruction),
+            }
+        }
+        insts::OP_MULH => {
+            let head_inst = Rtype(head_instruction);
+            let head_size = instruction_length(head_instruction);
+            let next_instruction = match decoder.decode_raw(memory, pc + head_size as u64) {
+                Ok(ni) => ni,
+                Err(_) => return Ok(head_instruction),
+            };
+            let next_opcode = extract_opcode(next_instruction);
+            match next_opcode {
+                insts::OP_MUL => {
+                    let next_inst = Rtype(next_instruction);
+                    if head_inst.rd() != head_inst.rs1()
+                        && head_inst.rd() != head_inst.rs2()
+                        && head_inst.rs1() == next_inst.rs1()
+                        && head_inst.rs2() == next_inst.rs2()
+                        && head_inst.rd() != next_inst.rd()
+                    {
+                        let next_size = instruction_length(next_instruction);
+                        let fuze_inst = R4type::new(
+                            insts::OP_WIDE_MUL,
+                            head_inst.rd(),
+                            head_inst.rs1(),
+                            head_inst.rs2(),
+                            next_inst.rd(),
+                        );
+                        let fuze_size = head_size + next_size;
+                        Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
+                    } else {
+                        Ok(head_instruction)
+                    }
+                }
+                _ => Ok(head_instruction),
+            }
+        }
+        insts::OP_MULHU => {
+            let head_inst = Rtype(head_instruction);
+            let head_size = instruction_length(head_instruction);
+            let next_instruction = match decoder.decode_raw(memory, pc + head_size as u64) {
+                Ok(ni) => ni,
+                Err(_) => return Ok(head_instruction),
+            };
+            let next_opcode = extract_opcode(next_instruction);
+            match next_opcode {
+                insts::OP_MUL => {
+                    let next_inst = Rtype(next_instruction);
+                    if head_inst.rd() != head_inst.rs1()
+                        && head_inst.rd() != head_inst.rs2()
+                        && head_inst.rs1() == next_inst.rs1()
+                        && head_inst.rs2() == next_inst.rs2()
+                        && head_inst.rd() != next_inst.rd()
+                    {
+                        let next_size = instruction_length(next_instruction);
+                        let fuze_inst = R4type::new(
+                            insts::OP_WIDE_MULU,
+                            head_inst.rd(),
+                            head_inst.rs1(),
+                            head_inst.rs2(),
+                            next_inst.rd(),
+                        );
+                        let fuze_size = head_size + next_size;
+                        Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
+                    } else {
+                        Ok(head_instruction)
+                    }
+                }
+                _ => Ok(head_instruction),
+            }
+        }
+        insts::OP_MULHSU => {
+            let head_inst = Rtype(head_instruction);
+            let head_size = instruction_length(head_instruction);
+            let next_instruction = match decoder.decode_raw(memory, pc + head_size as u64) {
+                Ok(ni) => ni,
+                Err(_) => return Ok(head_instruction),
+            };
+            let next_opcode = extract_opcode(next_instruction);
+            match next_opcode {
+                insts::OP_MUL => {
+                    let next_inst = Rtype(next_instruction);
+                    if head_inst.rd() != head_inst.rs1()
+                        && head_inst.rd() != head_inst.rs2()
+                        && head_inst.rs1() == next_inst.rs1()
+                        && head_inst.rs2() == next_inst.rs2()
+                        && head_inst.rd() != next_inst.rd()
+                    {
+                        let next_size = instruction_length(next_instruction);
+                        let fuze_inst = R4type::new(
+                            insts::OP_WIDE_MULSU,
+                            head_inst.rd(),
+                            head_inst.rs1(),
+                            head_inst.rs2(),
+                            next_inst.rd(),
+                        );
+                        let fuze_size = head_size + next_size;
+                        Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
+                    } else {
+                        Ok(head_instruction)
+                    }
+                }
+                _ => Ok(head_instruction),
+            }
+        }
+        insts::OP_DIV => {
+            let head_inst = Rtype(head_instruction);
+            let head_size = instruction_length(head_instruction);
+            let next_instruction = match decoder.decode_raw(memory, pc + head_size as u64) {
+                Ok(ni) => ni,
+                Err(_) => return Ok(head_instruction),
+            };
+            let next_opcode = extract_opcode(next_instruction);
+            match next_opcode {
+                insts::OP_REM => {
+                    let next_inst = Rtype(next_instruction);
+                    if head_inst.rd() != head_inst.rs1()
+                        && head_inst.rd() != head_inst.rs2()
+                        && head_inst.rs1() == next_inst.rs1()
+                        && head_inst.rs2() == next_inst.rs2()
+                        && head_inst.rd() != next_inst.rd()
+                    {
+                        let next_size = instruction_length(next_instruction);
+                        let fuze_inst = R4type::new(
+                            insts::OP_WIDE_DIV,
+                            head_inst.rd(),
+                            head_inst.rs1(),
+                            head_inst.rs2(),
+                            next_inst.rd(),
+                        );
+                        let fuze_size = head_size + next_size;
+                        Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
+                    } else {
+                        Ok(head_instruction)
+                    }
+                }
+                _ => Ok(head_instruction),
+            }
+        }
+        insts::OP_DIVU => {
+            let head_inst = Rtype(head_instruction);
+            let head_size = instruction_length(head_instruction);
+            let next_instruction = match decoder.decode_raw(memory, pc + head_size as u64) {
+                Ok(ni) => ni,
+                Err(_) => return Ok(head_instruction),
+            };
+            let next_opcode = extract_opcode(next_instruction);
+            match next_opcode {
+                insts::OP_REMU => {
+                    let next_inst = Rtype(next_instruction);
+                    if head_inst.rd() != head_inst.rs1()
+                        && head_inst.rd() != head_inst.rs2()
+                        && head_inst.rs1() == next_inst.rs1()
+                        && head_inst.rs2() == next_inst.rs2()
+                        && head_inst.rd() != next_inst.rd()
+                    {
+                        let next_size = instruction_length(next_instruction);
+                        let fuze_inst = R4type::new(
+                            insts::OP_WIDE_DIVU,
+                            head_inst.rd(),
+                            head_inst.rs1(),
+                            head_inst.rs2(),
+                            next_inst.rd(),
+                        );
+                        let fuze_size = head_size + next_size;
+                        Ok(set_instruction_length_n(fuze_inst.0, fuze_size))
+                    } else {
+                        Ok(head_instruction)
+                    }
+                }
+                _ => Ok(head_instruction),
+            }
+        }
+        _ => Ok(head_instruction),
+    }
+}
@@ -0,0 +1,329 @@
+#![cfg(all(has_asm, any(target_arch = "x86_64", target_arch = "aarch64")))]
+use bytes::Bytes;
+use ckb_vm::assembler::assemble;
+use ckb_vm::cost_model::estimate_cycles;
+use ckb_vm::machine::aot::{AotCache, AotCode, AotMachine};
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::trace::TraceMachine;
+use ckb_vm::machine::{VERSION1, VERSION2, VERSION3};
+use ckb_vm::memory::Memory;
+use ckb_vm::{
+    CoreMachine, DefaultMachine, DefaultMachineBuilder, Error, SupportMachine, ISA_A, ISA_B,
+    ISA_IMC, ISA_MOP,
+};
+use std::sync::Arc;
+
+const CONFIGS: &[(u8, u32)] = &[
+    (ISA_IMC | ISA_B, VERSION1),
+    (ISA_IMC | ISA_A | ISA_B | ISA_MOP, VERSION2),
+    (ISA_IMC | ISA_A | ISA_B | ISA_MOP, VERSION3),
+];
+
+// Result, cycles, registers, pc and memory after a run
+type Outcome = (Result<i8, Error>, u64, Vec<u64>, u64, Bytes);
+
+fn build(isa: u8, version: u32, max_cycles: u64) -> DefaultMachine<Box<AsmCoreMachine>> {
+    let core = AsmCoreMachine::new(isa, version, max_cycles);
+    DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(estimate_cycles))
+        .build()
+}
+
+fn outcome(
+    result: Result<i8, Error>,
+    machine: &mut DefaultMachine<Box<AsmCoreMachine>>,
+) -> Outcome {
+    let memory_size = machine.memory().memory_size() as u64;
+    (
+        result,
+        machine.cycles(),
+        machine.registers().to_vec(),
+        *machine.pc(),
+        machine.memory_mut().load_bytes(0, memory_size).unwrap(),
+    )
+}
+
+fn run_trace(program: &Bytes, isa: u8, version: u32, max_cycles: u64) -> Option<Outcome> {
+    let mut machine = TraceMachine::new(build(isa, version, max_cycles));
+    machine.load_program(program, &["main".into()]).ok()?;
+    let result = machine.run();
+    Some(outcome(result, &mut machine.machine))
+}
+
+fn run_aot(
+    program: &Bytes,
+    code: Arc<AotCode>,
+    isa: u8,
+    version: u32,
+    max_cycles: u64,
+) -> Option<Outcome> {
+    let mut machine = AotMachine::new(build(isa, version, max_cycles), code);
+    machine.load_program(program, &["main".into()]).ok()?;
+    let result = machine.run();
+    Some(outcome(result, &mut machine.machine))
+}
+
+fn assert_same_as_trace(name: &str, program: &Bytes, isa: u8, version: u32) {
+    let expected = match run_trace(program, isa, version, 20_000_000) {
+        Some(expected) => expected,
+        None => return,
+    };
+    let code = Arc::new(AotCode::compile(program, isa, version).unwrap());
+    let actual = run_aot(program, code.clone(), isa, version, 20_000_000).unwrap();
+    assert_eq!(actual.0, expected.0, "{} {} {}", name, isa, version);
+    assert!(actual == expected, "{} {} {}", name, isa, version);
+
+    // Running out of cycles in the middle of a trace stops at the same
+    // instruction.
+    let limit = expected.1 / 2 + 1;
+    let expected = run_trace(program, isa, version, limit).unwrap();
+    let actual = run_aot(program, code, isa, version, limit).unwrap();
+    assert!(actual == expected, "{} {} {} limit", name, isa, version);
+}
+
+#[test]
+pub fn test_aot_programs() {
+    let programs = [
+        "alloc_many",
+        "amo_check_write",
+        "amo_compare",
+        "amo_write_permission",
+        "asm_trace_bug",
+        "auipc_no_sign_extend",
+        "cadd_hints",
+        "clang_sample",
+        "clmul_bug",
+        "clzw_bug",
+        "ebreak64",
+        "invalid_read64",
+        "jalr_bug",
+        "jalr_bug_noc",
+        "jump0_64",
+        "misaligned_jump64",
+        "mop_adc",
+        "mop_add3",
+        "mop_far_jump",
+        "mop_jump_abs_version1_reg_not_updated_bug",
+        "mop_jump_rel_version1_bug",
+        "mop_ld_signextend_32",
+        "mop_random_adc_sbb",
+        "mop_wide_divide",
+        "mop_wide_multiply",
+        "mulw64",
+        "orc_bug",
+        "read_at_boundary64",
+        "read_memory",
+        "rorw_in_end_of_aot_block",
+        "rvc_pageend",
+        "sbinvi_aot_load_imm_bug",
+        "sc_after_sc",
+        "sc_only",
+        "simple64",
+        "sp_alignment_test",
+        "trace64",
+        "unaligned64",
+        "writable_page",
+        "write_at_boundary64",
+        "write_large_address64",
+        "zero_address",
+    ];
+    for name in programs {
+        let program: Bytes = std::fs::read(format!("tests/programs/{}", name))
+            .unwrap()
+            .into();
+        for (isa, version) in CONFIGS {
+            assert_same_as_trace(name, &program, *isa, *version);
+        }
+    }
+}
+
+const NATIVE_OPS: &str = r#"
+    .text
+    .globl _start
+_start:
+    la s0, buffer
+    li s1, 0
+    li s2, 40
+    li a0, 0x123456789abcdef
+loop:
+    add t0, a0, s1
+    sub t1, a0, s1
+    and t2, t0, t1
+    or t3, t0, t1
+    xor t4, t2, t3
+    sll t5, t4, s1
+    srl t6, t4, s1
+    sra a1, a0, s1
+    slt a2, t1, t0
+    sltu a3, t1, t0
+    mul a4, t0, t1
+    mulh a5, t0, t1
+    mulhu a6, t0, t1
+    addw a7, t0, t1
+    subw t0, a7, t4
+    sllw t1, t0, s1
+    srlw t2, t0, s1
+    sraw t3, t0, s1
+    mulw t4, t3, a6
+    addiw t5, t4, -2000
+    slli t6, t5, 17
+    srli a1, t6, 3
+    srai a2, t6, 9
+    slliw a3, t5, 7
+    srliw a4, t5, 5
+    sraiw a5, t5, 11
+    slti a6, a5, -3
+    sltiu a7, a5, 12
+    andi t0, a5, 0x7f0
+    ori t1, t0, -16
+    xori t2, t1, 0x555
+    lui t3, 0xfffff
+    auipc t4, 0x12
+    add a0, a0, t0
+    add a0, a0, t1
+    add a0, a0, t2
+    add a0, a0, t3
+    add a0, a0, t4
+    add a0, a0, t5
+    add a0, a0, t6
+    add a0, a0, a1
+    add a0, a0, a2
+    add a0, a0, a3
+    add a0, a0, a4
+    add a0, a0, a5
+    add a0, a0, a6
+    add a0, a0, a7
+    andi t0, s1, 7
+    add t0, t0, s0
+    sb a0, 0(t0)
+    sh a0, 2(t0)
+    sw a0, 4(t0)
+    sd a0, 8(t0)
+    lb t1, 1(t0)
+    lbu t2, 3(t0)
+    lh t3, 2(t0)
+    lhu t4, 6(t0)
+    lw t5, 4(t0)
+    lwu t6, 8(t0)
+    ld a1, 8(t0)
+    add a0, a0, t1
+    add a0, a0, t2
+    add a0, a0, t3
+    add a0, a0, t4
+    add a0, a0, t5
+    add a0, a0, t6
+    add a0, a0, a1
+    jal ra, mix
+    addi s1, s1, 1
+    blt s1, s2, loop
+    bge s1, s2, check
+    j fail
+check:
+    bltu s2, s1, fail
+    bgeu s1, s2, ok
+fail:
+    li a0, 1
+ok:
+    beq a0, zero, fail
+    bne s1, s2, fail
+    la t0, pointer
+    ld t0, 0(t0)
+    jalr ra, t0, 0
+    # Store into the code, which is not writable
+    la t0, _start
+    sd a0, 0(t0)
+    li a7, 93
+    ecall
+mix:
+    srli t0, a0, 29
+    xor a0, a0, t0
+    ret
+indirect:
+    addi a0, a0, 3
+    ret
+
+    .data
+pointer:
+    .dword indirect
+buffer:
+    .dword 0, 0, 0
+"#;
+
+#[test]
+pub fn test_aot_native_ops() {
+    let program = assemble::<u64>(NATIVE_OPS).unwrap().to_elf();
+    for (isa, version) in CONFIGS {
+        let code = AotCode::compile(&program, *isa, *version).unwrap();
+        assert!(code.trace_count() > 0);
+        let result = run_aot(&program, Arc::new(code), *isa, *version, u64::MAX).unwrap();
+        assert!(matches!(result.0, Err(Error::MemWriteOnExecutablePage(_))));
+        assert_same_as_trace("native_ops", &program, *isa, *version);
+    }
+}
+
+#[test]
+pub fn test_aot_matches_asm() {
+    let program: Bytes = std::fs::read("tests/programs/clang_sample").unwrap().into();
+    let code = Arc::new(AotCode::compile(&program, ISA_IMC, VERSION1).unwrap());
+    let mut aot = AotMachine::new(build(ISA_IMC, VERSION1, u64::MAX), code);
+    aot.load_program(&program, &["main".into()]).unwrap();
+    let mut asm = AsmMachine::new(build(ISA_IMC, VERSION1, u64::MAX));
+    asm.load_program(&program, &["main".into()]).unwrap();
+    assert_eq!(aot.run(), asm.run());
+    assert_eq!(aot.machine.registers(), asm.machine.registers());
+    assert_eq!(aot.machine.cycles(), asm.machine.cycles());
+}
+
+#[test]
+pub fn test_aot_other_program_falls_back() {
+    // Code compiled for another program is never entered.
+    let program: Bytes = std::fs::read("tests/programs/simple64").unwrap().into();
+    let other: Bytes = std::fs::read("tests/programs/clang_sample").unwrap().into();
+    let code = Arc::new(AotCode::compile(&other, ISA_IMC, VERSION1).unwrap());
+    let expected = run_trace(&program, ISA_IMC, VERSION1, u64::MAX).unwrap();
+    let actual = run_aot(&program, code, ISA_IMC, VERSION1, u64::MAX).unwrap();
+    assert!(actual == expected);
+}
+
+#[test]
+pub fn test_aot_cache() {
+    let dir = std::env::temp_dir().join(format!("ckb-vm-aot-cache-{}", std::process::id()));
+    let cache = AotCache::new(&dir);
+    let program: Bytes = std::fs::read("tests/programs/clang_sample").unwrap().into();
+    let (isa, version) = (ISA_IMC | ISA_B | ISA_MOP, VERSION2);
+    let path = cache.path(&program, isa, version);
+    assert!(!path.exists());
+
+    let compiled = cache.load_or_compile(&program, isa, version).unwrap();
+    let stored = std::fs::read(&path).unwrap();
+    assert_eq!(compiled.to_bytes(), stored);
+    let loaded = cache.load_or_compile(&program, isa, version).unwrap();
+    assert_eq!(loaded.to_bytes(), stored);
+    assert_eq!(loaded.program_hash(), compiled.program_hash());
+    let expected = run_trace(&program, isa, version, u64::MAX).unwrap();
+    let actual = run_aot(&program, Arc::new(loaded), isa, version, u64::MAX).unwrap();
+    assert!(actual == expected);
+
+    // Different ISA or version use different artifacts
+    assert_ne!(cache.path(&program, isa, VERSION1), path);
+    assert_ne!(cache.path(&program, ISA_IMC, version), path);
+    // Artifacts of other crate versions are never picked up
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert!(name.contains(env!("CARGO_PKG_VERSION")));
+
+    // A damaged artifact is rejected and replaced
+    let mut damaged = stored.clone();
+    let middle = damaged.len() / 2;
+    damaged[middle] ^= 0xff;
+    assert!(matches!(AotCode::from_bytes(&damaged), Err(Error::Aot(_))));
+    assert!(matches!(
+        AotCode::from_bytes(&stored[..stored.len() - 1]),
+        Err(Error::Aot(_))
+    ));
+    std::fs::write(&path, &damaged).unwrap();
+    let recompiled = cache.load_or_compile(&program, isa, version).unwrap();
+    assert_eq!(recompiled.to_bytes(), stored);
+    assert_eq!(std::fs::read(&path).unwrap(), stored);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,14 +1,22 @@
 #![cfg(has_asm)]
 use bytes::Bytes;
-use ckb_vm::cost_model::constant_cycles;
-use ckb_vm::decoder::build_decoder;
+use ckb_vm::assembler::assemble;
+use ckb_vm::ckb_vm_definitions::asm::{calculate_slot, FixedTrace, TRACE_SIZE};
+use ckb_vm::cost_model::{constant_cycles, estimate_cycles};
+use ckb_vm::decoder::{build_decoder, Decoder, InstDecoder};
 use ckb_vm::error::OutOfBoundKind;
-use ckb_vm::machine::asm::traces::{MemoizedDynamicTraceDecoder, MemoizedFixedTraceDecoder};
+use ckb_vm::machine::asm::traces::{
+    decode_fixed_trace, ChainedTraceDecoder, MemoizedDynamicTraceDecoder,
+    MemoizedFixedTraceDecoder, TraceDecoder,
+};
 use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
-use ckb_vm::machine::{CoreMachine, VERSION0, VERSION1, VERSION2};
+use ckb_vm::machine::{CoreMachine, DefaultMachine, VERSION0, VERSION1, VERSION2};
 use ckb_vm::memory::Memory;
 use ckb_vm::registers::{A0, A1, A2, A3, A4, A5, A7};
-use ckb_vm::{Debugger, DefaultMachineBuilder, Error, Register, SupportMachine, Syscalls, ISA_IMC};
+use ckb_vm::{
+    Debugger, DefaultMachineBuilder, Error, Instruction, Register, SupportMachine, Syscalls, ISA_B,
+    ISA_IMC, ISA_MOP,
+};
 use std::fs;
 use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::Arc;
@@ -496,6 +504,348 @@ fn test_memoized_dynamic_secp256k1() {
     assert_eq!(result.unwrap(), 0);
 }
 
+#[test]
+fn test_memoized_cache_round_trip() {
+    let isa = ISA_IMC | ISA_B | ISA_MOP;
+    let version = VERSION2;
+    let buffer: Bytes = fs::read("benches/data/secp256k1_bench").unwrap().into();
+    let args: Vec<Bytes> = vec!["secp256k1_bench",
+                                      "033f8cf9c4d51a33206a6c1c6b27d2cc5129daa19dbd1fc148d395284f6b26411f",
+                                      "304402203679d909f43f073c7c1dcf8468a485090589079ee834e6eed92fea9b09b06a2402201e46f1075afa18f306715e7db87493e7b7e779569aa13c64ab3d09980b3560a3",
+                                      "foo",
+                                      "bar"].into_iter().map(|a| a.into()).collect();
+    let build_with = |cycle_func: fn(Instruction) -> u64| {
+        let asm_core = AsmCoreMachine::new(isa, version, u64::MAX);
+        let core = DefaultMachineBuilder::new(asm_core)
+            .instruction_cycle_func(Box::new(cycle_func))
+            .build();
+        let mut machine = AsmMachine::new(core);
+        machine.load_program(&buffer, &args).unwrap();
+        machine
+    };
+    let build = || build_with(constant_cycles);
+
+    let mut machine = build();
+    let mut decoder = MemoizedFixedTraceDecoder::new(build_decoder::<u64>(isa, version));
+    assert_eq!(machine.run_with_decoder(&mut decoder), Ok(0));
+    let cycles = machine.machine.cycles();
+    let data = decoder.to_bytes(&buffer, isa, version);
+    let mut decoder = MemoizedFixedTraceDecoder::from_bytes(
+        build_decoder::<u64>(isa, version),
+        &data,
+        &buffer,
+        isa,
+        version,
+        &constant_cycles,
+    )
+    .unwrap();
+    assert_eq!(decoder.to_bytes(&buffer, isa, version), data);
+    let mut machine = build();
+    assert_eq!(machine.run_with_decoder(&mut decoder), Ok(0));
+    assert_eq!(machine.machine.cycles(), cycles);
+    assert_eq!(decoder.to_bytes(&buffer, isa, version), data);
+
+    let mut machine = build();
+    let mut decoder = MemoizedDynamicTraceDecoder::new(build_decoder::<u64>(isa, version));
+    assert_eq!(machine.run_with_decoder(&mut decoder), Ok(0));
+    assert_eq!(machine.machine.cycles(), cycles);
+    let data = decoder.to_bytes(&buffer, isa, version);
+    let mut decoder = MemoizedDynamicTraceDecoder::from_bytes(
+        build_decoder::<u64>(isa, version),
+        &data,
+        &buffer,
+        isa,
+        version,
+        &constant_cycles,
+    )
+    .unwrap();
+    assert_eq!(decoder.to_bytes(&buffer, isa, version), data);
+    let mut machine = build();
+    assert_eq!(machine.run_with_decoder(&mut decoder), Ok(0));
+    assert_eq!(machine.machine.cycles(), cycles);
+    assert_eq!(decoder.to_bytes(&buffer, isa, version), data);
+
+    // Cycles are computed by the cycle function given on load, not taken
+    // from the cache.
+    let mut machine = build_with(estimate_cycles);
+    assert_eq!(machine.run(), Ok(0));
+    let estimated = machine.machine.cycles();
+    assert_ne!(estimated, cycles);
+    let mut decoder = MemoizedDynamicTraceDecoder::from_bytes(
+        build_decoder::<u64>(isa, version),
+        &data,
+        &buffer,
+        isa,
+        version,
+        &estimate_cycles,
+    )
+    .unwrap();
+    let mut machine = build_with(estimate_cycles);
+    assert_eq!(machine.run_with_decoder(&mut decoder), Ok(0));
+    assert_eq!(machine.machine.cycles(), estimated);
+
+    // Damaged caches are rejected.
+    let mut damaged = data.clone();
+    damaged[20] ^= 1;
+    assert!(matches!(
+        MemoizedDynamicTraceDecoder::from_bytes(
+            build_decoder::<u64>(isa, version),
+            &damaged,
+            &buffer,
+            isa,
+            version,
+            &constant_cycles,
+        ),
+        Err(Error::TraceCache(_))
+    ));
+    assert!(matches!(
+        MemoizedDynamicTraceDecoder::from_bytes(
+            build_decoder::<u64>(isa, version),
+            &data[..data.len() - 1],
+            &buffer,
+            isa,
+            version,
+            &constant_cycles,
+        ),
+        Err(Error::TraceCache(_))
+    ));
+    assert!(matches!(
+        MemoizedFixedTraceDecoder::from_bytes(
+            build_decoder::<u64>(isa, version),
+            &[],
+            &buffer,
+            isa,
+            version,
+            &constant_cycles,
+        ),
+        Err(Error::TraceCache(_))
+    ));
+
+    // Caches are bound to the program, ISA, VERSION and register width.
+    let other: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    assert!(matches!(
+        MemoizedDynamicTraceDecoder::from_bytes(
+            build_decoder::<u64>(isa, version),
+            &data,
+            &other,
+            isa,
+            version,
+            &constant_cycles,
+        ),
+        Err(Error::TraceCache(_))
+    ));
+    assert!(matches!(
+        MemoizedDynamicTraceDecoder::from_bytes(
+            build_decoder::<u64>(ISA_IMC | ISA_MOP, version),
+            &data,
+            &buffer,
+            ISA_IMC | ISA_MOP,
+            version,
+            &constant_cycles,
+        ),
+        Err(Error::TraceCache(_))
+    ));
+    assert!(matches!(
+        MemoizedDynamicTraceDecoder::from_bytes(
+            build_decoder::<u64>(isa, VERSION1),
+            &data,
+            &buffer,
+            isa,
+            VERSION1,
+            &constant_cycles,
+        ),
+        Err(Error::TraceCache(_))
+    ));
+    assert!(matches!(
+        MemoizedDynamicTraceDecoder::from_bytes(
+            build_decoder::<u32>(isa, version),
+            &data,
+            &buffer,
+            isa,
+            version,
+            &constant_cycles,
+        ),
+        Err(Error::TraceCache(_))
+    ));
+}
+
+#[test]
+fn test_chained_secp256k1() {
+    let buffer: Bytes = fs::read("benches/data/secp256k1_bench").unwrap().into();
+    let args: Vec<Bytes> = vec!["secp256k1_bench",
+                                      "033f8cf9c4d51a33206a6c1c6b27d2cc5129daa19dbd1fc148d395284f6b26411f",
+                                      "304402203679d909f43f073c7c1dcf8468a485090589079ee834e6eed92fea9b09b06a2402201e46f1075afa18f306715e7db87493e7b7e779569aa13c64ab3d09980b3560a3",
+                                      "foo",
+                                      "bar"].into_iter().map(|a| a.into()).collect();
+    for (isa, version) in [(ISA_IMC, VERSION1), (ISA_IMC | ISA_B | ISA_MOP, VERSION2)] {
+        let run = |decoder: Option<&mut ChainedTraceDecoder<_>>| {
+            let asm_core = AsmCoreMachine::new(isa, version, u64::MAX);
+            let core = DefaultMachineBuilder::new(asm_core)
+                .instruction_cycle_func(Box::new(constant_cycles))
+                .build();
+            let mut machine = AsmMachine::new(core);
+            machine.load_program(&buffer, &args).unwrap();
+            let result = match decoder {
+                Some(decoder) => machine.run_with_decoder(decoder),
+                None => machine.run(),
+            };
+            (
+                result,
+                machine.machine.cycles(),
+                machine.machine.registers().to_vec(),
+            )
+        };
+        let expected = run(None);
+        assert_eq!(expected.0, Ok(0));
+        let mut decoder = ChainedTraceDecoder::new(build_decoder::<u64>(isa, version));
+        assert_eq!(run(Some(&mut decoder)), expected);
+        assert!(decoder.chained_count() > 0);
+        // Chained traces are reused by the next machine
+        decoder.clear_traces();
+        assert_eq!(run(Some(&mut decoder)), expected);
+    }
+}
+
+#[test]
+fn test_chained_cycles_exceeded() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    for max_cycles in 1..200 {
+        let run = |chained: bool| {
+            let asm_core = AsmCoreMachine::new(ISA_IMC, VERSION1, max_cycles);
+            let core = DefaultMachineBuilder::new(asm_core)
+                .instruction_cycle_func(Box::new(constant_cycles))
+                .build();
+            let mut machine = AsmMachine::new(core);
+            machine.load_program(&buffer, &["simple".into()]).unwrap();
+            let result = if chained {
+                let mut decoder = ChainedTraceDecoder::new(build_decoder::<u64>(ISA_IMC, VERSION1));
+                machine.run_with_decoder(&mut decoder)
+            } else {
+                machine.run()
+            };
+            (result, machine.machine.cycles(), *machine.machine.pc())
+        };
+        assert_eq!(run(true), run(false));
+    }
+}
+
+#[test]
+fn test_chained_tight_loop_pause() {
+    // Both traces jump to each other without ever going through the trace
+    // table, pause must still be honored.
+    let program = assemble::<u64>(
+        "
+    .text
+    .globl _start
+_start:
+    addi a0, a0, 1
+    bne a0, zero, next
+    li a7, 93
+    ecall
+next:
+    j _start
+",
+    )
+    .unwrap()
+    .to_elf();
+    let asm_core = AsmCoreMachine::new(ISA_IMC, VERSION2, u64::MAX);
+    let core = DefaultMachineBuilder::new(asm_core).build();
+    let mut machine = AsmMachine::new(core);
+    machine.load_program(&program, &["main".into()]).unwrap();
+    let mut decoder = ChainedTraceDecoder::new(build_decoder::<u64>(ISA_IMC, VERSION2));
+    let signal = machine.machine.pause();
+    let jh = thread::spawn(move || {
+        let result = machine.run_with_decoder(&mut decoder);
+        (result, decoder.chained_count())
+    });
+    thread::sleep(std::time::Duration::from_millis(100));
+    signal.interrupt();
+    let (result, chained) = jh.join().unwrap();
+    assert_eq!(result, Err(Error::Pause));
+    assert!(chained >= 2);
+}
+
+// Links every trace to a trace starting nowhere near the jump targets,
+// charging more cycles than any machine has.
+struct MislinkedTraceDecoder {
+    decoder: Decoder,
+    traces: Vec<FixedTrace>,
+    bogus: Box<FixedTrace>,
+}
+
+impl MislinkedTraceDecoder {
+    fn new(decoder: Decoder) -> Self {
+        Self {
+            decoder,
+            traces: vec![FixedTrace::default(); TRACE_SIZE],
+            bogus: Box::new(FixedTrace {
+                address: 0x2,
+                length: 4,
+                cycles: u64::MAX / 2,
+                ..FixedTrace::default()
+            }),
+        }
+    }
+}
+
+impl InstDecoder for MislinkedTraceDecoder {
+    fn decode<M: Memory>(&mut self, memory: &mut M, pc: u64) -> Result<Instruction, Error> {
+        self.decoder.decode(memory, pc)
+    }
+
+    fn reset_instructions_cache(&mut self) -> Result<(), Error> {
+        self.decoder.reset_instructions_cache()
+    }
+}
+
+impl TraceDecoder for MislinkedTraceDecoder {
+    fn fixed_traces(&self) -> *const FixedTrace {
+        self.traces.as_ptr()
+    }
+
+    fn fixed_trace_size(&self) -> u64 {
+        TRACE_SIZE as u64
+    }
+
+    fn prepare_traces(
+        &mut self,
+        machine: &mut DefaultMachine<Box<AsmCoreMachine>>,
+    ) -> Result<(), Error> {
+        let (mut trace, _) = decode_fixed_trace(&mut self.decoder, machine, None)?;
+        trace.jump_trace = self.bogus.as_ref() as *const FixedTrace as u64;
+        self.traces[calculate_slot(*machine.pc())] = trace;
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        self.traces.fill(FixedTrace::default());
+        self.decoder.reset_instructions_cache()
+    }
+}
+
+#[test]
+pub fn test_chained_trace_address_mismatch() {
+    // A linked trace not starting at the jump target is never entered.
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    for version in [VERSION1, VERSION2] {
+        let run = |decoder: Option<&mut MislinkedTraceDecoder>| {
+            let asm_core = AsmCoreMachine::new(ISA_IMC, version, 1 << 40);
+            let core = DefaultMachineBuilder::new(asm_core)
+                .instruction_cycle_func(Box::new(constant_cycles))
+                .build();
+            let mut machine = AsmMachine::new(core);
+            machine.load_program(&buffer, &["simple".into()]).unwrap();
+            let result = match decoder {
+                Some(decoder) => machine.run_with_decoder(decoder),
+                None => machine.run(),
+            };
+            (result, machine.machine.cycles())
+        };
+        let mut decoder = MislinkedTraceDecoder::new(build_decoder::<u64>(ISA_IMC, version));
+        assert_eq!(run(Some(&mut decoder)), run(None));
+    }
+}
+
 #[test]
 pub fn test_big_binary() {
     let buffer = fs::read("tests/programs/big_binary").unwrap().into();
@@ -0,0 +1,562 @@
+#![cfg(all(has_asm, target_arch = "x86_64"))]
+use bytes::Bytes;
+use ckb_vm::assembler::assemble_elf;
+use ckb_vm::decoder::build_decoder;
+use ckb_vm::elf::{AT_NULL, AT_RANDOM};
+use ckb_vm::machine::asm::traces::{
+    ChainedTraceDecoder, MemoizedDynamicTraceDecoder, MemoizedFixedTraceDecoder,
+};
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::trace::TraceMachine;
+use ckb_vm::machine::{
+    CoreMachine, DefaultCoreMachine, StackEnv, VERSION0, VERSION1, VERSION2, VERSION3,
+};
+use ckb_vm::memory::Memory;
+use ckb_vm::registers::{A7, SP};
+use ckb_vm::{
+    DefaultMachineBuilder, Error, Register, SparseMemory, SupportMachine, Syscalls, WXorXMemory,
+    ISA_A, ISA_B, ISA_IMC, ISA_MOP,
+};
+use std::sync::{Arc, Mutex};
+
+const ISA: u8 = ISA_IMC | ISA_A | ISA_B;
+
+// Records all registers whenever the program calls ecall 2000.
+struct RecordSyscall {
+    records: Arc<Mutex<Vec<Vec<u64>>>>,
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for RecordSyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != 2000 {
+            return Ok(false);
+        }
+        let registers = machine.registers().iter().map(|r| r.to_u64()).collect();
+        self.records.lock().unwrap().push(registers);
+        Ok(true)
+    }
+}
+
+struct Outcome {
+    result: Result<i8, Error>,
+    cycles: u64,
+    pc: u64,
+    registers: Vec<u64>,
+    records: Vec<Vec<u64>>,
+    stack: Vec<u8>,
+}
+
+fn sign_extend(value: u64) -> u64 {
+    value as u32 as i32 as i64 as u64
+}
+
+fn read_word<M: Memory>(memory: &mut M, address: u64) -> u64 {
+    let bytes = memory.load_bytes(address, 4).unwrap();
+    u64::from(u32::from_le_bytes(bytes[..].try_into().unwrap()))
+}
+
+fn read_string<M: Memory>(memory: &mut M, mut address: u64) -> Vec<u8> {
+    let mut string = vec![];
+    loop {
+        let byte = memory.load_bytes(address, 1).unwrap()[0];
+        string.push(byte);
+        if byte == 0 {
+            return string;
+        }
+        address += 1;
+    }
+}
+
+// Bytes the loader writes above sp: argc, the argv and envp pointers, the
+// auxiliary vector, and the data they point to. Alignment padding is left
+// out, chaos mode fills it with random bytes on the asm machine.
+fn loaded_stack<M: Memory>(memory: &mut M, sp: u64, version: u32, env: bool) -> Vec<u8> {
+    let argc = read_word(memory, sp);
+    let mut words = vec![argc];
+    let mut data = vec![];
+    let mut address = sp + 4;
+    for _ in 0..argc {
+        let pointer = read_word(memory, address);
+        words.push(pointer);
+        data.extend(read_string(memory, pointer));
+        address += 4;
+    }
+    if version >= VERSION1 {
+        words.push(read_word(memory, address));
+        address += 4;
+    }
+    if env {
+        loop {
+            let pointer = read_word(memory, address);
+            words.push(pointer);
+            address += 4;
+            if pointer == 0 {
+                break;
+            }
+            data.extend(read_string(memory, pointer));
+        }
+        loop {
+            let key = read_word(memory, address);
+            let value = read_word(memory, address + 4);
+            words.extend([key, value]);
+            address += 8;
+            if key == AT_RANDOM {
+                data.extend(memory.load_bytes(value, 16).unwrap().iter());
+            }
+            if key == AT_NULL {
+                break;
+            }
+        }
+    }
+    words
+        .iter()
+        .flat_map(|word| (*word as u32).to_le_bytes())
+        .chain(data)
+        .collect()
+}
+
+fn run_interpreter(program: &Bytes, version: u32, env: Option<&StackEnv>) -> Outcome {
+    let records = Arc::new(Mutex::new(vec![]));
+    let core =
+        DefaultCoreMachine::<u32, WXorXMemory<SparseMemory<u32>>>::new(ISA, version, u64::MAX);
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::new(core)
+            .instruction_cycle_func(Box::new(|_| 1))
+            .syscall(Box::new(RecordSyscall {
+                records: Arc::clone(&records),
+            }))
+            .build(),
+    );
+    let args = vec![Bytes::from("main"), Bytes::from("rv32")];
+    match env {
+        Some(env) => machine
+            .machine
+            .load_program_with_env(program, &args, env)
+            .unwrap(),
+        None => machine.load_program(program, &args).unwrap(),
+    };
+    let sp = machine.registers()[SP].to_u64();
+    let stack = loaded_stack(machine.memory_mut(), sp, version, env.is_some());
+    let result = machine.run();
+    let registers = machine.registers().iter().map(|r| r.to_u64()).collect();
+    let records = records.lock().unwrap().clone();
+    Outcome {
+        result,
+        cycles: machine.machine.cycles(),
+        pc: machine.pc().to_u64(),
+        registers,
+        records,
+        stack,
+    }
+}
+
+fn build_asm(version: u32, records: &Arc<Mutex<Vec<Vec<u64>>>>) -> AsmMachine {
+    let core = AsmCoreMachine::new_rv32(ISA, version, u64::MAX);
+    AsmMachine::new(
+        DefaultMachineBuilder::new(core)
+            .instruction_cycle_func(Box::new(|_| 1))
+            .syscall(Box::new(RecordSyscall {
+                records: Arc::clone(records),
+            }))
+            .build(),
+    )
+}
+
+fn run_asm(program: &Bytes, version: u32, env: Option<&StackEnv>, decoder: usize) -> Outcome {
+    let records = Arc::new(Mutex::new(vec![]));
+    let mut machine = build_asm(version, &records);
+    let args = vec![Bytes::from("main"), Bytes::from("rv32")];
+    match env {
+        Some(env) => machine.load_program_with_env(program, &args, env).unwrap(),
+        None => machine.load_program(program, &args).unwrap(),
+    };
+    let sp = machine.machine.registers()[SP];
+    let stack = loaded_stack(machine.machine.memory_mut(), sp, version, env.is_some());
+    let inst_decoder = build_decoder::<u32>(ISA, version);
+    let result = match decoder {
+        0 => machine.run(),
+        1 => machine.run_with_decoder(&mut MemoizedFixedTraceDecoder::new(inst_decoder)),
+        2 => machine.run_with_decoder(&mut MemoizedDynamicTraceDecoder::new(inst_decoder)),
+        _ => machine.run_with_decoder(&mut ChainedTraceDecoder::new(inst_decoder)),
+    };
+    let registers = machine.machine.registers().to_vec();
+    let records = records.lock().unwrap().clone();
+    Outcome {
+        result,
+        cycles: machine.machine.cycles(),
+        pc: *machine.machine.pc(),
+        registers,
+        records,
+        stack,
+    }
+}
+
+// Runs the program on both machines, the asm machine must keep registers
+// sign extended from the values the u32 interpreter sees.
+fn check(program: &Bytes, version: u32, env: Option<&StackEnv>) -> Outcome {
+    let expected = run_interpreter(program, version, env);
+    for decoder in 0..4 {
+        let actual = run_asm(program, version, env, decoder);
+        assert_eq!(actual.result, expected.result, "decoder {}", decoder);
+        // Asm traces charge cycles and update pc per trace, they only
+        // match the interpreter on success.
+        if expected.result.is_ok() {
+            assert_eq!(actual.cycles, expected.cycles, "decoder {}", decoder);
+            assert_eq!(actual.pc, expected.pc, "decoder {}", decoder);
+        }
+        assert_eq!(actual.stack, expected.stack, "decoder {}", decoder);
+        let sign_extended =
+            |registers: &[u64]| -> Vec<u64> { registers.iter().map(|r| sign_extend(*r)).collect() };
+        assert_eq!(
+            actual.registers,
+            sign_extended(&expected.registers),
+            "decoder {}",
+            decoder
+        );
+        assert_eq!(actual.records.len(), expected.records.len());
+        for (i, (a, e)) in actual.records.iter().zip(&expected.records).enumerate() {
+            assert_eq!(a, &sign_extended(e), "decoder {} record {}", decoder, i);
+        }
+    }
+    expected
+}
+
+const VALUES: &[i64] = &[
+    0,
+    1,
+    -1,
+    2,
+    31,
+    32,
+    -33,
+    0x7fff,
+    0xffff,
+    0x7fffffff,
+    -0x80000000,
+    -0x7fffffff,
+    0x12345678,
+    -0x21524111,
+];
+
+const EXIT: &str = "
+    li a0, 0
+    li a7, 93
+    ecall
+";
+
+fn record() -> &'static str {
+    "
+    li a7, 2000
+    ecall
+"
+}
+
+fn check_rtype(ops: &[&str]) {
+    let mut source = String::new();
+    for op in ops {
+        for x in VALUES {
+            for y in VALUES {
+                source += &format!("li a1, {}\nli a2, {}\n{} a0, a1, a2\n", x, y, op);
+                source += record();
+            }
+        }
+    }
+    source += EXIT;
+    let program = assemble_elf::<u32>(&source).unwrap();
+    let outcome = check(&program, VERSION2, None);
+    assert_eq!(
+        outcome.records.len(),
+        ops.len() * VALUES.len() * VALUES.len()
+    );
+}
+
+#[test]
+fn test_asm_rv32_base_rtype() {
+    check_rtype(&[
+        "add", "sub", "sll", "slt", "sltu", "xor", "srl", "sra", "or", "and",
+    ]);
+}
+
+#[test]
+fn test_asm_rv32_m_extension() {
+    check_rtype(&[
+        "mul", "mulh", "mulhsu", "mulhu", "div", "divu", "rem", "remu",
+    ]);
+}
+
+#[test]
+fn test_asm_rv32_b_extension_rtype() {
+    check_rtype(&[
+        "add.uw", "andn", "orn", "xnor", "rol", "ror", "bclr", "bext", "binv", "bset", "sh1add",
+        "sh2add", "sh3add", "clmul", "clmulh", "clmulr", "min", "minu", "max", "maxu",
+    ]);
+    check_rtype(&["sh1add.uw", "sh2add.uw", "sh3add.uw"]);
+}
+
+#[test]
+fn test_asm_rv32_unary() {
+    let mut source = String::new();
+    for op in [
+        "clz", "ctz", "cpop", "sext.b", "sext.h", "orc.b", "rev8", "neg", "not", "seqz", "snez",
+    ] {
+        for x in VALUES {
+            source += &format!("li a1, {}\n{} a0, a1\n", x, op);
+            source += record();
+        }
+    }
+    source += EXIT;
+    let program = assemble_elf::<u32>(&source).unwrap();
+    check(&program, VERSION2, None);
+}
+
+#[test]
+fn test_asm_rv32_itype() {
+    let mut source = String::new();
+    for op in ["addi", "slti", "sltiu", "xori", "ori", "andi"] {
+        for x in VALUES {
+            for imm in [0, 1, -1, 31, 2047, -2048] {
+                source += &format!("li a1, {}\n{} a0, a1, {}\n", x, op, imm);
+                source += record();
+            }
+        }
+    }
+    for op in [
+        "slli", "srli", "srai", "bclri", "bexti", "binvi", "bseti", "rori", "slli.uw",
+    ] {
+        for x in VALUES {
+            for shamt in [0, 1, 15, 16, 31] {
+                source += &format!("li a1, {}\n{} a0, a1, {}\n", x, op, shamt);
+                source += record();
+            }
+        }
+    }
+    for imm in [0, 1, 0x7ffff, 0x80000, 0xfffff] {
+        source += &format!("lui a0, {}\n", imm);
+        source += record();
+        source += &format!("auipc a0, {}\n", imm);
+        source += record();
+    }
+    source += EXIT;
+    let program = assemble_elf::<u32>(&source).unwrap();
+    check(&program, VERSION2, None);
+}
+
+#[test]
+fn test_asm_rv32_branches_and_jumps() {
+    let mut source = String::new();
+    let mut label = 0;
+    for op in ["beq", "bne", "blt", "bge", "bltu", "bgeu"] {
+        for x in VALUES {
+            for y in [0, -1, 0x7fffffff, -0x80000000] {
+                source += &format!(
+                    "li a1, {x}\nli a2, {y}\nli a0, 0\n{op} a1, a2, taken{label}\nli a0, 1\ntaken{label}:\n",
+                );
+                source += record();
+                label += 1;
+            }
+        }
+    }
+    source += "
+    jal ra, function
+    la t0, function
+    jalr ra, 1(t0)
+    la t0, function
+    addi t0, t0, 3
+    jalr ra, -2(t0)
+    j done
+function:
+    mv a0, ra
+    li a7, 2000
+    ecall
+    ret
+done:
+";
+    source += EXIT;
+    let program = assemble_elf::<u32>(&source).unwrap();
+    check(&program, VERSION2, None);
+}
+
+#[test]
+fn test_asm_rv32_memory() {
+    let mut source = String::from(
+        "
+    la s0, buffer
+",
+    );
+    for (i, x) in VALUES.iter().enumerate() {
+        source += &format!("li a1, {}\nsw a1, {}(s0)\n", x, i * 4);
+    }
+    for i in 0..VALUES.len() * 4 {
+        for op in ["lb", "lbu"] {
+            source += &format!("{} a0, {}(s0)\n", op, i);
+            source += record();
+        }
+    }
+    for i in 0..VALUES.len() * 2 {
+        for op in ["lh", "lhu"] {
+            source += &format!("{} a0, {}(s0)\n", op, i * 2);
+            source += record();
+        }
+    }
+    for i in 0..VALUES.len() {
+        source += &format!("lw a0, {}(s0)\nsh a0, 1(s0)\nsb a0, 7(s0)\n", i * 4);
+        source += record();
+    }
+    // Addresses wrap around at 32 bits.
+    source += "
+    li t0, -4
+    add t0, t0, s0
+    lw a0, 4(t0)
+    li t0, 0x7fffffff
+    add t0, t0, s0
+    addi t0, t0, 1
+    li t1, 0x80000000
+    sub t0, t0, t1
+    sw a0, 64(t0)
+    lw a1, 64(s0)
+";
+    source += record();
+    for op in [
+        "amoswap.w",
+        "amoadd.w",
+        "amoxor.w",
+        "amoand.w",
+        "amoor.w",
+        "amomin.w",
+        "amomax.w",
+        "amominu.w",
+        "amomaxu.w",
+    ] {
+        for x in [1, -1, 0x7fffffff, -0x80000000] {
+            source += &format!("li a1, {}\n{} a0, a1, (s0)\nlw a2, 0(s0)\n", x, op);
+            source += record();
+        }
+    }
+    source += "
+    lr.w a0, (s0)
+    addi a0, a0, 1
+    sc.w a1, a0, (s0)
+    lw a2, 0(s0)
+    sc.w a3, a0, (s0)
+";
+    source += record();
+    source += EXIT;
+    source += "
+.data
+buffer:
+    .zero 256
+";
+    let program = assemble_elf::<u32>(&source).unwrap();
+    check(&program, VERSION2, None);
+}
+
+#[test]
+fn test_asm_rv32_loop_with_compressed_instructions() {
+    let source = "
+.option rvc
+    la s0, buffer
+    li s1, 0
+    li s2, 1000
+    li a0, 0x9e3779b9
+loop:
+    slli a1, a0, 7
+    srli a2, a0, 25
+    or a0, a1, a2
+    add a0, a0, s1
+    mulhu a3, a0, s2
+    xor a0, a0, a3
+    andi a4, s1, 63
+    slli a4, a4, 2
+    add a4, a4, s0
+    lw a5, 0(a4)
+    add a5, a5, a0
+    sw a5, 0(a4)
+    addi s1, s1, 1
+    bne s1, s2, loop
+    li a7, 2000
+    ecall
+    lw a0, 0(s0)
+    andi a0, a0, 127
+    li a7, 93
+    ecall
+.data
+buffer:
+    .zero 256
+";
+    let program = assemble_elf::<u32>(source).unwrap();
+    for version in [VERSION0, VERSION1, VERSION2] {
+        let outcome = check(&program, version, None);
+        assert_eq!(outcome.records.len(), 1);
+    }
+}
+
+#[test]
+fn test_asm_rv32_out_of_bound() {
+    let source = format!("li t0, 0x7ffff000\nlw a0, 0(t0)\n{}", EXIT);
+    let program = assemble_elf::<u32>(&source).unwrap();
+    let outcome = check(&program, VERSION2, None);
+    assert!(matches!(outcome.result, Err(Error::MemOutOfBound(..))));
+
+    let source = format!("li t0, -4\njr t0\n{}", EXIT);
+    let program = assemble_elf::<u32>(&source).unwrap();
+    let outcome = check(&program, VERSION2, None);
+    assert!(matches!(
+        outcome.result,
+        Err(Error::MemOutOfBound(0xfffffffc, _))
+    ));
+}
+
+#[test]
+fn test_asm_rv32_programs() {
+    // These programs read memory nothing writes, chaos mode fills it with
+    // random bytes on the asm machine only.
+    if cfg!(feature = "enable-chaos-mode-by-default") {
+        return;
+    }
+    for name in [
+        "op_rvc_slli_crash_32",
+        "op_rvc_srai_crash_32",
+        "op_rvc_srli_crash_32",
+    ] {
+        let program: Bytes = std::fs::read(format!("tests/programs/{}", name))
+            .unwrap()
+            .into();
+        check(&program, VERSION1, None);
+    }
+}
+
+#[test]
+fn test_asm_rv32_stack_with_env() {
+    let program = assemble_elf::<u32>(&format!("mv a0, sp\n{}{}", record(), EXIT)).unwrap();
+    let env = StackEnv::new(&[Bytes::from("A=1"), Bytes::from("B=2")]);
+    let outcome = check(&program, VERSION3, Some(&env));
+    assert_eq!(outcome.records.len(), 1);
+}
+
+#[test]
+fn test_asm_rv32_rejects_mismatched_decoders_and_mop() {
+    let program = assemble_elf::<u32>(EXIT).unwrap();
+    let records = Arc::new(Mutex::new(vec![]));
+    let mut machine = build_asm(VERSION2, &records);
+    machine.load_program(&program, &[]).unwrap();
+    let mut decoder = MemoizedFixedTraceDecoder::new(build_decoder::<u64>(ISA, VERSION2));
+    assert!(matches!(
+        machine.run_with_decoder(&mut decoder),
+        Err(Error::InvalidIsa(_))
+    ));
+    assert_eq!(machine.run(), Ok(0));
+
+    let core = AsmCoreMachine::new_rv32(ISA | ISA_MOP, VERSION2, u64::MAX);
+    let mut machine = AsmMachine::new(DefaultMachineBuilder::new(core).build());
+    machine.load_program(&program, &[]).unwrap();
+    assert!(matches!(machine.run(), Err(Error::InvalidIsa(_))));
+
+    // RV64 programs are rejected when loading.
+    let program = assemble_elf::<u64>(EXIT).unwrap();
+    let mut machine = build_asm(VERSION2, &records);
+    assert_eq!(machine.load_program(&program, &[]), Err(Error::ElfBits));
+}
@@ -0,0 +1,278 @@
+use ckb_vm::assembler::{assemble, assemble_elf, Section, TEXT_ADDRESS};
+use ckb_vm::disasm::disassemble;
+use ckb_vm::elf::{parse_elf, ProgramInfo, STB_GLOBAL, STT_FUNC, STT_OBJECT};
+use ckb_vm::instructions::{insts, Itype};
+use ckb_vm::machine::VERSION2;
+use ckb_vm::registers::{A0, A1, T0};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, Error, Memory,
+    Register, SparseMemory, WXorXMemory, ISA_A, ISA_B, ISA_IMC, ISA_MOP,
+};
+use proptest::prelude::*;
+
+type Machine<R> = DefaultMachine<DefaultCoreMachine<R, WXorXMemory<SparseMemory<R>>>>;
+
+fn run<R: Register>(source: &str) -> (i8, Machine<R>) {
+    let program = assemble_elf::<R>(source).unwrap();
+    let core_machine = DefaultCoreMachine::<R, WXorXMemory<SparseMemory<R>>>::new(
+        ISA_IMC | ISA_A | ISA_B | ISA_MOP,
+        VERSION2,
+        u64::MAX,
+    );
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    machine.load_program(&program, &["main".into()]).unwrap();
+    let exit = machine.run().unwrap();
+    (exit, machine)
+}
+
+const SUM: &str = r#"
+    .equ COUNT, 5
+    .data
+values:
+    .dword 1, 2, 3, 4, 0x10
+    .bss
+result:
+    .zero 8
+
+    .text
+    .globl _start
+_start:
+    la a0, values
+    li a1, COUNT
+    call sum
+    la t0, result
+    sd a0, 0(t0)
+    lui t2, %hi(result)
+    ld a1, %lo(result)(t2)
+    li a7, 93
+    ecall
+
+# a0: array of dwords, a1: length
+sum:
+    li t0, 0
+loop:
+    beqz a1, done
+    ld t1, 0(a0)
+    add t0, t0, t1
+    addi a0, a0, 8
+    addi a1, a1, -1
+    j loop
+done:
+    mv a0, t0
+    ret
+"#;
+
+#[test]
+pub fn test_assembler_run() {
+    let (exit, mut machine) = run::<u64>(SUM);
+    assert_eq!(exit, 26);
+    assert_eq!(machine.registers()[A1], 26);
+    let program = assemble::<u64>(SUM).unwrap();
+    let result = program.symbol("result").unwrap();
+    assert_eq!(machine.memory_mut().load64(&result).unwrap(), 26);
+
+    assert_eq!(program.entry, TEXT_ADDRESS);
+    assert_eq!(program.data.len(), 40);
+    assert_eq!(program.data_address % 4096, 0);
+    assert_eq!(program.bss_size, 8);
+    // la and call are 2 instructions, everything else 1.
+    assert_eq!(program.text.len(), 4 * 21);
+    // auipc a0, 0x1, values is on the page after the code.
+    assert_eq!(&program.text[..4], &0x00001517u32.to_le_bytes());
+}
+
+#[test]
+pub fn test_assembler_rvc() {
+    let source = format!("    .option rvc\n{}", SUM);
+    let (exit, _) = run::<u64>(&source);
+    assert_eq!(exit, 26);
+    let program = assemble::<u64>(&source).unwrap();
+    assert!(program.compressed);
+    assert!(program.text.len() < 4 * 21);
+
+    let lines = disassemble::<u64>(&program.to_elf(), ISA_IMC, VERSION2).unwrap();
+    let sum = lines
+        .iter()
+        .find(|l| l.symbol.as_deref() == Some("sum"))
+        .unwrap();
+    assert!(sum.is_compressed());
+    let li = Itype(sum.instruction.unwrap());
+    assert_eq!(
+        (li.op(), li.rd(), li.rs1(), li.immediate_s()),
+        (insts::OP_ADDI, T0, 0, 0)
+    );
+    // Operands referring to labels keep the full encoding.
+    let branch = lines.iter().find(|l| l.pc == sum.pc + 2).unwrap();
+    assert!(!branch.is_compressed());
+}
+
+#[test]
+pub fn test_assembler_rv32() {
+    let source = r#"
+    .section .rodata
+message:
+    .asciz "hello, world\n"
+    .text
+_start:
+    la a1, message
+    li a0, 0
+loop:
+    add t0, a1, a0
+    lbu t0, 0(t0)
+    beqz t0, exit
+    addi a0, a0, 1
+    j loop
+exit:
+    li a7, 93
+    ecall
+"#;
+    let (exit, _) = run::<u32>(source);
+    assert_eq!(exit, 13);
+    let (exit, _) = run::<u64>(source);
+    assert_eq!(exit, 13);
+    let program = assemble::<u32>(source).unwrap();
+    assert_eq!(program.rodata, b"hello, world\n\0");
+    assert!(program.data.is_empty());
+
+    assert!(matches!(
+        assemble::<u32>("ld a0, 0(sp)"),
+        Err(Error::Assembler { line: 1, .. })
+    ));
+    assert!(assemble::<u64>("ld a0, 0(sp)").is_ok());
+}
+
+#[test]
+pub fn test_assembler_elf() {
+    let program = assemble_elf::<u64>(SUM).unwrap();
+    let metadata = parse_elf::<u64>(&program, VERSION2).unwrap();
+    assert_eq!(metadata.entry, TEXT_ADDRESS);
+    assert_eq!(metadata.actions.len(), 2);
+
+    let info = ProgramInfo::parse::<u64>(&program, VERSION2).unwrap();
+    let start = info.symbols.iter().find(|s| s.name == "_start").unwrap();
+    assert_eq!(start.bind, STB_GLOBAL);
+    assert_eq!(start.kind, STT_FUNC);
+    let values = info.symbols.iter().find(|s| s.name == "values").unwrap();
+    assert_eq!(values.kind, STT_OBJECT);
+    let sections: Vec<&str> = info.sections.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(
+        sections,
+        [
+            "",
+            ".text",
+            ".data",
+            ".bss",
+            ".symtab",
+            ".strtab",
+            ".shstrtab"
+        ]
+    );
+
+    let labels = assemble::<u64>(SUM).unwrap().labels;
+    let result = labels.iter().find(|l| l.name == "result").unwrap();
+    assert_eq!(result.section, Section::Bss);
+    assert!(!result.global);
+}
+
+#[test]
+pub fn test_assembler_expressions() {
+    let source = r#"
+    .equ BASE, 0x100
+    .equ SIZE, (BASE << 2) | 3
+    .data
+table:
+    .word 'A', -1, end - table, SIZE
+    .half 0xffff
+    .byte '\n'
+    .p2align 3
+end:
+    .text
+_start:
+    li a0, SIZE - 1027 + (7 * 3 / 2) % 4
+    li a7, 93
+    ecall
+"#;
+    let (exit, _) = run::<u64>(source);
+    assert_eq!(exit, 2);
+    let program = assemble::<u64>(source).unwrap();
+    assert_eq!(
+        program.data,
+        [
+            0x41, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 24, 0, 0, 0, 0x03, 0x04, 0, 0, 0xff, 0xff, 0x0a,
+            0, 0, 0, 0, 0
+        ]
+    );
+}
+
+#[test]
+pub fn test_assembler_errors() {
+    let error = |source: &str| match assemble::<u64>(source) {
+        Err(Error::Assembler { line, message }) => (line, message),
+        other => panic!("unexpected {:?}", other),
+    };
+    assert_eq!(
+        error("nop\n\n  frob a0, a1"),
+        (3, "unknown instruction frob".to_string())
+    );
+    assert_eq!(
+        error("addi a0, a0, 2048"),
+        (1, "immediate 2048 out of range".to_string())
+    );
+    assert_eq!(
+        error("nop\nj nowhere"),
+        (2, "undefined symbol nowhere".to_string())
+    );
+    assert_eq!(
+        error("a:\na:"),
+        (2, "symbol a is already defined".to_string())
+    );
+    assert_eq!(
+        error("add a0, a1, x32"),
+        (1, "invalid register x32".to_string())
+    );
+    assert_eq!(error(".data\nnop").1, "instruction nop outside of .text");
+    assert_eq!(error(".foo").1, "unknown directive .foo");
+    assert!(error("li a0, label\nlabel:").1.contains("not a constant"));
+}
+
+fn run_li<R: Register>(value: i64) -> u64 {
+    let source = format!("li a0, {}\nli a7, 93\necall", value);
+    let (_, machine) = run::<R>(&source);
+    machine.registers()[A0].to_u64()
+}
+
+#[test]
+pub fn test_assembler_li() {
+    for value in [
+        0,
+        1,
+        -1,
+        2047,
+        -2048,
+        2048,
+        0x7ffff800,
+        0x7fffffff,
+        0x80000000,
+        -0x80000000,
+        0xffffffff,
+        0x100000000,
+        0x123456789abcdef0,
+        i64::MAX,
+        i64::MIN,
+    ] {
+        assert_eq!(run_li::<u64>(value), value as u64);
+        assert_eq!(run_li::<u32>(value as i32 as i64), value as u32 as u64);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(500))]
+    #[test]
+    #[cfg_attr(all(miri, feature = "miri-ci"), ignore)]
+    fn test_assembler_li_proptest(value: i64) {
+        prop_assert_eq!(run_li::<u64>(value), value as u64);
+        // li never takes more than 8 instructions.
+        let program = assemble::<u64>(&format!("li a0, {}", value)).unwrap();
+        prop_assert!(program.text.len() <= 32);
+    }
+}
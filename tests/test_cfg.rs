@@ -0,0 +1,192 @@
+use ckb_vm::assembler::assemble;
+use ckb_vm::cfg::{ControlFlowGraph, Edge, EdgeKind, Terminator};
+use ckb_vm::disasm::disassemble;
+use ckb_vm::machine::{VERSION0, VERSION2};
+use ckb_vm::{Bytes, ISA_A, ISA_B, ISA_IMC, ISA_MOP};
+use std::fs;
+
+const PROGRAM: &str = r#"
+    .text
+    .globl _start
+_start:
+    li a0, 3
+    call square
+    jal double
+    beqz a0, skip
+    addi a0, a0, 1
+skip:
+    la t1, table
+    ld t1, 0(t1)
+    jalr t1
+    tail finish
+square:
+    mul a0, a0, a0
+    ret
+double:
+    add a0, a0, a0
+    jr t2
+finish:
+    li a7, 93
+    ecall
+
+    .data
+table:
+    .dword 0
+"#;
+
+fn edge(target: u64, kind: EdgeKind) -> Edge {
+    Edge { target, kind }
+}
+
+#[test]
+pub fn test_cfg_edges() {
+    let program = assemble::<u64>(PROGRAM).unwrap();
+    let symbol = |name| program.symbol(name).unwrap();
+    let cfg = ControlFlowGraph::build::<u64>(&program.to_elf(), ISA_IMC, VERSION2).unwrap();
+    assert_eq!(cfg.entry, symbol("_start"));
+
+    // li + auipc, AUIPC ends a block just like it ends a trace.
+    let first = cfg.block(symbol("_start")).unwrap();
+    assert_eq!(first.instructions.len(), 2);
+    assert_eq!(first.terminator, Terminator::Fallthrough);
+    // The jalr of call is resolved through the auipc before it.
+    let call = cfg.block(first.end).unwrap();
+    assert_eq!(
+        call.terminator,
+        Terminator::Call {
+            target: Some(symbol("square"))
+        }
+    );
+    assert_eq!(
+        call.successors,
+        [
+            edge(symbol("square"), EdgeKind::Call),
+            edge(call.end, EdgeKind::Fallthrough)
+        ]
+    );
+    let jal = cfg.block(call.end).unwrap();
+    assert_eq!(
+        jal.terminator,
+        Terminator::Call {
+            target: Some(symbol("double"))
+        }
+    );
+    let branch = cfg.block(jal.end).unwrap();
+    assert_eq!(
+        branch.successors,
+        [
+            edge(symbol("skip"), EdgeKind::Branch),
+            edge(branch.end, EdgeKind::Fallthrough)
+        ]
+    );
+    // The jalr after ld is a call to an unknown function.
+    let indirect = cfg.block_containing(symbol("skip") + 12).unwrap();
+    assert_eq!(indirect.terminator, Terminator::Call { target: None });
+    assert!(indirect.is_unresolved());
+    assert_eq!(indirect.instructions.len(), 3);
+    let tail = cfg.block(indirect.end + 4).unwrap();
+    assert_eq!(
+        tail.terminator,
+        Terminator::Jump {
+            target: symbol("finish")
+        }
+    );
+
+    assert_eq!(
+        cfg.block(symbol("square")).unwrap().terminator,
+        Terminator::Return
+    );
+    let double = cfg.block(symbol("double")).unwrap();
+    assert_eq!(double.terminator, Terminator::IndirectJump);
+    assert!(double.successors.is_empty());
+    assert_eq!(
+        cfg.unresolved_blocks()
+            .iter()
+            .map(|b| b.start)
+            .collect::<Vec<_>>(),
+        [indirect.start, double.start]
+    );
+    let finish = cfg.block(symbol("finish")).unwrap();
+    assert_eq!(finish.end, program.entry + program.text.len() as u64);
+    assert_eq!(finish.successors, [edge(finish.end, EdgeKind::Fallthrough)]);
+
+    assert_eq!(cfg.predecessors(symbol("skip")), [branch.start, branch.end]);
+    assert_eq!(cfg.predecessors(symbol("finish")), [tail.start]);
+}
+
+#[test]
+pub fn test_cfg_functions() {
+    let program = assemble::<u64>(PROGRAM).unwrap();
+    let symbol = |name| program.symbol(name).unwrap();
+    let cfg = ControlFlowGraph::build::<u64>(&program.to_elf(), ISA_IMC, VERSION2).unwrap();
+    assert_eq!(cfg.functions[&symbol("_start")], "_start");
+    assert_eq!(cfg.functions[&symbol("square")], "square");
+
+    // Calls are not followed, the tail call is.
+    let blocks = cfg.function_blocks(symbol("_start"));
+    assert_eq!(blocks.len(), 10);
+    assert!(blocks.contains(&symbol("finish")));
+    assert!(!blocks.contains(&symbol("square")));
+    assert_eq!(cfg.function_blocks(symbol("square")), [symbol("square")]);
+    assert!(cfg.function_blocks(0).is_empty());
+}
+
+#[test]
+pub fn test_cfg_covers_program() {
+    let buffer: Bytes = fs::read("tests/programs/clang_sample").unwrap().into();
+    let isa = ISA_IMC | ISA_A | ISA_B | ISA_MOP;
+    for version in [VERSION0, VERSION2] {
+        let lines = disassemble::<u64>(&buffer, isa, version).unwrap();
+        let cfg = ControlFlowGraph::build::<u64>(&buffer, isa, version).unwrap();
+        // Every instruction belongs to exactly one block, in order.
+        let flattened: Vec<_> = cfg
+            .blocks
+            .values()
+            .flat_map(|b| b.instructions.iter().cloned())
+            .collect();
+        assert_eq!(flattened, lines);
+        for block in cfg.blocks.values() {
+            assert_eq!(cfg.block_containing(block.end - 1), Some(block));
+            for edge in &block.successors {
+                if edge.kind != EdgeKind::Fallthrough {
+                    assert!(cfg.block(edge.target).is_some());
+                }
+            }
+        }
+        let start = cfg
+            .functions
+            .iter()
+            .find(|(_, n)| *n == "_start_c")
+            .unwrap();
+        assert!(cfg.function_blocks(*start.0).len() > 1);
+        assert!(cfg
+            .blocks
+            .values()
+            .any(|b| b.terminator == Terminator::Return));
+    }
+}
+
+#[test]
+pub fn test_cfg_dot() {
+    let program = assemble::<u64>(PROGRAM).unwrap();
+    let symbol = |name| program.symbol(name).unwrap();
+    let cfg = ControlFlowGraph::build::<u64>(&program.to_elf(), ISA_IMC, VERSION2).unwrap();
+    let dot = cfg.to_dot();
+    assert!(dot.starts_with("digraph cfg {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert_eq!(
+        dot.lines().filter(|l| l.contains("[label=\"<")).count(),
+        cfg.functions.len()
+    );
+    assert!(dot.contains(&format!(
+        "\"{:x}\" [label=\"<double>:\\l{:x}:",
+        symbol("double"),
+        symbol("double")
+    )));
+    assert!(dot.contains(&format!(
+        "\"{:x}\" -> \"{:x}\" [label=\"call\", style=dashed];",
+        symbol("_start") + 8,
+        symbol("square")
+    )));
+    assert_eq!(dot.matches("color=red").count(), 2);
+}
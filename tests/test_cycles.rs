@@ -0,0 +1,163 @@
+use ckb_vm::assembler::assemble;
+use ckb_vm::cfg::ControlFlowGraph;
+use ckb_vm::cost_model::{constant_cycles, estimate_cycles};
+use ckb_vm::cycles::{CycleEstimator, Unbounded};
+use ckb_vm::generator::{generate, GeneratorConfig};
+use ckb_vm::machine::VERSION2;
+use ckb_vm::{
+    Bytes, DefaultCoreMachine, DefaultMachineBuilder, Instruction, SparseMemory, SupportMachine,
+    WXorXMemory, ISA_A, ISA_B, ISA_IMC, ISA_MOP,
+};
+
+fn run(program: &Bytes, isa: u8, cost: fn(Instruction) -> u64) -> u64 {
+    let core_machine =
+        DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(isa, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(cost))
+        .build();
+    machine.load_program(program, &["main".into()]).unwrap();
+    machine.run().unwrap();
+    machine.cycles()
+}
+
+const PROGRAM: &str = r#"
+helper:
+    beqz t0, done
+    mul t0, t0, t0
+done:
+    ret
+
+    .globl _start
+_start:
+    li a0, 10
+    li t0, 0
+loop:
+    add t0, t0, a0
+    addi a0, a0, -1
+    bnez a0, loop
+    call helper
+    li a7, 93
+    ecall
+"#;
+
+#[test]
+pub fn test_cycles_loop_bound() {
+    let program = assemble::<u64>(PROGRAM).unwrap();
+    let symbol = |name| program.symbol(name).unwrap();
+    let elf = program.to_elf();
+    let cfg = ControlFlowGraph::build::<u64>(&elf, ISA_IMC, VERSION2).unwrap();
+
+    let estimate = CycleEstimator::new(&cfg, &constant_cycles).estimate();
+    assert_eq!(estimate.blocks[&symbol("loop")], 3);
+    let helper = &estimate.functions[&symbol("helper")];
+    assert_eq!(helper.cycles, 3);
+    assert_eq!(helper.worst_case, Some(3));
+    assert_eq!(
+        helper.worst_path,
+        [symbol("helper"), symbol("helper") + 4, symbol("done")]
+    );
+    let start = &estimate.functions[&symbol("_start")];
+    assert_eq!(start.worst_case, None);
+    assert_eq!(start.unbounded, [Unbounded::Loop(symbol("loop"))]);
+    assert!(start.worst_path.is_empty());
+    assert_eq!(estimate.worst_case(), None);
+
+    let estimate = CycleEstimator::new(&cfg, &constant_cycles)
+        .loop_bound(symbol("loop"), 10)
+        .estimate();
+    // The bound is exact for this program.
+    assert_eq!(estimate.worst_case(), Some(39));
+    assert_eq!(run(&elf, ISA_IMC, constant_cycles), 39);
+    let start = &estimate.functions[&symbol("_start")];
+    assert_eq!(start.cycles, 9);
+    assert_eq!(start.worst_path[..2], [symbol("_start"), symbol("loop")]);
+    assert_eq!(estimate.most_expensive()[0].name, "_start");
+
+    // Fused instructions are charged once, the result is still an upper
+    // bound.
+    let isa = ISA_IMC | ISA_MOP;
+    let cfg = ControlFlowGraph::build::<u64>(&elf, isa, VERSION2).unwrap();
+    let estimate = CycleEstimator::new(&cfg, &estimate_cycles)
+        .loop_bound(symbol("loop"), 10)
+        .estimate();
+    let actual = run(&elf, isa, estimate_cycles);
+    assert!(estimate.worst_case().unwrap() >= actual);
+    assert!(estimate.worst_case().unwrap() <= actual + 3);
+}
+
+#[test]
+pub fn test_cycles_unbounded() {
+    let source = r#"
+recursive:
+    beqz a0, out
+    addi a0, a0, -1
+    call recursive
+out:
+    ret
+
+_start:
+    call recursive
+    beqz a0, b
+a:
+    addi a0, a0, 1
+b:
+    addi a0, a0, -1
+    bnez a0, a
+    jalr t1
+    li a7, 93
+    ecall
+"#;
+    let program = assemble::<u64>(source).unwrap();
+    let symbol = |name| program.symbol(name).unwrap();
+    let cfg = ControlFlowGraph::build::<u64>(&program.to_elf(), ISA_IMC, VERSION2).unwrap();
+    let estimate = CycleEstimator::new(&cfg, &constant_cycles)
+        .loop_bound(symbol("a"), 100)
+        .loop_bound(symbol("b"), 100)
+        .estimate();
+    let recursive = symbol("recursive");
+    assert_eq!(
+        estimate.functions[&recursive].unbounded,
+        [Unbounded::Call(recursive)]
+    );
+    let indirect = cfg.unresolved_blocks()[0].start;
+    assert_eq!(
+        estimate.functions[&symbol("_start")].unbounded,
+        [
+            Unbounded::Irreducible(symbol("a")),
+            Unbounded::Indirect(indirect),
+            Unbounded::Call(recursive)
+        ]
+    );
+    // Entered at a, the same loop has a single header.
+    assert_eq!(
+        estimate.functions[&symbol("a")].unbounded,
+        [Unbounded::Indirect(indirect)]
+    );
+}
+
+#[test]
+pub fn test_cycles_generated_programs() {
+    // Generated loops run at most loop_iterations times per entry, so
+    // bounding every block by it covers all loop headers.
+    let config = GeneratorConfig::default();
+    let isa = ISA_IMC | ISA_A | ISA_B | ISA_MOP;
+    for seed in 0..16u64 {
+        let program = generate::<u64>(&config, &seed.to_le_bytes()).unwrap();
+        let elf = program.elf();
+        let cfg = ControlFlowGraph::build::<u64>(&elf, isa, VERSION2).unwrap();
+        let mut estimator = CycleEstimator::new(&cfg, &estimate_cycles);
+        for start in cfg.blocks.keys() {
+            estimator = estimator.loop_bound(*start, config.loop_iterations);
+        }
+        let estimate = estimator.estimate();
+        let bound = estimate.worst_case().unwrap();
+        let actual = run(&elf, isa, estimate_cycles);
+        assert!(
+            bound >= actual,
+            "{} < {}\n{}",
+            bound,
+            actual,
+            program.source
+        );
+    }
+}
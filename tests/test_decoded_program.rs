@@ -0,0 +1,213 @@
+use bytes::Bytes;
+use ckb_vm::cost_model::estimate_cycles;
+use ckb_vm::decoder::DecodedProgram;
+use ckb_vm::elf::{parse_elf, ProgramMetadata};
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::{traces::SimpleFixedTraceDecoder, AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::trace::TraceMachine;
+use ckb_vm::machine::{DefaultCoreMachine, VERSION0, VERSION1, VERSION2};
+use ckb_vm::memory::{sparse::SparseMemory, wxorx::WXorXMemory};
+use ckb_vm::{
+    CoreMachine, DefaultMachineBuilder, Error, SupportMachine, ISA_A, ISA_B, ISA_IMC, ISA_MOP,
+};
+use std::sync::Arc;
+
+const CONFIGS: &[(u8, u32)] = &[
+    (ISA_IMC, VERSION0),
+    (ISA_IMC | ISA_B, VERSION1),
+    (ISA_IMC | ISA_B | ISA_MOP, VERSION1),
+    (ISA_IMC | ISA_A | ISA_B | ISA_MOP, VERSION2),
+];
+
+const PROGRAMS: &[&str] = &[
+    "alloc_many",
+    "amo_compare",
+    "asm_trace_bug",
+    "auipc_no_sign_extend",
+    "clang_sample",
+    "ebreak64",
+    "invalid_read64",
+    "jalr_bug",
+    "jump0_64",
+    "misaligned_jump64",
+    "mop_adc",
+    "mop_far_jump",
+    "mop_random_adc_sbb",
+    "mop_wide_divide",
+    "read_at_boundary64",
+    "rvc_pageend",
+    "simple64",
+    "trace64",
+    "unaligned64",
+    "writable_page",
+    "zero_address",
+];
+
+// Result, cycles, registers and pc after a run
+type Outcome = (Result<i8, Error>, u64, Vec<u64>, u64);
+
+fn run_trace(
+    program: &Bytes,
+    metadata: &ProgramMetadata,
+    isa: u8,
+    version: u32,
+    decoded: Option<&Arc<DecodedProgram>>,
+) -> Option<Outcome> {
+    let core =
+        DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(isa, version, 20_000_000);
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::new(core)
+            .instruction_cycle_func(Box::new(estimate_cycles))
+            .build(),
+    );
+    machine.load_program(program, &["main".into()]).ok()?;
+    let result = match decoded {
+        Some(decoded) => {
+            machine.run_with_decoder(&mut decoded.decoder::<u64>(program, metadata).unwrap())
+        }
+        None => machine.run(),
+    };
+    Some((
+        result,
+        machine.machine.cycles(),
+        machine.registers().to_vec(),
+        *machine.pc(),
+    ))
+}
+
+#[test]
+pub fn test_decoded_program_trace() {
+    for name in PROGRAMS {
+        let program: Bytes = std::fs::read(format!("tests/programs/{}", name))
+            .unwrap()
+            .into();
+        for (isa, version) in CONFIGS {
+            let metadata = match parse_elf::<u64>(&program, *version) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            let expected = match run_trace(&program, &metadata, *isa, *version, None) {
+                Some(expected) => expected,
+                None => continue,
+            };
+            let decoded =
+                Arc::new(DecodedProgram::new::<u64>(&program, &metadata, *isa, *version).unwrap());
+            // The same decoded program serves several machines.
+            for _ in 0..2 {
+                let actual =
+                    run_trace(&program, &metadata, *isa, *version, Some(&decoded)).unwrap();
+                assert_eq!(actual, expected, "{} {} {}", name, isa, version);
+            }
+            assert_eq!(Arc::strong_count(&decoded), 1);
+        }
+    }
+}
+
+#[test]
+pub fn test_decoded_program_lookup() {
+    let program: Bytes = std::fs::read("tests/programs/simple64").unwrap().into();
+    let metadata = parse_elf::<u64>(&program, VERSION1).unwrap();
+    let decoded = DecodedProgram::new::<u64>(&program, &metadata, ISA_IMC, VERSION1).unwrap();
+    assert_eq!(decoded.isa(), ISA_IMC);
+    assert_eq!(decoded.version(), VERSION1);
+    assert!(decoded.instruction(metadata.entry).is_some());
+    assert!(decoded.instruction(metadata.entry + 1).is_none());
+    assert!(decoded.instruction(0).is_none());
+}
+
+#[test]
+pub fn test_decoded_program_mismatch() {
+    let program: Bytes = std::fs::read("tests/programs/simple64").unwrap().into();
+    let metadata = parse_elf::<u64>(&program, VERSION1).unwrap();
+    let decoded =
+        Arc::new(DecodedProgram::new::<u64>(&program, &metadata, ISA_IMC, VERSION1).unwrap());
+    assert!(decoded.decoder::<u64>(&program, &metadata).is_ok());
+
+    let other: Bytes = std::fs::read("tests/programs/trace64").unwrap().into();
+    let other_metadata = parse_elf::<u64>(&other, VERSION1).unwrap();
+    assert!(matches!(
+        decoded.decoder::<u64>(&other, &other_metadata),
+        Err(Error::DecodedProgram(_))
+    ));
+    let mut moved = metadata.clone();
+    moved.entry += 4;
+    assert!(matches!(
+        decoded.decoder::<u64>(&program, &moved),
+        Err(Error::DecodedProgram(_))
+    ));
+    assert!(matches!(
+        decoded.decoder::<u32>(&program, &metadata),
+        Err(Error::DecodedProgram(_))
+    ));
+
+    // The machine must match the isa and version the program was decoded for.
+    for (isa, version) in [(ISA_IMC | ISA_B, VERSION1), (ISA_IMC, VERSION2)] {
+        let core = DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(
+            isa, version, 20_000_000,
+        );
+        let mut machine = TraceMachine::new(
+            DefaultMachineBuilder::new(core)
+                .instruction_cycle_func(Box::new(estimate_cycles))
+                .build(),
+        );
+        machine.load_program(&program, &["simple".into()]).unwrap();
+        let mut decoder = decoded.decoder::<u64>(&program, &metadata).unwrap();
+        assert!(matches!(
+            machine.run_with_decoder(&mut decoder),
+            Err(Error::DecodedProgram(_))
+        ));
+    }
+}
+
+#[cfg(has_asm)]
+#[test]
+pub fn test_decoded_program_asm() {
+    for name in PROGRAMS {
+        let program: Bytes = std::fs::read(format!("tests/programs/{}", name))
+            .unwrap()
+            .into();
+        for (isa, version) in CONFIGS {
+            if *isa & ISA_MOP != 0 && *version == VERSION0 {
+                continue;
+            }
+            let metadata = match parse_elf::<u64>(&program, *version) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            // Programs failing to load are rejected by the machines too.
+            let decoded = match DecodedProgram::new::<u64>(&program, &metadata, *isa, *version) {
+                Ok(decoded) => Arc::new(decoded),
+                Err(_) => continue,
+            };
+            let mut outcomes = vec![];
+            for shared in [false, true] {
+                let core = AsmCoreMachine::new(*isa, *version, 20_000_000);
+                let mut machine = AsmMachine::new(
+                    DefaultMachineBuilder::new(core)
+                        .instruction_cycle_func(Box::new(estimate_cycles))
+                        .build(),
+                );
+                if machine.load_program(&program, &["main".into()]).is_err() {
+                    break;
+                }
+                let result = if shared {
+                    let mut decoder = SimpleFixedTraceDecoder::new(
+                        decoded.decoder::<u64>(&program, &metadata).unwrap(),
+                    );
+                    machine.run_with_decoder(&mut decoder)
+                } else {
+                    machine.run()
+                };
+                outcomes.push((
+                    result,
+                    machine.machine.cycles(),
+                    machine.machine.registers().to_vec(),
+                    *machine.machine.pc(),
+                ));
+            }
+            if outcomes.len() == 2 {
+                assert_eq!(outcomes[0], outcomes[1], "{} {} {}", name, isa, version);
+            }
+        }
+    }
+}
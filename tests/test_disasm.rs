@@ -0,0 +1,62 @@
+use ckb_vm::disasm::disassemble;
+use ckb_vm::instructions::{extract_opcode, insts};
+use ckb_vm::machine::{VERSION1, VERSION2};
+use ckb_vm::{Bytes, ISA_A, ISA_B, ISA_IMC, ISA_MOP};
+use std::fs;
+
+#[test]
+pub fn test_disasm() {
+    let buffer: Bytes = fs::read("tests/programs/clang_sample").unwrap().into();
+    let lines = disassemble::<u64>(&buffer, ISA_IMC | ISA_A | ISA_B, VERSION2).unwrap();
+    assert_eq!(lines.len(), 672);
+    assert!(lines.iter().all(|l| l.instruction.is_some()));
+    assert!(lines.iter().all(|l| l.fused.is_none()));
+    // Instructions are contiguous.
+    for w in lines.windows(2) {
+        assert!(w[0].pc + w[0].length() <= w[1].pc);
+    }
+
+    let main = lines
+        .iter()
+        .position(|l| l.symbol.as_deref() == Some("main"))
+        .unwrap();
+    assert_eq!(lines[main].pc, 0x11190);
+    assert!(lines[main].is_compressed());
+    assert_eq!(
+        lines[main].to_string(),
+        "   11190:  1101      addi sp,-32(sp)"
+    );
+    assert!(!lines[main + 5].is_compressed());
+    assert_eq!(lines[main + 5].bits, 0xfea42623);
+}
+
+#[test]
+pub fn test_disasm_mop() {
+    let buffer: Bytes = fs::read("tests/programs/clang_sample").unwrap().into();
+    let lines = disassemble::<u64>(&buffer, ISA_IMC | ISA_A | ISA_B | ISA_MOP, VERSION2).unwrap();
+    let start = lines
+        .iter()
+        .find(|l| l.symbol.as_deref() == Some("_start"))
+        .unwrap();
+    // auipc gp, 0x2; addi gp, gp, -38
+    let fused = start.fused.unwrap();
+    assert_eq!(extract_opcode(fused), insts::OP_CUSTOM_LOAD_IMM);
+    assert!(start.to_string().ends_with("custom_load_imm gp,78208"));
+    // AUIPC fusion only exists since VERSION1.
+    let lines = disassemble::<u64>(&buffer, ISA_IMC | ISA_A | ISA_B | ISA_MOP, VERSION1).unwrap();
+    let start = lines
+        .iter()
+        .find(|l| l.symbol.as_deref() == Some("_start"))
+        .unwrap();
+    assert!(start.fused.is_none());
+}
+
+#[test]
+pub fn test_disasm_invalid_instructions() {
+    // Without ISA_B, bit manipulation instructions are shown as raw data.
+    let buffer: Bytes = fs::read("tests/programs/clang_sample").unwrap().into();
+    let lines = disassemble::<u64>(&buffer, ISA_IMC | ISA_A, VERSION2).unwrap();
+    let invalid: Vec<_> = lines.iter().filter(|l| l.instruction.is_none()).collect();
+    assert!(!invalid.is_empty());
+    assert!(invalid[0].to_string().contains(".4byte"));
+}
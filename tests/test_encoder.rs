@@ -0,0 +1,146 @@
+use ckb_vm::encoder::{encode, encode_compressed, encode_full};
+use ckb_vm::instructions::{
+    a, b, i, instruction_length, insts, m, rvc, Instruction, InstructionFactory, Itype, R5type,
+    Register, Rtype, Stype, Utype,
+};
+use ckb_vm::machine::{VERSION0, VERSION2};
+use ckb_vm::registers::{A0, A1, A2, RA, SP, T0};
+use ckb_vm::Error;
+use proptest::prelude::*;
+
+fn decode<R: Register>(bits: u32, version: u32) -> Option<Instruction> {
+    let factories: [InstructionFactory; 5] = [
+        rvc::factory::<R>,
+        i::factory::<R>,
+        m::factory::<R>,
+        b::factory::<R>,
+        a::factory::<R>,
+    ];
+    factories.iter().find_map(|f| f(bits, version))
+}
+
+fn check_round_trip<R: Register>(bits: u32, version: u32) -> bool {
+    let inst = match decode::<R>(bits, version) {
+        Some(inst) => inst,
+        None => return false,
+    };
+    let encoded = encode::<R>(inst).unwrap_or_else(|e| panic!("{:x}: {}", bits, e));
+    assert_eq!(
+        decode::<R>(encoded, version),
+        Some(inst),
+        "bits={:x} encoded={:x}",
+        bits,
+        encoded
+    );
+    if instruction_length(inst) == 2 {
+        // Every compressed instruction has a 32-bit form as well.
+        let full = encode_full::<R>(inst).unwrap();
+        assert_eq!(
+            decode::<R>(full, version),
+            Some(inst & !0xf000000 | 0x2000000)
+        );
+    }
+    true
+}
+
+#[test]
+pub fn test_encode_rvc_exhaustive() {
+    // VERSION0 rejects the HINT encodings that later versions decode as
+    // NOP, both are covered.
+    for version in [VERSION0, VERSION2] {
+        for bits in 0..=0xffffu32 {
+            if bits & 0x3 != 0x3 {
+                check_round_trip::<u64>(bits, version);
+                check_round_trip::<u32>(bits, version);
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_encode_all_opcodes() {
+    // Walks through every combination of major opcode, funct3 and funct7 so
+    // each instruction known to the factories is covered.
+    let mut opcodes = std::collections::HashSet::new();
+    for major in 0..32u32 {
+        for funct3 in 0..8u32 {
+            for funct7 in 0..128u32 {
+                for rs2 in 0..32u32 {
+                    let (rd, rs1) = ((rs2 + 1) % 32, rs2 * 7 % 32);
+                    let bits = (funct7 << 25)
+                        | (rs2 << 20)
+                        | (rs1 << 15)
+                        | (funct3 << 12)
+                        | (rd << 7)
+                        | (major << 2)
+                        | 0b11;
+                    for version in [VERSION0, VERSION2] {
+                        if check_round_trip::<u64>(bits, version) {
+                            opcodes.insert(Rtype(decode::<u64>(bits, version).unwrap()).op());
+                        }
+                        check_round_trip::<u32>(bits, version);
+                    }
+                }
+            }
+        }
+    }
+    // ECALL, EBREAK, FENCE and FENCE.I have fixed encodings.
+    for bits in [0x00000073, 0x00100073, 0x0ff0000f, 0x0000100f] {
+        assert!(check_round_trip::<u64>(bits, VERSION2));
+        opcodes.insert(Rtype(decode::<u64>(bits, VERSION2).unwrap()).op());
+    }
+    // All opcodes up to JALR_VERSION1 except UNLOADED and the fused ones.
+    let fused = insts::OP_SD_PAIR - insts::OP_WIDE_MUL + 1;
+    assert_eq!(
+        opcodes.len() as u16,
+        insts::OP_JALR_VERSION1 - insts::OP_ADD + 1 - fused
+    );
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(10000))]
+    #[test]
+    #[cfg_attr(all(miri, feature = "miri-ci"), ignore)]
+    fn test_encode_round_trip_proptest(bits: u32, version in 0u32..3) {
+        check_round_trip::<u64>(bits | 0b11, version);
+        check_round_trip::<u32>(bits | 0b11, version);
+        check_round_trip::<u64>(bits & 0xffff, version);
+        check_round_trip::<u32>(bits & 0xffff, version);
+    }
+}
+
+#[test]
+pub fn test_encode_canonical() {
+    let addi = Itype::new_s(insts::OP_ADDI, SP, SP, -16).0;
+    assert_eq!(encode_full::<u64>(addi), Ok(0xff010113));
+    assert_eq!(encode_compressed::<u64>(addi), Some(0x1141));
+    // Instructions without a recorded length use the 32-bit form.
+    assert_eq!(encode::<u64>(addi), Ok(0xff010113));
+    let sd = Stype::new_s(insts::OP_SD, 8, SP, RA).0;
+    assert_eq!(encode_full::<u64>(sd), Ok(0x00113423));
+    assert_eq!(encode_compressed::<u64>(sd), Some(0xe406));
+    assert_eq!(encode_compressed::<u32>(sd), None);
+    let lui = Utype::new_s(insts::OP_LUI, T0, 0x12345000).0;
+    assert_eq!(encode_full::<u64>(lui), Ok(0x123452b7));
+    assert_eq!(encode_compressed::<u64>(lui), None);
+    let add = Rtype::new(insts::OP_ADD, A0, A0, A1).0;
+    assert_eq!(encode_full::<u64>(add), Ok(0x00b50533));
+    assert_eq!(encode_compressed::<u64>(add), Some(0x952e));
+}
+
+#[test]
+pub fn test_encode_invalid() {
+    // Immediates out of range.
+    let addi = Itype::new_s(insts::OP_ADDI, A0, A0, 4096).0;
+    assert_eq!(encode_full::<u64>(addi), Err(Error::InvalidEncoding(addi)));
+    let beq = Stype::new_s(insts::OP_BEQ, 3, A0, A1).0;
+    assert_eq!(encode_full::<u64>(beq), Err(Error::InvalidEncoding(beq)));
+    let slli = Itype::new_u(insts::OP_SLLI, A0, A0, 40).0;
+    assert!(encode_full::<u64>(slli).is_ok());
+    assert_eq!(encode_full::<u32>(slli), Err(Error::InvalidEncoding(slli)));
+    // Fused instructions have no encoding.
+    let adc = Rtype::new(insts::OP_ADC, A0, A1, A2).0;
+    assert_eq!(encode_full::<u64>(adc), Err(Error::InvalidEncoding(adc)));
+    let add3 = R5type::new(insts::OP_ADD3A, A0, A1, A2, T0, RA).0;
+    assert!(encode_full::<u64>(add3).is_err());
+}
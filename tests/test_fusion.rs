@@ -0,0 +1,386 @@
+use ckb_vm::assembler::assemble;
+use ckb_vm::decoder::{build_decoder, Decoder};
+use ckb_vm::elf::parse_elf;
+use ckb_vm::fusion::{
+    default_fusion_rules, Captures, Constraint, FusionRule, Operand, PatternInstruction,
+    DEFAULT_FUSION_RULES,
+};
+use ckb_vm::generator::{generate, GeneratorConfig};
+use ckb_vm::instructions::{extract_opcode, instruction_length, Instruction, Itype};
+use ckb_vm::machine::{VERSION0, VERSION1, VERSION2, VERSION3};
+use ckb_vm::memory::{Memory, FLAG_EXECUTABLE};
+use ckb_vm::registers::A0;
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, Error, Register,
+    SparseMemory, WXorXMemory, ISA_A, ISA_B, ISA_IMC, ISA_MOP,
+};
+use ckb_vm_definitions::instructions as insts;
+pub mod legacy_mop;
+
+use Operand::{Imm, Reg};
+
+const PROGRAM: &str = r#"
+    .text
+    .globl _start
+_start:
+    li a0, 5
+pair:
+    addi a0, a0, 100
+    addi a0, a0, -3
+    addi a1, a1, 1
+    addi a0, a0, 1
+    li a7, 93
+    ecall
+"#;
+
+// addi rd, rd, imm0; addi rd, rd, imm1 => addi rd, rd, imm0 + imm1
+const ADDI_PAIR: FusionRule = FusionRule {
+    name: "addi_pair",
+    min_version: VERSION2,
+    max_version: u32::MAX,
+    pattern: &[
+        PatternInstruction::new(insts::OP_ADDI, &[Reg(0), Reg(0), Imm(0)]),
+        PatternInstruction::new(insts::OP_ADDI, &[Reg(0), Reg(0), Imm(1)]),
+    ],
+    constraints: &[Constraint::NonZero(0)],
+    fuse: fuse_addi_pair,
+};
+
+fn fuse_addi_pair(c: &Captures) -> Option<Instruction> {
+    let immediate = c.imm(0).checked_add(c.imm(1))?;
+    Some(Itype::new_s(insts::OP_ADDI, c.reg(0), c.reg(0), immediate).0)
+}
+
+fn machine(
+    version: u32,
+) -> (
+    DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>,
+    u64,
+) {
+    let program = assemble::<u64>(PROGRAM).unwrap();
+    let core =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC | ISA_MOP, version, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core).build();
+    machine
+        .load_program(&program.to_elf(), &["fusion".into()])
+        .unwrap();
+    (machine, program.symbol("pair").unwrap())
+}
+
+#[test]
+pub fn test_custom_fusion_rule() {
+    let (mut machine, pair) = machine(VERSION2);
+    let mut decoder = build_decoder::<u64>(machine.isa(), machine.version());
+    decoder.add_fusion_rule(ADDI_PAIR).unwrap();
+    assert!(decoder.fusion_rules().any(|r| r.name == "addi_pair"));
+
+    let fused = decoder.decode_mop(machine.memory_mut(), pair).unwrap();
+    assert_eq!(extract_opcode(fused), insts::OP_ADDI);
+    assert_eq!(Itype(fused).immediate_s(), 97);
+    assert_eq!(instruction_length(fused), 8);
+    // addi a1, a1, 1 writes another register, so the rule does not match.
+    let single = decoder.decode_mop(machine.memory_mut(), pair + 8).unwrap();
+    assert_eq!(instruction_length(single), 4);
+
+    assert_eq!(machine.run_with_decoder(&mut decoder).unwrap(), 103);
+    assert_eq!(machine.registers()[A0], 103);
+}
+
+#[test]
+pub fn test_fusion_rule_version() {
+    let (mut machine, pair) = machine(VERSION1);
+    let mut decoder = build_decoder::<u64>(machine.isa(), machine.version());
+    decoder.add_fusion_rule(ADDI_PAIR).unwrap();
+    assert!(decoder.fusion_rules().all(|r| r.name != "addi_pair"));
+    let instruction = decoder.decode_mop(machine.memory_mut(), pair).unwrap();
+    assert_eq!(instruction_length(instruction), 4);
+    assert_eq!(machine.run_with_decoder(&mut decoder).unwrap(), 103);
+}
+
+#[test]
+pub fn test_default_fusion_rules() {
+    for version in [VERSION0, VERSION1, VERSION2] {
+        let names: Vec<_> = Decoder::new(true, version)
+            .fusion_rules()
+            .map(|r| r.name)
+            .collect();
+        let mut expected: Vec<_> = default_fusion_rules(version)
+            .iter()
+            .map(|r| r.name)
+            .collect();
+        expected.sort_unstable();
+        let mut sorted = names.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, expected);
+        assert!(names.len() < DEFAULT_FUSION_RULES.len());
+    }
+    assert!(default_fusion_rules(VERSION2)
+        .iter()
+        .any(|r| r.name == "adcs"));
+    assert!(default_fusion_rules(VERSION1)
+        .iter()
+        .all(|r| r.name != "adcs"));
+    assert_eq!(Decoder::new(false, VERSION2).fusion_rules().count(), 0);
+
+    let mut decoder = build_decoder::<u64>(ISA_IMC | ISA_MOP, VERSION2);
+    decoder.clear_fusion_rules();
+    assert_eq!(decoder.fusion_rules().count(), 0);
+}
+
+// Rules rejected when added to a decoder.
+const INVALID_RULES: &[FusionRule] = &[
+    // Empty pattern.
+    FusionRule {
+        pattern: &[],
+        ..ADDI_PAIR
+    },
+    // Too many operands.
+    FusionRule {
+        pattern: &[PatternInstruction::new(
+            insts::OP_ADDI,
+            &[Reg(0), Reg(0), Imm(0), Imm(1)],
+        )],
+        ..ADDI_PAIR
+    },
+    // Immediate in a register slot.
+    FusionRule {
+        pattern: &[PatternInstruction::new(insts::OP_ADDI, &[Imm(0), Reg(0)])],
+        ..ADDI_PAIR
+    },
+    // Rtype has no immediate.
+    FusionRule {
+        pattern: &[PatternInstruction::new(
+            insts::OP_ADD,
+            &[Reg(0), Reg(1), Imm(0)],
+        )],
+        ..ADDI_PAIR
+    },
+    // Immediate variable used twice.
+    FusionRule {
+        pattern: &[
+            PatternInstruction::new(insts::OP_ADDI, &[Reg(0), Reg(0), Imm(0)]),
+            PatternInstruction::new(insts::OP_ADDI, &[Reg(0), Reg(0), Imm(0)]),
+        ],
+        ..ADDI_PAIR
+    },
+    // Register variable out of range.
+    FusionRule {
+        pattern: &[PatternInstruction::new(insts::OP_ADDI, &[Reg(8), Reg(0)])],
+        ..ADDI_PAIR
+    },
+    // Constraint variable out of range.
+    FusionRule {
+        constraints: &[Constraint::Distinct(0, 8)],
+        ..ADDI_PAIR
+    },
+    // Unknown opcode.
+    FusionRule {
+        pattern: &[PatternInstruction::new(0, &[])],
+        ..ADDI_PAIR
+    },
+];
+
+#[test]
+pub fn test_invalid_fusion_rules() {
+    let mut decoder = Decoder::new(true, VERSION2);
+    for rule in INVALID_RULES {
+        match decoder.add_fusion_rule(*rule) {
+            Err(Error::InvalidFusionRule { name, .. }) => assert_eq!(name, "addi_pair"),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+    // Invalid rules are rejected even in VERSIONs they are not enabled in.
+    let mut decoder = Decoder::new(true, VERSION0);
+    assert!(decoder.add_fusion_rule(INVALID_RULES[0]).is_err());
+}
+
+const IDIOMS: &str = r#"
+    .text
+    .globl _start
+_start:
+    la s0, buffer
+    li s1, 0
+    li s2, 16
+    li a0, 0
+loop:
+shift_add:
+    slli t0, s1, 3
+    add t0, t0, s0
+ld_pair:
+    ld t1, 0(t0)
+    ld t2, 8(t0)
+    add a0, a0, t1
+    xor a0, a0, t2
+load_imm:
+    lui t3, 0x12345
+    addi t3, t3, 0x678
+shift_addi:
+    slli t3, t3, 12
+    addi t3, t3, -0x123
+    slli t3, t3, 12
+    addi t3, t3, 0x456
+    add a0, a0, t3
+sd_pair:
+    sd t3, 0(t0)
+    sd a0, 8(t0)
+    addi s1, s1, 1
+slt_bnez:
+    slt t4, s1, s2
+    bnez t4, loop
+    li s1, 0
+count:
+    addi s1, s1, 3
+sltu_bnez:
+    sltu t4, s1, s2
+    bnez t4, count
+slt_beqz:
+    slt t5, a0, zero
+    beqz t5, positive
+    xori a0, a0, -1
+positive:
+sltu_beqz:
+    sltu t6, s2, s1
+    beqz t6, done
+    addi a0, a0, 7
+done:
+    add a0, a0, s1
+    li a7, 93
+    ecall
+    # Offsets 0 and 16 are not adjacent.
+not_adjacent:
+    ld t1, 0(t0)
+    ld t2, 16(t0)
+    # Descending pairs would fault on another access when fused.
+descending_ld:
+    ld t1, 8(t0)
+    ld t2, 0(t0)
+descending_sd:
+    sd a0, 8(t0)
+    sd t3, 0(t0)
+
+    .data
+buffer:
+    .dword 1, -2, 3, -4, 5, -6, 7, -8, 9, -10, 11, -12, 13, -14, 15, -16, 17
+"#;
+
+const IDIOM_FUSIONS: &[(&str, u16)] = &[
+    ("shift_add", insts::OP_SLLI_ADD),
+    ("ld_pair", insts::OP_LD_PAIR),
+    ("load_imm", insts::OP_CUSTOM_LOAD_IMM),
+    ("shift_addi", insts::OP_SLLI_ADDI),
+    ("sd_pair", insts::OP_SD_PAIR),
+    ("slt_bnez", insts::OP_SLT_BNEZ),
+    ("sltu_bnez", insts::OP_SLTU_BNEZ),
+    ("slt_beqz", insts::OP_SLT_BEQZ),
+    ("sltu_beqz", insts::OP_SLTU_BEQZ),
+];
+
+// Exit code, registers and buffer contents after running IDIOMS.
+fn run_idioms(isa: u8) -> (i8, Vec<u64>, Vec<u8>) {
+    let program = assemble::<u64>(IDIOMS).unwrap();
+    let core = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(isa, VERSION3, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core).build();
+    machine
+        .load_program(&program.to_elf(), &["idioms".into()])
+        .unwrap();
+    let exit = machine.run().unwrap();
+    let buffer = program.symbol("buffer").unwrap();
+    let memory = machine.memory_mut().load_bytes(buffer, 17 * 8).unwrap();
+    (exit, machine.registers().to_vec(), memory.to_vec())
+}
+
+#[test]
+pub fn test_compiler_idiom_fusions() {
+    let program = assemble::<u64>(IDIOMS).unwrap();
+    let mut machine = DefaultMachineBuilder::new(
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC | ISA_MOP, VERSION3, u64::MAX),
+    )
+    .build();
+    machine
+        .load_program(&program.to_elf(), &["idioms".into()])
+        .unwrap();
+    let mut decoder = build_decoder::<u64>(machine.isa(), VERSION3);
+    let mut previous = build_decoder::<u64>(machine.isa(), VERSION2);
+    for (label, opcode) in IDIOM_FUSIONS {
+        let pc = program.symbol(label).unwrap();
+        let fused = decoder.decode_mop(machine.memory_mut(), pc).unwrap();
+        assert_eq!(extract_opcode(fused), *opcode, "{}", label);
+        let unfused = previous.decode_mop(machine.memory_mut(), pc).unwrap();
+        assert_ne!(extract_opcode(unfused), *opcode, "{}", label);
+    }
+    let pc = program.symbol("not_adjacent").unwrap();
+    let single = decoder.decode_mop(machine.memory_mut(), pc).unwrap();
+    assert_eq!(extract_opcode(single), insts::OP_LD_VERSION1);
+    let pc = program.symbol("descending_ld").unwrap();
+    let single = decoder.decode_mop(machine.memory_mut(), pc).unwrap();
+    assert_eq!(extract_opcode(single), insts::OP_LD_VERSION1);
+    let pc = program.symbol("descending_sd").unwrap();
+    let single = decoder.decode_mop(machine.memory_mut(), pc).unwrap();
+    assert_eq!(extract_opcode(single), insts::OP_SD);
+
+    assert_eq!(run_idioms(ISA_IMC | ISA_MOP), run_idioms(ISA_IMC));
+}
+
+#[cfg(has_asm)]
+#[test]
+pub fn test_compiler_idiom_fusions_asm() {
+    use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+
+    let program = assemble::<u64>(IDIOMS).unwrap();
+    let asm_core = AsmCoreMachine::new(ISA_IMC | ISA_MOP, VERSION3, u64::MAX);
+    let core = DefaultMachineBuilder::new(asm_core).build();
+    let mut machine = AsmMachine::new(core);
+    machine
+        .load_program(&program.to_elf(), &["idioms".into()])
+        .unwrap();
+    let exit = machine.run().unwrap();
+    let buffer = program.symbol("buffer").unwrap();
+    let memory = machine
+        .machine
+        .memory_mut()
+        .load_bytes(buffer, 17 * 8)
+        .unwrap();
+    let result = (exit, machine.machine.registers().to_vec(), memory.to_vec());
+    assert_eq!(result, run_idioms(ISA_IMC));
+}
+
+// Decodes every halfword of the code of generated programs, including the
+// ones in the middle of instructions and right before the end of the code,
+// where the rest of a sequence fails to decode.
+fn check_legacy_fusion<R: Register>(seed: u64) {
+    let isa = ISA_IMC | ISA_A | ISA_B | ISA_MOP;
+    let program = generate::<R>(&GeneratorConfig::default(), &seed.to_le_bytes()).unwrap();
+    let elf = program.elf();
+    for version in [VERSION0, VERSION1, VERSION2] {
+        let core = DefaultCoreMachine::<R, WXorXMemory<SparseMemory<R>>>::new(isa, version, 0);
+        let mut machine = DefaultMachineBuilder::new(core).build();
+        machine.load_program(&elf, &["main".into()]).unwrap();
+        let mut decoder = build_decoder::<R>(isa, version);
+        let mut legacy = build_decoder::<R>(isa, version);
+        let metadata = parse_elf::<R>(&elf, version).unwrap();
+        for action in metadata.actions {
+            if action.flags & FLAG_EXECUTABLE == 0 {
+                continue;
+            }
+            for pc in (action.addr..action.addr + action.size).step_by(2) {
+                let memory = machine.memory_mut();
+                assert_eq!(
+                    decoder.decode_mop(memory, pc),
+                    legacy_mop::decode_mop(&mut legacy, version, memory, pc),
+                    "seed {} version {} pc {:#x}",
+                    seed,
+                    version,
+                    pc
+                );
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_fusion_matches_legacy_decoder() {
+    for seed in 0..16 {
+        check_legacy_fusion::<u64>(seed);
+        check_legacy_fusion::<u32>(seed);
+    }
+}
@@ -0,0 +1,181 @@
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::disasm::disassemble;
+use ckb_vm::generator::{generate, GeneratedProgram, GeneratorConfig, BUFFER_SIZE};
+use ckb_vm::instructions::{extract_opcode, instruction_opcode_name, insts};
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::{VERSION1, VERSION2};
+use ckb_vm::{
+    Bytes, DefaultCoreMachine, DefaultMachineBuilder, Memory, Register, SparseMemory,
+    SupportMachine, TraceMachine, WXorXMemory, ISA_A, ISA_B, ISA_IMC, ISA_MOP,
+};
+use proptest::prelude::*;
+
+// Exit code, registers, buffer content and cycles after a run.
+type Outcome = (i8, Vec<u64>, Bytes, u64);
+
+fn observe<M: SupportMachine>(exit: i8, machine: &mut M, buffer: u64) -> Outcome {
+    let registers = machine.registers().iter().map(|r| r.to_u64()).collect();
+    let memory = machine
+        .memory_mut()
+        .load_bytes(buffer, BUFFER_SIZE)
+        .unwrap();
+    (exit, registers, memory, machine.cycles())
+}
+
+fn run_interpreter<R: Register>(program: &GeneratedProgram, isa: u8, version: u32) -> Outcome {
+    let core_machine =
+        DefaultCoreMachine::<R, WXorXMemory<SparseMemory<R>>>::new(isa, version, 10_000_000);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    machine
+        .load_program(&program.elf(), &["main".into()])
+        .unwrap();
+    let exit = machine.run().unwrap();
+    observe(exit, &mut machine, program.buffer())
+}
+
+fn run_trace<R: Register>(program: &GeneratedProgram, isa: u8, version: u32) -> Outcome {
+    let core_machine =
+        DefaultCoreMachine::<R, WXorXMemory<SparseMemory<R>>>::new(isa, version, 10_000_000);
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .build(),
+    );
+    machine
+        .load_program(&program.elf(), &["main".into()])
+        .unwrap();
+    let exit = machine.run().unwrap();
+    observe(exit, &mut machine.machine, program.buffer())
+}
+
+#[cfg(has_asm)]
+fn run_asm(program: &GeneratedProgram, isa: u8, version: u32) -> Outcome {
+    let asm_core = AsmCoreMachine::new(isa, version, 10_000_000);
+    let core = DefaultMachineBuilder::<Box<AsmCoreMachine>>::new(asm_core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let mut machine = AsmMachine::new(core);
+    machine
+        .load_program(&program.elf(), &["main".into()])
+        .unwrap();
+    let exit = machine.run().unwrap();
+    observe(exit, &mut machine.machine, program.buffer())
+}
+
+fn check_differential(entropy: &[u8]) {
+    let isa = ISA_IMC | ISA_A | ISA_B;
+    let config = GeneratorConfig::default();
+    let program = generate::<u64>(&config, entropy).unwrap();
+    let expected = run_interpreter::<u64>(&program, isa, VERSION2);
+    assert_eq!(run_trace::<u64>(&program, isa, VERSION2), expected);
+    // Fused instructions cost fewer cycles, everything else is the same.
+    let fused = run_interpreter::<u64>(&program, isa | ISA_MOP, VERSION2);
+    assert_eq!(
+        (fused.0, &fused.1, &fused.2),
+        (expected.0, &expected.1, &expected.2),
+        "{}",
+        program.source
+    );
+    assert!(fused.3 <= expected.3);
+    assert_eq!(run_trace::<u64>(&program, isa | ISA_MOP, VERSION2), fused);
+    let v1 = run_interpreter::<u64>(&program, isa | ISA_MOP, VERSION1);
+    assert_eq!(v1.1, expected.1, "{}", program.source);
+    #[cfg(has_asm)]
+    {
+        assert_eq!(run_asm(&program, isa, VERSION2), expected);
+        assert_eq!(run_asm(&program, isa | ISA_MOP, VERSION2), fused);
+        assert_eq!(run_asm(&program, isa | ISA_MOP, VERSION1), v1);
+    }
+
+    let program = generate::<u32>(&config, entropy).unwrap();
+    let expected = run_interpreter::<u32>(&program, isa, VERSION2);
+    assert_eq!(run_trace::<u32>(&program, isa, VERSION2), expected);
+    let fused = run_interpreter::<u32>(&program, isa | ISA_MOP, VERSION2);
+    assert_eq!(fused.1, expected.1, "{}", program.source);
+}
+
+#[test]
+pub fn test_generator_differential() {
+    for seed in 0..32u64 {
+        check_differential(&seed.to_le_bytes());
+    }
+}
+
+#[test]
+pub fn test_generator_deterministic() {
+    let config = GeneratorConfig::default();
+    let a = generate::<u64>(&config, b"entropy").unwrap();
+    let b = generate::<u64>(&config, b"entropy").unwrap();
+    assert_eq!(a.source, b.source);
+    assert_eq!(a.elf(), b.elf());
+    let c = generate::<u64>(&config, b"entropz").unwrap();
+    assert_ne!(a.source, c.source);
+    // Running out of entropy is fine, as is having none at all.
+    assert!(generate::<u64>(&config, &[]).is_ok());
+    assert!(generate::<u64>(&config, &[0xff; 100_000]).is_ok());
+}
+
+#[test]
+pub fn test_generator_isa_subsets() {
+    // Without B and A, the programs run on the base ISA.
+    let config = GeneratorConfig {
+        isa: ISA_IMC,
+        rvc: false,
+        ..Default::default()
+    };
+    for seed in 0..8u64 {
+        let program = generate::<u64>(&config, &seed.to_le_bytes()).unwrap();
+        run_interpreter::<u64>(&program, ISA_IMC, VERSION2);
+        let lines = disassemble::<u64>(&program.elf(), ISA_IMC, VERSION2).unwrap();
+        assert!(lines.iter().all(|l| l.instruction.is_some()));
+        assert!(lines.iter().all(|l| !l.is_compressed()));
+    }
+}
+
+#[test]
+pub fn test_generator_fusion_patterns() {
+    // Over a few programs, every fused opcode shows up.
+    let config = GeneratorConfig::default();
+    let mut fused = std::collections::HashSet::new();
+    for seed in 0..64u64 {
+        let program = generate::<u64>(&config, &seed.to_le_bytes()).unwrap();
+        let lines = disassemble::<u64>(&program.elf(), ISA_IMC | ISA_A | ISA_B | ISA_MOP, VERSION2)
+            .unwrap();
+        for line in lines {
+            if let Some(i) = line.fused {
+                fused.insert(extract_opcode(i));
+            }
+        }
+    }
+    for op in [
+        insts::OP_ADC,
+        insts::OP_SBB,
+        insts::OP_ADD3A,
+        insts::OP_ADD3B,
+        insts::OP_ADD3C,
+        insts::OP_ADCS,
+        insts::OP_SBBS,
+        insts::OP_WIDE_MUL,
+        insts::OP_WIDE_MULU,
+        insts::OP_WIDE_MULSU,
+        insts::OP_WIDE_DIV,
+        insts::OP_WIDE_DIVU,
+        insts::OP_CUSTOM_LOAD_IMM,
+        insts::OP_FAR_JUMP_REL,
+        insts::OP_FAR_JUMP_ABS,
+    ] {
+        assert!(fused.contains(&op), "{}", instruction_opcode_name(op));
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+    #[test]
+    #[cfg_attr(all(miri, feature = "miri-ci"), ignore)]
+    fn test_generator_differential_proptest(entropy in prop::collection::vec(any::<u8>(), 0..2048)) {
+        check_differential(&entropy);
+    }
+}
@@ -0,0 +1,47 @@
+use ckb_vm::elf::check_isa;
+use ckb_vm::machine::VERSION2;
+use ckb_vm::{
+    Bytes, DefaultCoreMachine, DefaultMachineBuilder, Error, SparseMemory, WXorXMemory, ISA_A,
+    ISA_B, ISA_E, ISA_IMC, ISA_MOP,
+};
+use std::fs;
+
+fn load(program: &Bytes, isa: u8, check: bool) -> Result<u64, Error> {
+    let core_machine =
+        DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(isa, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .check_isa(check)
+        .build();
+    machine.load_program(program, &["main".into()])
+}
+
+#[test]
+pub fn test_check_isa() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    // simple64 is built for rv64imac.
+    assert!(matches!(
+        load(&buffer, ISA_IMC, true),
+        Err(Error::InvalidIsa(_))
+    ));
+    assert!(load(&buffer, ISA_IMC, false).is_ok());
+    assert!(load(&buffer, ISA_IMC | ISA_A, true).is_ok());
+    assert!(matches!(
+        load(&buffer, ISA_IMC | ISA_A | ISA_E, true),
+        Err(Error::InvalidIsa(_))
+    ));
+
+    let buffer: Bytes = fs::read("tests/programs/clang_sample").unwrap().into();
+    assert!(load(&buffer, ISA_IMC | ISA_A | ISA_MOP, true).is_err());
+    assert!(load(&buffer, ISA_IMC | ISA_A | ISA_B | ISA_MOP, true).is_ok());
+}
+
+#[test]
+pub fn test_check_isa_without_attributes() {
+    let buffer: Bytes = fs::read("tests/programs/simple").unwrap().into();
+    assert_eq!(check_isa::<u32>(&buffer, VERSION2, ISA_IMC), Ok(()));
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    assert_eq!(
+        check_isa::<u32>(&buffer, VERSION2, ISA_IMC | ISA_A),
+        Err(Error::ElfBits)
+    );
+}
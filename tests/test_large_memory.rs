@@ -0,0 +1,61 @@
+use ckb_vm::assembler::assemble_elf;
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::trace::TraceMachine;
+use ckb_vm::machine::VERSION2;
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Memory, SparseMemory, WXorXMemory,
+    ISA_A, ISA_B, ISA_IMC,
+};
+
+const MEMORY_SIZE: usize = 8 << 30;
+
+// Writes and reads back words above 4 GB, then checks the sum.
+const PROGRAM: &str = "
+    li s0, 0x100000000
+    li s1, 0x1c0000000
+    li a0, 0
+    li a1, 1
+store:
+    sd a1, 0(s0)
+    add a0, a0, a1
+    addi a1, a1, 1
+    li t0, 0x100000
+    add s0, s0, t0
+    bltu s0, s1, store
+    li s0, 0x100000000
+load:
+    ld a1, 0(s0)
+    sub a0, a0, a1
+    li t0, 0x100000
+    add s0, s0, t0
+    bltu s0, s1, load
+    li a7, 93
+    ecall
+";
+
+#[test]
+fn test_large_memory_sparse() {
+    let program = assemble_elf::<u64>(PROGRAM).unwrap();
+    let core_machine = DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new_with_memory(
+        ISA_IMC | ISA_A | ISA_B,
+        VERSION2,
+        u64::MAX,
+        WXorXMemory::new(SparseMemory::new_with_memory(MEMORY_SIZE)),
+    );
+    let mut machine = TraceMachine::new(DefaultMachineBuilder::new(core_machine).build());
+    machine.load_program(&program, &[]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.machine.memory().memory_size(), MEMORY_SIZE);
+}
+
+#[cfg(has_asm)]
+#[test]
+fn test_large_memory_asm() {
+    let program = assemble_elf::<u64>(PROGRAM).unwrap();
+    let core =
+        AsmCoreMachine::new_with_memory(ISA_IMC | ISA_A | ISA_B, VERSION2, u64::MAX, MEMORY_SIZE);
+    let mut machine = AsmMachine::new(DefaultMachineBuilder::new(core).build());
+    machine.load_program(&program, &[]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+}
@@ -0,0 +1,204 @@
+use ckb_vm::elf::{parse_exported_symbols, R_RISCV_64, R_RISCV_JUMP_SLOT};
+use ckb_vm::loader::Loader;
+use ckb_vm::machine::VERSION3;
+use ckb_vm::snapshot2::{DataSource, Snapshot2Context};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, Error,
+    SparseMemory, WXorXMemory, ISA_IMC,
+};
+use std::collections::HashMap;
+pub mod elf_build;
+use elf_build::{build_pie, build_pie_with_hash, HashStyle, Rela, Symbol, DATA, RODATA, TEXT};
+
+const LIBRARY_BASE: u64 = 0x200000;
+const MAIN_ID: u64 = 0;
+const LIB_ID: u64 = 1;
+
+type Mem = WXorXMemory<SparseMemory<u64>>;
+
+fn build_library() -> Bytes {
+    build_library_with_hash(HashStyle::Sysv)
+}
+
+// Exports get, returning 40, and answer, a 64-bit value of 2.
+fn build_library_with_hash(hash: HashStyle) -> Bytes {
+    let code = [
+        0x02800513, // li a0, 40
+        0x00008067, // ret
+    ];
+    let symbols = [
+        Symbol {
+            name: "get",
+            value: Some(TEXT),
+        },
+        Symbol {
+            name: "answer",
+            value: Some(RODATA),
+        },
+    ];
+    build_pie_with_hash(&code, &2u64.to_le_bytes(), &[], &symbols, &[], hash)
+}
+
+// Exits with get() + answer.
+fn build_main() -> Bytes {
+    let code = [
+        0x00001297, // auipc t0, 0x1
+        0xf002b303, // ld t1, -256(t0)
+        0x000300e7, // jalr t1
+        0x00001297, // auipc t0, 0x1
+        0xefc2b383, // ld t2, -260(t0)
+        0x0003b383, // ld t2, 0(t2)
+        0x00750533, // add a0, a0, t2
+        0x05d00893, // li a7, 93
+        0x00000073, // ecall
+    ];
+    let symbols = [
+        Symbol {
+            name: "get",
+            value: None,
+        },
+        Symbol {
+            name: "answer",
+            value: None,
+        },
+    ];
+    let relas = [
+        Rela {
+            offset: DATA,
+            kind: R_RISCV_JUMP_SLOT,
+            symbol: 1,
+            addend: 0,
+        },
+        Rela {
+            offset: DATA + 8,
+            kind: R_RISCV_64,
+            symbol: 2,
+            addend: 0,
+        },
+    ];
+    build_pie(&code, &[], &[0u8; 16], &symbols, &relas)
+}
+
+fn build_machine() -> DefaultMachine<DefaultCoreMachine<u64, Mem>> {
+    let core_machine = DefaultCoreMachine::<u64, Mem>::new(ISA_IMC, VERSION3, u64::MAX);
+    DefaultMachineBuilder::new(core_machine).build()
+}
+
+#[test]
+pub fn test_exported_symbols() {
+    for hash in [HashStyle::Sysv, HashStyle::Gnu] {
+        let library = build_library_with_hash(hash);
+        let symbols = parse_exported_symbols::<u64>(&library, VERSION3, 0x1000).unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols["get"], 0x1000 + TEXT);
+        assert_eq!(symbols["answer"], 0x1000 + RODATA);
+    }
+    let symbols = parse_exported_symbols::<u64>(&build_main(), VERSION3, 0).unwrap();
+    assert!(symbols.is_empty());
+}
+
+#[test]
+pub fn test_loader() {
+    test_loader_with_hash(HashStyle::Sysv);
+}
+
+#[test]
+pub fn test_loader_gnu_hash() {
+    test_loader_with_hash(HashStyle::Gnu);
+}
+
+fn test_loader_with_hash(hash: HashStyle) {
+    let main = build_main();
+    let lib = build_library_with_hash(hash);
+    let mut machine = build_machine();
+    let mut loader = Loader::new(LIBRARY_BASE);
+    let module = loader
+        .load_library(&mut machine, "libanswer.so", LIB_ID, &lib)
+        .unwrap();
+    assert_eq!(module.load_bias, LIBRARY_BASE);
+    assert_eq!(module.exports["get"], LIBRARY_BASE + TEXT);
+    let metadata = loader
+        .prepare_executable(&machine, "main", MAIN_ID, &main)
+        .unwrap();
+    machine
+        .load_program_with_metadata(&main, &metadata, &["main".into()])
+        .unwrap();
+
+    let modules = loader.modules();
+    assert_eq!(modules.len(), 2);
+    assert_eq!(modules[0].name, "libanswer.so");
+    assert_eq!(modules[0].range.start, LIBRARY_BASE);
+    assert_eq!(modules[1].name, "main");
+    assert_eq!(modules[1].range.start, 0);
+    assert_eq!(loader.symbols()["answer"], LIBRARY_BASE + RODATA);
+    assert_eq!(loader.find_module(TEXT).unwrap().id, MAIN_ID);
+    assert_eq!(loader.find_module(LIBRARY_BASE + TEXT).unwrap().id, LIB_ID);
+    assert!(loader.find_module(LIBRARY_BASE * 2).is_none());
+
+    assert_eq!(machine.run(), Ok(42));
+    assert_eq!(loader.find_module(*machine.pc()).unwrap().id, MAIN_ID);
+}
+
+#[test]
+pub fn test_loader_missing_library() {
+    let mut machine = build_machine();
+    let mut loader: Loader<u64> = Loader::new(LIBRARY_BASE);
+    let result = loader.prepare_executable(&machine, "main", MAIN_ID, &build_main());
+    assert_eq!(result, Err(Error::ElfUndefinedSymbol("get".to_string())));
+    assert!(loader.modules().is_empty());
+    // Libraries cannot overlap the executable.
+    let mut loader = Loader::new(0);
+    loader
+        .load_library(&mut machine, "libanswer.so", LIB_ID, &build_library())
+        .unwrap();
+    let result = loader.prepare_executable(&machine, "main", MAIN_ID, &build_main());
+    assert_eq!(result, Err(Error::ElfSegmentAddrOrSizeError(0)));
+}
+
+#[derive(Clone)]
+struct ImageSource(HashMap<u64, Bytes>);
+
+impl DataSource<u64> for ImageSource {
+    fn load_data(&self, id: &u64, offset: u64, length: u64) -> Option<(Bytes, u64)> {
+        let data = self.0.get(id)?;
+        let end = if length > 0 {
+            offset + length
+        } else {
+            data.len() as u64
+        };
+        Some((data.slice(offset as usize..end as usize), end - offset))
+    }
+}
+
+#[test]
+pub fn test_loader_snapshot2() {
+    let main = build_main();
+    let lib = build_library();
+    let mut machine1 = build_machine();
+    let mut loader = Loader::new(LIBRARY_BASE);
+    loader
+        .load_library(&mut machine1, "libanswer.so", LIB_ID, &lib)
+        .unwrap();
+    let metadata = loader
+        .prepare_executable(&machine1, "main", MAIN_ID, &main)
+        .unwrap();
+    machine1
+        .load_program_with_metadata(&main, &metadata, &["main".into()])
+        .unwrap();
+
+    let mut images = HashMap::new();
+    images.insert(MAIN_ID, main);
+    images.insert(LIB_ID, lib);
+    let mut ctx = Snapshot2Context::new(ImageSource(images));
+    ctx.mark_modules(&mut machine1, loader.modules()).unwrap();
+    let snapshot = ctx.make_snapshot(&mut machine1).unwrap();
+    // The library text page is restored from its own data source entry.
+    assert!(snapshot
+        .pages_from_source
+        .iter()
+        .any(|(addr, _, id, _, _)| *addr == LIBRARY_BASE && *id == LIB_ID));
+
+    let mut machine2 = build_machine();
+    ctx.resume(&mut machine2, &snapshot).unwrap();
+    assert_eq!(machine2.run(), Ok(42));
+}
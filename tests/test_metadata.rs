@@ -0,0 +1,382 @@
+use ckb_vm::cost_model::estimate_cycles;
+use ckb_vm::encoder::encode_full;
+use ckb_vm::instructions::tagged::TaggedInstruction;
+use ckb_vm::instructions::{
+    a, b, blank_instruction, execute_instruction, extract_opcode, i, instruction_metadata, insts,
+    is_basic_block_end_instruction, m, Instruction, InstructionFactory, Itype, R4type, R5type,
+    Register, Rtype, Stype, Utype,
+};
+use ckb_vm::machine::{VERSION0, VERSION1, VERSION2};
+use ckb_vm::{
+    CoreMachine, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, Memory, SparseMemory,
+    ISA_A, ISA_B, ISA_IMC, ISA_MOP,
+};
+use insts::{
+    ControlFlow, Extension, InstructionFormat, InstructionMetadata, INSTRUCTION_METADATA,
+    OPERAND_RD, OPERAND_RS1, OPERAND_RS2, OPERAND_RS3, OPERAND_RS4,
+};
+use std::convert::TryFrom;
+pub mod legacy_decoder;
+
+fn decode<R: Register>(bits: u32, version: u32) -> Option<Instruction> {
+    i::factory::<R>(bits, version)
+        .or_else(|| m::factory::<R>(bits, version))
+        .or_else(|| b::factory::<R>(bits, version))
+        .or_else(|| a::factory::<R>(bits, version))
+}
+
+// Register operands are a0 to a4, in the order of the OPERAND_* flags.
+const OPERANDS: [(u8, usize); 5] = [
+    (OPERAND_RD, 10),
+    (OPERAND_RS1, 11),
+    (OPERAND_RS2, 12),
+    (OPERAND_RS3, 13),
+    (OPERAND_RS4, 14),
+];
+const ADDRESS: u64 = 0x1000;
+// Memory observed around ADDRESS.
+const WINDOW: u64 = 64;
+
+fn build(metadata: &InstructionMetadata) -> Instruction {
+    let op = metadata.opcode;
+    match metadata.format {
+        InstructionFormat::Rtype => Rtype::new(op, 10, 11, 12).0,
+        InstructionFormat::R4type => R4type::new(op, 10, 11, 12, 13).0,
+        InstructionFormat::R5type => R5type::new(op, 10, 11, 12, 13, 14).0,
+        InstructionFormat::Itype => {
+            Itype::new_s(op, 10, 11, if metadata.is_load() { 0 } else { 3 }).0
+        }
+        InstructionFormat::Stype => Stype::new_s(op, 0, 11, 12).0,
+        InstructionFormat::Utype => Utype::new_s(op, 10, 0x12345000).0,
+    }
+}
+
+fn registers(flags: u8) -> Vec<usize> {
+    OPERANDS
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, r)| *r)
+        .collect()
+}
+
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        // xorshift64*
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    // Small values and boundaries show up more often than in uniform
+    // values, so comparisons and shifts take both sides.
+    fn value(&mut self) -> u64 {
+        match self.next() % 4 {
+            0 => self.next() % 64,
+            1 => [0, 1, u64::MAX, 1 << 63, 1 << 31][(self.next() % 5) as usize],
+            _ => self.next(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct State {
+    registers: [u64; 32],
+    memory: Vec<u8>,
+}
+
+fn execute(instruction: Instruction, state: &State) -> State {
+    let core_machine = DefaultCoreMachine::<u64, SparseMemory<u64>>::new(
+        ISA_IMC | ISA_A | ISA_B | ISA_MOP,
+        VERSION2,
+        u64::MAX,
+    );
+    let mut machine: DefaultMachine<_> = DefaultMachineBuilder::new(core_machine).build();
+    for (i, value) in state.registers.iter().enumerate().skip(1) {
+        machine.set_register(i, *value);
+    }
+    machine
+        .memory_mut()
+        .store_bytes(ADDRESS - WINDOW, &state.memory)
+        .unwrap();
+    execute_instruction(instruction, &mut machine).unwrap();
+    let mut registers = [0; 32];
+    registers.copy_from_slice(machine.registers());
+    let memory = machine
+        .memory_mut()
+        .load_bytes(ADDRESS - WINDOW, WINDOW * 2)
+        .unwrap()
+        .to_vec();
+    State { registers, memory }
+}
+
+fn random_state(random: &mut Random, metadata: &InstructionMetadata) -> State {
+    let mut registers = [0; 32];
+    for r in registers.iter_mut().skip(1) {
+        *r = random.value();
+    }
+    if metadata.is_load() || metadata.is_store() {
+        registers[11] = ADDRESS;
+    }
+    let memory = (0..WINDOW * 2).map(|_| random.next() as u8).collect();
+    State { registers, memory }
+}
+
+// Indices into State::memory of the bytes at ADDRESS.
+fn accessed(bytes: u8) -> std::ops::Range<usize> {
+    WINDOW as usize..(WINDOW as usize + bytes as usize)
+}
+
+#[test]
+pub fn test_metadata_matches_executor() {
+    let mut random = Random(0x9e3779b97f4a7c15);
+    let mut checked = 0;
+    for metadata in INSTRUCTION_METADATA.iter() {
+        // Control flow and system instructions depend on more than
+        // registers and memory.
+        if metadata.control != ControlFlow::None || metadata.extension == Extension::Internal {
+            continue;
+        }
+        checked += 1;
+        let instruction = build(metadata);
+        let mut written = vec![];
+        let mut read = vec![];
+        let mut outputs = registers(metadata.writes);
+        outputs.extend((1..32).filter(|r| metadata.implicit_writes & (1 << r) != 0));
+        let inputs = registers(metadata.reads);
+        for _ in 0..64 {
+            let initial = random_state(&mut random, metadata);
+            let result = execute(instruction, &initial);
+            for r in 1..32 {
+                if result.registers[r] != initial.registers[r] {
+                    assert!(outputs.contains(&r), "{} writes x{}", metadata.name, r);
+                    written.push(r);
+                }
+            }
+            let stored = accessed(metadata.store_bytes);
+            for i in 0..initial.memory.len() {
+                if result.memory[i] != initial.memory[i] {
+                    assert!(stored.contains(&i), "{} stores byte {}", metadata.name, i);
+                }
+            }
+
+            // Anything that is not read may change without affecting the
+            // outputs.
+            let mut other = random_state(&mut random, metadata);
+            for r in &inputs {
+                other.registers[*r] = initial.registers[*r];
+            }
+            let loaded = accessed(metadata.load_bytes);
+            other.memory[loaded.clone()].copy_from_slice(&initial.memory[loaded]);
+            let other_result = execute(instruction, &other);
+            for r in &outputs {
+                assert_eq!(
+                    other_result.registers[*r], result.registers[*r],
+                    "{} reads more than declared",
+                    metadata.name
+                );
+            }
+            for i in stored {
+                // SC only stores when the reservation holds.
+                if result.memory[i] != initial.memory[i]
+                    || other_result.memory[i] != other.memory[i]
+                {
+                    assert_eq!(
+                        other_result.memory[i], result.memory[i],
+                        "{} loads more than declared",
+                        metadata.name
+                    );
+                }
+            }
+
+            // And each register declared as read matters.
+            for r in &inputs {
+                if *r == 11 && (metadata.is_load() || metadata.is_store()) {
+                    continue;
+                }
+                let mut changed = initial.clone();
+                changed.registers[*r] = random.value();
+                if execute(instruction, &changed) != result {
+                    read.push(*r);
+                }
+            }
+        }
+        for r in &outputs {
+            assert!(written.contains(r), "{} never writes x{}", metadata.name, r);
+        }
+        for r in &inputs {
+            if *r != 11 || !(metadata.is_load() || metadata.is_store()) {
+                assert!(read.contains(r), "{} never reads x{}", metadata.name, r);
+            }
+        }
+    }
+    assert_eq!(checked, 144);
+}
+
+#[test]
+pub fn test_metadata_format_and_control_flow() {
+    for metadata in INSTRUCTION_METADATA.iter() {
+        let instruction = blank_instruction(metadata.opcode);
+        let format = match TaggedInstruction::try_from(instruction).unwrap() {
+            TaggedInstruction::Rtype(_) => InstructionFormat::Rtype,
+            TaggedInstruction::R4type(_) => InstructionFormat::R4type,
+            TaggedInstruction::R5type(_) => InstructionFormat::R5type,
+            TaggedInstruction::Itype(_) => InstructionFormat::Itype,
+            TaggedInstruction::Stype(_) => InstructionFormat::Stype,
+            TaggedInstruction::Utype(_) => InstructionFormat::Utype,
+        };
+        assert_eq!(metadata.format, format, "{}", metadata.name);
+        if metadata.control != ControlFlow::None && metadata.extension != Extension::Internal {
+            assert!(
+                is_basic_block_end_instruction(instruction),
+                "{}",
+                metadata.name
+            );
+        }
+    }
+    let ld = instruction_metadata(insts::OP_LD_VERSION1).unwrap();
+    assert_eq!((ld.load_bytes, ld.store_bytes), (8, 0));
+    let amo = instruction_metadata(insts::OP_AMOADD_W).unwrap();
+    assert_eq!((amo.load_bytes, amo.store_bytes), (4, 4));
+    assert_eq!(
+        instruction_metadata(insts::OP_FAR_JUMP_REL)
+            .unwrap()
+            .implicit_writes,
+        1 << ckb_vm::registers::RA
+    );
+    assert!(instruction_metadata(0).is_none());
+}
+
+#[test]
+pub fn test_metadata_encodings_decode() {
+    for metadata in INSTRUCTION_METADATA.iter() {
+        let encoding = match metadata.encoding {
+            Some(encoding) => encoding,
+            None => continue,
+        };
+        // All operands zero.
+        let bits = encoding.value;
+        let version = encoding.min_version;
+        let instruction = decode::<u64>(bits, version).unwrap();
+        assert_eq!(
+            extract_opcode(instruction),
+            metadata.opcode,
+            "{}",
+            metadata.name
+        );
+        assert_eq!(encode_full::<u64>(instruction).unwrap(), bits);
+        let rv32 = decode::<u32>(bits, version).map(extract_opcode);
+        if encoding.rv64_only {
+            assert_ne!(rv32, Some(metadata.opcode), "{}", metadata.name);
+        } else {
+            assert_eq!(rv32, Some(metadata.opcode), "{}", metadata.name);
+        }
+        if encoding.max_version < VERSION2 {
+            let later = decode::<u64>(bits, encoding.max_version + 1).unwrap();
+            assert_ne!(extract_opcode(later), metadata.opcode);
+        }
+    }
+    for (op, cycles) in [
+        (insts::OP_ADD, 1),
+        (insts::OP_LD_VERSION1, 2),
+        (insts::OP_LW_VERSION0, 3),
+        (insts::OP_DIVUW, 32),
+        (insts::OP_AMOADD_D, 3),
+        (insts::OP_ECALL, 500),
+        (insts::OP_WIDE_MULU, 5),
+        (insts::OP_FAR_JUMP_ABS, 3),
+    ] {
+        assert_eq!(estimate_cycles(blank_instruction(op)), cycles);
+    }
+    assert_eq!(estimate_cycles(blank_instruction(0)), 1);
+}
+
+// Generated decoders must agree with the hand-written ones they replaced on
+// every opcode, funct3, funct7 and rs2 / shamt combination, rd and rs1 are
+// both zero or sampled.
+#[test]
+pub fn test_decoders_match_legacy_factories() {
+    fn check<R: Register>(version: u32) {
+        let decoders: [(InstructionFactory, InstructionFactory); 4] = [
+            (i::factory::<R>, legacy_decoder::i_factory::<R>),
+            (m::factory::<R>, legacy_decoder::m_factory::<R>),
+            (a::factory::<R>, legacy_decoder::a_factory::<R>),
+            (b::factory::<R>, legacy_decoder::b_factory::<R>),
+        ];
+        let mut seed = 0x2545_f491u32;
+        for shape in 0..(1u32 << 20) {
+            // opcode[6:2], funct3 and bits[31:20]
+            let fixed =
+                0b11 | ((shape & 0x1f) << 2) | (((shape >> 5) & 0x7) << 12) | (shape >> 8 << 20);
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let sampled = fixed | (seed & 0x000f_8f80);
+            for bits in [fixed, sampled] {
+                for (generated, legacy) in decoders.iter() {
+                    assert_eq!(
+                        generated(bits, version),
+                        legacy(bits, version),
+                        "{:#010x} rv{} version {}",
+                        bits,
+                        R::BITS,
+                        version
+                    );
+                }
+            }
+        }
+    }
+    for version in [VERSION0, VERSION1, VERSION2] {
+        check::<u32>(version);
+        check::<u64>(version);
+    }
+}
+
+#[test]
+pub fn test_decoders_preserve_legacy_quirks() {
+    let decode_one = |factory: InstructionFactory, bits: u32| factory(bits, VERSION2);
+    // On RV32, SLLI / SRLI / SRAI accept shamt[5] and mask it away.
+    let slli = Itype(i::factory::<u32>(0x02051513, VERSION2).unwrap());
+    assert_eq!((slli.op(), slli.immediate_s()), (insts::OP_SLLI, 0));
+    let slli = Itype(i::factory::<u64>(0x02051513, VERSION2).unwrap());
+    assert_eq!((slli.op(), slli.immediate_s()), (insts::OP_SLLI, 0x20));
+    // W variants of the B extension decode on RV32 too, except zext.h.
+    for (bits, op) in [
+        (0x08c5053b, insts::OP_ADDUW),
+        (0x60c5153b, insts::OP_ROLW),
+        (0x60c5553b, insts::OP_RORW),
+        (0x60051513, insts::OP_CLZ),
+        (0x6005151b, insts::OP_CLZW),
+        (0x0805151b, insts::OP_SLLIUW),
+    ] {
+        let rv32 = decode_one(b::factory::<u32>, bits).map(extract_opcode);
+        assert_eq!(rv32, Some(op), "{:#010x}", bits);
+    }
+    assert_eq!(decode_one(b::factory::<u32>, 0x0805453b), None);
+    assert_eq!(
+        decode_one(b::factory::<u64>, 0x0805453b).map(extract_opcode),
+        Some(insts::OP_ZEXTH)
+    );
+    // RORI takes a 6-bit shamt on both widths, RORIW a 5-bit one with
+    // funct7 fixed.
+    for factory in [b::factory::<u32>, b::factory::<u64>] {
+        let rori = Itype(decode_one(factory, 0x63f55513).unwrap());
+        assert_eq!((rori.op(), rori.immediate_u()), (insts::OP_RORI, 0x3f));
+    }
+    let roriw = Itype(decode_one(b::factory::<u64>, 0x61f5551b).unwrap());
+    assert_eq!((roriw.op(), roriw.immediate_u()), (insts::OP_RORIW, 0x1f));
+    assert_eq!(decode_one(b::factory::<u64>, 0x63f5551b), None);
+    // LR requires rs2 to be zero, SC does not.
+    assert_eq!(
+        decode_one(a::factory::<u64>, 0x1005252f).map(extract_opcode),
+        Some(insts::OP_LR_W)
+    );
+    assert_eq!(decode_one(a::factory::<u64>, 0x1015252f), None);
+    assert_eq!(decode_one(a::factory::<u64>, 0x1015352f), None);
+    assert_eq!(
+        decode_one(a::factory::<u64>, 0x1815252f).map(extract_opcode),
+        Some(insts::OP_SC_W)
+    );
+}
@@ -1,11 +1,12 @@
 use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::elf::{AT_ENTRY, AT_NULL, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_RANDOM};
 use ckb_vm::error::OutOfBoundKind;
-use ckb_vm::machine::VERSION0;
-use ckb_vm::registers::{A0, A1, A2, A3, A4, A5, A7};
+use ckb_vm::machine::{VERSION0, VERSION2, VERSION3};
+use ckb_vm::registers::{A0, A1, A2, A3, A4, A5, A7, SP};
 use ckb_vm::{
-    run, CoreMachine, Debugger, DefaultCoreMachine, DefaultMachineBuilder, Error, FlatMemory,
-    Memory, Register, SparseMemory, SupportMachine, Syscalls, WXorXMemory, DEFAULT_MEMORY_SIZE,
-    ISA_IMC, RISCV_PAGESIZE,
+    run, Bytes, CoreMachine, Debugger, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder,
+    Error, FlatMemory, Memory, Register, SparseMemory, StackEnv, SupportMachine, Syscalls,
+    WXorXMemory, DEFAULT_MEMORY_SIZE, ISA_IMC, RISCV_PAGESIZE,
 };
 #[cfg(has_asm)]
 use ckb_vm_definitions::asm::AsmCoreMachine;
@@ -460,3 +461,74 @@ pub fn test_clang() {
         assert!(ret_asm.is_ok());
     }
 }
+
+#[test]
+pub fn test_stack_env() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION3, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    let mut env = StackEnv::new(&["HOME=/".into(), "LANG=C".into()]);
+    env.random = [7; 16];
+    machine
+        .load_program_with_env(&buffer, &["simple64".into()], &env)
+        .unwrap();
+
+    let sp = machine.registers()[SP];
+    assert_eq!(sp % 16, 0);
+    let mut values: Vec<u64> = vec![];
+    // argc, argv, NULL, envp, NULL, then auxv pairs terminated by AT_NULL.
+    while values.len() < 6 || (values.len() % 2 == 1 || values[values.len() - 2] != AT_NULL) {
+        let addr = sp + values.len() as u64 * 8;
+        values.push(machine.memory_mut().load64(&addr).unwrap());
+    }
+    let load_string = |machine: &mut DefaultMachine<DefaultCoreMachine<u64, SparseMemory<u64>>>,
+                       addr: u64| {
+        let mut s = vec![];
+        loop {
+            let c = machine
+                .memory_mut()
+                .load8(&(addr + s.len() as u64))
+                .unwrap() as u8;
+            if c == 0 {
+                return s;
+            }
+            s.push(c);
+        }
+    };
+    assert_eq!(values[0], 1);
+    assert_eq!(load_string(&mut machine, values[1]), b"simple64");
+    assert_eq!(values[2], 0);
+    assert_eq!(load_string(&mut machine, values[3]), b"HOME=/");
+    assert_eq!(load_string(&mut machine, values[4]), b"LANG=C");
+    assert_eq!(values[5], 0);
+    let mut auxv = vec![];
+    for pair in values[6..values.len() - 2].chunks(2) {
+        auxv.push((pair[0], pair[1]));
+    }
+    let find = |key: u64| auxv.iter().find(|(k, _)| *k == key).unwrap().1;
+    assert_eq!(find(AT_ENTRY), 0x100c0);
+    assert_eq!(find(AT_PHDR), 0x10040);
+    assert_eq!(find(AT_PHENT), 56);
+    assert_eq!(find(AT_PHNUM), 2);
+    assert_eq!(find(AT_PAGESZ), RISCV_PAGESIZE as u64);
+    let random = machine
+        .memory_mut()
+        .load_bytes(find(AT_RANDOM), 16)
+        .unwrap();
+    assert_eq!(&random[..], &[7; 16]);
+
+    let result = machine.run();
+    assert!(result.is_ok());
+    assert_eq!(result.unwrap(), 0);
+}
+
+#[test]
+pub fn test_stack_env_requires_version3() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    let result = machine.load_program_with_env(&buffer, &["simple64".into()], &StackEnv::default());
+    assert_eq!(result, Err(Error::InvalidVersion));
+}
@@ -0,0 +1,291 @@
+#![cfg(has_asm)]
+use bytes::Bytes;
+use ckb_vm::assembler::assemble_elf;
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::elf::parse_elf;
+use ckb_vm::machine::asm::pool::{AsmMachinePool, PoolKey, PoolMetrics, PooledAsmMachine};
+use ckb_vm::machine::asm::AsmCoreMachine;
+use ckb_vm::machine::{DefaultMachine, DefaultMachineBuilder, VERSION1, VERSION2};
+use ckb_vm::registers::A7;
+use ckb_vm::{
+    CoreMachine, Error, Memory, Register, SupportMachine, Syscalls, DEFAULT_MEMORY_SIZE, ISA_IMC,
+    ISA_MOP,
+};
+use std::thread;
+
+// Cycle models of the pool keys, builders not installing a cycle function
+// use DEFAULT_CYCLES.
+const DEFAULT_CYCLES: u64 = 0;
+const CONSTANT_CYCLES: u64 = 1;
+const DOUBLE_CYCLES: u64 = 2;
+
+// Exits with the value found in memory, which must be zero on a reset
+// machine, plus the given increment.
+fn program(increment: u64) -> Bytes {
+    assemble_elf::<u64>(&format!(
+        "
+    li t0, 0x100000
+    ld a0, 0(t0)
+    li t1, -1
+    sd t1, 0(t0)
+    addi a0, a0, {}
+    li a7, 93
+    ecall
+",
+        increment
+    ))
+    .unwrap()
+}
+
+// Acquires a machine with chaos mode off, programs here check that memory
+// they never wrote reads zero on a reset machine.
+fn acquire<F>(pool: &AsmMachinePool, key: PoolKey, build: F) -> PooledAsmMachine
+where
+    F: FnOnce(DefaultMachineBuilder<Box<AsmCoreMachine>>) -> DefaultMachine<Box<AsmCoreMachine>>,
+{
+    let mut machine = pool.acquire(key, u64::MAX, build);
+    machine.machine.inner_mut().chaos_mode = 0;
+    machine
+}
+
+fn run(pool: &AsmMachinePool, key: PoolKey, program: &Bytes) -> (Result<i8, Error>, u64) {
+    let mut machine = acquire(pool, key, |builder| {
+        builder
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .build()
+    });
+    assert_eq!(machine.machine.cycles(), 0);
+    assert!(machine.machine.registers().iter().all(|r| *r == 0));
+    machine.load_program(program, &[]).unwrap();
+    let result = machine.run();
+    (result, machine.machine.cycles())
+}
+
+#[test]
+fn test_pool_reuses_machines_and_decoders() {
+    let pool = AsmMachinePool::new(4);
+    let key = PoolKey::new(ISA_IMC, VERSION2, DEFAULT_MEMORY_SIZE, CONSTANT_CYCLES);
+    let code = program(1);
+    let (result, cycles) = run(&pool, key, &code);
+    assert_eq!(result, Ok(1));
+    for _ in 0..3 {
+        assert_eq!(run(&pool, key, &code), (Ok(1), cycles));
+    }
+    let metrics = pool.metrics();
+    assert_eq!(
+        metrics,
+        PoolMetrics {
+            machine_hits: 3,
+            machine_misses: 1,
+            decoder_hits: 3,
+            decoder_misses: 1,
+        }
+    );
+    assert_eq!(metrics.machine_hit_rate(), 0.75);
+    assert_eq!(metrics.decoder_hit_rate(), 0.75);
+}
+
+#[test]
+fn test_pool_decoders_per_program() {
+    let pool = AsmMachinePool::new(4);
+    let key = PoolKey::new(ISA_IMC, VERSION2, DEFAULT_MEMORY_SIZE, CONSTANT_CYCLES);
+    // Both programs share their code addresses, traces of one must not be
+    // used for the other.
+    let first = program(1);
+    let second = program(2);
+    for _ in 0..2 {
+        assert_eq!(run(&pool, key, &first).0, Ok(1));
+        assert_eq!(run(&pool, key, &second).0, Ok(2));
+    }
+    let metrics = pool.metrics();
+    assert_eq!(metrics.machine_hits, 3);
+    assert_eq!(metrics.decoder_hits, 2);
+    assert_eq!(metrics.decoder_misses, 2);
+}
+
+#[test]
+fn test_pool_decoders_per_metadata() {
+    let pool = AsmMachinePool::new(4);
+    let key = PoolKey::new(ISA_IMC, VERSION2, DEFAULT_MEMORY_SIZE, DEFAULT_CYCLES);
+    let code = assemble_elf::<u64>("li a0, 1\naddi a0, a0, 1\nli a7, 93\necall").unwrap();
+    let metadata = parse_elf::<u64>(&code, VERSION2).unwrap();
+    let mut skipped = metadata.clone();
+    skipped.entry += 4;
+    for _ in 0..2 {
+        for (metadata, expected) in [(&metadata, 2), (&skipped, 1)] {
+            let mut machine = acquire(&pool, key, |builder| builder.build());
+            machine
+                .load_program_with_metadata(&code, metadata, &[])
+                .unwrap();
+            assert_eq!(machine.run(), Ok(expected));
+        }
+    }
+    let metrics = pool.metrics();
+    assert_eq!(metrics.decoder_hits, 2);
+    assert_eq!(metrics.decoder_misses, 2);
+}
+
+#[test]
+fn test_pool_cycle_models() {
+    let pool = AsmMachinePool::new(4);
+    let key = PoolKey::new(ISA_IMC, VERSION2, DEFAULT_MEMORY_SIZE, CONSTANT_CYCLES);
+    let cycle_key = PoolKey {
+        cycle_model: DOUBLE_CYCLES,
+        ..key
+    };
+    let code = program(1);
+    let (result, cycles) = run(&pool, key, &code);
+    assert_eq!(result, Ok(1));
+    // Traces memoized with constant_cycles must not be used for another
+    // cycle model.
+    for _ in 0..2 {
+        let mut machine = acquire(&pool, cycle_key, |builder| {
+            builder.instruction_cycle_func(Box::new(|_| 2)).build()
+        });
+        machine.load_program(&code, &[]).unwrap();
+        assert_eq!(machine.run(), Ok(1));
+        assert_eq!(machine.machine.cycles(), cycles * 2);
+    }
+    assert_eq!(run(&pool, key, &code), (Ok(1), cycles));
+    let metrics = pool.metrics();
+    assert_eq!(metrics.machine_misses, 2);
+    assert_eq!(metrics.decoder_hits, 2);
+    assert_eq!(metrics.decoder_misses, 2);
+}
+
+#[test]
+fn test_pool_keys() {
+    let pool = AsmMachinePool::new(4);
+    let code = program(1);
+    for key in [
+        PoolKey::new(ISA_IMC, VERSION2, DEFAULT_MEMORY_SIZE, CONSTANT_CYCLES),
+        PoolKey::new(ISA_IMC, VERSION2, DEFAULT_MEMORY_SIZE * 2, CONSTANT_CYCLES),
+        PoolKey::new(
+            ISA_IMC | ISA_MOP,
+            VERSION2,
+            DEFAULT_MEMORY_SIZE,
+            CONSTANT_CYCLES,
+        ),
+        PoolKey::new(ISA_IMC, VERSION1, DEFAULT_MEMORY_SIZE, CONSTANT_CYCLES),
+    ] {
+        assert_eq!(run(&pool, key, &code).0, Ok(1));
+        let mut machine = acquire(&pool, key, |builder| {
+            builder
+                .instruction_cycle_func(Box::new(constant_cycles))
+                .build()
+        });
+        assert_eq!(machine.machine.isa(), key.isa);
+        assert_eq!(machine.machine.version(), key.version);
+        assert_eq!(machine.machine.memory_mut().memory_size(), key.memory_size);
+    }
+    let metrics = pool.metrics();
+    assert_eq!(metrics.machine_hits, 4);
+    assert_eq!(metrics.machine_misses, 4);
+    assert_eq!(metrics.decoder_misses, 4);
+}
+
+#[test]
+fn test_pool_max_idle() {
+    let pool = AsmMachinePool::new(1);
+    let key = PoolKey::new(ISA_IMC, VERSION2, DEFAULT_MEMORY_SIZE, DEFAULT_CYCLES);
+    let code = program(1);
+    let machines: Vec<_> = (0..3)
+        .map(|_| {
+            let mut machine = acquire(&pool, key, |builder| builder.build());
+            machine.load_program(&code, &[]).unwrap();
+            machine
+        })
+        .collect();
+    drop(machines);
+    for _ in 0..2 {
+        let mut machine = acquire(&pool, key, |builder| builder.build());
+        machine.load_program(&code, &[]).unwrap();
+        assert_eq!(machine.run(), Ok(1));
+    }
+    let metrics = pool.metrics();
+    assert_eq!(metrics.machine_hits, 2);
+    assert_eq!(metrics.machine_misses, 3);
+    assert_eq!(metrics.decoder_hits, 2);
+    assert_eq!(metrics.decoder_misses, 3);
+}
+
+pub struct ExecSyscall {
+    code: Bytes,
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for ExecSyscall {
+    fn initialize(&mut self, _: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != 1111 {
+            return Ok(false);
+        }
+        machine.reset(machine.max_cycles())?;
+        machine.load_elf(&self.code, true)?;
+        Ok(true)
+    }
+}
+
+#[test]
+fn test_pool_reset_while_running() {
+    let pool = AsmMachinePool::new(4);
+    let key = PoolKey::new(ISA_IMC, VERSION2, DEFAULT_MEMORY_SIZE, CONSTANT_CYCLES);
+    let caller = assemble_elf::<u64>("li a7, 1111\necall").unwrap();
+    let callee = program(2);
+    for _ in 0..2 {
+        let mut machine = acquire(&pool, key, |builder| {
+            builder
+                .instruction_cycle_func(Box::new(constant_cycles))
+                .syscall(Box::new(ExecSyscall {
+                    code: callee.clone(),
+                }))
+                .build()
+        });
+        machine.load_program(&caller, &[]).unwrap();
+        assert_eq!(machine.run(), Ok(2));
+    }
+    // The decoder now holding traces of the callee is not kept for the
+    // caller.
+    assert_eq!(pool.metrics().decoder_misses, 2);
+    assert_eq!(run(&pool, key, &callee).0, Ok(2));
+    assert_eq!(pool.metrics().decoder_misses, 3);
+}
+
+#[test]
+fn test_pool_rv32() {
+    let pool = AsmMachinePool::new(4);
+    let key = PoolKey::new_rv32(ISA_IMC, VERSION2, DEFAULT_MEMORY_SIZE, CONSTANT_CYCLES);
+    let code = assemble_elf::<u32>("li a0, 3\nli a7, 93\necall").unwrap();
+    for _ in 0..2 {
+        assert_eq!(run(&pool, key, &code).0, Ok(3));
+    }
+    assert_eq!(pool.metrics().machine_hits, 1);
+    assert_eq!(pool.metrics().decoder_hits, 1);
+}
+
+#[test]
+fn test_pool_threads() {
+    let pool = AsmMachinePool::new(4);
+    let key = PoolKey::new(ISA_IMC, VERSION2, DEFAULT_MEMORY_SIZE, CONSTANT_CYCLES);
+    let code = program(1);
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let pool = pool.clone();
+            let code = code.clone();
+            thread::spawn(move || {
+                for _ in 0..8 {
+                    assert_eq!(run(&pool, key, &code).0, Ok(1));
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let metrics = pool.metrics();
+    assert_eq!(metrics.machine_hits + metrics.machine_misses, 32);
+    assert!(metrics.machine_misses <= 4);
+    assert_eq!(metrics.decoder_hits + metrics.decoder_misses, 32);
+}
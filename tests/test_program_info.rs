@@ -0,0 +1,96 @@
+use ckb_vm::elf::{ProgramInfo, RiscvAttributes, SHT_NOBITS, STB_GLOBAL, STT_FUNC};
+use ckb_vm::machine::{VERSION0, VERSION3};
+use ckb_vm::Bytes;
+use std::fs;
+pub mod elf_build;
+use elf_build::{build_pie, Symbol, BUILD_ID, RODATA, TEXT};
+
+#[test]
+pub fn test_program_info_sections() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    for version in [VERSION0, VERSION3] {
+        let info = ProgramInfo::parse::<u64>(&buffer, version).unwrap();
+        assert_eq!(info.header.e_entry, 0x100c0);
+        assert_eq!(info.program_headers.len(), 2);
+        assert_eq!(info.sections.len(), 22);
+        let text = info.section(".text").unwrap();
+        assert_eq!(text.header.sh_addr, 0x100b0);
+        assert_eq!(info.section_data(text).unwrap().len(), 0x6b4);
+        let bss = info.section(".bss").unwrap();
+        assert_eq!(bss.header.sh_type, SHT_NOBITS);
+        assert!(info.section_data(bss).unwrap().is_empty());
+        assert!(info.section(".dynsym").is_none());
+        // .shstrtab is the last section.
+        assert_eq!(info.string(21, 1).unwrap(), ".symtab");
+    }
+}
+
+#[test]
+pub fn test_program_info_symbols() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let info = ProgramInfo::parse::<u64>(&buffer, VERSION3).unwrap();
+    assert_eq!(info.symbols.len(), 68);
+    assert!(info.dynamic_symbols.is_empty());
+    let register_fini = info.symbol("register_fini").unwrap();
+    assert_eq!(register_fini.value, 0x100b0);
+    assert_eq!(register_fini.size, 16);
+    assert_eq!(register_fini.kind, STT_FUNC);
+    assert_eq!(info.symbol_at(0x100b4).unwrap().name, "register_fini");
+    assert!(info.symbol("not_exist").is_none());
+
+    let buffer: Bytes = fs::read("tests/programs/simple").unwrap().into();
+    let info = ProgramInfo::parse::<u32>(&buffer, VERSION3).unwrap();
+    let main = info.symbol("main").unwrap();
+    assert_eq!(main.value, 0x10152);
+    assert_eq!(main.size, 1098);
+    assert_eq!(main.bind, STB_GLOBAL);
+    assert_eq!(info.symbol_at(0x10152 + 1000).unwrap().name, "main");
+}
+
+#[test]
+pub fn test_program_info_riscv_attributes() {
+    let buffer: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let info = ProgramInfo::parse::<u64>(&buffer, VERSION3).unwrap();
+    let attributes = info.riscv_attributes.as_ref().unwrap();
+    assert_eq!(attributes.arch(), Some("rv64i2p0_m2p0_a2p0_c2p0"));
+    assert_eq!(attributes.stack_align(), Some(16));
+    assert!(info.build_id().is_none());
+
+    let buffer: Bytes = fs::read("tests/programs/clang_sample").unwrap().into();
+    let info = ProgramInfo::parse::<u64>(&buffer, VERSION3).unwrap();
+    assert_eq!(
+        info.riscv_attributes.unwrap().arch(),
+        Some("rv64i2p1_m2p0_a2p1_c2p0_zba1p0_zbb1p0_zbc1p0_zbs1p0")
+    );
+
+    // Binaries built by older toolchains might not carry attributes at all.
+    let buffer: Bytes = fs::read("tests/programs/simple").unwrap().into();
+    let info = ProgramInfo::parse::<u32>(&buffer, VERSION3).unwrap();
+    assert!(info.riscv_attributes.is_none());
+
+    assert!(RiscvAttributes::parse(b"B").is_err());
+    assert!(RiscvAttributes::parse(b"A\xff\x00\x00\x00riscv\x00").is_err());
+}
+
+#[test]
+pub fn test_program_info_stripped() {
+    // Images without section headers still expose dynamic symbols and notes
+    // through program headers.
+    let symbols = [
+        Symbol {
+            name: "get",
+            value: Some(TEXT),
+        },
+        Symbol {
+            name: "answer",
+            value: Some(RODATA),
+        },
+    ];
+    let buffer = build_pie(&[0x00008067], &[], &[], &symbols, &[]);
+    let info = ProgramInfo::parse::<u64>(&buffer, VERSION3).unwrap();
+    assert!(info.sections.is_empty());
+    assert!(info.symbols.is_empty());
+    assert_eq!(info.dynamic_symbols.len(), 2);
+    assert_eq!(info.symbol("answer").unwrap().value, RODATA);
+    assert_eq!(&info.build_id().unwrap()[..], &BUILD_ID[..]);
+}
@@ -0,0 +1,210 @@
+use ckb_vm::elf::{
+    parse_elf, parse_elf_with_load_bias, SymbolTable, R_RISCV_64, R_RISCV_JUMP_SLOT,
+    R_RISCV_RELATIVE,
+};
+use ckb_vm::machine::{VERSION2, VERSION3};
+use ckb_vm::snapshot2::{DataSource, Snapshot2Context};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, Error, Memory,
+    SparseMemory, WXorXMemory, ISA_IMC,
+};
+use std::fs;
+pub mod elf_build;
+use elf_build::{build_pie, Rela, Symbol, DATA, RODATA, TEXT};
+
+const LOAD_BIAS: u64 = 0x100000;
+
+type Mem = WXorXMemory<SparseMemory<u64>>;
+
+// Loads the byte pointed to by DATA, adds the value stored at DATA + 8 and
+// exits with the result.
+fn build_program() -> Bytes {
+    let code = [
+        0x00001297, // auipc t0, 0x1
+        0xf002b583, // ld a1, -256(t0)
+        0x0005c503, // lbu a0, 0(a1)
+        0xf082b603, // ld a2, -248(t0)
+        0x00c50533, // add a0, a0, a2
+        0x05d00893, // li a7, 93
+        0x00000073, // ecall
+    ];
+    let mut rodata = vec![42u8; 8];
+    rodata.extend_from_slice(&[0u8; 8]);
+    let symbols = [
+        Symbol {
+            name: "ext",
+            value: None,
+        },
+        Symbol {
+            name: "main",
+            value: Some(TEXT),
+        },
+    ];
+    let relas = [
+        Rela {
+            offset: DATA,
+            kind: R_RISCV_RELATIVE,
+            symbol: 0,
+            addend: RODATA as i64,
+        },
+        Rela {
+            offset: DATA + 8,
+            kind: R_RISCV_64,
+            symbol: 1,
+            addend: 0,
+        },
+        Rela {
+            offset: DATA + 16,
+            kind: R_RISCV_JUMP_SLOT,
+            symbol: 2,
+            addend: 0,
+        },
+        Rela {
+            offset: RODATA + 8,
+            kind: R_RISCV_RELATIVE,
+            symbol: 0,
+            addend: TEXT as i64,
+        },
+    ];
+    build_pie(&code, &rodata, &[0u8; 24], &symbols, &relas)
+}
+
+fn build_machine(version: u32) -> DefaultMachine<DefaultCoreMachine<u64, Mem>> {
+    let core_machine = DefaultCoreMachine::<u64, Mem>::new(ISA_IMC, version, u64::MAX);
+    DefaultMachineBuilder::new(core_machine).build()
+}
+
+fn ext_symbols() -> SymbolTable {
+    let mut symbols = SymbolTable::new();
+    symbols.insert("ext".to_string(), 8);
+    symbols
+}
+
+#[test]
+pub fn test_pie_load_bias() {
+    let program = build_program();
+    let metadata =
+        parse_elf_with_load_bias::<u64>(&program, VERSION3, LOAD_BIAS, &ext_symbols()).unwrap();
+    assert_eq!(metadata.entry, LOAD_BIAS + TEXT);
+    assert_eq!(metadata.actions[0].addr, LOAD_BIAS);
+    assert_eq!(metadata.actions[1].addr, LOAD_BIAS + DATA);
+
+    let mut machine = build_machine(VERSION3);
+    machine
+        .load_program_with_metadata(&program, &metadata, &["main".into()])
+        .unwrap();
+    let memory = machine.memory_mut();
+    assert_eq!(
+        memory.load64(&(LOAD_BIAS + DATA)).unwrap(),
+        LOAD_BIAS + RODATA
+    );
+    assert_eq!(memory.load64(&(LOAD_BIAS + DATA + 8)).unwrap(), 8);
+    assert_eq!(
+        memory.load64(&(LOAD_BIAS + DATA + 16)).unwrap(),
+        LOAD_BIAS + TEXT
+    );
+    assert_eq!(
+        memory.load64(&(LOAD_BIAS + RODATA + 8)).unwrap(),
+        LOAD_BIAS + TEXT
+    );
+    let result = machine.run();
+    assert_eq!(result, Ok(50));
+}
+
+#[test]
+pub fn test_pie_without_load_bias() {
+    // parse_elf keeps loading images at their link-time addresses without
+    // touching relocations.
+    let program = build_program();
+    let metadata = parse_elf::<u64>(&program, VERSION2).unwrap();
+    assert_eq!(metadata.entry, TEXT);
+    assert!(metadata.actions.iter().all(|a| a.relocations.is_empty()));
+
+    let metadata = parse_elf_with_load_bias::<u64>(&program, VERSION3, 0, &ext_symbols()).unwrap();
+    assert_eq!(metadata.entry, TEXT);
+    let mut machine = build_machine(VERSION3);
+    machine
+        .load_program_with_metadata(&program, &metadata, &["main".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(50));
+}
+
+#[test]
+pub fn test_pie_undefined_symbol() {
+    let program = build_program();
+    let result =
+        parse_elf_with_load_bias::<u64>(&program, VERSION3, LOAD_BIAS, &SymbolTable::new());
+    assert_eq!(result, Err(Error::ElfUndefinedSymbol("ext".to_string())));
+}
+
+#[test]
+pub fn test_pie_invalid_load_bias() {
+    let program = build_program();
+    let result = parse_elf_with_load_bias::<u64>(&program, VERSION2, LOAD_BIAS, &ext_symbols());
+    assert_eq!(result, Err(Error::InvalidVersion));
+    let result = parse_elf_with_load_bias::<u64>(&program, VERSION3, 0x1234, &ext_symbols());
+    assert!(matches!(result, Err(Error::ElfParseError(_))));
+
+    // Executables can only be loaded at their link-time addresses.
+    let program: Bytes = fs::read("tests/programs/simple64").unwrap().into();
+    let result = parse_elf_with_load_bias::<u64>(&program, VERSION3, LOAD_BIAS, &ext_symbols());
+    assert!(matches!(result, Err(Error::ElfParseError(_))));
+    let metadata = parse_elf_with_load_bias::<u64>(&program, VERSION3, 0, &ext_symbols()).unwrap();
+    assert_eq!(metadata, parse_elf::<u64>(&program, VERSION3).unwrap());
+}
+
+#[test]
+pub fn test_pie_offset_overflow() {
+    // Offsets near u64::MAX are rejected instead of overflowing.
+    let mut program = build_program().to_vec();
+    // p_offset of the first PT_LOAD, which holds .rela.dyn
+    program[0xc08..0xc10].copy_from_slice(&(u64::MAX - 0x100).to_le_bytes());
+    let result =
+        parse_elf_with_load_bias::<u64>(&program.into(), VERSION3, LOAD_BIAS, &ext_symbols());
+    assert!(matches!(result, Err(Error::ElfSegmentAddrOrSizeError(_))));
+
+    let mut program = build_program().to_vec();
+    // DT_RELASZ and DT_RELAENT, the second and third entries of .dynamic
+    let dynamic = DATA as usize + 24;
+    program[dynamic + 24..dynamic + 32].copy_from_slice(&u64::MAX.to_le_bytes());
+    program[dynamic + 40..dynamic + 48].copy_from_slice(&(u64::MAX / 4).to_le_bytes());
+    let result =
+        parse_elf_with_load_bias::<u64>(&program.into(), VERSION3, LOAD_BIAS, &ext_symbols());
+    assert!(matches!(result, Err(Error::ElfParseError(_))));
+}
+
+#[derive(Clone)]
+struct ProgramSource(Bytes);
+
+impl DataSource<u64> for ProgramSource {
+    fn load_data(&self, _id: &u64, offset: u64, length: u64) -> Option<(Bytes, u64)> {
+        let end = if length > 0 {
+            offset + length
+        } else {
+            self.0.len() as u64
+        };
+        Some((self.0.slice(offset as usize..end as usize), end - offset))
+    }
+}
+
+#[test]
+pub fn test_pie_snapshot2() {
+    let program = build_program();
+    let metadata =
+        parse_elf_with_load_bias::<u64>(&program, VERSION3, LOAD_BIAS, &ext_symbols()).unwrap();
+    let mut ctx = Snapshot2Context::new(ProgramSource(program.clone()));
+    let mut machine1 = build_machine(VERSION3);
+    machine1
+        .load_program_with_metadata(&program, &metadata, &["main".into()])
+        .unwrap();
+    ctx.mark_program(&mut machine1, &metadata, &0, 0).unwrap();
+    let snapshot = ctx.make_snapshot(&mut machine1).unwrap();
+
+    let mut machine2 = build_machine(VERSION3);
+    ctx.resume(&mut machine2, &snapshot).unwrap();
+    assert_eq!(
+        machine2.memory_mut().load64(&(LOAD_BIAS + RODATA + 8)),
+        Ok(LOAD_BIAS + TEXT)
+    );
+    assert_eq!(machine2.run(), Ok(50));
+}
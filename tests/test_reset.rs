@@ -1,12 +1,18 @@
 use bytes::Bytes;
+use ckb_vm::assembler::assemble_elf;
 use ckb_vm::cost_model::constant_cycles;
 #[cfg(has_asm)]
-use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
-use ckb_vm::machine::{DefaultCoreMachine, DefaultMachineBuilder, VERSION1};
+use ckb_vm::decoder::build_decoder;
+use ckb_vm::decoder::DecodedProgram;
+use ckb_vm::elf::parse_elf;
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::{traces::ChainedTraceDecoder, AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachineBuilder, VERSION1, VERSION2};
 use ckb_vm::{
-    registers::A7, Error, Register, SparseMemory, SupportMachine, Syscalls, TraceMachine,
-    WXorXMemory, DEFAULT_MEMORY_SIZE, ISA_IMC, ISA_MOP,
+    registers::A7, CoreMachine, Error, FlatMemory, Memory, Register, SparseMemory, SupportMachine,
+    Syscalls, TraceMachine, WXorXMemory, DEFAULT_MEMORY_SIZE, ISA_IMC, ISA_MOP,
 };
+use std::sync::Arc;
 
 #[allow(dead_code)]
 mod machine_build;
@@ -84,6 +90,62 @@ fn test_reset_int_with_trace() {
     assert_eq!(cycles, 775);
 }
 
+#[test]
+fn test_reset_int_with_superblocks() {
+    let code_data = std::fs::read("tests/programs/reset_caller").unwrap();
+    let code = Bytes::from(code_data);
+
+    let mut cycles = vec![];
+    for superblocks in [false, true] {
+        let core_machine = DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(
+            ISA_IMC | ISA_MOP,
+            VERSION2,
+            u64::MAX,
+        );
+        let mut machine = TraceMachine::new(
+            DefaultMachineBuilder::new(core_machine)
+                .instruction_cycle_func(Box::new(constant_cycles))
+                .syscall(Box::new(CustomSyscall {}))
+                .build(),
+        );
+        if superblocks {
+            machine = machine.with_superblocks();
+        }
+        machine.load_program(&code, &[]).unwrap();
+        let result = machine.run();
+        assert_eq!(result.unwrap(), 0);
+        cycles.push(machine.machine.cycles());
+    }
+    assert_eq!(cycles[0], cycles[1]);
+}
+
+#[test]
+fn test_reset_int_with_decoded_program() {
+    let code_data = std::fs::read("tests/programs/reset_caller").unwrap();
+    let code = Bytes::from(code_data);
+    let metadata = parse_elf::<u64>(&code, VERSION1).unwrap();
+    let decoded = Arc::new(
+        DecodedProgram::new::<u64>(&code, &metadata, ISA_IMC | ISA_MOP, VERSION1).unwrap(),
+    );
+
+    let core_machine = DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(
+        ISA_IMC | ISA_MOP,
+        VERSION1,
+        u64::MAX,
+    );
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .syscall(Box::new(CustomSyscall {}))
+            .build(),
+    );
+    machine.load_program(&code, &[]).unwrap();
+    let result = machine.run_with_decoder(&mut decoded.decoder::<u64>(&code, &metadata).unwrap());
+    let cycles = machine.machine.cycles();
+    assert_eq!(result.unwrap(), 0);
+    assert_eq!(cycles, 775);
+}
+
 #[test]
 #[cfg(has_asm)]
 fn test_reset_asm() {
@@ -104,3 +166,113 @@ fn test_reset_asm() {
     assert_eq!(result.unwrap(), 0);
     assert_eq!(cycles, 775);
 }
+
+#[test]
+#[cfg(has_asm)]
+fn test_reset_asm_chained() {
+    let code_data = std::fs::read("tests/programs/reset_caller").unwrap();
+    let code = Bytes::from(code_data);
+
+    let asm_core = AsmCoreMachine::new(ISA_IMC | ISA_MOP, VERSION1, u64::MAX);
+    let core = DefaultMachineBuilder::<Box<AsmCoreMachine>>::new(asm_core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .syscall(Box::new(CustomSyscall {}))
+        .build();
+    let mut machine = AsmMachine::new(core);
+    machine.load_program(&code, &vec![]).unwrap();
+
+    let mut decoder = ChainedTraceDecoder::new(build_decoder::<u64>(ISA_IMC | ISA_MOP, VERSION1));
+    let result = machine.run_with_decoder(&mut decoder);
+    let cycles = machine.machine.cycles();
+    assert!(result.is_ok());
+    assert_eq!(result.unwrap(), 0);
+    assert_eq!(cycles, 775);
+}
+
+// Fills 1 MB to 22 MB, which takes consecutive memory frames long enough to
+// be released to the OS on reset.
+const FILL: &str = "
+    li s0, 0x100000
+    li s1, 0x1600000
+    li a1, -1
+loop:
+    sd a1, 0(s0)
+    addi s0, s0, 64
+    bltu s0, s1, loop
+    li a0, 0
+    li a7, 93
+    ecall
+";
+
+const FILL_MEMORY_SIZE: usize = 32 << 20;
+
+fn check_zeroed<M: Memory>(memory: &mut M) {
+    let data = memory.load_bytes(0, FILL_MEMORY_SIZE as u64).unwrap();
+    assert!(data.iter().all(|b| *b == 0));
+}
+
+#[test]
+fn test_reset_flat_memory_zeroes_touched_frames() {
+    let code = assemble_elf::<u64>(FILL).unwrap();
+    let core_machine = DefaultCoreMachine::<u64, WXorXMemory<FlatMemory<u64>>>::new_with_memory(
+        ISA_IMC,
+        VERSION2,
+        u64::MAX,
+        WXorXMemory::new(FlatMemory::new_with_memory(FILL_MEMORY_SIZE)),
+    );
+    let mut machine = DefaultMachineBuilder::new(core_machine).build();
+    for _ in 0..2 {
+        machine.load_program(&code, &vec![]).unwrap();
+        assert_eq!(machine.run(), Ok(0));
+        assert_eq!(
+            machine.memory_mut().load_bytes(0x15fffc0, 8).unwrap(),
+            Bytes::from(vec![0xff; 8])
+        );
+        machine.reset(u64::MAX).unwrap();
+        check_zeroed(machine.memory_mut());
+    }
+}
+
+// Frames are initialized again on their next access, with random data in
+// chaos mode, so memory is only checked to read zero without it.
+#[cfg(has_asm)]
+fn check_asm_reset(machine: &mut AsmMachine) {
+    let core = machine.machine.inner_mut();
+    let frames = core.cast_ptr_to_slice(core.frames_ptr, 0, core.frames_size as usize);
+    assert!(frames.iter().all(|f| *f == 0));
+    if core.chaos_mode == 0 {
+        check_zeroed(machine.machine.memory_mut());
+    }
+}
+
+#[test]
+#[cfg(has_asm)]
+fn test_reset_asm_zeroes_touched_frames() {
+    let code = assemble_elf::<u64>(FILL).unwrap();
+    let asm_core = AsmCoreMachine::new_with_memory(ISA_IMC, VERSION2, u64::MAX, FILL_MEMORY_SIZE);
+    let core = DefaultMachineBuilder::<Box<AsmCoreMachine>>::new(asm_core).build();
+    let mut machine = AsmMachine::new(core);
+    for _ in 0..2 {
+        machine.load_program(&code, &vec![]).unwrap();
+        assert_eq!(machine.run(), Ok(0));
+        assert_eq!(
+            machine
+                .machine
+                .memory_mut()
+                .load_bytes(0x15fffc0, 8)
+                .unwrap(),
+            Bytes::from(vec![0xff; 8])
+        );
+        machine.machine.reset(u64::MAX).unwrap();
+        check_asm_reset(&mut machine);
+    }
+    // A frame written to from the outside, next to the ones written by the
+    // program, is zeroed as well.
+    machine
+        .machine
+        .memory_mut()
+        .store_bytes(0x1600000, &[1; 16])
+        .unwrap();
+    machine.machine.reset(u64::MAX).unwrap();
+    check_asm_reset(&mut machine);
+}
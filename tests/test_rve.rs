@@ -0,0 +1,236 @@
+use ckb_vm::assembler::assemble_elf;
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::trace::TraceMachine;
+use ckb_vm::machine::{DefaultMachine, VERSION2};
+use ckb_vm::registers::{A0, A1, A7, T0};
+use ckb_vm::snapshot::{make_snapshot, resume};
+use ckb_vm::snapshot2::{DataSource, Snapshot2Context};
+use ckb_vm::{
+    Bytes, CoreMachine, DefaultCoreMachine, DefaultMachineBuilder, Error, Register, SparseMemory,
+    SupportMachine, Syscalls, WXorXMemory, ISA_A, ISA_E, ISA_IMC, RISCV_EMBEDDED_REGISTER_NUMBER,
+    RISCV_GENERAL_REGISTER_NUMBER,
+};
+
+type Machine<R> = DefaultMachine<DefaultCoreMachine<R, WXorXMemory<SparseMemory<R>>>>;
+
+fn build<R: Register>(isa: u8, max_cycles: u64) -> Machine<R> {
+    let core_machine =
+        DefaultCoreMachine::<R, WXorXMemory<SparseMemory<R>>>::new(isa, VERSION2, max_cycles);
+    DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(|_| 1))
+        .build()
+}
+
+// Sums 1 to 100 using x0-x15 only, the exit syscall number goes to t0.
+const SUM: &str = "
+    li a0, 0
+    li a1, 100
+loop:
+    add a0, a0, a1
+    addi a1, a1, -1
+    bnez a1, loop
+    li a2, 5050
+    sub a0, a0, a2
+    li t0, 93
+    ecall
+";
+
+#[test]
+fn test_rve_run() {
+    let program = assemble_elf::<u64>(SUM).unwrap();
+    let mut machine = TraceMachine::new(build::<u64>(ISA_IMC | ISA_E, u64::MAX));
+    machine.load_program(&program, &[]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+
+    let program = assemble_elf::<u32>(SUM).unwrap();
+    let mut machine = TraceMachine::new(build::<u32>(ISA_IMC | ISA_E, u64::MAX));
+    machine.load_program(&program, &[]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+}
+
+#[test]
+fn test_rve_invalid_register() {
+    for (source, register) in [
+        ("add a0, a0, a6", 16),
+        ("addi s2, a0, 1", 18),
+        ("sd t3, 0(sp)", 28),
+        (".option rvc\nmv a7, a0", 17),
+        ("lui t6, 1", 31),
+    ] {
+        let program =
+            assemble_elf::<u64>(&format!("li a0, 1\n{}\nli t0, 93\necall", source)).unwrap();
+        let mut machine = build::<u64>(ISA_IMC | ISA_A | ISA_E, u64::MAX);
+        machine.load_program(&program, &[]).unwrap();
+        match machine.run() {
+            Err(Error::InvalidRegister {
+                pc, register: r, ..
+            }) => {
+                assert_eq!(pc, 0x10004);
+                assert_eq!(r, register);
+            }
+            result => panic!("unexpected result {:?} for {}", result, source),
+        }
+
+        let mut machine = build::<u64>(ISA_IMC | ISA_A, u64::MAX);
+        machine.load_program(&program, &[]).unwrap();
+        assert!(matches!(machine.run(), Err(Error::InvalidEcall(_))));
+    }
+}
+
+// Adds a0 and a1 when the syscall number is 1111.
+struct AddSyscall {}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for AddSyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.syscall_number() != 1111 {
+            return Ok(false);
+        }
+        let result = machine.registers()[A0].overflowing_add(&machine.registers()[A1]);
+        machine.set_register(A0, result);
+        Ok(true)
+    }
+}
+
+const CUSTOM_SYSCALL: &str = "
+    li a0, 20
+    li a1, 22
+    li t0, 1111
+    ecall
+    addi a0, a0, -42
+    li t0, 93
+    ecall
+";
+
+#[test]
+fn test_rve_custom_syscall() {
+    let program = assemble_elf::<u64>(CUSTOM_SYSCALL).unwrap();
+    let core_machine = DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(
+        ISA_IMC | ISA_E,
+        VERSION2,
+        u64::MAX,
+    );
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(|_| 1))
+        .syscall(Box::new(AddSyscall {}))
+        .build();
+    machine.load_program(&program, &[]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    // x17 doesn't exist on ISA_E, ecall leaves it alone.
+    assert_eq!(machine.registers()[A7], 0);
+}
+
+#[cfg(has_asm)]
+#[test]
+fn test_rve_asm_custom_syscall() {
+    let program = assemble_elf::<u64>(CUSTOM_SYSCALL).unwrap();
+    let core = AsmCoreMachine::new(ISA_IMC | ISA_E, VERSION2, u64::MAX);
+    let mut machine = AsmMachine::new(
+        DefaultMachineBuilder::new(core)
+            .syscall(Box::new(AddSyscall {}))
+            .build(),
+    );
+    machine.load_program(&program, &[]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+}
+
+#[cfg(has_asm)]
+#[test]
+fn test_rve_asm() {
+    let program = assemble_elf::<u64>(SUM).unwrap();
+    let core = AsmCoreMachine::new(ISA_IMC | ISA_E, VERSION2, u64::MAX);
+    let mut machine = AsmMachine::new(DefaultMachineBuilder::new(core).build());
+    machine.load_program(&program, &[]).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+
+    let program = assemble_elf::<u64>("li a0, 1\nmv a5, a0\nmv a7, a5\nli t0, 93\necall").unwrap();
+    let core = AsmCoreMachine::new(ISA_IMC | ISA_E, VERSION2, u64::MAX);
+    let mut machine = AsmMachine::new(DefaultMachineBuilder::new(core).build());
+    machine.load_program(&program, &[]).unwrap();
+    assert_eq!(
+        machine.run(),
+        Err(Error::InvalidRegister {
+            pc: 0x10008,
+            instruction: 0x00078893,
+            register: 17
+        })
+    );
+}
+
+#[test]
+fn test_rve_snapshot() {
+    let program = assemble_elf::<u64>(SUM).unwrap();
+    let mut machine = build::<u64>(ISA_IMC | ISA_E, 100);
+    machine.load_program(&program, &[]).unwrap();
+    assert_eq!(machine.run(), Err(Error::CyclesExceeded));
+    let mut snapshot = make_snapshot(&mut machine).unwrap();
+    assert_eq!(snapshot.isa, ISA_IMC | ISA_E);
+    assert_eq!(snapshot.registers.len(), RISCV_EMBEDDED_REGISTER_NUMBER);
+
+    let mut machine = build::<u64>(ISA_IMC, u64::MAX);
+    assert_eq!(
+        resume(&mut machine, &snapshot),
+        Err(Error::SnapshotDataLoadError)
+    );
+    assert_eq!(
+        make_snapshot(&mut machine).unwrap().registers.len(),
+        RISCV_GENERAL_REGISTER_NUMBER
+    );
+
+    // The recorded ISA must match the register file too.
+    snapshot.isa = ISA_IMC;
+    let mut machine = build::<u64>(ISA_IMC | ISA_E, u64::MAX);
+    assert_eq!(
+        resume(&mut machine, &snapshot),
+        Err(Error::SnapshotDataLoadError)
+    );
+    snapshot.isa = ISA_IMC | ISA_E;
+
+    let mut machine = build::<u64>(ISA_IMC | ISA_E, u64::MAX);
+    resume(&mut machine, &snapshot).unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(machine.registers()[A0].to_u64(), 0);
+    assert_eq!(machine.registers()[T0].to_u64(), 93);
+}
+
+#[derive(Clone)]
+struct Program(Bytes);
+
+impl DataSource<u32> for Program {
+    fn load_data(&self, _id: &u32, offset: u64, length: u64) -> Option<(Bytes, u64)> {
+        let end = if length > 0 {
+            offset + length
+        } else {
+            self.0.len() as u64
+        };
+        let data = self.0.slice(offset as usize..end as usize);
+        Some((data, self.0.len() as u64))
+    }
+}
+
+#[test]
+fn test_rve_snapshot2() {
+    let program = assemble_elf::<u64>(SUM).unwrap();
+    let mut context = Snapshot2Context::new(Program(program.clone()));
+    let mut machine = build::<u64>(ISA_IMC | ISA_E, 100);
+    machine.load_program(&program, &[]).unwrap();
+    assert_eq!(machine.run(), Err(Error::CyclesExceeded));
+    let snapshot = context.make_snapshot(&mut machine).unwrap();
+    assert_eq!(snapshot.isa, ISA_IMC | ISA_E);
+    assert_eq!(snapshot.registers.len(), RISCV_EMBEDDED_REGISTER_NUMBER);
+
+    let mut machine = build::<u64>(ISA_IMC, 100);
+    assert_eq!(
+        context.resume(&mut machine, &snapshot),
+        Err(Error::SnapshotDataLoadError)
+    );
+
+    let mut machine = build::<u64>(ISA_IMC | ISA_E, 100);
+    context.resume(&mut machine, &snapshot).unwrap();
+    machine.set_max_cycles(u64::MAX);
+    assert_eq!(machine.run(), Ok(0));
+}
@@ -0,0 +1,199 @@
+use bytes::Bytes;
+use ckb_vm::assembler::assemble_elf;
+use ckb_vm::cost_model::estimate_cycles;
+use ckb_vm::machine::trace::TraceMachine;
+use ckb_vm::machine::{DefaultCoreMachine, VERSION1, VERSION2};
+use ckb_vm::memory::{sparse::SparseMemory, wxorx::WXorXMemory, Memory};
+use ckb_vm::{
+    CoreMachine, DefaultMachine, DefaultMachineBuilder, Error, SupportMachine, ISA_A, ISA_B,
+    ISA_IMC, ISA_MOP,
+};
+
+const CONFIGS: &[(u8, u32)] = &[
+    (ISA_IMC | ISA_B, VERSION1),
+    (ISA_IMC | ISA_A | ISA_B | ISA_MOP, VERSION2),
+];
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+// Result, cycles, registers, pc and memory after a run
+type Outcome = (Result<i8, Error>, u64, Vec<u64>, u64, Bytes);
+
+fn build(isa: u8, version: u32, max_cycles: u64) -> DefaultMachine<Core> {
+    DefaultMachineBuilder::new(Core::new(isa, version, max_cycles))
+        .instruction_cycle_func(Box::new(estimate_cycles))
+        .build()
+}
+
+fn run(
+    program: &Bytes,
+    isa: u8,
+    version: u32,
+    max_cycles: u64,
+    superblocks: bool,
+) -> Option<(Outcome, usize)> {
+    let mut machine = TraceMachine::new(build(isa, version, max_cycles));
+    if superblocks {
+        machine = machine.with_superblocks();
+    }
+    machine.load_program(program, &["main".into()]).ok()?;
+    let result = machine.run();
+    let memory_size = machine.memory().memory_size() as u64;
+    let outcome = (
+        result,
+        machine.machine.cycles(),
+        machine.registers().to_vec(),
+        *machine.pc(),
+        machine.memory_mut().load_bytes(0, memory_size).unwrap(),
+    );
+    Some((outcome, machine.superblock_count()))
+}
+
+fn assert_same_as_trace(name: &str, program: &Bytes, isa: u8, version: u32) {
+    let (expected, _) = match run(program, isa, version, 20_000_000, false) {
+        Some(expected) => expected,
+        None => return,
+    };
+    let (actual, count) = run(program, isa, version, 20_000_000, true).unwrap();
+    assert_eq!(actual.0, expected.0, "{} {} {}", name, isa, version);
+    assert!(actual == expected, "{} {} {}", name, isa, version);
+    assert_eq!(
+        count > 0,
+        version >= VERSION2,
+        "{} {} {}",
+        name,
+        isa,
+        version
+    );
+
+    // Running out of cycles in the middle of a superblock stops at the same
+    // instruction.
+    let limit = expected.1 / 2 + 1;
+    let (expected, _) = run(program, isa, version, limit, false).unwrap();
+    let (actual, _) = run(program, isa, version, limit, true).unwrap();
+    assert!(actual == expected, "{} {} {} limit", name, isa, version);
+}
+
+#[test]
+pub fn test_superblock_programs() {
+    let programs = [
+        "alloc_many",
+        "amo_compare",
+        "asm_trace_bug",
+        "auipc_no_sign_extend",
+        "clang_sample",
+        "ebreak64",
+        "invalid_read64",
+        "jalr_bug",
+        "jump0_64",
+        "misaligned_jump64",
+        "mop_adc",
+        "mop_far_jump",
+        "mop_random_adc_sbb",
+        "mop_wide_divide",
+        "read_at_boundary64",
+        "rvc_pageend",
+        "sc_after_sc",
+        "simple64",
+        "trace64",
+        "unaligned64",
+        "writable_page",
+        "write_large_address64",
+        "zero_address",
+    ];
+    for name in programs {
+        let program: Bytes = std::fs::read(format!("tests/programs/{}", name))
+            .unwrap()
+            .into();
+        for (isa, version) in CONFIGS {
+            assert_same_as_trace(name, &program, *isa, *version);
+        }
+    }
+}
+
+#[test]
+pub fn test_superblock_secp256k1() {
+    let program: Bytes = std::fs::read("benches/data/secp256k1_bench")
+        .unwrap()
+        .into();
+    let args: Vec<Bytes> = vec![
+        "secp256k1_bench".into(),
+        "033f8cf9c4d51a33206a6c1c6b27d2cc5129daa19dbd1fc148d395284f6b26411f".into(),
+        "304402203679d909f43f073c7c1dcf8468a485090589079ee834e6eed92fea9b09b06a2402201e46f1075afa18f306715e7db87493e7b7e779569aa13c64ab3d09980b3560a3".into(),
+        "foo".into(),
+        "bar".into(),
+    ];
+    let mut outcomes = vec![];
+    for superblocks in [false, true] {
+        let mut machine = TraceMachine::new(build(ISA_IMC | ISA_B | ISA_MOP, VERSION2, u64::MAX));
+        if superblocks {
+            machine = machine.with_superblocks();
+        }
+        machine.load_program(&program, &args).unwrap();
+        let result = machine.run();
+        assert_eq!(result, Ok(0));
+        outcomes.push((machine.machine.cycles(), machine.registers().to_vec()));
+    }
+    assert_eq!(outcomes[0], outcomes[1]);
+}
+
+#[test]
+pub fn test_superblock_side_exits() {
+    // The loop body spans several conditional branches, most of them are
+    // not taken, while the last one leaves the superblock at every
+    // iteration.
+    let source = r#"
+    .text
+    .globl _start
+_start:
+    li s0, 0
+    li s1, 1000
+    li a0, 0
+loop:
+    andi t0, s0, 3
+    beqz t0, skip1
+    addi a0, a0, 3
+skip1:
+    andi t1, s0, 7
+    bnez t1, skip2
+    xori a0, a0, 0x55
+skip2:
+    slli t2, a0, 1
+    bltu t2, a0, skip3
+    add a0, a0, t2
+skip3:
+    addi s0, s0, 1
+    bne s0, s1, loop
+    li a7, 93
+    ecall
+"#;
+    let program = assemble_elf::<u64>(source).unwrap();
+    let isa = ISA_IMC | ISA_B;
+    let (expected, _) = run(&program, isa, VERSION2, u64::MAX, false).unwrap();
+    let (actual, count) = run(&program, isa, VERSION2, u64::MAX, true).unwrap();
+    assert!(actual == expected);
+    assert!(count > 0);
+    for limit in [1, 7, 100, 1001, expected.1 / 3, expected.1 - 1] {
+        let (expected, _) = run(&program, isa, VERSION2, limit, false).unwrap();
+        let (actual, _) = run(&program, isa, VERSION2, limit, true).unwrap();
+        assert_eq!(actual.0, Err(Error::CyclesExceeded));
+        assert!(actual == expected, "limit {}", limit);
+    }
+}
+
+#[test]
+pub fn test_superblock_slots() {
+    // Every jump starts a new superblock, more of them than there are slots.
+    let mut source = String::from("li a0, 0\n");
+    for i in 0..10000 {
+        source.push_str(&format!("j l{}\nl{}:\n", i, i));
+    }
+    source.push_str("li a7, 93\necall\n");
+    let program = assemble_elf::<u64>(&source).unwrap();
+    let isa = ISA_IMC | ISA_B;
+    let (expected, _) = run(&program, isa, VERSION2, u64::MAX, false).unwrap();
+    let (actual, count) = run(&program, isa, VERSION2, u64::MAX, true).unwrap();
+    assert!(actual == expected);
+    assert_eq!(actual.0, Ok(0));
+    assert!(count <= 8192);
+}